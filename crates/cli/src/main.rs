@@ -1,27 +1,61 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::{anyhow, Context};
 use clap::{Args, Parser, Subcommand, ValueEnum};
-use mihomo_core::output::{ConfigDeployer, FileDeployer};
+use mihomo_core::blocklist::{parse_blocklist, BlockedDomain, BlocklistFormat};
+use mihomo_core::controller::{
+    self, connections_from_json, ConnectionInfo, ControllerClient, LogEntry, DEFAULT_DELAY_URL,
+};
+use mihomo_core::diff::{NodeChanges, NodeSet};
+use mihomo_core::groups::{expand_groups, ExpandedGroup};
+use mihomo_core::history::{self, HistoryEntry};
+use mihomo_core::liveness::{exclude_dead, NodeLiveness};
+use mihomo_core::mirror::{
+    configured_mirrors, is_github_url, mirror_url, pick_mirror, resource_mirror_template,
+};
+use mihomo_core::output::{
+    annotate_proxies, strip_header, strip_proxy_annotations, with_header, ConfigDeployer,
+    FileDeployer, GenerationInfo, HttpDeployer, ReloadTarget, SshDeployer,
+};
+use mihomo_core::probe::{probe_proxies, probed_proxies, ProbeAction, ProbeOptions};
+use mihomo_core::redact::{redact_secrets, redact_urls};
+use mihomo_core::region::{
+    add_region_groups, GeoIp, RegionClassifier, RegionGroupsSpec, RegionSource, RegionTable,
+    RemoteRegions, ServerRegionLookup,
+};
+use mihomo_core::rename::{rename_proxies, RenameConfig};
+use mihomo_core::schedule::{self, TimeWindow};
 use mihomo_core::storage::{
-    self, AppPaths, CustomRule, ManagedTailscaleCompat, ManualServerRef, RuleKind, SubscriptionList,
+    self, AppPaths, CustomRule, ExternalUi, ImportedRuleSet, ListenerDef, ListenerKind,
+    ManagedTailscaleCompat, ManualServerRef, ResourcesMode, RuleKind,
 };
-use mihomo_core::subscription::{Subscription, SubscriptionKind};
-use mihomo_core::{merge_configs, Template};
+use mihomo_core::subscription::{
+    daily_usage, detect_and_parse, explain_parse_failure, format_bytes, load_usage_history,
+    project_usage, share_link, FetchLimits, ParseMode, ParseOptions, Subscription,
+    SubscriptionCookies, SubscriptionKind, SubscriptionUsage,
+};
+use mihomo_core::throttle::{parse_rate, set_max_download_rate, throttle_download};
+use mihomo_core::{
+    add_provider_groups, apply_base_config_sections, apply_group_icons, apply_health_check_urls,
+    find_proxies, merge_configs_with_warnings, sync_bundled_template, BaseSection,
+    BundledTemplateSync, GroupOrder, HealthCheckUrls, MergeOptions, MergeReport, ProviderGroupKind,
+    ProviderNodes, ProxyDedup, ProxyFilterSpec, Template, WarningKind, Warnings,
+};
+use mihomo_core::{interpolate, secrets};
 use serde::Deserialize;
 use serde_yaml::Value;
 use tokio::fs;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 use tracing_subscriber::EnvFilter;
 
 const SAFE_FAKE_IP_RANGE: &str = "172.19.0.1/16";
+/// User-Agent sent to subscription providers unless `--subscription-ua` says otherwise.
+const DEFAULT_SUBSCRIPTION_UA: &str = "clash-verge/v2.4.2";
 const TAILSCALE_BASE_FAKE_IP_BYPASS: [&str; 2] = ["+.tailscale.com", "+.ts.net"];
-const TAILSCALE_ROUTE_EXCLUDES: [&str; 3] = [
-    "100.64.0.0/10",
-    "100.100.100.100/32",
-    "fd7a:115c:a1e0::/48",
-];
+const TAILSCALE_ROUTE_EXCLUDES: [&str; 3] =
+    ["100.64.0.0/10", "100.100.100.100/32", "fd7a:115c:a1e0::/48"];
 const TAILSCALE_BASE_DIRECT_RULES: [&str; 5] = [
     "DOMAIN-SUFFIX,tailscale.com,DIRECT",
     "DOMAIN-SUFFIX,ts.net,DIRECT",
@@ -39,6 +73,8 @@ const TAILSCALE_SYSTEM_PROXY_BYPASS_BASE: [&str; 2] = ["100.64.0.0/10", "100.100
     about = "Mihomo subscription merge CLI",
     long_about = "Generate Mihomo/Clash configuration files by combining a template with one or more subscriptions.\n\nUse `mihomo-cli merge --help` for command-specific options and defaults for runtime directories under ~/.config/mihomocli.",
     arg_required_else_help = true,
+    // Lets flags typed after a `--preset` expansion replace the preset's values.
+    args_override_self = true,
     after_long_help = r#"
 Quick Start Examples
 
@@ -123,6 +159,34 @@ Notes
 "#
 )]
 struct Cli {
+    /// Never write to the config/cache directories: subscription caches, usage history,
+    /// subscriptions.yaml, app.yaml and the merge report are left untouched, and commands that
+    /// exist to change them fail. `merge --stdout` keeps working.
+    #[arg(long = "read-only", global = true)]
+    read_only: bool,
+
+    /// Config directory (app.yaml, subscriptions.yaml, templates, output); overrides
+    /// MIHOMOCLI_CONFIG_DIR and the platform default.
+    #[arg(long = "config-dir", value_name = "DIR", global = true)]
+    config_dir: Option<PathBuf>,
+
+    /// Cap the combined speed of subscription, geodata and dashboard downloads, in bytes per
+    /// second; accepts K/M/G suffixes (binary), e.g. `512K` or `2M`.
+    #[arg(long = "max-download-rate", value_name = "RATE", global = true, value_parser = parse_rate)]
+    max_download_rate: Option<u64>,
+
+    /// Log output: human-readable text, JSON lines on stderr (span fields such as subscription
+    /// ids and byte counts included, plus busy/idle durations when a span closes), or spans
+    /// exported over OTLP/HTTP (requires a build with the `otlp` feature). JSON and OTLP log
+    /// at `info` unless RUST_LOG says otherwise.
+    #[arg(long = "log-format", value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// OTLP/HTTP traces endpoint for `--log-format otlp`; defaults to
+    /// OTEL_EXPORTER_OTLP_TRACES_ENDPOINT or http://localhost:4318/v1/traces.
+    #[arg(long = "otlp-endpoint", value_name = "URL", global = true)]
+    otlp_endpoint: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -215,7 +279,7 @@ Notes:
     mihomo-cli merge --tailscale-compatible --tailscale-tailnet-suffix example.com --sync-to-clash-verge-sources
 "#
     )]
-    Merge(MergeArgs),
+    Merge(Box<MergeArgs>),
 
     #[command(
         name = "refresh-clash-verge",
@@ -235,3987 +299,11195 @@ Notes:
     Manage(Manage),
 
     /// Run mihomo to test the generated config (-t)
-    #[command(about = "Validate output config with mihomo -t")]
+    #[command(
+        about = "Validate output config with mihomo -t",
+        long_about = "Validate the output config with `mihomo -t`, or, with --against-schema, offline against a bundled schema snapshot of a mihomo release.",
+        visible_alias = "validate"
+    )]
     Test(TestArgs),
 
     /// Initialize config directories and default template
     #[command(about = "Create ~/.config/mihomocli structure and seed template")]
-    Init,
+    Init(InitArgs),
 
     #[command(
         about = "Inspect local Mihomo, Clash Verge, system proxy, and Tailscale state",
-        long_about = "Best-effort local diagnostics for the common desktop setup. Reports file-backed Clash/Mihomo config state, whether macOS system proxies appear enabled, Tailscale CLI health when available, and live controller connection hints when the controller API is reachable."
+        long_about = "Best-effort local diagnostics for the common desktop setup. Starts with pass/fail checks (config directory layout, template parse, subscription reachability, geodata files, mihomo binary, controller connectivity), then reports file-backed Clash/Mihomo config state, whether macOS system proxies appear enabled, Tailscale CLI health when available, and live controller connection hints when the controller API is reachable. Exits non-zero when a check fails."
     )]
-    Doctor(DoctorArgs),
-}
+    Doctor(DoctorCmdArgs),
 
-// Note: default clap styles are used to avoid introducing extra dependencies
+    #[command(
+        about = "Install or open a web dashboard (metacubexd, yacd)",
+        long_about = "Download a web dashboard into ~/.config/mihomocli/ui/<name> and remember it in app.yaml so merge injects external-ui/external-ui-url. `ui open` launches the dashboard of the generated config with the controller address and secret pre-filled."
+    )]
+    Ui(UiArgs),
 
-#[derive(Args)]
-struct MergeArgs {
-    /// Template YAML file path. Defaults to the auto-installed CVR-aligned template.
-    #[arg(long)]
-    template: Option<PathBuf>,
+    #[command(
+        about = "Add, list, show and select named templates",
+        long_about = "Download (or copy) templates into ~/.config/mihomocli/templates under a name, record where they came from in templates.yaml, and pick the one merge uses by default. `merge --template <name>` also resolves these names."
+    )]
+    Template(TemplateArgs),
 
-    /// Optional base config to inherit fields/rules from (e.g., clash-verge.yaml).
-    #[arg(long)]
-    base_config: Option<PathBuf>,
+    #[command(
+        about = "Print the last merged config, or one section of it, as YAML or JSON",
+        long_about = "Print the config written by the last merge (or the file given with --config). --section limits the output to proxies, rules, proxy-groups or dns, so scripts can read one part without yq; --format json emits JSON instead of YAML."
+    )]
+    Show(ShowArgs),
 
-    /// Optional subscriptions YAML definition (defaults to ~/.config/mihomocli/subscriptions.yaml).
-    #[arg(long)]
-    subscriptions_file: Option<PathBuf>,
+    #[command(
+        about = "Query and steer the running mihomo through its external-controller API",
+        long_about = "Talk to the REST API of the running mihomo: print its version, list proxy groups with their current selection, switch a select group, or measure a proxy's delay. The controller address and secret come from the generated config (or --config); --controller and --secret override them, and the secret generated into app.yaml is used when the config has none."
+    )]
+    Ctl(CtlArgs),
 
-    /// Additional subscription sources (URL or file path). May be repeated.
-    #[arg(long = "subscription", short = 's')]
-    subscriptions: Vec<String>,
+    /// Search the proxies of the merged config
+    #[command(subcommand)]
+    Nodes(NodesCommand),
 
-    /// Output config file path. Defaults to ~/.config/mihomocli/output/clash-verge.yaml if omitted.
-    #[arg(long)]
-    output: Option<PathBuf>,
+    #[command(
+        about = "Parse a subscription payload and print its proxies, without merging",
+        long_about = "Run only the subscription parser on FILE (or standard input with --stdin) and print the detected format and every proxy with its type and server. When nothing parses, the error explains what each format tripped over (the YAML error of a broken Clash config, share links with an unsupported scheme, a base64 payload without --allow-base64), which is what a bug report about a provider format needs."
+    )]
+    Parse(ParseArgs),
 
-    /// Final Clash mode for the generated config.
-    #[arg(long = "mode", value_enum, default_value_t = ConfigMode::Rule)]
-    mode: ConfigMode,
+    #[command(
+        name = "export-links",
+        about = "Print the proxies of the merged config as ss://, vmess:// and trojan:// share links",
+        long_about = "Turn the proxies of the last merged config (or --config) back into share links, one per line, for phone clients that only import links. --filter and --type select proxies like `nodes find`; --format base64 wraps the list the way subscription servers serve it. Proxies whose type or transport has no share-link form are skipped with a note on stderr."
+    )]
+    ExportLinks(ExportLinksArgs),
 
-    /// Sniffer preset for transparent/TUN traffic.
-    #[arg(long = "sniffer-preset", value_enum, default_value_t = SnifferPreset::Tun)]
-    sniffer_preset: SnifferPreset,
+    #[command(
+        about = "Serve the merged config over HTTP as a subscription URL, optionally re-merging on a schedule",
+        long_about = "Answer GET requests for the merged config (or --config) with token auth, an ETag and the combined subscription-userinfo of your subscriptions, so routers and phones can import it like a provider subscription. With --interval the config is re-merged on that schedule (using the merge flags of --preset); concurrent fetches of one subscription are coalesced. On SIGINT/SIGTERM open requests and a running merge get --drain-timeout to finish."
+    )]
+    Serve(ServeArgs),
 
-    /// Also copy the generated YAML into the detected Clash Verge config.yaml.
-    #[arg(long = "sync-to-clash-verge", default_value_t = false)]
-    sync_to_clash_verge: bool,
+    /// Manage the mihomo-cli installation itself
+    #[command(name = "self", subcommand)]
+    SelfCmd(SelfCmd),
 
-    /// Also sync Tailscale-safe dns/profile settings into Clash Verge source files.
-    #[arg(long = "sync-to-clash-verge-sources", default_value_t = false)]
-    sync_to_clash_verge_sources: bool,
+    /// Manage the mihomo core binary used by `test` and `doctor`
+    #[command(subcommand)]
+    Core(CoreCommand),
 
-    /// Write merged config to stdout instead of a file.
-    #[arg(long)]
-    stdout: bool,
+    /// Turn hosts files and Adblock lists into rules for every merge
+    #[command(subcommand)]
+    Rules(RulesCommand),
 
-    /// Prepend common developer domains with proxy rules (GitHub, Docker, GCR, cache.nixos.org).
-    #[arg(long = "dev-rules", default_value_t = true)]
-    dev_rules: bool,
+    #[command(
+        about = "Start mihomo with the generated config",
+        long_about = "Start mihomo as `mihomo -d ~/.config/mihomocli -f ~/.config/mihomocli/output/clash-verge.yaml` (the binary is found like `test` finds it). By default mihomo runs in the background with its output appended to ~/.config/mihomocli/mihomo.log; --foreground keeps it attached and forwards Ctrl-C and SIGTERM to it. The pid is recorded in ~/.config/mihomocli/mihomo.pid for `stop` and `status`."
+    )]
+    Run(RunArgs),
 
-    /// Proxy group/tag used by generated dev rules when --dev-rules is set.
-    #[arg(long = "dev-rules-via", default_value = DEFAULT_DEV_RULE_VIA)]
-    dev_rules_via: String,
+    /// Stop the mihomo started by `run`
+    Stop,
 
-    /// Show the dev rule list that would be added (without modifying the result unless --dev-rules is set).
-    #[arg(long = "dev-rules-show", default_value_t = false)]
-    dev_rules_show: bool,
+    /// Report whether the mihomo started by `run` is alive (exit code 3 when it is not)
+    Status,
 
-    /// Reuse the cached last subscription URL when no -s/--subscription is provided.
-    /// If both are set, explicit subscriptions take precedence.
-    #[arg(long = "use-last", default_value_t = false)]
-    use_last: bool,
+    /// Run mihomo (and optionally a periodic merge) as a systemd or launchd service
+    #[command(subcommand)]
+    Service(ServiceCommand),
 
-    /// HTTP User-Agent used to fetch subscriptions (some providers vary output by UA).
-    /// Defaults to clash-verge UA to obtain Clash YAML with rules when available.
-    #[arg(long = "subscription-ua")]
-    subscription_ua: Option<String>,
+    /// Manage the geodata files in ~/.config/mihomocli/resources
+    #[command(subcommand)]
+    Resources(ResourcesCommand),
 
-    /// Allow decoding base64/subscription share-link lists when fetching subscriptions.
-    /// Disabled by default to prefer native Clash YAML from providers.
-    #[arg(long = "subscription-allow-base64", default_value_t = false)]
-    subscription_allow_base64: bool,
+    /// List previously generated configs and restore one of them
+    #[command(subcommand)]
+    History(HistoryCommand),
+}
 
-    /// Host/IP for external-controller (e.g., 0.0.0.0)
-    #[arg(long = "external-controller-url")]
-    external_controller_url: Option<String>,
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Show the configs kept in ~/.config/mihomocli/output/history, newest first (`*` marks
+    /// the one currently in place)
+    List,
 
-    /// Port for external-controller (e.g., 9090)
-    #[arg(long = "external-controller-port")]
-    external_controller_port: Option<u16>,
+    #[command(
+        about = "Restore a previous config and optionally reload or redeploy it",
+        long_about = "Copy the config with the given id (or a unique prefix of it, see `history list`) back to where it was written, or to --output. --reload and --deploy-api work like they do for `merge`, so the running mihomo can be switched back in the same step."
+    )]
+    Rollback(HistoryRollbackArgs),
+}
 
-    /// Secret used by external controller API
-    #[arg(long = "external-controller-secret")]
-    external_controller_secret: Option<String>,
+#[derive(Args)]
+struct HistoryRollbackArgs {
+    /// Id from `history list` (a unique prefix is enough)
+    id: String,
 
-    /// Append entries to dns.fake-ip-filter (to avoid DNS hijacking under fake-ip mode)
-    /// Example: --fake-ip-filter-add "+.example.com" --fake-ip-filter-add "hs.example.com"
-    #[arg(long = "fake-ip-filter-add")]
-    fake_ip_filter_add: Vec<String>,
+    /// Restore to this file instead of the path the config was originally written to
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
 
-    /// Set dns.fake-ip-filter-mode: blacklist|whitelist (only applies in fake-ip mode)
-    #[arg(long = "fake-ip-filter-mode")]
-    fake_ip_filter_mode: Option<String>,
+    /// Reload the running mihomo afterwards (same targets as `merge --reload`)
+    #[arg(long = "reload", value_name = "TARGET")]
+    reload: Option<ReloadTarget>,
 
-    /// Add CIDRs to tun.route-exclude-address (repeatable).
-    /// Useful for Kubernetes Pod/Service CIDRs to avoid tun DNS/service hijacking.
-    /// Defaults already include 10.42.0.0/16 and 10.43.0.0/16.
-    #[arg(long = "k8s-cidr-exclude")]
-    k8s_cidr_exclude: Vec<String>,
+    /// Push the restored config to a running mihomo via its external controller
+    #[arg(long = "deploy-api", value_name = "URL")]
+    deploy_api: Option<String>,
 
-    /// Add arbitrary CIDRs to tun.route-exclude-address (repeatable).
-    /// Use this to keep specific remote IPs/subnets out of mihomo TUN routing.
-    #[arg(long = "route-exclude-address-add")]
-    route_exclude_address_add: Vec<String>,
+    /// Controller secret for --deploy-api (defaults to the restored config's `secret`).
+    #[arg(long = "deploy-secret", requires = "deploy_api")]
+    deploy_secret: Option<String>,
 
-    /// Bypass fake-ip for specific domains/patterns (shorthand for adding to dns.fake-ip-filter in blacklist mode)
-    /// Example: --fake-ip-bypass '+.example.com' --fake-ip-bypass 'hs.example.com'
-    #[arg(long = "fake-ip-bypass")]
-    fake_ip_bypass: Vec<String>,
+    /// PEM CA certificate to trust when --deploy-api is an https:// URL.
+    #[arg(long = "deploy-ca-cert", value_name = "PEM", requires = "deploy_api")]
+    deploy_ca_cert: Option<PathBuf>,
+}
 
-    /// Do not write output; print a concise summary of the merged result
-    #[arg(long = "dry-run", default_value_t = false)]
-    dry_run: bool,
+#[derive(Subcommand)]
+enum ResourcesCommand {
+    #[command(
+        about = "Refresh Country.mmdb, geoip.dat and geosite.dat when upstream has changed",
+        long_about = "Compare each resource with the `.sha256sum` published next to it in MetaCubeX/meta-rules-dat (or, without one, ask the server whether the file changed since the local copy was written) and download the ones that differ or are missing. Downloads go through --resource-mirror, `resource_mirrors` in app.yaml or the GitHub mirrors, are checked against the checksum and the file format, and only then replace the old file. Prints the size, age and SHA-256 of every file."
+    )]
+    Update(ResourcesUpdateArgs),
+}
 
-    /// Keep fake-ip and tun compatible with Tailscale by avoiding fake-ip overlap,
-    /// bypassing Tailscale domains, and excluding tailnet CIDRs from tun routing.
-    #[arg(long = "tailscale-compatible", default_value_t = false)]
-    tailscale_compatible: bool,
+#[derive(Args)]
+struct ResourcesUpdateArgs {
+    /// Download every file even when it is current
+    #[arg(long, default_value_t = false)]
+    force: bool,
 
-    /// Tailnet DNS suffixes whose `tail.<suffix>` names should bypass fake-ip
-    /// and be forced DIRECT under --tailscale-compatible. Repeatable.
-    #[arg(long = "tailscale-tailnet-suffix")]
-    tailscale_tailnet_suffixes: Vec<String>,
+    /// Upstream proxy for the downloads (`direct` ignores the environment's proxy settings)
+    #[arg(long = "fetch-proxy", value_name = "URL")]
+    fetch_proxy: Option<String>,
 
-    /// Additional exact domains or domain suffixes that should bypass fake-ip
-    /// and be forced DIRECT under --tailscale-compatible. Repeatable.
-    /// Examples: --tailscale-direct-domain derp.example.com --tailscale-direct-domain +.corp.example.com
-    #[arg(long = "tailscale-direct-domain")]
-    tailscale_direct_domains: Vec<String>,
+    /// Download from this mirror instead of `resource_mirrors` in app.yaml: a base URL that
+    /// holds the files, or a template with `{asset}`/`{url}` (repeatable, tried in order)
+    #[arg(long = "resource-mirror", value_name = "URL")]
+    resource_mirrors: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum ServiceCommand {
+    #[command(
+        about = "Install and start mihomo as a systemd unit (Linux) or launchd job (macOS)",
+        long_about = "Write a systemd unit `mihomo-cli.service` (Linux) or a launchd job `io.github.mihomocli.mihomo` (macOS) that runs mihomo with the generated config, then enable and start it. --merge-interval adds a `mihomo-cli-merge` timer/job that runs `mihomo-cli merge --if-changed --reload api` that often. System services go to /etc/systemd/system or /Library/LaunchDaemons and usually need root; --user installs per-user ones instead. Installing again replaces the files."
+    )]
+    Install(ServiceInstallArgs),
+    /// Stop the installed service (and merge timer) and delete its files
+    Uninstall(ServiceScopeArgs),
+    /// Report whether the installed service is running (exit code 3 when it is not)
+    Status(ServiceScopeArgs),
 }
 
 #[derive(Args)]
-struct RefreshClashVergeArgs {
-    /// Explicit subscription URL. If omitted, the current Clash Verge remote subscription is used.
-    subscription: Option<String>,
+struct ServiceScopeArgs {
+    /// Per-user service (`systemctl --user`, ~/Library/LaunchAgents) instead of a system one
+    #[arg(long, default_value_t = false)]
+    user: bool,
+}
 
-    /// Final Clash mode for the generated config.
-    #[arg(long = "mode")]
-    mode: Option<ConfigMode>,
+#[derive(Args)]
+struct ServiceInstallArgs {
+    #[command(flatten)]
+    scope: ServiceScopeArgs,
 
-    /// Sniffer preset for transparent/TUN traffic.
-    #[arg(long = "sniffer-preset")]
-    sniffer_preset: Option<SnifferPreset>,
+    /// Path to mihomo binary (defaults to `mihomo` in PATH, then the one from `core install`)
+    #[arg(long = "mihomo-bin")]
+    mihomo_bin: Option<String>,
 
-    /// Disable the Tailscale compatibility patch set.
-    #[arg(long = "no-tailscale-compatible", default_value_t = false)]
-    no_tailscale_compatible: bool,
+    /// Config to run (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    /// Tailnet DNS suffixes whose `tail.<suffix>` names should bypass fake-ip and be forced DIRECT.
-    #[arg(long = "tailscale-tailnet-suffix")]
-    tailscale_tailnet_suffixes: Vec<String>,
+    /// Working directory passed to mihomo via -d (defaults to ~/.config/mihomocli)
+    #[arg(long = "mihomo-dir")]
+    mihomo_dir: Option<PathBuf>,
 
-    /// Additional domains or suffixes that should bypass fake-ip and be forced DIRECT.
-    #[arg(long = "tailscale-direct-domain")]
-    tailscale_direct_domains: Vec<String>,
+    /// Also regenerate the config this often, e.g. `6h` (plain numbers are seconds)
+    #[arg(long = "merge-interval", value_name = "DURATION", value_parser = parse_interval)]
+    merge_interval: Option<std::time::Duration>,
 
-    /// Additional CIDRs that should stay out of the Mihomo TUN.
-    #[arg(long = "route-exclude-address-add")]
-    route_exclude_address_add: Vec<String>,
+    /// Extra argument for the periodic merge, repeatable (`--merge-arg=--profile --merge-arg=router`)
+    #[arg(
+        long = "merge-arg",
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        requires = "merge_interval"
+    )]
+    merge_args: Vec<String>,
 
-    /// Preview the resolved merge without writing files.
-    #[arg(long = "dry-run", default_value_t = false)]
-    dry_run: bool,
+    /// Write and enable the service without starting it now
+    #[arg(long = "no-start", default_value_t = false)]
+    no_start: bool,
 }
 
 #[derive(Args)]
-struct RuntimeArgs {
-    #[command(subcommand)]
-    command: RuntimeCommand,
+struct RunArgs {
+    /// Stay attached to mihomo instead of starting it in the background
+    #[arg(long, default_value_t = false)]
+    foreground: bool,
+
+    /// Path to mihomo binary (defaults to `mihomo` in PATH, then the one from `core install`)
+    #[arg(long = "mihomo-bin")]
+    mihomo_bin: Option<String>,
+
+    /// Config to run (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Working directory passed to mihomo via -d (defaults to ~/.config/mihomocli)
+    #[arg(long = "mihomo-dir")]
+    mihomo_dir: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
-enum RuntimeCommand {
-    /// Show detected runtime config/controller state
-    Status(DoctorArgs),
-    /// Reload the running Mihomo process from the detected runtime file
-    Reload,
-    /// Update the detected runtime files to a specific Clash mode and reload
-    Mode(RuntimeModeArgs),
+enum RulesCommand {
+    #[command(
+        about = "Import a hosts file or Adblock/AdGuard list as block rules",
+        long_about = "Convert a hosts file (`0.0.0.0 ads.example.com`) or an Adblock/AdGuard DNS list (`||ads.example.com^`) into domain rules routed to --via (REJECT by default). By default the domains are stored in ~/.config/mihomocli/rules/<name>.yaml and every merge adds them as an inline rule-provider with a RULE-SET rule right after the custom rules; --inline adds them as custom rules instead. Domains already covered by a custom rule or a rule of the last merged config are skipped. Importing under the same name again replaces the list."
+    )]
+    Import(RulesImportArgs),
+    /// Stop merging an imported rule set and delete its file
+    Remove(RulesRemoveArgs),
 }
 
 #[derive(Args)]
-struct RuntimeModeArgs {
-    /// Target Clash mode for the local runtime config.
-    #[arg(value_enum)]
-    mode: ConfigMode,
+struct RulesImportArgs {
+    /// File path or http(s) URL of the list
+    source: String,
+
+    #[arg(long, value_enum)]
+    format: BlocklistFormatArg,
+
+    /// Proxy, group or policy the listed domains are routed to
+    #[arg(long, default_value = "REJECT")]
+    via: String,
+
+    /// Rule-provider name (defaults to the file name without extension)
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Add the domains as custom rules (see `manage custom list`) instead of a rule-provider
+    #[arg(long, default_value_t = false)]
+    inline: bool,
+
+    /// Upstream proxy for the download (`direct` ignores the environment's proxy settings)
+    #[arg(long = "fetch-proxy", value_name = "URL")]
+    fetch_proxy: Option<String>,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
-enum ConfigMode {
-    Rule,
-    Global,
-    Direct,
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum BlocklistFormatArg {
+    Hosts,
+    Adblock,
 }
 
-impl ConfigMode {
-    fn as_str(self) -> &'static str {
-        match self {
-            Self::Rule => "rule",
-            Self::Global => "global",
-            Self::Direct => "direct",
-        }
-    }
+#[derive(Args)]
+struct RulesRemoveArgs {
+    name: String,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
-enum SnifferPreset {
-    Off,
-    Tun,
+#[derive(Subcommand)]
+enum CoreCommand {
+    #[command(
+        about = "Download the mihomo release for this platform into ~/.config/mihomocli/bin",
+        long_about = "Download the MetaCubeX/mihomo release asset for this OS and architecture, verify its SHA-256 against the digest GitHub publishes for the asset, and install it as ~/.config/mihomocli/bin/mihomo. `test` and `doctor` use that binary when `mihomo` is not in PATH. Run it again to update."
+    )]
+    Install(CoreInstallArgs),
 }
 
 #[derive(Args)]
-struct DoctorArgs {
-    /// Include a short sample of current live controller connections when available.
-    #[arg(long = "show-connections", default_value_t = true)]
-    show_connections: bool,
+struct CoreInstallArgs {
+    /// Install this release tag (e.g. v1.19.3) instead of the latest one.
+    #[arg(long)]
+    version: Option<String>,
 
-    /// Domains to highlight in live connections.
-    #[arg(long = "focus-domain")]
-    focus_domains: Vec<String>,
+    /// Reinstall even when the managed binary already has this version.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+
+    /// Upstream proxy for the downloads (`direct` ignores the environment's proxy settings)
+    #[arg(long = "fetch-proxy", value_name = "URL")]
+    fetch_proxy: Option<String>,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    init_tracing();
+#[derive(Args)]
+struct ShowArgs {
+    /// Only print this top-level section (`groups` is short for `proxy-groups`)
+    #[arg(value_enum, value_name = "SECTION", conflicts_with = "section")]
+    what: Option<ShowSection>,
 
-    let cli = Cli::parse();
+    /// Only print this top-level section
+    #[arg(long, value_enum)]
+    section: Option<ShowSection>,
 
-    match cli.command {
-        Commands::Merge(args) => run_merge(args).await?,
-        Commands::RefreshClashVerge(args) => run_refresh_clash_verge(args).await?,
-        Commands::Runtime(args) => run_runtime(args).await?,
-        Commands::Manage(cmd) => run_manage(cmd).await?,
-        Commands::Test(args) => run_test(args).await?,
-        Commands::Init => run_init().await?,
-        Commands::Doctor(args) => run_doctor(args).await?,
-    }
+    /// With `groups`: print each group's final members as mihomo resolves them
+    /// (`include-all`, `filter`, `exclude-filter`, `exclude-type`) instead of the YAML
+    #[arg(long, default_value_t = false)]
+    expanded: bool,
 
-    Ok(())
-}
+    #[arg(long, value_enum, default_value_t = ShowFormat::Yaml)]
+    format: ShowFormat,
 
-async fn run_init() -> anyhow::Result<()> {
-    let paths = AppPaths::new()?;
-    // Create runtime directories (config, templates, resources, output, cache)
-    paths.ensure_runtime_dirs().await?;
-    // Install bundled default template if missing
-    ensure_default_template(&paths).await?;
+    /// Config to read (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    println!(
-        "Initialized at: {}\n  - templates: {}\n  - resources: {}\n  - output: {}\n  - cache: {}",
-        paths.config_dir().display(),
-        paths.templates_dir().display(),
-        paths.resources_dir().display(),
-        paths
-            .output_config_path()
-            .parent()
-            .map(|p| p.display().to_string())
-            .unwrap_or_else(|| "<unknown>".into()),
-        paths.cache_dir().display()
-    );
+    /// Mask credentials (see `merge --redact-secrets`)
+    #[arg(long = "redact-secrets", default_value_t = false)]
+    redact_secrets: bool,
+}
 
-    Ok(())
+#[derive(Subcommand)]
+enum NodesCommand {
+    /// Print proxies whose name or server matches PATTERN, with the groups that list them
+    Find(NodesFindArgs),
 }
 
-#[derive(Debug, Deserialize)]
-struct ClashVergeProfiles {
-    current: Option<String>,
-    #[serde(default)]
-    items: Vec<ClashVergeProfileItem>,
+#[derive(Args)]
+struct NodesFindArgs {
+    /// Case-insensitive regex, e.g. "HK|香港"
+    pattern: String,
+
+    /// Only proxies of these types (trojan, vmess, ss, ...); comma-separated or repeated
+    #[arg(long = "type", value_delimiter = ',')]
+    types: Vec<String>,
+
+    /// Config to search (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Also print each matching proxy entry as YAML
+    #[arg(long, default_value_t = false)]
+    full: bool,
 }
 
-#[derive(Debug, Deserialize)]
-struct ClashVergeProfileItem {
-    uid: Option<String>,
-    url: Option<String>,
+#[derive(Args)]
+struct ParseArgs {
+    /// File holding the payload (as the provider serves it)
+    #[arg(required_unless_present = "stdin")]
+    file: Option<PathBuf>,
+
+    /// Read the payload from standard input
+    #[arg(long, default_value_t = false, conflicts_with = "file")]
+    stdin: bool,
+
+    /// Also decode base64-wrapped payloads, like `merge --subscription-allow-base64`
+    #[arg(long = "allow-base64", default_value_t = false)]
+    allow_base64: bool,
 }
 
-async fn run_refresh_clash_verge(args: RefreshClashVergeArgs) -> anyhow::Result<()> {
-    let paths = AppPaths::new()?;
-    paths.ensure_runtime_dirs().await?;
-    let app_cfg = storage::load_app_config(&paths).await?;
+#[derive(Args)]
+struct ExportLinksArgs {
+    /// Only proxies whose name or server matches this case-insensitive regex
+    #[arg(long)]
+    filter: Option<String>,
 
-    let subscription = if let Some(subscription) = args.subscription.clone() {
-        subscription
-    } else {
-        detect_current_clash_verge_subscription_url(&paths).await?
-    };
+    /// Only proxies of these types (trojan, vmess, ss); comma-separated or repeated
+    #[arg(long = "type", value_delimiter = ',')]
+    types: Vec<String>,
 
-    let mode = args
-        .mode
-        .or_else(|| config_mode_from_env("MIHOMOCLI_MODE"))
-        .unwrap_or(ConfigMode::Rule);
-    let sniffer_preset = args
-        .sniffer_preset
-        .or_else(|| sniffer_preset_from_env("MIHOMOCLI_SNIFFER_PRESET"))
-        .unwrap_or(SnifferPreset::Tun);
+    /// Config to read (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    let managed_tailnet_suffixes = app_cfg
-        .managed_tailscale_compat
-        .as_ref()
-        .map(derive_tailnet_suffixes_from_managed)
-        .unwrap_or_default();
-    let managed_direct_domains = app_cfg
-        .managed_tailscale_compat
-        .as_ref()
-        .map(derive_direct_domains_from_managed)
-        .unwrap_or_default();
-    let managed_route_excludes = app_cfg
-        .managed_tailscale_compat
-        .as_ref()
-        .map(derive_extra_route_excludes_from_managed)
-        .unwrap_or_default();
+    #[arg(long, value_enum, default_value_t = LinkFormat::Plain)]
+    format: LinkFormat,
+}
 
-    let configured_tailnet_suffixes = app_cfg
-        .tailscale_compat_defaults
-        .as_ref()
-        .map(|defaults| defaults.tailnet_suffixes.clone())
-        .filter(|items| !items.is_empty())
-        .unwrap_or(managed_tailnet_suffixes);
-    let configured_direct_domains = app_cfg
-        .tailscale_compat_defaults
-        .as_ref()
-        .map(|defaults| defaults.direct_domains.clone())
-        .filter(|items| !items.is_empty())
-        .unwrap_or(managed_direct_domains);
-    let configured_route_excludes = app_cfg
-        .tailscale_compat_defaults
-        .as_ref()
-        .map(|defaults| defaults.route_exclude_address.clone())
-        .filter(|items| !items.is_empty())
-        .unwrap_or(managed_route_excludes);
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on, e.g. 0.0.0.0:8199 to serve the LAN
+    #[arg(long, default_value = "127.0.0.1:8199")]
+    listen: std::net::SocketAddr,
 
-    let tailnet_suffixes = merge_string_lists(
-        configured_tailnet_suffixes,
-        env_list("MIHOMOCLI_TAILSCALE_SUFFIXES"),
-        env_list("MIHOMOCLI_TAILSCALE_SUFFIX"),
-        args.tailscale_tailnet_suffixes,
-    );
-    let direct_domains = merge_string_lists(
-        configured_direct_domains,
-        env_list("MIHOMOCLI_TAILSCALE_DIRECT_DOMAINS"),
-        Vec::new(),
-        args.tailscale_direct_domains,
-    );
-    let direct_cidrs = merge_string_lists(
-        configured_route_excludes,
-        env_list("MIHOMOCLI_TAILSCALE_DIRECT_CIDRS"),
-        Vec::new(),
-        args.route_exclude_address_add,
-    );
+    /// Token clients must send as `?token=` or `Authorization: Bearer`; accepts a
+    /// `keyring:<name>` reference
+    #[arg(long)]
+    token: String,
 
-    println!("Refreshing generated Clash Verge config from subscription:");
-    println!("  {}", subscription);
-    println!("Using Tailscale tailnet suffixes:");
-    println!(
-        "  {}",
-        if tailnet_suffixes.is_empty() {
-            "<none>".to_string()
-        } else {
-            tailnet_suffixes.join(", ")
-        }
-    );
-    println!("Using extra Tailscale direct domains:");
-    println!(
-        "  {}",
-        if direct_domains.is_empty() {
-            "<none>".to_string()
-        } else {
-            direct_domains.join(", ")
-        }
-    );
-    println!("Using extra Tailscale route-exclude CIDRs:");
-    println!(
-        "  {}",
-        if direct_cidrs.is_empty() {
-            "<none>".to_string()
-        } else {
-            direct_cidrs.join(", ")
-        }
-    );
-    println!("Using Clash mode:");
-    println!("  {}", mode.as_str());
-    println!("Using sniffer preset:");
-    println!(
-        "  {}",
-        match sniffer_preset {
-            SnifferPreset::Off => "off",
-            SnifferPreset::Tun => "tun",
-        }
-    );
+    /// Re-merge this often, e.g. `30m`, `6h` (plain numbers are seconds); without it the
+    /// file written by the last merge is served as-is
+    #[arg(long, value_name = "DURATION", value_parser = parse_interval)]
+    interval: Option<std::time::Duration>,
 
-    let merge_args = MergeArgs {
-        template: None,
-        base_config: None,
-        subscriptions_file: None,
-        subscriptions: vec![subscription],
-        output: None,
-        mode,
-        sniffer_preset,
-        sync_to_clash_verge: true,
-        sync_to_clash_verge_sources: true,
-        stdout: false,
-        dev_rules: true,
-        dev_rules_via: DEFAULT_DEV_RULE_VIA.to_string(),
-        dev_rules_show: false,
-        use_last: false,
-        subscription_ua: None,
-        subscription_allow_base64: false,
-        external_controller_url: None,
-        external_controller_port: None,
-        external_controller_secret: None,
-        fake_ip_filter_add: Vec::new(),
-        fake_ip_filter_mode: None,
-        k8s_cidr_exclude: Vec::new(),
-        route_exclude_address_add: direct_cidrs,
-        fake_ip_bypass: Vec::new(),
-        dry_run: args.dry_run,
-        tailscale_compatible: !args.no_tailscale_compatible,
-        tailscale_tailnet_suffixes: tailnet_suffixes,
-        tailscale_direct_domains: direct_domains,
-    };
+    /// Merge flags from `presets` in app.yaml for the scheduled merges
+    #[arg(long, value_name = "NAME", requires = "interval")]
+    preset: Option<String>,
 
-    run_merge(merge_args).await
+    /// Config to serve (defaults to ~/.config/mihomocli/output/clash-verge.yaml); scheduled
+    /// merges write here
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// On SIGINT/SIGTERM, how long to let a running merge and open requests finish
+    #[arg(
+        long = "drain-timeout",
+        value_name = "DURATION",
+        default_value = "30s",
+        value_parser = parse_interval
+    )]
+    drain_timeout: std::time::Duration,
 }
 
-async fn run_runtime(args: RuntimeArgs) -> anyhow::Result<()> {
-    let paths = AppPaths::new()?;
-    paths.ensure_runtime_dirs().await?;
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum LinkFormat {
+    /// One link per line
+    Plain,
+    /// The link lines as one base64 blob, like a subscription payload
+    Base64,
+}
 
-    match args.command {
-        RuntimeCommand::Status(args) => run_runtime_status(&paths, &args).await,
-        RuntimeCommand::Reload => run_runtime_reload(&paths).await,
-        RuntimeCommand::Mode(args) => run_runtime_mode(&paths, args.mode).await,
-    }
+#[derive(Args)]
+struct CtlArgs {
+    #[command(flatten)]
+    target: ControllerTarget,
+
+    #[command(subcommand)]
+    command: CtlCommand,
 }
 
-async fn run_runtime_status(paths: &AppPaths, args: &DoctorArgs) -> anyhow::Result<()> {
-    let runtime_paths = existing_runtime_paths(paths).await;
-    let runtime_summaries = load_runtime_summaries(&runtime_paths).await;
+#[derive(Args)]
+struct ControllerTarget {
+    /// Controller base URL, e.g. http://127.0.0.1:9090, https://router:9443 or
+    /// unix:///var/run/mihomo.sock (defaults to the config's external-controller)
+    #[arg(long, global = true)]
+    controller: Option<String>,
+
+    /// PEM CA certificate to trust for an https:// controller with a self-signed certificate
+    #[arg(long = "ca-cert", value_name = "PEM", global = true)]
+    ca_cert: Option<PathBuf>,
+
+    /// Controller secret (defaults to the config's `secret`, then app.yaml's
+    /// `controller_secret`)
+    #[arg(long, global = true)]
+    secret: Option<String>,
 
-    println!("mihomo-cli runtime status");
-    println!();
+    /// Config to read the controller from (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+}
 
-    if runtime_summaries.is_empty() {
-        println!("Runtime files:");
-        println!("  status: no local runtime files detected");
-        return Ok(());
-    }
+#[derive(Subcommand)]
+enum CtlCommand {
+    /// Print the version of the running mihomo
+    Version,
+    /// List proxy groups with their type and current selection
+    Groups(CtlGroupsArgs),
+    /// Make PROXY the selection of the select group GROUP
+    Select(CtlSelectArgs),
+    /// Print (or save) the current selection of every select group as YAML `GROUP: PROXY`
+    DumpSelections(CtlDumpSelectionsArgs),
+    /// Select the node of every group listed in a file written by dump-selections
+    ApplySelections(CtlApplySelectionsArgs),
+    /// Measure the delay of a proxy (or group) through the controller
+    Delay(CtlDelayArgs),
+    /// Print live upload/download rates, one line per second
+    Traffic(CtlTrafficArgs),
+    /// List active connections, or close them with --kill
+    Connections(CtlConnectionsArgs),
+    /// Stream the core's log until interrupted
+    Logs(CtlLogsArgs),
+}
 
-    println!("Runtime files:");
-    for summary in &runtime_summaries {
-        print_runtime_summary(summary);
-    }
+#[derive(Args)]
+struct CtlLogsArgs {
+    /// Lowest level to stream
+    #[arg(long, value_enum, default_value_t = CtlLogLevel::Info)]
+    level: CtlLogLevel,
 
-    if runtime_summaries.len() >= 2 {
-        let primary = &runtime_summaries[0];
-        let aligned = runtime_summaries[1..]
-            .iter()
-            .all(|candidate| runtime_summary_core_eq(primary, candidate));
-        println!(
-            "  file-state-aligned: {}",
-            if aligned { "yes" } else { "no" }
-        );
-    }
+    /// Only lines whose message matches this regex, e.g. "youtube|googlevideo"
+    #[arg(long)]
+    filter: Option<String>,
 
-    if let Some(source_summary) = load_merge_profile_summary(paths).await {
-        println!("Clash Verge source profile:");
-        print_runtime_summary(&source_summary);
-    }
+    /// Print the controller's JSON lines as they arrive
+    #[arg(long, default_value_t = false)]
+    json: bool,
 
-    println!();
-    if let Some(controller) = runtime_summaries
-        .iter()
-        .find_map(|summary| summary.controller.clone())
-    {
-        print_controller_summary(&controller, args);
-    } else {
-        println!("Controller:");
-        println!("  status: unavailable (no controller settings found in local config)");
-    }
+    /// Never colorize levels (they are colorized on a terminal unless NO_COLOR is set)
+    #[arg(long = "no-color", default_value_t = false)]
+    no_color: bool,
+}
 
-    Ok(())
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum CtlLogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
 }
 
-async fn run_runtime_reload(paths: &AppPaths) -> anyhow::Result<()> {
-    let runtime_paths = existing_runtime_paths(paths).await;
-    let primary_path = preferred_runtime_path(&runtime_paths)
-        .ok_or_else(|| anyhow!("no local Clash Verge runtime config was detected"))?;
-    let cfg = load_runtime_config(primary_path).await?;
-
-    reload_clash_verge_runtime(&cfg, &runtime_paths).await?;
-    println!("runtime reload completed from {}", primary_path.display());
-    Ok(())
-}
-
-async fn run_runtime_mode(paths: &AppPaths, mode: ConfigMode) -> anyhow::Result<()> {
-    let runtime_paths = existing_runtime_paths(paths).await;
-    let primary_path = preferred_runtime_path(&runtime_paths)
-        .ok_or_else(|| anyhow!("no local Clash Verge runtime config was detected"))?
-        .clone();
-
-    let mut reload_cfg: Option<mihomo_core::ClashConfig> = None;
-    for path in &runtime_paths {
-        let mut cfg = load_runtime_config(path).await?;
-        apply_mode_override(&mut cfg, mode);
-        fs::write(path, cfg.to_yaml_string()?)
-            .await
-            .with_context(|| format!("failed to write runtime mode to {}", path.display()))?;
-        println!("updated runtime mode in {}", path.display());
-        if *path == primary_path {
-            reload_cfg = Some(cfg);
+impl CtlLogLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            CtlLogLevel::Debug => "debug",
+            CtlLogLevel::Info => "info",
+            CtlLogLevel::Warning => "warning",
+            CtlLogLevel::Error => "error",
         }
     }
+}
 
-    if let Some(merge_path) = paths.detected_clash_verge_profile_merge_path() {
-        if fs::try_exists(&merge_path).await.unwrap_or(false) {
-            sync_merge_profile_mode(&merge_path, mode).await?;
-        }
-    }
+#[derive(Args)]
+struct CtlTrafficArgs {
+    /// Stop after this many samples (default: run until interrupted)
+    #[arg(long)]
+    count: Option<u64>,
 
-    let reload_cfg = reload_cfg.ok_or_else(|| {
-        anyhow!(
-            "failed to prepare reload config from preferred runtime path {}",
-            primary_path.display()
-        )
-    })?;
-    reload_clash_verge_runtime(&reload_cfg, &runtime_paths).await?;
-    println!("runtime mode is now {}", mode.as_str());
-    Ok(())
+    /// Print the controller's JSON samples as they arrive
+    #[arg(long, default_value_t = false)]
+    json: bool,
 }
 
-async fn detect_current_clash_verge_subscription_url(paths: &AppPaths) -> anyhow::Result<String> {
-    let profiles_path = paths
-        .detected_clash_verge_profiles_path()
-        .ok_or_else(|| anyhow!("could not detect Clash Verge profiles.yaml path"))?;
+#[derive(Args)]
+struct CtlConnectionsArgs {
+    /// Only connections whose id, host, process, chain or rule contains this text
+    /// (case-insensitive)
+    #[arg(long)]
+    filter: Option<String>,
 
-    let raw = fs::read_to_string(&profiles_path)
-        .await
-        .with_context(|| format!("failed to read {}", profiles_path.display()))?;
-    let profiles: ClashVergeProfiles = serde_yaml::from_str(&raw)
-        .with_context(|| format!("failed to parse {}", profiles_path.display()))?;
+    /// Close the listed connections (all of them without --filter)
+    #[arg(long, default_value_t = false)]
+    kill: bool,
 
-    extract_current_clash_verge_subscription_url(&profiles).ok_or_else(|| {
-        anyhow!(
-            "no current Clash Verge remote subscription URL found in {}",
-            profiles_path.display()
-        )
-    })
+    /// Print the controller's JSON (filtered entries only with --filter)
+    #[arg(long, default_value_t = false)]
+    json: bool,
 }
 
-fn extract_current_clash_verge_subscription_url(profiles: &ClashVergeProfiles) -> Option<String> {
-    let current = profiles.current.as_ref()?;
-    profiles
-        .items
-        .iter()
-        .find(|item| item.uid.as_deref() == Some(current.as_str()))
-        .and_then(|item| item.url.as_ref())
-        .map(|url| url.trim().to_string())
-        .filter(|url| !url.is_empty())
+#[derive(Args)]
+struct CtlGroupsArgs {
+    /// Also list each group's members, marking the current one with `*`
+    #[arg(long, default_value_t = false)]
+    members: bool,
 }
 
-fn env_list(name: &str) -> Vec<String> {
-    std::env::var(name)
-        .ok()
-        .map(|raw| split_list_items(&raw))
-        .unwrap_or_default()
+#[derive(Args)]
+struct CtlSelectArgs {
+    group: String,
+    proxy: String,
 }
 
-fn split_list_items(raw: &str) -> Vec<String> {
-    raw.split(|ch: char| ch == ',' || ch.is_whitespace())
-        .filter(|item| !item.is_empty())
-        .map(|item| item.to_string())
-        .collect()
+#[derive(Args)]
+struct CtlDumpSelectionsArgs {
+    /// Write the selections to this file instead of stdout
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
 }
 
-fn merge_string_lists(
-    primary: Vec<String>,
-    secondary: Vec<String>,
-    tertiary: Vec<String>,
-    explicit: Vec<String>,
-) -> Vec<String> {
-    let mut merged = Vec::new();
-    extend_unique(&mut merged, primary);
-    extend_unique(&mut merged, secondary);
-    extend_unique(&mut merged, tertiary);
-    extend_unique(&mut merged, explicit);
-    merged
+#[derive(Args)]
+struct CtlApplySelectionsArgs {
+    /// YAML mapping of group name to proxy name, e.g. from dump-selections
+    file: PathBuf,
 }
 
-fn extend_unique(dest: &mut Vec<String>, values: Vec<String>) {
-    for value in values {
-        if !dest.iter().any(|existing| existing == &value) {
-            dest.push(value);
-        }
-    }
+#[derive(Args)]
+struct CtlDelayArgs {
+    proxy: String,
+
+    /// URL fetched through the proxy
+    #[arg(long, default_value = DEFAULT_DELAY_URL)]
+    url: String,
+
+    #[arg(long = "timeout-ms", default_value_t = 5000)]
+    timeout_ms: u64,
 }
 
-fn config_mode_from_env(name: &str) -> Option<ConfigMode> {
-    match std::env::var(name)
-        .ok()?
-        .trim()
-        .to_ascii_lowercase()
-        .as_str()
-    {
-        "rule" => Some(ConfigMode::Rule),
-        "global" => Some(ConfigMode::Global),
-        "direct" => Some(ConfigMode::Direct),
-        other => {
-            warn!(env = %name, value = %other, "ignoring invalid config mode from environment");
-            None
-        }
-    }
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ShowSection {
+    Proxies,
+    Rules,
+    #[value(alias = "groups")]
+    ProxyGroups,
+    Dns,
 }
 
-fn sniffer_preset_from_env(name: &str) -> Option<SnifferPreset> {
-    match std::env::var(name)
-        .ok()?
-        .trim()
-        .to_ascii_lowercase()
-        .as_str()
-    {
-        "tun" => Some(SnifferPreset::Tun),
-        "off" => Some(SnifferPreset::Off),
-        other => {
-            warn!(env = %name, value = %other, "ignoring invalid sniffer preset from environment");
-            None
+impl ShowSection {
+    fn key(self) -> &'static str {
+        match self {
+            Self::Proxies => "proxies",
+            Self::Rules => "rules",
+            Self::ProxyGroups => "proxy-groups",
+            Self::Dns => "dns",
         }
     }
 }
 
-fn derive_tailnet_suffixes_from_managed(managed: &ManagedTailscaleCompat) -> Vec<String> {
-    managed
-        .fake_ip_filter
-        .iter()
-        .filter_map(|entry| entry.strip_prefix("+.tail."))
-        .map(|suffix| suffix.to_string())
-        .collect()
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ShowFormat {
+    Yaml,
+    Json,
 }
 
-fn derive_direct_domains_from_managed(managed: &ManagedTailscaleCompat) -> Vec<String> {
-    let mut domains = Vec::new();
+#[derive(Args)]
+struct TemplateArgs {
+    #[command(subcommand)]
+    command: TemplateCommand,
+}
 
-    for rule in &managed.rules {
-        if TAILSCALE_BASE_DIRECT_RULES.contains(&rule.as_str()) {
-            continue;
-        }
+#[derive(Subcommand)]
+enum TemplateCommand {
+    /// Download or copy a template and store it under a name
+    Add(TemplateAddArgs),
+    /// List stored templates; `*` marks the one merge uses by default
+    List,
+    /// Print a stored template (`default` is the bundled one)
+    Show(TemplateShowArgs),
+    /// Make a stored template the default for merge (`default` restores the bundled one)
+    Use(TemplateNameArgs),
+}
 
-        let mut parts = rule.splitn(3, ',');
-        let Some(kind) = parts.next() else {
-            continue;
-        };
-        let Some(target) = parts.next() else {
-            continue;
-        };
-        let Some(via) = parts.next() else {
-            continue;
-        };
-        if !via.eq_ignore_ascii_case("DIRECT") {
-            continue;
-        }
+#[derive(Args)]
+struct TemplateAddArgs {
+    /// Template URL or file path
+    source: String,
 
-        match kind {
-            "DOMAIN" => {
-                if !target.is_empty() {
-                    domains.push(target.to_string());
-                }
-            }
-            "DOMAIN-SUFFIX" => {
-                if !target.starts_with("tail.")
-                    && !target.eq_ignore_ascii_case("tailscale.com")
-                    && !target.eq_ignore_ascii_case("ts.net")
-                {
-                    domains.push(format!("+.{}", target));
-                }
-            }
-            _ => {}
-        }
-    }
+    /// Name to store it under (default: the file name without extension)
+    #[arg(long)]
+    name: Option<String>,
 
-    let mut deduped = Vec::new();
-    extend_unique(&mut deduped, domains);
-    deduped
-}
+    /// Replace a template of the same name that was added from a different source
+    #[arg(long, default_value_t = false)]
+    force: bool,
 
-fn derive_extra_route_excludes_from_managed(managed: &ManagedTailscaleCompat) -> Vec<String> {
-    managed
-        .route_exclude_address
-        .iter()
-        .filter(|entry| !TAILSCALE_ROUTE_EXCLUDES.contains(&entry.as_str()))
-        .cloned()
-        .collect()
+    /// Upstream proxy for the download (`direct` ignores the environment's proxy settings)
+    #[arg(long = "fetch-proxy", value_name = "URL")]
+    fetch_proxy: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-struct RuntimeSummary {
-    path: PathBuf,
-    mode: Option<String>,
-    tun_enabled: Option<bool>,
-    tun_auto_route: Option<bool>,
-    sniffer_enabled: bool,
-    enhanced_mode: Option<String>,
-    fake_ip_range: Option<String>,
-    route_excludes: Vec<String>,
-    rules_count: usize,
-    controller: Option<ControllerEndpoint>,
+#[derive(Args)]
+struct TemplateNameArgs {
+    name: String,
 }
 
-#[derive(Debug, Clone)]
-struct ControllerEndpoint {
-    host: Option<String>,
-    port: Option<u16>,
-    secret: Option<String>,
-    unix_socket: Option<String>,
-}
+#[derive(Args)]
+struct TemplateShowArgs {
+    name: String,
 
-#[derive(Debug, Clone)]
-struct ConnectionRecord {
-    host: Option<String>,
-    inbound_name: Option<String>,
-    source_ip: Option<String>,
-    chains: Vec<String>,
+    /// Mask credentials (see `merge --redact-secrets`); comments are not preserved
+    #[arg(long = "redact-secrets", default_value_t = false)]
+    redact_secrets: bool,
 }
 
-#[derive(Debug, Clone)]
-struct ProxyEntry {
-    kind: &'static str,
-    host: String,
-    port: String,
+#[derive(Subcommand)]
+enum SelfCmd {
+    #[command(
+        about = "Update mihomo-cli from the latest GitHub release",
+        long_about = "Check the project's GitHub releases, download the archive for this platform, verify it against the published .sha256 file, and replace the running executable. Intended for installs from release archives; cargo/nix installs should update through their own tooling."
+    )]
+    Update(SelfUpdateArgs),
 }
 
-async fn run_doctor(args: DoctorArgs) -> anyhow::Result<()> {
-    let paths = AppPaths::new()?;
-    paths.ensure_runtime_dirs().await?;
+#[derive(Args)]
+struct SelfUpdateArgs {
+    /// Only report whether an update is available.
+    #[arg(long, default_value_t = false)]
+    check: bool,
 
-    let runtime_paths = paths.detected_clash_verge_runtime_config_paths();
-    let runtime_summaries = load_runtime_summaries(&runtime_paths).await;
+    /// Install this release tag (e.g. v0.2.0) instead of the latest one.
+    #[arg(long)]
+    version: Option<String>,
 
-    println!("mihomo-cli doctor");
-    println!();
+    /// Reinstall even when the release is not newer than the running version.
+    #[arg(long, default_value_t = false)]
+    force: bool,
 
-    if runtime_summaries.is_empty() {
-        println!("Clash Verge runtime files:");
-        println!("  status: no local runtime files detected");
-    } else {
-        println!("Clash Verge runtime files:");
-        for summary in &runtime_summaries {
-            print_runtime_summary(summary);
-        }
+    /// Upstream proxy for the downloads (`direct` ignores the environment's proxy settings)
+    #[arg(long = "fetch-proxy", value_name = "URL")]
+    fetch_proxy: Option<String>,
+}
 
-        if runtime_summaries.len() >= 2 {
-            let primary = &runtime_summaries[0];
-            let aligned = runtime_summaries[1..]
-                .iter()
-                .all(|candidate| runtime_summary_core_eq(primary, candidate));
-            println!(
-                "  file-state-aligned: {}",
-                if aligned { "yes" } else { "no" }
-            );
-        }
-    }
+#[derive(Args)]
+struct UiArgs {
+    #[command(subcommand)]
+    command: UiCommand,
+}
 
-    println!();
-    print_system_proxy_summary();
-    println!();
-    print_tailscale_summary();
-    println!();
+#[derive(Subcommand)]
+enum UiCommand {
+    /// Download a dashboard and enable it for future merges
+    Install(UiInstallArgs),
+    /// Open the dashboard of the generated config in a browser
+    Open(UiOpenArgs),
+}
 
-    if let Some(controller) = runtime_summaries
-        .iter()
-        .find_map(|summary| summary.controller.clone())
-    {
-        print_controller_summary(&controller, &args);
-    } else {
-        println!("Controller:");
-        println!("  status: unavailable (no controller settings found in local config)");
-    }
+#[derive(Args)]
+struct UiInstallArgs {
+    /// Dashboard to install.
+    #[arg(value_enum)]
+    dashboard: Dashboard,
 
-    Ok(())
-}
+    /// Download from this archive URL instead of the dashboard's gh-pages zip.
+    #[arg(long)]
+    url: Option<String>,
 
-async fn load_runtime_summaries(paths: &[PathBuf]) -> Vec<RuntimeSummary> {
-    let mut summaries = Vec::new();
+    /// Upstream proxy for the download (`direct` ignores the environment's proxy settings).
+    #[arg(long = "fetch-proxy", value_name = "URL")]
+    fetch_proxy: Option<String>,
+}
 
-    for path in paths {
-        let Ok(true) = fs::try_exists(path).await else {
-            continue;
-        };
-        let Ok(raw) = fs::read_to_string(path).await else {
-            continue;
-        };
-        let Ok(cfg) = mihomo_core::ClashConfig::from_yaml_str(&raw) else {
-            continue;
-        };
-        summaries.push(runtime_summary_from_config(path, &cfg));
-    }
+#[derive(Args)]
+struct UiOpenArgs {
+    /// Config whose external-controller/secret to use (defaults to the generated output).
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    summaries
+    /// Print the URL without launching a browser.
+    #[arg(long = "print", default_value_t = false)]
+    print_only: bool,
 }
 
-async fn load_runtime_config(path: &Path) -> anyhow::Result<mihomo_core::ClashConfig> {
-    let raw = fs::read_to_string(path)
-        .await
-        .with_context(|| format!("failed to read {}", path.display()))?;
-    mihomo_core::ClashConfig::from_yaml_str(&raw)
-        .with_context(|| format!("failed to parse {}", path.display()))
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum Dashboard {
+    Metacubexd,
+    Yacd,
 }
 
-async fn existing_runtime_paths(paths: &AppPaths) -> Vec<PathBuf> {
-    let mut existing = Vec::new();
-    for path in paths.detected_clash_verge_runtime_config_paths() {
-        if fs::try_exists(&path).await.unwrap_or(false) {
-            existing.push(path);
+impl Dashboard {
+    fn as_str(self) -> &'static str {
+        match self {
+            Dashboard::Metacubexd => "metacubexd",
+            Dashboard::Yacd => "yacd",
         }
     }
-    existing
-}
 
-fn preferred_runtime_path(paths: &[PathBuf]) -> Option<&PathBuf> {
-    paths
-        .iter()
-        .find(|path| {
-            path.file_name()
-                .and_then(|name| name.to_str())
-                .is_some_and(|name| name == "clash-verge.yaml")
-        })
-        .or_else(|| {
-            paths.iter().find(|path| {
-                path.file_name()
-                    .and_then(|name| name.to_str())
-                    .is_some_and(|name| name == "config.yaml")
-            })
-        })
-        .or_else(|| paths.first())
+    fn default_url(self) -> &'static str {
+        match self {
+            Dashboard::Metacubexd => {
+                "https://github.com/MetaCubeX/metacubexd/archive/refs/heads/gh-pages.zip"
+            }
+            Dashboard::Yacd => {
+                "https://github.com/MetaCubeX/Yacd-meta/archive/refs/heads/gh-pages.zip"
+            }
+        }
+    }
 }
 
-fn runtime_summary_from_config(path: &Path, cfg: &mihomo_core::ClashConfig) -> RuntimeSummary {
-    let mode = cfg
-        .extra
-        .get("mode")
-        .and_then(|value| value.as_str())
-        .map(ToOwned::to_owned);
-
-    let tun_map = cfg.extra.get("tun").and_then(|value| value.as_mapping());
-    let tun_enabled = tun_map.and_then(|map| bool_from_mapping(map, "enable"));
-    let tun_auto_route = tun_map.and_then(|map| bool_from_mapping(map, "auto-route"));
-    let route_excludes = tun_map
-        .map(|map| string_list_from_mapping(map, "route-exclude-address"))
-        .unwrap_or_default();
+// Note: default clap styles are used to avoid introducing extra dependencies
 
-    let dns_map = cfg.extra.get("dns").and_then(|value| value.as_mapping());
-    let enhanced_mode = dns_map.and_then(|map| string_from_mapping(map, "enhanced-mode"));
-    let fake_ip_range = dns_map.and_then(|map| string_from_mapping(map, "fake-ip-range"));
+#[derive(Args, Clone)]
+struct MergeArgs {
+    /// Template YAML file path, or a name stored with `template add`. Defaults to the one
+    /// selected with `template use`, else the auto-installed CVR-aligned template.
+    #[arg(long)]
+    template: Option<PathBuf>,
 
-    let sniffer_enabled = cfg.extra.contains_key("sniffer");
+    /// Optional base config to inherit fields/rules from (e.g., clash-verge.yaml).
+    #[arg(long)]
+    base_config: Option<PathBuf>,
 
-    RuntimeSummary {
-        path: path.to_path_buf(),
-        mode,
-        tun_enabled,
-        tun_auto_route,
-        sniffer_enabled,
-        enhanced_mode,
-        fake_ip_range,
-        route_excludes,
-        rules_count: cfg.rules.len(),
-        controller: parse_controller_endpoint(cfg),
-    }
-}
+    /// Base-config sections to inherit (comma-separated; default: all but rules).
+    /// Overrides `base_sections` in app.yaml.
+    #[arg(long = "base-sections", value_enum, value_delimiter = ',')]
+    base_sections: Vec<BaseSectionArg>,
 
-async fn load_merge_profile_summary(paths: &AppPaths) -> Option<RuntimeSummary> {
-    let merge_path = paths.detected_clash_verge_profile_merge_path()?;
-    if !fs::try_exists(&merge_path).await.unwrap_or(false) {
-        return None;
-    }
-    let cfg = load_runtime_config(&merge_path).await.ok()?;
-    Some(runtime_summary_from_config(&merge_path, &cfg))
-}
+    /// Optional subscriptions YAML definition (defaults to ~/.config/mihomocli/subscriptions.yaml).
+    #[arg(long)]
+    subscriptions_file: Option<PathBuf>,
 
-async fn sync_merge_profile_mode(path: &Path, mode: ConfigMode) -> anyhow::Result<()> {
-    let raw = fs::read_to_string(path)
-        .await
-        .with_context(|| format!("failed to read {}", path.display()))?;
-    let mut doc: serde_yaml::Value = serde_yaml::from_str(&raw)
-        .with_context(|| format!("failed to parse {}", path.display()))?;
-    ensure_root_mapping(&mut doc).insert(
-        serde_yaml::Value::String("mode".to_string()),
-        serde_yaml::Value::String(mode.as_str().to_string()),
-    );
-    fs::write(path, serde_yaml::to_string(&doc)?)
-        .await
-        .with_context(|| format!("failed to write {}", path.display()))?;
-    println!("updated source profile mode in {}", path.display());
-    Ok(())
-}
+    /// Additional subscription sources (URL or file path). May be repeated.
+    /// Prefix with `base64:` or `clash:` to force the parse mode for that source only.
+    #[arg(long = "subscription", short = 's')]
+    subscriptions: Vec<String>,
 
-fn parse_controller_endpoint(cfg: &mihomo_core::ClashConfig) -> Option<ControllerEndpoint> {
-    let http = cfg
-        .extra
-        .get("external-controller")
-        .and_then(|value| value.as_str())
-        .and_then(parse_host_port);
+    /// Output config file path. Defaults to ~/.config/mihomocli/output/clash-verge.yaml if omitted.
+    #[arg(long)]
+    output: Option<PathBuf>,
 
-    let unix_socket = cfg
-        .extra
-        .get("external-controller-unix")
-        .and_then(|value| value.as_str())
-        .map(ToOwned::to_owned);
+    /// Final Clash mode for the generated config.
+    #[arg(long = "mode", value_enum, default_value_t = ConfigMode::Rule)]
+    mode: ConfigMode,
 
-    let secret = cfg
-        .extra
-        .get("secret")
-        .and_then(|value| value.as_str())
-        .map(ToOwned::to_owned);
+    /// Sniffer preset for transparent/TUN traffic.
+    #[arg(long = "sniffer-preset", value_enum, default_value_t = SnifferPreset::Tun)]
+    sniffer_preset: SnifferPreset,
 
-    if http.is_none() && unix_socket.is_none() {
-        return None;
-    }
+    /// Also copy the generated YAML into the detected Clash Verge config.yaml.
+    #[arg(long = "sync-to-clash-verge", default_value_t = false)]
+    sync_to_clash_verge: bool,
 
-    Some(ControllerEndpoint {
-        host: http.as_ref().map(|(host, _)| host.clone()),
-        port: http.map(|(_, port)| port),
-        secret,
-        unix_socket,
-    })
-}
+    /// Also sync Tailscale-safe dns/profile settings into Clash Verge source files.
+    #[arg(long = "sync-to-clash-verge-sources", default_value_t = false)]
+    sync_to_clash_verge_sources: bool,
 
-fn print_runtime_summary(summary: &RuntimeSummary) {
-    println!("  path: {}", summary.path.display());
-    println!(
-        "    mode={}, tun={}, auto-route={}, sniffer={}",
-        summary.mode.as_deref().unwrap_or("<unset>"),
-        format_bool_opt(summary.tun_enabled),
-        format_bool_opt(summary.tun_auto_route),
-        if summary.sniffer_enabled { "on" } else { "off" }
-    );
-    println!(
-        "    dns.enhanced-mode={}, fake-ip-range={}, rules={}",
-        summary.enhanced_mode.as_deref().unwrap_or("<unset>"),
-        summary.fake_ip_range.as_deref().unwrap_or("<unset>"),
-        summary.rules_count
-    );
-    println!(
-        "    route-excludes={}",
-        if summary.route_excludes.is_empty() {
-            "<none>".to_string()
-        } else {
-            summary.route_excludes.join(", ")
-        }
-    );
-    if let Some(controller) = summary.controller.as_ref() {
-        let http = match (&controller.host, controller.port) {
-            (Some(host), Some(port)) => format!("{host}:{port}"),
-            _ => "<unset>".to_string(),
-        };
-        println!(
-            "    controller=http:{} unix:{}",
-            http,
-            controller.unix_socket.as_deref().unwrap_or("<unset>")
-        );
-    }
-}
+    /// Also export the result as a Clash Verge profile (profile YAML plus Merge/Script chain
+    /// files from merge/script subscriptions) into DIR, the directory holding profiles.yaml.
+    /// Defaults to the detected Clash Verge directory.
+    #[arg(long = "clash-verge-profile", value_name = "DIR", num_args = 0..=1)]
+    clash_verge_profile: Option<Option<PathBuf>>,
 
-fn runtime_summary_core_eq(left: &RuntimeSummary, right: &RuntimeSummary) -> bool {
-    left.mode == right.mode
-        && left.tun_enabled == right.tun_enabled
-        && left.tun_auto_route == right.tun_auto_route
-        && left.sniffer_enabled == right.sniffer_enabled
-        && left.enhanced_mode == right.enhanced_mode
-        && left.fake_ip_range == right.fake_ip_range
-        && left.route_excludes == right.route_excludes
-        && left.rules_count == right.rules_count
-}
+    /// Write merged config to stdout instead of a file.
+    #[arg(long)]
+    stdout: bool,
 
-fn print_system_proxy_summary() {
-    println!("System proxy:");
+    /// With --stdout or --diff: mask passwords, uuids, psks, the controller secret and
+    /// provider URLs in the printed config, and URLs in the printed warnings, so the output
+    /// can be shared; files written by other outputs keep the real values.
+    #[arg(long = "redact-secrets", default_value_t = false)]
+    redact_secrets: bool,
 
-    if cfg!(target_os = "macos") {
-        print_macos_system_proxy_summary();
-        return;
-    }
+    /// Prepend common developer domains with proxy rules (GitHub, Docker, GCR, cache.nixos.org).
+    #[arg(long = "dev-rules", default_value_t = true)]
+    dev_rules: bool,
 
-    if cfg!(target_os = "windows") {
-        print_windows_system_proxy_summary();
-        return;
-    }
+    /// Proxy group/tag used by generated dev rules when --dev-rules is set.
+    #[arg(long = "dev-rules-via", default_value = DEFAULT_DEV_RULE_VIA)]
+    dev_rules_via: String,
 
-    println!("  status: unsupported on this platform");
+    /// Show the dev rule list that would be added (without modifying the result unless --dev-rules is set).
+    #[arg(long = "dev-rules-show", default_value_t = false)]
+    dev_rules_show: bool,
+
+    /// Reuse the cached last subscription URL when no -s/--subscription is provided.
+    /// If both are set, explicit subscriptions take precedence.
+    #[arg(long = "use-last", default_value_t = false)]
+    use_last: bool,
+
+    /// HTTP User-Agent used to fetch subscriptions (some providers vary output by UA).
+    /// Defaults to clash-verge UA to obtain Clash YAML with rules when available.
+    #[arg(long = "subscription-ua")]
+    subscription_ua: Option<String>,
+
+    /// Upstream proxy for subscription and resource downloads (e.g. socks5://127.0.0.1:7890).
+    /// A subscription's own `proxy` field takes precedence.
+    #[arg(long = "fetch-proxy", value_name = "URL")]
+    fetch_proxy: Option<String>,
+
+    /// Mirror for missing geodata instead of `resource_mirrors` in app.yaml: a base URL that
+    /// holds the files, or a template with `{asset}`/`{url}` (repeatable, tried in order).
+    #[arg(long = "resource-mirror", value_name = "URL")]
+    resource_mirrors: Vec<String>,
+
+    /// Allow decoding base64/subscription share-link lists when fetching subscriptions.
+    /// Disabled by default to prefer native Clash YAML from providers.
+    #[arg(long = "subscription-allow-base64", default_value_t = false)]
+    subscription_allow_base64: bool,
+
+    /// Host/IP for external-controller (e.g., 0.0.0.0)
+    #[arg(long = "external-controller-url")]
+    external_controller_url: Option<String>,
+
+    /// Port for external-controller (e.g., 9090)
+    #[arg(long = "external-controller-port")]
+    external_controller_port: Option<u16>,
+
+    /// Secret used by external controller API
+    #[arg(long = "external-controller-secret")]
+    external_controller_secret: Option<String>,
+
+    /// Append entries to dns.fake-ip-filter (to avoid DNS hijacking under fake-ip mode)
+    /// Example: --fake-ip-filter-add "+.example.com" --fake-ip-filter-add "hs.example.com"
+    #[arg(long = "fake-ip-filter-add")]
+    fake_ip_filter_add: Vec<String>,
+
+    /// Set dns.fake-ip-filter-mode: blacklist|whitelist (only applies in fake-ip mode)
+    #[arg(long = "fake-ip-filter-mode")]
+    fake_ip_filter_mode: Option<String>,
+
+    /// Add CIDRs to tun.route-exclude-address (repeatable).
+    /// Useful for Kubernetes Pod/Service CIDRs to avoid tun DNS/service hijacking.
+    /// Defaults already include 10.42.0.0/16 and 10.43.0.0/16.
+    #[arg(long = "k8s-cidr-exclude")]
+    k8s_cidr_exclude: Vec<String>,
+
+    /// Add arbitrary CIDRs to tun.route-exclude-address (repeatable).
+    /// Use this to keep specific remote IPs/subnets out of mihomo TUN routing.
+    #[arg(long = "route-exclude-address-add")]
+    route_exclude_address_add: Vec<String>,
+
+    /// Bypass fake-ip for specific domains/patterns (shorthand for adding to dns.fake-ip-filter in blacklist mode)
+    /// Example: --fake-ip-bypass '+.example.com' --fake-ip-bypass 'hs.example.com'
+    #[arg(long = "fake-ip-bypass")]
+    fake_ip_bypass: Vec<String>,
+
+    /// Do not write output; print a concise summary of the merged result
+    #[arg(long = "dry-run", default_value_t = false)]
+    dry_run: bool,
+
+    /// Keep fake-ip and tun compatible with Tailscale by avoiding fake-ip overlap,
+    /// bypassing Tailscale domains, and excluding tailnet CIDRs from tun routing.
+    #[arg(long = "tailscale-compatible", default_value_t = false)]
+    tailscale_compatible: bool,
+
+    /// Tailnet DNS suffixes whose `tail.<suffix>` names should bypass fake-ip
+    /// and be forced DIRECT under --tailscale-compatible. Repeatable.
+    #[arg(long = "tailscale-tailnet-suffix")]
+    tailscale_tailnet_suffixes: Vec<String>,
+
+    /// Additional exact domains or domain suffixes that should bypass fake-ip
+    /// and be forced DIRECT under --tailscale-compatible. Repeatable.
+    /// Examples: --tailscale-direct-domain derp.example.com --tailscale-direct-domain +.corp.example.com
+    #[arg(long = "tailscale-direct-domain")]
+    tailscale_direct_domains: Vec<String>,
+
+    /// Proxy-group ordering: keep template/arrival order, or sort subscription groups by name.
+    /// Given explicitly, it overrides `group_order` in app.yaml [default: template]
+    #[arg(long = "group-order", value_enum)]
+    group_order: Option<GroupOrderArg>,
+
+    /// Explicit group order (repeatable). Listed groups come first; overrides --group-order
+    /// and `group_order` in app.yaml.
+    #[arg(long = "group-order-list")]
+    group_order_list: Vec<String>,
+
+    /// Health-check URL for every url-test/fallback/load-balance group; overrides
+    /// `health_check_urls.default` in app.yaml.
+    #[arg(long = "health-check-url", value_name = "URL")]
+    health_check_url: Option<String>,
+
+    /// Health-check URL for one group as GROUP=URL (repeatable); wins over
+    /// --health-check-url and applies to select groups too.
+    #[arg(
+        long = "group-health-check-url",
+        value_name = "GROUP=URL",
+        value_parser = parse_group_url
+    )]
+    group_health_check_urls: Vec<(String, String)>,
+
+    /// Icon URL for one group as GROUP=URL (repeatable), e.g. `HK=https://.../hk.png` for a
+    /// generated region group; extends `group_icons` in app.yaml. Groups that already have an
+    /// `icon` keep it.
+    #[arg(long = "group-icon", value_name = "GROUP=URL", value_parser = parse_group_url)]
+    group_icons: Vec<(String, String)>,
+
+    /// How nodes present in several subscriptions are handled: rename clashing names with a
+    /// -2 suffix, drop nodes whose name is taken, or drop nodes whose type/server/port repeat a
+    /// node from an earlier source (renaming any remaining clashes).
+    #[arg(long = "dedup-proxies", value_enum, default_value_t = ProxyDedupArg::Rename)]
+    dedup_proxies: ProxyDedupArg,
+
+    /// Annotate proxies with their latest delay from the running mihomo's controller
+    /// (the external-controller of the merged config): a trailing `# 89ms` comment, or an
+    /// `x-latency` key in milliseconds (0 = last probe failed).
+    #[arg(long = "annotate-latency", value_enum, value_name = "STYLE")]
+    annotate_latency: Option<LatencyAnnotation>,
+
+    /// Generate one group per subscription (named after it) with only that provider's nodes,
+    /// and list those groups first in the main selector.
+    #[arg(long = "provider-groups", value_enum, value_name = "TYPE")]
+    provider_groups: Option<ProviderGroupsArg>,
+
+    /// TCP-connect to every subscription node's server:port before merging and drop nodes
+    /// that do not answer (`drop`, the default), or keep them tagged `x-probe: unreachable`
+    /// (`annotate`). UDP-only types (hysteria, tuic, wireguard) are not probed.
+    #[arg(
+        long = "probe",
+        value_enum,
+        value_name = "ACTION",
+        num_args = 0..=1,
+        default_missing_value = "drop"
+    )]
+    probe: Option<ProbeArg>,
+
+    /// Leave out nodes that were unreachable in the last N runs in a row. Runs observe nodes
+    /// through --probe and through the delay history of the running mihomo's controller; the
+    /// record is kept in the cache dir and cleared with `manage nodes reset`.
+    #[arg(long = "exclude-dead-after", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    exclude_dead_after: Option<u32>,
+
+    /// Connect timeout per node for --probe, in milliseconds.
+    #[arg(long = "probe-timeout-ms", value_name = "MS", default_value_t = 3000)]
+    probe_timeout_ms: u64,
+
+    /// Nodes probed at the same time by --probe.
+    #[arg(long = "probe-concurrency", value_name = "N", default_value_t = 32)]
+    probe_concurrency: usize,
+
+    /// Generate url-test groups per region (HK, JP, US, SG unless --region-groups says
+    /// otherwise) and list them first in the main selector. Also enabled by an
+    /// `auto-region-groups:` section in the template.
+    #[arg(long = "auto-region-groups", default_value_t = false)]
+    auto_region_groups: bool,
+
+    /// Region codes for --auto-region-groups, in selector order (comma separated).
+    #[arg(long = "region-groups", value_delimiter = ',', value_name = "CODES")]
+    region_groups: Vec<String>,
+
+    /// How proxies are assigned to regions: name keywords, a Country.mmdb lookup of the
+    /// server, or names with Country.mmdb as fallback.
+    #[arg(long = "region-source", value_enum, value_name = "SOURCE")]
+    region_source: Option<RegionSourceArg>,
+
+    /// Offline `server,CODE` table consulted before Country.mmdb for server-based region
+    /// lookups (overrides `region_lookup.csv` in app.yaml).
+    #[arg(long = "region-csv", value_name = "PATH")]
+    region_csv: Option<PathBuf>,
+
+    /// IP-geo API asked (rate-limited, cached) about servers no other source could place;
+    /// `{server}`/`{ip}` are substituted (overrides `region_lookup.api` in app.yaml).
+    #[arg(long = "region-api", value_name = "URL")]
+    region_api: Option<String>,
+
+    /// Remove proxy-groups left without members (recursively), drop references to them and
+    /// point rules that targeted them at the main selector.
+    #[arg(long = "prune-empty-groups", default_value_t = false)]
+    prune_empty_groups: bool,
+
+    /// Move each run of at least --extract-min-rules rules with the same target into
+    /// rule-provider files next to the output (`<output>.rule-providers/`) and reference them
+    /// with RULE-SET.
+    #[arg(
+        long = "extract-rule-providers",
+        default_value_t = false,
+        conflicts_with = "stdout"
+    )]
+    extract_rule_providers: bool,
+
+    /// Smallest run of rules --extract-rule-providers moves into a provider.
+    #[arg(
+        long = "extract-min-rules",
+        value_name = "N",
+        default_value_t = 50,
+        requires = "extract_rule_providers"
+    )]
+    extract_min_rules: usize,
+
+    /// Merge only proxies from every subscription, dropping their groups, rules and other keys.
+    /// Per-subscription `ignore_groups`/`ignore_rules` in subscriptions.yaml offer finer control.
+    #[arg(long = "proxies-only", default_value_t = false)]
+    proxies_only: bool,
+
+    /// Keep only subscription proxies whose name matches this regex (repeatable; any match
+    /// keeps). Per-subscription `filter` in subscriptions.yaml applies first.
+    #[arg(long = "include-proxy", value_name = "REGEX")]
+    include_proxy: Vec<String>,
+
+    /// Drop subscription proxies whose name matches this regex (repeatable), e.g.
+    /// "剩余流量|官网|过期". Dropped names are also removed from every proxy-group.
+    #[arg(long = "exclude-proxy", value_name = "REGEX")]
+    exclude_proxy: Vec<String>,
+
+    /// Prepend a comment header recording tool version, template, subscriptions and time.
+    #[arg(long = "header", default_value_t = false)]
+    header: bool,
+
+    /// Leave the generation timestamp out of --header so repeated runs produce identical output.
+    #[arg(long = "no-timestamp", default_value_t = false)]
+    no_timestamp: bool,
+
+    /// Byte-identical output for identical inputs: implies --no-timestamp and sorts the keys
+    /// of every proxy and proxy-group entry.
+    #[arg(long = "deterministic", default_value_t = false)]
+    deterministic: bool,
+
+    /// Disable a saved subscription after N consecutive fetch failures (0 = never).
+    /// Defaults to `disable_after_failures` in app.yaml. Re-enable with `manage sub enable`.
+    #[arg(long = "disable-after-failures", value_name = "N")]
+    disable_after_failures: Option<u32>,
+
+    /// Do not generate a secret when external-controller is set without one.
+    #[arg(long = "no-auto-secret", default_value_t = false)]
+    no_auto_secret: bool,
+
+    /// Reject subscription bodies larger than this many bytes (default 32 MiB).
+    #[arg(long = "max-payload-bytes", value_name = "BYTES")]
+    max_payload_bytes: Option<u64>,
+
+    /// Reject subscriptions with more than this many proxies (default 10000).
+    #[arg(long = "max-proxies", value_name = "N")]
+    max_proxies: Option<usize>,
+
+    /// Compare the new result with the existing output file instead of writing it: print the
+    /// added/removed/changed nodes, groups, rules, ports and settings, and exit with status 1 if
+    /// anything changed.
+    #[arg(long = "diff", default_value_t = false)]
+    diff: bool,
+
+    /// Replace an edited default template with the newer bundled one (the old copy is kept
+    /// as `cvr_template.yaml.bak`). Untouched copies are updated automatically.
+    #[arg(long = "force-template-update", default_value_t = false)]
+    force_template_update: bool,
+
+    /// Keep this many timestamped backups (`<output>.<time>.bak`) of the previous output file.
+    #[arg(long = "keep-backups", value_name = "N", default_value_t = 3)]
+    keep_backups: usize,
+
+    /// Keep this many configs in output/history for `history rollback` (0 disables it).
+    #[arg(long = "keep-history", value_name = "N", default_value_t = history::DEFAULT_HISTORY_LIMIT)]
+    keep_history: usize,
+
+    /// Push the merged config to a running mihomo via its external controller
+    /// (e.g. http://127.0.0.1:9090, https://router:9443 or unix:///var/run/mihomo.sock),
+    /// using `PUT /configs?force=true`.
+    #[arg(long = "deploy-api", value_name = "URL")]
+    deploy_api: Option<String>,
+
+    /// Controller secret for --deploy-api (defaults to the merged config's `secret`).
+    #[arg(long = "deploy-secret", requires = "deploy_api")]
+    deploy_secret: Option<String>,
+
+    /// PEM CA certificate to trust when --deploy-api is an https:// URL with a self-signed
+    /// certificate.
+    #[arg(long = "deploy-ca-cert", value_name = "PEM", requires = "deploy_api")]
+    deploy_ca_cert: Option<PathBuf>,
+
+    /// Copy the merged config to a remote host over ssh: `[user@]host:/remote/path`.
+    #[arg(long = "deploy-ssh", value_name = "TARGET")]
+    deploy_ssh: Option<String>,
+
+    /// SSH port for --deploy-ssh.
+    #[arg(long = "deploy-ssh-port", requires = "deploy_ssh")]
+    deploy_ssh_port: Option<u16>,
+
+    /// SSH identity file for --deploy-ssh.
+    #[arg(long = "deploy-ssh-identity", requires = "deploy_ssh")]
+    deploy_ssh_identity: Option<PathBuf>,
+
+    /// Command run on the remote host after --deploy-ssh copied the file
+    /// (e.g. "/etc/init.d/mihomo restart").
+    #[arg(
+        long = "deploy-ssh-reload",
+        value_name = "CMD",
+        requires = "deploy_ssh"
+    )]
+    deploy_ssh_reload: Option<String>,
+
+    /// Shell command to run after the config was written and deployed (may be repeated;
+    /// runs after `post_hooks` from app.yaml). Gets MIHOMO_CONFIG_PATH and node/group/rule
+    /// counts in its environment.
+    #[arg(long = "post-hook", value_name = "CMD")]
+    post_hooks: Vec<String>,
+
+    /// Leave the output file alone (and skip deploys, reload and hooks) when the merged config
+    /// is identical to what it already contains, ignoring the generation header.
+    #[arg(long = "if-changed", default_value_t = false)]
+    if_changed: bool,
+
+    /// Stay resident and re-run the merge every --interval; implies --if-changed, so the
+    /// config is only rewritten and redeployed when it actually changed. Stop with Ctrl-C or
+    /// SIGTERM.
+    #[arg(long = "watch", default_value_t = false, conflicts_with_all = ["diff", "dry_run"])]
+    watch: bool,
+
+    /// Time between --watch runs, e.g. `30m`, `6h`, `1d` (plain numbers are seconds).
+    #[arg(
+        long = "interval",
+        value_name = "DURATION",
+        default_value = "6h",
+        value_parser = parse_interval,
+        requires = "watch"
+    )]
+    interval: std::time::Duration,
+
+    /// On SIGINT/SIGTERM, how long --watch lets a running merge finish before exiting.
+    #[arg(
+        long = "drain-timeout",
+        value_name = "DURATION",
+        default_value = "30s",
+        value_parser = parse_interval,
+        requires = "watch"
+    )]
+    drain_timeout: std::time::Duration,
+
+    /// Follow at most this many redirects per subscription request.
+    #[arg(long = "max-redirects", value_name = "N", default_value_t = 10)]
+    max_redirects: usize,
+
+    /// Reload the running mihomo after writing the config: `systemd:<unit>`,
+    /// `systemd-user:<unit>`, `launchd:<label>`, `signal:<pid-file>` (SIGHUP), `api` (the
+    /// merged config's external-controller) or `api:<url>`.
+    #[arg(long = "reload", value_name = "TARGET", conflicts_with = "stdout")]
+    reload: Option<ReloadTarget>,
+
+    /// Do not download geodata (Country.mmdb, geoip.dat, geosite.dat); same as
+    /// `--resources-mode never`.
+    #[arg(long = "skip-resources", default_value_t = false)]
+    skip_resources: bool,
+
+    /// When to download geodata: `always` (every missing file, the default), `lazy` (only files
+    /// the merged config references) or `never`. Overrides `resources.mode` in app.yaml.
+    #[arg(long = "resources-mode", value_enum, conflicts_with = "skip_resources")]
+    resources_mode: Option<ResourcesModeArg>,
+
+    /// Treat every warning (failed subscription, fallback via target, dropped or duplicate
+    /// nodes/groups, ...) as an error: nothing is written and the exit status is non-zero.
+    #[arg(long = "strict", default_value_t = false)]
+    strict: bool,
+
+    /// Report rules, group members and dialer-proxies that point at nonexistent proxies,
+    /// groups or providers as `unknown-reference` warnings instead of failing the merge.
+    #[arg(long = "lenient", default_value_t = false)]
+    lenient: bool,
+
+    /// Apply a named flag bundle from `presets` in app.yaml; flags given on the command line
+    /// take precedence over the preset's.
+    #[arg(long = "preset", value_name = "NAME")]
+    preset: Option<String>,
+
+    /// Use a named profile from profiles.yaml (template, base config, subscriptions, dev
+    /// rules, output, extra flags); flags given on the command line take precedence.
+    #[arg(long = "profile", value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Produce one output per named variant from app.yaml `variants` in a single run,
+    /// fetching each subscription once (e.g. `--variants stable,canary`).
+    #[arg(long = "variants", value_name = "NAME", value_delimiter = ',')]
+    variants: Vec<String>,
+
+    /// Also write the config for these devices from app.yaml `devices` (tun, allow-lan,
+    /// ports, dns listen and overrides on top of this merge), e.g. `--devices router,laptop`.
+    #[arg(
+        long = "devices",
+        value_name = "NAME",
+        value_delimiter = ',',
+        conflicts_with = "stdout"
+    )]
+    devices: Vec<String>,
+
+    /// Only merge these stored subscriptions (id or name; may be repeated).
+    #[arg(long = "only-subscription", value_name = "NAME")]
+    only_subscriptions: Vec<String>,
 }
 
-fn print_macos_system_proxy_summary() {
-    match std::process::Command::new("scutil").arg("--proxy").output() {
-        Ok(output) if output.status.success() => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let proxies = parse_scutil_proxy(&stdout);
-            if proxies.is_empty() {
-                println!("  status: scutil reports no enabled system proxies");
-            } else {
-                println!("  status: enabled");
-                for proxy in proxies {
-                    println!("  {} -> {}:{}", proxy.kind, proxy.host, proxy.port);
-                }
-            }
-        }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            println!(
-                "  status: unavailable ({})",
-                trimmed_single_line(&stderr).unwrap_or_else(|| "scutil failed".to_string())
-            );
-        }
-        Err(err) => {
-            println!("  status: unavailable ({err})");
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum BaseSectionArg {
+    Ports,
+    Dns,
+    Tun,
+    Groups,
+    Rules,
+    Other,
+}
+
+impl From<BaseSectionArg> for BaseSection {
+    fn from(arg: BaseSectionArg) -> Self {
+        match arg {
+            BaseSectionArg::Ports => BaseSection::Ports,
+            BaseSectionArg::Dns => BaseSection::Dns,
+            BaseSectionArg::Tun => BaseSection::Tun,
+            BaseSectionArg::Groups => BaseSection::Groups,
+            BaseSectionArg::Rules => BaseSection::Rules,
+            BaseSectionArg::Other => BaseSection::Other,
         }
     }
 }
 
-fn print_windows_system_proxy_summary() {
-    let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
-    let enable_output = std::process::Command::new("reg")
-        .args(["query", key, "/v", "ProxyEnable"])
-        .output();
-    let server_output = std::process::Command::new("reg")
-        .args(["query", key, "/v", "ProxyServer"])
-        .output();
-
-    match (enable_output, server_output) {
-        (Ok(enable), Ok(server)) if enable.status.success() && server.status.success() => {
-            let enable_raw = String::from_utf8_lossy(&enable.stdout);
-            let server_raw = String::from_utf8_lossy(&server.stdout);
-            let proxies = parse_windows_proxy_query(&enable_raw, &server_raw);
-            if proxies.is_empty() {
-                println!("  status: reg query reports no enabled system proxies");
-            } else {
-                println!("  status: enabled");
-                for proxy in proxies {
-                    println!("  {} -> {}:{}", proxy.kind, proxy.host, proxy.port);
-                }
-            }
-        }
-        (Ok(enable), Ok(server)) => {
-            let stderr = format!(
-                "{} {}",
-                String::from_utf8_lossy(&enable.stderr),
-                String::from_utf8_lossy(&server.stderr)
-            );
-            println!(
-                "  status: unavailable ({})",
-                trimmed_single_line(&stderr).unwrap_or_else(|| "reg query failed".to_string())
-            );
-        }
-        (Err(err), _) | (_, Err(err)) => {
-            println!("  status: unavailable ({err})");
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ListenerKindArg {
+    Mixed,
+    Socks,
+    Http,
+}
+
+impl From<ListenerKindArg> for ListenerKind {
+    fn from(arg: ListenerKindArg) -> Self {
+        match arg {
+            ListenerKindArg::Mixed => ListenerKind::Mixed,
+            ListenerKindArg::Socks => ListenerKind::Socks,
+            ListenerKindArg::Http => ListenerKind::Http,
         }
     }
 }
 
-fn parse_windows_proxy_query(enable_raw: &str, server_raw: &str) -> Vec<ProxyEntry> {
-    let enabled = enable_raw
-        .lines()
-        .find(|line| line.contains("ProxyEnable"))
-        .map(|line| {
-            let normalized = line.trim().to_ascii_lowercase();
-            normalized.ends_with("0x1") || normalized.ends_with("0x00000001")
-        })
-        .unwrap_or(false);
-
-    if !enabled {
-        return Vec::new();
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ResourcesModeArg {
+    Never,
+    Lazy,
+    Always,
+}
+
+impl From<ResourcesModeArg> for ResourcesMode {
+    fn from(arg: ResourcesModeArg) -> Self {
+        match arg {
+            ResourcesModeArg::Never => ResourcesMode::Never,
+            ResourcesModeArg::Lazy => ResourcesMode::Lazy,
+            ResourcesModeArg::Always => ResourcesMode::Always,
+        }
     }
+}
 
-    let Some(server_value) = server_raw
-        .lines()
-        .find(|line| line.contains("ProxyServer"))
-        .and_then(|line| line.split("REG_SZ").nth(1))
-        .map(str::trim)
-        .filter(|value| !value.is_empty())
-    else {
-        return Vec::new();
-    };
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ProviderGroupsArg {
+    Select,
+    UrlTest,
+}
 
-    let mut proxies = Vec::new();
-    if server_value.contains('=') {
-        for item in server_value.split(';').filter(|item| !item.is_empty()) {
-            let mut parts = item.splitn(2, '=');
-            let Some(kind) = parts.next().map(str::trim) else {
-                continue;
-            };
-            let Some(target) = parts.next().map(str::trim) else {
-                continue;
-            };
-            if let Some((host, port)) = split_host_port(target) {
-                let label = match kind.to_ascii_lowercase().as_str() {
-                    "http" => "HTTP",
-                    "https" => "HTTPS",
-                    "socks" | "socks5" => "SOCKS",
-                    _ => "WININET",
-                };
-                proxies.push(ProxyEntry {
-                    kind: label,
-                    host,
-                    port,
-                });
-            }
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ProbeArg {
+    Drop,
+    Annotate,
+}
+
+impl From<ProbeArg> for ProbeAction {
+    fn from(arg: ProbeArg) -> Self {
+        match arg {
+            ProbeArg::Drop => ProbeAction::Drop,
+            ProbeArg::Annotate => ProbeAction::Annotate,
         }
-    } else if let Some((host, port)) = split_host_port(server_value) {
-        proxies.push(ProxyEntry {
-            kind: "WININET",
-            host,
-            port,
-        });
     }
-
-    proxies
 }
 
-fn split_host_port(raw: &str) -> Option<(String, String)> {
-    let value = raw.trim();
-    if value.is_empty() {
-        return None;
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum RegionSourceArg {
+    Name,
+    Geoip,
+    Both,
+}
+
+impl From<RegionSourceArg> for RegionSource {
+    fn from(arg: RegionSourceArg) -> Self {
+        match arg {
+            RegionSourceArg::Name => RegionSource::Name,
+            RegionSourceArg::Geoip => RegionSource::Geoip,
+            RegionSourceArg::Both => RegionSource::Both,
+        }
     }
+}
 
-    let (host, port) = value.rsplit_once(':')?;
-    Some((host.trim().to_string(), port.trim().to_string()))
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum GroupOrderArg {
+    Template,
+    Name,
 }
 
-fn parse_scutil_proxy(output: &str) -> Vec<ProxyEntry> {
-    let mut http_enable = false;
-    let mut https_enable = false;
-    let mut socks_enable = false;
-    let mut http_proxy = String::new();
-    let mut https_proxy = String::new();
-    let mut socks_proxy = String::new();
-    let mut http_port = String::new();
-    let mut https_port = String::new();
-    let mut socks_port = String::new();
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum LatencyAnnotation {
+    Comment,
+    Key,
+}
 
-    for line in output.lines() {
-        let mut parts = line.splitn(2, ':');
-        let Some(key) = parts.next().map(str::trim) else {
-            continue;
-        };
-        let Some(value) = parts.next().map(str::trim) else {
-            continue;
-        };
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ProxyDedupArg {
+    Rename,
+    Name,
+    Endpoint,
+}
+
+impl From<ProxyDedupArg> for ProxyDedup {
+    fn from(arg: ProxyDedupArg) -> Self {
+        match arg {
+            ProxyDedupArg::Rename => ProxyDedup::Rename,
+            ProxyDedupArg::Name => ProxyDedup::Name,
+            ProxyDedupArg::Endpoint => ProxyDedup::Endpoint,
+        }
+    }
+}
 
-        match key {
-            "HTTPEnable" => http_enable = value == "1",
-            "HTTPProxy" => http_proxy = value.to_string(),
-            "HTTPPort" => http_port = value.to_string(),
-            "HTTPSEnable" => https_enable = value == "1",
-            "HTTPSProxy" => https_proxy = value.to_string(),
-            "HTTPSPort" => https_port = value.to_string(),
-            "SOCKSEnable" => socks_enable = value == "1",
-            "SOCKSProxy" => socks_proxy = value.to_string(),
-            "SOCKSPort" => socks_port = value.to_string(),
-            _ => {}
-        }
-    }
+#[derive(Args)]
+struct RefreshClashVergeArgs {
+    /// Explicit subscription URL. If omitted, the current Clash Verge remote subscription is used.
+    subscription: Option<String>,
 
-    let mut proxies = Vec::new();
-    if http_enable && !http_proxy.is_empty() {
-        proxies.push(ProxyEntry {
-            kind: "HTTP",
-            host: http_proxy,
-            port: http_port,
-        });
-    }
-    if https_enable && !https_proxy.is_empty() {
-        proxies.push(ProxyEntry {
-            kind: "HTTPS",
-            host: https_proxy,
-            port: https_port,
-        });
-    }
-    if socks_enable && !socks_proxy.is_empty() {
-        proxies.push(ProxyEntry {
-            kind: "SOCKS",
-            host: socks_proxy,
-            port: socks_port,
-        });
-    }
-    proxies
-}
+    /// Final Clash mode for the generated config.
+    #[arg(long = "mode")]
+    mode: Option<ConfigMode>,
 
-fn print_tailscale_summary() {
-    println!("Tailscale:");
+    /// Sniffer preset for transparent/TUN traffic.
+    #[arg(long = "sniffer-preset")]
+    sniffer_preset: Option<SnifferPreset>,
 
-    let tailscale_candidates = [
-        "/Applications/Tailscale.app/Contents/MacOS/Tailscale",
-        "tailscale",
-    ];
+    /// Disable the Tailscale compatibility patch set.
+    #[arg(long = "no-tailscale-compatible", default_value_t = false)]
+    no_tailscale_compatible: bool,
 
-    let mut last_error: Option<String> = None;
-    for candidate in tailscale_candidates {
-        let output = std::process::Command::new(candidate)
-            .args(["status", "--json"])
-            .output();
-        match output {
-            Ok(output) if output.status.success() => {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                match serde_json::from_str::<serde_json::Value>(&stdout) {
-                    Ok(json) => {
-                        let backend_state = json
-                            .get("BackendState")
-                            .and_then(|value| value.as_str())
-                            .unwrap_or("<unknown>");
-                        let health_count = json
-                            .get("Health")
-                            .and_then(|value| value.as_array())
-                            .map(|items| items.len())
-                            .unwrap_or(0);
-                        println!(
-                            "  status: backend={}, health-warnings={}",
-                            backend_state, health_count
-                        );
-                        if let Some(health) = json.get("Health").and_then(|value| value.as_array())
-                        {
-                            for item in health.iter().take(3) {
-                                if let Some(message) = item.as_str() {
-                                    println!("  warning: {}", message);
-                                }
-                            }
-                        }
-                        return;
-                    }
-                    Err(err) => {
-                        last_error = Some(format!("invalid JSON from {}: {}", candidate, err));
-                    }
-                }
-            }
-            Ok(output) => {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                last_error = trimmed_single_line(&stderr)
-                    .or_else(|| trimmed_single_line(&stdout))
-                    .or_else(|| Some(format!("{} exited with {}", candidate, output.status)));
-            }
-            Err(err) => {
-                last_error = Some(format!("{} unavailable: {}", candidate, err));
-            }
-        }
-    }
+    /// Tailnet DNS suffixes whose `tail.<suffix>` names should bypass fake-ip and be forced DIRECT.
+    #[arg(long = "tailscale-tailnet-suffix")]
+    tailscale_tailnet_suffixes: Vec<String>,
 
-    println!(
-        "  status: unavailable ({})",
-        last_error.unwrap_or_else(|| "tailscale CLI probe failed".to_string())
-    );
+    /// Additional domains or suffixes that should bypass fake-ip and be forced DIRECT.
+    #[arg(long = "tailscale-direct-domain")]
+    tailscale_direct_domains: Vec<String>,
+
+    /// Additional CIDRs that should stay out of the Mihomo TUN.
+    #[arg(long = "route-exclude-address-add")]
+    route_exclude_address_add: Vec<String>,
+
+    /// Preview the resolved merge without writing files.
+    #[arg(long = "dry-run", default_value_t = false)]
+    dry_run: bool,
 }
 
-fn print_controller_summary(controller: &ControllerEndpoint, args: &DoctorArgs) {
-    println!("Controller:");
+#[derive(Args)]
+struct RuntimeArgs {
+    #[command(subcommand)]
+    command: RuntimeCommand,
+}
 
-    match (&controller.host, controller.port) {
-        (Some(host), Some(port)) => println!("  configured-http: {}:{}", host, port),
-        _ => println!("  configured-http: <unset>"),
-    }
-    println!(
-        "  configured-unix: {}",
-        controller.unix_socket.as_deref().unwrap_or("<unset>")
-    );
+#[derive(Subcommand)]
+enum RuntimeCommand {
+    /// Show detected runtime config/controller state
+    Status(DoctorArgs),
+    /// Reload the running Mihomo process from the detected runtime file
+    Reload,
+    /// Update the detected runtime files to a specific Clash mode and reload
+    Mode(RuntimeModeArgs),
+}
 
-    if let Some(probe) = probe_controller_http(controller) {
-        println!("  live-api: http");
-        if let Some(version) = probe.version_line {
-            println!("  version: {}", version);
-        }
-        print_connection_summary(probe.connections_json.as_deref(), args);
-        return;
-    }
+#[derive(Args)]
+struct RuntimeModeArgs {
+    /// Target Clash mode for the local runtime config.
+    #[arg(value_enum)]
+    mode: ConfigMode,
+}
 
-    if let Some(probe) = probe_controller_unix(controller) {
-        println!("  live-api: unix-socket");
-        if let Some(version) = probe.version_line {
-            println!("  version: {}", version);
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum ConfigMode {
+    Rule,
+    Global,
+    Direct,
+}
+
+impl ConfigMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Rule => "rule",
+            Self::Global => "global",
+            Self::Direct => "direct",
         }
-        print_connection_summary(probe.connections_json.as_deref(), args);
-        return;
     }
+}
 
-    println!("  live-api: unavailable");
-    println!("  note: controller could not be reached over HTTP or unix socket");
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+    Otlp,
 }
 
-struct ControllerProbe {
-    version_line: Option<String>,
-    connections_json: Option<String>,
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+enum SnifferPreset {
+    Off,
+    Tun,
 }
 
-fn probe_controller_http(controller: &ControllerEndpoint) -> Option<ControllerProbe> {
-    let host = controller.host.as_ref()?;
-    let port = controller.port?;
-    let base = format!("http://{}:{}", normalize_controller_host(host), port);
-    let version_text = curl_controller_get(controller, &(base.clone() + "/version"))?;
-    let connections_text = curl_controller_get(controller, &(base + "/connections"));
+#[derive(Args)]
+struct DoctorArgs {
+    /// Include a short sample of current live controller connections when available.
+    #[arg(long = "show-connections", default_value_t = true)]
+    show_connections: bool,
 
-    Some(ControllerProbe {
-        version_line: trimmed_single_line(&version_text),
-        connections_json: connections_text,
-    })
+    /// Domains to highlight in live connections.
+    #[arg(long = "focus-domain")]
+    focus_domains: Vec<String>,
 }
 
-fn probe_controller_unix(controller: &ControllerEndpoint) -> Option<ControllerProbe> {
-    let socket = controller.unix_socket.as_ref()?;
-    if !Path::new(socket).exists() {
-        return None;
-    }
+#[derive(Args)]
+struct DoctorCmdArgs {
+    #[command(flatten)]
+    live: DoctorArgs,
 
-    let version_text =
-        curl_controller_get_with_socket(controller, socket, "http://localhost/version")?;
-    if version_text.trim().is_empty() {
-        return None;
-    }
+    /// Path to the mihomo binary to check (defaults to `mihomo` in PATH, then the one from
+    /// `core install`).
+    #[arg(long = "mihomo-bin")]
+    mihomo_bin: Option<String>,
 
-    let connections_text =
-        curl_controller_get_with_socket(controller, socket, "http://localhost/connections");
+    /// Do not contact subscription providers.
+    #[arg(long = "skip-subscriptions", default_value_t = false)]
+    skip_subscriptions: bool,
+}
 
-    Some(ControllerProbe {
-        version_line: trimmed_single_line(&version_text),
-        connections_json: connections_text,
-    })
+/// Ends the command with a non-zero exit code after its output was printed, without an error
+/// message; `main` turns it into the process exit code once everything is dropped.
+#[derive(Debug)]
+struct ExitStatus(u8);
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exit code {}", self.0)
+    }
 }
 
-fn curl_controller_get(controller: &ControllerEndpoint, url: &str) -> Option<String> {
-    let mut command = std::process::Command::new("curl");
-    command.args(["-s", "-f"]);
-    if let Some(secret) = controller.secret.as_ref() {
-        if !secret.is_empty() {
-            command.args(["-H", &format!("Authorization: Bearer {}", secret)]);
-        }
+impl std::error::Error for ExitStatus {}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<std::process::ExitCode> {
+    let cli = Cli::parse();
+    let tracing_guard = init_tracing(cli.log_format, cli.otlp_endpoint.as_deref())?;
+    storage::set_read_only(cli.read_only);
+    if let Some(dir) = cli.config_dir {
+        storage::set_config_dir(dir);
     }
-    command.arg(url);
-    let output = command.output().ok()?;
-    if !output.status.success() {
-        return None;
+    if let Some(rate) = cli.max_download_rate {
+        set_max_download_rate(rate);
     }
-    let text = String::from_utf8_lossy(&output.stdout).to_string();
-    if text.trim().is_empty() {
-        None
-    } else {
-        Some(text)
+
+    let result = run_command(cli.command).await;
+    tracing_guard.shutdown().await;
+    match result {
+        Ok(()) => Ok(std::process::ExitCode::SUCCESS),
+        Err(err) => match err.downcast_ref::<ExitStatus>() {
+            Some(ExitStatus(code)) => Ok(std::process::ExitCode::from(*code)),
+            None => Err(err),
+        },
     }
 }
 
-fn curl_controller_get_with_socket(
-    controller: &ControllerEndpoint,
-    socket: &str,
-    url: &str,
-) -> Option<String> {
-    let mut command = std::process::Command::new("curl");
-    command.args(["--unix-socket", socket, "-s", "-f"]);
-    if let Some(secret) = controller.secret.as_ref() {
-        if !secret.is_empty() {
-            command.args(["-H", &format!("Authorization: Bearer {}", secret)]);
-        }
-    }
-    command.arg(url);
-    let output = command.output().ok()?;
-    if !output.status.success() {
-        return None;
-    }
-    let text = String::from_utf8_lossy(&output.stdout).to_string();
-    if text.trim().is_empty() {
-        None
-    } else {
-        Some(text)
+async fn run_command(command: Commands) -> anyhow::Result<()> {
+    match command {
+        Commands::Merge(args) => {
+            let args = resolve_merge_args(*args).await?;
+            if args.watch {
+                run_merge_watch(args).await?
+            } else {
+                run_merge_outputs(args).await?
+            }
+        }
+        Commands::RefreshClashVerge(args) => run_refresh_clash_verge(args).await?,
+        Commands::Runtime(args) => run_runtime(args).await?,
+        Commands::Manage(cmd) => run_manage(cmd).await?,
+        Commands::Test(args) => run_test(args).await?,
+        Commands::Init(args) => run_init(args).await?,
+        Commands::Doctor(args) => run_doctor(args).await?,
+        Commands::Ui(args) => run_ui(args).await?,
+        Commands::Template(args) => run_template(args).await?,
+        Commands::Show(args) => run_show(args).await?,
+        Commands::Ctl(args) => run_ctl(args).await?,
+        Commands::Nodes(NodesCommand::Find(args)) => run_nodes_find(args).await?,
+        Commands::Parse(args) => run_parse(args).await?,
+        Commands::ExportLinks(args) => run_export_links(args).await?,
+        Commands::Serve(args) => run_serve(args).await?,
+        Commands::SelfCmd(SelfCmd::Update(args)) => run_self_update(args).await?,
+        Commands::Core(CoreCommand::Install(args)) => run_core_install(args).await?,
+        Commands::Rules(cmd) => run_rules(cmd).await?,
+        Commands::Run(args) => run_mihomo(args).await?,
+        Commands::Stop => stop_mihomo().await?,
+        Commands::Status => mihomo_status().await?,
+        Commands::Service(cmd) => run_service(cmd).await?,
+        Commands::Resources(ResourcesCommand::Update(args)) => run_resources_update(args).await?,
+        Commands::History(cmd) => run_history(cmd).await?,
     }
+
+    Ok(())
 }
 
-fn print_connection_summary(connections_json: Option<&str>, args: &DoctorArgs) {
-    if !args.show_connections {
-        println!("  connections: skipped");
-        return;
+/// Expand `merge --profile NAME` and `merge --preset NAME` by re-parsing the profile's flags,
+/// then the preset's, then the flags typed on the command line, so explicit flags win.
+async fn resolve_merge_args(args: MergeArgs) -> anyhow::Result<MergeArgs> {
+    if args.profile.is_none() && args.preset.is_none() {
+        return Ok(args);
     }
+    let paths = AppPaths::new()?;
+    let (prefix, disable_dev_rules) = merge_bundle_prefix(&args, &paths).await?;
+    reparse_merge_args(&prefix, &[], disable_dev_rules)
+}
 
-    let Some(raw) = connections_json else {
-        println!("  connections: unavailable");
-        return;
-    };
-
-    let Ok(json) = serde_json::from_str::<serde_json::Value>(raw) else {
-        println!("  connections: unavailable (invalid JSON)");
-        return;
-    };
-
-    let records = extract_connection_records(&json);
-    if records.is_empty() {
-        println!("  connections: no active connections reported");
-        return;
+/// Flags contributed by `--profile` and `--preset`, and whether the profile turns dev rules
+/// off.
+async fn merge_bundle_prefix(
+    args: &MergeArgs,
+    paths: &AppPaths,
+) -> anyhow::Result<(Vec<String>, bool)> {
+    let mut prefix = Vec::new();
+    let mut disable_dev_rules = false;
+
+    if let Some(name) = args.profile.as_deref() {
+        let profiles = storage::load_profiles(paths).await?;
+        let profile = profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            anyhow!(
+                "unknown profile '{}' (defined in {}: {})",
+                name,
+                paths.profiles_path().display(),
+                if known.is_empty() {
+                    "<none>".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })?;
+        prefix.extend(profile_argv(profile)?);
+        disable_dev_rules = profile.dev_rules == Some(false);
+        info!(profile = %name, "applying merge profile");
     }
 
-    println!("  active-connections: {}", records.len());
-
-    let focus_domains = if args.focus_domains.is_empty() {
-        vec![
-            "chat.openai.com".to_string(),
-            "chatgpt.com".to_string(),
-            "ab.chatgpt.com".to_string(),
-            "api.anthropic.com".to_string(),
-        ]
-    } else {
-        args.focus_domains.clone()
-    };
-
-    let focused: Vec<&ConnectionRecord> = records
-        .iter()
-        .filter(|record| connection_matches_focus(record, &focus_domains))
+    if let Some(name) = args.preset.as_deref() {
+        let app_cfg = storage::load_app_config(paths).await?;
+        let preset = app_cfg.presets.get(name).ok_or_else(|| {
+            let known: Vec<&str> = app_cfg.presets.keys().map(String::as_str).collect();
+            anyhow!(
+                "unknown preset '{}' (defined in app.yaml: {})",
+                name,
+                if known.is_empty() {
+                    "<none>".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })?;
+        reject_nested_bundles(preset, "presets")?;
+        prefix.extend(preset.iter().cloned());
+        info!(preset = %name, "applying merge preset");
+    }
+    Ok((prefix, disable_dev_rules))
+}
+
+/// Re-parse `prefix`, then the merge flags typed on the command line, then `suffix`; later
+/// flags win.
+fn reparse_merge_args(
+    prefix: &[String],
+    suffix: &[String],
+    disable_dev_rules: bool,
+) -> anyhow::Result<MergeArgs> {
+    let mut cli_args: Vec<String> = std::env::args()
+        .skip_while(|arg| arg != "merge")
+        .skip(1)
         .collect();
-
-    if focused.is_empty() {
-        println!("  focus-matches: none");
-        return;
-    }
-
-    let path_hint = infer_connection_path(&focused);
-    println!("  focus-matches: {}", focused.len());
-    println!("  likely-path: {}", path_hint);
-    for record in focused.iter().take(6) {
-        println!(
-            "  connection: host={} inbound={} source={} chains={}",
-            record.host.as_deref().unwrap_or("<unknown>"),
-            record.inbound_name.as_deref().unwrap_or("<unknown>"),
-            record.source_ip.as_deref().unwrap_or("<unknown>"),
-            if record.chains.is_empty() {
-                "<none>".to_string()
-            } else {
-                record.chains.join(">")
+    cli_args.extend(suffix.iter().cloned());
+    let argv = merge_argv(prefix, &cli_args);
+    let cli = Cli::try_parse_from(&argv).with_context(|| {
+        format!(
+            "invalid merge flags: {}",
+            prefix
+                .iter()
+                .chain(suffix)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    })?;
+    match cli.command {
+        Commands::Merge(mut args) => {
+            if disable_dev_rules {
+                args.dev_rules = false;
             }
-        );
+            Ok(*args)
+        }
+        _ => unreachable!("merge argv always starts with the merge subcommand"),
     }
 }
 
-fn extract_connection_records(json: &serde_json::Value) -> Vec<ConnectionRecord> {
-    let Some(items) = json
-        .get("connections")
-        .and_then(|value| value.as_array())
-        .or_else(|| json.as_array())
-    else {
-        return Vec::new();
-    };
-
-    items.iter().map(connection_record_from_json).collect()
+/// Run a single merge, or one per `--variants` entry.
+async fn run_merge_outputs(args: MergeArgs) -> anyhow::Result<()> {
+    if args.variants.is_empty() {
+        run_merge(args).await
+    } else {
+        run_merge_variants(args).await
+    }
 }
 
-fn connection_record_from_json(value: &serde_json::Value) -> ConnectionRecord {
-    let host = json_string_path(value, &["metadata", "host"])
-        .or_else(|| json_string_path(value, &["metadata", "destinationIP"]))
-        .or_else(|| json_string_path(value, &["host"]));
-    let inbound_name = json_string_path(value, &["metadata", "inboundName"])
-        .or_else(|| json_string_path(value, &["inboundName"]));
-    let source_ip = json_string_path(value, &["metadata", "sourceIP"])
-        .or_else(|| json_string_path(value, &["sourceIP"]));
-    let chains = json_string_list_path(value, &["chains"])
-        .or_else(|| json_string_list_path(value, &["metadata", "chains"]))
-        .unwrap_or_default();
+/// `merge --variants a,b`: re-run the merge once per app.yaml variant with its flags applied
+/// last, sharing downloaded subscription payloads between the runs.
+async fn run_merge_variants(args: MergeArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let app_cfg = storage::load_app_config(&paths).await?;
+    let (prefix, profile_disables_dev_rules) = merge_bundle_prefix(&args, &paths).await?;
+    let default_output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
 
-    ConnectionRecord {
-        host,
-        inbound_name,
-        source_ip,
-        chains,
+    let mut plans = Vec::new();
+    for name in &args.variants {
+        let variant = app_cfg.variants.get(name).ok_or_else(|| {
+            let known: Vec<&str> = app_cfg.variants.keys().map(String::as_str).collect();
+            anyhow!(
+                "unknown variant '{}' (defined in app.yaml: {})",
+                name,
+                if known.is_empty() {
+                    "<none>".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })?;
+        let mut suffix =
+            profile_argv(variant).with_context(|| format!("invalid variant '{}'", name))?;
+        if variant.output.is_none() {
+            suffix.push("--output".to_string());
+            suffix.push(
+                variant_output_path(&default_output, name)
+                    .display()
+                    .to_string(),
+            );
+        }
+        let disable_dev_rules = profile_disables_dev_rules || variant.dev_rules == Some(false);
+        let mut variant_args = reparse_merge_args(&prefix, &suffix, disable_dev_rules)
+            .with_context(|| format!("invalid variant '{}'", name))?;
+        if variant.dev_rules == Some(true) {
+            variant_args.dev_rules = true;
+        }
+        variant_args.variants.clear();
+        variant_args.if_changed = args.if_changed;
+        variant_args.watch = args.watch;
+        plans.push((name.clone(), variant_args));
+    }
+
+    mihomo_core::subscription::share_fetches(true);
+    let mut failed = Vec::new();
+    let mut exit_status = None;
+    for (name, variant_args) in plans {
+        info!(variant = %name, "merging variant");
+        match run_merge(variant_args).await {
+            Ok(()) => {}
+            // `--diff` found changes in this variant; keep comparing the others.
+            Err(err) if err.downcast_ref::<ExitStatus>().is_some() => exit_status = Some(err),
+            Err(err) => {
+                warn!(variant = %name, error = %format!("{:#}", err), "variant failed");
+                failed.push(name);
+            }
+        }
     }
-}
-
-fn connection_matches_focus(record: &ConnectionRecord, focus_domains: &[String]) -> bool {
-    let Some(host) = record.host.as_ref() else {
-        return false;
-    };
-    focus_domains.iter().any(|focus| host.contains(focus))
-}
+    mihomo_core::subscription::share_fetches(false);
 
-fn infer_connection_path(records: &[&ConnectionRecord]) -> &'static str {
-    if records.iter().any(|record| {
-        record.source_ip.as_deref() == Some("127.0.0.1")
-            || record
-                .inbound_name
-                .as_deref()
-                .map(|name| name.contains("MIXED"))
-                == Some(true)
-    }) {
-        "local proxy / DEFAULT-MIXED"
-    } else if records.iter().any(|record| {
-        record
-            .inbound_name
-            .as_deref()
-            .map(|name| name.to_ascii_uppercase().contains("TUN"))
-            == Some(true)
-    }) {
-        "transparent TUN"
+    if !failed.is_empty() {
+        Err(anyhow!("variant(s) failed: {}", failed.join(", ")))
+    } else if let Some(status) = exit_status {
+        Err(status)
     } else {
-        "unknown"
+        Ok(())
     }
 }
 
-fn json_string_path(value: &serde_json::Value, path: &[&str]) -> Option<String> {
-    let mut current = value;
-    for key in path {
-        current = current.get(*key)?;
+/// `merge --devices`: `merged` with the device's settings applied, written to its own file.
+async fn write_device_output(
+    merged: &mihomo_core::ClashConfig,
+    name: &str,
+    device: &storage::DeviceProfile,
+    output_path: &Path,
+    header: Option<&GenerationInfo>,
+    args: &MergeArgs,
+) -> anyhow::Result<()> {
+    let path = device
+        .output
+        .clone()
+        .unwrap_or_else(|| variant_output_path(output_path, name));
+    let config = mihomo_core::merge::apply_override(merged.clone(), &device.overlay())?;
+    let mut yaml = config.to_yaml_string()?;
+    let unchanged = args.if_changed
+        && fs::read_to_string(&path)
+            .await
+            .is_ok_and(|existing| strip_header(&existing) == yaml);
+    if unchanged {
+        println!(
+            "device '{}' config unchanged; {} left as is",
+            name,
+            path.display()
+        );
+        return Ok(());
     }
-    current.as_str().map(ToOwned::to_owned)
-}
-
-fn json_string_list_path(value: &serde_json::Value, path: &[&str]) -> Option<Vec<String>> {
-    let mut current = value;
-    for key in path {
-        current = current.get(*key)?;
+    if let Some(info) = header {
+        yaml = with_header(&yaml, info);
     }
-    Some(
-        current
-            .as_array()?
-            .iter()
-            .filter_map(|value| value.as_str().map(ToOwned::to_owned))
-            .collect(),
-    )
-}
-
-fn string_from_mapping(map: &serde_yaml::Mapping, key: &str) -> Option<String> {
-    map.get(Value::String(key.to_string()))
-        .and_then(|value| value.as_str())
-        .map(ToOwned::to_owned)
+    ensure_parent(&path).await?;
+    FileDeployer {
+        path: path.clone(),
+        keep_backups: args.keep_backups,
+    }
+    .deploy(&yaml)
+    .await?;
+    println!("device '{}' config written to {}", name, path.display());
+    Ok(())
 }
 
-fn bool_from_mapping(map: &serde_yaml::Mapping, key: &str) -> Option<bool> {
-    map.get(Value::String(key.to_string()))
-        .and_then(|value| value.as_bool())
+/// `clash-verge.yaml` + `canary` -> `clash-verge.canary.yaml`.
+fn variant_output_path(output: &Path, variant: &str) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "config".to_string());
+    let name = match output.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, variant, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, variant),
+    };
+    output.with_file_name(name)
 }
 
-fn string_list_from_mapping(map: &serde_yaml::Mapping, key: &str) -> Vec<String> {
-    map.get(Value::String(key.to_string()))
-        .and_then(|value| value.as_sequence())
-        .map(|seq| {
-            seq.iter()
-                .filter_map(|value| value.as_str().map(ToOwned::to_owned))
-                .collect()
-        })
-        .unwrap_or_default()
+/// Flags equivalent to a profiles.yaml entry (`dev_rules: false` is applied after parsing,
+/// as there is no flag for it).
+fn profile_argv(profile: &storage::MergeProfile) -> anyhow::Result<Vec<String>> {
+    reject_nested_bundles(&profile.args, "profile args")?;
+    let mut argv = Vec::new();
+    let mut push = |flag: &str, value: String| {
+        argv.push(flag.to_string());
+        argv.push(value);
+    };
+    if let Some(path) = &profile.template {
+        push("--template", path.display().to_string());
+    }
+    if let Some(path) = &profile.base_config {
+        push("--base-config", path.display().to_string());
+    }
+    for name in &profile.subscriptions {
+        push("--only-subscription", name.clone());
+    }
+    if let Some(via) = &profile.dev_rules_via {
+        push("--dev-rules-via", via.clone());
+    }
+    if let Some(path) = &profile.output {
+        push("--output", path.display().to_string());
+    }
+    argv.extend(profile.args.iter().cloned());
+    Ok(argv)
 }
 
-fn format_bool_opt(value: Option<bool>) -> &'static str {
-    match value {
-        Some(true) => "on",
-        Some(false) => "off",
-        None => "<unset>",
+fn reject_nested_bundles(flags: &[String], what: &str) -> anyhow::Result<()> {
+    let nested = flags.iter().any(|arg| {
+        ["--preset", "--profile"]
+            .iter()
+            .any(|flag| arg == flag || arg.starts_with(&format!("{}=", flag)))
+    });
+    if nested {
+        return Err(anyhow!("{} cannot reference presets or profiles", what));
     }
+    Ok(())
 }
 
-fn trimmed_single_line(raw: &str) -> Option<String> {
-    let trimmed = raw.trim();
-    if trimmed.is_empty() {
-        None
-    } else {
-        Some(trimmed.lines().next().unwrap_or(trimmed).trim().to_string())
-    }
+fn merge_argv(prefix: &[String], cli_args: &[String]) -> Vec<String> {
+    let mut argv = vec!["mihomo-cli".to_string(), "merge".to_string()];
+    argv.extend(prefix.iter().cloned());
+    argv.extend(cli_args.iter().cloned());
+    argv
 }
 
-fn init_tracing() {
-    let _ = tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .try_init();
+#[derive(Args)]
+struct InitArgs {
+    /// Replace an edited default template with the bundled one (the old copy is kept as
+    /// `cvr_template.yaml.bak`).
+    #[arg(long = "force-template-update", default_value_t = false)]
+    force_template_update: bool,
 }
 
-async fn run_merge(args: MergeArgs) -> anyhow::Result<()> {
+async fn run_init(args: InitArgs) -> anyhow::Result<()> {
     let paths = AppPaths::new()?;
+    // Create runtime directories (config, templates, resources, output, cache)
     paths.ensure_runtime_dirs().await?;
-    let mut app_cfg = storage::load_app_config(&paths).await?;
-    let original_app_cfg = app_cfg.clone();
-    let previous_managed_tailscale = app_cfg.managed_tailscale_compat.clone();
-
-    // Mimic clash-verge UA so some providers return Clash YAML (with rules)
-    let ua = args
-        .subscription_ua
-        .clone()
-        .unwrap_or_else(|| "clash-verge/v2.4.2".to_string());
-    let client = reqwest::Client::builder().user_agent(&ua).build()?;
-
-    // Configure core parser behavior (align with UA behavior):
-    // by default, do NOT attempt base64 decoding; allow only if explicitly requested.
-    mihomo_core::subscription::set_parse_options(mihomo_core::subscription::ParseOptions {
-        allow_base64: args.subscription_allow_base64,
-    });
-
-    ensure_mihomo_resources(&client, &paths).await?;
-
-    ensure_default_template(&paths).await?;
+    // Install bundled default template if missing, or update an untouched older copy
+    ensure_default_template(&paths, args.force_template_update).await?;
 
-    let template_path = args
-        .template
-        .as_ref()
-        .map(|p| resolve_template_path(&paths, p))
-        .unwrap_or_else(|| paths.default_template_path());
+    println!(
+        "Initialized at: {}\n  - templates: {}\n  - resources: {}\n  - output: {}\n  - cache: {}",
+        paths.config_dir().display(),
+        paths.templates_dir().display(),
+        paths.resources_dir().display(),
+        paths
+            .output_config_path()
+            .parent()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "<unknown>".into()),
+        paths.cache_dir().display()
+    );
 
-    let template = Template::load(&template_path)
-        .await
-        .with_context(|| format!("failed to load template from {}", template_path.display()))?
-        .into_config();
+    Ok(())
+}
 
-    let base_config_path = args
-        .base_config
-        .as_ref()
-        .map(|p| resolve_base_path(&paths, p))
-        .or_else(|| default_base_config_path(&paths));
+#[derive(Debug, Deserialize)]
+struct ClashVergeProfiles {
+    current: Option<String>,
+    #[serde(default)]
+    items: Vec<ClashVergeProfileItem>,
+}
 
-    let base_config = if let Some(path) = base_config_path {
-        Some(
-            Template::load(&path)
-                .await
-                .with_context(|| format!("failed to load base config from {}", path.display()))?
-                .into_config(),
-        )
-    } else {
-        None
-    };
+#[derive(Debug, Deserialize)]
+struct ClashVergeProfileItem {
+    uid: Option<String>,
+    url: Option<String>,
+}
 
-    let mut subscription_list = if let Some(path) = args.subscriptions_file.as_ref() {
-        load_subscriptions_from_path(path).await?
+async fn run_refresh_clash_verge(args: RefreshClashVergeArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    paths.ensure_runtime_dirs().await?;
+    let app_cfg = storage::load_app_config(&paths).await?;
+
+    let subscription = if let Some(subscription) = args.subscription.clone() {
+        subscription
     } else {
-        storage::load_subscription_list(&paths).await?
+        detect_current_clash_verge_subscription_url(&paths).await?
     };
 
-    let mut configs = Vec::new();
-    let mut used_url: Option<String> = None;
+    let mode = args
+        .mode
+        .or_else(|| config_mode_from_env("MIHOMOCLI_MODE"))
+        .unwrap_or(ConfigMode::Rule);
+    let sniffer_preset = args
+        .sniffer_preset
+        .or_else(|| sniffer_preset_from_env("MIHOMOCLI_SNIFFER_PRESET"))
+        .unwrap_or(SnifferPreset::Tun);
 
-    for subscription in subscription_list.items.iter_mut() {
-        match subscription.load_config(&client, &paths).await {
-            Ok(Some(config)) => configs.push(config),
-            Ok(None) => {}
-            Err(err) => {
-                tracing::error!(id = %subscription.id, error = %err, "failed to load subscription");
-            }
-        }
-        if let Some(url) = subscription.url.clone() {
-            used_url = Some(url);
-        }
-    }
+    let managed_tailnet_suffixes = app_cfg
+        .managed_tailscale_compat
+        .as_ref()
+        .map(derive_tailnet_suffixes_from_managed)
+        .unwrap_or_default();
+    let managed_direct_domains = app_cfg
+        .managed_tailscale_compat
+        .as_ref()
+        .map(derive_direct_domains_from_managed)
+        .unwrap_or_default();
+    let managed_route_excludes = app_cfg
+        .managed_tailscale_compat
+        .as_ref()
+        .map(derive_extra_route_excludes_from_managed)
+        .unwrap_or_default();
 
-    for (idx, source) in args.subscriptions.iter().enumerate() {
-        let mut subscription = subscription_from_input(idx, source);
-        match subscription.load_config(&client, &paths).await {
-            Ok(Some(config)) => configs.push(config),
-            Ok(None) => {}
-            Err(err) => {
-                tracing::error!(source = source, error = %err, "failed to load ad-hoc subscription");
-            }
+    let configured_tailnet_suffixes = app_cfg
+        .tailscale_compat_defaults
+        .as_ref()
+        .map(|defaults| defaults.tailnet_suffixes.clone())
+        .filter(|items| !items.is_empty())
+        .unwrap_or(managed_tailnet_suffixes);
+    let configured_direct_domains = app_cfg
+        .tailscale_compat_defaults
+        .as_ref()
+        .map(|defaults| defaults.direct_domains.clone())
+        .filter(|items| !items.is_empty())
+        .unwrap_or(managed_direct_domains);
+    let configured_route_excludes = app_cfg
+        .tailscale_compat_defaults
+        .as_ref()
+        .map(|defaults| defaults.route_exclude_address.clone())
+        .filter(|items| !items.is_empty())
+        .unwrap_or(managed_route_excludes);
+
+    let tailnet_suffixes = merge_string_lists(
+        configured_tailnet_suffixes,
+        env_list("MIHOMOCLI_TAILSCALE_SUFFIXES"),
+        env_list("MIHOMOCLI_TAILSCALE_SUFFIX"),
+        args.tailscale_tailnet_suffixes,
+    );
+    let direct_domains = merge_string_lists(
+        configured_direct_domains,
+        env_list("MIHOMOCLI_TAILSCALE_DIRECT_DOMAINS"),
+        Vec::new(),
+        args.tailscale_direct_domains,
+    );
+    let direct_cidrs = merge_string_lists(
+        configured_route_excludes,
+        env_list("MIHOMOCLI_TAILSCALE_DIRECT_CIDRS"),
+        Vec::new(),
+        args.route_exclude_address_add,
+    );
+
+    println!("Refreshing generated Clash Verge config from subscription:");
+    println!("  {}", subscription);
+    println!("Using Tailscale tailnet suffixes:");
+    println!(
+        "  {}",
+        if tailnet_suffixes.is_empty() {
+            "<none>".to_string()
+        } else {
+            tailnet_suffixes.join(", ")
         }
-        if let Some(url) = subscription.url.clone() {
-            used_url = Some(url);
+    );
+    println!("Using extra Tailscale direct domains:");
+    println!(
+        "  {}",
+        if direct_domains.is_empty() {
+            "<none>".to_string()
+        } else {
+            direct_domains.join(", ")
         }
-    }
-
-    // If requested and no explicit sources, reuse cached last subscription URL
-    if configs.is_empty() && args.subscriptions.is_empty() && subscription_list.items.is_empty() {
-        if args.use_last {
-            if let Some(last_url) = app_cfg.last_subscription_url.clone() {
-                tracing::info!(last_url = %last_url, "using cached last subscription URL");
-                let mut subscription = subscription_from_input(0, &last_url);
-                match subscription.load_config(&client, &paths).await {
-                    Ok(Some(config)) => {
-                        configs.push(config);
-                        used_url = Some(last_url);
-                    }
-                    Ok(None) => {}
-                    Err(err) => {
-                        return Err(anyhow!(
-                            "failed to load cached subscription {}: {}",
-                            last_url,
-                            err
-                        ));
-                    }
-                }
-            } else {
-                return Err(anyhow!(
-                    "--use-last set but no cached last subscription URL found. Merge once with -s/--subscription first."
-                ));
-            }
+    );
+    println!("Using extra Tailscale route-exclude CIDRs:");
+    println!(
+        "  {}",
+        if direct_cidrs.is_empty() {
+            "<none>".to_string()
         } else {
-            return Err(anyhow!(
-                "no subscription provided. Pass -s/--subscription or use --use-last to reuse the cached last URL."
-            ));
+            direct_cidrs.join(", ")
         }
-    }
+    );
+    println!("Using Clash mode:");
+    println!("  {}", mode.as_str());
+    println!("Using sniffer preset:");
+    println!(
+        "  {}",
+        match sniffer_preset {
+            SnifferPreset::Off => "off",
+            SnifferPreset::Tun => "tun",
+        }
+    );
 
-    let mut merged = merge_configs(template, configs);
+    let merge_args = MergeArgs {
+        template: None,
+        base_config: None,
+        base_sections: Vec::new(),
+        subscriptions_file: None,
+        subscriptions: vec![subscription],
+        output: None,
+        mode,
+        sniffer_preset,
+        sync_to_clash_verge: true,
+        sync_to_clash_verge_sources: true,
+        clash_verge_profile: None,
+        stdout: false,
+        redact_secrets: false,
+        dev_rules: true,
+        dev_rules_via: DEFAULT_DEV_RULE_VIA.to_string(),
+        dev_rules_show: false,
+        use_last: false,
+        subscription_ua: None,
+        subscription_allow_base64: false,
+        fetch_proxy: None,
+        resource_mirrors: Vec::new(),
+        external_controller_url: None,
+        external_controller_port: None,
+        external_controller_secret: None,
+        fake_ip_filter_add: Vec::new(),
+        fake_ip_filter_mode: None,
+        k8s_cidr_exclude: Vec::new(),
+        route_exclude_address_add: direct_cidrs,
+        fake_ip_bypass: Vec::new(),
+        dry_run: args.dry_run,
+        tailscale_compatible: !args.no_tailscale_compatible,
+        tailscale_tailnet_suffixes: tailnet_suffixes,
+        tailscale_direct_domains: direct_domains,
+        group_order: None,
+        dedup_proxies: ProxyDedupArg::Rename,
+        annotate_latency: None,
+        group_order_list: Vec::new(),
+        health_check_url: None,
+        group_health_check_urls: Vec::new(),
+        group_icons: Vec::new(),
+        provider_groups: None,
+        probe: None,
+        exclude_dead_after: None,
+        probe_timeout_ms: 3000,
+        probe_concurrency: 32,
+        auto_region_groups: false,
+        region_groups: Vec::new(),
+        region_source: None,
+        region_csv: None,
+        region_api: None,
+        prune_empty_groups: false,
+        extract_rule_providers: false,
+        extract_min_rules: 50,
+        proxies_only: false,
+        include_proxy: Vec::new(),
+        exclude_proxy: Vec::new(),
+        header: false,
+        no_timestamp: false,
+        deterministic: false,
+        disable_after_failures: None,
+        // Clash Verge manages the controller secret of its own runtime.
+        no_auto_secret: true,
+        max_payload_bytes: None,
+        max_proxies: None,
+        diff: false,
+        force_template_update: false,
+        keep_backups: 3,
+        keep_history: history::DEFAULT_HISTORY_LIMIT,
+        deploy_api: None,
+        deploy_secret: None,
+        deploy_ca_cert: None,
+        deploy_ssh: None,
+        deploy_ssh_port: None,
+        deploy_ssh_identity: None,
+        deploy_ssh_reload: None,
+        post_hooks: Vec::new(),
+        max_redirects: 10,
+        if_changed: false,
+        watch: false,
+        interval: std::time::Duration::from_secs(6 * 3600),
+        drain_timeout: std::time::Duration::from_secs(30),
+        reload: None,
+        skip_resources: false,
+        resources_mode: None,
+        strict: false,
+        // Clash Verge applies its own Merge/Script chain afterwards, which may define them.
+        lenient: true,
+        preset: None,
+        profile: None,
+        variants: Vec::new(),
+        devices: Vec::new(),
+        only_subscriptions: Vec::new(),
+    };
 
-    // Inject manually-managed proxies (e.g. a private trojan server) before applying base-config,
-    // so that base-config group rebuild sees all proxy names.
-    if !app_cfg.manual_servers.is_empty() {
-        let added = inject_manual_servers(&mut merged, &app_cfg).await?;
-        if added > 0 {
-            info!(added = added, "injected manual server proxies");
-        }
-    }
+    run_merge(merge_args).await
+}
 
-    if let Some(base) = base_config.as_ref() {
-        merged = mihomo_core::merge::apply_base_config(merged, base);
+async fn run_runtime(args: RuntimeArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    paths.ensure_runtime_dirs().await?;
+
+    match args.command {
+        RuntimeCommand::Status(args) => run_runtime_status(&paths, &args).await,
+        RuntimeCommand::Reload => run_runtime_reload(&paths).await,
+        RuntimeCommand::Mode(args) => run_runtime_mode(&paths, args.mode).await,
     }
+}
 
-    apply_mode_override(&mut merged, args.mode);
-    apply_tun_enabled(&mut merged, true);
-    apply_sniffer_preset(&mut merged, args.sniffer_preset);
+async fn run_runtime_status(paths: &AppPaths, args: &DoctorArgs) -> anyhow::Result<()> {
+    let runtime_paths = existing_runtime_paths(paths).await;
+    let runtime_summaries = load_runtime_summaries(&runtime_paths).await;
 
-    if let Some(previous) = app_cfg.managed_tailscale_compat.as_ref() {
-        remove_tailscale_managed_items(&mut merged, previous);
-    }
+    println!("mihomo-cli runtime status");
+    println!();
 
-    let mut dev_rules_listing = None;
-    let mut summary_dev_via: Option<String> = None;
-    let mut summary_dev_added: usize = 0;
-    if args.dev_rules || args.dev_rules_show {
-        let resolved_via =
-            resolve_dev_rules_via(&args.dev_rules_via, DEFAULT_DEV_RULE_VIA, &merged);
-        if resolved_via != args.dev_rules_via && args.dev_rules {
-            warn!(
-                requested = %args.dev_rules_via,
-                using = %resolved_via,
-                "--dev-rules-via not found in config; using fallback"
-            );
-        }
+    if runtime_summaries.is_empty() {
+        println!("Runtime files:");
+        println!("  status: no local runtime files detected");
+        return Ok(());
+    }
 
-        let list = build_dev_rules(&resolved_via);
-        if args.dev_rules {
-            let mut combined = list.clone();
-            combined.extend(merged.rules.into_iter());
-            merged.rules = combined;
-            summary_dev_via = Some(resolved_via.clone());
-            summary_dev_added = list.len();
-        } else {
-            // even if not applied, keep via for summary visibility
-            summary_dev_via = Some(resolved_via.clone());
-        }
-        dev_rules_listing = Some(list);
+    println!("Runtime files:");
+    for summary in &runtime_summaries {
+        print_runtime_summary(summary);
     }
 
-    // Prepend custom quick rules (take precedence)
-    if !app_cfg.custom_rules.is_empty() {
-        let mut quick = Vec::with_capacity(app_cfg.custom_rules.len());
-        for r in &app_cfg.custom_rules {
-            let tag = match r.kind {
-                RuleKind::Domain => "DOMAIN",
-                RuleKind::DomainSuffix => "DOMAIN-SUFFIX",
-                RuleKind::DomainKeyword => "DOMAIN-KEYWORD",
-            };
-            quick.push(format!("{},{},{}", tag, r.domain, r.via));
-        }
-        let mut new_rules = quick;
-        new_rules.extend(merged.rules.into_iter());
-        merged.rules = new_rules;
+    if runtime_summaries.len() >= 2 {
+        let primary = &runtime_summaries[0];
+        let aligned = runtime_summaries[1..]
+            .iter()
+            .all(|candidate| runtime_summary_core_eq(primary, candidate));
+        println!(
+            "  file-state-aligned: {}",
+            if aligned { "yes" } else { "no" }
+        );
     }
 
-    // Apply external-controller overrides if provided
-    if args.external_controller_url.is_some()
-        || args.external_controller_port.is_some()
-        || args.external_controller_secret.is_some()
+    if let Some(source_summary) = load_merge_profile_summary(paths).await {
+        println!("Clash Verge source profile:");
+        print_runtime_summary(&source_summary);
+    }
+
+    println!();
+    if let Some(controller) = runtime_summaries
+        .iter()
+        .find_map(|summary| summary.controller.clone())
     {
-        use serde_yaml::Value;
+        print_controller_summary(&controller, args);
+    } else {
+        println!("Controller:");
+        println!("  status: unavailable (no controller settings found in local config)");
+    }
 
-        // Read any existing external-controller value like "host:port"
-        let mut existing_host: Option<String> = None;
-        let mut existing_port: Option<u16> = None;
-        if let Some(Value::String(s)) = merged.extra.get("external-controller") {
-            if let Some((h, p)) = parse_host_port(s) {
-                existing_host = Some(h);
-                existing_port = Some(p);
-            }
-        }
+    Ok(())
+}
 
-        let host = args
-            .external_controller_url
-            .clone()
-            .or(existing_host)
-            .unwrap_or_else(|| "127.0.0.1".to_string());
-        let port = args
-            .external_controller_port
-            .or(existing_port)
-            .unwrap_or(9090);
-        merged.extra.insert(
-            "external-controller".to_string(),
-            Value::String(format!("{}:{}", host, port)),
-        );
+async fn run_runtime_reload(paths: &AppPaths) -> anyhow::Result<()> {
+    let runtime_paths = existing_runtime_paths(paths).await;
+    let primary_path = preferred_runtime_path(&runtime_paths)
+        .ok_or_else(|| anyhow!("no local Clash Verge runtime config was detected"))?;
+    let cfg = load_runtime_config(primary_path).await?;
 
-        if let Some(secret) = args.external_controller_secret.as_ref() {
-            merged
-                .extra
-                .insert("secret".to_string(), Value::String(secret.clone()));
-        }
-    }
+    reload_clash_verge_runtime(&cfg, &runtime_paths).await?;
+    println!("runtime reload completed from {}", primary_path.display());
+    Ok(())
+}
 
-    // Append fake-ip bypass entries: combine new clearer option with legacy flag
-    let mut bypass_entries: Vec<String> = Vec::new();
-    bypass_entries.extend(args.fake_ip_bypass.iter().cloned());
-    bypass_entries.extend(args.fake_ip_filter_add.iter().cloned());
-    if !bypass_entries.is_empty() {
-        use serde_yaml::{Mapping, Value};
-        // Ensure dns mapping exists
-        let dns_value = merged
-            .extra
-            .entry("dns".to_string())
-            .or_insert_with(|| Value::Mapping(Mapping::new()));
-        if let Value::Mapping(dns_map) = dns_value {
-            // Ensure fake-ip-filter sequence exists
-            let key = Value::String("fake-ip-filter".to_string());
-            let filter_seq = dns_map
-                .entry(key)
-                .or_insert_with(|| Value::Sequence(Vec::new()));
-            if let Value::Sequence(seq) = filter_seq {
-                for item in bypass_entries {
-                    seq.push(Value::String(item));
-                }
-            }
-            // Force blacklist mode when user requests bypass entries
-            let mode_key = Value::String("fake-ip-filter-mode".to_string());
-            let current_mode = dns_map.get(&mode_key).and_then(|v| v.as_str());
-            let desired = "blacklist";
-            if current_mode.map(|s| s.eq_ignore_ascii_case(desired)) != Some(true) {
-                if let Some(cm) = current_mode {
-                    warn!(current = %cm, "overriding fake-ip-filter-mode to 'blacklist' for --fake-ip-bypass");
-                }
-                dns_map.insert(mode_key, Value::String(desired.to_string()));
-            }
+async fn run_runtime_mode(paths: &AppPaths, mode: ConfigMode) -> anyhow::Result<()> {
+    let runtime_paths = existing_runtime_paths(paths).await;
+    let primary_path = preferred_runtime_path(&runtime_paths)
+        .ok_or_else(|| anyhow!("no local Clash Verge runtime config was detected"))?
+        .clone();
+
+    let mut reload_cfg: Option<mihomo_core::ClashConfig> = None;
+    for path in &runtime_paths {
+        let mut cfg = load_runtime_config(path).await?;
+        apply_mode_override(&mut cfg, mode);
+        storage::ensure_writable(path)?;
+        fs::write(path, cfg.to_yaml_string()?)
+            .await
+            .with_context(|| format!("failed to write runtime mode to {}", path.display()))?;
+        println!("updated runtime mode in {}", path.display());
+        if *path == primary_path {
+            reload_cfg = Some(cfg);
         }
     }
 
-    // Apply fake-ip-filter-mode if provided explicitly (advanced)
-    if let Some(mode) = args.fake_ip_filter_mode.as_ref() {
-        let m = mode.to_ascii_lowercase();
-        if m == "blacklist" || m == "whitelist" {
-            use serde_yaml::{Mapping, Value};
-            let dns_value = merged
-                .extra
-                .entry("dns".to_string())
-                .or_insert_with(|| Value::Mapping(Mapping::new()));
-            if let Value::Mapping(dns_map) = dns_value {
-                // If user also used --fake-ip-bypass and asks for whitelist, warn and keep blacklist
-                let requested_whitelist = m == "whitelist";
-                let used_bypass = !args.fake_ip_bypass.is_empty();
-                if requested_whitelist && used_bypass {
-                    warn!("--fake-ip-bypass works with blacklist mode; keeping 'blacklist' instead of requested 'whitelist'");
-                } else {
-                    dns_map.insert(
-                        Value::String("fake-ip-filter-mode".to_string()),
-                        Value::String(m),
-                    );
-                }
-            }
-        } else {
-            warn!(
-                value = %mode,
-                "invalid --fake-ip-filter-mode (expected 'blacklist' or 'whitelist')"
-            );
+    if let Some(merge_path) = paths.detected_clash_verge_profile_merge_path() {
+        if fs::try_exists(&merge_path).await.unwrap_or(false) {
+            sync_merge_profile_mode(&merge_path, mode).await?;
         }
     }
 
-    // Ensure Kubernetes cluster DNS names are not forced into fake-ip.
-    //
-    // When tun + dns-hijack is enabled, in-cluster lookups like
-    // *.svc.cluster.local may be intercepted and incorrectly resolved into the
-    // fake-ip range (198.18.0.0/16), breaking Kubernetes service discovery.
-    //
-    // Keep this minimal and only apply in fake-ip mode when filter mode is not
-    // whitelist.
-    {
-        use serde_yaml::{Mapping, Value};
+    let reload_cfg = reload_cfg.ok_or_else(|| {
+        anyhow!(
+            "failed to prepare reload config from preferred runtime path {}",
+            primary_path.display()
+        )
+    })?;
+    reload_clash_verge_runtime(&reload_cfg, &runtime_paths).await?;
+    println!("runtime mode is now {}", mode.as_str());
+    Ok(())
+}
 
-        let dns_value = merged
-            .extra
-            .entry("dns".to_string())
-            .or_insert_with(|| Value::Mapping(Mapping::new()));
+async fn detect_current_clash_verge_subscription_url(paths: &AppPaths) -> anyhow::Result<String> {
+    let profiles_path = paths
+        .detected_clash_verge_profiles_path()
+        .ok_or_else(|| anyhow!("could not detect Clash Verge profiles.yaml path"))?;
 
-        if let Value::Mapping(dns_map) = dns_value {
-            let enhanced_key = Value::String("enhanced-mode".to_string());
-            let mode_key = Value::String("fake-ip-filter-mode".to_string());
-            let filter_key = Value::String("fake-ip-filter".to_string());
+    let raw = fs::read_to_string(&profiles_path)
+        .await
+        .with_context(|| format!("failed to read {}", profiles_path.display()))?;
+    let profiles: ClashVergeProfiles = serde_yaml::from_str(&raw)
+        .with_context(|| format!("failed to parse {}", profiles_path.display()))?;
 
-            let enhanced = dns_map
-                .get(&enhanced_key)
-                .and_then(|v| v.as_str())
-                .unwrap_or("");
+    extract_current_clash_verge_subscription_url(&profiles).ok_or_else(|| {
+        anyhow!(
+            "no current Clash Verge remote subscription URL found in {}",
+            profiles_path.display()
+        )
+    })
+}
+
+fn extract_current_clash_verge_subscription_url(profiles: &ClashVergeProfiles) -> Option<String> {
+    let current = profiles.current.as_ref()?;
+    profiles
+        .items
+        .iter()
+        .find(|item| item.uid.as_deref() == Some(current.as_str()))
+        .and_then(|item| item.url.as_ref())
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+}
+
+fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .ok()
+        .map(|raw| split_list_items(&raw))
+        .unwrap_or_default()
+}
+
+fn split_list_items(raw: &str) -> Vec<String> {
+    raw.split(|ch: char| ch == ',' || ch.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+fn merge_string_lists(
+    primary: Vec<String>,
+    secondary: Vec<String>,
+    tertiary: Vec<String>,
+    explicit: Vec<String>,
+) -> Vec<String> {
+    let mut merged = Vec::new();
+    extend_unique(&mut merged, primary);
+    extend_unique(&mut merged, secondary);
+    extend_unique(&mut merged, tertiary);
+    extend_unique(&mut merged, explicit);
+    merged
+}
+
+fn extend_unique(dest: &mut Vec<String>, values: Vec<String>) {
+    for value in values {
+        if !dest.iter().any(|existing| existing == &value) {
+            dest.push(value);
+        }
+    }
+}
+
+fn config_mode_from_env(name: &str) -> Option<ConfigMode> {
+    match std::env::var(name)
+        .ok()?
+        .trim()
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "rule" => Some(ConfigMode::Rule),
+        "global" => Some(ConfigMode::Global),
+        "direct" => Some(ConfigMode::Direct),
+        other => {
+            warn!(env = %name, value = %other, "ignoring invalid config mode from environment");
+            None
+        }
+    }
+}
+
+fn sniffer_preset_from_env(name: &str) -> Option<SnifferPreset> {
+    match std::env::var(name)
+        .ok()?
+        .trim()
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "tun" => Some(SnifferPreset::Tun),
+        "off" => Some(SnifferPreset::Off),
+        other => {
+            warn!(env = %name, value = %other, "ignoring invalid sniffer preset from environment");
+            None
+        }
+    }
+}
+
+fn derive_tailnet_suffixes_from_managed(managed: &ManagedTailscaleCompat) -> Vec<String> {
+    managed
+        .fake_ip_filter
+        .iter()
+        .filter_map(|entry| entry.strip_prefix("+.tail."))
+        .map(|suffix| suffix.to_string())
+        .collect()
+}
+
+fn derive_direct_domains_from_managed(managed: &ManagedTailscaleCompat) -> Vec<String> {
+    let mut domains = Vec::new();
+
+    for rule in &managed.rules {
+        if TAILSCALE_BASE_DIRECT_RULES.contains(&rule.as_str()) {
+            continue;
+        }
+
+        let mut parts = rule.splitn(3, ',');
+        let Some(kind) = parts.next() else {
+            continue;
+        };
+        let Some(target) = parts.next() else {
+            continue;
+        };
+        let Some(via) = parts.next() else {
+            continue;
+        };
+        if !via.eq_ignore_ascii_case("DIRECT") {
+            continue;
+        }
+
+        match kind {
+            "DOMAIN" if !target.is_empty() => {
+                domains.push(target.to_string());
+            }
+            "DOMAIN-SUFFIX"
+                if !target.starts_with("tail.")
+                    && !target.eq_ignore_ascii_case("tailscale.com")
+                    && !target.eq_ignore_ascii_case("ts.net") =>
+            {
+                domains.push(format!("+.{}", target));
+            }
+            _ => {}
+        }
+    }
+
+    let mut deduped = Vec::new();
+    extend_unique(&mut deduped, domains);
+    deduped
+}
+
+fn derive_extra_route_excludes_from_managed(managed: &ManagedTailscaleCompat) -> Vec<String> {
+    managed
+        .route_exclude_address
+        .iter()
+        .filter(|entry| !TAILSCALE_ROUTE_EXCLUDES.contains(&entry.as_str()))
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct RuntimeSummary {
+    path: PathBuf,
+    mode: Option<String>,
+    tun_enabled: Option<bool>,
+    tun_auto_route: Option<bool>,
+    sniffer_enabled: bool,
+    enhanced_mode: Option<String>,
+    fake_ip_range: Option<String>,
+    route_excludes: Vec<String>,
+    rules_count: usize,
+    controller: Option<ControllerEndpoint>,
+}
+
+#[derive(Debug, Clone)]
+struct ControllerEndpoint {
+    host: Option<String>,
+    port: Option<u16>,
+    secret: Option<String>,
+    unix_socket: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionRecord {
+    host: Option<String>,
+    inbound_name: Option<String>,
+    source_ip: Option<String>,
+    chains: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ProxyEntry {
+    kind: &'static str,
+    host: String,
+    port: String,
+}
+
+async fn run_doctor(cmd: DoctorCmdArgs) -> anyhow::Result<()> {
+    let args = cmd.live;
+    let paths = AppPaths::new()?;
+    paths.ensure_runtime_dirs().await?;
+
+    let runtime_paths = paths.detected_clash_verge_runtime_config_paths();
+    let runtime_summaries = load_runtime_summaries(&runtime_paths).await;
+    let controller = match runtime_summaries
+        .iter()
+        .find_map(|summary| summary.controller.clone())
+    {
+        Some(controller) => Some(controller),
+        None => load_runtime_config(&paths.output_config_path())
+            .await
+            .ok()
+            .and_then(|cfg| parse_controller_endpoint(&cfg)),
+    };
+
+    println!("mihomo-cli doctor");
+    println!();
+
+    let mut checks = Vec::new();
+    check_config_layout(&paths, &mut checks).await;
+    check_template(&paths, &mut checks).await;
+    if !cmd.skip_subscriptions {
+        check_subscriptions(&paths, &mut checks).await;
+    }
+    check_resources(&paths, &mut checks).await;
+    check_mihomo_binary(&mihomo_binary(&paths, cmd.mihomo_bin), &mut checks).await;
+    check_controller(controller.as_ref(), &mut checks);
+    println!("Checks:");
+    for check in &checks {
+        println!(
+            "  [{}] {}: {}",
+            check.status.label(),
+            check.name,
+            check.detail
+        );
+    }
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Fail)
+        .count();
+    println!();
+
+    if runtime_summaries.is_empty() {
+        println!("Clash Verge runtime files:");
+        println!("  status: no local runtime files detected");
+    } else {
+        println!("Clash Verge runtime files:");
+        for summary in &runtime_summaries {
+            print_runtime_summary(summary);
+        }
+
+        if runtime_summaries.len() >= 2 {
+            let primary = &runtime_summaries[0];
+            let aligned = runtime_summaries[1..]
+                .iter()
+                .all(|candidate| runtime_summary_core_eq(primary, candidate));
+            println!(
+                "  file-state-aligned: {}",
+                if aligned { "yes" } else { "no" }
+            );
+        }
+    }
+
+    println!();
+    print_system_proxy_summary();
+    println!();
+    print_tailscale_summary();
+    println!();
+
+    if let Some(controller) = controller.as_ref() {
+        print_controller_summary(controller, &args);
+    } else {
+        println!("Controller:");
+        println!("  status: unavailable (no controller settings found in local config)");
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} doctor check(s) failed", failed));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    fn label(self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "FAIL",
+        }
+    }
+}
+
+struct DoctorCheck {
+    name: String,
+    status: CheckStatus,
+    detail: String,
+}
+
+impl DoctorCheck {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+async fn check_config_layout(paths: &AppPaths, checks: &mut Vec<DoctorCheck>) {
+    for (name, dir) in [
+        ("config-dir", paths.config_dir().to_path_buf()),
+        ("templates-dir", paths.templates_dir()),
+        ("resources-dir", paths.resources_dir()),
+        ("cache-dir", paths.cache_dir().to_path_buf()),
+    ] {
+        let check = match fs::metadata(&dir).await {
+            Ok(meta) if meta.is_dir() => {
+                DoctorCheck::new(name, CheckStatus::Pass, dir.display().to_string())
+            }
+            Ok(_) => DoctorCheck::new(
+                name,
+                CheckStatus::Fail,
+                format!("{} is not a directory", dir.display()),
+            ),
+            Err(_) => DoctorCheck::new(
+                name,
+                CheckStatus::Fail,
+                format!("{} is missing; run `mihomo-cli init`", dir.display()),
+            ),
+        };
+        checks.push(check);
+    }
+
+    checks.push(match storage::load_app_config(paths).await {
+        Ok(_) => DoctorCheck::new(
+            "app-config",
+            CheckStatus::Pass,
+            paths.app_config_path().display().to_string(),
+        ),
+        Err(err) => DoctorCheck::new("app-config", CheckStatus::Fail, format!("{:#}", err)),
+    });
+    checks.push(match storage::load_subscription_list(paths).await {
+        Ok(list) => DoctorCheck::new(
+            "subscriptions-file",
+            CheckStatus::Pass,
+            format!("{} subscription(s)", list.items.len()),
+        ),
+        Err(err) => DoctorCheck::new(
+            "subscriptions-file",
+            CheckStatus::Fail,
+            format!("{:#}", err),
+        ),
+    });
+}
+
+async fn check_template(paths: &AppPaths, checks: &mut Vec<DoctorCheck>) {
+    let selected = storage::load_app_config(paths)
+        .await
+        .ok()
+        .and_then(|cfg| cfg.template);
+    let path = match selected.as_deref() {
+        Some(name) => paths.named_template_path(name),
+        None => paths.default_template_path(),
+    };
+    let check = if !fs::try_exists(&path).await.unwrap_or(false) {
+        DoctorCheck::new(
+            "template",
+            CheckStatus::Warn,
+            format!(
+                "{} not installed yet; `init` or `merge` creates it",
+                path.display()
+            ),
+        )
+    } else {
+        match Template::load(&path).await {
+            Ok(template) => DoctorCheck::new(
+                "template",
+                CheckStatus::Pass,
+                format!(
+                    "{} ({} group(s), {} rule(s))",
+                    path.display(),
+                    template.config().proxy_groups.len(),
+                    template.config().rules.len()
+                ),
+            ),
+            Err(err) => DoctorCheck::new("template", CheckStatus::Fail, format!("{:#}", err)),
+        }
+    };
+    checks.push(check);
+}
+
+/// Timeout for each subscription request made by `doctor`.
+const DOCTOR_FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+async fn check_subscriptions(paths: &AppPaths, checks: &mut Vec<DoctorCheck>) {
+    let Ok(list) = storage::load_subscription_list(paths).await else {
+        return;
+    };
+    let mut fetches = tokio::task::JoinSet::new();
+    for (idx, subscription) in list.enabled().enumerate() {
+        let name = format!("subscription {}", subscription_label(subscription));
+        if let Some(path) = subscription.path.clone() {
+            let check = if fs::try_exists(&path).await.unwrap_or(false) {
+                DoctorCheck::new(name, CheckStatus::Pass, path.display().to_string())
+            } else {
+                DoctorCheck::new(
+                    name,
+                    CheckStatus::Fail,
+                    format!("{} does not exist", path.display()),
+                )
+            };
+            fetches.spawn(async move { (idx, check) });
+            continue;
+        }
+        let Some(url) = subscription.url.clone() else {
+            continue;
+        };
+        let client = build_fetch_client(
+            DEFAULT_SUBSCRIPTION_UA,
+            subscription.proxy.as_deref(),
+            10,
+            None,
+        );
+        fetches.spawn(async move {
+            let url = match interpolate::expand_env_vars(&url) {
+                Ok(url) => secrets::resolve_secret(&url).await,
+                Err(err) => Err(err),
+            };
+            let outcome = match (client, url) {
+                (Ok(client), Ok(url)) => client
+                    .get(&url)
+                    .timeout(DOCTOR_FETCH_TIMEOUT)
+                    .send()
+                    .await
+                    .map_err(|err| err.to_string()),
+                (Err(err), _) | (_, Err(err)) => Err(format!("{:#}", err)),
+            };
+            let check = match outcome {
+                Ok(response) if response.status().is_success() => DoctorCheck::new(
+                    name,
+                    CheckStatus::Pass,
+                    format!("HTTP {}", response.status().as_u16()),
+                ),
+                Ok(response) => DoctorCheck::new(
+                    name,
+                    CheckStatus::Fail,
+                    format!("HTTP {}", response.status().as_u16()),
+                ),
+                Err(err) => DoctorCheck::new(name, CheckStatus::Fail, err),
+            };
+            (idx, check)
+        });
+    }
+    let mut results = Vec::new();
+    while let Some(joined) = fetches.join_next().await {
+        if let Ok(result) = joined {
+            results.push(result);
+        }
+    }
+    if results.is_empty() {
+        checks.push(DoctorCheck::new(
+            "subscriptions",
+            CheckStatus::Warn,
+            "no enabled subscriptions",
+        ));
+    }
+    results.sort_by_key(|(idx, _)| *idx);
+    checks.extend(results.into_iter().map(|(_, check)| check));
+}
+
+async fn check_resources(paths: &AppPaths, checks: &mut Vec<DoctorCheck>) {
+    for (name, _) in RESOURCE_SOURCES.iter() {
+        let path = paths.resource_file(name);
+        let check = match fs::read(&path).await {
+            Err(_) => DoctorCheck::new(
+                *name,
+                CheckStatus::Warn,
+                "missing; merge downloads it when needed",
+            ),
+            Ok(bytes) => match resource_problem(name, &bytes) {
+                None => DoctorCheck::new(
+                    *name,
+                    CheckStatus::Pass,
+                    format!("{} ({})", path.display(), format_bytes(bytes.len() as u64)),
+                ),
+                Some(problem) => DoctorCheck::new(
+                    *name,
+                    CheckStatus::Fail,
+                    format!(
+                        "{} in {}; run `mihomo-cli resources update --force`",
+                        problem,
+                        path.display()
+                    ),
+                ),
+            },
+        };
+        checks.push(check);
+    }
+}
+
+/// Marker that starts the metadata section at the end of every MaxMind DB file.
+const MMDB_METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// Why the contents of resource `name` cannot be valid, judged by their leading/trailing bytes.
+fn resource_problem(name: &str, bytes: &[u8]) -> Option<&'static str> {
+    if bytes.is_empty() {
+        return Some("file is empty");
+    }
+    if name.ends_with(".mmdb") {
+        // The metadata section lives in the last 128 KiB of the file.
+        let tail = &bytes[bytes.len().saturating_sub(128 * 1024)..];
+        let found = tail
+            .windows(MMDB_METADATA_MARKER.len())
+            .any(|window| window == MMDB_METADATA_MARKER);
+        return (!found).then_some("no MaxMind metadata marker (corrupt or not an mmdb)");
+    }
+    // geoip.dat / geosite.dat are protobuf lists whose first field is a length-delimited entry.
+    (bytes[0] != 0x0a).then_some("does not start like a v2ray geodata file")
+}
+
+async fn check_mihomo_binary(bin: &Path, checks: &mut Vec<DoctorCheck>) {
+    let output = tokio::process::Command::new(bin).arg("-v").output().await;
+    let check = match output {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            DoctorCheck::new(
+                "mihomo",
+                CheckStatus::Pass,
+                trimmed_single_line(&stdout).unwrap_or_else(|| bin.display().to_string()),
+            )
+        }
+        Ok(output) => DoctorCheck::new(
+            "mihomo",
+            CheckStatus::Fail,
+            format!(
+                "`{} -v` exited with {:?}",
+                bin.display(),
+                output.status.code()
+            ),
+        ),
+        Err(err) => DoctorCheck::new(
+            "mihomo",
+            CheckStatus::Fail,
+            format!("cannot run {}: {}", bin.display(), err),
+        ),
+    };
+    checks.push(check);
+}
+
+fn check_controller(controller: Option<&ControllerEndpoint>, checks: &mut Vec<DoctorCheck>) {
+    let check = match controller {
+        None => DoctorCheck::new(
+            "controller",
+            CheckStatus::Warn,
+            "no external-controller configured",
+        ),
+        Some(controller) => {
+            match probe_controller_http(controller).or_else(|| probe_controller_unix(controller)) {
+                Some(probe) => DoctorCheck::new(
+                    "controller",
+                    CheckStatus::Pass,
+                    probe
+                        .version_line
+                        .unwrap_or_else(|| "reachable".to_string()),
+                ),
+                None => DoctorCheck::new(
+                    "controller",
+                    CheckStatus::Fail,
+                    "not reachable over HTTP or unix socket",
+                ),
+            }
+        }
+    };
+    checks.push(check);
+}
+
+async fn load_runtime_summaries(paths: &[PathBuf]) -> Vec<RuntimeSummary> {
+    let mut summaries = Vec::new();
+
+    for path in paths {
+        let Ok(true) = fs::try_exists(path).await else {
+            continue;
+        };
+        let Ok(raw) = fs::read_to_string(path).await else {
+            continue;
+        };
+        let Ok(cfg) = mihomo_core::ClashConfig::from_yaml_str(&raw) else {
+            continue;
+        };
+        summaries.push(runtime_summary_from_config(path, &cfg));
+    }
+
+    summaries
+}
+
+async fn load_runtime_config(path: &Path) -> anyhow::Result<mihomo_core::ClashConfig> {
+    let raw = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    mihomo_core::ClashConfig::from_yaml_str(&raw)
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+async fn existing_runtime_paths(paths: &AppPaths) -> Vec<PathBuf> {
+    let mut existing = Vec::new();
+    for path in paths.detected_clash_verge_runtime_config_paths() {
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            existing.push(path);
+        }
+    }
+    existing
+}
+
+fn preferred_runtime_path(paths: &[PathBuf]) -> Option<&PathBuf> {
+    paths
+        .iter()
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name == "clash-verge.yaml")
+        })
+        .or_else(|| {
+            paths.iter().find(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name == "config.yaml")
+            })
+        })
+        .or_else(|| paths.first())
+}
+
+fn runtime_summary_from_config(path: &Path, cfg: &mihomo_core::ClashConfig) -> RuntimeSummary {
+    let mode = cfg
+        .extra
+        .get("mode")
+        .and_then(|value| value.as_str())
+        .map(ToOwned::to_owned);
+
+    let tun_map = cfg.extra.get("tun").and_then(|value| value.as_mapping());
+    let tun_enabled = tun_map.and_then(|map| bool_from_mapping(map, "enable"));
+    let tun_auto_route = tun_map.and_then(|map| bool_from_mapping(map, "auto-route"));
+    let route_excludes = tun_map
+        .map(|map| string_list_from_mapping(map, "route-exclude-address"))
+        .unwrap_or_default();
+
+    let dns_map = cfg.extra.get("dns").and_then(|value| value.as_mapping());
+    let enhanced_mode = dns_map.and_then(|map| string_from_mapping(map, "enhanced-mode"));
+    let fake_ip_range = dns_map.and_then(|map| string_from_mapping(map, "fake-ip-range"));
+
+    let sniffer_enabled = cfg.extra.contains_key("sniffer");
+
+    RuntimeSummary {
+        path: path.to_path_buf(),
+        mode,
+        tun_enabled,
+        tun_auto_route,
+        sniffer_enabled,
+        enhanced_mode,
+        fake_ip_range,
+        route_excludes,
+        rules_count: cfg.rules.len(),
+        controller: parse_controller_endpoint(cfg),
+    }
+}
+
+async fn load_merge_profile_summary(paths: &AppPaths) -> Option<RuntimeSummary> {
+    let merge_path = paths.detected_clash_verge_profile_merge_path()?;
+    if !fs::try_exists(&merge_path).await.unwrap_or(false) {
+        return None;
+    }
+    let cfg = load_runtime_config(&merge_path).await.ok()?;
+    Some(runtime_summary_from_config(&merge_path, &cfg))
+}
+
+async fn sync_merge_profile_mode(path: &Path, mode: ConfigMode) -> anyhow::Result<()> {
+    let raw = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut doc: serde_yaml::Value = serde_yaml::from_str(&raw)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    ensure_root_mapping(&mut doc).insert(
+        serde_yaml::Value::String("mode".to_string()),
+        serde_yaml::Value::String(mode.as_str().to_string()),
+    );
+    storage::ensure_writable(path)?;
+    fs::write(path, serde_yaml::to_string(&doc)?)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    println!("updated source profile mode in {}", path.display());
+    Ok(())
+}
+
+/// Give an exposed external-controller a secret: reuse the one stored in app.yaml or generate
+/// (and store) a new one. Explicit secrets in the config are left alone.
+/// Returns whether a new secret was generated.
+fn ensure_controller_secret(
+    merged: &mut mihomo_core::ClashConfig,
+    app_cfg: &mut mihomo_core::storage::AppConfig,
+) -> bool {
+    let has_controller = merged
+        .extra
+        .get("external-controller")
+        .and_then(|value| value.as_str())
+        .is_some_and(|value| !value.trim().is_empty());
+    let has_secret = merged
+        .extra
+        .get("secret")
+        .and_then(|value| value.as_str())
+        .is_some_and(|value| !value.is_empty());
+    if !has_controller || has_secret {
+        return false;
+    }
+
+    let (secret, generated) = match app_cfg.controller_secret.clone() {
+        Some(secret) if !secret.is_empty() => (secret, false),
+        _ => {
+            let secret = uuid::Uuid::new_v4().simple().to_string();
+            app_cfg.controller_secret = Some(secret.clone());
+            (secret, true)
+        }
+    };
+    merged
+        .extra
+        .insert("secret".to_string(), serde_yaml::Value::String(secret));
+    generated
+}
+
+/// Move a freshly generated controller secret from app.yaml into the OS keyring, leaving a
+/// `keyring:` reference behind. Without keyring support, or when the keyring is unavailable
+/// (e.g. no Secret Service on a headless host), it stays in app.yaml, which only its owner can
+/// read.
+async fn store_generated_controller_secret(app_cfg: &mut mihomo_core::storage::AppConfig) {
+    let Some(secret) = app_cfg.controller_secret.clone() else {
+        return;
+    };
+    if !cfg!(feature = "keyring") {
+        info!("generated external-controller secret and stored it in app.yaml");
+        return;
+    }
+    match secrets::store_secret(secrets::CONTROLLER_SECRET_ENTRY, &secret).await {
+        Ok(()) => {
+            app_cfg.controller_secret =
+                Some(secrets::keyring_reference(secrets::CONTROLLER_SECRET_ENTRY));
+            info!("generated external-controller secret and stored it in the keyring");
+        }
+        Err(err) => warn!(
+            error = %format!("{:#}", err),
+            "keyring unavailable; stored the generated external-controller secret in app.yaml"
+        ),
+    }
+}
+
+fn parse_controller_endpoint(cfg: &mihomo_core::ClashConfig) -> Option<ControllerEndpoint> {
+    let http = cfg
+        .extra
+        .get("external-controller")
+        .and_then(|value| value.as_str())
+        .and_then(parse_host_port);
+
+    let unix_socket = cfg
+        .extra
+        .get("external-controller-unix")
+        .and_then(|value| value.as_str())
+        .map(ToOwned::to_owned);
+
+    let secret = cfg
+        .extra
+        .get("secret")
+        .and_then(|value| value.as_str())
+        .map(ToOwned::to_owned);
+
+    if http.is_none() && unix_socket.is_none() {
+        return None;
+    }
+
+    Some(ControllerEndpoint {
+        host: http.as_ref().map(|(host, _)| host.clone()),
+        port: http.map(|(_, port)| port),
+        secret,
+        unix_socket,
+    })
+}
+
+fn print_runtime_summary(summary: &RuntimeSummary) {
+    println!("  path: {}", summary.path.display());
+    println!(
+        "    mode={}, tun={}, auto-route={}, sniffer={}",
+        summary.mode.as_deref().unwrap_or("<unset>"),
+        format_bool_opt(summary.tun_enabled),
+        format_bool_opt(summary.tun_auto_route),
+        if summary.sniffer_enabled { "on" } else { "off" }
+    );
+    println!(
+        "    dns.enhanced-mode={}, fake-ip-range={}, rules={}",
+        summary.enhanced_mode.as_deref().unwrap_or("<unset>"),
+        summary.fake_ip_range.as_deref().unwrap_or("<unset>"),
+        summary.rules_count
+    );
+    println!(
+        "    route-excludes={}",
+        if summary.route_excludes.is_empty() {
+            "<none>".to_string()
+        } else {
+            summary.route_excludes.join(", ")
+        }
+    );
+    if let Some(controller) = summary.controller.as_ref() {
+        let http = match (&controller.host, controller.port) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            _ => "<unset>".to_string(),
+        };
+        println!(
+            "    controller=http:{} unix:{}",
+            http,
+            controller.unix_socket.as_deref().unwrap_or("<unset>")
+        );
+    }
+}
+
+fn runtime_summary_core_eq(left: &RuntimeSummary, right: &RuntimeSummary) -> bool {
+    left.mode == right.mode
+        && left.tun_enabled == right.tun_enabled
+        && left.tun_auto_route == right.tun_auto_route
+        && left.sniffer_enabled == right.sniffer_enabled
+        && left.enhanced_mode == right.enhanced_mode
+        && left.fake_ip_range == right.fake_ip_range
+        && left.route_excludes == right.route_excludes
+        && left.rules_count == right.rules_count
+}
+
+fn print_system_proxy_summary() {
+    println!("System proxy:");
+
+    if cfg!(target_os = "macos") {
+        print_macos_system_proxy_summary();
+        return;
+    }
+
+    if cfg!(target_os = "windows") {
+        print_windows_system_proxy_summary();
+        return;
+    }
+
+    println!("  status: unsupported on this platform");
+}
+
+fn print_macos_system_proxy_summary() {
+    match std::process::Command::new("scutil").arg("--proxy").output() {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let proxies = parse_scutil_proxy(&stdout);
+            if proxies.is_empty() {
+                println!("  status: scutil reports no enabled system proxies");
+            } else {
+                println!("  status: enabled");
+                for proxy in proxies {
+                    println!("  {} -> {}:{}", proxy.kind, proxy.host, proxy.port);
+                }
+            }
+        }
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            println!(
+                "  status: unavailable ({})",
+                trimmed_single_line(&stderr).unwrap_or_else(|| "scutil failed".to_string())
+            );
+        }
+        Err(err) => {
+            println!("  status: unavailable ({err})");
+        }
+    }
+}
+
+fn print_windows_system_proxy_summary() {
+    let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+    let enable_output = std::process::Command::new("reg")
+        .args(["query", key, "/v", "ProxyEnable"])
+        .output();
+    let server_output = std::process::Command::new("reg")
+        .args(["query", key, "/v", "ProxyServer"])
+        .output();
+
+    match (enable_output, server_output) {
+        (Ok(enable), Ok(server)) if enable.status.success() && server.status.success() => {
+            let enable_raw = String::from_utf8_lossy(&enable.stdout);
+            let server_raw = String::from_utf8_lossy(&server.stdout);
+            let proxies = parse_windows_proxy_query(&enable_raw, &server_raw);
+            if proxies.is_empty() {
+                println!("  status: reg query reports no enabled system proxies");
+            } else {
+                println!("  status: enabled");
+                for proxy in proxies {
+                    println!("  {} -> {}:{}", proxy.kind, proxy.host, proxy.port);
+                }
+            }
+        }
+        (Ok(enable), Ok(server)) => {
+            let stderr = format!(
+                "{} {}",
+                String::from_utf8_lossy(&enable.stderr),
+                String::from_utf8_lossy(&server.stderr)
+            );
+            println!(
+                "  status: unavailable ({})",
+                trimmed_single_line(&stderr).unwrap_or_else(|| "reg query failed".to_string())
+            );
+        }
+        (Err(err), _) | (_, Err(err)) => {
+            println!("  status: unavailable ({err})");
+        }
+    }
+}
+
+fn parse_windows_proxy_query(enable_raw: &str, server_raw: &str) -> Vec<ProxyEntry> {
+    let enabled = enable_raw
+        .lines()
+        .find(|line| line.contains("ProxyEnable"))
+        .map(|line| {
+            let normalized = line.trim().to_ascii_lowercase();
+            normalized.ends_with("0x1") || normalized.ends_with("0x00000001")
+        })
+        .unwrap_or(false);
+
+    if !enabled {
+        return Vec::new();
+    }
+
+    let Some(server_value) = server_raw
+        .lines()
+        .find(|line| line.contains("ProxyServer"))
+        .and_then(|line| line.split("REG_SZ").nth(1))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+    else {
+        return Vec::new();
+    };
+
+    let mut proxies = Vec::new();
+    if server_value.contains('=') {
+        for item in server_value.split(';').filter(|item| !item.is_empty()) {
+            let mut parts = item.splitn(2, '=');
+            let Some(kind) = parts.next().map(str::trim) else {
+                continue;
+            };
+            let Some(target) = parts.next().map(str::trim) else {
+                continue;
+            };
+            if let Some((host, port)) = split_host_port(target) {
+                let label = match kind.to_ascii_lowercase().as_str() {
+                    "http" => "HTTP",
+                    "https" => "HTTPS",
+                    "socks" | "socks5" => "SOCKS",
+                    _ => "WININET",
+                };
+                proxies.push(ProxyEntry {
+                    kind: label,
+                    host,
+                    port,
+                });
+            }
+        }
+    } else if let Some((host, port)) = split_host_port(server_value) {
+        proxies.push(ProxyEntry {
+            kind: "WININET",
+            host,
+            port,
+        });
+    }
+
+    proxies
+}
+
+fn split_host_port(raw: &str) -> Option<(String, String)> {
+    let value = raw.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    let (host, port) = value.rsplit_once(':')?;
+    Some((host.trim().to_string(), port.trim().to_string()))
+}
+
+fn parse_scutil_proxy(output: &str) -> Vec<ProxyEntry> {
+    let mut http_enable = false;
+    let mut https_enable = false;
+    let mut socks_enable = false;
+    let mut http_proxy = String::new();
+    let mut https_proxy = String::new();
+    let mut socks_proxy = String::new();
+    let mut http_port = String::new();
+    let mut https_port = String::new();
+    let mut socks_port = String::new();
+
+    for line in output.lines() {
+        let mut parts = line.splitn(2, ':');
+        let Some(key) = parts.next().map(str::trim) else {
+            continue;
+        };
+        let Some(value) = parts.next().map(str::trim) else {
+            continue;
+        };
+
+        match key {
+            "HTTPEnable" => http_enable = value == "1",
+            "HTTPProxy" => http_proxy = value.to_string(),
+            "HTTPPort" => http_port = value.to_string(),
+            "HTTPSEnable" => https_enable = value == "1",
+            "HTTPSProxy" => https_proxy = value.to_string(),
+            "HTTPSPort" => https_port = value.to_string(),
+            "SOCKSEnable" => socks_enable = value == "1",
+            "SOCKSProxy" => socks_proxy = value.to_string(),
+            "SOCKSPort" => socks_port = value.to_string(),
+            _ => {}
+        }
+    }
+
+    let mut proxies = Vec::new();
+    if http_enable && !http_proxy.is_empty() {
+        proxies.push(ProxyEntry {
+            kind: "HTTP",
+            host: http_proxy,
+            port: http_port,
+        });
+    }
+    if https_enable && !https_proxy.is_empty() {
+        proxies.push(ProxyEntry {
+            kind: "HTTPS",
+            host: https_proxy,
+            port: https_port,
+        });
+    }
+    if socks_enable && !socks_proxy.is_empty() {
+        proxies.push(ProxyEntry {
+            kind: "SOCKS",
+            host: socks_proxy,
+            port: socks_port,
+        });
+    }
+    proxies
+}
+
+fn print_tailscale_summary() {
+    println!("Tailscale:");
+
+    let tailscale_candidates = [
+        "/Applications/Tailscale.app/Contents/MacOS/Tailscale",
+        "tailscale",
+    ];
+
+    let mut last_error: Option<String> = None;
+    for candidate in tailscale_candidates {
+        let output = std::process::Command::new(candidate)
+            .args(["status", "--json"])
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                match serde_json::from_str::<serde_json::Value>(&stdout) {
+                    Ok(json) => {
+                        let backend_state = json
+                            .get("BackendState")
+                            .and_then(|value| value.as_str())
+                            .unwrap_or("<unknown>");
+                        let health_count = json
+                            .get("Health")
+                            .and_then(|value| value.as_array())
+                            .map(|items| items.len())
+                            .unwrap_or(0);
+                        println!(
+                            "  status: backend={}, health-warnings={}",
+                            backend_state, health_count
+                        );
+                        if let Some(health) = json.get("Health").and_then(|value| value.as_array())
+                        {
+                            for item in health.iter().take(3) {
+                                if let Some(message) = item.as_str() {
+                                    println!("  warning: {}", message);
+                                }
+                            }
+                        }
+                        return;
+                    }
+                    Err(err) => {
+                        last_error = Some(format!("invalid JSON from {}: {}", candidate, err));
+                    }
+                }
+            }
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                last_error = trimmed_single_line(&stderr)
+                    .or_else(|| trimmed_single_line(&stdout))
+                    .or_else(|| Some(format!("{} exited with {}", candidate, output.status)));
+            }
+            Err(err) => {
+                last_error = Some(format!("{} unavailable: {}", candidate, err));
+            }
+        }
+    }
+
+    println!(
+        "  status: unavailable ({})",
+        last_error.unwrap_or_else(|| "tailscale CLI probe failed".to_string())
+    );
+}
+
+fn print_controller_summary(controller: &ControllerEndpoint, args: &DoctorArgs) {
+    println!("Controller:");
+
+    match (&controller.host, controller.port) {
+        (Some(host), Some(port)) => println!("  configured-http: {}:{}", host, port),
+        _ => println!("  configured-http: <unset>"),
+    }
+    println!(
+        "  configured-unix: {}",
+        controller.unix_socket.as_deref().unwrap_or("<unset>")
+    );
+
+    if let Some(probe) = probe_controller_http(controller) {
+        println!("  live-api: http");
+        if let Some(version) = probe.version_line {
+            println!("  version: {}", version);
+        }
+        print_connection_summary(probe.connections_json.as_deref(), args);
+        return;
+    }
+
+    if let Some(probe) = probe_controller_unix(controller) {
+        println!("  live-api: unix-socket");
+        if let Some(version) = probe.version_line {
+            println!("  version: {}", version);
+        }
+        print_connection_summary(probe.connections_json.as_deref(), args);
+        return;
+    }
+
+    println!("  live-api: unavailable");
+    println!("  note: controller could not be reached over HTTP or unix socket");
+}
+
+struct ControllerProbe {
+    version_line: Option<String>,
+    connections_json: Option<String>,
+}
+
+fn probe_controller_http(controller: &ControllerEndpoint) -> Option<ControllerProbe> {
+    let host = controller.host.as_ref()?;
+    let port = controller.port?;
+    let base = format!("http://{}:{}", normalize_controller_host(host), port);
+    let version_text = curl_controller_get(controller, &(base.clone() + "/version"))?;
+    let connections_text = curl_controller_get(controller, &(base + "/connections"));
+
+    Some(ControllerProbe {
+        version_line: trimmed_single_line(&version_text),
+        connections_json: connections_text,
+    })
+}
+
+fn probe_controller_unix(controller: &ControllerEndpoint) -> Option<ControllerProbe> {
+    let socket = controller.unix_socket.as_ref()?;
+    if !Path::new(socket).exists() {
+        return None;
+    }
+
+    let version_text =
+        curl_controller_get_with_socket(controller, socket, "http://localhost/version")?;
+    if version_text.trim().is_empty() {
+        return None;
+    }
+
+    let connections_text =
+        curl_controller_get_with_socket(controller, socket, "http://localhost/connections");
+
+    Some(ControllerProbe {
+        version_line: trimmed_single_line(&version_text),
+        connections_json: connections_text,
+    })
+}
+
+fn curl_controller_get(controller: &ControllerEndpoint, url: &str) -> Option<String> {
+    let mut command = std::process::Command::new("curl");
+    command.args(["-s", "-f"]);
+    if let Some(secret) = controller.secret.as_ref() {
+        if !secret.is_empty() {
+            command.args(["-H", &format!("Authorization: Bearer {}", secret)]);
+        }
+    }
+    command.arg(url);
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout).to_string();
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn curl_controller_get_with_socket(
+    controller: &ControllerEndpoint,
+    socket: &str,
+    url: &str,
+) -> Option<String> {
+    let mut command = std::process::Command::new("curl");
+    command.args(["--unix-socket", socket, "-s", "-f"]);
+    if let Some(secret) = controller.secret.as_ref() {
+        if !secret.is_empty() {
+            command.args(["-H", &format!("Authorization: Bearer {}", secret)]);
+        }
+    }
+    command.arg(url);
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout).to_string();
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn print_connection_summary(connections_json: Option<&str>, args: &DoctorArgs) {
+    if !args.show_connections {
+        println!("  connections: skipped");
+        return;
+    }
+
+    let Some(raw) = connections_json else {
+        println!("  connections: unavailable");
+        return;
+    };
+
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(raw) else {
+        println!("  connections: unavailable (invalid JSON)");
+        return;
+    };
+
+    let records = extract_connection_records(&json);
+    if records.is_empty() {
+        println!("  connections: no active connections reported");
+        return;
+    }
+
+    println!("  active-connections: {}", records.len());
+
+    let focus_domains = if args.focus_domains.is_empty() {
+        vec![
+            "chat.openai.com".to_string(),
+            "chatgpt.com".to_string(),
+            "ab.chatgpt.com".to_string(),
+            "api.anthropic.com".to_string(),
+        ]
+    } else {
+        args.focus_domains.clone()
+    };
+
+    let focused: Vec<&ConnectionRecord> = records
+        .iter()
+        .filter(|record| connection_matches_focus(record, &focus_domains))
+        .collect();
+
+    if focused.is_empty() {
+        println!("  focus-matches: none");
+        return;
+    }
+
+    let path_hint = infer_connection_path(&focused);
+    println!("  focus-matches: {}", focused.len());
+    println!("  likely-path: {}", path_hint);
+    for record in focused.iter().take(6) {
+        println!(
+            "  connection: host={} inbound={} source={} chains={}",
+            record.host.as_deref().unwrap_or("<unknown>"),
+            record.inbound_name.as_deref().unwrap_or("<unknown>"),
+            record.source_ip.as_deref().unwrap_or("<unknown>"),
+            if record.chains.is_empty() {
+                "<none>".to_string()
+            } else {
+                record.chains.join(">")
+            }
+        );
+    }
+}
+
+fn extract_connection_records(json: &serde_json::Value) -> Vec<ConnectionRecord> {
+    let Some(items) = json
+        .get("connections")
+        .and_then(|value| value.as_array())
+        .or_else(|| json.as_array())
+    else {
+        return Vec::new();
+    };
+
+    items.iter().map(connection_record_from_json).collect()
+}
+
+fn connection_record_from_json(value: &serde_json::Value) -> ConnectionRecord {
+    let host = json_string_path(value, &["metadata", "host"])
+        .or_else(|| json_string_path(value, &["metadata", "destinationIP"]))
+        .or_else(|| json_string_path(value, &["host"]));
+    let inbound_name = json_string_path(value, &["metadata", "inboundName"])
+        .or_else(|| json_string_path(value, &["inboundName"]));
+    let source_ip = json_string_path(value, &["metadata", "sourceIP"])
+        .or_else(|| json_string_path(value, &["sourceIP"]));
+    let chains = json_string_list_path(value, &["chains"])
+        .or_else(|| json_string_list_path(value, &["metadata", "chains"]))
+        .unwrap_or_default();
+
+    ConnectionRecord {
+        host,
+        inbound_name,
+        source_ip,
+        chains,
+    }
+}
+
+fn connection_matches_focus(record: &ConnectionRecord, focus_domains: &[String]) -> bool {
+    let Some(host) = record.host.as_ref() else {
+        return false;
+    };
+    focus_domains.iter().any(|focus| host.contains(focus))
+}
+
+fn infer_connection_path(records: &[&ConnectionRecord]) -> &'static str {
+    if records.iter().any(|record| {
+        record.source_ip.as_deref() == Some("127.0.0.1")
+            || record
+                .inbound_name
+                .as_deref()
+                .map(|name| name.contains("MIXED"))
+                == Some(true)
+    }) {
+        "local proxy / DEFAULT-MIXED"
+    } else if records.iter().any(|record| {
+        record
+            .inbound_name
+            .as_deref()
+            .map(|name| name.to_ascii_uppercase().contains("TUN"))
+            == Some(true)
+    }) {
+        "transparent TUN"
+    } else {
+        "unknown"
+    }
+}
+
+fn json_string_path(value: &serde_json::Value, path: &[&str]) -> Option<String> {
+    let mut current = value;
+    for key in path {
+        current = current.get(*key)?;
+    }
+    current.as_str().map(ToOwned::to_owned)
+}
+
+fn json_string_list_path(value: &serde_json::Value, path: &[&str]) -> Option<Vec<String>> {
+    let mut current = value;
+    for key in path {
+        current = current.get(*key)?;
+    }
+    Some(
+        current
+            .as_array()?
+            .iter()
+            .filter_map(|value| value.as_str().map(ToOwned::to_owned))
+            .collect(),
+    )
+}
+
+fn string_from_mapping(map: &serde_yaml::Mapping, key: &str) -> Option<String> {
+    map.get(Value::String(key.to_string()))
+        .and_then(|value| value.as_str())
+        .map(ToOwned::to_owned)
+}
+
+fn bool_from_mapping(map: &serde_yaml::Mapping, key: &str) -> Option<bool> {
+    map.get(Value::String(key.to_string()))
+        .and_then(|value| value.as_bool())
+}
+
+fn string_list_from_mapping(map: &serde_yaml::Mapping, key: &str) -> Vec<String> {
+    map.get(Value::String(key.to_string()))
+        .and_then(|value| value.as_sequence())
+        .map(|seq| {
+            seq.iter()
+                .filter_map(|value| value.as_str().map(ToOwned::to_owned))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn format_bool_opt(value: Option<bool>) -> &'static str {
+    match value {
+        Some(true) => "on",
+        Some(false) => "off",
+        None => "<unset>",
+    }
+}
+
+fn trimmed_single_line(raw: &str) -> Option<String> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.lines().next().unwrap_or(trimmed).trim().to_string())
+    }
+}
+
+/// Keeps the OTLP exporter alive until [`TracingGuard::shutdown`] flushes it.
+#[derive(Default)]
+struct TracingGuard {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl TracingGuard {
+    async fn shutdown(self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.provider {
+            // The batch exporter blocks on its runtime task while flushing.
+            let _ = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        }
+    }
+}
+
+fn init_tracing(format: LogFormat, otlp_endpoint: Option<&str>) -> anyhow::Result<TracingGuard> {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let structured_filter =
+        || EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    match format {
+        LogFormat::Text => {
+            let _ = tracing_subscriber::fmt()
+                .with_env_filter(EnvFilter::from_default_env())
+                .try_init();
+            Ok(TracingGuard::default())
+        }
+        LogFormat::Json => {
+            let _ = tracing_subscriber::fmt()
+                .json()
+                .with_env_filter(structured_filter())
+                .with_writer(std::io::stderr)
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_span_events(FmtSpan::CLOSE)
+                .try_init();
+            Ok(TracingGuard::default())
+        }
+        #[cfg(feature = "otlp")]
+        LogFormat::Otlp => {
+            use opentelemetry::trace::TracerProvider as _;
+            use opentelemetry_otlp::WithExportConfig;
+            use tracing_subscriber::layer::SubscriberExt;
+            use tracing_subscriber::util::SubscriberInitExt;
+
+            let mut exporter = opentelemetry_otlp::SpanExporter::builder().with_http();
+            if let Some(endpoint) = otlp_endpoint {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            let exporter = exporter
+                .build()
+                .context("failed to set up the OTLP exporter")?;
+            let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+                .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+                .with_resource(opentelemetry_sdk::Resource::new([
+                    opentelemetry::KeyValue::new("service.name", "mihomo-cli"),
+                    opentelemetry::KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+                ]))
+                .build();
+            let tracer = provider.tracer("mihomo-cli");
+            let _ = tracing_subscriber::registry()
+                .with(structured_filter())
+                .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+                .with(tracing_opentelemetry::layer().with_tracer(tracer))
+                .try_init();
+            Ok(TracingGuard {
+                provider: Some(provider),
+            })
+        }
+        #[cfg(not(feature = "otlp"))]
+        LogFormat::Otlp => {
+            let _ = otlp_endpoint;
+            Err(anyhow!(
+                "this build has no OTLP support; rebuild with `cargo build --features otlp`"
+            ))
+        }
+    }
+}
+
+/// `GROUP=URL` for --group-health-check-url and --group-icon.
+fn parse_group_url(raw: &str) -> Result<(String, String), String> {
+    let (group, url) = raw
+        .split_once('=')
+        .ok_or_else(|| format!("expected GROUP=URL, got '{}'", raw))?;
+    let (group, url) = (group.trim(), url.trim());
+    if group.is_empty() || url.is_empty() {
+        return Err(format!("expected GROUP=URL, got '{}'", raw));
+    }
+    Ok((group.to_string(), url.to_string()))
+}
+
+/// `merge --watch`: re-run the merge every `--interval` until SIGINT/SIGTERM. Runs that fail
+/// are logged and retried at the next tick instead of stopping the loop; a run in progress when
+/// the signal arrives may finish within `--drain-timeout`.
+async fn run_merge_watch(mut args: MergeArgs) -> anyhow::Result<()> {
+    args.if_changed = true;
+    mihomo_core::subscription::set_refresh_window(args.interval);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let merge = run_merge_outputs(args.clone());
+        tokio::pin!(merge);
+        let result = tokio::select! {
+            result = &mut merge => result,
+            signal = &mut shutdown => {
+                info!(signal, "shutting down");
+                drain_merge(merge, args.drain_timeout).await?;
+                info!("watch stopped");
+                return Ok(());
+            }
+        };
+        if let Err(err) = result {
+            warn!(error = %format!("{:#}", err), "merge failed; retrying at the next interval");
+        }
+        let wait = next_watch_wait(&args).await;
+        info!(wait_secs = wait.as_secs(), "waiting for the next refresh");
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            signal = &mut shutdown => {
+                info!(signal, "watch stopped");
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Resolves with the signal's name on the first SIGINT or SIGTERM (Ctrl-C elsewhere). The
+/// handlers are installed when this is called, so a signal during a merge is not lost.
+fn shutdown_signal() -> impl std::future::Future<Output = &'static str> {
+    #[cfg(unix)]
+    let signals = {
+        use tokio::signal::unix::{signal, SignalKind};
+        (
+            signal(SignalKind::interrupt()),
+            signal(SignalKind::terminate()),
+        )
+    };
+    async move {
+        #[cfg(unix)]
+        if let (Ok(mut interrupt), Ok(mut terminate)) = signals {
+            return tokio::select! {
+                _ = interrupt.recv() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            };
+        }
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl-C"
+    }
+}
+
+/// Let a merge that was running when the shutdown signal came finish, so its caches, usage
+/// history and app.yaml are written; fails when it takes longer than `timeout`.
+async fn drain_merge(
+    merge: impl std::future::Future<Output = anyhow::Result<()>>,
+    timeout: std::time::Duration,
+) -> anyhow::Result<()> {
+    info!(
+        drain_secs = timeout.as_secs(),
+        "waiting for the running merge to finish"
+    );
+    match tokio::time::timeout(timeout, merge).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(err)) => {
+            warn!(error = %format!("{:#}", err), "merge failed");
+            Ok(())
+        }
+        Err(_) => Err(anyhow!(
+            "the running merge did not finish within the {}s drain timeout",
+            timeout.as_secs()
+        )),
+    }
+}
+
+/// `serve`: answer HTTP requests for the merged config until Ctrl-C, re-merging every
+/// `--interval` in the background when given.
+async fn run_serve(args: ServeArgs) -> anyhow::Result<()> {
+    let token = secrets::resolve_secret(&args.token).await?;
+    if token.trim().is_empty() {
+        return Err(anyhow!("--token must not be empty"));
+    }
+    let paths = AppPaths::new()?;
+    let config_path = args
+        .config
+        .clone()
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    let options = Arc::new(mihomo_core::serve::ServeOptions::new(
+        token,
+        config_path.clone(),
+        args.interval,
+    ));
+    options.set_usage(combined_usage(&paths).await);
+
+    let merge_args = match args.interval {
+        Some(interval) => {
+            if storage::is_read_only() {
+                return Err(anyhow!(
+                    "read-only mode: serve cannot re-merge; drop --interval"
+                ));
+            }
+            mihomo_core::subscription::set_refresh_window(interval);
+            Some(serve_merge_args(&args, &config_path, &paths).await?)
+        }
+        None => None,
+    };
+    let shutdown = shutdown_signal();
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let until_stopped = |mut stopped: tokio::sync::watch::Receiver<bool>| async move {
+        let _ = stopped.wait_for(|stop| *stop).await;
+    };
+    let server = mihomo_core::serve::serve(
+        args.listen,
+        options.clone(),
+        until_stopped(stopped.clone()),
+        args.drain_timeout,
+    );
+    // Merges are not Send, so they run on this task next to the server instead of spawned.
+    let refresh = async {
+        let Some(merge_args) = merge_args else {
+            until_stopped(stopped.clone()).await;
+            return Ok(());
+        };
+        loop {
+            let merge = run_merge_outputs(merge_args.clone());
+            tokio::pin!(merge);
+            tokio::select! {
+                result = &mut merge => {
+                    if let Err(err) = result {
+                        warn!(error = %format!("{:#}", err), "merge failed; retrying at the next interval");
+                    }
+                }
+                _ = until_stopped(stopped.clone()) => {
+                    return drain_merge(merge, args.drain_timeout).await;
+                }
+            }
+            options.set_usage(combined_usage(&paths).await);
+            let wait = next_watch_wait(&merge_args).await;
+            info!(wait_secs = wait.as_secs(), "waiting for the next refresh");
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = until_stopped(stopped.clone()) => return Ok(()),
+            }
+        }
+    };
+    tokio::pin!(server, refresh);
+    tokio::select! {
+        // Neither ends before the stop signal unless the server cannot listen.
+        result = &mut server => return result,
+        result = &mut refresh => return result,
+        signal = shutdown => info!(signal, "shutting down"),
+    }
+    let _ = stop.send(true);
+    let (served, refreshed) = tokio::join!(server, refresh);
+    info!("serve stopped");
+    served.and(refreshed)
+}
+
+/// Merge flags for `serve --interval`: the preset's, writing to the served file and only when
+/// the result changed.
+async fn serve_merge_args(
+    args: &ServeArgs,
+    config_path: &Path,
+    paths: &AppPaths,
+) -> anyhow::Result<MergeArgs> {
+    let mut base = vec!["mihomo-cli".to_string(), "merge".to_string()];
+    if let Some(preset) = &args.preset {
+        base.extend(["--preset".to_string(), preset.clone()]);
+    }
+    let Commands::Merge(base) = Cli::try_parse_from(&base)?.command else {
+        unreachable!("merge argv always starts with the merge subcommand");
+    };
+    let (prefix, disable_dev_rules) = merge_bundle_prefix(&base, paths).await?;
+    let suffix = [
+        "--output".to_string(),
+        config_path.display().to_string(),
+        "--if-changed".to_string(),
+    ];
+    let cli = Cli::try_parse_from(merge_argv(&prefix, &suffix))
+        .with_context(|| format!("invalid merge flags: {}", prefix.join(" ")))?;
+    let Commands::Merge(mut merge_args) = cli.command else {
+        unreachable!("merge argv always starts with the merge subcommand");
+    };
+    if disable_dev_rules {
+        merge_args.dev_rules = false;
+    }
+    // `--interval` belongs to --watch; the serve loop waits on its own interval instead.
+    merge_args.interval = args.interval.unwrap_or(merge_args.interval);
+    Ok(*merge_args)
+}
+
+/// Quota and expiry of all enabled subscriptions together, for `subscription-userinfo`.
+async fn combined_usage(paths: &AppPaths) -> Option<SubscriptionUsage> {
+    let list = storage::load_subscription_list(paths).await.ok()?;
+    let mut usages = Vec::new();
+    for subscription in list.enabled() {
+        if let Ok(Some(usage)) = subscription.usage(paths).await {
+            usages.push(usage);
+        }
+    }
+    SubscriptionUsage::sum(usages)
+}
+
+/// Time until the next stored subscription is due (provider `update_interval` or
+/// `--interval`), at most `--interval` and at least a minute, or until the next custom rule
+/// `active_hours` boundary when that comes first.
+async fn next_watch_wait(args: &MergeArgs) -> std::time::Duration {
+    let refresh = next_refresh_wait(args).await;
+    let Ok(paths) = AppPaths::new() else {
+        return refresh;
+    };
+    let Ok(app_cfg) = storage::load_app_config(&paths).await else {
+        return refresh;
+    };
+    let now = chrono::Local::now();
+    let windows: Vec<TimeWindow> = app_cfg
+        .custom_rules
+        .iter()
+        .flat_map(|rule| rule.active_hours.iter().copied())
+        .collect();
+    match schedule::next_boundary(&windows, &now) {
+        // A second late so the re-merge already sees the new window.
+        Some(boundary) => (boundary - now + chrono::Duration::seconds(1))
+            .to_std()
+            .unwrap_or_default()
+            .min(refresh),
+        None => refresh,
+    }
+}
+
+async fn next_refresh_wait(args: &MergeArgs) -> std::time::Duration {
+    let min_wait = std::time::Duration::from_secs(60).min(args.interval);
+    let list = match args.subscriptions_file.as_ref() {
+        Some(path) => storage::load_subscription_list_from(path).await,
+        None => match AppPaths::new() {
+            Ok(paths) => storage::load_subscription_list(&paths).await,
+            Err(err) => Err(err),
+        },
+    };
+    let Ok(list) = list else {
+        return args.interval;
+    };
+    let now = chrono::Utc::now();
+    list.enabled()
+        .filter(|sub| sub.url.is_some())
+        .map(|sub| match sub.next_refresh(args.interval) {
+            Some(next) => (next - now).to_std().unwrap_or_default(),
+            None => std::time::Duration::ZERO,
+        })
+        .min()
+        .unwrap_or(args.interval)
+        .clamp(min_wait, args.interval)
+}
+
+/// Parse `--interval` values such as `90s`, `30m`, `6h` or `1d`; bare numbers are seconds.
+fn parse_interval(raw: &str) -> Result<std::time::Duration, String> {
+    let raw = raw.trim();
+    let (number, unit) = match raw.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => raw.split_at(idx),
+        None => (raw, "s"),
+    };
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid interval '{}'", raw))?;
+    let multiplier = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        other => {
+            return Err(format!(
+                "unknown interval unit '{}' (use s, m, h or d)",
+                other
+            ))
+        }
+    };
+    if value == 0 {
+        return Err("interval must be greater than zero".to_string());
+    }
+    let secs = value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("interval '{}' is too large", raw))?;
+    Ok(std::time::Duration::from_secs(secs))
+}
+
+#[tracing::instrument(
+    name = "merge",
+    skip_all,
+    fields(
+        subscriptions = tracing::field::Empty,
+        proxies = tracing::field::Empty,
+        groups = tracing::field::Empty,
+        rules = tracing::field::Empty,
+        warnings = tracing::field::Empty,
+    )
+)]
+async fn run_merge(args: MergeArgs) -> anyhow::Result<()> {
+    if storage::is_read_only() && !args.stdout {
+        return Err(anyhow!(
+            "read-only mode: merge can only print the config; pass --stdout"
+        ));
+    }
+    if args.redact_secrets && !args.stdout && !args.diff {
+        return Err(anyhow!(
+            "--redact-secrets only applies to --stdout and --diff"
+        ));
+    }
+    // Summaries printed for a shared --stdout/--diff run must not leak subscription tokens.
+    let shown = |text: String| {
+        if args.redact_secrets {
+            redact_urls(&text)
+        } else {
+            text
+        }
+    };
+    let paths = AppPaths::new()?;
+    paths.ensure_runtime_dirs().await?;
+    let mut app_cfg = storage::load_app_config(&paths).await?;
+    let original_app_cfg = app_cfg.clone();
+    let previous_managed_tailscale = app_cfg.managed_tailscale_compat.clone();
+    let devices: Vec<(String, storage::DeviceProfile)> = args
+        .devices
+        .iter()
+        .map(|name| match app_cfg.devices.get(name) {
+            Some(device) => Ok((name.clone(), device.clone())),
+            None => {
+                let known: Vec<&str> = app_cfg.devices.keys().map(String::as_str).collect();
+                Err(anyhow!(
+                    "unknown device '{}' (defined in app.yaml: {})",
+                    name,
+                    if known.is_empty() {
+                        "none".to_string()
+                    } else {
+                        known.join(", ")
+                    }
+                ))
+            }
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let proxy_filter = ProxyFilterSpec {
+        include: args.include_proxy.clone(),
+        exclude: args.exclude_proxy.clone(),
+    }
+    .compile()?;
+
+    // Mimic clash-verge UA so some providers return Clash YAML (with rules)
+    let ua = args
+        .subscription_ua
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SUBSCRIPTION_UA.to_string());
+    let client = build_fetch_client(&ua, args.fetch_proxy.as_deref(), args.max_redirects, None)?;
+
+    // Configure core parser behavior (align with UA behavior):
+    // by default, do NOT attempt base64 decoding; allow only if explicitly requested.
+    mihomo_core::subscription::set_parse_options(mihomo_core::subscription::ParseOptions {
+        allow_base64: args.subscription_allow_base64,
+    });
+    let default_limits = FetchLimits::default();
+    mihomo_core::subscription::set_fetch_limits(FetchLimits {
+        max_payload_bytes: args
+            .max_payload_bytes
+            .unwrap_or(default_limits.max_payload_bytes),
+        max_proxies: args.max_proxies.unwrap_or(default_limits.max_proxies),
+    });
+
+    let resources_mode = if args.skip_resources || storage::is_read_only() {
+        ResourcesMode::Never
+    } else {
+        args.resources_mode
+            .map(ResourcesMode::from)
+            .unwrap_or(app_cfg.resources.mode)
+    };
+    if resources_mode == ResourcesMode::Always {
+        ensure_mihomo_resources(&client, &paths, &args.resource_mirrors, |_| true).await?;
+    }
+
+    ensure_default_template(&paths, args.force_template_update).await?;
+
+    let template_path = match (args.template.as_ref(), app_cfg.template.as_deref()) {
+        (Some(provided), _) => resolve_template_path(&paths, provided),
+        (None, Some(name)) => paths.named_template_path(name),
+        (None, None) => paths.default_template_path(),
+    };
+
+    let mut warnings = Warnings::default();
+
+    let template = Template::load(&template_path)
+        .await
+        .with_context(|| format!("failed to load template from {}", template_path.display()))?;
+    note_lint_findings(&template, &template_path, &mut warnings);
+    let mut template = template.into_config();
+    let template_rename: RenameConfig = match template.extra.shift_remove("rename") {
+        Some(value) => serde_yaml::from_value(value)
+            .with_context(|| format!("invalid rename section in {}", template_path.display()))?,
+        None => RenameConfig::default(),
+    };
+    let template_region_groups: Option<RegionGroupsSpec> =
+        match template.extra.shift_remove("auto-region-groups") {
+            Some(Value::Null) => Some(RegionGroupsSpec::default()),
+            Some(value) => Some(serde_yaml::from_value(value).with_context(|| {
+                format!(
+                    "invalid auto-region-groups section in {}",
+                    template_path.display()
+                )
+            })?),
+            None => None,
+        };
+
+    let base_config_path = args
+        .base_config
+        .as_ref()
+        .map(|p| resolve_base_path(&paths, p))
+        .or_else(|| default_base_config_path(&paths));
+
+    let base_config = if let Some(path) = base_config_path {
+        let base = Template::load(&path)
+            .await
+            .with_context(|| format!("failed to load base config from {}", path.display()))?;
+        note_lint_findings(&base, &path, &mut warnings);
+        Some(base.into_config())
+    } else {
+        None
+    };
+
+    let mut subscription_list = if let Some(path) = args.subscriptions_file.as_ref() {
+        storage::load_subscription_list_from(path).await?
+    } else {
+        storage::load_subscription_list(&paths).await?
+    };
+
+    for key in &args.only_subscriptions {
+        let known = subscription_list
+            .items
+            .iter()
+            .any(|sub| &sub.id == key || &sub.name == key);
+        if !known {
+            return Err(anyhow!(
+                "unknown subscription '{}' in --only-subscription",
+                key
+            ));
+        }
+    }
+
+    check_resource_freshness(&paths, &mut warnings).await;
+
+    let mut configs = Vec::new();
+    let mut source_names: Vec<String> = Vec::new();
+    let mut source_rows: Vec<SourceRow> = Vec::new();
+    let mut used_url: Option<String> = None;
+    let mut merge_patches: Vec<(String, serde_yaml::Mapping)> = Vec::new();
+    let mut scripts: Vec<(String, String)> = Vec::new();
+
+    for subscription in subscription_list.items.iter_mut() {
+        if !args.only_subscriptions.is_empty()
+            && !args
+                .only_subscriptions
+                .iter()
+                .any(|key| key == &subscription.id || key == &subscription.name)
+        {
+            continue;
+        }
+        let (sub_client, cookies) = subscription_client(subscription, &ua, &args, &paths).await?;
+        if matches!(subscription.kind, SubscriptionKind::Merge) {
+            let loaded = subscription.load_merge_patch(&sub_client, &paths).await;
+            save_subscription_cookies(subscription, &paths, &cookies).await;
+            match loaded {
+                Ok(Some(patch)) => merge_patches.push((subscription.name.clone(), patch)),
+                Ok(None) => {}
+                Err(err) => warnings.push(
+                    WarningKind::Subscription,
+                    format!("failed to load merge patch {}: {:#}", subscription.id, err),
+                ),
+            }
+            continue;
+        }
+        if matches!(subscription.kind, SubscriptionKind::Script) {
+            let loaded = subscription.load_script(&sub_client, &paths).await;
+            save_subscription_cookies(subscription, &paths, &cookies).await;
+            match loaded {
+                Ok(Some(source)) => scripts.push((subscription.name.clone(), source)),
+                Ok(None) => {}
+                Err(err) => warnings.push(
+                    WarningKind::Subscription,
+                    format!("failed to load script {}: {:#}", subscription.id, err),
+                ),
+            }
+            continue;
+        }
+        let loaded = if args.watch {
+            subscription
+                .load_config_if_due(&sub_client, &paths, args.interval)
+                .await
+        } else {
+            subscription.load_config(&sub_client, &paths).await
+        };
+        save_subscription_cookies(subscription, &paths, &cookies).await;
+        match loaded {
+            Ok(Some(config)) => {
+                note_cache_fallback(subscription, &mut warnings);
+                let usage = note_subscription_usage(subscription, &paths, &mut warnings).await;
+                let cache_modified = subscription.cache_modified(&paths).await;
+                let changes = note_node_changes(subscription, &config, &paths, &mut warnings).await;
+                source_rows.push(SourceRow::loaded(
+                    subscription,
+                    &config,
+                    usage,
+                    cache_modified,
+                    changes,
+                ));
+                configs.push(config);
+                source_names.push(subscription.name.clone());
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warnings.push(
+                    WarningKind::Subscription,
+                    format!("failed to load subscription {}: {:#}", subscription.id, err),
+                );
+                source_rows.push(SourceRow::failed(subscription));
+            }
+        }
+        if let Some(url) = subscription.url.clone() {
+            used_url = Some(url);
+        }
+    }
+
+    for (idx, source) in args.subscriptions.iter().enumerate() {
+        let mut subscription = subscription_from_input(idx, source);
+        let (sub_client, cookies) = subscription_client(&subscription, &ua, &args, &paths).await?;
+        let loaded = subscription.load_config(&sub_client, &paths).await;
+        save_subscription_cookies(&subscription, &paths, &cookies).await;
+        match loaded {
+            Ok(Some(config)) => {
+                note_cache_fallback(&subscription, &mut warnings);
+                let usage = note_subscription_usage(&subscription, &paths, &mut warnings).await;
+                let cache_modified = subscription.cache_modified(&paths).await;
+                let changes =
+                    note_node_changes(&subscription, &config, &paths, &mut warnings).await;
+                source_rows.push(SourceRow::loaded(
+                    &subscription,
+                    &config,
+                    usage,
+                    cache_modified,
+                    changes,
+                ));
+                configs.push(config);
+                source_names.push(subscription.name.clone());
+            }
+            Ok(None) => {}
+            Err(err) => {
+                warnings.push(
+                    WarningKind::Subscription,
+                    format!("failed to load ad-hoc subscription {}: {:#}", source, err),
+                );
+                source_rows.push(SourceRow::failed(&subscription));
+            }
+        }
+        if let Some(url) = subscription.url.clone() {
+            used_url = Some(url);
+        }
+    }
+
+    let failure_threshold = args
+        .disable_after_failures
+        .unwrap_or(app_cfg.disable_after_failures);
+    for id in subscription_list.disable_failing(failure_threshold) {
+        warn!(
+            id = %id,
+            threshold = failure_threshold,
+            "subscription disabled after repeated fetch failures; re-enable with `manage sub enable`"
+        );
+    }
+
+    // If requested and no explicit sources, reuse cached last subscription URL
+    if configs.is_empty() && args.subscriptions.is_empty() && subscription_list.items.is_empty() {
+        if args.use_last {
+            if let Some(last_url) = app_cfg.last_subscription_url.clone() {
+                tracing::info!(last_url = %last_url, "using cached last subscription URL");
+                let mut subscription = subscription_from_input(0, &last_url);
+                let (sub_client, cookies) =
+                    subscription_client(&subscription, &ua, &args, &paths).await?;
+                let loaded = subscription.load_config(&sub_client, &paths).await;
+                save_subscription_cookies(&subscription, &paths, &cookies).await;
+                match loaded {
+                    Ok(Some(config)) => {
+                        configs.push(config);
+                        source_names.push(subscription.name.clone());
+                        used_url = Some(last_url);
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        return Err(anyhow!(
+                            "failed to load cached subscription {}: {}",
+                            last_url,
+                            err
+                        ));
+                    }
+                }
+            } else {
+                return Err(anyhow!(
+                    "--use-last set but no cached last subscription URL found. Merge once with -s/--subscription first."
+                ));
+            }
+        } else {
+            return Err(anyhow!(
+                "no subscription provided. Pass -s/--subscription or use --use-last to reuse the cached last URL."
+            ));
+        }
+    }
+
+    if args.proxies_only {
+        for config in configs.iter_mut() {
+            config.proxy_groups.clear();
+            config.rules.clear();
+            config.extra.clear();
+        }
+    }
+
+    if !proxy_filter.is_empty() {
+        let removed: usize = configs
+            .iter_mut()
+            .map(|config| proxy_filter.apply(config).len())
+            .sum();
+        info!(removed = removed, "filtered subscription proxies");
+    }
+
+    // Reachability seen in this run, by proxy name; recorded in the liveness cache.
+    let mut observed: BTreeMap<String, bool> = BTreeMap::new();
+    if let Some(action) = args.probe {
+        let options = ProbeOptions {
+            timeout: std::time::Duration::from_millis(args.probe_timeout_ms),
+            concurrency: args.probe_concurrency,
+        };
+        let mut unreachable = Vec::new();
+        for config in configs.iter_mut() {
+            observed.extend(probed_proxies(config).into_iter().map(|name| (name, true)));
+            unreachable.extend(probe_proxies(config, options, action.into()).await);
+        }
+        observed.extend(unreachable.iter().map(|name| (name.clone(), false)));
+        info!(
+            unreachable = unreachable.len(),
+            "probed subscription proxies"
+        );
+        if !unreachable.is_empty() {
+            let verb = match action {
+                ProbeArg::Drop => "dropped",
+                ProbeArg::Annotate => "tagged",
+            };
+            warnings.push(
+                WarningKind::DroppedNode,
+                format!(
+                    "{} unreachable proxies {} by --probe: {}",
+                    unreachable.len(),
+                    verb,
+                    unreachable.join(", ")
+                ),
+            );
+        }
+    }
+
+    let liveness_path = paths.node_liveness_file();
+    let mut liveness = if args.probe.is_some() || args.exclude_dead_after.is_some() {
+        NodeLiveness::load(&liveness_path).await
+    } else {
+        NodeLiveness::default()
+    };
+    if !observed.is_empty() {
+        liveness.record(&observed, chrono::Utc::now());
+        save_node_liveness(&liveness, &liveness_path).await;
+    }
+    if let Some(threshold) = args.exclude_dead_after {
+        let excluded: Vec<String> = configs
+            .iter_mut()
+            .flat_map(|config| exclude_dead(config, &liveness, threshold))
+            .collect();
+        if !excluded.is_empty() {
+            warnings.push(
+                WarningKind::DroppedNode,
+                format!(
+                    "{} proxies unreachable in the last {} runs excluded by --exclude-dead-after: {}",
+                    excluded.len(),
+                    threshold,
+                    excluded.join(", ")
+                ),
+            );
+        }
+    }
+
+    let group_order = match args.group_order {
+        _ if !args.group_order_list.is_empty() => {
+            GroupOrder::Explicit(args.group_order_list.clone())
+        }
+        Some(GroupOrderArg::Template) => GroupOrder::Template,
+        Some(GroupOrderArg::Name) => GroupOrder::Name,
+        None if !app_cfg.group_order.is_empty() => {
+            GroupOrder::Explicit(app_cfg.group_order.clone())
+        }
+        None => GroupOrder::Template,
+    };
+    let health_checks = app_cfg
+        .health_check_urls
+        .clone()
+        .overridden_by(&HealthCheckUrls {
+            default: args.health_check_url.clone(),
+            groups: args.group_health_check_urls.iter().cloned().collect(),
+        });
+    let merge_options = MergeOptions {
+        group_order,
+        dedup: args.dedup_proxies.into(),
+        health_checks: health_checks.clone(),
+    };
+
+    let mut merged = merge_configs_with_warnings(template, configs, &merge_options, &mut warnings);
+
+    // Inject manually-managed proxies (e.g. a private trojan server) before applying base-config,
+    // so that base-config group rebuild sees all proxy names.
+    if !app_cfg.manual_servers.is_empty() {
+        let added = inject_manual_servers(&mut merged, &app_cfg).await?;
+        if added > 0 {
+            info!(added = added, "injected manual server proxies");
+        }
+    }
+
+    let rename = template_rename.then(&app_cfg.rename);
+    if !rename.is_empty() {
+        let changes = rename_proxies(&mut merged, &rename)?;
+        if !changes.is_empty() {
+            info!(renamed = changes.len(), "renamed proxies");
+        }
+    }
+
+    if let Some(base) = base_config.as_ref() {
+        let sections: Vec<BaseSection> = if !args.base_sections.is_empty() {
+            args.base_sections.iter().copied().map(Into::into).collect()
+        } else if !app_cfg.base_sections.is_empty() {
+            app_cfg.base_sections.clone()
+        } else {
+            BaseSection::DEFAULT.to_vec()
+        };
+        merged = apply_base_config_sections(merged, base, &sections);
+    }
+
+    if let Some(kind) = args.provider_groups {
+        let kind = match kind {
+            ProviderGroupsArg::Select => ProviderGroupKind::Select,
+            ProviderGroupsArg::UrlTest => ProviderGroupKind::UrlTest,
+        };
+        let main_selector =
+            resolve_dev_rules_via(&args.dev_rules_via, DEFAULT_DEV_RULE_VIA, &merged);
+        let added = add_provider_groups(
+            &mut merged,
+            &source_rows
+                .iter()
+                .map(|row| ProviderNodes {
+                    name: row.name.clone(),
+                    proxies: row.proxies.clone(),
+                })
+                .collect::<Vec<_>>(),
+            kind,
+            &main_selector,
+            &mut warnings,
+        );
+        info!(added = added, selector = %main_selector, "generated per-provider groups");
+    }
+
+    let region_spec = match template_region_groups {
+        Some(spec) => Some(spec),
+        None if args.auto_region_groups => Some(RegionGroupsSpec::default()),
+        None => None,
+    }
+    .map(|mut spec| {
+        if !args.region_groups.is_empty() {
+            spec.regions = args.region_groups.clone();
+        }
+        if let Some(source) = args.region_source {
+            spec.source = source.into();
+        }
+        spec
+    });
+    if let Some(mut spec) = region_spec {
+        let mut lookup = app_cfg.region_lookup.clone();
+        if let Some(csv) = args.region_csv.clone() {
+            lookup.csv = Some(csv);
+        }
+        if let Some(api) = args.region_api.clone() {
+            lookup.api = Some(api);
+        }
+        let table = match lookup.csv.as_deref() {
+            Some(path) if spec.source.uses_geoip() => Some(RegionTable::load(path)?),
+            _ => None,
+        };
+        let geoip = if spec.source.uses_geoip() {
+            if resources_mode != ResourcesMode::Never {
+                if let Err(err) =
+                    ensure_mihomo_resources(&client, &paths, &args.resource_mirrors, |name| {
+                        name == "Country.mmdb"
+                    })
+                    .await
+                {
+                    warn!(error = %format!("{:#}", err), "Country.mmdb unavailable");
+                }
+            }
+            match GeoIp::open(&paths.resource_file("Country.mmdb")) {
+                Ok(geoip) => Some(geoip),
+                Err(err) if table.is_none() && lookup.api.is_none() => {
+                    warnings.push(
+                        WarningKind::StaleResource,
+                        format!("{:#}; region groups fall back to name keywords", err),
+                    );
+                    spec.source = RegionSource::Name;
+                    None
+                }
+                Err(err) => {
+                    warnings.push(
+                        WarningKind::StaleResource,
+                        format!("{:#}; region groups use the CSV table and API only", err),
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let resolved = if spec.source.uses_geoip() {
+            resolve_proxy_servers(&merged).await
+        } else {
+            HashMap::new()
+        };
+        let mut lookups: Vec<&dyn ServerRegionLookup> = Vec::new();
+        if let Some(table) = table.as_ref() {
+            lookups.push(table);
+        }
+        if let Some(geoip) = geoip.as_ref() {
+            lookups.push(geoip);
+        }
+        let ask_api = lookup.api.is_some() && spec.source.uses_geoip();
+        let cache_path = paths.region_cache_file();
+        let mut remote = if ask_api {
+            RemoteRegions::load(&cache_path).await
+        } else {
+            RemoteRegions::default()
+        };
+        if ask_api {
+            let unclassified = RegionClassifier {
+                source: spec.source,
+                dictionary: &rename.dictionary,
+                lookups: lookups.clone(),
+                resolved: &resolved,
+            }
+            .unclassified_servers(&merged.proxies);
+            let requests = remote.refresh(&client, &lookup, &unclassified).await;
+            if requests > 0 && !storage::is_read_only() {
+                if let Err(err) = remote.save(&cache_path).await {
+                    warn!(error = %format!("{:#}", err), "failed to save region lookup cache");
+                }
+            }
+            debug!(requests = requests, "asked region API");
+            lookups.push(&remote);
+        }
+        let main_selector =
+            resolve_dev_rules_via(&args.dev_rules_via, DEFAULT_DEV_RULE_VIA, &merged);
+        let classifier = RegionClassifier {
+            source: spec.source,
+            dictionary: &rename.dictionary,
+            lookups,
+            resolved: &resolved,
+        };
+        let added = add_region_groups(
+            &mut merged,
+            &spec,
+            &classifier,
+            &main_selector,
+            &mut warnings,
+        );
+        for (group, members) in &added {
+            info!(group = %group, members = members, "generated region group");
+        }
+    }
+
+    // Provider and region groups are generated after the merge pass; give them the same URLs.
+    apply_health_check_urls(&mut merged, &health_checks);
+    let mut group_icons = app_cfg.group_icons.clone();
+    group_icons.extend(args.group_icons.iter().cloned());
+    apply_group_icons(&mut merged, &group_icons);
+
+    apply_mode_override(&mut merged, args.mode);
+    apply_tun_enabled(&mut merged, true);
+    apply_sniffer_preset(&mut merged, args.sniffer_preset);
+
+    if let Some(previous) = app_cfg.managed_tailscale_compat.as_ref() {
+        remove_tailscale_managed_items(&mut merged, previous);
+    }
+
+    let mut dev_rules_listing = None;
+    let mut summary_dev_via: Option<String> = None;
+    let mut summary_dev_added: usize = 0;
+    if args.dev_rules || args.dev_rules_show {
+        let resolved_via =
+            resolve_dev_rules_via(&args.dev_rules_via, DEFAULT_DEV_RULE_VIA, &merged);
+        if resolved_via != args.dev_rules_via && args.dev_rules {
+            warnings.push(
+                WarningKind::FallbackVia,
+                format!(
+                    "--dev-rules-via '{}' not found in config; using '{}'",
+                    args.dev_rules_via, resolved_via
+                ),
+            );
+        }
+
+        let list = build_dev_rules(&resolved_via);
+        if args.dev_rules {
+            let mut combined = list.clone();
+            combined.extend(merged.rules);
+            merged.rules = combined;
+            summary_dev_via = Some(resolved_via.clone());
+            summary_dev_added = list.len();
+        } else {
+            // even if not applied, keep via for summary visibility
+            summary_dev_via = Some(resolved_via.clone());
+        }
+        dev_rules_listing = Some(list);
+    }
+
+    // Block lists from `rules import` go in as inline rule-providers, right after the custom
+    // rules.
+    if !app_cfg.rule_sets.is_empty() {
+        let mut providers = match merged.extra.get("rule-providers") {
+            Some(Value::Mapping(existing)) => existing.clone(),
+            _ => serde_yaml::Mapping::new(),
+        };
+        let mut set_rules = Vec::with_capacity(app_cfg.rule_sets.len());
+        for (name, set) in &app_cfg.rule_sets {
+            let payload = storage::load_rule_set(&paths, name).await.with_context(|| {
+                format!(
+                    "imported rule set '{}' is unreadable; import it again or run `rules remove {}`",
+                    name, name
+                )
+            })?;
+            let mut provider = serde_yaml::Mapping::new();
+            provider.insert("type".into(), "inline".into());
+            provider.insert("behavior".into(), "domain".into());
+            provider.insert("payload".into(), payload.into());
+            providers.insert(name.as_str().into(), Value::Mapping(provider));
+            set_rules.push(format!("RULE-SET,{},{}", name, set.via));
+        }
+        merged
+            .extra
+            .insert("rule-providers".to_string(), Value::Mapping(providers));
+        set_rules.extend(merged.rules);
+        merged.rules = set_rules;
+    }
+
+    // Prepend custom quick rules (take precedence)
+    if !app_cfg.custom_rules.is_empty() {
+        let mut quick = Vec::with_capacity(app_cfg.custom_rules.len());
+        let now = chrono::Local::now().time();
+        for r in &app_cfg.custom_rules {
+            if !r.is_active_at(now) {
+                debug!(domain = %r.domain, via = %r.via, "custom rule outside its active hours");
+                continue;
+            }
+            let tag = match r.kind {
+                RuleKind::Domain => "DOMAIN",
+                RuleKind::DomainSuffix => "DOMAIN-SUFFIX",
+                RuleKind::DomainKeyword => "DOMAIN-KEYWORD",
+            };
+            quick.push(format!("{},{},{}", tag, r.domain, r.via));
+        }
+        let mut new_rules = quick;
+        new_rules.extend(merged.rules);
+        merged.rules = new_rules;
+    }
+
+    // Apply external-controller overrides if provided
+    if args.external_controller_url.is_some()
+        || args.external_controller_port.is_some()
+        || args.external_controller_secret.is_some()
+    {
+        use serde_yaml::Value;
+
+        // Read any existing external-controller value like "host:port"
+        let mut existing_host: Option<String> = None;
+        let mut existing_port: Option<u16> = None;
+        if let Some(Value::String(s)) = merged.extra.get("external-controller") {
+            if let Some((h, p)) = parse_host_port(s) {
+                existing_host = Some(h);
+                existing_port = Some(p);
+            }
+        }
+
+        let host = args
+            .external_controller_url
+            .clone()
+            .or(existing_host)
+            .unwrap_or_else(|| "127.0.0.1".to_string());
+        let port = args
+            .external_controller_port
+            .or(existing_port)
+            .unwrap_or(9090);
+        merged.extra.insert(
+            "external-controller".to_string(),
+            Value::String(format!("{}:{}", host, port)),
+        );
+
+        if let Some(secret) = args.external_controller_secret.as_ref() {
+            merged
+                .extra
+                .insert("secret".to_string(), Value::String(secret.clone()));
+        }
+    }
+
+    if !args.no_auto_secret && ensure_controller_secret(&mut merged, &mut app_cfg) {
+        store_generated_controller_secret(&mut app_cfg).await;
+    }
+    // A `keyring:` secret (stored by `manage secrets`, or written in a template) is only
+    // resolved into the generated config, never into app.yaml.
+    if let Some(reference) = merged
+        .extra
+        .get("secret")
+        .and_then(Value::as_str)
+        .filter(|secret| secrets::keyring_name(secret).is_some())
+        .map(str::to_string)
+    {
+        let secret = secrets::resolve_secret(&reference)
+            .await
+            .context("external-controller secret")?;
+        merged
+            .extra
+            .insert("secret".to_string(), Value::String(secret));
+    }
+
+    if !app_cfg.listeners.is_empty() {
+        inject_listeners(&mut merged, &app_cfg.listeners)?;
+    }
+
+    if !app_cfg.hosts.is_empty() {
+        inject_hosts(&mut merged, &app_cfg.hosts);
+    }
+
+    if let Some(ui) = app_cfg.external_ui.as_ref() {
+        use serde_yaml::Value;
+        merged.extra.insert(
+            "external-ui".to_string(),
+            Value::String(ui.path.display().to_string()),
+        );
+        merged
+            .extra
+            .insert("external-ui-url".to_string(), Value::String(ui.url.clone()));
+    }
+
+    // Append fake-ip bypass entries: combine new clearer option with legacy flag
+    let mut bypass_entries: Vec<String> = Vec::new();
+    bypass_entries.extend(args.fake_ip_bypass.iter().cloned());
+    bypass_entries.extend(args.fake_ip_filter_add.iter().cloned());
+    if !bypass_entries.is_empty() {
+        use serde_yaml::{Mapping, Value};
+        // Ensure dns mapping exists
+        let dns_value = merged
+            .extra
+            .entry("dns".to_string())
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        if let Value::Mapping(dns_map) = dns_value {
+            // Ensure fake-ip-filter sequence exists
+            let key = Value::String("fake-ip-filter".to_string());
+            let filter_seq = dns_map
+                .entry(key)
+                .or_insert_with(|| Value::Sequence(Vec::new()));
+            if let Value::Sequence(seq) = filter_seq {
+                for item in bypass_entries {
+                    seq.push(Value::String(item));
+                }
+            }
+            // Force blacklist mode when user requests bypass entries
+            let mode_key = Value::String("fake-ip-filter-mode".to_string());
+            let current_mode = dns_map.get(&mode_key).and_then(|v| v.as_str());
+            let desired = "blacklist";
+            if current_mode.map(|s| s.eq_ignore_ascii_case(desired)) != Some(true) {
+                if let Some(cm) = current_mode {
+                    warn!(current = %cm, "overriding fake-ip-filter-mode to 'blacklist' for --fake-ip-bypass");
+                }
+                dns_map.insert(mode_key, Value::String(desired.to_string()));
+            }
+        }
+    }
+
+    // Apply fake-ip-filter-mode if provided explicitly (advanced)
+    if let Some(mode) = args.fake_ip_filter_mode.as_ref() {
+        let m = mode.to_ascii_lowercase();
+        if m == "blacklist" || m == "whitelist" {
+            use serde_yaml::{Mapping, Value};
+            let dns_value = merged
+                .extra
+                .entry("dns".to_string())
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            if let Value::Mapping(dns_map) = dns_value {
+                // If user also used --fake-ip-bypass and asks for whitelist, warn and keep blacklist
+                let requested_whitelist = m == "whitelist";
+                let used_bypass = !args.fake_ip_bypass.is_empty();
+                if requested_whitelist && used_bypass {
+                    warn!("--fake-ip-bypass works with blacklist mode; keeping 'blacklist' instead of requested 'whitelist'");
+                } else {
+                    dns_map.insert(
+                        Value::String("fake-ip-filter-mode".to_string()),
+                        Value::String(m),
+                    );
+                }
+            }
+        } else {
+            warn!(
+                value = %mode,
+                "invalid --fake-ip-filter-mode (expected 'blacklist' or 'whitelist')"
+            );
+        }
+    }
+
+    // Ensure Kubernetes cluster DNS names are not forced into fake-ip.
+    //
+    // When tun + dns-hijack is enabled, in-cluster lookups like
+    // *.svc.cluster.local may be intercepted and incorrectly resolved into the
+    // fake-ip range (198.18.0.0/16), breaking Kubernetes service discovery.
+    //
+    // Keep this minimal and only apply in fake-ip mode when filter mode is not
+    // whitelist.
+    {
+        use serde_yaml::{Mapping, Value};
+
+        let dns_value = merged
+            .extra
+            .entry("dns".to_string())
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+        if let Value::Mapping(dns_map) = dns_value {
+            let enhanced_key = Value::String("enhanced-mode".to_string());
+            let mode_key = Value::String("fake-ip-filter-mode".to_string());
+            let filter_key = Value::String("fake-ip-filter".to_string());
+
+            let enhanced = dns_map
+                .get(&enhanced_key)
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+
+            if enhanced.eq_ignore_ascii_case("fake-ip") {
+                let filter_mode = dns_map
+                    .get(&mode_key)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("blacklist");
+
+                if !filter_mode.eq_ignore_ascii_case("whitelist") {
+                    // Cover both the canonical FQDNs and resolver-expanded names
+                    // (when search domains are appended).
+                    let wanted = ["+.cluster.local", "*.cluster.local.*"];
+
+                    let filter_seq = dns_map
+                        .entry(filter_key)
+                        .or_insert_with(|| Value::Sequence(Vec::new()));
+
+                    if let Value::Sequence(seq) = filter_seq {
+                        for item in wanted {
+                            let exists = seq.iter().any(|v| v.as_str() == Some(item));
+                            if !exists {
+                                seq.push(Value::String(item.to_string()));
+                                info!(value = %item, "auto-added fake-ip bypass");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if args.tailscale_compatible {
+        apply_tailscale_compatibility(
+            &mut merged,
+            &args.tailscale_tailnet_suffixes,
+            &args.tailscale_direct_domains,
+        );
+        app_cfg.managed_tailscale_compat = Some(build_tailscale_managed_state(
+            &args.tailscale_tailnet_suffixes,
+            &args.tailscale_direct_domains,
+            &args.route_exclude_address_add,
+        ));
+    } else if app_cfg.managed_tailscale_compat.is_some() {
+        app_cfg.managed_tailscale_compat = None;
+    }
+
+    let fallback_via = resolve_dev_rules_via(&args.dev_rules_via, DEFAULT_DEV_RULE_VIA, &merged);
+    ensure_fallback_match_rule(&mut merged, &fallback_via);
+
+    // Avoid hijacking Kubernetes pod/service CIDRs in tun mode.
+    // This keeps in-cluster traffic (including DNS to kube-dns) out of the tun
+    // device so service discovery remains stable.
+    {
+        use serde_yaml::{Mapping, Value};
+
+        let tun_value = merged
+            .extra
+            .entry("tun".to_string())
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+        if let Value::Mapping(tun_map) = tun_value {
+            let key = Value::String("route-exclude-address".to_string());
+            let seq_value = tun_map
+                .entry(key)
+                .or_insert_with(|| Value::Sequence(Vec::new()));
+
+            if let Value::Sequence(seq) = seq_value {
+                let mut cidrs: Vec<String> =
+                    vec!["10.42.0.0/16".to_string(), "10.43.0.0/16".to_string()];
+                cidrs.extend(args.k8s_cidr_exclude.iter().cloned());
+                cidrs.extend(args.route_exclude_address_add.iter().cloned());
+
+                for cidr in cidrs {
+                    if !cidr.contains('/') {
+                        warn!(value = %cidr, "invalid CIDR for route-exclude-address addition (expected like 10.42.0.0/16)");
+                        continue;
+                    }
+                    let exists = seq.iter().any(|v| v.as_str() == Some(cidr.as_str()));
+                    if !exists {
+                        seq.push(Value::String(cidr.clone()));
+                        info!(value = %cidr, "auto-added tun route-exclude-address");
+                    }
+                }
+            }
+        }
+    }
+
+    if args.prune_empty_groups {
+        let fallback = resolve_dev_rules_via(&args.dev_rules_via, DEFAULT_DEV_RULE_VIA, &merged);
+        for group in mihomo_core::merge::prune_empty_groups(&mut merged, &fallback) {
+            warnings.push(
+                WarningKind::PrunedGroup,
+                format!("removed empty proxy-group '{}'", group),
+            );
+        }
+    }
+
+    // prepend-/append- directives carried in by the template or base config.
+    merged = mihomo_core::apply_merge_directives(merged)
+        .context("failed to apply prepend/append directives")?;
+
+    // Clash Verge exports carry the merge/script chain as separate files instead.
+    let verge_base = args.clash_verge_profile.is_some().then(|| merged.clone());
+
+    // Merge-kind subscriptions patch the merged profile, clash-verge style.
+    for (name, patch) in &merge_patches {
+        merged = mihomo_core::apply_merge_patch(merged, patch)
+            .with_context(|| format!("failed to apply merge patch {}", name))?;
+        info!(subscription = %name, "applied merge patch");
+    }
+
+    // Scripts run after every merge patch, like clash-verge's global Merge then Script.
+    let profile_name = args
+        .profile
+        .clone()
+        .or_else(|| source_names.first().cloned())
+        .unwrap_or_default();
+    for (name, source) in &scripts {
+        merged = mihomo_core::script::run_script(source, merged, &profile_name)
+            .with_context(|| format!("script {} failed", name))?;
+        info!(subscription = %name, "applied script");
+    }
+
+    // Machine-local tweaks win over everything above.
+    let override_path = paths.override_path();
+    if fs::try_exists(&override_path).await.unwrap_or(false) {
+        let raw = fs::read_to_string(&override_path)
+            .await
+            .with_context(|| format!("failed to read {}", override_path.display()))?;
+        let overlay: serde_yaml::Value = serde_yaml::from_str(&raw)
+            .with_context(|| format!("failed to parse {}", override_path.display()))?;
+        if !overlay.is_null() {
+            merged = mihomo_core::merge::apply_override(merged, &overlay)
+                .with_context(|| format!("failed to apply {}", override_path.display()))?;
+            info!(path = %override_path.display(), "applied local override");
+        }
+    }
+
+    for rule in mihomo_core::report::find_duplicate_rules(&merged.rules) {
+        warnings.push(
+            WarningKind::DuplicateRule,
+            format!("rule '{}' appears more than once", rule),
+        );
+    }
+    for group in mihomo_core::report::find_duplicates(&merged.proxy_group_names()) {
+        warnings.push(
+            WarningKind::DuplicateGroup,
+            format!("proxy-group '{}' is defined more than once", group),
+        );
+    }
+
+    let unknown = mihomo_core::report::find_unknown_references(&merged);
+    if !unknown.is_empty() && !args.lenient {
+        return Err(anyhow!(
+            "the merged config refers to things it does not define (pass --lenient to write it anyway):\n  - {}",
+            unknown.join("\n  - ")
+        ));
+    }
+    for reference in unknown {
+        warnings.push(WarningKind::UnknownReference, reference);
+    }
+
+    if args.strict && !warnings.is_empty() {
+        eprint!("{}", render_source_table(&source_rows, &merged));
+        return Err(anyhow!(
+            "--strict: refusing to produce a degraded config\n{}",
+            warnings.render_summary().trim_end()
+        ));
+    }
+
+    let output_path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    let rule_provider_dir = mihomo_core::rule_providers::provider_dir(&output_path);
+    let rule_sets = if args.extract_rule_providers {
+        let sets = mihomo_core::rule_providers::extract_rule_providers(
+            &mut merged,
+            args.extract_min_rules,
+            &rule_provider_dir,
+        );
+        info!(
+            providers = sets.len(),
+            rules = sets.iter().map(|set| set.payload.len()).sum::<usize>(),
+            "moved rule blocks into rule-providers"
+        );
+        sets
+    } else {
+        Vec::new()
+    };
+
+    // If dry-run, print a concise summary and skip writing
+    if args.dry_run {
+        print_merge_summary(
+            &merged,
+            &args,
+            summary_dev_via.as_deref(),
+            summary_dev_added,
+            &paths,
+        );
+        if let Some(list) = dev_rules_listing.as_ref().filter(|_| args.dev_rules_show) {
+            for rule in list {
+                eprintln!("dev-rule: {}", rule);
+            }
+        }
+        eprint!("{}", render_source_table(&source_rows, &merged));
+        eprint!("{}", warnings.render_summary());
+        return Ok(());
+    }
+
+    if args.deterministic {
+        merged.canonicalize_entries();
+    }
+
+    if args.diff {
+        let previous = match fs::read_to_string(&output_path).await {
+            Ok(raw) => mihomo_core::ClashConfig::from_yaml_str(&raw).with_context(|| {
+                format!("failed to parse existing output {}", output_path.display())
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                mihomo_core::ClashConfig::default()
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let diff = mihomo_core::diff::diff_configs(&previous, &merged);
+        print!("{}", diff.render());
+        eprint!("{}", shown(render_source_table(&source_rows, &merged)));
+        eprint!("{}", shown(warnings.render_summary()));
+        if !diff.is_empty() {
+            return Err(ExitStatus(1).into());
+        }
+        return Ok(());
+    }
+
+    let delays = if args.annotate_latency.is_some() || args.exclude_dead_after.is_some() {
+        match fetch_proxy_delays(&merged).await {
+            Ok(delays) => delays,
+            Err(err) if args.annotate_latency.is_some() => {
+                warn!(error = %err, "could not read proxy delays from the controller; skipping latency annotations");
+                HashMap::new()
+            }
+            Err(err) => {
+                debug!(error = %err, "could not read proxy delays from the controller");
+                HashMap::new()
+            }
+        }
+    } else {
+        HashMap::new()
+    };
+    if args.exclude_dead_after.is_some() {
+        // Nodes the probe already observed count once per run.
+        let from_controller: BTreeMap<String, bool> = merged
+            .proxy_names()
+            .into_iter()
+            .filter(|name| !observed.contains_key(name))
+            .filter_map(|name| delays.get(&name).map(|&delay| (name, delay > 0)))
+            .collect();
+        if !from_controller.is_empty() {
+            liveness.record(&from_controller, chrono::Utc::now());
+            save_node_liveness(&liveness, &liveness_path).await;
+        }
+    }
+    if args.annotate_latency == Some(LatencyAnnotation::Key) {
+        for proxy in merged.proxies.iter_mut() {
+            let Some(delay) = proxy_name(proxy).and_then(|name| delays.get(&name).copied()) else {
+                continue;
+            };
+            if let Some(map) = proxy.as_mapping_mut() {
+                map.insert(Value::from("x-latency"), Value::from(delay));
+            }
+        }
+    }
+
+    let mut yaml = if args.redact_secrets {
+        let mut shared = merged.clone();
+        let masked = redact_secrets(&mut shared);
+        debug!(masked, "redacted secrets for stdout");
+        shared.to_yaml_string()?
+    } else {
+        merged.to_yaml_string()?
+    };
+    let unchanged = args.if_changed
+        && !args.stdout
+        && fs::read_to_string(&output_path)
+            .await
+            .is_ok_and(|existing| strip_proxy_annotations(strip_header(&existing)) == yaml)
+        && !(args.extract_rule_providers
+            && mihomo_core::rule_providers::rule_files_changed(&rule_provider_dir, &rule_sets)
+                .await);
+    if args.annotate_latency == Some(LatencyAnnotation::Comment) && !delays.is_empty() {
+        let notes: Vec<Option<String>> = merged
+            .proxies
+            .iter()
+            .map(|proxy| {
+                let delay = delays.get(&proxy_name(proxy)?)?;
+                Some(match delay {
+                    0 => "timeout".to_string(),
+                    ms => format!("{}ms", ms),
+                })
+            })
+            .collect();
+        yaml = annotate_proxies(&yaml, &notes);
+    }
+    let header = args.header.then(|| GenerationInfo {
+        timestamp: (!args.no_timestamp && !args.deterministic).then(chrono::Utc::now),
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        template: template_path.display().to_string(),
+        subscriptions: source_names.iter().cloned().map(shown).collect(),
+    });
+    if let Some(info) = header.as_ref() {
+        yaml = with_header(&yaml, info);
+    }
+
+    if resources_mode == ResourcesMode::Lazy {
+        let needed = referenced_resources(&merged);
+        ensure_mihomo_resources(&client, &paths, &args.resource_mirrors, |name| {
+            needed.contains(&name)
+        })
+        .await?;
+    }
+
+    if unchanged {
+        println!(
+            "merged config unchanged; {} left as is",
+            output_path.display()
+        );
+    } else if args.stdout {
+        println!("{}", yaml);
+    } else {
+        // Providers first, so the config never points at files that are not there yet.
+        if args.extract_rule_providers {
+            mihomo_core::rule_providers::write_rule_files(&rule_provider_dir, &rule_sets)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to write rule providers to {}",
+                        rule_provider_dir.display()
+                    )
+                })?;
+        }
+        ensure_parent(&output_path).await?;
+        let deployer = FileDeployer {
+            path: output_path.clone(),
+            keep_backups: args.keep_backups,
+        };
+        deployer.deploy(&yaml).await.with_context(|| {
+            format!("failed to write merged config to {}", output_path.display())
+        })?;
+        println!("merged config written to {}", output_path.display());
+        if args.keep_history > 0 && !storage::is_read_only() {
+            let entry = HistoryEntry::new(
+                &yaml,
+                &output_path,
+                source_names.clone(),
+                merged.proxies.len(),
+            );
+            if let Err(err) =
+                history::record(&paths.history_dir(), &entry, &yaml, args.keep_history).await
+            {
+                warn!(error = %format!("{:#}", err), "failed to record config history");
+            }
+        }
+
+        if args.sync_to_clash_verge {
+            let clash_verge_paths = paths.detected_clash_verge_runtime_config_paths();
+            if clash_verge_paths.is_empty() {
+                return Err(anyhow!(
+                    "--sync-to-clash-verge requested, but no local Clash Verge runtime config was detected"
+                ));
+            }
+            for clash_verge_path in &clash_verge_paths {
+                ensure_parent(clash_verge_path).await?;
+                if clash_verge_path.exists() {
+                    if let Some(backup) = backup_existing_file(clash_verge_path).await? {
+                        println!(
+                            "backed up existing Clash Verge config to {}",
+                            backup.display()
+                        );
+                    }
+                }
+                let deployer = FileDeployer::new(clash_verge_path.clone());
+                deployer.deploy(&yaml).await.with_context(|| {
+                    format!(
+                        "failed to sync merged config to Clash Verge runtime path {}",
+                        clash_verge_path.display()
+                    )
+                })?;
+                println!("synced config to {}", clash_verge_path.display());
+            }
+
+            if let Err(err) = reload_clash_verge_runtime(&merged, &clash_verge_paths).await {
+                warn!(error = %err, "failed to auto-reload Clash Verge runtime after sync");
+            }
+        }
+
+        if args.sync_to_clash_verge_sources {
+            sync_clash_verge_source_configs(&paths, &merged).await?;
+        }
+    }
+
+    for (name, device) in &devices {
+        write_device_output(&merged, name, device, &output_path, header.as_ref(), &args)
+            .await
+            .with_context(|| format!("failed to write the config for device '{}'", name))?;
+    }
+
+    if let Some(endpoint) = args.deploy_api.as_ref().filter(|_| !unchanged) {
+        let secret = args.deploy_secret.clone().or_else(|| {
+            merged
+                .extra
+                .get("secret")
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+        });
+        let deployer = HttpDeployer {
+            endpoint: endpoint.clone(),
+            secret,
+            ca_cert: args.deploy_ca_cert.clone(),
+        };
+        deployer.deploy(&yaml).await?;
+        println!("deployed config to external controller {}", endpoint);
+    }
+
+    if let Some(target) = args.deploy_ssh.as_deref().filter(|_| !unchanged) {
+        let (destination, remote_path) = SshDeployer::parse_target(target)?;
+        let deployer = SshDeployer {
+            destination,
+            remote_path,
+            port: args.deploy_ssh_port,
+            identity: args.deploy_ssh_identity.clone(),
+            reload_command: args.deploy_ssh_reload.clone(),
+        };
+        deployer.deploy(&yaml).await?;
+        println!("deployed config over ssh to {}", target);
+    }
+
+    if let (Some(dir), Some(base)) = (
+        args.clash_verge_profile.as_ref().filter(|_| !unchanged),
+        verge_base.as_ref(),
+    ) {
+        let dir = match dir {
+            Some(dir) => dir.clone(),
+            None => paths.detect_clash_verge_dir().ok_or_else(|| {
+                anyhow!("--clash-verge-profile: no Clash Verge directory detected; pass one")
+            })?,
+        };
+        let patches: Vec<_> = merge_patches
+            .iter()
+            .map(|(_, patch)| patch.clone())
+            .collect();
+        let sources: Vec<&str> = scripts.iter().map(|(_, source)| source.as_str()).collect();
+        let profile = mihomo_core::clash_verge::VergeProfile {
+            name: if profile_name.is_empty() {
+                "mihomo-cli"
+            } else {
+                &profile_name
+            },
+            config: base,
+            merge: (!patches.is_empty()).then(|| mihomo_core::combine_merge_patches(&patches)),
+            script: (!sources.is_empty()).then(|| mihomo_core::script::chain_scripts(&sources)),
+        };
+        let written = mihomo_core::clash_verge::write_profile(&dir, &profile).await?;
+        println!(
+            "exported Clash Verge profile '{}' to {}",
+            profile.name,
+            written.display()
+        );
+    }
+
+    if let Some(target) = args.reload.as_ref().filter(|_| !unchanged) {
+        let controller = controller_endpoint(&merged);
+        let secret = merged.extra.get("secret").and_then(Value::as_str);
+        target
+            .reload(
+                &output_path,
+                controller.as_deref().map(|endpoint| (endpoint, secret)),
+            )
+            .await
+            .context("failed to reload mihomo")?;
+        println!("reloaded mihomo via --reload");
+    }
+
+    let hooks: Vec<&String> = app_cfg.post_hooks.iter().chain(&args.post_hooks).collect();
+    if !hooks.is_empty() && !unchanged {
+        let env = post_hook_env(
+            (!args.stdout).then_some(output_path.as_path()),
+            &merged,
+            &source_names,
+            warnings.as_slice().len(),
+        );
+        for hook in hooks {
+            run_post_hook(hook, &env, &mut warnings).await;
+        }
+    }
+
+    if let Some(list) = dev_rules_listing.as_ref().filter(|_| args.dev_rules_show) {
+        for rule in list {
+            eprintln!("dev-rule: {}", rule);
+        }
+    }
+
+    // Failure counters, last_source and the cached URL are by-products; a read-only run keeps
+    // them in memory only.
+    if storage::is_read_only() {
+        debug!("read-only mode: not saving subscriptions or app config");
+    } else if args.subscriptions_file.is_none() {
+        storage::save_subscription_list(&paths, &subscription_list).await?;
+    } else if let Some(custom) = args.subscriptions_file.as_ref() {
+        storage::save_subscription_list_to(custom, &subscription_list).await?;
+    }
+
+    // Update caches after successful merge
+    if let Some(url) = used_url.take() {
+        app_cfg.last_subscription_url = Some(url);
+    }
+
+    if app_cfg != original_app_cfg && !storage::is_read_only() {
+        storage::save_app_config(&paths, &app_cfg).await?;
+    }
+
+    let report = MergeReport {
+        generated_at: chrono::Utc::now(),
+        output: (!args.stdout).then(|| output_path.clone()),
+        subscriptions: source_names,
+        proxies: merged.proxies.len(),
+        proxy_groups: merged.proxy_groups.len(),
+        rules: merged.rules.len(),
+        warnings: warnings.as_slice().to_vec(),
+        node_changes: source_rows
+            .iter()
+            .filter(|row| !row.changes.is_empty())
+            .map(|row| (row.name.clone(), row.changes.clone()))
+            .collect(),
+    };
+    let span = tracing::Span::current();
+    span.record("subscriptions", report.subscriptions.len());
+    span.record("proxies", report.proxies);
+    span.record("groups", report.proxy_groups);
+    span.record("rules", report.rules);
+    span.record("warnings", report.warnings.len());
+    if storage::is_read_only() {
+        debug!("read-only mode: not writing merge report");
+    } else if let Err(err) = report.write(&paths.report_path()).await {
+        warn!(error = %err, "failed to write merge report");
+    }
+    eprint!("{}", shown(render_source_table(&source_rows, &merged)));
+    eprint!("{}", shown(warnings.render_summary()));
+
+    if args.sync_to_clash_verge {
+        if let Err(err) = sync_system_proxy_bypass(
+            previous_managed_tailscale.as_ref(),
+            app_cfg.managed_tailscale_compat.as_ref(),
+        ) {
+            warn!(error = %err, "failed to sync system proxy bypass entries");
+        }
+    }
+
+    Ok(())
+}
+
+/// Environment passed to post-merge hooks.
+fn post_hook_env(
+    output: Option<&Path>,
+    merged: &mihomo_core::ClashConfig,
+    subscriptions: &[String],
+    warning_count: usize,
+) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "MIHOMO_CONFIG_PATH",
+            output
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        ),
+        ("MIHOMO_PROXY_COUNT", merged.proxies.len().to_string()),
+        ("MIHOMO_GROUP_COUNT", merged.proxy_groups.len().to_string()),
+        ("MIHOMO_RULE_COUNT", merged.rules.len().to_string()),
+        ("MIHOMO_WARNING_COUNT", warning_count.to_string()),
+        ("MIHOMO_SUBSCRIPTIONS", subscriptions.join(",")),
+    ]
+}
+
+/// Run one hook through the platform shell. Its output goes to stderr so `--stdout` stays
+/// clean; failures become warnings because the config has already been written.
+async fn run_post_hook(hook: &str, env: &[(&'static str, String)], warnings: &mut Warnings) {
+    let mut command = if cfg!(windows) {
+        let mut command = tokio::process::Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    } else {
+        let mut command = tokio::process::Command::new("sh");
+        command.arg("-c").arg(hook);
+        command
+    };
+    command.envs(env.iter().map(|(key, value)| (*key, value)));
+
+    info!(hook = %hook, "running post-merge hook");
+    match command.output().await {
+        Ok(output) => {
+            eprint!("{}", String::from_utf8_lossy(&output.stdout));
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            if !output.status.success() {
+                warnings.push(
+                    WarningKind::Hook,
+                    format!("post-hook `{}` exited with {}", hook, output.status),
+                );
+            }
+        }
+        Err(err) => warnings.push(
+            WarningKind::Hook,
+            format!("failed to run post-hook `{}`: {}", hook, err),
+        ),
+    }
+}
+
+fn print_merge_summary(
+    merged: &mihomo_core::ClashConfig,
+    args: &MergeArgs,
+    dev_via: Option<&str>,
+    dev_added: usize,
+    paths: &AppPaths,
+) {
+    use serde_yaml::Value;
+
+    let proxies = merged.proxy_names().len();
+    let groups = merged.proxy_group_names().len();
+    let rules = merged.rules.len();
+
+    // DNS fake-ip summary
+    let mut dns_mode: Option<String> = None;
+    let mut dns_filter_total: Option<usize> = None;
+    let mut dns_fake_ip_range: Option<String> = None;
+    if let Some(Value::Mapping(dns)) = merged.extra.get("dns") {
+        if let Some(Value::String(m)) = dns.get(Value::String("fake-ip-filter-mode".into())) {
+            dns_mode = Some(m.clone());
+        }
+        if let Some(Value::Sequence(seq)) = dns.get(Value::String("fake-ip-filter".into())) {
+            dns_filter_total = Some(seq.len());
+        }
+        if let Some(Value::String(range)) = dns.get(Value::String("fake-ip-range".into())) {
+            dns_fake_ip_range = Some(range.clone());
+        }
+    }
+
+    // External controller
+    let mut ext_ctrl: Option<String> = None;
+    if let Some(Value::String(s)) = merged.extra.get("external-controller") {
+        ext_ctrl = Some(s.clone());
+    }
+    let secret_present = merged
+        .extra
+        .get("secret")
+        .and_then(|v| v.as_str())
+        .is_some();
+
+    println!("dry-run summary:");
+    println!(
+        "- proxies: {}, groups: {}, rules: {}",
+        proxies, groups, rules
+    );
+    println!(
+        "- fake-ip: mode={}, range={}, filter+={} (requested), total={}",
+        dns_mode.as_deref().unwrap_or("<none>"),
+        dns_fake_ip_range.as_deref().unwrap_or("<unset>"),
+        args.fake_ip_bypass.len(),
+        dns_filter_total
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| "<unknown>".into())
+    );
+    println!(
+        "- dev-rules: enabled={}, via={}, added={}",
+        if args.dev_rules { "true" } else { "false" },
+        dev_via.unwrap_or("<n/a>"),
+        if args.dev_rules { dev_added } else { 0 }
+    );
+    println!("- mode: {}", args.mode.as_str());
+    println!(
+        "- sniffer-preset: {}",
+        match args.sniffer_preset {
+            SnifferPreset::Off => "off",
+            SnifferPreset::Tun => "tun",
+        }
+    );
+    println!("- manual-servers: <see app.yaml> (not shown in dry-run summary)");
+    println!(
+        "- external-controller: {}, secret={}",
+        ext_ctrl.unwrap_or_else(|| "<unset>".into()),
+        if secret_present { "set" } else { "unset" }
+    );
+    let would_write = args
+        .output
+        .clone()
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    println!(
+        "- output: would write to {} (suppressed by --dry-run)",
+        would_write.display()
+    );
+    if args.sync_to_clash_verge {
+        let targets = paths.detected_clash_verge_runtime_config_paths();
+        let target = if targets.is_empty() {
+            "<not detected>".to_string()
+        } else {
+            targets
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        println!("- clash-verge-sync: {}", target);
+    }
+    if args.sync_to_clash_verge_sources {
+        let target = paths
+            .detect_clash_verge_dir()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "<not detected>".into());
+        println!("- clash-verge-source-sync: {}", target);
+    }
+    println!(
+        "- tailscale-compatible: {}",
+        if args.tailscale_compatible {
+            "true"
+        } else {
+            "false"
+        }
+    );
+    if args.tailscale_compatible {
+        println!(
+            "- tailscale-tailnet-suffixes: {}",
+            if args.tailscale_tailnet_suffixes.is_empty() {
+                "<none>".to_string()
+            } else {
+                args.tailscale_tailnet_suffixes.join(",")
+            }
+        );
+        println!(
+            "- tailscale-direct-domains: {}",
+            if args.tailscale_direct_domains.is_empty() {
+                "<none>".to_string()
+            } else {
+                args.tailscale_direct_domains.join(",")
+            }
+        );
+    }
+}
+
+fn normalize_direct_domain(domain: &str) -> Option<String> {
+    let normalized = domain.trim().trim_matches('.').to_ascii_lowercase();
+    if normalized.is_empty() {
+        None
+    } else {
+        Some(normalized)
+    }
+}
+
+fn build_tailscale_fake_ip_bypass(
+    tailnet_suffixes: &[String],
+    direct_domains: &[String],
+) -> Vec<String> {
+    let mut items: Vec<String> = TAILSCALE_BASE_FAKE_IP_BYPASS
+        .iter()
+        .map(|s| (*s).to_string())
+        .collect();
+    for suffix in tailnet_suffixes {
+        if let Some(suffix) = normalize_direct_domain(suffix) {
+            items.push(format!("+.tail.{suffix}"));
+        }
+    }
+    for domain in direct_domains {
+        if let Some(domain) = normalize_direct_domain(domain) {
+            if let Some(stripped) = domain.strip_prefix("+.") {
+                items.push(format!("+.{stripped}"));
+            } else {
+                items.push(domain);
+            }
+        }
+    }
+    items.sort();
+    items.dedup();
+    items
+}
+
+fn build_tailscale_direct_rules(
+    tailnet_suffixes: &[String],
+    direct_domains: &[String],
+) -> Vec<String> {
+    let mut rules: Vec<String> = TAILSCALE_BASE_DIRECT_RULES
+        .iter()
+        .map(|s| (*s).to_string())
+        .collect();
+    for suffix in tailnet_suffixes {
+        if let Some(suffix) = normalize_direct_domain(suffix) {
+            rules.push(format!("DOMAIN-SUFFIX,tail.{suffix},DIRECT"));
+        }
+    }
+    for domain in direct_domains {
+        if let Some(domain) = normalize_direct_domain(domain) {
+            if let Some(stripped) = domain.strip_prefix("+.") {
+                rules.push(format!("DOMAIN-SUFFIX,{stripped},DIRECT"));
+            } else {
+                rules.push(format!("DOMAIN,{domain},DIRECT"));
+            }
+        }
+    }
+    rules.sort();
+    rules.dedup();
+    rules
+}
+
+fn build_tailscale_managed_state(
+    tailnet_suffixes: &[String],
+    direct_domains: &[String],
+    route_exclude_address_add: &[String],
+) -> ManagedTailscaleCompat {
+    let mut route_exclude_address: Vec<String> = TAILSCALE_ROUTE_EXCLUDES
+        .iter()
+        .map(|cidr| (*cidr).to_string())
+        .collect();
+    route_exclude_address.extend(route_exclude_address_add.iter().cloned());
+    route_exclude_address.sort();
+    route_exclude_address.dedup();
+
+    ManagedTailscaleCompat {
+        fake_ip_filter: build_tailscale_fake_ip_bypass(tailnet_suffixes, direct_domains),
+        route_exclude_address,
+        rules: build_tailscale_direct_rules(tailnet_suffixes, direct_domains),
+    }
+}
+
+fn build_system_proxy_bypass_entries(
+    tailnet_suffixes: &[String],
+    direct_domains: &[String],
+) -> Vec<String> {
+    let mut items: Vec<String> = TAILSCALE_SYSTEM_PROXY_BYPASS_BASE
+        .iter()
+        .map(|s| (*s).to_string())
+        .collect();
+
+    for suffix in tailnet_suffixes {
+        if let Some(suffix) = normalize_direct_domain(suffix) {
+            items.push(format!("*.tail.{suffix}"));
+        }
+    }
+
+    for domain in direct_domains {
+        if let Some(domain) = normalize_direct_domain(domain) {
+            if let Some(stripped) = domain.strip_prefix("+.") {
+                items.push(format!("*.{stripped}"));
+            } else {
+                items.push(domain);
+            }
+        }
+    }
+
+    items.sort();
+    items.dedup();
+    items
+}
+
+fn normalize_proxy_bypass_token(value: &str) -> String {
+    value.trim().to_ascii_lowercase()
+}
+
+fn merge_proxy_bypass_entries(
+    current: Vec<String>,
+    previous_managed: &[String],
+    desired: &[String],
+) -> Vec<String> {
+    let stale: HashSet<String> = previous_managed
+        .iter()
+        .map(|item| normalize_proxy_bypass_token(item))
+        .collect();
+    let desired_normalized: HashSet<String> = desired
+        .iter()
+        .map(|item| normalize_proxy_bypass_token(item))
+        .collect();
+
+    let mut merged = Vec::new();
+    let mut seen = HashSet::new();
+
+    for item in current {
+        let normalized = normalize_proxy_bypass_token(&item);
+        if stale.contains(&normalized) {
+            continue;
+        }
+        if seen.insert(normalized) {
+            merged.push(item);
+        }
+    }
+
+    for item in desired {
+        let normalized = normalize_proxy_bypass_token(item);
+        if desired_normalized.contains(&normalized) && seen.insert(normalized) {
+            merged.push(item.clone());
+        }
+    }
+
+    merged
+}
+
+fn derive_system_proxy_bypass_entries_from_managed(
+    managed: Option<&ManagedTailscaleCompat>,
+) -> Vec<String> {
+    managed
+        .map(|state| {
+            build_system_proxy_bypass_entries(
+                &derive_tailnet_suffixes_from_managed(state),
+                &derive_direct_domains_from_managed(state),
+            )
+        })
+        .unwrap_or_default()
+}
+
+fn sync_system_proxy_bypass(
+    previous_managed: Option<&ManagedTailscaleCompat>,
+    current_managed: Option<&ManagedTailscaleCompat>,
+) -> anyhow::Result<()> {
+    let previous_entries = derive_system_proxy_bypass_entries_from_managed(previous_managed);
+    let desired_entries = derive_system_proxy_bypass_entries_from_managed(current_managed);
+
+    if cfg!(target_os = "macos") {
+        return sync_macos_proxy_bypass_entries(&previous_entries, &desired_entries);
+    }
+
+    if cfg!(target_os = "windows") {
+        return sync_windows_proxy_bypass_entries(&previous_entries, &desired_entries);
+    }
+
+    Ok(())
+}
+
+fn list_macos_network_services() -> anyhow::Result<Vec<String>> {
+    let output = std::process::Command::new("networksetup")
+        .arg("-listallnetworkservices")
+        .output()
+        .context("failed to list macOS network services")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "networksetup -listallnetworkservices failed: {}",
+            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let services = stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter(|line| !line.starts_with("An asterisk"))
+        .filter(|line| !line.starts_with('*'))
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    Ok(services)
+}
+
+fn get_macos_proxy_bypass_domains(service: &str) -> anyhow::Result<Vec<String>> {
+    let output = std::process::Command::new("networksetup")
+        .args(["-getproxybypassdomains", service])
+        .output()
+        .with_context(|| format!("failed to get proxy bypass domains for {service}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "networksetup -getproxybypassdomains {} failed: {}",
+            service,
+            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let domains = stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    Ok(domains)
+}
+
+fn set_macos_proxy_bypass_domains(service: &str, domains: &[String]) -> anyhow::Result<()> {
+    let mut command = std::process::Command::new("networksetup");
+    command.arg("-setproxybypassdomains").arg(service);
+    for domain in domains {
+        command.arg(domain);
+    }
+
+    let output = command
+        .output()
+        .with_context(|| format!("failed to set proxy bypass domains for {service}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "networksetup -setproxybypassdomains {} failed: {}",
+            service,
+            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
+        ));
+    }
+
+    Ok(())
+}
+
+fn sync_macos_proxy_bypass_entries(
+    previous_managed: &[String],
+    desired: &[String],
+) -> anyhow::Result<()> {
+    for service in list_macos_network_services()? {
+        let current = get_macos_proxy_bypass_domains(&service)?;
+        let merged = merge_proxy_bypass_entries(current.clone(), previous_managed, desired);
+        if merged != current {
+            set_macos_proxy_bypass_domains(&service, &merged)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_windows_proxy_override(raw: &str) -> Vec<String> {
+    raw.lines()
+        .find(|line| line.contains("ProxyOverride"))
+        .and_then(|line| {
+            let mut parts = line.split_whitespace();
+            let _name = parts.next()?;
+            let _type = parts.next()?;
+            Some(parts.collect::<Vec<_>>().join(" "))
+        })
+        .map(|value| {
+            value
+                .split(';')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(ToOwned::to_owned)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default()
+}
+
+fn get_windows_proxy_override_entries() -> anyhow::Result<Vec<String>> {
+    let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+    let output = std::process::Command::new("reg")
+        .args(["query", key, "/v", "ProxyOverride"])
+        .output()
+        .context("failed to query Windows proxy override list")?;
+
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+
+    Ok(parse_windows_proxy_override(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+fn set_windows_proxy_override_entries(entries: &[String]) -> anyhow::Result<()> {
+    let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+    let value = entries.join(";");
+    let output = std::process::Command::new("reg")
+        .args([
+            "add",
+            key,
+            "/v",
+            "ProxyOverride",
+            "/t",
+            "REG_SZ",
+            "/d",
+            &value,
+            "/f",
+        ])
+        .output()
+        .context("failed to update Windows proxy override list")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "reg add ProxyOverride failed: {}",
+            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
+        ));
+    }
+
+    Ok(())
+}
+
+fn sync_windows_proxy_bypass_entries(
+    previous_managed: &[String],
+    desired: &[String],
+) -> anyhow::Result<()> {
+    let current = get_windows_proxy_override_entries()?;
+    let merged = merge_proxy_bypass_entries(current.clone(), previous_managed, desired);
+    if merged != current {
+        set_windows_proxy_override_entries(&merged)?;
+    }
+    Ok(())
+}
+
+fn remove_tailscale_managed_items(
+    merged: &mut mihomo_core::ClashConfig,
+    managed: &ManagedTailscaleCompat,
+) {
+    use serde_yaml::Value;
+    use std::collections::HashSet;
+
+    let managed_filters: HashSet<&str> =
+        managed.fake_ip_filter.iter().map(String::as_str).collect();
+    if let Some(filters) = merged
+        .extra
+        .get_mut("dns")
+        .and_then(|v| v.as_mapping_mut())
+        .and_then(|map| map.get_mut(Value::String("fake-ip-filter".to_string())))
+        .and_then(|value| value.as_sequence_mut())
+    {
+        filters.retain(|item| match item.as_str() {
+            Some(value) => !managed_filters.contains(value),
+            None => true,
+        });
+    }
+
+    let managed_route_excludes: HashSet<&str> = managed
+        .route_exclude_address
+        .iter()
+        .map(String::as_str)
+        .collect();
+    if let Some(excludes) = merged
+        .extra
+        .get_mut("tun")
+        .and_then(|v| v.as_mapping_mut())
+        .and_then(|map| map.get_mut(Value::String("route-exclude-address".to_string())))
+        .and_then(|value| value.as_sequence_mut())
+    {
+        excludes.retain(|item| match item.as_str() {
+            Some(value) => !managed_route_excludes.contains(value),
+            None => true,
+        });
+    }
+
+    let managed_rules: HashSet<&str> = managed.rules.iter().map(String::as_str).collect();
+    merged
+        .rules
+        .retain(|rule| !managed_rules.contains(rule.as_str()));
+}
+
+fn apply_tailscale_compatibility(
+    merged: &mut mihomo_core::ClashConfig,
+    tailnet_suffixes: &[String],
+    direct_domains: &[String],
+) {
+    use serde_yaml::{Mapping, Value};
+    let fake_ip_bypass = build_tailscale_fake_ip_bypass(tailnet_suffixes, direct_domains);
+    let direct_rules = build_tailscale_direct_rules(tailnet_suffixes, direct_domains);
+
+    let dns_value = merged
+        .extra
+        .entry("dns".to_string())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+    if let Value::Mapping(dns_map) = dns_value {
+        let enhanced_key = Value::String("enhanced-mode".to_string());
+        let range_key = Value::String("fake-ip-range".to_string());
+        let mode_key = Value::String("fake-ip-filter-mode".to_string());
+        let filter_key = Value::String("fake-ip-filter".to_string());
+
+        let enhanced = dns_map
+            .get(&enhanced_key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+
+        if enhanced.eq_ignore_ascii_case("fake-ip") {
+            dns_map.insert(mode_key, Value::String("blacklist".to_string()));
+
+            let current_range = dns_map
+                .get(&range_key)
+                .and_then(|v| v.as_str())
+                .map(str::to_owned);
+
+            match current_range.as_deref() {
+                Some(range) if range.starts_with("198.18.") || range.starts_with("198.19.") => {
+                    dns_map.insert(range_key, Value::String(SAFE_FAKE_IP_RANGE.to_string()));
+                    warn!(
+                        previous = %range,
+                        replacement = %SAFE_FAKE_IP_RANGE,
+                        "rewrote fake-ip-range to avoid Tailscale conflict"
+                    );
+                }
+                None => {
+                    dns_map.insert(range_key, Value::String(SAFE_FAKE_IP_RANGE.to_string()));
+                    info!(
+                        value = %SAFE_FAKE_IP_RANGE,
+                        "set default fake-ip-range compatible with Tailscale"
+                    );
+                }
+                _ => {}
+            }
+
+            let filter_seq = dns_map
+                .entry(filter_key)
+                .or_insert_with(|| Value::Sequence(Vec::new()));
+            if let Value::Sequence(seq) = filter_seq {
+                for item in &fake_ip_bypass {
+                    if !seq.iter().any(|v| v.as_str() == Some(item.as_str())) {
+                        seq.push(Value::String(item.clone()));
+                        info!(value = %item, "auto-added Tailscale fake-ip bypass");
+                    }
+                }
+            }
+        }
+    }
+
+    let tun_value = merged
+        .extra
+        .entry("tun".to_string())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+    if let Value::Mapping(tun_map) = tun_value {
+        let key = Value::String("route-exclude-address".to_string());
+        let seq_value = tun_map
+            .entry(key)
+            .or_insert_with(|| Value::Sequence(Vec::new()));
+
+        if let Value::Sequence(seq) = seq_value {
+            for cidr in TAILSCALE_ROUTE_EXCLUDES {
+                if !seq.iter().any(|v| v.as_str() == Some(cidr)) {
+                    seq.push(Value::String(cidr.to_string()));
+                    info!(value = %cidr, "auto-added Tailscale tun route exclusion");
+                }
+            }
+        }
+    }
+
+    let mut prefixed_rules = Vec::new();
+    for rule in &direct_rules {
+        if !merged.rules.iter().any(|existing| existing == rule) {
+            prefixed_rules.push(rule.clone());
+        }
+    }
+    if !prefixed_rules.is_empty() {
+        prefixed_rules.extend(std::mem::take(&mut merged.rules));
+        merged.rules = prefixed_rules;
+    }
+}
+
+fn has_fallback_rule(cfg: &mihomo_core::ClashConfig) -> bool {
+    cfg.rules.iter().any(|rule| {
+        let normalized = rule.trim().to_ascii_uppercase();
+        normalized.starts_with("MATCH,")
+            || normalized.starts_with("FINAL,")
+            || normalized.starts_with("IP-CIDR,0.0.0.0/0,")
+            || normalized.starts_with("IP-CIDR6,::/0,")
+    })
+}
+
+fn ensure_fallback_match_rule(cfg: &mut mihomo_core::ClashConfig, via: &str) {
+    if has_fallback_rule(cfg) {
+        return;
+    }
+    cfg.rules.push(format!("MATCH,{via}"));
+}
+
+fn apply_mode_override(cfg: &mut mihomo_core::ClashConfig, mode: ConfigMode) {
+    cfg.extra
+        .insert("mode".to_string(), Value::String(mode.as_str().to_string()));
+}
+
+fn apply_tun_enabled(cfg: &mut mihomo_core::ClashConfig, enabled: bool) {
+    use serde_yaml::{Mapping, Value};
+
+    let tun_value = cfg
+        .extra
+        .entry("tun".to_string())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if let Value::Mapping(tun_map) = tun_value {
+        tun_map.insert(Value::String("enable".to_string()), Value::Bool(enabled));
+    }
+}
+
+fn apply_sniffer_preset(cfg: &mut mihomo_core::ClashConfig, preset: SnifferPreset) {
+    match preset {
+        SnifferPreset::Off => {
+            cfg.extra.shift_remove("sniffer");
+        }
+        SnifferPreset::Tun => {
+            let sniffer = serde_yaml::from_str::<Value>(
+                r#"
+enable: true
+force-dns-mapping: true
+parse-pure-ip: true
+override-destination: false
+sniff:
+  HTTP:
+    ports: [80, 8080-8880]
+    override-destination: true
+  TLS:
+    ports: [443, 8443]
+  QUIC:
+    ports: [443, 8443]
+"#,
+            )
+            .expect("valid built-in sniffer preset");
+            cfg.extra.insert("sniffer".to_string(), sniffer);
+        }
+    }
+}
+
+async fn sync_clash_verge_source_configs(
+    paths: &AppPaths,
+    merged: &mihomo_core::ClashConfig,
+) -> anyhow::Result<()> {
+    use serde_yaml::Value;
+
+    let dns_path = paths
+        .detected_clash_verge_dns_config_path()
+        .ok_or_else(|| {
+            anyhow!("--sync-to-clash-verge-sources requested, but dns_config.yaml was not detected")
+        })?;
+    let merge_path = paths
+        .detected_clash_verge_profile_merge_path()
+        .ok_or_else(|| {
+            anyhow!(
+                "--sync-to-clash-verge-sources requested, but profiles/Merge.yaml was not detected"
+            )
+        })?;
+
+    let dns_source = fs::read_to_string(&dns_path)
+        .await
+        .with_context(|| format!("failed to read {}", dns_path.display()))?;
+    let merge_source = fs::read_to_string(&merge_path)
+        .await
+        .with_context(|| format!("failed to read {}", merge_path.display()))?;
+
+    let mut dns_doc: Value = serde_yaml::from_str(&dns_source)
+        .with_context(|| format!("failed to parse {}", dns_path.display()))?;
+    let mut merge_doc: Value = serde_yaml::from_str(&merge_source)
+        .with_context(|| format!("failed to parse {}", merge_path.display()))?;
+
+    let merged_dns = merged.extra.get("dns").cloned();
+    let merged_tun = merged.extra.get("tun").cloned();
+    let merged_hosts = merged.extra.get("hosts").cloned();
+    let merged_mode = merged.extra.get("mode").cloned();
+    let merged_sniffer = merged.extra.get("sniffer").cloned();
+
+    if let Some(ref src_dns) = merged_dns {
+        let dns_map = ensure_mapping_entry(&mut dns_doc, "dns");
+        if let Some(src_map) = src_dns.as_mapping() {
+            copy_mapping_key(src_map, dns_map, "enhanced-mode");
+            copy_mapping_key(src_map, dns_map, "fake-ip-range");
+            copy_mapping_key(src_map, dns_map, "fake-ip-filter-mode");
+            copy_mapping_key(src_map, dns_map, "fake-ip-filter");
+        }
+    }
+
+    if let Some(ref src_tun) = merged_tun {
+        let tun_map = ensure_mapping_entry(&mut dns_doc, "tun");
+        if let Some(src_map) = src_tun.as_mapping() {
+            copy_mapping_key(src_map, tun_map, "enable");
+            copy_mapping_key(src_map, tun_map, "route-exclude-address");
+        }
+    }
+
+    if let Some(ref src_dns) = merged_dns {
+        let dns_map = ensure_mapping_entry(&mut merge_doc, "dns");
+        if let Some(src_map) = src_dns.as_mapping() {
+            copy_mapping_key(src_map, dns_map, "fake-ip-filter");
+        }
+    }
+
+    if let Some(ref src_tun) = merged_tun {
+        let tun_map = ensure_mapping_entry(&mut merge_doc, "tun");
+        if let Some(src_map) = src_tun.as_mapping() {
+            copy_mapping_key(src_map, tun_map, "enable");
+            copy_mapping_key(src_map, tun_map, "route-exclude-address");
+        }
+    }
+
+    if let Some(src_mode) = merged_mode {
+        let root_map = ensure_root_mapping(&mut merge_doc);
+        root_map.insert(Value::String("mode".to_string()), src_mode);
+    }
+
+    {
+        let root_map = ensure_root_mapping(&mut merge_doc);
+        let key = Value::String("sniffer".to_string());
+        if let Some(src_sniffer) = merged_sniffer {
+            root_map.insert(key, src_sniffer);
+        } else {
+            root_map.remove(&key);
+        }
+    }
+
+    {
+        let rules_value = ensure_root_mapping(&mut merge_doc)
+            .entry(Value::String("rules".to_string()))
+            .or_insert_with(|| Value::Sequence(Vec::new()));
+        if let Value::Sequence(seq) = rules_value {
+            seq.clear();
+            seq.extend(merged.rules.iter().cloned().map(Value::String));
+        }
+    }
+
+    if let Some(src_hosts) = merged_hosts {
+        let root_map = ensure_root_mapping(&mut merge_doc);
+        root_map.insert(Value::String("hosts".to_string()), src_hosts);
+    }
+
+    storage::ensure_writable(&dns_path)?;
+    fs::write(&dns_path, serde_yaml::to_string(&dns_doc)?)
+        .await
+        .with_context(|| format!("failed to write {}", dns_path.display()))?;
+    fs::write(&merge_path, serde_yaml::to_string(&merge_doc)?)
+        .await
+        .with_context(|| format!("failed to write {}", merge_path.display()))?;
+
+    println!("synced Clash Verge source config {}", dns_path.display());
+    println!("synced Clash Verge source config {}", merge_path.display());
+
+    Ok(())
+}
+
+async fn save_node_liveness(liveness: &NodeLiveness, path: &Path) {
+    if storage::is_read_only() {
+        return;
+    }
+    if let Err(err) = liveness.save(path).await {
+        warn!(error = %format!("{:#}", err), "failed to save node liveness");
+    }
+}
+
+/// Latest delay per proxy from the controller's `/proxies` history, in ms; 0 means the last
+/// probe failed. Proxies that were never probed are left out.
+async fn fetch_proxy_delays(
+    merged: &mihomo_core::ClashConfig,
+) -> anyhow::Result<HashMap<String, u64>> {
+    let base = controller_endpoint(merged)
+        .ok_or_else(|| anyhow!("merged config does not define external-controller"))?;
+    let secret = merged
+        .extra
+        .get("secret")
+        .and_then(Value::as_str)
+        .unwrap_or("");
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?;
+    let mut req = client.get(format!("{}/proxies", base));
+    if !secret.is_empty() {
+        req = req.bearer_auth(secret);
+    }
+    let body: serde_json::Value = req.send().await?.error_for_status()?.json().await?;
+    Ok(latest_delays(&body))
+}
+
+fn latest_delays(body: &serde_json::Value) -> HashMap<String, u64> {
+    let Some(proxies) = body.get("proxies").and_then(|p| p.as_object()) else {
+        return HashMap::new();
+    };
+    proxies
+        .iter()
+        .filter_map(|(name, proxy)| {
+            let last = proxy.get("history")?.as_array()?.last()?;
+            Some((name.clone(), last.get("delay")?.as_u64()?))
+        })
+        .collect()
+}
+
+async fn reload_clash_verge_runtime(
+    merged: &mihomo_core::ClashConfig,
+    runtime_paths: &[PathBuf],
+) -> anyhow::Result<()> {
+    use serde_json::json;
+    use serde_yaml::Value;
+
+    let external_controller = merged
+        .extra
+        .get("external-controller")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("merged config does not define external-controller"))?;
+    let (host, port) = parse_host_port(external_controller).ok_or_else(|| {
+        anyhow!(
+            "invalid external-controller value '{}' (expected host:port)",
+            external_controller
+        )
+    })?;
+
+    let host = normalize_controller_host(&host);
+    let secret = merged
+        .extra
+        .get("secret")
+        .and_then(Value::as_str)
+        .unwrap_or("");
+
+    let runtime_path = runtime_paths
+        .iter()
+        .find(|path| path.file_name().and_then(|name| name.to_str()) == Some("config.yaml"))
+        .or_else(|| runtime_paths.first())
+        .ok_or_else(|| anyhow!("no synced Clash Verge runtime path available for reload"))?;
+
+    let client = reqwest::Client::builder().build()?;
+    let version_url = format!("http://{}:{}/version", host, port);
+    let mut version_req = client.get(&version_url);
+    if !secret.is_empty() {
+        version_req = version_req.bearer_auth(secret);
+    }
+    version_req.send().await?.error_for_status()?;
+
+    let reload_url = format!("http://{}:{}/configs", host, port);
+    let mut reload_req = client.put(&reload_url).json(&json!({
+        "path": runtime_path.display().to_string(),
+    }));
+    if !secret.is_empty() {
+        reload_req = reload_req.bearer_auth(secret);
+    }
+    reload_req.send().await?.error_for_status()?;
+
+    println!("reloaded Clash Verge runtime via {}", reload_url);
+    Ok(())
+}
+
+fn ensure_root_mapping(doc: &mut serde_yaml::Value) -> &mut serde_yaml::Mapping {
+    if !doc.is_mapping() {
+        *doc = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+    }
+    doc.as_mapping_mut().expect("mapping just initialized")
+}
+
+fn ensure_mapping_entry<'a>(
+    doc: &'a mut serde_yaml::Value,
+    key: &str,
+) -> &'a mut serde_yaml::Mapping {
+    use serde_yaml::{Mapping, Value};
+
+    let root = ensure_root_mapping(doc);
+    let entry = root
+        .entry(Value::String(key.to_string()))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if !entry.is_mapping() {
+        *entry = Value::Mapping(Mapping::new());
+    }
+    entry.as_mapping_mut().expect("mapping just initialized")
+}
+
+fn copy_mapping_key(src: &serde_yaml::Mapping, dst: &mut serde_yaml::Mapping, key: &str) {
+    let yaml_key = serde_yaml::Value::String(key.to_string());
+    if let Some(value) = src.get(&yaml_key) {
+        dst.insert(yaml_key, value.clone());
+    }
+}
+
+fn resolve_template_path(paths: &AppPaths, provided: &Path) -> PathBuf {
+    if provided.is_absolute() {
+        provided.to_path_buf()
+    } else if let Some(named) = provided
+        .to_str()
+        .filter(|name| is_template_name(name))
+        .map(|name| paths.named_template_path(name))
+        .filter(|path| path.exists())
+    {
+        named
+    } else {
+        let candidate = paths.templates_dir().join(provided);
+        if candidate.exists() {
+            candidate
+        } else {
+            provided.to_path_buf()
+        }
+    }
+}
+
+fn resolve_base_path(paths: &AppPaths, provided: &Path) -> PathBuf {
+    if provided.is_absolute() {
+        provided.to_path_buf()
+    } else {
+        let candidate = paths.config_dir().join(provided);
+        if candidate.exists() {
+            candidate
+        } else {
+            provided.to_path_buf()
+        }
+    }
+}
+
+const DEFAULT_DEV_RULE_VIA: &str = "Proxy";
+
+fn resolve_dev_rules_via(via: &str, default_via: &str, cfg: &mihomo_core::ClashConfig) -> String {
+    // If the requested via exists as a group or proxy, use it as-is.
+    let group_names = cfg.proxy_group_names();
+    let proxy_names = cfg.proxy_names();
+    if group_names.iter().any(|n| n == via) || proxy_names.iter().any(|n| n == via) {
+        return via.to_string();
+    }
+
+    // If the user explicitly set a via different from our default, respect it even if missing.
+    // This preserves explicit intent; mihomo will surface the error if it's invalid.
+    if via != default_via {
+        return via.to_string();
+    }
+
+    // Prefer common selector name if present.
+    let common = "🚀 节点选择";
+    if group_names.iter().any(|n| n == common) {
+        return common.to_string();
+    }
+
+    // Otherwise pick the first available group, then first proxy, else last-resort DIRECT.
+    if let Some(first_group) = group_names.first() {
+        return first_group.clone();
+    }
+    if let Some(first_proxy) = proxy_names.first() {
+        return first_proxy.clone();
+    }
+    "DIRECT".to_string()
+}
+
+// Built-in developer/AI endpoints considered proxy-worthy.
+// Tuple format: (rule kind, target)
+// - Use DOMAIN for exact host matches
+// - Use DOMAIN-SUFFIX for suffix matches
+const DEV_RULE_TARGETS: &[(&str, &str)] = &[
+    // Git & code hosting
+    ("DOMAIN-SUFFIX", "api.github.com"),
+    ("DOMAIN-SUFFIX", "github.com"),
+    ("DOMAIN-SUFFIX", "github.dev"),
+    ("DOMAIN-SUFFIX", "githubassets.com"),
+    ("DOMAIN-SUFFIX", "githubusercontent.com"),
+    ("DOMAIN-SUFFIX", "raw.githubusercontent.com"),
+    ("DOMAIN-SUFFIX", "codeload.github.com"),
+    ("DOMAIN-SUFFIX", "release-assets.githubusercontent.com"),
+    ("DOMAIN-SUFFIX", "gitlab.com"),
+    ("DOMAIN-SUFFIX", "bitbucket.org"),
+    // Language ecosystems / registries
+    ("DOMAIN-SUFFIX", "registry.npmjs.org"),
+    ("DOMAIN-SUFFIX", "registry.yarnpkg.com"),
+    ("DOMAIN-SUFFIX", "registry.npmjs.com"),
+    ("DOMAIN-SUFFIX", "nodejs.org"),
+    ("DOMAIN-SUFFIX", "pypi.org"),
+    ("DOMAIN-SUFFIX", "files.pythonhosted.org"),
+    ("DOMAIN-SUFFIX", "pythonhosted.org"),
+    ("DOMAIN-SUFFIX", "crates.io"),
+    ("DOMAIN-SUFFIX", "index.crates.io"),
+    ("DOMAIN-SUFFIX", "static.crates.io"),
+    ("DOMAIN-SUFFIX", "rubygems.org"),
+    ("DOMAIN-SUFFIX", "golang.org"),
+    ("DOMAIN-SUFFIX", "go.dev"),
+    ("DOMAIN-SUFFIX", "proxy.golang.org"),
+    ("DOMAIN-SUFFIX", "sum.golang.org"),
+    ("DOMAIN-SUFFIX", "pkg.go.dev"),
+    ("DOMAIN-SUFFIX", "golang.google.cn"),
+    ("DOMAIN-SUFFIX", "rust-lang.org"),
+    ("DOMAIN-SUFFIX", "static.rust-lang.org"),
+    ("DOMAIN-SUFFIX", "doc.rust-lang.org"),
+    // Kubernetes / cloud tooling
+    ("DOMAIN-SUFFIX", "k8s.io"),
+    ("DOMAIN-SUFFIX", "dl.k8s.io"),
+    ("DOMAIN-SUFFIX", "k3s.io"),
+    ("DOMAIN-SUFFIX", "vultr.com"),
+    ("DOMAIN-SUFFIX", "vultrstatus.com"),
+    // Containers / registries
+    ("DOMAIN-SUFFIX", "docker.com"),
+    ("DOMAIN-SUFFIX", "docker.io"),
+    ("DOMAIN-SUFFIX", "registry-1.docker.io"),
+    ("DOMAIN-SUFFIX", "ghcr.io"),
+    ("DOMAIN-SUFFIX", "gcr.io"),
+    ("DOMAIN-SUFFIX", "pkg.dev"),
+    ("DOMAIN-SUFFIX", "quay.io"),
+    // Nix infra
+    ("DOMAIN", "cache.nixos.org"),
+    ("DOMAIN-SUFFIX", "channels.nixos.org"),
+    ("DOMAIN-SUFFIX", "releases.nixos.org"),
+    ("DOMAIN-SUFFIX", "nixos.org"),
+    ("DOMAIN-SUFFIX", "nix.dev"),
+    ("DOMAIN-SUFFIX", "cachix.org"),
+    ("DOMAIN-SUFFIX", "flakehub.com"),
+    ("DOMAIN-SUFFIX", "determinate.systems"),
+    // AI APIs
+    ("DOMAIN-SUFFIX", "api.openai.com"),
+    ("DOMAIN-SUFFIX", "api.anthropic.com"),
+    ("DOMAIN-SUFFIX", "claude.ai"),
+    ("DOMAIN-SUFFIX", "platform.claude.com"),
+    ("DOMAIN-SUFFIX", "anthropic.com"),
+    ("DOMAIN-SUFFIX", "openai.com"),
+    ("DOMAIN-SUFFIX", "chatgpt.com"),
+    ("DOMAIN-SUFFIX", "openrouter.ai"),
+    ("DOMAIN-SUFFIX", "ai.google.dev"),
+    ("DOMAIN-SUFFIX", "generativelanguage.googleapis.com"),
+    ("DOMAIN-SUFFIX", "gemini.google.com"),
+    ("DOMAIN-SUFFIX", "cursor.com"),
+    ("DOMAIN-SUFFIX", "cursor.sh"),
+];
+
+fn build_dev_rules(via: &str) -> Vec<String> {
+    DEV_RULE_TARGETS
+        .iter()
+        .map(|(kind, target)| format!("{kind},{target},{via}"))
+        .collect()
+}
+
+fn domain_matches_rule(kind: &str, target: &str, domain: &str) -> bool {
+    let d = domain.to_ascii_lowercase();
+    let t = target.to_ascii_lowercase();
+    match kind {
+        "DOMAIN" => d == t,
+        "DOMAIN-SUFFIX" => d == t || d.ends_with(&format!(".{t}")),
+        "DOMAIN-KEYWORD" => d.contains(&t),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetch_client_accepts_proxy_schemes() {
+        assert!(build_fetch_client("ua", None, 10, None).is_ok());
+        assert!(build_fetch_client("ua", Some("direct"), 10, None).is_ok());
+        assert!(build_fetch_client("ua", Some("socks5://127.0.0.1:7890"), 10, None).is_ok());
+        assert!(build_fetch_client("ua", Some("http://127.0.0.1:7890"), 10, None).is_ok());
+        assert!(build_fetch_client("ua", Some("ftp://127.0.0.1:21"), 10, None).is_err());
+    }
+
+    #[test]
+    fn source_table_counts_kept_and_filtered_nodes() {
+        let merged: mihomo_core::ClashConfig = serde_yaml::from_str(
+            "proxies:\n  - {name: a1, type: http, server: s, port: 1}\n  - {name: b1, type: http, server: s, port: 1}\n",
+        )
+        .unwrap();
+        let rows = vec![
+            SourceRow {
+                name: "alpha".into(),
+                origin: "network",
+                proxies: vec!["a1".into(), "a2".into()],
+                age: Some(chrono::Duration::hours(3)),
+                remaining: Some(5 * 1024 * 1024 * 1024),
+                changes: NodeChanges::default(),
+            },
+            SourceRow {
+                name: "beta".into(),
+                origin: "cache",
+                proxies: vec!["b1".into()],
+                age: None,
+                remaining: None,
+                changes: NodeChanges {
+                    removed: vec!["b2".into()],
+                    ..Default::default()
+                },
+            },
+        ];
+        let table = render_source_table(&rows, &merged);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            lines[0],
+            "SUBSCRIPTION  SOURCE   AGE  NODES  FILTERED  REMAINING"
+        );
+        assert_eq!(
+            lines[1],
+            "alpha         network  3h   1      1         5.00 GiB"
+        );
+        assert_eq!(lines[2], "beta          cache    -    1      0         -");
+        assert_eq!(
+            lines[3],
+            "beta: 0 added, 1 removed, 0 renamed since the last merge"
+        );
+        assert_eq!(lines[4], "  - b2");
+        assert!(render_source_table(&[], &merged).is_empty());
+    }
+
+    #[test]
+    fn read_only_is_accepted_before_or_after_the_subcommand() {
+        for argv in [
+            ["mihomo-cli", "--read-only", "merge", "--stdout"],
+            ["mihomo-cli", "merge", "--stdout", "--read-only"],
+        ] {
+            let cli = Cli::try_parse_from(argv).unwrap();
+            assert!(cli.read_only);
+        }
+        assert!(
+            !Cli::try_parse_from(["mihomo-cli", "merge"])
+                .unwrap()
+                .read_only
+        );
+    }
+
+    #[test]
+    fn preset_flags_are_overridden_by_command_line() {
+        let preset = vec![
+            "--output".to_string(),
+            "/etc/mihomo/config.yaml".to_string(),
+            "--header".to_string(),
+            "-s".to_string(),
+            "https://a.example/sub".to_string(),
+        ];
+        let cli_args = vec![
+            "--preset".to_string(),
+            "router".to_string(),
+            "--output".to_string(),
+            "/tmp/out.yaml".to_string(),
+            "--header".to_string(),
+        ];
+        let argv = merge_argv(&preset, &cli_args);
+        let Commands::Merge(args) = Cli::try_parse_from(&argv).unwrap().command else {
+            panic!("expected merge");
+        };
+        assert_eq!(args.output, Some(PathBuf::from("/tmp/out.yaml")));
+        assert!(args.header);
+        assert_eq!(
+            args.subscriptions,
+            vec!["https://a.example/sub".to_string()]
+        );
+        assert_eq!(args.preset.as_deref(), Some("router"));
+
+        let nested = vec!["--preset=laptop".to_string()];
+        assert!(reject_nested_bundles(&nested, "presets").is_err());
+    }
+
+    #[tokio::test]
+    async fn post_hook_sees_merge_env_and_failures_warn() {
+        let merged: mihomo_core::ClashConfig =
+            serde_yaml::from_str("proxies: []\nrules: [\"MATCH,DIRECT\"]\n").unwrap();
+        let env = post_hook_env(
+            Some(Path::new("/tmp/out.yaml")),
+            &merged,
+            &["main".to_string()],
+            0,
+        );
+        assert!(env.contains(&("MIHOMO_RULE_COUNT", "1".to_string())));
+
+        let mut warnings = Warnings::default();
+        run_post_hook(
+            r#"test "$MIHOMO_CONFIG_PATH" = /tmp/out.yaml"#,
+            &env,
+            &mut warnings,
+        )
+        .await;
+        assert!(warnings.as_slice().is_empty());
+        run_post_hook("exit 3", &env, &mut warnings).await;
+        assert_eq!(warnings.as_slice().len(), 1);
+        assert_eq!(warnings.as_slice()[0].kind, WarningKind::Hook);
+    }
+
+    #[test]
+    fn lazy_resources_follow_geo_references() {
+        let cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            r#"
+rules: ["DOMAIN-SUFFIX,example.com,DIRECT", "MATCH,DIRECT"]
+"#,
+        )
+        .unwrap();
+        assert!(referenced_resources(&cfg).is_empty());
+
+        let cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            r#"
+geodata-mode: true
+dns:
+  nameserver-policy:
+    "geosite:cn": [223.5.5.5]
+rules: ["GEOIP,CN,DIRECT", "MATCH,Proxy"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(referenced_resources(&cfg), vec!["geoip.dat", "geosite.dat"]);
+
+        let cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            "dns:\n  fallback-filter:\n    geoip: true\nrules: [\"MATCH,DIRECT\"]\n",
+        )
+        .unwrap();
+        assert_eq!(referenced_resources(&cfg), vec!["Country.mmdb"]);
+    }
+
+    #[test]
+    fn show_selects_one_section() {
+        let cfg = mihomo_core::ClashConfig::from_yaml_str(
+            r#"
+mixed-port: 7890
+proxies:
+  - { name: a, type: ss, server: a.example, port: 1, cipher: aes-128-gcm, password: x }
+proxy-groups:
+  - { name: PROXY, type: select, proxies: [a] }
+rules:
+  - MATCH,PROXY
+"#,
+        )
+        .unwrap();
+        let groups = select_section(&cfg, Some(ShowSection::ProxyGroups)).unwrap();
+        assert_eq!(groups[0]["name"], Value::from("PROXY"));
+        let rules = select_section(&cfg, Some(ShowSection::Rules)).unwrap();
+        assert_eq!(rules, Value::Sequence(vec![Value::from("MATCH,PROXY")]));
+        assert!(select_section(&cfg, Some(ShowSection::Dns)).is_err());
+        assert_eq!(select_section(&cfg, None).unwrap()["mixed-port"], 7890);
+    }
+
+    #[test]
+    fn resource_problem_checks_magic_bytes() {
+        let mut mmdb = vec![0u8; 64];
+        mmdb.extend_from_slice(MMDB_METADATA_MARKER);
+        mmdb.extend_from_slice(&[0xe9, 0x5b]);
+        assert_eq!(resource_problem("Country.mmdb", &mmdb), None);
+        assert!(resource_problem("Country.mmdb", b"<html>rate limited</html>").is_some());
+        assert!(resource_problem("Country.mmdb", b"").is_some());
+        assert_eq!(resource_problem("geosite.dat", &[0x0a, 0x10, b'c']), None);
+        assert!(resource_problem("geoip.dat", b"Not Found").is_some());
+    }
+
+    #[test]
+    fn interval_units() {
+        assert_eq!(parse_interval("90").unwrap().as_secs(), 90);
+        assert_eq!(parse_interval("30m").unwrap().as_secs(), 1800);
+        assert_eq!(parse_interval("6h").unwrap().as_secs(), 21_600);
+        assert_eq!(parse_interval("1d").unwrap().as_secs(), 86_400);
+        assert!(parse_interval("0h").is_err());
+        assert!(parse_interval("5w").is_err());
+        assert!(parse_interval("h").is_err());
+        assert!(parse_interval("300000000000000d").is_err());
+    }
+
+    #[test]
+    fn content_range_must_continue_the_partial_file() {
+        assert_eq!(parse_content_range("bytes 100-199/200", 100), Some(200));
+        assert_eq!(parse_content_range("bytes 0-99/200", 100), None);
+        assert_eq!(parse_content_range("bytes 100-199/*", 100), None);
+        assert_eq!(parse_content_range("items 100-199/200", 100), None);
+    }
+
+    #[test]
+    fn resume_validator_prefers_a_strong_etag() {
+        use reqwest::header::{HeaderMap, HeaderValue, ETAG, LAST_MODIFIED};
+
+        let modified = "Wed, 21 Oct 2026 07:28:00 GMT";
+        let mut headers = HeaderMap::new();
+        assert_eq!(resume_validator(&headers), None);
+        headers.insert(LAST_MODIFIED, HeaderValue::from_static(modified));
+        assert_eq!(resume_validator(&headers).as_deref(), Some(modified));
+        headers.insert(ETAG, HeaderValue::from_static("W/\"weak\""));
+        assert_eq!(resume_validator(&headers).as_deref(), Some(modified));
+        headers.insert(ETAG, HeaderValue::from_static("\"strong\""));
+        assert_eq!(resume_validator(&headers).as_deref(), Some("\"strong\""));
+    }
+
+    #[test]
+    fn profile_expands_to_merge_flags() {
+        let profile = storage::MergeProfile {
+            template: Some(PathBuf::from("router.yaml")),
+            subscriptions: vec!["main".to_string(), "backup".to_string()],
+            dev_rules_via: Some("Auto".to_string()),
+            output: Some(PathBuf::from("/etc/mihomo/config.yaml")),
+            args: vec!["--mode".to_string(), "global".to_string()],
+            ..Default::default()
+        };
+        let prefix = profile_argv(&profile).unwrap();
+        let cli_args = vec!["--output".to_string(), "/tmp/out.yaml".to_string()];
+        let Commands::Merge(args) = Cli::try_parse_from(merge_argv(&prefix, &cli_args))
+            .unwrap()
+            .command
+        else {
+            panic!("expected merge");
+        };
+        assert_eq!(args.template, Some(PathBuf::from("router.yaml")));
+        assert_eq!(args.only_subscriptions, vec!["main", "backup"]);
+        assert_eq!(args.dev_rules_via, "Auto");
+        assert_eq!(args.mode, ConfigMode::Global);
+        assert_eq!(args.output, Some(PathBuf::from("/tmp/out.yaml")));
+
+        let nested = storage::MergeProfile {
+            args: vec!["--profile".to_string(), "laptop".to_string()],
+            ..Default::default()
+        };
+        assert!(profile_argv(&nested).is_err());
+    }
+
+    #[test]
+    fn listeners_are_upserted_and_port_clashes_rejected() {
+        let mut merged: mihomo_core::ClashConfig = serde_yaml::from_str(
+            "mixed-port: 7890\nlisteners:\n  - { name: lan, type: socks, port: 1080 }\n  - { name: keep, type: http, port: 8080 }\n",
+        )
+        .unwrap();
+        let lan = ListenerDef {
+            name: "lan".to_string(),
+            kind: ListenerKind::Mixed,
+            port: 7891,
+            listen: "0.0.0.0".to_string(),
+            proxy: Some("DIRECT".to_string()),
+            udp: true,
+        };
+        inject_listeners(&mut merged, std::slice::from_ref(&lan)).unwrap();
+        let listeners = merged.extra["listeners"].as_sequence().unwrap();
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0]["name"], Value::from("keep"));
+        assert_eq!(listeners[1]["port"], Value::from(7891));
+        assert_eq!(listeners[1]["proxy"], Value::from("DIRECT"));
+
+        let clash = ListenerDef {
+            name: "other".to_string(),
+            port: 7890,
+            ..lan
+        };
+        let err = inject_listeners(&mut merged, &[clash]).unwrap_err();
+        assert!(err.to_string().contains("mixed-port"), "{err}");
+    }
+
+    #[test]
+    fn latest_delays_take_the_last_history_entry() {
+        let body = serde_json::json!({
+            "proxies": {
+                "HK 01": { "type": "Shadowsocks", "history": [
+                    { "time": "2026-10-01T10:00:00Z", "delay": 120 },
+                    { "time": "2026-10-01T10:05:00Z", "delay": 89 }
+                ] },
+                "JP 01": { "type": "Vmess", "history": [{ "time": "2026-10-01T10:05:00Z", "delay": 0 }] },
+                "US 01": { "type": "Trojan", "history": [] },
+                "DIRECT": { "type": "Direct" }
+            }
+        });
+        let delays = latest_delays(&body);
+        assert_eq!(delays.len(), 2);
+        assert_eq!(delays["HK 01"], 89);
+        assert_eq!(delays["JP 01"], 0);
+    }
+
+    #[test]
+    fn managed_hosts_override_template_hosts() {
+        let mut merged: mihomo_core::ClashConfig =
+            serde_yaml::from_str("hosts:\n  router.lan: 192.168.1.1\n  nas.lan: 192.168.1.2\n")
+                .unwrap();
+        let hosts = BTreeMap::from([
+            ("nas.lan".to_string(), vec!["10.0.0.2".to_string()]),
+            (
+                "git.corp".to_string(),
+                vec!["10.0.0.3".to_string(), "fd00::3".to_string()],
+            ),
+        ]);
+        inject_hosts(&mut merged, &hosts);
+        let out = &merged.extra["hosts"];
+        assert_eq!(out["router.lan"], Value::from("192.168.1.1"));
+        assert_eq!(out["nas.lan"], Value::from("10.0.0.2"));
+        assert_eq!(out["git.corp"].as_sequence().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn template_names_are_plain_file_stems() {
+        assert!(is_template_name("home"));
+        assert!(is_template_name("router-v2.1"));
+        assert!(!is_template_name("default"));
+        assert!(!is_template_name("../home"));
+        assert!(!is_template_name("home.yaml"));
+        assert!(!is_template_name(".hidden"));
+        assert!(!is_template_name(""));
+    }
+
+    #[test]
+    fn variant_flags_override_typed_flags() {
+        let variant = storage::MergeProfile {
+            template: Some(PathBuf::from("canary.yaml")),
+            ..Default::default()
+        };
+        let mut cli_args = vec![
+            "--template".to_string(),
+            "stable.yaml".to_string(),
+            "--variants".to_string(),
+            "stable,canary".to_string(),
+        ];
+        cli_args.extend(profile_argv(&variant).unwrap());
+        let Commands::Merge(args) = Cli::try_parse_from(merge_argv(&[], &cli_args))
+            .unwrap()
+            .command
+        else {
+            panic!("expected merge");
+        };
+        assert_eq!(args.template, Some(PathBuf::from("canary.yaml")));
+        assert_eq!(args.variants, vec!["stable", "canary"]);
+
+        assert_eq!(
+            variant_output_path(Path::new("/out/clash-verge.yaml"), "canary"),
+            PathBuf::from("/out/clash-verge.canary.yaml")
+        );
+        assert_eq!(
+            variant_output_path(Path::new("/out/config"), "canary"),
+            PathBuf::from("/out/config.canary")
+        );
+    }
+
+    #[test]
+    fn dev_rules_use_selected_via() {
+        let via = "MyProxy";
+        let rules = build_dev_rules(via);
+        assert!(rules
+            .iter()
+            .all(|rule| rule.ends_with(&format!(",{}", via))));
+        for prefix in [
+            "DOMAIN-SUFFIX,api.github.com,",
+            "DOMAIN-SUFFIX,github.com,",
+            "DOMAIN-SUFFIX,registry.npmjs.org,",
+            "DOMAIN-SUFFIX,pypi.org,",
+            "DOMAIN-SUFFIX,crates.io,",
+            "DOMAIN-SUFFIX,index.crates.io,",
+            "DOMAIN-SUFFIX,proxy.golang.org,",
+            "DOMAIN-SUFFIX,golang.google.cn,",
+            "DOMAIN-SUFFIX,rust-lang.org,",
+            "DOMAIN-SUFFIX,static.rust-lang.org,",
+            "DOMAIN-SUFFIX,k3s.io,",
+            "DOMAIN-SUFFIX,vultr.com,",
+            "DOMAIN-SUFFIX,api.openai.com,",
+            "DOMAIN-SUFFIX,api.anthropic.com,",
+            "DOMAIN-SUFFIX,claude.ai,",
+            "DOMAIN-SUFFIX,platform.claude.com,",
+            "DOMAIN-SUFFIX,anthropic.com,",
+            "DOMAIN-SUFFIX,openai.com,",
+            "DOMAIN-SUFFIX,chatgpt.com,",
+            "DOMAIN,cache.nixos.org,",
+            "DOMAIN-SUFFIX,channels.nixos.org,",
+            "DOMAIN-SUFFIX,cachix.org,",
+            "DOMAIN-SUFFIX,openrouter.ai,",
+            "DOMAIN-SUFFIX,dl.k8s.io,",
+        ] {
+            assert!(
+                rules.iter().any(|rule| rule.starts_with(prefix)),
+                "missing {prefix}"
+            );
+        }
+    }
+
+    #[test]
+    fn attach_group_appends_without_duplicates() {
+        use serde_yaml::Value;
+
+        let mut groups: Vec<Value> =
+            vec![
+                serde_yaml::from_str("name: \"BosLife\"\ntype: select\nproxies:\n  - A\n  - B\n")
+                    .unwrap(),
+            ];
+
+        let names = vec!["B".to_string(), "jp-vultr".to_string()];
+        assert!(attach_proxy_names_to_group(&mut groups, "BosLife", &names));
+
+        let map = groups[0].as_mapping().unwrap();
+        let seq = map
+            .get(Value::from("proxies"))
+            .and_then(|v| v.as_sequence())
+            .unwrap();
+        let items: Vec<_> = seq.iter().filter_map(|v| v.as_str()).collect();
+        assert_eq!(items, vec!["A", "B", "jp-vultr"]);
+    }
+
+    #[test]
+    fn parse_scutil_proxy_detects_enabled_entries() {
+        let raw = r#"
+<dictionary> {
+  HTTPEnable : 1
+  HTTPPort : 7897
+  HTTPProxy : 127.0.0.1
+  HTTPSEnable : 1
+  HTTPSPort : 7897
+  HTTPSProxy : 127.0.0.1
+  SOCKSEnable : 1
+  SOCKSPort : 7897
+  SOCKSProxy : 127.0.0.1
+}
+"#;
+
+        let entries = parse_scutil_proxy(raw);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].kind, "HTTP");
+        assert_eq!(entries[1].kind, "HTTPS");
+        assert_eq!(entries[2].kind, "SOCKS");
+    }
+
+    #[test]
+    fn parse_windows_proxy_query_detects_per_protocol_entries() {
+        let enable = r#"
+HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
+    ProxyEnable    REG_DWORD    0x1
+"#;
+        let server = r#"
+HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
+    ProxyServer    REG_SZ    http=127.0.0.1:7897;https=127.0.0.1:7897;socks=127.0.0.1:7897
+"#;
+
+        let entries = parse_windows_proxy_query(enable, server);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].kind, "HTTP");
+        assert_eq!(entries[1].kind, "HTTPS");
+        assert_eq!(entries[2].kind, "SOCKS");
+    }
+
+    #[test]
+    fn parse_windows_proxy_query_detects_single_wininet_proxy() {
+        let enable = r#"
+HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
+    ProxyEnable    REG_DWORD    0x00000001
+"#;
+        let server = r#"
+HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
+    ProxyServer    REG_SZ    127.0.0.1:7897
+"#;
+
+        let entries = parse_windows_proxy_query(enable, server);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].kind, "WININET");
+        assert_eq!(entries[0].host, "127.0.0.1");
+        assert_eq!(entries[0].port, "7897");
+    }
+
+    #[test]
+    fn split_list_items_handles_commas_spaces_and_newlines() {
+        assert_eq!(
+            split_list_items("foo.com, bar.com\nbaz.com"),
+            vec!["foo.com", "bar.com", "baz.com"]
+        );
+    }
+
+    #[test]
+    fn extract_current_clash_verge_subscription_url_prefers_current_uid() {
+        let profiles = ClashVergeProfiles {
+            current: Some("active".to_string()),
+            items: vec![
+                ClashVergeProfileItem {
+                    uid: Some("other".to_string()),
+                    url: Some("https://example.com/other.yaml".to_string()),
+                },
+                ClashVergeProfileItem {
+                    uid: Some("active".to_string()),
+                    url: Some("https://example.com/active.yaml".to_string()),
+                },
+            ],
+        };
+
+        assert_eq!(
+            extract_current_clash_verge_subscription_url(&profiles).as_deref(),
+            Some("https://example.com/active.yaml")
+        );
+    }
+
+    #[test]
+    fn infer_connection_path_prefers_local_proxy_for_default_mixed() {
+        let record = ConnectionRecord {
+            host: Some("chatgpt.com".to_string()),
+            inbound_name: Some("DEFAULT-MIXED".to_string()),
+            source_ip: Some("127.0.0.1".to_string()),
+            chains: vec!["BosLife".to_string()],
+        };
+
+        assert_eq!(
+            infer_connection_path(&[&record]),
+            "local proxy / DEFAULT-MIXED"
+        );
+    }
+
+    #[test]
+    fn tailscale_compatibility_rewrites_fake_ip_and_adds_exclusions() {
+        use serde_yaml::Value;
+
+        let raw = r#"
+dns:
+  enhanced-mode: fake-ip
+  fake-ip-range: 198.18.0.1/16
+  fake-ip-filter:
+    - "*.local"
+tun:
+  enable: true
+"#;
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(raw).unwrap();
+
+        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
+
+        let dns = cfg.extra.get("dns").and_then(|v| v.as_mapping()).unwrap();
+        assert_eq!(
+            dns.get(Value::String("fake-ip-range".into()))
+                .and_then(|v| v.as_str()),
+            Some(SAFE_FAKE_IP_RANGE)
+        );
+        let filters = dns
+            .get(Value::String("fake-ip-filter".into()))
+            .and_then(|v| v.as_sequence())
+            .unwrap();
+        for item in build_tailscale_fake_ip_bypass(&[String::from("zhsjf.cn")], &[]) {
+            assert!(filters.iter().any(|v| v.as_str() == Some(item.as_str())));
+        }
+
+        let tun = cfg.extra.get("tun").and_then(|v| v.as_mapping()).unwrap();
+        let excludes = tun
+            .get(Value::String("route-exclude-address".into()))
+            .and_then(|v| v.as_sequence())
+            .unwrap();
+        for cidr in TAILSCALE_ROUTE_EXCLUDES {
+            assert!(excludes.iter().any(|v| v.as_str() == Some(cidr)));
+        }
+    }
+
+    #[test]
+    fn tailscale_compatibility_deduplicates_entries() {
+        use serde_yaml::Value;
+
+        let raw = r#"
+dns:
+  enhanced-mode: fake-ip
+  fake-ip-range: 172.19.0.1/16
+  fake-ip-filter:
+    - +.tailscale.com
+tun:
+  route-exclude-address:
+    - 100.64.0.0/10
+"#;
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(raw).unwrap();
+
+        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
+        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
+
+        let dns = cfg.extra.get("dns").and_then(|v| v.as_mapping()).unwrap();
+        let filters = dns
+            .get(Value::String("fake-ip-filter".into()))
+            .and_then(|v| v.as_sequence())
+            .unwrap();
+        assert_eq!(
+            filters
+                .iter()
+                .filter(|v| v.as_str() == Some("+.tailscale.com"))
+                .count(),
+            1
+        );
+
+        let tun = cfg.extra.get("tun").and_then(|v| v.as_mapping()).unwrap();
+        let excludes = tun
+            .get(Value::String("route-exclude-address".into()))
+            .and_then(|v| v.as_sequence())
+            .unwrap();
+        assert_eq!(
+            excludes
+                .iter()
+                .filter(|v| v.as_str() == Some("100.64.0.0/10"))
+                .count(),
+            1
+        );
+        assert_eq!(
+            cfg.rules
+                .iter()
+                .filter(|rule| rule == &&"DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string())
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn tailscale_direct_rules_are_prefixed() {
+        let raw = r#"
+rules:
+  - MATCH,Proxy
+"#;
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(raw).unwrap();
+
+        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
+
+        assert_eq!(cfg.rules[0], "DOMAIN-SUFFIX,tail.zhsjf.cn,DIRECT");
+        assert_eq!(cfg.rules[1], "DOMAIN-SUFFIX,tailscale.com,DIRECT");
+        assert_eq!(cfg.rules[2], "DOMAIN-SUFFIX,ts.net,DIRECT");
+        assert_eq!(cfg.rules[3], "IP-CIDR,100.100.100.100/32,DIRECT");
+        assert_eq!(cfg.rules[4], "IP-CIDR,100.64.0.0/10,DIRECT");
+        assert_eq!(cfg.rules[5], "IP-CIDR6,fd7a:115c:a1e0::/48,DIRECT");
+        assert_eq!(cfg.rules[6], "MATCH,Proxy");
+    }
+
+    #[test]
+    fn tailscale_tailnet_suffixes_are_parameterized() {
+        assert_eq!(
+            build_tailscale_fake_ip_bypass(&[String::from("example.com")], &[]),
+            vec![
+                "+.tail.example.com".to_string(),
+                "+.tailscale.com".to_string(),
+                "+.ts.net".to_string()
+            ]
+        );
+        assert_eq!(
+            build_tailscale_direct_rules(&[String::from("example.com")], &[]),
+            vec![
+                "DOMAIN-SUFFIX,tail.example.com,DIRECT".to_string(),
+                "DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string(),
+                "DOMAIN-SUFFIX,ts.net,DIRECT".to_string(),
+                "IP-CIDR,100.100.100.100/32,DIRECT".to_string(),
+                "IP-CIDR,100.64.0.0/10,DIRECT".to_string(),
+                "IP-CIDR6,fd7a:115c:a1e0::/48,DIRECT".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn tailscale_direct_domains_are_parameterized() {
+        assert_eq!(
+            build_tailscale_fake_ip_bypass(
+                &[String::from("example.com")],
+                &[
+                    String::from("derp.zhsjf.cn"),
+                    String::from("+.corp.example.com")
+                ]
+            ),
+            vec![
+                "+.corp.example.com".to_string(),
+                "+.tail.example.com".to_string(),
+                "+.tailscale.com".to_string(),
+                "+.ts.net".to_string(),
+                "derp.zhsjf.cn".to_string()
+            ]
+        );
+        assert_eq!(
+            build_tailscale_direct_rules(
+                &[String::from("example.com")],
+                &[
+                    String::from("derp.zhsjf.cn"),
+                    String::from("+.corp.example.com")
+                ]
+            ),
+            vec![
+                "DOMAIN,derp.zhsjf.cn,DIRECT".to_string(),
+                "DOMAIN-SUFFIX,corp.example.com,DIRECT".to_string(),
+                "DOMAIN-SUFFIX,tail.example.com,DIRECT".to_string(),
+                "DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string(),
+                "DOMAIN-SUFFIX,ts.net,DIRECT".to_string(),
+                "IP-CIDR,100.100.100.100/32,DIRECT".to_string(),
+                "IP-CIDR,100.64.0.0/10,DIRECT".to_string(),
+                "IP-CIDR6,fd7a:115c:a1e0::/48,DIRECT".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn system_proxy_bypass_entries_include_tailnet_and_direct_domains() {
+        assert_eq!(
+            build_system_proxy_bypass_entries(
+                &[String::from("zhsjf.cn")],
+                &[
+                    String::from("airs.zhsjf.cn"),
+                    String::from("+.corp.example.com")
+                ]
+            ),
+            vec![
+                "*.corp.example.com".to_string(),
+                "*.tail.zhsjf.cn".to_string(),
+                "100.100.100.100".to_string(),
+                "100.64.0.0/10".to_string(),
+                "airs.zhsjf.cn".to_string(),
+            ]
+        );
+    }
 
-            if enhanced.eq_ignore_ascii_case("fake-ip") {
-                let filter_mode = dns_map
-                    .get(&mode_key)
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("blacklist");
+    #[test]
+    fn merge_proxy_bypass_entries_replaces_previous_managed_values() {
+        let current = vec![
+            "127.0.0.1".to_string(),
+            "mihomo.zhsjf.cn".to_string(),
+            "old.example.com".to_string(),
+        ];
+        let previous = vec!["mihomo.zhsjf.cn".to_string(), "old.example.com".to_string()];
+        let desired = vec!["mihomo.zhsjf.cn".to_string(), "airs.zhsjf.cn".to_string()];
 
-                if !filter_mode.eq_ignore_ascii_case("whitelist") {
-                    // Cover both the canonical FQDNs and resolver-expanded names
-                    // (when search domains are appended).
-                    let wanted = ["+.cluster.local", "*.cluster.local.*"];
+        assert_eq!(
+            merge_proxy_bypass_entries(current, &previous, &desired),
+            vec![
+                "127.0.0.1".to_string(),
+                "mihomo.zhsjf.cn".to_string(),
+                "airs.zhsjf.cn".to_string(),
+            ]
+        );
+    }
 
-                    let filter_seq = dns_map
-                        .entry(filter_key)
-                        .or_insert_with(|| Value::Sequence(Vec::new()));
+    #[test]
+    fn parse_windows_proxy_override_splits_semicolon_list() {
+        let raw = r#"
+HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
+    ProxyOverride    REG_SZ    localhost;<local>;100.64.0.0/10;100.100.100.100;airs.zhsjf.cn
+"#;
 
-                    if let Value::Sequence(seq) = filter_seq {
-                        for item in wanted {
-                            let exists = seq.iter().any(|v| v.as_str() == Some(item));
-                            if !exists {
-                                seq.push(Value::String(item.to_string()));
-                                info!(value = %item, "auto-added fake-ip bypass");
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        assert_eq!(
+            parse_windows_proxy_override(raw),
+            vec![
+                "localhost".to_string(),
+                "<local>".to_string(),
+                "100.64.0.0/10".to_string(),
+                "100.100.100.100".to_string(),
+                "airs.zhsjf.cn".to_string(),
+            ]
+        );
     }
 
-    if args.tailscale_compatible {
-        apply_tailscale_compatibility(
-            &mut merged,
-            &args.tailscale_tailnet_suffixes,
-            &args.tailscale_direct_domains,
+    #[test]
+    fn tailscale_managed_state_tracks_extra_route_excludes() {
+        let state = build_tailscale_managed_state(
+            &[String::from("example.com")],
+            &[String::from("derp.example.com")],
+            &[String::from("203.0.113.10/32")],
         );
-        app_cfg.managed_tailscale_compat = Some(build_tailscale_managed_state(
-            &args.tailscale_tailnet_suffixes,
-            &args.tailscale_direct_domains,
-            &args.route_exclude_address_add,
-        ));
-    } else if app_cfg.managed_tailscale_compat.is_some() {
-        app_cfg.managed_tailscale_compat = None;
+
+        assert!(state
+            .fake_ip_filter
+            .contains(&"+.tail.example.com".to_string()));
+        assert!(state
+            .rules
+            .contains(&"DOMAIN,derp.example.com,DIRECT".to_string()));
+        assert!(state
+            .route_exclude_address
+            .contains(&"203.0.113.10/32".to_string()));
     }
 
-    let fallback_via = resolve_dev_rules_via(&args.dev_rules_via, DEFAULT_DEV_RULE_VIA, &merged);
-    ensure_fallback_match_rule(&mut merged, &fallback_via);
+    #[test]
+    fn remove_tailscale_managed_items_cleans_previous_entries() {
+        use serde_yaml::Value;
 
-    // Avoid hijacking Kubernetes pod/service CIDRs in tun mode.
-    // This keeps in-cluster traffic (including DNS to kube-dns) out of the tun
-    // device so service discovery remains stable.
-    {
-        use serde_yaml::{Mapping, Value};
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            r#"
+dns:
+  fake-ip-filter:
+    - ts-d.zhsjf.cn
+    - keep.example.com
+tun:
+  route-exclude-address:
+    - 114.215.124.90/32
+    - 203.0.113.10/32
+rules:
+  - DOMAIN,ts-d.zhsjf.cn,DIRECT
+  - MATCH,Proxy
+"#,
+        )
+        .unwrap();
 
-        let tun_value = merged
-            .extra
-            .entry("tun".to_string())
-            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        let managed = ManagedTailscaleCompat {
+            fake_ip_filter: vec!["ts-d.zhsjf.cn".to_string()],
+            route_exclude_address: vec!["114.215.124.90/32".to_string()],
+            rules: vec!["DOMAIN,ts-d.zhsjf.cn,DIRECT".to_string()],
+        };
 
-        if let Value::Mapping(tun_map) = tun_value {
-            let key = Value::String("route-exclude-address".to_string());
-            let seq_value = tun_map
-                .entry(key)
-                .or_insert_with(|| Value::Sequence(Vec::new()));
+        remove_tailscale_managed_items(&mut cfg, &managed);
 
-            if let Value::Sequence(seq) = seq_value {
-                let mut cidrs: Vec<String> =
-                    vec!["10.42.0.0/16".to_string(), "10.43.0.0/16".to_string()];
-                cidrs.extend(args.k8s_cidr_exclude.iter().cloned());
-                cidrs.extend(args.route_exclude_address_add.iter().cloned());
+        let dns = cfg.extra.get("dns").and_then(|v| v.as_mapping()).unwrap();
+        let filters = dns
+            .get(Value::String("fake-ip-filter".into()))
+            .and_then(|v| v.as_sequence())
+            .unwrap();
+        assert!(filters
+            .iter()
+            .any(|v| v.as_str() == Some("keep.example.com")));
+        assert!(!filters.iter().any(|v| v.as_str() == Some("ts-d.zhsjf.cn")));
 
-                for cidr in cidrs {
-                    if !cidr.contains('/') {
-                        warn!(value = %cidr, "invalid CIDR for route-exclude-address addition (expected like 10.42.0.0/16)");
-                        continue;
-                    }
-                    let exists = seq.iter().any(|v| v.as_str() == Some(cidr.as_str()));
-                    if !exists {
-                        seq.push(Value::String(cidr.clone()));
-                        info!(value = %cidr, "auto-added tun route-exclude-address");
-                    }
-                }
-            }
-        }
-    }
+        let tun = cfg.extra.get("tun").and_then(|v| v.as_mapping()).unwrap();
+        let excludes = tun
+            .get(Value::String("route-exclude-address".into()))
+            .and_then(|v| v.as_sequence())
+            .unwrap();
+        assert!(excludes
+            .iter()
+            .any(|v| v.as_str() == Some("203.0.113.10/32")));
+        assert!(!excludes
+            .iter()
+            .any(|v| v.as_str() == Some("114.215.124.90/32")));
 
-    // If dry-run, print a concise summary and skip writing
-    if args.dry_run {
-        print_merge_summary(
-            &merged,
-            &args,
-            summary_dev_via.as_deref(),
-            summary_dev_added,
-            &paths,
-        );
-        if let Some(list) = dev_rules_listing.as_ref().filter(|_| args.dev_rules_show) {
-            for rule in list {
-                eprintln!("dev-rule: {}", rule);
-            }
-        }
-        return Ok(());
+        assert_eq!(cfg.rules, vec!["MATCH,Proxy".to_string()]);
     }
 
-    let yaml = merged.to_yaml_string()?;
-
-    let output_path = args
-        .output
-        .clone()
-        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    #[test]
+    fn ensure_fallback_match_rule_appends_when_missing() {
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - DOMAIN-SUFFIX,tailscale.com,DIRECT
+"#,
+        )
+        .unwrap();
 
-    if args.stdout {
-        println!("{}", yaml);
-    } else {
-        ensure_parent(&output_path).await?;
-        let deployer = FileDeployer {
-            path: output_path.clone(),
-        };
-        deployer.deploy(&yaml).await.with_context(|| {
-            format!("failed to write merged config to {}", output_path.display())
-        })?;
-        println!("merged config written to {}", output_path.display());
+        ensure_fallback_match_rule(&mut cfg, "BosLife");
 
-        if args.sync_to_clash_verge {
-            let clash_verge_paths = paths.detected_clash_verge_runtime_config_paths();
-            if clash_verge_paths.is_empty() {
-                return Err(anyhow!(
-                    "--sync-to-clash-verge requested, but no local Clash Verge runtime config was detected"
-                ));
-            }
-            for clash_verge_path in &clash_verge_paths {
-                ensure_parent(&clash_verge_path).await?;
-                if clash_verge_path.exists() {
-                    if let Some(backup) = backup_existing_file(&clash_verge_path).await? {
-                        println!(
-                            "backed up existing Clash Verge config to {}",
-                            backup.display()
-                        );
-                    }
-                }
-                let deployer = FileDeployer {
-                    path: clash_verge_path.clone(),
-                };
-                deployer.deploy(&yaml).await.with_context(|| {
-                    format!(
-                        "failed to sync merged config to Clash Verge runtime path {}",
-                        clash_verge_path.display()
-                    )
-                })?;
-                println!("synced config to {}", clash_verge_path.display());
-            }
+        assert_eq!(cfg.rules.last().map(String::as_str), Some("MATCH,BosLife"));
+    }
 
-            if let Err(err) = reload_clash_verge_runtime(&merged, &clash_verge_paths).await {
-                warn!(error = %err, "failed to auto-reload Clash Verge runtime after sync");
-            }
-        }
+    #[test]
+    fn ensure_fallback_match_rule_keeps_existing_match() {
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - DOMAIN-SUFFIX,tailscale.com,DIRECT
+  - MATCH,Proxy
+"#,
+        )
+        .unwrap();
 
-        if args.sync_to_clash_verge_sources {
-            sync_clash_verge_source_configs(&paths, &merged).await?;
-        }
-    }
+        ensure_fallback_match_rule(&mut cfg, "BosLife");
 
-    if let Some(list) = dev_rules_listing.as_ref().filter(|_| args.dev_rules_show) {
-        for rule in list {
-            eprintln!("dev-rule: {}", rule);
-        }
+        assert_eq!(
+            cfg.rules,
+            vec![
+                "DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string(),
+                "MATCH,Proxy".to_string()
+            ]
+        );
     }
 
-    if args.subscriptions_file.is_none() {
-        storage::save_subscription_list(&paths, &subscription_list).await?;
-    } else if let Some(custom) = args.subscriptions_file.as_ref() {
-        save_subscriptions_to_path(custom, &subscription_list).await?;
+    #[test]
+    fn normalize_controller_host_maps_wildcards_to_loopback() {
+        assert_eq!(normalize_controller_host("0.0.0.0"), "127.0.0.1");
+        assert_eq!(normalize_controller_host("::"), "127.0.0.1");
+        assert_eq!(normalize_controller_host("[::]"), "127.0.0.1");
+        assert_eq!(normalize_controller_host("127.0.0.1"), "127.0.0.1");
     }
 
-    // Update caches after successful merge
-    if let Some(url) = used_url.take() {
-        app_cfg.last_subscription_url = Some(url);
-    }
+    #[test]
+    fn preferred_runtime_path_prefers_clash_verge_yaml() {
+        let paths = vec![
+            PathBuf::from("/tmp/clash-verge.yaml"),
+            PathBuf::from("/tmp/config.yaml"),
+        ];
 
-    if app_cfg != original_app_cfg {
-        storage::save_app_config(&paths, &app_cfg).await?;
+        assert_eq!(
+            preferred_runtime_path(&paths).map(|path| path.as_path()),
+            Some(Path::new("/tmp/clash-verge.yaml"))
+        );
     }
 
-    if args.sync_to_clash_verge {
-        if let Err(err) = sync_system_proxy_bypass(
-            previous_managed_tailscale.as_ref(),
-            app_cfg.managed_tailscale_compat.as_ref(),
-        ) {
-            warn!(error = %err, "failed to sync system proxy bypass entries");
-        }
-    }
+    #[test]
+    fn preferred_runtime_path_falls_back_to_first_entry() {
+        let paths = vec![PathBuf::from("/tmp/clash-verge.yaml")];
 
-    Ok(())
-}
+        assert_eq!(
+            preferred_runtime_path(&paths).map(|path| path.as_path()),
+            Some(Path::new("/tmp/clash-verge.yaml"))
+        );
+    }
 
-fn print_merge_summary(
-    merged: &mihomo_core::ClashConfig,
-    args: &MergeArgs,
-    dev_via: Option<&str>,
-    dev_added: usize,
-    paths: &AppPaths,
-) {
-    use serde_yaml::Value;
+    #[test]
+    fn apply_mode_override_sets_rule_mode() {
+        let mut cfg = mihomo_core::ClashConfig::default();
+        cfg.extra
+            .insert("mode".to_string(), Value::String("global".to_string()));
 
-    let proxies = merged.proxy_names().len();
-    let groups = merged.proxy_group_names().len();
-    let rules = merged.rules.len();
+        apply_mode_override(&mut cfg, ConfigMode::Rule);
 
-    // DNS fake-ip summary
-    let mut dns_mode: Option<String> = None;
-    let mut dns_filter_total: Option<usize> = None;
-    let mut dns_fake_ip_range: Option<String> = None;
-    if let Some(Value::Mapping(dns)) = merged.extra.get("dns") {
-        if let Some(Value::String(m)) = dns.get(&Value::String("fake-ip-filter-mode".into())) {
-            dns_mode = Some(m.clone());
-        }
-        if let Some(Value::Sequence(seq)) = dns.get(&Value::String("fake-ip-filter".into())) {
-            dns_filter_total = Some(seq.len());
-        }
-        if let Some(Value::String(range)) = dns.get(&Value::String("fake-ip-range".into())) {
-            dns_fake_ip_range = Some(range.clone());
-        }
+        assert_eq!(cfg.extra.get("mode").and_then(Value::as_str), Some("rule"));
     }
 
-    // External controller
-    let mut ext_ctrl: Option<String> = None;
-    if let Some(Value::String(s)) = merged.extra.get("external-controller") {
-        ext_ctrl = Some(s.clone());
-    }
-    let secret_present = merged
-        .extra
-        .get("secret")
-        .and_then(|v| v.as_str())
-        .is_some();
+    #[test]
+    fn apply_tun_enabled_sets_true() {
+        let mut cfg = mihomo_core::ClashConfig::default();
 
-    println!("dry-run summary:");
-    println!(
-        "- proxies: {}, groups: {}, rules: {}",
-        proxies, groups, rules
-    );
-    println!(
-        "- fake-ip: mode={}, range={}, filter+={} (requested), total={}",
-        dns_mode.as_deref().unwrap_or("<none>"),
-        dns_fake_ip_range.as_deref().unwrap_or("<unset>"),
-        args.fake_ip_bypass.len(),
-        dns_filter_total
-            .map(|n| n.to_string())
-            .unwrap_or_else(|| "<unknown>".into())
-    );
-    println!(
-        "- dev-rules: enabled={}, via={}, added={}",
-        if args.dev_rules { "true" } else { "false" },
-        dev_via.unwrap_or("<n/a>"),
-        if args.dev_rules { dev_added } else { 0 }
-    );
-    println!("- mode: {}", args.mode.as_str());
-    println!(
-        "- sniffer-preset: {}",
-        match args.sniffer_preset {
-            SnifferPreset::Off => "off",
-            SnifferPreset::Tun => "tun",
-        }
-    );
-    println!("- manual-servers: <see app.yaml> (not shown in dry-run summary)");
-    println!(
-        "- external-controller: {}, secret={}",
-        ext_ctrl.unwrap_or_else(|| "<unset>".into()),
-        if secret_present { "set" } else { "unset" }
-    );
-    let would_write = args
-        .output
-        .clone()
-        .unwrap_or_else(|| paths.generated_clash_verge_path());
-    println!(
-        "- output: would write to {} (suppressed by --dry-run)",
-        would_write.display()
-    );
-    if args.sync_to_clash_verge {
-        let targets = paths.detected_clash_verge_runtime_config_paths();
-        let target = if targets.is_empty() {
-            "<not detected>".to_string()
-        } else {
-            targets
-                .iter()
-                .map(|p| p.display().to_string())
-                .collect::<Vec<_>>()
-                .join(", ")
-        };
-        println!("- clash-verge-sync: {}", target);
-    }
-    if args.sync_to_clash_verge_sources {
-        let target = paths
-            .detect_clash_verge_dir()
-            .map(|p| p.display().to_string())
-            .unwrap_or_else(|| "<not detected>".into());
-        println!("- clash-verge-source-sync: {}", target);
-    }
-    println!(
-        "- tailscale-compatible: {}",
-        if args.tailscale_compatible {
-            "true"
-        } else {
-            "false"
-        }
-    );
-    if args.tailscale_compatible {
-        println!(
-            "- tailscale-tailnet-suffixes: {}",
-            if args.tailscale_tailnet_suffixes.is_empty() {
-                "<none>".to_string()
-            } else {
-                args.tailscale_tailnet_suffixes.join(",")
-            }
-        );
-        println!(
-            "- tailscale-direct-domains: {}",
-            if args.tailscale_direct_domains.is_empty() {
-                "<none>".to_string()
-            } else {
-                args.tailscale_direct_domains.join(",")
-            }
+        apply_tun_enabled(&mut cfg, true);
+
+        let tun = cfg.extra.get("tun").and_then(Value::as_mapping).unwrap();
+        assert_eq!(
+            tun.get(Value::String("enable".into()))
+                .and_then(Value::as_bool),
+            Some(true)
         );
     }
-}
 
-fn normalize_direct_domain(domain: &str) -> Option<String> {
-    let normalized = domain.trim().trim_matches('.').to_ascii_lowercase();
-    if normalized.is_empty() {
-        None
-    } else {
-        Some(normalized)
-    }
-}
+    #[test]
+    fn apply_sniffer_preset_tun_installs_sniffer() {
+        let mut cfg = mihomo_core::ClashConfig::default();
 
-fn build_tailscale_fake_ip_bypass(
-    tailnet_suffixes: &[String],
-    direct_domains: &[String],
-) -> Vec<String> {
-    let mut items: Vec<String> = TAILSCALE_BASE_FAKE_IP_BYPASS
-        .iter()
-        .map(|s| (*s).to_string())
-        .collect();
-    for suffix in tailnet_suffixes {
-        if let Some(suffix) = normalize_direct_domain(suffix) {
-            items.push(format!("+.tail.{suffix}"));
-        }
-    }
-    for domain in direct_domains {
-        if let Some(domain) = normalize_direct_domain(domain) {
-            if let Some(stripped) = domain.strip_prefix("+.") {
-                items.push(format!("+.{stripped}"));
-            } else {
-                items.push(domain);
-            }
-        }
-    }
-    items.sort();
-    items.dedup();
-    items
-}
+        apply_sniffer_preset(&mut cfg, SnifferPreset::Tun);
 
-fn build_tailscale_direct_rules(
-    tailnet_suffixes: &[String],
-    direct_domains: &[String],
-) -> Vec<String> {
-    let mut rules: Vec<String> = TAILSCALE_BASE_DIRECT_RULES
-        .iter()
-        .map(|s| (*s).to_string())
-        .collect();
-    for suffix in tailnet_suffixes {
-        if let Some(suffix) = normalize_direct_domain(suffix) {
-            rules.push(format!("DOMAIN-SUFFIX,tail.{suffix},DIRECT"));
-        }
-    }
-    for domain in direct_domains {
-        if let Some(domain) = normalize_direct_domain(domain) {
-            if let Some(stripped) = domain.strip_prefix("+.") {
-                rules.push(format!("DOMAIN-SUFFIX,{stripped},DIRECT"));
-            } else {
-                rules.push(format!("DOMAIN,{domain},DIRECT"));
-            }
-        }
+        let sniffer = cfg
+            .extra
+            .get("sniffer")
+            .and_then(Value::as_mapping)
+            .unwrap();
+        assert_eq!(
+            sniffer
+                .get(Value::String("enable".into()))
+                .and_then(Value::as_bool),
+            Some(true)
+        );
+        let sniff = sniffer
+            .get(Value::String("sniff".into()))
+            .and_then(Value::as_mapping)
+            .unwrap();
+        assert!(sniff.contains_key(Value::String("HTTP".into())));
+        assert!(sniff.contains_key(Value::String("TLS".into())));
+        assert!(sniff.contains_key(Value::String("QUIC".into())));
     }
-    rules.sort();
-    rules.dedup();
-    rules
-}
 
-fn build_tailscale_managed_state(
-    tailnet_suffixes: &[String],
-    direct_domains: &[String],
-    route_exclude_address_add: &[String],
-) -> ManagedTailscaleCompat {
-    let mut route_exclude_address: Vec<String> = TAILSCALE_ROUTE_EXCLUDES
-        .iter()
-        .map(|cidr| (*cidr).to_string())
-        .collect();
-    route_exclude_address.extend(route_exclude_address_add.iter().cloned());
-    route_exclude_address.sort();
-    route_exclude_address.dedup();
+    #[test]
+    fn apply_sniffer_preset_off_removes_sniffer() {
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            r#"
+sniffer:
+  enable: true
+"#,
+        )
+        .unwrap();
 
-    ManagedTailscaleCompat {
-        fake_ip_filter: build_tailscale_fake_ip_bypass(tailnet_suffixes, direct_domains),
-        route_exclude_address,
-        rules: build_tailscale_direct_rules(tailnet_suffixes, direct_domains),
+        apply_sniffer_preset(&mut cfg, SnifferPreset::Off);
+
+        assert!(!cfg.extra.contains_key("sniffer"));
     }
-}
 
-fn build_system_proxy_bypass_entries(
-    tailnet_suffixes: &[String],
-    direct_domains: &[String],
-) -> Vec<String> {
-    let mut items: Vec<String> = TAILSCALE_SYSTEM_PROXY_BYPASS_BASE
-        .iter()
-        .map(|s| (*s).to_string())
-        .collect();
+    #[test]
+    fn ensure_root_mapping_allows_mode_override_in_source_doc() {
+        let mut doc = serde_yaml::from_str::<Value>(
+            r#"
+rules:
+  - MATCH,Proxy
+"#,
+        )
+        .unwrap();
 
-    for suffix in tailnet_suffixes {
-        if let Some(suffix) = normalize_direct_domain(suffix) {
-            items.push(format!("*.tail.{suffix}"));
-        }
-    }
+        ensure_root_mapping(&mut doc).insert(
+            Value::String("mode".to_string()),
+            Value::String("rule".to_string()),
+        );
 
-    for domain in direct_domains {
-        if let Some(domain) = normalize_direct_domain(domain) {
-            if let Some(stripped) = domain.strip_prefix("+.") {
-                items.push(format!("*.{stripped}"));
-            } else {
-                items.push(domain);
-            }
-        }
+        let root = doc.as_mapping().unwrap();
+        assert_eq!(
+            root.get(Value::String("mode".to_string()))
+                .and_then(Value::as_str),
+            Some("rule")
+        );
     }
 
-    items.sort();
-    items.dedup();
-    items
-}
-
-fn normalize_proxy_bypass_token(value: &str) -> String {
-    value.trim().to_ascii_lowercase()
-}
+    #[test]
+    fn subscription_from_input_honours_parse_prefix() {
+        let sub = subscription_from_input(0, "base64:https://example.com/sub");
+        assert_eq!(sub.parse_mode, Some(ParseMode::Base64));
+        assert_eq!(sub.url.as_deref(), Some("https://example.com/sub"));
 
-fn merge_proxy_bypass_entries(
-    current: Vec<String>,
-    previous_managed: &[String],
-    desired: &[String],
-) -> Vec<String> {
-    let stale: HashSet<String> = previous_managed
-        .iter()
-        .map(|item| normalize_proxy_bypass_token(item))
-        .collect();
-    let desired_normalized: HashSet<String> = desired
-        .iter()
-        .map(|item| normalize_proxy_bypass_token(item))
-        .collect();
+        let sub = subscription_from_input(1, "clash:./local.yaml");
+        assert_eq!(sub.parse_mode, Some(ParseMode::Clash));
+        assert_eq!(sub.path, Some(PathBuf::from("./local.yaml")));
 
-    let mut merged = Vec::new();
-    let mut seen = HashSet::new();
+        let sub = subscription_from_input(2, "https://example.com/sub");
+        assert_eq!(sub.parse_mode, None);
 
-    for item in current {
-        let normalized = normalize_proxy_bypass_token(&item);
-        if stale.contains(&normalized) {
-            continue;
-        }
-        if seen.insert(normalized) {
-            merged.push(item);
-        }
+        let sub = subscription_from_input(3, "keyring:subscription.main");
+        assert_eq!(sub.url.as_deref(), Some("keyring:subscription.main"));
+        assert_eq!(sub.path, None);
     }
 
-    for item in desired {
-        let normalized = normalize_proxy_bypass_token(item);
-        if desired_normalized.contains(&normalized) && seen.insert(normalized) {
-            merged.push(item.clone());
+    #[test]
+    fn import_dedupe_ignores_rules_merged_after_it() {
+        let mut app_cfg = mihomo_core::storage::AppConfig::default();
+        app_cfg.custom_rules.push(CustomRule {
+            domain: "doubleclick.net".to_string(),
+            kind: RuleKind::DomainSuffix,
+            via: "REJECT".to_string(),
+            active_hours: Vec::new(),
+        });
+        for name in ["aa", "zz"] {
+            app_cfg.rule_sets.insert(
+                name.to_string(),
+                ImportedRuleSet {
+                    source: format!("{name}.txt"),
+                    via: "Proxy".to_string(),
+                },
+            );
         }
-    }
-
-    merged
-}
+        let payloads = BTreeMap::from([
+            ("aa".to_string(), vec!["+.tracker.com".to_string()]),
+            // Later set, like a template's `DOMAIN-SUFFIX,google.com,Proxy`: merged after.
+            ("zz".to_string(), vec!["+.google.com".to_string()]),
+        ]);
+        let list = "||ads.google.com^\n||ad.doubleclick.net^\n||x.tracker.com^\n";
+        let domains = parse_blocklist(list, BlocklistFormat::Adblock);
+        let kept = |name: Option<&str>| -> Vec<String> {
+            let ahead = rules_ahead_of_import(&app_cfg, &payloads, name);
+            domains
+                .iter()
+                .filter(|domain| !ahead.iter().any(|rule| domain.is_covered_by(rule)))
+                .map(|domain| domain.domain.clone())
+                .collect()
+        };
 
-fn derive_system_proxy_bypass_entries_from_managed(
-    managed: Option<&ManagedTailscaleCompat>,
-) -> Vec<String> {
-    managed
-        .map(|state| {
-            build_system_proxy_bypass_entries(
-                &derive_tailnet_suffixes_from_managed(state),
-                &derive_direct_domains_from_managed(state),
-            )
-        })
-        .unwrap_or_default()
-}
+        assert_eq!(kept(Some("ads")), vec!["ads.google.com"]);
+        assert_eq!(kept(None), vec!["ads.google.com", "x.tracker.com"]);
+    }
 
-fn sync_system_proxy_bypass(
-    previous_managed: Option<&ManagedTailscaleCompat>,
-    current_managed: Option<&ManagedTailscaleCompat>,
-) -> anyhow::Result<()> {
-    let previous_entries = derive_system_proxy_bypass_entries_from_managed(previous_managed);
-    let desired_entries = derive_system_proxy_bypass_entries_from_managed(current_managed);
+    #[test]
+    fn version_is_newer_compares_numerically() {
+        assert!(version_is_newer("v0.10.0", "0.9.1"));
+        assert!(version_is_newer("v1.0.0", "0.1.0"));
+        assert!(!version_is_newer("v0.1.0", "0.1.0"));
+        assert!(!version_is_newer("0.0.9", "0.1.0"));
+    }
 
-    if cfg!(target_os = "macos") {
-        return sync_macos_proxy_bypass_entries(&previous_entries, &desired_entries);
+    #[test]
+    fn checksum_for_picks_the_asset_line() {
+        let (a, b) = ("a".repeat(64), "b".repeat(64));
+        let sums = format!(
+            "{a}  mihomo-linux-amd64-compatible-v1.19.3.gz\n{b}  mihomo-linux-amd64-v1.19.3.gz\n"
+        );
+        assert_eq!(
+            checksum_for(&sums, "mihomo-linux-amd64-v1.19.3.gz"),
+            Some(b)
+        );
+        assert_eq!(checksum_for(&sums, "mihomo-darwin-arm64-v1.19.3.gz"), None);
+        assert_eq!(checksum_for(&format!("{a}\n"), "anything.gz"), Some(a));
+        let name = mihomo_asset_name("v1.19.3").unwrap();
+        assert!(name.starts_with("mihomo-") && name.contains("-v1.19.3."));
     }
 
-    if cfg!(target_os = "windows") {
-        return sync_windows_proxy_bypass_entries(&previous_entries, &desired_entries);
+    #[test]
+    fn parse_sha256_file_handles_shasum_and_certutil() {
+        let digest = "a".repeat(64);
+        assert_eq!(
+            parse_sha256_file(&format!("{}  mihomo-cli-linux-x86_64.tar.gz\n", digest)),
+            Some(digest.clone())
+        );
+        let certutil = format!(
+            "SHA256 hash of mihomo-cli.zip:\r\n{}\r\nCertUtil: -hashfile command completed successfully.\r\n",
+            digest.to_ascii_uppercase()
+        );
+        assert_eq!(parse_sha256_file(&certutil), Some(digest));
     }
 
-    Ok(())
-}
+    #[test]
+    fn dashboard_url_prefills_controller() {
+        assert_eq!(
+            dashboard_url("metacubexd", "http", "127.0.0.1", 9090, "a b"),
+            "http://127.0.0.1:9090/ui/#/setup?hostname=127%2E0%2E0%2E1&port=9090&secret=a%20b"
+        );
+        assert!(dashboard_url("yacd", "https", "127.0.0.1", 9443, "")
+            .starts_with("https://127.0.0.1:9443/ui/?hostname="));
+    }
 
-fn list_macos_network_services() -> anyhow::Result<Vec<String>> {
-    let output = std::process::Command::new("networksetup")
-        .arg("-listallnetworkservices")
-        .output()
-        .context("failed to list macOS network services")?;
+    #[test]
+    fn common_top_level_dir_detects_archive_wrapper() {
+        let wrapped = vec![
+            PathBuf::from("metacubexd-gh-pages/"),
+            PathBuf::from("metacubexd-gh-pages/index.html"),
+        ];
+        assert_eq!(
+            common_top_level_dir(&wrapped),
+            Some(PathBuf::from("metacubexd-gh-pages"))
+        );
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow!(
-            "networksetup -listallnetworkservices failed: {}",
-            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
-        ));
+        let flat = vec![PathBuf::from("index.html"), PathBuf::from("assets/app.js")];
+        assert_eq!(common_top_level_dir(&flat), None);
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let services = stdout
-        .lines()
-        .map(str::trim)
-        .filter(|line| !line.is_empty())
-        .filter(|line| !line.starts_with("An asterisk"))
-        .filter(|line| !line.starts_with('*'))
-        .map(ToOwned::to_owned)
-        .collect::<Vec<_>>();
-
-    Ok(services)
-}
+    #[test]
+    fn ensure_controller_secret_generates_once_and_reuses() {
+        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
+            r#"
+external-controller: 127.0.0.1:9090
+secret: ""
+"#,
+        )
+        .unwrap();
+        let mut app_cfg = mihomo_core::storage::AppConfig::default();
 
-fn get_macos_proxy_bypass_domains(service: &str) -> anyhow::Result<Vec<String>> {
-    let output = std::process::Command::new("networksetup")
-        .args(["-getproxybypassdomains", service])
-        .output()
-        .with_context(|| format!("failed to get proxy bypass domains for {service}"))?;
+        assert!(ensure_controller_secret(&mut cfg, &mut app_cfg));
+        let generated = app_cfg.controller_secret.clone().unwrap();
+        assert_eq!(generated.len(), 32);
+        assert_eq!(
+            cfg.extra.get("secret").and_then(Value::as_str),
+            Some(generated.as_str())
+        );
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow!(
-            "networksetup -getproxybypassdomains {} failed: {}",
-            service,
-            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
-        ));
+        let mut next: mihomo_core::ClashConfig =
+            serde_yaml::from_str("external-controller: 127.0.0.1:9090\n").unwrap();
+        assert!(!ensure_controller_secret(&mut next, &mut app_cfg));
+        assert_eq!(
+            next.extra.get("secret").and_then(Value::as_str),
+            Some(generated.as_str())
+        );
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let domains = stdout
-        .lines()
-        .map(str::trim)
-        .filter(|line| !line.is_empty())
-        .map(ToOwned::to_owned)
-        .collect::<Vec<_>>();
-
-    Ok(domains)
+    #[test]
+    fn service_files_quote_paths_and_add_merge_timer() {
+        let spec = ServiceSpec {
+            user: false,
+            mihomo: ["/opt/mihomo", "-d", "/home/a b/.config/mihomocli"]
+                .map(String::from)
+                .to_vec(),
+            merge: Some((
+                ["/usr/bin/mihomo-cli", "merge", "--profile", "50%&off"]
+                    .map(String::from)
+                    .to_vec(),
+                std::time::Duration::from_secs(6 * 3600),
+            )),
+            home: Some(PathBuf::from("/home/a b")),
+            log: PathBuf::from("/home/a b/.config/mihomocli/mihomo.log"),
+        };
+        let units = systemd_units(&spec);
+        let names: Vec<&str> = units.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "mihomo-cli.service",
+                "mihomo-cli-merge.service",
+                "mihomo-cli-merge.timer"
+            ]
+        );
+        assert!(units[0]
+            .1
+            .contains("ExecStart=/opt/mihomo -d \"/home/a b/.config/mihomocli\"\n"));
+        assert!(units[0].1.contains("WantedBy=multi-user.target\n"));
+        assert!(units[1].1.contains("Environment=\"HOME=/home/a b\"\n"));
+        assert!(units[1].1.contains("--profile 50%%&off\n"));
+        assert!(units[2].1.contains("OnUnitActiveSec=21600s\n"));
+
+        let plists = launchd_plists(&spec);
+        assert_eq!(plists[1].0, "io.github.mihomocli.merge.plist");
+        assert!(plists[1].1.contains("<string>50%&amp;off</string>"));
+        assert!(plists[1].1.contains("<integer>21600</integer>"));
+        assert_eq!(
+            launchd_job_state(true, "{\n\t\"PID\" = 4242;\n\t\"Label\" = \"x\";\n};\n"),
+            "running (pid 4242)"
+        );
+        assert_eq!(launchd_job_state(false, ""), "not loaded");
+    }
 }
 
-fn set_macos_proxy_bypass_domains(service: &str, domains: &[String]) -> anyhow::Result<()> {
-    let mut command = std::process::Command::new("networksetup");
-    command.arg("-setproxybypassdomains").arg(service);
-    for domain in domains {
-        command.arg(domain);
+fn default_base_config_path(paths: &AppPaths) -> Option<PathBuf> {
+    let candidate = paths.app_config_path().with_file_name("base-config.yaml");
+    if candidate.exists() {
+        return Some(candidate);
     }
 
-    let output = command
-        .output()
-        .with_context(|| format!("failed to set proxy bypass domains for {service}"))?;
+    paths
+        .detected_clash_verge_base_config_candidates()
+        .into_iter()
+        .find(|candidate| candidate.exists())
+}
+
+async fn ensure_parent(path: &Path) -> anyhow::Result<()> {
+    storage::ensure_writable(path)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    Ok(())
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow!(
-            "networksetup -setproxybypassdomains {} failed: {}",
-            service,
-            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
-        ));
+async fn backup_existing_file(path: &Path) -> anyhow::Result<Option<PathBuf>> {
+    if !fs::try_exists(path).await.unwrap_or(false) {
+        return Ok(None);
     }
 
-    Ok(())
+    let backup = path.with_extension("mihomocli.bak");
+    fs::copy(path, &backup).await.with_context(|| {
+        format!(
+            "failed to back up {} to {}",
+            path.display(),
+            backup.display()
+        )
+    })?;
+    Ok(Some(backup))
 }
 
-fn sync_macos_proxy_bypass_entries(
-    previous_managed: &[String],
-    desired: &[String],
-) -> anyhow::Result<()> {
-    for service in list_macos_network_services()? {
-        let current = get_macos_proxy_bypass_domains(&service)?;
-        let merged = merge_proxy_bypass_entries(current.clone(), previous_managed, desired);
-        if merged != current {
-            set_macos_proxy_bypass_domains(&service, &merged)?;
+const DEFAULT_TEMPLATE_CONTENT: &str = include_str!("../../../examples/cvr_template.yaml");
+
+async fn ensure_default_template(paths: &AppPaths, force_update: bool) -> anyhow::Result<()> {
+    let template_path = paths.default_template_path();
+    if storage::is_read_only() {
+        return Ok(());
+    }
+
+    match sync_bundled_template(&template_path, DEFAULT_TEMPLATE_CONTENT, force_update).await? {
+        BundledTemplateSync::Installed => {
+            tracing::info!(path = %template_path.display(), "installed default template");
+        }
+        BundledTemplateSync::Updated => {
+            tracing::info!(path = %template_path.display(), "updated default template");
+        }
+        BundledTemplateSync::Overwritten { backup } => {
+            tracing::info!(
+                path = %template_path.display(),
+                backup = %backup.display(),
+                "replaced edited default template"
+            );
+        }
+        BundledTemplateSync::Modified { diff: Some(diff) } => {
+            eprintln!(
+                "A newer bundled template is available, but {} has local edits and was left unchanged.\n\
+                 Changes from your copy to the bundled template:\n{}\
+                 Re-run with --force-template-update to replace it (your copy is kept as a .bak).",
+                template_path.display(),
+                diff
+            );
+        }
+        BundledTemplateSync::Modified { diff: None } => {
+            tracing::debug!(path = %template_path.display(), "keeping edited default template");
         }
+        BundledTemplateSync::UpToDate => {}
     }
 
     Ok(())
 }
 
-fn parse_windows_proxy_override(raw: &str) -> Vec<String> {
-    raw.lines()
-        .find(|line| line.contains("ProxyOverride"))
-        .and_then(|line| {
-            let mut parts = line.split_whitespace();
-            let _name = parts.next()?;
-            let _type = parts.next()?;
-            Some(parts.collect::<Vec<_>>().join(" "))
-        })
-        .map(|value| {
-            value
-                .split(';')
-                .map(str::trim)
-                .filter(|item| !item.is_empty())
-                .map(ToOwned::to_owned)
-                .collect::<Vec<_>>()
-        })
-        .unwrap_or_default()
-}
+#[derive(Args)]
+struct TestArgs {
+    /// Path to mihomo binary (defaults to `mihomo` in PATH, then the one from `core install`)
+    #[arg(long = "mihomo-bin")]
+    mihomo_bin: Option<String>,
 
-fn get_windows_proxy_override_entries() -> anyhow::Result<Vec<String>> {
-    let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
-    let output = std::process::Command::new("reg")
-        .args(["query", key, "/v", "ProxyOverride"])
-        .output()
-        .context("failed to query Windows proxy override list")?;
+    /// Config file to test (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    if !output.status.success() {
-        return Ok(Vec::new());
-    }
+    /// Working directory passed to mihomo via -d (defaults to ~/.config/mihomocli)
+    #[arg(long = "mihomo-dir")]
+    mihomo_dir: Option<PathBuf>,
 
-    Ok(parse_windows_proxy_override(&String::from_utf8_lossy(
-        &output.stdout,
-    )))
+    /// Check against the bundled schema snapshot of this mihomo version (e.g. v1.19) instead
+    /// of running mihomo
+    #[arg(long = "against-schema", value_name = "VERSION")]
+    against_schema: Option<String>,
 }
 
-fn set_windows_proxy_override_entries(entries: &[String]) -> anyhow::Result<()> {
-    let key = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
-    let value = entries.join(";");
-    let output = std::process::Command::new("reg")
-        .args([
-            "add",
-            key,
-            "/v",
-            "ProxyOverride",
-            "/t",
-            "REG_SZ",
-            "/d",
-            &value,
-            "/f",
-        ])
-        .output()
-        .context("failed to update Windows proxy override list")?;
+async fn run_test(args: TestArgs) -> anyhow::Result<()> {
+    use tokio::process::Command;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow!(
-            "reg add ProxyOverride failed: {}",
-            trimmed_single_line(&stderr).unwrap_or_else(|| "unknown error".to_string())
-        ));
+    let paths = AppPaths::new()?;
+    let config_path = args
+        .config
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    if let Some(version) = args.against_schema.as_deref() {
+        return validate_config_schema(&config_path, version).await;
     }
+    let workdir = args
+        .mihomo_dir
+        .unwrap_or_else(|| paths.config_dir().to_path_buf());
 
-    Ok(())
+    let status = Command::new(mihomo_binary(&paths, args.mihomo_bin))
+        .arg("-d")
+        .arg(workdir)
+        .arg("-f")
+        .arg(&config_path)
+        .arg("-m")
+        .arg("-t")
+        .status()
+        .await?;
+
+    if status.success() {
+        println!("mihomo config test passed: {}", config_path.display());
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "mihomo config test failed (exit code: {:?})",
+            status.code()
+        ))
+    }
 }
 
-fn sync_windows_proxy_bypass_entries(
-    previous_managed: &[String],
-    desired: &[String],
-) -> anyhow::Result<()> {
-    let current = get_windows_proxy_override_entries()?;
-    let merged = merge_proxy_bypass_entries(current.clone(), previous_managed, desired);
-    if merged != current {
-        set_windows_proxy_override_entries(&merged)?;
+async fn validate_config_schema(config_path: &Path, version: &str) -> anyhow::Result<()> {
+    let raw = fs::read_to_string(config_path)
+        .await
+        .with_context(|| format!("failed to read {}", config_path.display()))?;
+    let config: mihomo_core::ClashConfig = serde_yaml::from_str(&raw)
+        .with_context(|| format!("failed to parse {}", config_path.display()))?;
+    let violations = mihomo_core::schema::validate_against_schema(&config, version)?;
+    if violations.is_empty() {
+        println!(
+            "schema check passed: {} conforms to mihomo {}",
+            config_path.display(),
+            version
+        );
+        return Ok(());
     }
-    Ok(())
+    for violation in &violations {
+        eprintln!("{}", violation);
+    }
+    Err(anyhow!(
+        "{} does not match the mihomo {} schema ({} problem(s))",
+        config_path.display(),
+        version,
+        violations.len()
+    ))
 }
 
-fn remove_tailscale_managed_items(
-    merged: &mut mihomo_core::ClashConfig,
-    managed: &ManagedTailscaleCompat,
-) {
-    use serde_yaml::Value;
-    use std::collections::HashSet;
+fn subscription_from_input(index: usize, input: &str) -> Subscription {
+    let (parse_mode, input) = split_parse_prefix(input);
+    let mut subscription = Subscription {
+        name: format!("cli-{}", index),
+        kind: SubscriptionKind::Clash,
+        parse_mode,
+        ..Default::default()
+    };
 
-    let managed_filters: HashSet<&str> =
-        managed.fake_ip_filter.iter().map(String::as_str).collect();
-    if let Some(filters) = merged
-        .extra
-        .get_mut("dns")
-        .and_then(|v| v.as_mapping_mut())
-        .and_then(|map| map.get_mut(Value::String("fake-ip-filter".to_string())))
-        .and_then(|value| value.as_sequence_mut())
-    {
-        filters.retain(|item| match item.as_str() {
-            Some(value) => !managed_filters.contains(value),
-            None => true,
-        });
+    if let Some(entry) = secrets::keyring_name(input) {
+        // A saved subscription moved into the keyring, e.g. `last_subscription_url` after
+        // `manage secrets migrate`.
+        subscription.url = Some(input.to_string());
+        subscription.name = entry.to_string();
+    } else if is_url(input) {
+        subscription.url = Some(input.to_string());
+        subscription.name = url_name(input).unwrap_or(subscription.name.clone());
+    } else {
+        subscription.path = Some(PathBuf::from(input));
+        subscription.name = Path::new(input)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(|s| s.to_string())
+            .unwrap_or(subscription.name.clone());
     }
 
-    let managed_route_excludes: HashSet<&str> = managed
-        .route_exclude_address
-        .iter()
-        .map(String::as_str)
-        .collect();
-    if let Some(excludes) = merged
-        .extra
-        .get_mut("tun")
-        .and_then(|v| v.as_mapping_mut())
-        .and_then(|map| map.get_mut(Value::String("route-exclude-address".to_string())))
-        .and_then(|value| value.as_sequence_mut())
-    {
-        excludes.retain(|item| match item.as_str() {
-            Some(value) => !managed_route_excludes.contains(value),
-            None => true,
-        });
-    }
+    subscription.ensure_id();
+    subscription
+}
 
-    let managed_rules: HashSet<&str> = managed.rules.iter().map(String::as_str).collect();
-    merged
-        .rules
-        .retain(|rule| !managed_rules.contains(rule.as_str()));
+/// Split a `base64:`/`clash:` parse-mode prefix off an ad-hoc `-s` source.
+fn split_parse_prefix(input: &str) -> (Option<ParseMode>, &str) {
+    for mode in [ParseMode::Base64, ParseMode::Clash] {
+        if let Some(rest) = input
+            .strip_prefix(mode.as_str())
+            .and_then(|rest| rest.strip_prefix(':'))
+        {
+            return (Some(mode), rest);
+        }
+    }
+    (None, input)
 }
 
-fn apply_tailscale_compatibility(
-    merged: &mut mihomo_core::ClashConfig,
-    tailnet_suffixes: &[String],
-    direct_domains: &[String],
-) {
-    use serde_yaml::{Mapping, Value};
-    let fake_ip_bypass = build_tailscale_fake_ip_bypass(tailnet_suffixes, direct_domains);
-    let direct_rules = build_tailscale_direct_rules(tailnet_suffixes, direct_domains);
+/// Parse host:port from a string. Supports "host:port" and "[IPv6]:port".
+fn parse_host_port(s: &str) -> Option<(String, u16)> {
+    // Bracketed IPv6 like [::1]:9090
+    if let Some(close_idx) = s.rfind(']') {
+        let open_idx = s.find('[')?;
+        if close_idx < s.len().saturating_sub(2) && s.as_bytes().get(close_idx + 1) == Some(&b':') {
+            let host = s.get(open_idx + 1..close_idx)?.to_string();
+            let port_str = s.get(close_idx + 2..)?;
+            if let Ok(port) = port_str.parse::<u16>() {
+                return Some((host, port));
+            }
+        }
+        return None;
+    }
 
-    let dns_value = merged
-        .extra
-        .entry("dns".to_string())
-        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    // Fallback: split by last ':'
+    if let Some(idx) = s.rfind(':') {
+        let (host, port_str) = s.split_at(idx);
+        let port_str = &port_str[1..];
+        if let Ok(port) = port_str.parse::<u16>() {
+            return Some((host.to_string(), port));
+        }
+    }
+    None
+}
 
-    if let Value::Mapping(dns_map) = dns_value {
-        let enhanced_key = Value::String("enhanced-mode".to_string());
-        let range_key = Value::String("fake-ip-range".to_string());
-        let mode_key = Value::String("fake-ip-filter-mode".to_string());
-        let filter_key = Value::String("fake-ip-filter".to_string());
+/// `http://host:port` of the config's external-controller, if it has one.
+fn controller_endpoint(cfg: &mihomo_core::ClashConfig) -> Option<String> {
+    let controller = cfg.extra.get("external-controller")?.as_str()?;
+    let (host, port) = parse_host_port(controller)?;
+    Some(format!(
+        "http://{}:{}",
+        normalize_controller_host(&host),
+        port
+    ))
+}
+
+fn normalize_controller_host(host: &str) -> String {
+    let trimmed = host.trim().trim_matches(['[', ']']);
+    if trimmed == "0.0.0.0" || trimmed == "::" || trimmed == "*" || trimmed.is_empty() {
+        "127.0.0.1".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
 
-        let enhanced = dns_map
-            .get(&enhanced_key)
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
+fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
 
-        if enhanced.eq_ignore_ascii_case("fake-ip") {
-            dns_map.insert(mode_key, Value::String("blacklist".to_string()));
+fn url_name(input: &str) -> Option<String> {
+    let start = input.find("//")? + 2;
+    let rest = &input[start..];
+    let end = rest.find('/')?;
+    Some(rest[..end].to_string())
+}
 
-            let current_range = dns_map
-                .get(&range_key)
-                .and_then(|v| v.as_str())
-                .map(str::to_owned);
+const RESOURCE_SOURCES: &[(&str, &str)] = &[
+    (
+        "Country.mmdb",
+        "https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/country.mmdb",
+    ),
+    (
+        "geoip.dat",
+        "https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/geoip.dat",
+    ),
+    (
+        "geosite.dat",
+        "https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/geosite.dat",
+    ),
+];
 
-            match current_range.as_deref() {
-                Some(range) if range.starts_with("198.18.") || range.starts_with("198.19.") => {
-                    dns_map.insert(range_key, Value::String(SAFE_FAKE_IP_RANGE.to_string()));
-                    warn!(
-                        previous = %range,
-                        replacement = %SAFE_FAKE_IP_RANGE,
-                        "rewrote fake-ip-range to avoid Tailscale conflict"
-                    );
-                }
-                None => {
-                    dns_map.insert(range_key, Value::String(SAFE_FAKE_IP_RANGE.to_string()));
-                    info!(
-                        value = %SAFE_FAKE_IP_RANGE,
-                        "set default fake-ip-range compatible with Tailscale"
-                    );
-                }
-                _ => {}
-            }
+/// Resource files older than this are reported as stale during merge.
+const RESOURCE_STALE_AFTER_DAYS: u64 = 30;
 
-            let filter_seq = dns_map
-                .entry(filter_key)
-                .or_insert_with(|| Value::Sequence(Vec::new()));
-            if let Value::Sequence(seq) = filter_seq {
-                for item in &fake_ip_bypass {
-                    if !seq.iter().any(|v| v.as_str() == Some(item.as_str())) {
-                        seq.push(Value::String(item.clone()));
-                        info!(value = %item, "auto-added Tailscale fake-ip bypass");
-                    }
-                }
-            }
+async fn check_resource_freshness(paths: &AppPaths, warnings: &mut Warnings) {
+    for (name, _) in RESOURCE_SOURCES.iter() {
+        let Ok(meta) = fs::metadata(paths.resource_file(name)).await else {
+            continue;
+        };
+        let Some(age) = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+        else {
+            continue;
+        };
+        let days = age.as_secs() / 86_400;
+        if days > RESOURCE_STALE_AFTER_DAYS {
+            warnings.push(
+                WarningKind::StaleResource,
+                format!(
+                    "{} is {} days old; run `mihomo-cli resources update`",
+                    name, days
+                ),
+            );
         }
     }
+}
 
-    let tun_value = merged
-        .extra
-        .entry("tun".to_string())
-        .or_insert_with(|| Value::Mapping(Mapping::new()));
+fn note_lint_findings(template: &Template, path: &Path, warnings: &mut Warnings) {
+    for finding in template.lint() {
+        warnings.push(
+            WarningKind::Deprecated,
+            format!("{}: {}", path.display(), finding),
+        );
+    }
+}
 
-    if let Value::Mapping(tun_map) = tun_value {
-        let key = Value::String("route-exclude-address".to_string());
-        let seq_value = tun_map
-            .entry(key)
-            .or_insert_with(|| Value::Sequence(Vec::new()));
+fn note_cache_fallback(subscription: &Subscription, warnings: &mut Warnings) {
+    if subscription.failure_count > 0 {
+        warnings.push(
+            WarningKind::Subscription,
+            format!(
+                "{}: fetch failed ({}); using cached copy",
+                subscription.id,
+                subscription
+                    .last_error
+                    .as_deref()
+                    .unwrap_or("unknown error")
+            ),
+        );
+    }
+}
 
-        if let Value::Sequence(seq) = seq_value {
-            for cidr in TAILSCALE_ROUTE_EXCLUDES {
-                if !seq.iter().any(|v| v.as_str() == Some(cidr)) {
-                    seq.push(Value::String(cidr.to_string()));
-                    info!(value = %cidr, "auto-added Tailscale tun route exclusion");
-                }
-            }
+async fn note_subscription_usage(
+    subscription: &Subscription,
+    paths: &AppPaths,
+    warnings: &mut Warnings,
+) -> Option<SubscriptionUsage> {
+    let usage = match subscription.usage(paths).await {
+        Ok(Some(usage)) => usage,
+        Ok(None) => return None,
+        Err(err) => {
+            warn!(id = %subscription.id, error = %err, "failed to read subscription usage");
+            return None;
         }
+    };
+    let now = chrono::Utc::now();
+    let mut messages = usage.warnings(now);
+    if let Ok(history) = load_usage_history(&paths.usage_history_file(&subscription.id)).await {
+        messages.extend(project_usage(&history, None, now).and_then(|p| p.warning()));
+    }
+    for message in messages {
+        warnings.push(
+            WarningKind::SubscriptionUsage,
+            format!("{}: {}", subscription_label(subscription), message),
+        );
     }
+    Some(usage)
+}
 
-    let mut prefixed_rules = Vec::new();
-    for rule in &direct_rules {
-        if !merged.rules.iter().any(|existing| existing == rule) {
-            prefixed_rules.push(rule.clone());
+/// One line of the per-subscription table printed after a merge.
+struct SourceRow {
+    name: String,
+    /// `network`, `not-modified`, `cache`, `file` or `failed`.
+    origin: &'static str,
+    /// Proxy names the subscription delivered (before any filtering).
+    proxies: Vec<String>,
+    /// Time since the cached payload last changed.
+    age: Option<chrono::Duration>,
+    remaining: Option<u64>,
+    /// Nodes added, removed or renamed since the previous merge.
+    changes: NodeChanges,
+}
+
+impl SourceRow {
+    fn loaded(
+        subscription: &Subscription,
+        config: &mihomo_core::ClashConfig,
+        usage: Option<SubscriptionUsage>,
+        cache_modified: Option<chrono::DateTime<chrono::Utc>>,
+        changes: NodeChanges,
+    ) -> Self {
+        Self {
+            name: subscription_label(subscription).to_string(),
+            origin: subscription
+                .last_source
+                .map(|source| source.as_str())
+                .unwrap_or("-"),
+            proxies: config
+                .proxies
+                .iter()
+                .map(|proxy| {
+                    proxy
+                        .get("name")
+                        .and_then(|name| name.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect(),
+            age: cache_modified.map(|modified| chrono::Utc::now() - modified),
+            remaining: usage.and_then(|usage| usage.remaining()),
+            changes,
         }
     }
-    if !prefixed_rules.is_empty() {
-        prefixed_rules.extend(std::mem::take(&mut merged.rules));
-        merged.rules = prefixed_rules;
+
+    fn failed(subscription: &Subscription) -> Self {
+        Self {
+            name: subscription_label(subscription).to_string(),
+            origin: "failed",
+            proxies: Vec::new(),
+            age: None,
+            remaining: None,
+            changes: NodeChanges::default(),
+        }
     }
 }
 
-fn has_fallback_rule(cfg: &mihomo_core::ClashConfig) -> bool {
-    cfg.rules.iter().any(|rule| {
-        let normalized = rule.trim().to_ascii_uppercase();
-        normalized.starts_with("MATCH,")
-            || normalized.starts_with("FINAL,")
-            || normalized.starts_with("IP-CIDR,0.0.0.0/0,")
-            || normalized.starts_with("IP-CIDR6,::/0,")
-    })
+/// Render the per-subscription table: origin, nodes kept in the output, nodes filtered out,
+/// and remaining traffic when the provider reports it.
+fn render_source_table(rows: &[SourceRow], merged: &mihomo_core::ClashConfig) -> String {
+    if rows.is_empty() {
+        return String::new();
+    }
+    let kept_names: HashSet<String> = merged.proxy_names().into_iter().collect();
+    let header = [
+        "SUBSCRIPTION",
+        "SOURCE",
+        "AGE",
+        "NODES",
+        "FILTERED",
+        "REMAINING",
+    ];
+    let mut table: Vec<[String; 6]> = vec![header.map(str::to_string)];
+    for row in rows {
+        let kept = row
+            .proxies
+            .iter()
+            .filter(|name| kept_names.contains(*name))
+            .count();
+        table.push([
+            row.name.clone(),
+            row.origin.to_string(),
+            row.age.map(format_age).unwrap_or_else(|| "-".to_string()),
+            kept.to_string(),
+            (row.proxies.len() - kept).to_string(),
+            row.remaining
+                .map(format_bytes)
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+
+    let mut widths = [0usize; 6];
+    for line in &table {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for line in &table {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    for row in rows.iter().filter(|row| !row.changes.is_empty()) {
+        let changes = &row.changes;
+        out.push_str(&format!(
+            "{}: {} added, {} removed, {} renamed since the last merge\n",
+            row.name,
+            changes.added.len(),
+            changes.removed.len(),
+            changes.renamed.len()
+        ));
+        let lines = changes
+            .added
+            .iter()
+            .map(|name| format!("+ {}", name))
+            .chain(changes.removed.iter().map(|name| format!("- {}", name)))
+            .chain(
+                changes
+                    .renamed
+                    .iter()
+                    .map(|(old, new)| format!("~ {} -> {}", old, new)),
+            );
+        let total = changes.added.len() + changes.removed.len() + changes.renamed.len();
+        for line in lines.take(NODE_CHANGES_SHOWN) {
+            out.push_str(&format!("  {}\n", line));
+        }
+        if total > NODE_CHANGES_SHOWN {
+            out.push_str(&format!(
+                "  ... {} more in report.json\n",
+                total - NODE_CHANGES_SHOWN
+            ));
+        }
+    }
+    out
 }
 
-fn ensure_fallback_match_rule(cfg: &mut mihomo_core::ClashConfig, via: &str) {
-    if has_fallback_rule(cfg) {
-        return;
+/// Node changes listed per subscription under the source table; report.json has them all.
+const NODE_CHANGES_SHOWN: usize = 10;
+
+/// Compare the nodes `subscription` delivered with those of the previous merge and save the
+/// new set. Losing half or more of the previous nodes is also reported as a warning.
+async fn note_node_changes(
+    subscription: &Subscription,
+    config: &mihomo_core::ClashConfig,
+    paths: &AppPaths,
+    warnings: &mut Warnings,
+) -> NodeChanges {
+    let path = paths.cache_nodes_file(&subscription.id);
+    let current = NodeSet::of(config);
+    let previous = NodeSet::load(&path).await;
+    if previous.as_ref() != Some(&current) && !storage::is_read_only() {
+        if let Err(err) = current.save(&path).await {
+            warn!(error = %format!("{:#}", err), "failed to save subscription node list");
+        }
     }
-    cfg.rules.push(format!("MATCH,{via}"));
+    let Some(previous) = previous else {
+        return NodeChanges::default();
+    };
+    let changes = current.changes_since(&previous);
+    if !previous.is_empty() && changes.removed.len() * 2 >= previous.len() {
+        warnings.push(
+            WarningKind::Subscription,
+            format!(
+                "subscription {} dropped {} of its {} nodes since the last merge",
+                subscription_label(subscription),
+                changes.removed.len(),
+                previous.len()
+            ),
+        );
+    }
+    changes
 }
 
-fn apply_mode_override(cfg: &mut mihomo_core::ClashConfig, mode: ConfigMode) {
-    cfg.extra
-        .insert("mode".to_string(), Value::String(mode.as_str().to_string()));
+/// Compact age such as `45s`, `12m`, `3h` or `5d`.
+fn format_age(age: chrono::Duration) -> String {
+    let secs = age.num_seconds().max(0);
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86_399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86_400),
+    }
 }
 
-fn apply_tun_enabled(cfg: &mut mihomo_core::ClashConfig, enabled: bool) {
-    use serde_yaml::{Mapping, Value};
-
-    let tun_value = cfg
-        .extra
-        .entry("tun".to_string())
-        .or_insert_with(|| Value::Mapping(Mapping::new()));
-    if let Value::Mapping(tun_map) = tun_value {
-        tun_map.insert(Value::String("enable".to_string()), Value::Bool(enabled));
+fn subscription_label(subscription: &Subscription) -> &str {
+    if subscription.name.is_empty() {
+        &subscription.id
+    } else {
+        &subscription.name
     }
 }
 
-fn apply_sniffer_preset(cfg: &mut mihomo_core::ClashConfig, preset: SnifferPreset) {
-    match preset {
-        SnifferPreset::Off => {
-            cfg.extra.shift_remove("sniffer");
-        }
-        SnifferPreset::Tun => {
-            let sniffer = serde_yaml::from_str::<Value>(
-                r#"
-enable: true
-force-dns-mapping: true
-parse-pure-ip: true
-override-destination: false
-sniff:
-  HTTP:
-    ports: [80, 8080-8880]
-    override-destination: true
-  TLS:
-    ports: [443, 8443]
-  QUIC:
-    ports: [443, 8443]
-"#,
+/// HTTP client for subscriptions and resource downloads. `proxy` is an upstream proxy URL
+/// (`http://`, `https://`, `socks5://`, `socks5h://`) or `direct` to ignore the environment's
+/// proxy settings; without it reqwest honours `HTTPS_PROXY`/`ALL_PROXY`.
+/// Client for one subscription fetch: its own cookie jar (so panels that set a session cookie
+/// and redirect work) and its own upstream proxy, falling back to `--fetch-proxy`.
+async fn subscription_client(
+    subscription: &Subscription,
+    ua: &str,
+    args: &MergeArgs,
+    paths: &AppPaths,
+) -> anyhow::Result<(reqwest::Client, Arc<SubscriptionCookies>)> {
+    let cookies = Arc::new(subscription.cookie_jar(paths).await);
+    let proxy = subscription
+        .proxy
+        .as_deref()
+        .or(args.fetch_proxy.as_deref());
+    let client = build_fetch_client(ua, proxy, args.max_redirects, Some(cookies.clone()))
+        .with_context(|| format!("invalid proxy for subscription {}", subscription.id))?;
+    Ok((client, cookies))
+}
+
+async fn save_subscription_cookies(
+    subscription: &Subscription,
+    paths: &AppPaths,
+    cookies: &SubscriptionCookies,
+) {
+    if let Err(err) = subscription.save_cookies(paths, cookies).await {
+        warn!(id = %subscription.id, error = %err, "failed to save subscription cookies");
+    }
+}
+
+fn build_fetch_client(
+    ua: &str,
+    proxy: Option<&str>,
+    max_redirects: usize,
+    cookies: Option<Arc<SubscriptionCookies>>,
+) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(ua)
+        .redirect(reqwest::redirect::Policy::limited(max_redirects));
+    if let Some(cookies) = cookies {
+        builder = builder.cookie_provider(cookies);
+    }
+    match proxy.map(str::trim) {
+        None | Some("") => {}
+        Some(direct) if direct.eq_ignore_ascii_case("direct") => builder = builder.no_proxy(),
+        Some(url) => {
+            builder = builder.proxy(
+                reqwest::Proxy::all(url).with_context(|| format!("invalid proxy URL {}", url))?,
             )
-            .expect("valid built-in sniffer preset");
-            cfg.extra.insert("sniffer".to_string(), sniffer);
         }
     }
+    Ok(builder.build()?)
 }
 
-async fn sync_clash_verge_source_configs(
+async fn ensure_mihomo_resources(
+    client: &reqwest::Client,
     paths: &AppPaths,
-    merged: &mihomo_core::ClashConfig,
+    mirrors: &[String],
+    wanted: impl Fn(&str) -> bool,
 ) -> anyhow::Result<()> {
-    use serde_yaml::Value;
-
-    let dns_path = paths
-        .detected_clash_verge_dns_config_path()
-        .ok_or_else(|| {
-            anyhow!("--sync-to-clash-verge-sources requested, but dns_config.yaml was not detected")
-        })?;
-    let merge_path = paths
-        .detected_clash_verge_profile_merge_path()
-        .ok_or_else(|| {
-            anyhow!(
-                "--sync-to-clash-verge-sources requested, but profiles/Merge.yaml was not detected"
-            )
-        })?;
-
-    let dns_source = fs::read_to_string(&dns_path)
-        .await
-        .with_context(|| format!("failed to read {}", dns_path.display()))?;
-    let merge_source = fs::read_to_string(&merge_path)
-        .await
-        .with_context(|| format!("failed to read {}", merge_path.display()))?;
+    let mut missing = Vec::new();
+    for (name, url) in RESOURCE_SOURCES.iter() {
+        if !wanted(name) {
+            continue;
+        }
+        let target = paths.resource_file(name);
+        if !fs::try_exists(&target).await.unwrap_or(false) {
+            missing.push((*name, *url, target));
+        }
+    }
+    let mirror = match missing.first() {
+        Some((_, url, _)) => resource_mirror(client, paths, mirrors, url).await,
+        None => None,
+    };
 
-    let mut dns_doc: Value = serde_yaml::from_str(&dns_source)
-        .with_context(|| format!("failed to parse {}", dns_path.display()))?;
-    let mut merge_doc: Value = serde_yaml::from_str(&merge_source)
-        .with_context(|| format!("failed to parse {}", merge_path.display()))?;
+    let mut downloads = tokio::task::JoinSet::new();
+    for (name, url, target) in missing {
+        let url = mirror
+            .as_deref()
+            .and_then(|mirror| mirror_url(mirror, url))
+            .unwrap_or_else(|| url.to_string());
+        let client = client.clone();
+        downloads.spawn(async move {
+            info!(resource = %name, "downloading resource");
+            download_resource(&client, &url, &target)
+                .await
+                .with_context(|| format!("failed to download {name} from {url}"))
+        });
+    }
 
-    let merged_dns = merged.extra.get("dns").cloned();
-    let merged_tun = merged.extra.get("tun").cloned();
-    let merged_hosts = merged.extra.get("hosts").cloned();
-    let merged_mode = merged.extra.get("mode").cloned();
-    let merged_sniffer = merged.extra.get("sniffer").cloned();
+    let mut first_error = None;
+    while let Some(joined) = downloads.join_next().await {
+        if let Err(err) = joined? {
+            warn!(error = %format!("{:#}", err), "resource download failed");
+            first_error.get_or_insert(err);
+        }
+    }
+    match first_error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
 
-    if let Some(ref src_dns) = merged_dns {
-        let dns_map = ensure_mapping_entry(&mut dns_doc, "dns");
-        if let Some(src_map) = src_dns.as_mapping() {
-            copy_mapping_key(src_map, dns_map, "enhanced-mode");
-            copy_mapping_key(src_map, dns_map, "fake-ip-range");
-            copy_mapping_key(src_map, dns_map, "fake-ip-filter-mode");
-            copy_mapping_key(src_map, dns_map, "fake-ip-filter");
+/// Mirror for geodata: the first of `explicit` (`--resource-mirror`), or else of
+/// `resource_mirrors` in app.yaml, that answers for `probe_url`; without either, or when none
+/// answers, the GitHub mirrors are probed instead.
+async fn resource_mirror(
+    client: &reqwest::Client,
+    paths: &AppPaths,
+    explicit: &[String],
+    probe_url: &str,
+) -> Option<String> {
+    let configured = if explicit.is_empty() {
+        storage::load_app_config(paths)
+            .await
+            .map(|cfg| cfg.resource_mirrors)
+            .unwrap_or_default()
+    } else {
+        explicit.to_vec()
+    };
+    if !configured.is_empty() {
+        let mirrors: Vec<String> = configured
+            .iter()
+            .map(|mirror| resource_mirror_template(mirror))
+            .collect();
+        if let Some(winner) = pick_mirror(client, &mirrors, None, probe_url).await {
+            info!(mirror = %winner, "using resource mirror");
+            return Some(winner);
         }
+        warn!(
+            mirrors = %configured.join(", "),
+            "no resource mirror answered; trying GitHub mirrors"
+        );
     }
+    github_mirror(client, paths, probe_url).await
+}
 
-    if let Some(ref src_tun) = merged_tun {
-        let tun_map = ensure_mapping_entry(&mut dns_doc, "tun");
-        if let Some(src_map) = src_tun.as_mapping() {
-            copy_mapping_key(src_map, tun_map, "enable");
-            copy_mapping_key(src_map, tun_map, "route-exclude-address");
+/// Probe the configured GitHub mirrors with `probe_url` and remember the winner in app.yaml.
+/// `None` means download from the original URL.
+async fn github_mirror(
+    client: &reqwest::Client,
+    paths: &AppPaths,
+    probe_url: &str,
+) -> Option<String> {
+    let mut app_cfg = storage::load_app_config(paths).await.ok()?;
+    let mirrors = configured_mirrors(&app_cfg.github_mirrors);
+    let preferred = app_cfg.preferred_github_mirror.as_deref();
+    let winner = pick_mirror(client, &mirrors, preferred, probe_url).await;
+    match winner.as_deref() {
+        Some(winner) if Some(winner) != preferred => {
+            info!(mirror = winner, "using GitHub mirror");
+            app_cfg.preferred_github_mirror = Some(winner.to_string());
+            if !storage::is_read_only() {
+                if let Err(err) = storage::save_app_config(paths, &app_cfg).await {
+                    warn!(error = %format!("{:#}", err), "failed to remember GitHub mirror");
+                }
+            }
         }
+        Some(_) => {}
+        None => warn!(
+            url = probe_url,
+            "no GitHub mirror answered; using the original URL"
+        ),
+    }
+    winner
+}
+
+/// Resolve the hostname servers of `config`'s proxies for GeoIP lookups; names that fail to
+/// resolve within a few seconds are left out.
+async fn resolve_proxy_servers(
+    config: &mihomo_core::ClashConfig,
+) -> HashMap<String, std::net::IpAddr> {
+    let hosts: HashSet<String> = config
+        .proxies
+        .iter()
+        .filter_map(|proxy| proxy.get("server").and_then(Value::as_str))
+        .filter(|server| server.parse::<std::net::IpAddr>().is_err())
+        .map(str::to_string)
+        .collect();
+    let mut lookups = tokio::task::JoinSet::new();
+    for host in hosts {
+        lookups.spawn(async move {
+            let lookup = tokio::net::lookup_host((host.as_str(), 0));
+            let addr = tokio::time::timeout(std::time::Duration::from_secs(3), lookup)
+                .await
+                .ok()
+                .and_then(Result::ok)
+                .and_then(|mut addrs| addrs.next())
+                .map(|addr| addr.ip());
+            (host, addr)
+        });
     }
-
-    if let Some(ref src_dns) = merged_dns {
-        let dns_map = ensure_mapping_entry(&mut merge_doc, "dns");
-        if let Some(src_map) = src_dns.as_mapping() {
-            copy_mapping_key(src_map, dns_map, "fake-ip-filter");
+    let mut resolved = HashMap::new();
+    while let Some(joined) = lookups.join_next().await {
+        if let Ok((host, Some(ip))) = joined {
+            resolved.insert(host, ip);
         }
     }
-
-    if let Some(ref src_tun) = merged_tun {
-        let tun_map = ensure_mapping_entry(&mut merge_doc, "tun");
-        if let Some(src_map) = src_tun.as_mapping() {
-            copy_mapping_key(src_map, tun_map, "enable");
-            copy_mapping_key(src_map, tun_map, "route-exclude-address");
+    debug!(
+        resolved = resolved.len(),
+        "resolved proxy servers for GeoIP"
+    );
+    resolved
+}
+
+/// Resource files the config needs at runtime: `geosite.dat` for GEOSITE rules or `geosite:`
+/// DNS policies, and the GeoIP database for GEOIP rules, `geoip:` policies or the DNS
+/// fallback-filter (`geoip.dat` in geodata-mode, `Country.mmdb` otherwise).
+fn referenced_resources(cfg: &mihomo_core::ClashConfig) -> Vec<&'static str> {
+    fn visit(value: &Value, found: &mut (bool, bool)) {
+        match value {
+            Value::String(text) => {
+                let lower = text.to_ascii_lowercase();
+                found.0 |= lower.starts_with("geosite:");
+                found.1 |= lower.starts_with("geoip:");
+            }
+            Value::Sequence(items) => items.iter().for_each(|item| visit(item, found)),
+            Value::Mapping(map) => map.iter().for_each(|(key, value)| {
+                visit(key, found);
+                visit(value, found);
+            }),
+            _ => {}
         }
     }
 
-    if let Some(src_mode) = merged_mode {
-        let root_map = ensure_root_mapping(&mut merge_doc);
-        root_map.insert(Value::String("mode".to_string()), src_mode);
+    let mut found = (false, false);
+    for rule in &cfg.rules {
+        let upper = rule.to_ascii_uppercase();
+        found.0 |= upper.contains("GEOSITE,");
+        found.1 |= upper.contains("GEOIP,");
+    }
+    if let Some(dns) = cfg.extra.get("dns") {
+        visit(dns, &mut found);
+        let fallback_geoip = dns
+            .get("fallback-filter")
+            .and_then(|filter| filter.get("geoip"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        found.1 |= fallback_geoip;
     }
 
-    {
-        let root_map = ensure_root_mapping(&mut merge_doc);
-        let key = Value::String("sniffer".to_string());
-        if let Some(src_sniffer) = merged_sniffer {
-            root_map.insert(key, src_sniffer);
+    let geodata_mode = cfg
+        .extra
+        .get("geodata-mode")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let mut needed = Vec::new();
+    if found.1 {
+        needed.push(if geodata_mode {
+            "geoip.dat"
         } else {
-            root_map.remove(&key);
-        }
+            "Country.mmdb"
+        });
     }
-
-    {
-        let rules_value = ensure_root_mapping(&mut merge_doc)
-            .entry(Value::String("rules".to_string()))
-            .or_insert_with(|| Value::Sequence(Vec::new()));
-        if let Value::Sequence(seq) = rules_value {
-            seq.clear();
-            seq.extend(merged.rules.iter().cloned().map(Value::String));
-        }
+    if found.0 {
+        needed.push("geosite.dat");
     }
+    needed
+}
 
-    if let Some(src_hosts) = merged_hosts {
-        let root_map = ensure_root_mapping(&mut merge_doc);
-        root_map.insert(Value::String("hosts".to_string()), src_hosts);
+/// Download `url` into `<target>.part`, resuming a previous partial download with a `Range`
+/// request, and rename it to `target` once its size matches what the server announced.
+///
+/// The ETag or Last-Modified of the response that started the `.part` is kept in
+/// `<target>.part.validator` and sent as `If-Range`, so a file that changed upstream comes back
+/// whole (200) instead of being appended to the old prefix. A `.part` without a validator is
+/// not resumed.
+async fn download_resource(
+    client: &reqwest::Client,
+    url: &str,
+    target: &Path,
+) -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
     }
+    let mut part_name = target.file_name().unwrap_or_default().to_os_string();
+    part_name.push(".part");
+    let part = target.with_file_name(&part_name);
+    part_name.push(".validator");
+    let validator_file = target.with_file_name(part_name);
 
-    fs::write(&dns_path, serde_yaml::to_string(&dns_doc)?)
-        .await
-        .with_context(|| format!("failed to write {}", dns_path.display()))?;
-    fs::write(&merge_path, serde_yaml::to_string(&merge_doc)?)
+    let validator = fs::read_to_string(&validator_file)
         .await
-        .with_context(|| format!("failed to write {}", merge_path.display()))?;
+        .ok()
+        .map(|raw| raw.trim().to_string())
+        .filter(|raw| !raw.is_empty());
+    let mut offset = match validator {
+        Some(_) => fs::metadata(&part)
+            .await
+            .map(|meta| meta.len())
+            .unwrap_or(0),
+        None => 0,
+    };
+    let mut request = client.get(url);
+    if let Some(validator) = validator.as_deref().filter(|_| offset > 0) {
+        request = request
+            .header(reqwest::header::RANGE, format!("bytes={}-", offset))
+            .header(reqwest::header::IF_RANGE, validator);
+    }
+    let mut response = request.send().await?;
+
+    if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file is no longer a prefix of what the server has; start over.
+        fs::remove_file(&part).await.ok();
+        offset = 0;
+        response = client.get(url).send().await?;
+    }
+    if !response.status().is_success() {
+        return Err(anyhow!("server returned {}", response.status()));
+    }
+
+    let expected = if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+        let total = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_content_range(value, offset));
+        match total {
+            Some(total) => Some(total),
+            None => return Err(anyhow!("server sent an unexpected Content-Range")),
+        }
+    } else {
+        // Full body: the server ignored the range, the file changed, or none was sent.
+        offset = 0;
+        match resume_validator(response.headers()) {
+            Some(validator) => fs::write(&validator_file, validator).await?,
+            None => {
+                fs::remove_file(&validator_file).await.ok();
+            }
+        }
+        response.content_length()
+    };
 
-    println!("synced Clash Verge source config {}", dns_path.display());
-    println!("synced Clash Verge source config {}", merge_path.display());
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(offset > 0)
+        .truncate(offset == 0)
+        .open(&part)
+        .await
+        .with_context(|| format!("failed to open {}", part.display()))?;
+    if offset > 0 {
+        info!(file = %part.display(), offset, "resuming download");
+    }
+    while let Some(chunk) = response.chunk().await? {
+        throttle_download(chunk.len()).await;
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    drop(file);
 
+    let written = fs::metadata(&part).await?.len();
+    if let Some(expected) = expected {
+        if written != expected {
+            return Err(anyhow!(
+                "incomplete download ({} of {} bytes); re-run to resume",
+                written,
+                expected
+            ));
+        }
+    }
+    fs::rename(&part, target).await?;
+    fs::remove_file(&validator_file).await.ok();
     Ok(())
 }
 
-async fn reload_clash_verge_runtime(
-    merged: &mihomo_core::ClashConfig,
-    runtime_paths: &[PathBuf],
-) -> anyhow::Result<()> {
-    use serde_json::json;
-    use serde_yaml::Value;
+/// `If-Range` value identifying the response a `.part` file was started from: a strong ETag,
+/// else Last-Modified. Weak ETags cannot be used with `If-Range`.
+fn resume_validator(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    let header = |name: reqwest::header::HeaderName| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    header(reqwest::header::ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(reqwest::header::LAST_MODIFIED))
+        .map(str::to_string)
+}
 
-    let external_controller = merged
-        .extra
-        .get("external-controller")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("merged config does not define external-controller"))?;
-    let (host, port) = parse_host_port(external_controller).ok_or_else(|| {
+/// Longest a one-off download (dashboard, template, rule list) may take, body included.
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Largest one-off download accepted.
+const MAX_DOWNLOAD_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Fetch `url` for `ui install`, `template add` and `rules import` with the client used for
+/// resources: `fetch_proxy` or the environment's proxy, GitHub URLs through the configured
+/// mirrors, `--max-download-rate`, [`DOWNLOAD_TIMEOUT`] and [`MAX_DOWNLOAD_BYTES`].
+async fn download_bytes(
+    paths: &AppPaths,
+    fetch_proxy: Option<&str>,
+    url: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let client = build_fetch_client(
+        concat!("mihomo-cli/", env!("CARGO_PKG_VERSION")),
+        fetch_proxy,
+        10,
+        None,
+    )?;
+    let url = if is_github_url(url) {
+        github_mirror(&client, paths, url)
+            .await
+            .and_then(|mirror| mirror_url(&mirror, url))
+            .unwrap_or_else(|| url.to_string())
+    } else {
+        url.to_string()
+    };
+    let mut response = client.get(&url).timeout(DOWNLOAD_TIMEOUT).send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("server returned {}", response.status()));
+    }
+    let too_large = || {
         anyhow!(
-            "invalid external-controller value '{}' (expected host:port)",
-            external_controller
+            "download is larger than {} MiB",
+            MAX_DOWNLOAD_BYTES / (1024 * 1024)
         )
-    })?;
+    };
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_DOWNLOAD_BYTES)
+    {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        throttle_download(chunk.len()).await;
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() as u64 > MAX_DOWNLOAD_BYTES {
+            return Err(too_large());
+        }
+    }
+    Ok(bytes)
+}
 
-    let host = normalize_controller_host(&host);
-    let secret = merged
-        .extra
-        .get("secret")
-        .and_then(Value::as_str)
-        .unwrap_or("");
+/// [`download_bytes`] for text sources.
+async fn download_text(
+    paths: &AppPaths,
+    fetch_proxy: Option<&str>,
+    url: &str,
+) -> anyhow::Result<String> {
+    let bytes = download_bytes(paths, fetch_proxy, url)
+        .await
+        .with_context(|| format!("failed to download {}", url))?;
+    String::from_utf8(bytes).with_context(|| format!("{} is not UTF-8 text", url))
+}
 
-    let runtime_path = runtime_paths
-        .iter()
-        .find(|path| path.file_name().and_then(|name| name.to_str()) == Some("config.yaml"))
-        .or_else(|| runtime_paths.first())
-        .ok_or_else(|| anyhow!("no synced Clash Verge runtime path available for reload"))?;
+/// Total size from a `Content-Range: bytes <start>-<end>/<total>` header, provided the
+/// range starts where the partial file ends.
+fn parse_content_range(value: &str, offset: u64) -> Option<u64> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (span, total) = range.split_once('/')?;
+    let (start, _) = span.split_once('-')?;
+    if start.trim().parse::<u64>().ok()? != offset {
+        return None;
+    }
+    total.trim().parse().ok()
+}
 
-    let client = reqwest::Client::builder().build()?;
-    let version_url = format!("http://{}:{}/version", host, port);
-    let mut version_req = client.get(&version_url);
-    if !secret.is_empty() {
-        version_req = version_req.bearer_auth(secret);
+/// What `resources update` did with one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResourceUpdate {
+    Current,
+    Downloaded,
+    Updated,
+}
+
+async fn run_resources_update(args: ResourcesUpdateArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    storage::ensure_writable(&paths.resources_dir())?;
+    let client = build_fetch_client(
+        concat!("mihomo-cli/", env!("CARGO_PKG_VERSION")),
+        args.fetch_proxy.as_deref(),
+        10,
+        None,
+    )?;
+    let mirror = resource_mirror(
+        &client,
+        &paths,
+        &args.resource_mirrors,
+        RESOURCE_SOURCES[0].1,
+    )
+    .await;
+    let via_mirror = |url: &str| {
+        mirror
+            .as_deref()
+            .and_then(|mirror| mirror_url(mirror, url))
+            .unwrap_or_else(|| url.to_string())
+    };
+
+    let mut failed = 0;
+    for (name, url) in RESOURCE_SOURCES.iter() {
+        let target = paths.resource_file(name);
+        let checksum_url = via_mirror(&format!("{}.sha256sum", url));
+        let outcome = update_resource(
+            &client,
+            name,
+            &via_mirror(url),
+            &checksum_url,
+            &target,
+            args.force,
+        )
+        .await;
+        match outcome {
+            Ok(outcome) => {
+                let status = match outcome {
+                    ResourceUpdate::Current => "up to date",
+                    ResourceUpdate::Downloaded => "downloaded",
+                    ResourceUpdate::Updated => "updated",
+                };
+                println!(
+                    "{}: {}, {}",
+                    name,
+                    status,
+                    describe_resource(&target).await?
+                );
+            }
+            Err(err) => {
+                failed += 1;
+                println!("{}: failed: {:#}", name, err);
+            }
+        }
     }
-    version_req.send().await?.error_for_status()?;
+    if failed > 0 {
+        return Err(anyhow!("{} resource(s) could not be updated", failed));
+    }
+    Ok(())
+}
 
-    let reload_url = format!("http://{}:{}/configs", host, port);
-    let mut reload_req = client.put(&reload_url).json(&json!({
-        "path": runtime_path.display().to_string(),
-    }));
-    if !secret.is_empty() {
-        reload_req = reload_req.bearer_auth(secret);
+/// Bring `target` up to date with `url`. The download is staged next to it and replaces it
+/// only after passing the checksum (when upstream publishes one) and the format check.
+async fn update_resource(
+    client: &reqwest::Client,
+    name: &str,
+    url: &str,
+    checksum_url: &str,
+    target: &Path,
+    force: bool,
+) -> anyhow::Result<ResourceUpdate> {
+    let expected = fetch_sha256_file(client, checksum_url).await;
+    let existing = fs::read(target).await.ok();
+    if let Some(local) = existing.as_deref().filter(|_| !force) {
+        let current = match expected.as_deref() {
+            Some(expected) => sha256_hex(local) == expected,
+            None => !resource_changed_upstream(client, url, target).await?,
+        };
+        if current && resource_problem(name, local).is_none() {
+            return Ok(ResourceUpdate::Current);
+        }
     }
-    reload_req.send().await?.error_for_status()?;
 
-    println!("reloaded Clash Verge runtime via {}", reload_url);
-    Ok(())
+    let mut staged_name = target.file_name().unwrap_or_default().to_os_string();
+    staged_name.push(".new");
+    let staged = target.with_file_name(staged_name);
+    download_resource(client, url, &staged).await?;
+    let verified = async {
+        let bytes = fs::read(&staged).await?;
+        if let Some(problem) = resource_problem(name, &bytes) {
+            return Err(anyhow!("downloaded file is invalid: {}", problem));
+        }
+        if let Some(expected) = expected {
+            let actual = sha256_hex(&bytes);
+            if actual != expected {
+                return Err(anyhow!(
+                    "checksum mismatch: expected {}, got {}",
+                    expected,
+                    actual
+                ));
+            }
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(err) = verified {
+        let _ = fs::remove_file(&staged).await;
+        return Err(err);
+    }
+    fs::rename(&staged, target).await?;
+    Ok(if existing.is_some() {
+        ResourceUpdate::Updated
+    } else {
+        ResourceUpdate::Downloaded
+    })
 }
 
-fn ensure_root_mapping<'a>(doc: &'a mut serde_yaml::Value) -> &'a mut serde_yaml::Mapping {
-    if !doc.is_mapping() {
-        *doc = serde_yaml::Value::Mapping(serde_yaml::Mapping::new());
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    hex::encode(sha2::Sha256::digest(bytes))
+}
+
+/// SHA-256 from a `.sha256sum` file, or `None` when it cannot be fetched.
+async fn fetch_sha256_file(client: &reqwest::Client, url: &str) -> Option<String> {
+    let response = client.get(url).send().await.ok()?.error_for_status().ok()?;
+    parse_sha256_file(&response.text().await.ok()?)
+}
+
+/// Ask the server (`HEAD` with `If-Modified-Since`) whether `url` changed after `target` was
+/// written; servers that ignore the header are judged by their `Last-Modified`.
+async fn resource_changed_upstream(
+    client: &reqwest::Client,
+    url: &str,
+    target: &Path,
+) -> anyhow::Result<bool> {
+    let modified: chrono::DateTime<chrono::Utc> = fs::metadata(target).await?.modified()?.into();
+    let response = client
+        .head(url)
+        .header(
+            reqwest::header::IF_MODIFIED_SINCE,
+            modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        )
+        .send()
+        .await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(false);
     }
-    doc.as_mapping_mut().expect("mapping just initialized")
+    if !response.status().is_success() {
+        return Err(anyhow!("server returned {}", response.status()));
+    }
+    let last_modified = response
+        .headers()
+        .get(reqwest::header::LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok());
+    Ok(last_modified.is_none_or(|upstream| upstream > modified))
 }
 
-fn ensure_mapping_entry<'a>(
-    doc: &'a mut serde_yaml::Value,
-    key: &str,
-) -> &'a mut serde_yaml::Mapping {
-    use serde_yaml::{Mapping, Value};
+/// `12.3 MiB, 3d old, sha256 0123456789ab`.
+async fn describe_resource(path: &Path) -> anyhow::Result<String> {
+    let bytes = fs::read(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let age = fs::metadata(path)
+        .await?
+        .modified()
+        .ok()
+        .map(|modified| chrono::Utc::now() - chrono::DateTime::<chrono::Utc>::from(modified));
+    let digest = sha256_hex(&bytes);
+    Ok(format!(
+        "{}, {} old, sha256 {}",
+        format_bytes(bytes.len() as u64),
+        age.map(format_age).unwrap_or_else(|| "-".to_string()),
+        &digest[..12]
+    ))
+}
 
-    let root = ensure_root_mapping(doc);
-    let entry = root
-        .entry(Value::String(key.to_string()))
-        .or_insert_with(|| Value::Mapping(Mapping::new()));
-    if !entry.is_mapping() {
-        *entry = Value::Mapping(Mapping::new());
+async fn run_show(args: ShowArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let path = args
+        .config
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    let raw = fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut config = mihomo_core::ClashConfig::from_yaml_str(&raw)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    if args.redact_secrets {
+        redact_secrets(&mut config);
     }
-    entry.as_mapping_mut().expect("mapping just initialized")
+    let section = args.what.or(args.section);
+    if args.expanded {
+        if section != Some(ShowSection::ProxyGroups) {
+            return Err(anyhow!("--expanded only applies to `show groups`"));
+        }
+        let groups = expand_groups(&config);
+        match args.format {
+            ShowFormat::Yaml => print_expanded_groups(&groups),
+            ShowFormat::Json => println!("{}", serde_json::to_string_pretty(&groups)?),
+        }
+        return Ok(());
+    }
+    let value =
+        select_section(&config, section).with_context(|| format!("in {}", path.display()))?;
+    match args.format {
+        ShowFormat::Yaml => print!("{}", serde_yaml::to_string(&value)?),
+        ShowFormat::Json => println!("{}", serde_json::to_string_pretty(&value)?),
+    }
+    Ok(())
 }
 
-fn copy_mapping_key(src: &serde_yaml::Mapping, dst: &mut serde_yaml::Mapping, key: &str) {
-    let yaml_key = serde_yaml::Value::String(key.to_string());
-    if let Some(value) = src.get(&yaml_key) {
-        dst.insert(yaml_key, value.clone());
+fn print_expanded_groups(groups: &[ExpandedGroup]) {
+    for group in groups {
+        println!(
+            "{} [{}] {} members",
+            group.name,
+            group.kind,
+            group.members.len()
+        );
+        for member in &group.members {
+            let flags: Vec<&str> = [
+                (member.is_group, "group"),
+                (member.included, "include-all"),
+                (member.matches_filter, "filter"),
+                (member.unknown, "unknown"),
+            ]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect();
+            if flags.is_empty() {
+                println!("  {}", member.name);
+            } else {
+                println!("  {}  ({})", member.name, flags.join(", "));
+            }
+        }
+        for provider in &group.providers {
+            println!("  + provider {} (members known at runtime)", provider);
+        }
+        for problem in &group.problems {
+            println!("  ! {}", problem);
+        }
     }
 }
 
-fn resolve_template_path(paths: &AppPaths, provided: &Path) -> PathBuf {
-    if provided.is_absolute() {
-        provided.to_path_buf()
-    } else {
-        let candidate = paths.templates_dir().join(provided);
-        if candidate.exists() {
-            candidate
-        } else {
-            provided.to_path_buf()
+async fn run_ctl(args: CtlArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let client = controller_client(&paths, &args.target).await?;
+    match args.command {
+        CtlCommand::Version => println!("{}", client.version().await?),
+        CtlCommand::Groups(opts) => {
+            for group in client.groups().await? {
+                let now = group.now.as_deref().unwrap_or("-");
+                println!(
+                    "{} [{}] -> {} ({} members)",
+                    group.name,
+                    group.kind,
+                    now,
+                    group.members.len()
+                );
+                if opts.members {
+                    for member in &group.members {
+                        let marker = if Some(member) == group.now.as_ref() {
+                            "*"
+                        } else {
+                            " "
+                        };
+                        println!("  {} {}", marker, member);
+                    }
+                }
+            }
+        }
+        CtlCommand::Select(select) => {
+            client.select(&select.group, &select.proxy).await?;
+            println!("{} -> {}", select.group, select.proxy);
+        }
+        CtlCommand::DumpSelections(opts) => {
+            let selections: serde_yaml::Mapping = controller::selections(&client.groups().await?)
+                .into_iter()
+                .map(|(group, proxy)| (Value::from(group), Value::from(proxy)))
+                .collect();
+            let yaml = if selections.is_empty() {
+                "{}\n".to_string()
+            } else {
+                serde_yaml::to_string(&selections)?
+            };
+            match opts.output {
+                Some(path) => {
+                    fs::write(&path, yaml)
+                        .await
+                        .with_context(|| format!("failed to write {}", path.display()))?;
+                    println!(
+                        "saved {} selection(s) to {}",
+                        selections.len(),
+                        path.display()
+                    );
+                }
+                None => print!("{}", yaml),
+            }
+        }
+        CtlCommand::ApplySelections(opts) => ctl_apply_selections(&client, &opts.file).await?,
+        CtlCommand::Delay(delay) => {
+            let timeout = std::time::Duration::from_millis(delay.timeout_ms);
+            let ms = client.delay(&delay.proxy, &delay.url, timeout).await?;
+            println!("{}: {} ms", delay.proxy, ms);
+        }
+        CtlCommand::Traffic(traffic) => {
+            let mut seen = 0;
+            client
+                .traffic(|sample, raw| {
+                    if traffic.json {
+                        println!("{}", raw);
+                    } else {
+                        println!(
+                            "up {}/s  down {}/s",
+                            format_bytes(sample.up),
+                            format_bytes(sample.down)
+                        );
+                    }
+                    seen += 1;
+                    traffic.count.is_none_or(|count| seen < count)
+                })
+                .await?;
+        }
+        CtlCommand::Connections(opts) => ctl_connections(&client, opts).await?,
+        CtlCommand::Logs(opts) => {
+            use std::io::IsTerminal;
+            let color = !opts.no_color
+                && std::env::var_os("NO_COLOR").is_none()
+                && std::io::stdout().is_terminal();
+            client
+                .logs(opts.level.as_str(), opts.filter.as_deref(), |entry, raw| {
+                    if opts.json {
+                        println!("{}", raw);
+                    } else {
+                        println!("{} {}", log_level_label(&entry, color), entry.payload);
+                    }
+                    true
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Select the proxies listed in `file`. Groups the running config no longer has, or that no
+/// longer list the proxy, are skipped with a note; errors from mihomo fail the command after
+/// the remaining groups were tried.
+async fn ctl_apply_selections(client: &ControllerClient, file: &Path) -> anyhow::Result<()> {
+    let raw = fs::read_to_string(file)
+        .await
+        .with_context(|| format!("failed to read {}", file.display()))?;
+    let wanted: serde_yaml::Mapping = match serde_yaml::from_str(&raw)
+        .with_context(|| format!("failed to parse {}", file.display()))?
+    {
+        Value::Mapping(map) => map,
+        Value::Null => serde_yaml::Mapping::new(),
+        _ => {
+            return Err(anyhow!(
+                "{} must map group names to proxy names",
+                file.display()
+            ))
+        }
+    };
+    let groups = client.groups().await?;
+    let (mut applied, mut failed) = (0, 0);
+    for (group, proxy) in &wanted {
+        let (Some(group), Some(proxy)) = (group.as_str(), proxy.as_str()) else {
+            return Err(anyhow!(
+                "{} must map group names to proxy names",
+                file.display()
+            ));
+        };
+        if let Some(problem) = controller::selection_problem(&groups, group, proxy) {
+            eprintln!("skipped {} -> {}: {}", group, proxy, problem);
+            continue;
+        }
+        match client.select(group, proxy).await {
+            Ok(()) => {
+                println!("{} -> {}", group, proxy);
+                applied += 1;
+            }
+            Err(err) => {
+                eprintln!("failed {} -> {}: {:#}", group, proxy, err);
+                failed += 1;
+            }
         }
     }
+    println!("applied {} of {} selection(s)", applied, wanted.len());
+    if failed > 0 {
+        return Err(anyhow!("{} selection(s) failed", failed));
+    }
+    Ok(())
 }
 
-fn resolve_base_path(paths: &AppPaths, provided: &Path) -> PathBuf {
-    if provided.is_absolute() {
-        provided.to_path_buf()
+/// `[level]` padded to a fixed width, in the level's ANSI color when `color` is set.
+fn log_level_label(entry: &LogEntry, color: bool) -> String {
+    let label = format!("{:<9}", format!("[{}]", entry.level));
+    let code = match entry.level.as_str() {
+        "debug" => "2",
+        "info" => "36",
+        "warning" => "33",
+        "error" => "31",
+        _ => return label,
+    };
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", code, label)
     } else {
-        let candidate = paths.config_dir().join(provided);
-        if candidate.exists() {
-            candidate
-        } else {
-            provided.to_path_buf()
-        }
+        label
     }
 }
 
-const DEFAULT_DEV_RULE_VIA: &str = "Proxy";
+async fn ctl_connections(
+    client: &ControllerClient,
+    opts: CtlConnectionsArgs,
+) -> anyhow::Result<()> {
+    let mut body = client.connections().await?;
+    let connections: Vec<ConnectionInfo> = connections_from_json(&body)
+        .into_iter()
+        .filter(|connection| {
+            opts.filter
+                .as_deref()
+                .is_none_or(|needle| connection.matches(needle))
+        })
+        .collect();
 
-fn resolve_dev_rules_via(via: &str, default_via: &str, cfg: &mihomo_core::ClashConfig) -> String {
-    // If the requested via exists as a group or proxy, use it as-is.
-    let group_names = cfg.proxy_group_names();
-    let proxy_names = cfg.proxy_names();
-    if group_names.iter().any(|n| n == via) || proxy_names.iter().any(|n| n == via) {
-        return via.to_string();
+    if opts.kill {
+        if opts.filter.is_none() {
+            client.close_all_connections().await?;
+        } else {
+            for connection in &connections {
+                client.close_connection(&connection.id).await?;
+            }
+        }
+        println!("closed {} connection(s)", connections.len());
+        return Ok(());
     }
 
-    // If the user explicitly set a via different from our default, respect it even if missing.
-    // This preserves explicit intent; mihomo will surface the error if it's invalid.
-    if via != default_via {
-        return via.to_string();
+    if opts.json {
+        if opts.filter.is_some() {
+            let keep: HashSet<&str> = connections.iter().map(|c| c.id.as_str()).collect();
+            if let Some(list) = body
+                .get_mut("connections")
+                .and_then(serde_json::Value::as_array_mut)
+            {
+                list.retain(|entry| {
+                    entry
+                        .get("id")
+                        .and_then(serde_json::Value::as_str)
+                        .is_some_and(|id| keep.contains(id))
+                });
+            }
+        }
+        println!("{}", serde_json::to_string_pretty(&body)?);
+        return Ok(());
     }
 
-    // Prefer common selector name if present.
-    let common = "🚀 节点选择";
-    if group_names.iter().any(|n| n == common) {
-        return common.to_string();
+    for connection in &connections {
+        let short_id: String = connection.id.chars().take(8).collect();
+        println!(
+            "{}  {} {}:{}  {}  [{}]  up {} down {}{}",
+            short_id,
+            connection.network,
+            connection.host,
+            connection.port,
+            connection.chains.join(">"),
+            connection.rule,
+            format_bytes(connection.upload),
+            format_bytes(connection.download),
+            connection
+                .process
+                .as_deref()
+                .map(|process| format!("  ({})", process))
+                .unwrap_or_default()
+        );
     }
+    println!("{} connection(s)", connections.len());
+    Ok(())
+}
 
-    // Otherwise pick the first available group, then first proxy, else last-resort DIRECT.
-    if let Some(first_group) = group_names.first() {
-        return first_group.clone();
+/// Controller client for `target`: explicit flags first, then the config's
+/// external-controller/secret, then app.yaml's generated secret.
+async fn controller_client(
+    paths: &AppPaths,
+    target: &ControllerTarget,
+) -> anyhow::Result<ControllerClient> {
+    let config_path = target
+        .config
+        .clone()
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    let config = match fs::read_to_string(&config_path).await {
+        Ok(raw) => Some(
+            mihomo_core::ClashConfig::from_yaml_str(&raw)
+                .with_context(|| format!("failed to parse {}", config_path.display()))?,
+        ),
+        Err(_) if target.controller.is_some() => None,
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!(
+                    "failed to read {} (pass --controller to skip it)",
+                    config_path.display()
+                )
+            })
+        }
+    };
+    let base = match target.controller.as_deref() {
+        Some(url) if is_url(url) || url.starts_with("unix://") => url.to_string(),
+        Some(host_port) => format!("http://{}", host_port),
+        None => config
+            .as_ref()
+            .and_then(|cfg| {
+                controller_endpoint(cfg).or_else(|| secure_controller_endpoint(cfg, &config_path))
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} does not define an external controller; pass --controller",
+                    config_path.display()
+                )
+            })?,
+    };
+    let mut secret = target.secret.clone().or_else(|| {
+        config
+            .as_ref()
+            .and_then(|cfg| cfg.extra.get("secret"))
+            .and_then(Value::as_str)
+            .filter(|secret| !secret.is_empty())
+            .map(str::to_string)
+    });
+    if secret.is_none() {
+        secret = storage::load_app_config(paths)
+            .await
+            .ok()
+            .and_then(|cfg| cfg.controller_secret);
     }
-    if let Some(first_proxy) = proxy_names.first() {
-        return first_proxy.clone();
+    if let Some(value) = secret.as_deref() {
+        secret = Some(secrets::resolve_secret(value).await?);
     }
-    "DIRECT".to_string()
+    ControllerClient::with_ca_cert(&base, secret.as_deref(), target.ca_cert.as_deref())
 }
 
-// Built-in developer/AI endpoints considered proxy-worthy.
-// Tuple format: (rule kind, target)
-// - Use DOMAIN for exact host matches
-// - Use DOMAIN-SUFFIX for suffix matches
-const DEV_RULE_TARGETS: &[(&str, &str)] = &[
-    // Git & code hosting
-    ("DOMAIN-SUFFIX", "api.github.com"),
-    ("DOMAIN-SUFFIX", "github.com"),
-    ("DOMAIN-SUFFIX", "github.dev"),
-    ("DOMAIN-SUFFIX", "githubassets.com"),
-    ("DOMAIN-SUFFIX", "githubusercontent.com"),
-    ("DOMAIN-SUFFIX", "raw.githubusercontent.com"),
-    ("DOMAIN-SUFFIX", "codeload.github.com"),
-    ("DOMAIN-SUFFIX", "release-assets.githubusercontent.com"),
-    ("DOMAIN-SUFFIX", "gitlab.com"),
-    ("DOMAIN-SUFFIX", "bitbucket.org"),
-    // Language ecosystems / registries
-    ("DOMAIN-SUFFIX", "registry.npmjs.org"),
-    ("DOMAIN-SUFFIX", "registry.yarnpkg.com"),
-    ("DOMAIN-SUFFIX", "registry.npmjs.com"),
-    ("DOMAIN-SUFFIX", "nodejs.org"),
-    ("DOMAIN-SUFFIX", "pypi.org"),
-    ("DOMAIN-SUFFIX", "files.pythonhosted.org"),
-    ("DOMAIN-SUFFIX", "pythonhosted.org"),
-    ("DOMAIN-SUFFIX", "crates.io"),
-    ("DOMAIN-SUFFIX", "index.crates.io"),
-    ("DOMAIN-SUFFIX", "static.crates.io"),
-    ("DOMAIN-SUFFIX", "rubygems.org"),
-    ("DOMAIN-SUFFIX", "golang.org"),
-    ("DOMAIN-SUFFIX", "go.dev"),
-    ("DOMAIN-SUFFIX", "proxy.golang.org"),
-    ("DOMAIN-SUFFIX", "sum.golang.org"),
-    ("DOMAIN-SUFFIX", "pkg.go.dev"),
-    ("DOMAIN-SUFFIX", "golang.google.cn"),
-    ("DOMAIN-SUFFIX", "rust-lang.org"),
-    ("DOMAIN-SUFFIX", "static.rust-lang.org"),
-    ("DOMAIN-SUFFIX", "doc.rust-lang.org"),
-    // Kubernetes / cloud tooling
-    ("DOMAIN-SUFFIX", "k8s.io"),
-    ("DOMAIN-SUFFIX", "dl.k8s.io"),
-    ("DOMAIN-SUFFIX", "k3s.io"),
-    ("DOMAIN-SUFFIX", "vultr.com"),
-    ("DOMAIN-SUFFIX", "vultrstatus.com"),
-    // Containers / registries
-    ("DOMAIN-SUFFIX", "docker.com"),
-    ("DOMAIN-SUFFIX", "docker.io"),
-    ("DOMAIN-SUFFIX", "registry-1.docker.io"),
-    ("DOMAIN-SUFFIX", "ghcr.io"),
-    ("DOMAIN-SUFFIX", "gcr.io"),
-    ("DOMAIN-SUFFIX", "pkg.dev"),
-    ("DOMAIN-SUFFIX", "quay.io"),
-    // Nix infra
-    ("DOMAIN", "cache.nixos.org"),
-    ("DOMAIN-SUFFIX", "channels.nixos.org"),
-    ("DOMAIN-SUFFIX", "releases.nixos.org"),
-    ("DOMAIN-SUFFIX", "nixos.org"),
-    ("DOMAIN-SUFFIX", "nix.dev"),
-    ("DOMAIN-SUFFIX", "cachix.org"),
-    ("DOMAIN-SUFFIX", "flakehub.com"),
-    ("DOMAIN-SUFFIX", "determinate.systems"),
-    // AI APIs
-    ("DOMAIN-SUFFIX", "api.openai.com"),
-    ("DOMAIN-SUFFIX", "api.anthropic.com"),
-    ("DOMAIN-SUFFIX", "claude.ai"),
-    ("DOMAIN-SUFFIX", "platform.claude.com"),
-    ("DOMAIN-SUFFIX", "anthropic.com"),
-    ("DOMAIN-SUFFIX", "openai.com"),
-    ("DOMAIN-SUFFIX", "chatgpt.com"),
-    ("DOMAIN-SUFFIX", "openrouter.ai"),
-    ("DOMAIN-SUFFIX", "ai.google.dev"),
-    ("DOMAIN-SUFFIX", "generativelanguage.googleapis.com"),
-    ("DOMAIN-SUFFIX", "gemini.google.com"),
-    ("DOMAIN-SUFFIX", "cursor.com"),
-    ("DOMAIN-SUFFIX", "cursor.sh"),
-];
-
-fn build_dev_rules(via: &str) -> Vec<String> {
-    DEV_RULE_TARGETS
-        .iter()
-        .map(|(kind, target)| format!("{kind},{target},{via}"))
-        .collect()
+/// `external-controller-tls` as an https:// URL, else `external-controller-unix` as a
+/// unix:// one (a relative socket path is resolved against the config's directory, which
+/// mihomo uses as its home when started with `-f`).
+fn secure_controller_endpoint(
+    cfg: &mihomo_core::ClashConfig,
+    config_path: &Path,
+) -> Option<String> {
+    let field = |key: &str| {
+        cfg.extra
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|v| !v.is_empty())
+    };
+    if let Some((host, port)) = field("external-controller-tls").and_then(parse_host_port) {
+        return Some(format!(
+            "https://{}:{}",
+            normalize_controller_host(&host),
+            port
+        ));
+    }
+    let socket = Path::new(field("external-controller-unix")?);
+    let socket = match config_path.parent() {
+        Some(dir) if socket.is_relative() => dir.join(socket),
+        _ => socket.to_path_buf(),
+    };
+    Some(format!("unix://{}", socket.display()))
 }
 
-fn domain_matches_rule(kind: &str, target: &str, domain: &str) -> bool {
-    let d = domain.to_ascii_lowercase();
-    let t = target.to_ascii_lowercase();
-    match kind {
-        "DOMAIN" => d == t,
-        "DOMAIN-SUFFIX" => d == t || d.ends_with(&format!(".{t}")),
-        "DOMAIN-KEYWORD" => d.contains(&t),
-        _ => false,
+async fn run_nodes_find(args: NodesFindArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let path = args
+        .config
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    let config = load_runtime_config(&path).await?;
+    let found = find_proxies(&config, &args.pattern, &args.types)?;
+    if found.is_empty() {
+        return Err(anyhow!(
+            "no proxies in {} match '{}'",
+            path.display(),
+            args.pattern
+        ));
+    }
+    for node in &found {
+        let endpoint = match (node.server.as_deref(), node.port.as_deref()) {
+            (Some(server), Some(port)) => format!("{}:{}", server, port),
+            (Some(server), None) => server.to_string(),
+            _ => "-".to_string(),
+        };
+        let groups = if node.groups.is_empty() {
+            "(no groups)".to_string()
+        } else {
+            node.groups.join(", ")
+        };
+        println!(
+            "{}  [{}]  {}  groups: {}",
+            node.name, node.kind, endpoint, groups
+        );
+        if args.full {
+            for line in serde_yaml::to_string(&node.entry)?.lines() {
+                println!("    {}", line);
+            }
+        }
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn dev_rules_use_selected_via() {
-        let via = "MyProxy";
-        let rules = build_dev_rules(via);
-        assert!(rules
-            .iter()
-            .all(|rule| rule.ends_with(&format!(",{}", via))));
-        for prefix in [
-            "DOMAIN-SUFFIX,api.github.com,",
-            "DOMAIN-SUFFIX,github.com,",
-            "DOMAIN-SUFFIX,registry.npmjs.org,",
-            "DOMAIN-SUFFIX,pypi.org,",
-            "DOMAIN-SUFFIX,crates.io,",
-            "DOMAIN-SUFFIX,index.crates.io,",
-            "DOMAIN-SUFFIX,proxy.golang.org,",
-            "DOMAIN-SUFFIX,golang.google.cn,",
-            "DOMAIN-SUFFIX,rust-lang.org,",
-            "DOMAIN-SUFFIX,static.rust-lang.org,",
-            "DOMAIN-SUFFIX,k3s.io,",
-            "DOMAIN-SUFFIX,vultr.com,",
-            "DOMAIN-SUFFIX,api.openai.com,",
-            "DOMAIN-SUFFIX,api.anthropic.com,",
-            "DOMAIN-SUFFIX,claude.ai,",
-            "DOMAIN-SUFFIX,platform.claude.com,",
-            "DOMAIN-SUFFIX,anthropic.com,",
-            "DOMAIN-SUFFIX,openai.com,",
-            "DOMAIN-SUFFIX,chatgpt.com,",
-            "DOMAIN,cache.nixos.org,",
-            "DOMAIN-SUFFIX,channels.nixos.org,",
-            "DOMAIN-SUFFIX,cachix.org,",
-            "DOMAIN-SUFFIX,openrouter.ai,",
-            "DOMAIN-SUFFIX,dl.k8s.io,",
-        ] {
-            assert!(
-                rules.iter().any(|rule| rule.starts_with(prefix)),
-                "missing {prefix}"
+async fn run_export_links(args: ExportLinksArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let path = args
+        .config
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    let config = load_runtime_config(&path).await?;
+    let pattern = args.filter.as_deref().unwrap_or("");
+    let found = find_proxies(&config, pattern, &args.types)?;
+    let mut links = Vec::new();
+    for node in &found {
+        match share_link(&node.entry) {
+            Ok(link) => links.push(link),
+            Err(err) => eprintln!("skipping {}: {:#}", node.name, err),
+        }
+    }
+    if links.is_empty() {
+        return Err(anyhow!(
+            "no proxies in {} could be exported as share links",
+            path.display()
+        ));
+    }
+    match args.format {
+        LinkFormat::Plain => {
+            for link in &links {
+                println!("{}", link);
+            }
+        }
+        LinkFormat::Base64 => {
+            use base64::Engine;
+            println!(
+                "{}",
+                base64::engine::general_purpose::STANDARD.encode(links.join("\n"))
             );
         }
     }
-
-    #[test]
-    fn attach_group_appends_without_duplicates() {
-        use serde_yaml::Value;
-
-        let mut groups: Vec<Value> =
-            vec![
-                serde_yaml::from_str("name: \"BosLife\"\ntype: select\nproxies:\n  - A\n  - B\n")
-                    .unwrap(),
-            ];
-
-        let names = vec!["B".to_string(), "jp-vultr".to_string()];
-        assert!(attach_proxy_names_to_group(&mut groups, "BosLife", &names));
-
-        let map = groups[0].as_mapping().unwrap();
-        let seq = map
-            .get(&Value::from("proxies"))
-            .and_then(|v| v.as_sequence())
-            .unwrap();
-        let items: Vec<_> = seq.iter().filter_map(|v| v.as_str()).collect();
-        assert_eq!(items, vec!["A", "B", "jp-vultr"]);
+    Ok(())
+}
+
+async fn run_parse(args: ParseArgs) -> anyhow::Result<()> {
+    let raw = match &args.file {
+        Some(path) => fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?,
+        None => {
+            use tokio::io::AsyncReadExt;
+            let mut raw = String::new();
+            tokio::io::stdin()
+                .read_to_string(&mut raw)
+                .await
+                .context("failed to read standard input")?;
+            raw
+        }
+    };
+    let opts = ParseOptions {
+        allow_base64: args.allow_base64,
+    };
+    let (config, format) = match detect_and_parse(&raw, opts) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            let reasons = explain_parse_failure(&raw, opts);
+            let mut message = format!("{:#}", err);
+            for reason in reasons.iter().take(10) {
+                message.push_str(&format!("\n  - {}", reason));
+            }
+            if reasons.len() > 10 {
+                message.push_str(&format!("\n  ... and {} more", reasons.len() - 10));
+            }
+            return Err(anyhow!(message));
+        }
+    };
+    println!(
+        "format: {} ({} proxies)",
+        format.as_str(),
+        config.proxies.len()
+    );
+    for proxy in &config.proxies {
+        let field = |key: &str| match proxy.get(key) {
+            Some(Value::String(text)) => Some(text.clone()),
+            Some(Value::Number(number)) => Some(number.to_string()),
+            _ => None,
+        };
+        let endpoint = match (field("server"), field("port")) {
+            (Some(server), Some(port)) => format!("{}:{}", server, port),
+            (Some(server), None) => server,
+            _ => "-".to_string(),
+        };
+        println!(
+            "{}  [{}]  {}",
+            field("name").unwrap_or_else(|| "(unnamed)".to_string()),
+            field("type").unwrap_or_else(|| "?".to_string()),
+            endpoint
+        );
     }
+    Ok(())
+}
 
-    #[test]
-    fn parse_scutil_proxy_detects_enabled_entries() {
-        let raw = r#"
-<dictionary> {
-  HTTPEnable : 1
-  HTTPPort : 7897
-  HTTPProxy : 127.0.0.1
-  HTTPSEnable : 1
-  HTTPSPort : 7897
-  HTTPSProxy : 127.0.0.1
-  SOCKSEnable : 1
-  SOCKSPort : 7897
-  SOCKSProxy : 127.0.0.1
+/// The whole config, or the value of one top-level section.
+fn select_section(
+    config: &mihomo_core::ClashConfig,
+    section: Option<ShowSection>,
+) -> anyhow::Result<Value> {
+    let mut value = serde_yaml::to_value(config)?;
+    let Some(section) = section else {
+        return Ok(value);
+    };
+    value
+        .as_mapping_mut()
+        .and_then(|map| map.shift_remove(section.key()))
+        .ok_or_else(|| anyhow!("config has no `{}` section", section.key()))
 }
-"#;
 
-        let entries = parse_scutil_proxy(raw);
-        assert_eq!(entries.len(), 3);
-        assert_eq!(entries[0].kind, "HTTP");
-        assert_eq!(entries[1].kind, "HTTPS");
-        assert_eq!(entries[2].kind, "SOCKS");
+async fn run_template(args: TemplateArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    paths.ensure_runtime_dirs().await?;
+    match args.command {
+        TemplateCommand::Add(args) => template_add(&paths, args).await,
+        TemplateCommand::List => template_list(&paths).await,
+        TemplateCommand::Show(args) => {
+            let path = stored_template_path(&paths, &args.name).await?;
+            let raw = fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            if args.redact_secrets {
+                let mut template: mihomo_core::ClashConfig = serde_yaml::from_str(&raw)
+                    .with_context(|| format!("failed to parse {}", path.display()))?;
+                redact_secrets(&mut template);
+                print!("{}", template.to_yaml_string()?);
+            } else {
+                print!("{}", raw);
+            }
+            Ok(())
+        }
+        TemplateCommand::Use(args) => {
+            let mut app_cfg = storage::load_app_config(&paths).await?;
+            if args.name == "default" {
+                app_cfg.template = None;
+            } else {
+                stored_template_path(&paths, &args.name).await?;
+                app_cfg.template = Some(args.name.clone());
+            }
+            storage::save_app_config(&paths, &app_cfg).await?;
+            println!("merge now uses template '{}'", args.name);
+            Ok(())
+        }
     }
+}
 
-    #[test]
-    fn parse_windows_proxy_query_detects_per_protocol_entries() {
-        let enable = r#"
-HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
-    ProxyEnable    REG_DWORD    0x1
-"#;
-        let server = r#"
-HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
-    ProxyServer    REG_SZ    http=127.0.0.1:7897;https=127.0.0.1:7897;socks=127.0.0.1:7897
-"#;
+/// Names double as file stems in the templates dir.
+fn is_template_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "default"
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+        && !name.ends_with(".yaml")
+        && !name.ends_with(".yml")
+}
 
-        let entries = parse_windows_proxy_query(enable, server);
-        assert_eq!(entries.len(), 3);
-        assert_eq!(entries[0].kind, "HTTP");
-        assert_eq!(entries[1].kind, "HTTPS");
-        assert_eq!(entries[2].kind, "SOCKS");
+async fn stored_template_path(paths: &AppPaths, name: &str) -> anyhow::Result<PathBuf> {
+    if name == "default" {
+        return Ok(paths.default_template_path());
+    }
+    let path = paths.named_template_path(name);
+    if !is_template_name(name) || !fs::try_exists(&path).await.unwrap_or(false) {
+        let index = storage::load_template_index(paths).await?;
+        let known: Vec<&str> = index.keys().map(String::as_str).collect();
+        return Err(anyhow!(
+            "unknown template '{}' (known: {})",
+            name,
+            if known.is_empty() {
+                "<none>".to_string()
+            } else {
+                known.join(", ")
+            }
+        ));
     }
+    Ok(path)
+}
 
-    #[test]
-    fn parse_windows_proxy_query_detects_single_wininet_proxy() {
-        let enable = r#"
-HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
-    ProxyEnable    REG_DWORD    0x00000001
-"#;
-        let server = r#"
-HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
-    ProxyServer    REG_SZ    127.0.0.1:7897
-"#;
+async fn template_add(paths: &AppPaths, args: TemplateAddArgs) -> anyhow::Result<()> {
+    let is_url = args.source.starts_with("http://") || args.source.starts_with("https://");
+    let (source, raw) = if is_url {
+        let raw = download_text(paths, args.fetch_proxy.as_deref(), &args.source).await?;
+        (args.source.clone(), raw)
+    } else {
+        let path = std::fs::canonicalize(&args.source)
+            .with_context(|| format!("failed to read {}", args.source))?;
+        let raw = fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        (path.display().to_string(), raw)
+    };
 
-        let entries = parse_windows_proxy_query(enable, server);
-        assert_eq!(entries.len(), 1);
-        assert_eq!(entries[0].kind, "WININET");
-        assert_eq!(entries[0].host, "127.0.0.1");
-        assert_eq!(entries[0].port, "7897");
+    let name = match args.name.clone() {
+        Some(name) => name,
+        None => {
+            let tail = source
+                .trim_end_matches('/')
+                .rsplit(['/', '\\'])
+                .next()
+                .unwrap_or_default();
+            let tail = tail.split(['?', '#']).next().unwrap_or_default();
+            Path::new(tail)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        }
+    };
+    if !is_template_name(&name) || name == "cvr_template" {
+        return Err(anyhow!(
+            "invalid template name '{}' (use letters, digits, '-', '_' or '.'; pass --name)",
+            name
+        ));
     }
 
-    #[test]
-    fn split_list_items_handles_commas_spaces_and_newlines() {
-        assert_eq!(
-            split_list_items("foo.com, bar.com\nbaz.com"),
-            vec!["foo.com", "bar.com", "baz.com"]
-        );
+    let template = Template::from_yaml_str(&raw)
+        .with_context(|| format!("{} is not a valid template", source))?;
+    for finding in template.lint() {
+        eprintln!("note: {}", finding);
     }
-
-    #[test]
-    fn extract_current_clash_verge_subscription_url_prefers_current_uid() {
-        let profiles = ClashVergeProfiles {
-            current: Some("active".to_string()),
-            items: vec![
-                ClashVergeProfileItem {
-                    uid: Some("other".to_string()),
-                    url: Some("https://example.com/other.yaml".to_string()),
-                },
-                ClashVergeProfileItem {
-                    uid: Some("active".to_string()),
-                    url: Some("https://example.com/active.yaml".to_string()),
-                },
-            ],
-        };
-
-        assert_eq!(
-            extract_current_clash_verge_subscription_url(&profiles).as_deref(),
-            Some("https://example.com/active.yaml")
+    if let Some(parent) = serde_yaml::from_str::<Value>(&raw).ok().and_then(|value| {
+        value
+            .get("extends")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    }) {
+        eprintln!(
+            "note: extends: {} is resolved relative to {} at merge time",
+            parent,
+            paths.templates_dir().display()
         );
     }
 
-    #[test]
-    fn infer_connection_path_prefers_local_proxy_for_default_mixed() {
-        let record = ConnectionRecord {
-            host: Some("chatgpt.com".to_string()),
-            inbound_name: Some("DEFAULT-MIXED".to_string()),
-            source_ip: Some("127.0.0.1".to_string()),
-            chains: vec!["BosLife".to_string()],
-        };
+    let mut index = storage::load_template_index(paths).await?;
+    let now = chrono::Utc::now();
+    let added = match index.get(&name) {
+        Some(existing) if existing.source != source && !args.force => {
+            return Err(anyhow!(
+                "template '{}' was added from {}; pass --force to replace it",
+                name,
+                existing.source
+            ));
+        }
+        Some(existing) => existing.added,
+        None => now,
+    };
+    let sha256 = {
+        use sha2::Digest;
+        hex::encode(sha2::Sha256::digest(raw.as_bytes()))
+    };
+    let path = paths.named_template_path(&name);
+    storage::ensure_writable(&path)?;
+    fs::write(&path, &raw)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    index.insert(
+        name.clone(),
+        storage::TemplateEntry {
+            source,
+            sha256,
+            added,
+            updated: now,
+        },
+    );
+    storage::save_template_index(paths, &index).await?;
+    println!("stored template '{}' at {}", name, path.display());
+    Ok(())
+}
 
-        assert_eq!(
-            infer_connection_path(&[&record]),
-            "local proxy / DEFAULT-MIXED"
+async fn template_list(paths: &AppPaths) -> anyhow::Result<()> {
+    let index = storage::load_template_index(paths).await?;
+    let current = storage::load_app_config(paths).await?.template;
+    let marker = |selected: bool| if selected { "*" } else { " " };
+    println!(
+        "{} {:<20} {:<17} (bundled)",
+        marker(current.is_none()),
+        "default",
+        ""
+    );
+    for (name, entry) in &index {
+        println!(
+            "{} {:<20} {:<17} {}",
+            marker(current.as_deref() == Some(name.as_str())),
+            name,
+            entry.updated.format("%Y-%m-%d %H:%M"),
+            entry.source
         );
     }
+    Ok(())
+}
 
-    #[test]
-    fn tailscale_compatibility_rewrites_fake_ip_and_adds_exclusions() {
-        use serde_yaml::Value;
-
-        let raw = r#"
-dns:
-  enhanced-mode: fake-ip
-  fake-ip-range: 198.18.0.1/16
-  fake-ip-filter:
-    - "*.local"
-tun:
-  enable: true
-"#;
-        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(raw).unwrap();
-
-        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
+async fn run_history(cmd: HistoryCommand) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    match cmd {
+        HistoryCommand::List => history_list(&paths).await,
+        HistoryCommand::Rollback(args) => history_rollback(&paths, args).await,
+    }
+}
 
-        let dns = cfg.extra.get("dns").and_then(|v| v.as_mapping()).unwrap();
-        assert_eq!(
-            dns.get(Value::String("fake-ip-range".into()))
-                .and_then(|v| v.as_str()),
-            Some(SAFE_FAKE_IP_RANGE)
-        );
-        let filters = dns
-            .get(Value::String("fake-ip-filter".into()))
-            .and_then(|v| v.as_sequence())
-            .unwrap();
-        for item in build_tailscale_fake_ip_bypass(&[String::from("zhsjf.cn")], &[]) {
-            assert!(filters.iter().any(|v| v.as_str() == Some(item.as_str())));
+async fn history_list(paths: &AppPaths) -> anyhow::Result<()> {
+    let entries = history::list(&paths.history_dir()).await?;
+    if entries.is_empty() {
+        println!("no configs in {}", paths.history_dir().display());
+        return Ok(());
+    }
+    let default_output = paths.generated_clash_verge_path();
+    let mut current: HashMap<PathBuf, Option<String>> = HashMap::new();
+    for entry in &entries {
+        if !current.contains_key(&entry.output) {
+            let id = fs::read_to_string(&entry.output)
+                .await
+                .ok()
+                .map(|yaml| history::config_id(&yaml));
+            current.insert(entry.output.clone(), id);
         }
-
-        let tun = cfg.extra.get("tun").and_then(|v| v.as_mapping()).unwrap();
-        let excludes = tun
-            .get(Value::String("route-exclude-address".into()))
-            .and_then(|v| v.as_sequence())
-            .unwrap();
-        for cidr in TAILSCALE_ROUTE_EXCLUDES {
-            assert!(excludes.iter().any(|v| v.as_str() == Some(cidr)));
+        let in_place = current[&entry.output].as_deref() == Some(entry.id.as_str());
+        let subscriptions = if entry.subscriptions.is_empty() {
+            "(no subscriptions)".to_string()
+        } else {
+            entry.subscriptions.join(", ")
+        };
+        let mut line = format!(
+            "{} {}  {}  {:>5} proxies  {}",
+            if in_place { "*" } else { " " },
+            entry.id,
+            entry
+                .generated_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M"),
+            entry.proxies,
+            subscriptions
+        );
+        if entry.output != default_output {
+            line.push_str(&format!("  -> {}", entry.output.display()));
         }
+        println!("{}", line);
     }
+    Ok(())
+}
 
-    #[test]
-    fn tailscale_compatibility_deduplicates_entries() {
-        use serde_yaml::Value;
-
-        let raw = r#"
-dns:
-  enhanced-mode: fake-ip
-  fake-ip-range: 172.19.0.1/16
-  fake-ip-filter:
-    - +.tailscale.com
-tun:
-  route-exclude-address:
-    - 100.64.0.0/10
-"#;
-        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(raw).unwrap();
-
-        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
-        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
-
-        let dns = cfg.extra.get("dns").and_then(|v| v.as_mapping()).unwrap();
-        let filters = dns
-            .get(Value::String("fake-ip-filter".into()))
-            .and_then(|v| v.as_sequence())
-            .unwrap();
-        assert_eq!(
-            filters
-                .iter()
-                .filter(|v| v.as_str() == Some("+.tailscale.com"))
-                .count(),
-            1
-        );
+async fn history_rollback(paths: &AppPaths, args: HistoryRollbackArgs) -> anyhow::Result<()> {
+    let (entry, yaml) = history::load(&paths.history_dir(), &args.id).await?;
+    let output = args.output.clone().unwrap_or_else(|| entry.output.clone());
+    ensure_parent(&output).await?;
+    FileDeployer::new(output.clone())
+        .deploy(&yaml)
+        .await
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    println!(
+        "restored config {} from {} to {}",
+        entry.id,
+        entry
+            .generated_at
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M"),
+        output.display()
+    );
 
-        let tun = cfg.extra.get("tun").and_then(|v| v.as_mapping()).unwrap();
-        let excludes = tun
-            .get(Value::String("route-exclude-address".into()))
-            .and_then(|v| v.as_sequence())
-            .unwrap();
-        assert_eq!(
-            excludes
-                .iter()
-                .filter(|v| v.as_str() == Some("100.64.0.0/10"))
-                .count(),
-            1
-        );
-        assert_eq!(
-            cfg.rules
-                .iter()
-                .filter(|rule| rule == &&"DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string())
-                .count(),
-            1
-        );
+    if args.deploy_api.is_none() && args.reload.is_none() {
+        return Ok(());
+    }
+    let config = mihomo_core::ClashConfig::from_yaml_str(&yaml)
+        .with_context(|| format!("history entry {} is not a valid config", entry.id))?;
+    let secret = config.extra.get("secret").and_then(Value::as_str);
+    if let Some(endpoint) = args.deploy_api.as_ref() {
+        let deployer = HttpDeployer {
+            endpoint: endpoint.clone(),
+            secret: args.deploy_secret.clone().or(secret.map(str::to_string)),
+            ca_cert: args.deploy_ca_cert.clone(),
+        };
+        deployer.deploy(&yaml).await?;
+        println!("deployed config to external controller {}", endpoint);
+    }
+    if let Some(target) = args.reload.as_ref() {
+        let controller = controller_endpoint(&config);
+        target
+            .reload(
+                &output,
+                controller.as_deref().map(|endpoint| (endpoint, secret)),
+            )
+            .await
+            .context("failed to reload mihomo")?;
+        println!("reloaded mihomo via --reload");
+    }
+    Ok(())
+}
+
+async fn run_ui(args: UiArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    paths.ensure_runtime_dirs().await?;
+    match args.command {
+        UiCommand::Install(args) => ui_install(&paths, args).await,
+        UiCommand::Open(args) => ui_open(&paths, args).await,
     }
+}
 
-    #[test]
-    fn tailscale_direct_rules_are_prefixed() {
-        let raw = r#"
-rules:
-  - MATCH,Proxy
-"#;
-        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(raw).unwrap();
+async fn ui_install(paths: &AppPaths, args: UiInstallArgs) -> anyhow::Result<()> {
+    let name = args.dashboard.as_str();
+    let url = args
+        .url
+        .clone()
+        .unwrap_or_else(|| args.dashboard.default_url().to_string());
+    let target = paths.ui_dir().join(name);
 
-        apply_tailscale_compatibility(&mut cfg, &[String::from("zhsjf.cn")], &[]);
+    info!(dashboard = name, url = %url, "downloading dashboard");
+    let bytes = download_bytes(paths, args.fetch_proxy.as_deref(), &url)
+        .await
+        .with_context(|| format!("failed to download {name} from {url}"))?;
 
-        assert_eq!(cfg.rules[0], "DOMAIN-SUFFIX,tail.zhsjf.cn,DIRECT");
-        assert_eq!(cfg.rules[1], "DOMAIN-SUFFIX,tailscale.com,DIRECT");
-        assert_eq!(cfg.rules[2], "DOMAIN-SUFFIX,ts.net,DIRECT");
-        assert_eq!(cfg.rules[3], "IP-CIDR,100.100.100.100/32,DIRECT");
-        assert_eq!(cfg.rules[4], "IP-CIDR,100.64.0.0/10,DIRECT");
-        assert_eq!(cfg.rules[5], "IP-CIDR6,fd7a:115c:a1e0::/48,DIRECT");
-        assert_eq!(cfg.rules[6], "MATCH,Proxy");
-    }
+    let extract_target = target.clone();
+    let files = tokio::task::spawn_blocking(move || extract_dashboard_zip(&bytes, &extract_target))
+        .await??;
 
-    #[test]
-    fn tailscale_tailnet_suffixes_are_parameterized() {
-        assert_eq!(
-            build_tailscale_fake_ip_bypass(&[String::from("example.com")], &[]),
-            vec![
-                "+.tail.example.com".to_string(),
-                "+.tailscale.com".to_string(),
-                "+.ts.net".to_string()
-            ]
-        );
-        assert_eq!(
-            build_tailscale_direct_rules(&[String::from("example.com")], &[]),
-            vec![
-                "DOMAIN-SUFFIX,tail.example.com,DIRECT".to_string(),
-                "DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string(),
-                "DOMAIN-SUFFIX,ts.net,DIRECT".to_string(),
-                "IP-CIDR,100.100.100.100/32,DIRECT".to_string(),
-                "IP-CIDR,100.64.0.0/10,DIRECT".to_string(),
-                "IP-CIDR6,fd7a:115c:a1e0::/48,DIRECT".to_string()
-            ]
-        );
+    let mut cfg = storage::load_app_config(paths).await?;
+    cfg.external_ui = Some(ExternalUi {
+        name: name.to_string(),
+        path: target.clone(),
+        url,
+    });
+    storage::save_app_config(paths, &cfg).await?;
+
+    println!(
+        "installed {} ({} files) into {}",
+        name,
+        files,
+        target.display()
+    );
+    println!("re-run merge to inject external-ui, then `mihomo-cli ui open`");
+    Ok(())
+}
+
+/// Unpack a dashboard archive into `target`, dropping the archive's single top-level
+/// directory (GitHub archives wrap everything in `<repo>-<branch>/`).
+fn extract_dashboard_zip(bytes: &[u8], target: &Path) -> anyhow::Result<usize> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))
+        .context("dashboard download is not a zip archive")?;
+
+    let mut entries = Vec::with_capacity(archive.len());
+    for idx in 0..archive.len() {
+        let file = archive.by_index(idx)?;
+        let path = file
+            .enclosed_name()
+            .ok_or_else(|| anyhow!("unsafe path in dashboard archive: {}", file.name()))?
+            .to_path_buf();
+        entries.push(path);
     }
+    let prefix = common_top_level_dir(&entries);
 
-    #[test]
-    fn tailscale_direct_domains_are_parameterized() {
-        assert_eq!(
-            build_tailscale_fake_ip_bypass(
-                &[String::from("example.com")],
-                &[
-                    String::from("derp.zhsjf.cn"),
-                    String::from("+.corp.example.com")
-                ]
-            ),
-            vec![
-                "+.corp.example.com".to_string(),
-                "+.tail.example.com".to_string(),
-                "+.tailscale.com".to_string(),
-                "+.ts.net".to_string(),
-                "derp.zhsjf.cn".to_string()
-            ]
-        );
-        assert_eq!(
-            build_tailscale_direct_rules(
-                &[String::from("example.com")],
-                &[
-                    String::from("derp.zhsjf.cn"),
-                    String::from("+.corp.example.com")
-                ]
-            ),
-            vec![
-                "DOMAIN,derp.zhsjf.cn,DIRECT".to_string(),
-                "DOMAIN-SUFFIX,corp.example.com,DIRECT".to_string(),
-                "DOMAIN-SUFFIX,tail.example.com,DIRECT".to_string(),
-                "DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string(),
-                "DOMAIN-SUFFIX,ts.net,DIRECT".to_string(),
-                "IP-CIDR,100.100.100.100/32,DIRECT".to_string(),
-                "IP-CIDR,100.64.0.0/10,DIRECT".to_string(),
-                "IP-CIDR6,fd7a:115c:a1e0::/48,DIRECT".to_string()
-            ]
-        );
+    storage::ensure_writable(target)?;
+    if target.exists() {
+        std::fs::remove_dir_all(target)?;
     }
+    std::fs::create_dir_all(target)?;
 
-    #[test]
-    fn system_proxy_bypass_entries_include_tailnet_and_direct_domains() {
-        assert_eq!(
-            build_system_proxy_bypass_entries(
-                &[String::from("zhsjf.cn")],
-                &[
-                    String::from("airs.zhsjf.cn"),
-                    String::from("+.corp.example.com")
-                ]
-            ),
-            vec![
-                "*.corp.example.com".to_string(),
-                "*.tail.zhsjf.cn".to_string(),
-                "100.100.100.100".to_string(),
-                "100.64.0.0/10".to_string(),
-                "airs.zhsjf.cn".to_string(),
-            ]
-        );
+    let mut written = 0;
+    for (idx, path) in entries.iter().enumerate() {
+        let relative = match prefix.as_ref() {
+            Some(prefix) => path.strip_prefix(prefix).unwrap_or(path),
+            None => path.as_path(),
+        };
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        let dest = target.join(relative);
+        let mut file = archive.by_index(idx)?;
+        if file.is_dir() {
+            std::fs::create_dir_all(&dest)?;
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut out = std::fs::File::create(&dest)?;
+        std::io::copy(&mut file, &mut out)?;
+        written += 1;
     }
+    Ok(written)
+}
 
-    #[test]
-    fn merge_proxy_bypass_entries_replaces_previous_managed_values() {
-        let current = vec![
-            "127.0.0.1".to_string(),
-            "mihomo.zhsjf.cn".to_string(),
-            "old.example.com".to_string(),
-        ];
-        let previous = vec!["mihomo.zhsjf.cn".to_string(), "old.example.com".to_string()];
-        let desired = vec!["mihomo.zhsjf.cn".to_string(), "airs.zhsjf.cn".to_string()];
+fn common_top_level_dir(entries: &[PathBuf]) -> Option<PathBuf> {
+    let first = entries.first()?.components().next()?;
+    let all_nested = entries.iter().all(|path| {
+        let mut components = path.components();
+        components.next() == Some(first) && (components.next().is_some() || path.ends_with(first))
+    });
+    let has_children = entries.iter().any(|path| path.components().count() > 1);
+    (all_nested && has_children).then(|| PathBuf::from(first.as_os_str()))
+}
 
-        assert_eq!(
-            merge_proxy_bypass_entries(current, &previous, &desired),
-            vec![
-                "127.0.0.1".to_string(),
-                "mihomo.zhsjf.cn".to_string(),
-                "airs.zhsjf.cn".to_string(),
-            ]
-        );
+async fn ui_open(paths: &AppPaths, args: UiOpenArgs) -> anyhow::Result<()> {
+    let config_path = args
+        .config
+        .clone()
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    let raw = fs::read_to_string(&config_path)
+        .await
+        .with_context(|| format!("failed to read {}", config_path.display()))?;
+    let cfg = mihomo_core::ClashConfig::from_yaml_str(&raw)?;
+    // The dashboard is served by the controller, so prefer its TLS listener when there is one.
+    let tls = cfg
+        .extra
+        .get("external-controller-tls")
+        .and_then(Value::as_str)
+        .and_then(parse_host_port);
+    let (scheme, host, port) = match tls {
+        Some((host, port)) => ("https", host, port),
+        None => {
+            let (host, port) = parse_controller_endpoint(&cfg)
+                .and_then(|endpoint| Some((endpoint.host?, endpoint.port?)))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} does not define an HTTP external-controller",
+                        config_path.display()
+                    )
+                })?;
+            ("http", host, port)
+        }
+    };
+    let secret = cfg
+        .extra
+        .get("secret")
+        .and_then(Value::as_str)
+        .unwrap_or("");
+    let app_cfg = storage::load_app_config(paths).await?;
+    let dashboard = app_cfg
+        .external_ui
+        .as_ref()
+        .map(|ui| ui.name.as_str())
+        .unwrap_or("metacubexd");
+
+    let url = dashboard_url(
+        dashboard,
+        scheme,
+        &normalize_controller_host(&host),
+        port,
+        secret,
+    );
+    println!("{}", url);
+    if args.print_only {
+        return Ok(());
     }
 
-    #[test]
-    fn parse_windows_proxy_override_splits_semicolon_list() {
-        let raw = r#"
-HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Internet Settings
-    ProxyOverride    REG_SZ    localhost;<local>;100.64.0.0/10;100.100.100.100;airs.zhsjf.cn
-"#;
-
-        assert_eq!(
-            parse_windows_proxy_override(raw),
-            vec![
-                "localhost".to_string(),
-                "<local>".to_string(),
-                "100.64.0.0/10".to_string(),
-                "100.100.100.100".to_string(),
-                "airs.zhsjf.cn".to_string(),
-            ]
-        );
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    let status = command.arg(&url).status()?;
+    if !status.success() {
+        return Err(anyhow!("failed to launch browser ({})", status));
     }
+    Ok(())
+}
 
-    #[test]
-    fn tailscale_managed_state_tracks_extra_route_excludes() {
-        let state = build_tailscale_managed_state(
-            &[String::from("example.com")],
-            &[String::from("derp.example.com")],
-            &[String::from("203.0.113.10/32")],
-        );
+fn dashboard_url(dashboard: &str, scheme: &str, host: &str, port: u16, secret: &str) -> String {
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
-        assert!(state
-            .fake_ip_filter
-            .contains(&"+.tail.example.com".to_string()));
-        assert!(state
-            .rules
-            .contains(&"DOMAIN,derp.example.com,DIRECT".to_string()));
-        assert!(state
-            .route_exclude_address
-            .contains(&"203.0.113.10/32".to_string()));
+    let query = format!(
+        "hostname={}&port={}&secret={}",
+        utf8_percent_encode(host, NON_ALPHANUMERIC),
+        port,
+        utf8_percent_encode(secret, NON_ALPHANUMERIC)
+    );
+    match dashboard {
+        "yacd" => format!("{}://{}:{}/ui/?{}", scheme, host, port, query),
+        _ => format!("{}://{}:{}/ui/#/setup?{}", scheme, host, port, query),
     }
+}
 
-    #[test]
-    fn remove_tailscale_managed_items_cleans_previous_entries() {
-        use serde_yaml::Value;
+const RELEASES_API: &str = "https://api.github.com/repos/fanrongchao/mihomocli/releases";
 
-        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
-            r#"
-dns:
-  fake-ip-filter:
-    - ts-d.zhsjf.cn
-    - keep.example.com
-tun:
-  route-exclude-address:
-    - 114.215.124.90/32
-    - 203.0.113.10/32
-rules:
-  - DOMAIN,ts-d.zhsjf.cn,DIRECT
-  - MATCH,Proxy
-"#,
-        )
-        .unwrap();
+#[derive(Debug, serde::Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    assets: Vec<GithubAsset>,
+}
 
-        let managed = ManagedTailscaleCompat {
-            fake_ip_filter: vec!["ts-d.zhsjf.cn".to_string()],
-            route_exclude_address: vec!["114.215.124.90/32".to_string()],
-            rules: vec!["DOMAIN,ts-d.zhsjf.cn,DIRECT".to_string()],
-        };
+#[derive(Debug, serde::Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+    /// `sha256:<hex>`, computed by GitHub for assets uploaded since mid-2025.
+    #[serde(default)]
+    digest: Option<String>,
+}
 
-        remove_tailscale_managed_items(&mut cfg, &managed);
+async fn run_self_update(args: SelfUpdateArgs) -> anyhow::Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let asset_base = release_asset_base()
+        .ok_or_else(|| anyhow!("no release binaries are published for this platform"))?;
+    let archive_name = if cfg!(windows) {
+        format!("{asset_base}.zip")
+    } else {
+        format!("{asset_base}.tar.gz")
+    };
 
-        let dns = cfg.extra.get("dns").and_then(|v| v.as_mapping()).unwrap();
-        let filters = dns
-            .get(Value::String("fake-ip-filter".into()))
-            .and_then(|v| v.as_sequence())
-            .unwrap();
-        assert!(filters
-            .iter()
-            .any(|v| v.as_str() == Some("keep.example.com")));
-        assert!(!filters.iter().any(|v| v.as_str() == Some("ts-d.zhsjf.cn")));
+    let paths = AppPaths::new()?;
+    let fetch_proxy = args.fetch_proxy.as_deref();
+    let release_url = match args.version.as_ref() {
+        Some(tag) => format!("{RELEASES_API}/tags/{tag}"),
+        None => format!("{RELEASES_API}/latest"),
+    };
+    let release: GithubRelease =
+        serde_json::from_str(&download_text(&paths, fetch_proxy, &release_url).await?)
+            .with_context(|| format!("failed to parse the release from {}", release_url))?;
 
-        let tun = cfg.extra.get("tun").and_then(|v| v.as_mapping()).unwrap();
-        let excludes = tun
-            .get(Value::String("route-exclude-address".into()))
-            .and_then(|v| v.as_sequence())
-            .unwrap();
-        assert!(excludes
-            .iter()
-            .any(|v| v.as_str() == Some("203.0.113.10/32")));
-        assert!(!excludes
+    let newer = version_is_newer(&release.tag_name, current);
+    println!(
+        "current: v{}, release: {}{}",
+        current,
+        release.tag_name,
+        if newer { " (update available)" } else { "" }
+    );
+    if args.check {
+        return Ok(());
+    }
+    if !newer && !args.force && args.version.is_none() {
+        println!("already up to date");
+        return Ok(());
+    }
+
+    let find_asset = |name: &str| {
+        release
+            .assets
             .iter()
-            .any(|v| v.as_str() == Some("114.215.124.90/32")));
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| anyhow!("release {} has no asset {}", release.tag_name, name))
+    };
+    let archive_asset = find_asset(&archive_name)?;
+    let checksum_asset = find_asset(&format!("{archive_name}.sha256"))?;
 
-        assert_eq!(cfg.rules, vec!["MATCH,Proxy".to_string()]);
+    info!(asset = %archive_asset.name, "downloading release archive");
+    let archive = download_bytes(&paths, fetch_proxy, &archive_asset.browser_download_url)
+        .await
+        .with_context(|| format!("failed to download {}", archive_asset.name))?;
+    let checksum_text =
+        download_text(&paths, fetch_proxy, &checksum_asset.browser_download_url).await?;
+
+    let expected = parse_sha256_file(&checksum_text)
+        .ok_or_else(|| anyhow!("could not read checksum from {}", checksum_asset.name))?;
+    let actual = {
+        use sha2::Digest;
+        hex::encode(sha2::Sha256::digest(&archive))
+    };
+    if actual != expected {
+        return Err(anyhow!(
+            "checksum mismatch for {}: expected {}, got {}",
+            archive_asset.name,
+            expected,
+            actual
+        ));
     }
 
-    #[test]
-    fn ensure_fallback_match_rule_appends_when_missing() {
-        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
-            r#"
-rules:
-  - DOMAIN-SUFFIX,tailscale.com,DIRECT
-"#,
-        )
-        .unwrap();
+    let binary = extract_release_binary(&archive, asset_base)?;
+    let exe = std::env::current_exe()?;
+    replace_executable(&exe, &binary)?;
+    println!("updated {} to {}", exe.display(), release.tag_name);
+    Ok(())
+}
 
-        ensure_fallback_match_rule(&mut cfg, "BosLife");
+/// Asset name (without archive extension) the release workflow publishes for this build.
+fn release_asset_base() -> Option<&'static str> {
+    if cfg!(all(
+        target_os = "linux",
+        target_arch = "x86_64",
+        target_env = "musl"
+    )) {
+        Some("mihomo-cli-linux-x86_64-musl")
+    } else if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        Some("mihomo-cli-linux-x86_64")
+    } else if cfg!(all(target_os = "macos", target_arch = "x86_64")) {
+        Some("mihomo-cli-macos-x86_64")
+    } else if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        Some("mihomo-cli-macos-arm64")
+    } else if cfg!(all(target_os = "windows", target_arch = "x86_64")) {
+        Some("mihomo-cli-windows-x86_64.exe")
+    } else {
+        None
+    }
+}
 
-        assert_eq!(cfg.rules.last().map(String::as_str), Some("MATCH,BosLife"));
+/// Compare `v1.2.3`-style tags numerically; non-numeric parts count as 0.
+fn version_is_newer(tag: &str, current: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        version
+            .trim()
+            .trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .take(3)
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
     }
+    parts(tag) > parts(current)
+}
 
-    #[test]
-    fn ensure_fallback_match_rule_keeps_existing_match() {
-        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
-            r#"
-rules:
-  - DOMAIN-SUFFIX,tailscale.com,DIRECT
-  - MATCH,Proxy
-"#,
-        )
-        .unwrap();
+/// Pull the hex digest out of `shasum -a 256` or `certutil -hashfile` output.
+fn parse_sha256_file(text: &str) -> Option<String> {
+    text.split_whitespace()
+        .find(|token| token.len() == 64 && token.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|token| token.to_ascii_lowercase())
+}
 
-        ensure_fallback_match_rule(&mut cfg, "BosLife");
+fn extract_release_binary(archive: &[u8], asset_base: &str) -> anyhow::Result<Vec<u8>> {
+    use std::io::Read;
 
-        assert_eq!(
-            cfg.rules,
-            vec![
-                "DOMAIN-SUFFIX,tailscale.com,DIRECT".to_string(),
-                "MATCH,Proxy".to_string()
-            ]
-        );
+    let mut binary = Vec::new();
+    if cfg!(windows) {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive))?;
+        let mut file = zip.by_name("mihomo-cli.exe")?;
+        file.read_to_end(&mut binary)?;
+        return Ok(binary);
     }
 
-    #[test]
-    fn normalize_controller_host_maps_wildcards_to_loopback() {
-        assert_eq!(normalize_controller_host("0.0.0.0"), "127.0.0.1");
-        assert_eq!(normalize_controller_host("::"), "127.0.0.1");
-        assert_eq!(normalize_controller_host("[::]"), "127.0.0.1");
-        assert_eq!(normalize_controller_host("127.0.0.1"), "127.0.0.1");
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.file_name().and_then(|name| name.to_str()) == Some(asset_base) {
+            entry.read_to_end(&mut binary)?;
+            return Ok(binary);
+        }
     }
+    Err(anyhow!("release archive does not contain {}", asset_base))
+}
 
-    #[test]
-    fn preferred_runtime_path_prefers_clash_verge_yaml() {
-        let paths = vec![
-            PathBuf::from("/tmp/clash-verge.yaml"),
-            PathBuf::from("/tmp/config.yaml"),
-        ];
-
-        assert_eq!(
-            preferred_runtime_path(&paths).map(|path| path.as_path()),
-            Some(Path::new("/tmp/clash-verge.yaml"))
-        );
+/// Swap the running executable for `binary` via a sibling temp file and rename.
+fn replace_executable(exe: &Path, binary: &[u8]) -> anyhow::Result<()> {
+    let staged = exe.with_extension("new");
+    std::fs::write(&staged, binary)
+        .with_context(|| format!("failed to write {}", staged.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+    if cfg!(windows) && exe.exists() {
+        // Windows refuses to overwrite a running executable but allows renaming it.
+        let old = exe.with_extension("old");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old)?;
     }
+    std::fs::rename(&staged, exe)
+        .with_context(|| format!("failed to replace {}", exe.display()))?;
+    Ok(())
+}
 
-    #[test]
-    fn preferred_runtime_path_falls_back_to_first_entry() {
-        let paths = vec![PathBuf::from("/tmp/clash-verge.yaml")];
+/// How long `stop` waits for mihomo to exit after SIGTERM before killing it.
+const STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
-        assert_eq!(
-            preferred_runtime_path(&paths).map(|path| path.as_path()),
-            Some(Path::new("/tmp/clash-verge.yaml"))
-        );
+async fn run_mihomo(args: RunArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let pid_path = paths.mihomo_pid_path();
+    storage::ensure_writable(&pid_path)?;
+    if let Some(pid) = running_mihomo_pid(&paths).await {
+        return Err(anyhow!(
+            "mihomo is already running (pid {}); run `mihomo-cli stop` first",
+            pid
+        ));
+    }
+    let config_path = args
+        .config
+        .unwrap_or_else(|| paths.generated_clash_verge_path());
+    if !fs::try_exists(&config_path).await.unwrap_or(false) {
+        return Err(anyhow!(
+            "{} does not exist; run `mihomo-cli merge` first",
+            config_path.display()
+        ));
+    }
+    let workdir = args
+        .mihomo_dir
+        .unwrap_or_else(|| paths.config_dir().to_path_buf());
+    let bin = mihomo_binary(&paths, args.mihomo_bin);
+    let mut command = std::process::Command::new(&bin);
+    command.arg("-d").arg(&workdir).arg("-f").arg(&config_path);
+
+    if args.foreground {
+        let mut child = tokio::process::Command::from(command)
+            .spawn()
+            .with_context(|| format!("failed to start {}", bin.display()))?;
+        let pid = child
+            .id()
+            .ok_or_else(|| anyhow!("mihomo exited before its pid was known"))?;
+        fs::write(&pid_path, format!("{}\n", pid)).await?;
+        let forwarder = tokio::spawn(forward_signals(pid));
+        let status = child.wait().await;
+        forwarder.abort();
+        let _ = fs::remove_file(&pid_path).await;
+        let status = status?;
+        return if status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!("mihomo exited with {}", status))
+        };
     }
 
-    #[test]
-    fn apply_mode_override_sets_rule_mode() {
-        let mut cfg = mihomo_core::ClashConfig::default();
-        cfg.extra
-            .insert("mode".to_string(), Value::String("global".to_string()));
+    let log_path = paths.mihomo_log_path();
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("failed to open {}", log_path.display()))?;
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    #[cfg(unix)]
+    {
+        // Keep terminal Ctrl-C away from the background process.
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("failed to start {}", bin.display()))?;
+    fs::write(&pid_path, format!("{}\n", child.id())).await?;
+    // A bad config or a port in use makes mihomo exit right away.
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    if let Some(status) = child.try_wait()? {
+        let _ = fs::remove_file(&pid_path).await;
+        return Err(anyhow!(
+            "mihomo exited immediately ({}); see {}",
+            status,
+            log_path.display()
+        ));
+    }
+    println!(
+        "mihomo started (pid {}), logging to {}",
+        child.id(),
+        log_path.display()
+    );
+    Ok(())
+}
 
-        apply_mode_override(&mut cfg, ConfigMode::Rule);
+/// `run --foreground`: pass Ctrl-C and SIGTERM on to mihomo so it shuts down cleanly (and
+/// this process exits with it) when run under a service manager.
+async fn forward_signals(pid: u32) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let (Ok(mut interrupt), Ok(mut terminate)) = (
+            signal(SignalKind::interrupt()),
+            signal(SignalKind::terminate()),
+        ) else {
+            return;
+        };
+        loop {
+            let name = tokio::select! {
+                _ = interrupt.recv() => "INT",
+                _ = terminate.recv() => "TERM",
+            };
+            if let Err(err) = signal_process(pid, name).await {
+                warn!(error = %err, "failed to forward SIG{} to mihomo", name);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    while tokio::signal::ctrl_c().await.is_ok() {
+        let _ = signal_process(pid, "TERM").await;
+    }
+}
 
-        assert_eq!(cfg.extra.get("mode").and_then(Value::as_str), Some("rule"));
+async fn stop_mihomo() -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let pid_path = paths.mihomo_pid_path();
+    let Some(pid) = running_mihomo_pid(&paths).await else {
+        if !storage::is_read_only() {
+            let _ = fs::remove_file(&pid_path).await;
+        }
+        println!("mihomo is not running");
+        return Ok(());
+    };
+    signal_process(pid, "TERM").await?;
+    let deadline = tokio::time::Instant::now() + STOP_TIMEOUT;
+    while process_alive(pid).await {
+        if tokio::time::Instant::now() >= deadline {
+            signal_process(pid, "KILL").await?;
+            println!(
+                "mihomo (pid {}) did not exit within {}s; killed it",
+                pid,
+                STOP_TIMEOUT.as_secs()
+            );
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
+    if !storage::is_read_only() {
+        let _ = fs::remove_file(&pid_path).await;
+    }
+    println!("stopped mihomo (pid {})", pid);
+    Ok(())
+}
 
-    #[test]
-    fn apply_tun_enabled_sets_true() {
-        let mut cfg = mihomo_core::ClashConfig::default();
+async fn mihomo_status() -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let Some(pid) = running_mihomo_pid(&paths).await else {
+        println!("mihomo is not running");
+        return Err(ExitStatus(3).into());
+    };
+    println!("mihomo is running (pid {})", pid);
+    let target = ControllerTarget {
+        controller: None,
+        ca_cert: None,
+        secret: None,
+        config: None,
+    };
+    if let Ok(client) = controller_client(&paths, &target).await {
+        match client.version().await {
+            Ok(version) => println!("controller {}: {}", client.base(), version),
+            Err(err) => println!("controller {}: unreachable ({:#})", client.base(), err),
+        }
+    }
+    Ok(())
+}
 
-        apply_tun_enabled(&mut cfg, true);
+/// Pid from the pid file when that process is still alive.
+async fn running_mihomo_pid(paths: &AppPaths) -> Option<u32> {
+    let raw = fs::read_to_string(paths.mihomo_pid_path()).await.ok()?;
+    let pid: u32 = raw.trim().parse().ok()?;
+    process_alive(pid).await.then_some(pid)
+}
 
-        let tun = cfg.extra.get("tun").and_then(Value::as_mapping).unwrap();
-        assert_eq!(
-            tun.get(Value::String("enable".into()))
-                .and_then(Value::as_bool),
-            Some(true)
-        );
+async fn process_alive(pid: u32) -> bool {
+    let pid = pid.to_string();
+    if cfg!(windows) {
+        tokio::process::Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .await
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid))
+    } else {
+        tokio::process::Command::new("kill")
+            .args(["-0", &pid])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .await
+            .is_ok_and(|status| status.success())
     }
+}
 
-    #[test]
-    fn apply_sniffer_preset_tun_installs_sniffer() {
-        let mut cfg = mihomo_core::ClashConfig::default();
+/// Send `signal` (`INT`, `TERM`, `KILL`) to `pid`; Windows only knows a polite and a forced
+/// `taskkill`.
+async fn signal_process(pid: u32, signal: &str) -> anyhow::Result<()> {
+    let pid = pid.to_string();
+    let mut command = if cfg!(windows) {
+        let mut command = tokio::process::Command::new("taskkill");
+        command.args(["/PID", &pid]);
+        if signal == "KILL" {
+            command.arg("/F");
+        }
+        command
+    } else {
+        let mut command = tokio::process::Command::new("kill");
+        command.args(["-s", signal, &pid]);
+        command
+    };
+    let status = command.status().await?;
+    if !status.success() {
+        return Err(anyhow!("failed to send SIG{} to pid {}", signal, pid));
+    }
+    Ok(())
+}
 
-        apply_sniffer_preset(&mut cfg, SnifferPreset::Tun);
+/// systemd unit running mihomo; the merge timer uses `<name>-merge`.
+const SYSTEMD_SERVICE_NAME: &str = "mihomo-cli";
+/// launchd label of the mihomo job; the merge job uses [`LAUNCHD_MERGE_LABEL`].
+const LAUNCHD_LABEL: &str = "io.github.mihomocli.mihomo";
+const LAUNCHD_MERGE_LABEL: &str = "io.github.mihomocli.merge";
 
-        let sniffer = cfg
-            .extra
-            .get("sniffer")
-            .and_then(Value::as_mapping)
-            .unwrap();
-        assert_eq!(
-            sniffer
-                .get(Value::String("enable".into()))
-                .and_then(Value::as_bool),
-            Some(true)
-        );
-        let sniff = sniffer
-            .get(Value::String("sniff".into()))
-            .and_then(Value::as_mapping)
-            .unwrap();
-        assert!(sniff.contains_key(Value::String("HTTP".into())));
-        assert!(sniff.contains_key(Value::String("TLS".into())));
-        assert!(sniff.contains_key(Value::String("QUIC".into())));
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ServiceManager {
+    Systemd,
+    Launchd,
+}
+
+impl ServiceManager {
+    fn current() -> anyhow::Result<Self> {
+        if cfg!(target_os = "macos") {
+            Ok(Self::Launchd)
+        } else if cfg!(target_os = "linux") {
+            Ok(Self::Systemd)
+        } else {
+            Err(anyhow!(
+                "`service` supports systemd (Linux) and launchd (macOS) only"
+            ))
+        }
     }
 
-    #[test]
-    fn apply_sniffer_preset_off_removes_sniffer() {
-        let mut cfg: mihomo_core::ClashConfig = serde_yaml::from_str(
-            r#"
-sniffer:
-  enable: true
-"#,
-        )
-        .unwrap();
+    /// Directory the unit files or plists live in.
+    fn dir(self, paths: &AppPaths, user: bool) -> PathBuf {
+        match (self, user) {
+            (Self::Systemd, false) => PathBuf::from("/etc/systemd/system"),
+            (Self::Systemd, true) => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| paths.home_dir_fallback().join(".config"))
+                .join("systemd/user"),
+            (Self::Launchd, false) => PathBuf::from("/Library/LaunchDaemons"),
+            (Self::Launchd, true) => paths.home_dir_fallback().join("Library/LaunchAgents"),
+        }
+    }
+}
 
-        apply_sniffer_preset(&mut cfg, SnifferPreset::Off);
+/// What `service install` runs.
+#[derive(Debug)]
+struct ServiceSpec {
+    user: bool,
+    /// mihomo and its arguments.
+    mihomo: Vec<String>,
+    /// `mihomo-cli merge ...` and how often to run it.
+    merge: Option<(Vec<String>, std::time::Duration)>,
+    /// HOME for the merge job of a system service, so it finds this user's subscriptions.
+    home: Option<PathBuf>,
+    log: PathBuf,
+}
 
-        assert!(!cfg.extra.contains_key("sniffer"));
+/// Unit files for `spec` as (file name, contents); the timer pair only with a merge interval.
+fn systemd_units(spec: &ServiceSpec) -> Vec<(String, String)> {
+    let wanted_by = if spec.user {
+        "default.target"
+    } else {
+        "multi-user.target"
+    };
+    let mut units = vec![(
+        format!("{}.service", SYSTEMD_SERVICE_NAME),
+        format!(
+            "[Unit]\n\
+             Description=mihomo (managed by mihomo-cli)\n\
+             After=network-online.target\n\
+             Wants=network-online.target\n\
+             \n\
+             [Service]\n\
+             Type=simple\n\
+             ExecStart={}\n\
+             Restart=on-failure\n\
+             RestartSec=5\n\
+             \n\
+             [Install]\n\
+             WantedBy={}\n",
+            systemd_command_line(&spec.mihomo),
+            wanted_by
+        ),
+    )];
+    if let Some((merge, interval)) = &spec.merge {
+        let environment = spec
+            .home
+            .as_ref()
+            .map(|home| {
+                format!(
+                    "Environment={}\n",
+                    systemd_quote(&format!("HOME={}", home.display()))
+                )
+            })
+            .unwrap_or_default();
+        units.push((
+            format!("{}-merge.service", SYSTEMD_SERVICE_NAME),
+            format!(
+                "[Unit]\n\
+                 Description=Regenerate the mihomo config (mihomo-cli merge)\n\
+                 After=network-online.target\n\
+                 Wants=network-online.target\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 {}ExecStart={}\n",
+                environment,
+                systemd_command_line(merge)
+            ),
+        ));
+        units.push((
+            format!("{}-merge.timer", SYSTEMD_SERVICE_NAME),
+            format!(
+                "[Unit]\n\
+                 Description=Periodic mihomo-cli merge\n\
+                 \n\
+                 [Timer]\n\
+                 OnBootSec=5min\n\
+                 OnUnitActiveSec={}s\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=timers.target\n",
+                interval.as_secs()
+            ),
+        ));
     }
+    units
+}
 
-    #[test]
-    fn ensure_root_mapping_allows_mode_override_in_source_doc() {
-        let mut doc = serde_yaml::from_str::<Value>(
-            r#"
-rules:
-  - MATCH,Proxy
-"#,
-        )
-        .unwrap();
+fn systemd_command_line(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| systemd_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
-        ensure_root_mapping(&mut doc).insert(
-            Value::String("mode".to_string()),
-            Value::String("rule".to_string()),
-        );
+/// Quote `arg` for an `ExecStart=`/`Environment=` line; `%` and `$` are escaped because
+/// systemd expands specifiers and variables there.
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty()
+        && !escaped
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'))
+    {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Plists for `spec` as (file name, contents).
+fn launchd_plists(spec: &ServiceSpec) -> Vec<(String, String)> {
+    let log = xml_escape(&spec.log.display().to_string());
+    let mut plists = vec![(
+        format!("{}.plist", LAUNCHD_LABEL),
+        launchd_plist(
+            LAUNCHD_LABEL,
+            &spec.mihomo,
+            &format!(
+                "  <key>RunAtLoad</key>\n  <true/>\n  <key>KeepAlive</key>\n  <true/>\n  \
+                 <key>StandardOutPath</key>\n  <string>{log}</string>\n  \
+                 <key>StandardErrorPath</key>\n  <string>{log}</string>\n"
+            ),
+        ),
+    )];
+    if let Some((merge, interval)) = &spec.merge {
+        let environment = spec
+            .home
+            .as_ref()
+            .map(|home| {
+                format!(
+                    "  <key>EnvironmentVariables</key>\n  <dict>\n    <key>HOME</key>\n    \
+                     <string>{}</string>\n  </dict>\n",
+                    xml_escape(&home.display().to_string())
+                )
+            })
+            .unwrap_or_default();
+        plists.push((
+            format!("{}.plist", LAUNCHD_MERGE_LABEL),
+            launchd_plist(
+                LAUNCHD_MERGE_LABEL,
+                merge,
+                &format!(
+                    "  <key>StartInterval</key>\n  <integer>{}</integer>\n{}",
+                    interval.as_secs(),
+                    environment
+                ),
+            ),
+        ));
+    }
+    plists
+}
 
-        let root = doc.as_mapping().unwrap();
-        assert_eq!(
-            root.get(Value::String("mode".to_string()))
-                .and_then(Value::as_str),
-            Some("rule")
-        );
+fn launchd_plist(label: &str, program: &[String], extra: &str) -> String {
+    let arguments: String = program
+        .iter()
+        .map(|arg| format!("    <string>{}</string>\n", xml_escape(arg)))
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n  \
+         <key>Label</key>\n  <string>{}</string>\n  \
+         <key>ProgramArguments</key>\n  <array>\n{}  </array>\n\
+         {}\
+         </dict>\n\
+         </plist>\n",
+        xml_escape(label),
+        arguments,
+        extra
+    )
+}
+
+fn xml_escape(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+async fn run_service(cmd: ServiceCommand) -> anyhow::Result<()> {
+    let manager = ServiceManager::current()?;
+    match cmd {
+        ServiceCommand::Install(args) => service_install(manager, args).await,
+        ServiceCommand::Uninstall(scope) => service_uninstall(manager, scope.user).await,
+        ServiceCommand::Status(scope) => service_status(manager, scope.user).await,
     }
 }
 
-fn default_base_config_path(paths: &AppPaths) -> Option<PathBuf> {
-    let candidate = paths.app_config_path().with_file_name("base-config.yaml");
-    if candidate.exists() {
-        return Some(candidate);
+async fn service_install(manager: ServiceManager, args: ServiceInstallArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let user = args.scope.user;
+    let config_path = std::path::absolute(
+        args.config
+            .unwrap_or_else(|| paths.generated_clash_verge_path()),
+    )?;
+    if !fs::try_exists(&config_path).await.unwrap_or(false) {
+        return Err(anyhow!(
+            "{} does not exist; run `mihomo-cli merge` first",
+            config_path.display()
+        ));
+    }
+    let workdir = std::path::absolute(
+        args.mihomo_dir
+            .unwrap_or_else(|| paths.config_dir().to_path_buf()),
+    )?;
+    // Service managers start with a minimal PATH, so resolve `mihomo` now.
+    let bin = mihomo_binary(&paths, args.mihomo_bin);
+    let bin = if bin.components().count() == 1 {
+        std::env::var_os("PATH")
+            .and_then(|path| {
+                std::env::split_paths(&path)
+                    .map(|dir| dir.join(&bin))
+                    .find(|candidate| candidate.is_file())
+            })
+            .unwrap_or(bin)
+    } else {
+        std::path::absolute(&bin)?
+    };
+    if !bin.is_file() {
+        return Err(anyhow!(
+            "mihomo binary {} not found; run `mihomo-cli core install` or pass --mihomo-bin",
+            bin.display()
+        ));
+    }
+    let mihomo = vec![
+        bin.display().to_string(),
+        "-d".to_string(),
+        workdir.display().to_string(),
+        "-f".to_string(),
+        config_path.display().to_string(),
+    ];
+    let merge = match args.merge_interval {
+        Some(interval) => {
+            let exe = std::env::current_exe().context("failed to locate mihomo-cli")?;
+            let mut merge = vec![
+                exe.display().to_string(),
+                "merge".to_string(),
+                "--if-changed".to_string(),
+                "--reload".to_string(),
+                "api".to_string(),
+                "--output".to_string(),
+                config_path.display().to_string(),
+            ];
+            merge.extend(args.merge_args);
+            Some((merge, interval))
+        }
+        None => None,
+    };
+    let spec = ServiceSpec {
+        user,
+        mihomo,
+        merge,
+        home: (!user).then(|| paths.home_dir_fallback()),
+        log: paths.mihomo_log_path(),
+    };
+
+    let dir = manager.dir(&paths, user);
+    let files = match manager {
+        ServiceManager::Systemd => systemd_units(&spec),
+        ServiceManager::Launchd => launchd_plists(&spec),
+    };
+    fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+    for (name, contents) in &files {
+        let path = dir.join(name);
+        if manager == ServiceManager::Launchd && fs::try_exists(&path).await.unwrap_or(false) {
+            // launchd keeps the old definition until the job is unloaded.
+            let _ = service_command("launchctl", &["unload", &path.display().to_string()]).await;
+        }
+        fs::write(&path, contents).await.with_context(|| {
+            format!(
+                "failed to write {} (system services usually need root; try --user)",
+                path.display()
+            )
+        })?;
+        println!("wrote {}", path.display());
     }
 
-    paths
-        .detected_clash_verge_base_config_candidates()
-        .into_iter()
-        .find(|candidate| candidate.exists())
-}
-
-async fn ensure_parent(path: &Path) -> anyhow::Result<()> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).await?;
+    match manager {
+        ServiceManager::Systemd => {
+            let scope: &[&str] = if user { &["--user"] } else { &[] };
+            service_command("systemctl", &[scope, &["daemon-reload"]].concat()).await?;
+            let mut enable: Vec<&str> = [scope, &["enable"]].concat();
+            if !args.no_start {
+                enable.push("--now");
+            }
+            let units: Vec<String> = files
+                .iter()
+                .map(|(name, _)| name.clone())
+                .filter(|name| !name.ends_with("-merge.service"))
+                .collect();
+            enable.extend(units.iter().map(String::as_str));
+            service_command("systemctl", &enable).await?;
+            if args.no_start {
+                println!("enabled {}", units.join(", "));
+            } else {
+                println!("enabled and started {}", units.join(", "));
+            }
+        }
+        ServiceManager::Launchd if args.no_start => {
+            for (name, _) in &files {
+                println!(
+                    "load it with: launchctl load -w {}",
+                    dir.join(name).display()
+                );
+            }
+        }
+        ServiceManager::Launchd => {
+            for (name, _) in &files {
+                let path = dir.join(name).display().to_string();
+                service_command("launchctl", &["load", "-w", &path]).await?;
+            }
+            println!("loaded {}", LAUNCHD_LABEL);
+        }
     }
     Ok(())
 }
 
-async fn backup_existing_file(path: &Path) -> anyhow::Result<Option<PathBuf>> {
-    if !fs::try_exists(path).await.unwrap_or(false) {
-        return Ok(None);
+async fn service_uninstall(manager: ServiceManager, user: bool) -> anyhow::Result<()> {
+    let dir = manager.dir(&AppPaths::new()?, user);
+    let mut installed = Vec::new();
+    for name in service_file_names(manager) {
+        let path = dir.join(&name);
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            installed.push((name, path));
+        }
     }
-
-    let backup = path.with_extension("mihomocli.bak");
-    fs::copy(path, &backup).await.with_context(|| {
-        format!(
-            "failed to back up {} to {}",
-            path.display(),
-            backup.display()
-        )
-    })?;
-    Ok(Some(backup))
+    if installed.is_empty() {
+        println!("no mihomo-cli service is installed in {}", dir.display());
+        return Ok(());
+    }
+    let scope: &[&str] = if user { &["--user"] } else { &[] };
+    for (name, path) in &installed {
+        let stopped = match manager {
+            ServiceManager::Systemd if name.ends_with("-merge.service") => Ok(()),
+            ServiceManager::Systemd => {
+                service_command(
+                    "systemctl",
+                    &[scope, &["disable", "--now", name.as_str()]].concat(),
+                )
+                .await
+            }
+            ServiceManager::Launchd => {
+                service_command("launchctl", &["unload", "-w", &path.display().to_string()]).await
+            }
+        };
+        if let Err(err) = stopped {
+            warn!(error = %err, "failed to stop {}", name);
+        }
+        fs::remove_file(path)
+            .await
+            .with_context(|| format!("failed to remove {}", path.display()))?;
+        println!("removed {}", path.display());
+    }
+    if manager == ServiceManager::Systemd {
+        service_command("systemctl", &[scope, &["daemon-reload"]].concat()).await?;
+    }
+    Ok(())
 }
 
-const DEFAULT_TEMPLATE_CONTENT: &str = include_str!("../../../examples/cvr_template.yaml");
-
-async fn ensure_default_template(paths: &AppPaths) -> anyhow::Result<()> {
-    let template_path = paths.default_template_path();
-
-    if !fs::try_exists(&template_path).await.unwrap_or(false) {
-        if let Some(parent) = template_path.parent() {
-            fs::create_dir_all(parent).await?;
+async fn service_status(manager: ServiceManager, user: bool) -> anyhow::Result<()> {
+    let dir = manager.dir(&AppPaths::new()?, user);
+    let mut running = false;
+    for (index, name) in service_file_names(manager).into_iter().enumerate() {
+        let path = dir.join(&name);
+        if !fs::try_exists(&path).await.unwrap_or(false) {
+            if index == 0 {
+                println!("{}: not installed", name);
+            }
+            continue;
+        }
+        let state = match manager {
+            ServiceManager::Systemd if name.ends_with("-merge.service") => continue,
+            ServiceManager::Systemd => {
+                let mut command = tokio::process::Command::new("systemctl");
+                if user {
+                    command.arg("--user");
+                }
+                let output = command.args(["is-active", &name]).output().await?;
+                String::from_utf8_lossy(&output.stdout).trim().to_string()
+            }
+            ServiceManager::Launchd => {
+                let label = name.trim_end_matches(".plist");
+                let output = tokio::process::Command::new("launchctl")
+                    .args(["list", label])
+                    .output()
+                    .await?;
+                launchd_job_state(
+                    output.status.success(),
+                    &String::from_utf8_lossy(&output.stdout),
+                )
+            }
+        };
+        if index == 0 {
+            running = state == "active" || state.starts_with("running");
         }
-        fs::write(&template_path, DEFAULT_TEMPLATE_CONTENT).await?;
-        tracing::info!(path = %template_path.display(), "installed default template");
+        println!("{}: {}", name, state);
+    }
+    if !running {
+        return Err(ExitStatus(3).into());
     }
-
     Ok(())
 }
 
-#[derive(Args)]
-struct TestArgs {
-    /// Path to mihomo binary (defaults to `mihomo` in PATH)
-    #[arg(long = "mihomo-bin", default_value = "mihomo")]
-    mihomo_bin: String,
-
-    /// Config file to test (defaults to ~/.config/mihomocli/output/clash-verge.yaml)
-    #[arg(long)]
-    config: Option<PathBuf>,
+/// Files `service install` may create, the mihomo service first.
+fn service_file_names(manager: ServiceManager) -> Vec<String> {
+    match manager {
+        ServiceManager::Systemd => [".service", "-merge.service", "-merge.timer"]
+            .iter()
+            .map(|suffix| format!("{}{}", SYSTEMD_SERVICE_NAME, suffix))
+            .collect(),
+        ServiceManager::Launchd => [LAUNCHD_LABEL, LAUNCHD_MERGE_LABEL]
+            .iter()
+            .map(|label| format!("{}.plist", label))
+            .collect(),
+    }
+}
 
-    /// Working directory passed to mihomo via -d (defaults to ~/.config/mihomocli)
-    #[arg(long = "mihomo-dir")]
-    mihomo_dir: Option<PathBuf>,
+/// `running (pid N)`, `loaded` or `not loaded` from `launchctl list <label>`.
+fn launchd_job_state(loaded: bool, listing: &str) -> String {
+    if !loaded {
+        return "not loaded".to_string();
+    }
+    listing
+        .lines()
+        .find_map(|line| {
+            let pid = line.trim().strip_prefix("\"PID\" = ")?;
+            Some(format!("running (pid {})", pid.trim_end_matches(';')))
+        })
+        .unwrap_or_else(|| "loaded".to_string())
 }
 
-async fn run_test(args: TestArgs) -> anyhow::Result<()> {
-    use tokio::process::Command;
+async fn service_command(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("failed to run {}", program))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "`{} {}` failed: {}",
+            program,
+            args.join(" "),
+            trimmed_single_line(&stderr).unwrap_or_else(|| output.status.to_string())
+        ));
+    }
+    Ok(())
+}
 
+async fn run_rules(cmd: RulesCommand) -> anyhow::Result<()> {
     let paths = AppPaths::new()?;
-    let config_path = args
-        .config
-        .unwrap_or_else(|| paths.generated_clash_verge_path());
-    let workdir = args
-        .mihomo_dir
-        .unwrap_or_else(|| paths.config_dir().to_path_buf());
-
-    let status = Command::new(&args.mihomo_bin)
-        .arg("-d")
-        .arg(workdir)
-        .arg("-f")
-        .arg(&config_path)
-        .arg("-m")
-        .arg("-t")
-        .status()
-        .await?;
-
-    if status.success() {
-        println!("mihomo config test passed: {}", config_path.display());
-        Ok(())
-    } else {
-        Err(anyhow!(
-            "mihomo config test failed (exit code: {:?})",
-            status.code()
-        ))
+    match cmd {
+        RulesCommand::Import(args) => rules_import(&paths, args).await,
+        RulesCommand::Remove(args) => {
+            let mut app_cfg = storage::load_app_config(&paths).await?;
+            if app_cfg.rule_sets.remove(&args.name).is_none() {
+                return Err(anyhow!("no imported rule set named '{}'", args.name));
+            }
+            storage::save_app_config(&paths, &app_cfg).await?;
+            let path = paths.rule_set_path(&args.name);
+            if let Err(err) = fs::remove_file(&path).await {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(err)
+                        .with_context(|| format!("failed to remove {}", path.display()));
+                }
+            }
+            println!("removed rule set '{}'", args.name);
+            Ok(())
+        }
     }
 }
 
-fn subscription_from_input(index: usize, input: &str) -> Subscription {
-    let mut subscription = Subscription {
-        id: String::new(),
-        name: format!("cli-{}", index),
-        url: None,
-        path: None,
-        last_updated: None,
-        etag: None,
-        last_modified: None,
-        kind: SubscriptionKind::Clash,
-        enabled: true,
-    };
-
-    if is_url(input) {
-        subscription.url = Some(input.to_string());
-        subscription.name = url_name(input).unwrap_or(subscription.name.clone());
+async fn rules_import(paths: &AppPaths, args: RulesImportArgs) -> anyhow::Result<()> {
+    let raw = if is_url(&args.source) {
+        download_text(paths, args.fetch_proxy.as_deref(), &args.source).await?
     } else {
-        subscription.path = Some(PathBuf::from(input));
-        subscription.name = Path::new(input)
-            .file_stem()
-            .and_then(|stem| stem.to_str())
-            .map(|s| s.to_string())
-            .unwrap_or(subscription.name.clone());
+        fs::read_to_string(&args.source)
+            .await
+            .with_context(|| format!("failed to read {}", args.source))?
+    };
+    let format = match args.format {
+        BlocklistFormatArg::Hosts => BlocklistFormat::Hosts,
+        BlocklistFormatArg::Adblock => BlocklistFormat::Adblock,
+    };
+    let domains = parse_blocklist(&raw, format);
+    if domains.is_empty() {
+        return Err(anyhow!(
+            "{} has no domain entries in {:?} format",
+            args.source,
+            args.format
+        ));
     }
 
-    subscription.ensure_id();
-    subscription
-}
-
-/// Parse host:port from a string. Supports "host:port" and "[IPv6]:port".
-fn parse_host_port(s: &str) -> Option<(String, u16)> {
-    // Bracketed IPv6 like [::1]:9090
-    if let Some(close_idx) = s.rfind(']') {
-        let open_idx = s.find('[')?;
-        if close_idx < s.len().saturating_sub(2) && s.as_bytes().get(close_idx + 1) == Some(&b':') {
-            let host = s.get(open_idx + 1..close_idx)?.to_string();
-            let port_str = s.get(close_idx + 2..)?;
-            if let Ok(port) = port_str.parse::<u16>() {
-                return Some((host, port));
+    let mut app_cfg = storage::load_app_config(paths).await?;
+    let name = if args.inline {
+        None
+    } else {
+        let name = match args.name.clone() {
+            Some(name) => name,
+            None => {
+                let tail = args
+                    .source
+                    .trim_end_matches('/')
+                    .rsplit(['/', '\\'])
+                    .next()
+                    .unwrap_or_default();
+                let tail = tail.split(['?', '#']).next().unwrap_or_default();
+                Path::new(tail)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
             }
+        };
+        if !is_template_name(&name) {
+            return Err(anyhow!(
+                "invalid rule set name '{}' (use letters, digits, '-', '_' or '.'; pass --name)",
+                name
+            ));
         }
-        return None;
-    }
+        Some(name)
+    };
 
-    // Fallback: split by last ':'
-    if let Some(idx) = s.rfind(':') {
-        let (host, port_str) = s.split_at(idx);
-        let port_str = &port_str[1..];
-        if let Ok(port) = port_str.parse::<u16>() {
-            return Some((host.to_string(), port));
+    let mut payloads = BTreeMap::new();
+    let earlier_sets = match name.as_deref() {
+        Some(name) => app_cfg
+            .rule_sets
+            .keys()
+            .take_while(|set_name| set_name.as_str() < name)
+            .collect(),
+        None => Vec::new(),
+    };
+    for set_name in earlier_sets {
+        if let Ok(payload) = storage::load_rule_set(paths, set_name).await {
+            payloads.insert(set_name.clone(), payload);
         }
     }
-    None
-}
+    let existing = rules_ahead_of_import(&app_cfg, &payloads, name.as_deref());
+    let total = domains.len();
+    let fresh: Vec<BlockedDomain> = domains
+        .into_iter()
+        .filter(|domain| !existing.iter().any(|rule| domain.is_covered_by(rule)))
+        .collect();
+    let covered = total - fresh.len();
+
+    let Some(name) = name else {
+        let added = fresh.len();
+        app_cfg
+            .custom_rules
+            .extend(fresh.into_iter().map(|domain| CustomRule {
+                domain: domain.domain,
+                kind: domain.kind,
+                via: args.via.clone(),
+                active_hours: Vec::new(),
+            }));
+        storage::save_app_config(paths, &app_cfg).await?;
+        println!(
+            "added {} custom rule(s) via {} ({} already covered by existing rules)",
+            added, args.via, covered
+        );
+        return Ok(());
+    };
 
-fn normalize_controller_host(host: &str) -> String {
-    let trimmed = host.trim().trim_matches(['[', ']']);
-    if trimmed == "0.0.0.0" || trimmed == "::" || trimmed == "*" || trimmed.is_empty() {
-        "127.0.0.1".to_string()
-    } else {
-        trimmed.to_string()
-    }
+    let payload: Vec<String> = fresh.iter().map(BlockedDomain::payload_entry).collect();
+    storage::save_rule_set(paths, &name, &payload).await?;
+    app_cfg.rule_sets.insert(
+        name.clone(),
+        ImportedRuleSet {
+            source: args.source.clone(),
+            via: args.via.clone(),
+        },
+    );
+    storage::save_app_config(paths, &app_cfg).await?;
+    println!(
+        "imported {} domain(s) into rule set '{}' via {} ({} already covered by existing rules); \
+         it is merged from the next run",
+        payload.len(),
+        name,
+        args.via,
+        covered
+    );
+    Ok(())
 }
 
-fn is_url(input: &str) -> bool {
-    input.starts_with("http://") || input.starts_with("https://")
+/// Rules a merge places ahead of an import, the only ones that can shadow it: the custom
+/// rules, then the imported sets (from `payloads`) that sort before `name`. An inline import
+/// (`name` is `None`) lands right after the custom rules.
+fn rules_ahead_of_import(
+    app_cfg: &mihomo_core::storage::AppConfig,
+    payloads: &BTreeMap<String, Vec<String>>,
+    name: Option<&str>,
+) -> Vec<String> {
+    let mut rules: Vec<String> = app_cfg
+        .custom_rules
+        .iter()
+        .map(|rule| {
+            BlockedDomain {
+                domain: rule.domain.clone(),
+                kind: rule.kind,
+            }
+            .rule(&rule.via)
+        })
+        .collect();
+    let Some(name) = name else {
+        return rules;
+    };
+    let earlier_sets = app_cfg
+        .rule_sets
+        .iter()
+        .take_while(|(set_name, _)| set_name.as_str() < name);
+    for (set_name, set) in earlier_sets {
+        let Some(payload) = payloads.get(set_name) else {
+            continue;
+        };
+        rules.extend(
+            payload
+                .iter()
+                .filter_map(|entry| BlockedDomain::from_payload_entry(entry))
+                .map(|domain| domain.rule(&set.via)),
+        );
+    }
+    rules
 }
 
-fn url_name(input: &str) -> Option<String> {
-    let start = input.find("//")? + 2;
-    let rest = &input[start..];
-    let end = rest.find('/')?;
-    Some(rest[..end].to_string())
-}
+const MIHOMO_RELEASES_API: &str = "https://api.github.com/repos/MetaCubeX/mihomo/releases";
 
-async fn load_subscriptions_from_path(path: &Path) -> anyhow::Result<SubscriptionList> {
-    match fs::read_to_string(path).await {
-        Ok(contents) => Ok(serde_yaml::from_str(&contents)?),
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(SubscriptionList::default()),
-        Err(err) => Err(err.into()),
+async fn run_core_install(args: CoreInstallArgs) -> anyhow::Result<()> {
+    let paths = AppPaths::new()?;
+    let target = paths.managed_mihomo_path();
+    storage::ensure_writable(&target)?;
+    let fetch_proxy = args.fetch_proxy.as_deref();
+    let release_url = match args.version.as_ref() {
+        Some(tag) => format!("{MIHOMO_RELEASES_API}/tags/{tag}"),
+        None => format!("{MIHOMO_RELEASES_API}/latest"),
+    };
+    let release: GithubRelease =
+        serde_json::from_str(&download_text(&paths, fetch_proxy, &release_url).await?)
+            .with_context(|| format!("failed to parse the release from {}", release_url))?;
+
+    if !args.force {
+        if let Some(installed) = installed_mihomo_version(&target).await {
+            if installed == release.tag_name {
+                println!("{} is already {}", target.display(), installed);
+                return Ok(());
+            }
+        }
     }
-}
 
-async fn save_subscriptions_to_path(path: &Path, list: &SubscriptionList) -> anyhow::Result<()> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).await?;
+    let asset_name = mihomo_asset_name(&release.tag_name).ok_or_else(|| {
+        anyhow!(
+            "mihomo publishes no binary for {}/{}",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        )
+    })?;
+    let asset = release
+        .assets
+        .iter()
+        .find(|asset| asset.name == asset_name)
+        .ok_or_else(|| anyhow!("release {} has no asset {}", release.tag_name, asset_name))?;
+    let expected = mihomo_asset_digest(&paths, fetch_proxy, &release, asset).await?;
+
+    info!(asset = %asset.name, "downloading mihomo");
+    let archive = download_bytes(&paths, fetch_proxy, &asset.browser_download_url)
+        .await
+        .with_context(|| format!("failed to download {}", asset.name))?;
+    let actual = {
+        use sha2::Digest;
+        hex::encode(sha2::Sha256::digest(&archive))
+    };
+    if actual != expected {
+        return Err(anyhow!(
+            "checksum mismatch for {}: expected {}, got {}",
+            asset.name,
+            expected,
+            actual
+        ));
     }
-    fs::write(path, serde_yaml::to_string(list)?).await?;
+
+    let binary = extract_mihomo_binary(&archive, &asset.name)?;
+    fs::create_dir_all(paths.bin_dir()).await?;
+    replace_executable(&target, &binary)?;
+    println!(
+        "installed mihomo {} to {}",
+        release.tag_name,
+        target.display()
+    );
     Ok(())
 }
 
-const RESOURCE_SOURCES: &[(&str, &str)] = &[
-    (
-        "Country.mmdb",
-        "https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/country.mmdb",
-    ),
-    (
-        "geoip.dat",
-        "https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/geoip.dat",
-    ),
-    (
-        "geosite.dat",
-        "https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/geosite.dat",
-    ),
-];
-
-async fn ensure_mihomo_resources(client: &reqwest::Client, paths: &AppPaths) -> anyhow::Result<()> {
-    for (name, url) in RESOURCE_SOURCES.iter() {
-        let target = paths.resource_file(name);
+/// `mihomo-<os>-<arch>-<tag>.gz` (`.zip` on Windows), as published by MetaCubeX/mihomo.
+fn mihomo_asset_name(tag: &str) -> Option<String> {
+    let os = match std::env::consts::OS {
+        "linux" => "linux",
+        "macos" => "darwin",
+        "windows" => "windows",
+        "freebsd" => "freebsd",
+        _ => return None,
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "arm" => "armv7",
+        "riscv64" => "riscv64",
+        _ => return None,
+    };
+    let extension = if os == "windows" { "zip" } else { "gz" };
+    Some(format!("mihomo-{os}-{arch}-{tag}.{extension}"))
+}
 
-        if fs::try_exists(&target).await.unwrap_or(false) {
-            continue;
-        }
+/// Expected SHA-256 of `asset`: GitHub's asset digest, else the entry of a `checksums.txt`
+/// style asset of the same release.
+async fn mihomo_asset_digest(
+    paths: &AppPaths,
+    fetch_proxy: Option<&str>,
+    release: &GithubRelease,
+    asset: &GithubAsset,
+) -> anyhow::Result<String> {
+    if let Some(digest) = asset
+        .digest
+        .as_deref()
+        .and_then(|digest| digest.strip_prefix("sha256:"))
+    {
+        return Ok(digest.to_ascii_lowercase());
+    }
+    let sums = release.assets.iter().find(|candidate| {
+        let name = candidate.name.to_ascii_lowercase();
+        name == format!("{}.sha256", asset.name.to_ascii_lowercase())
+            || (name.contains("checksum") && name.ends_with(".txt"))
+    });
+    let Some(sums) = sums else {
+        return Err(anyhow!(
+            "release {} publishes no checksum for {}",
+            release.tag_name,
+            asset.name
+        ));
+    };
+    let text = download_text(paths, fetch_proxy, &sums.browser_download_url).await?;
+    checksum_for(&text, &asset.name)
+        .ok_or_else(|| anyhow!("{} has no checksum for {}", sums.name, asset.name))
+}
+
+/// Digest for `asset_name` in a `checksums.txt` (`<hex>  <name>` per line) or a bare
+/// `.sha256` file.
+fn checksum_for(text: &str, asset_name: &str) -> Option<String> {
+    text.lines()
+        .filter(|line| {
+            let mut fields = line.split_whitespace();
+            fields.next().is_some()
+                && fields
+                    .next()
+                    .is_none_or(|name| name.trim_start_matches('*') == asset_name)
+        })
+        .find_map(parse_sha256_file)
+}
 
-        info!(resource = %name, "downloading resource");
-        let response = client.get(*url).send().await?;
-        if !response.status().is_success() {
-            warn!(resource = %name, status = ?response.status(), "failed to download resource");
-            return Err(anyhow!("failed to download {name} from {url}"));
-        }
+fn extract_mihomo_binary(archive: &[u8], asset_name: &str) -> anyhow::Result<Vec<u8>> {
+    use std::io::Read;
 
-        let bytes = response.bytes().await?;
-        if let Some(parent) = target.parent() {
-            fs::create_dir_all(parent).await?;
+    let mut binary = Vec::new();
+    if asset_name.ends_with(".zip") {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive))?;
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index)?;
+            if file.name().ends_with(".exe") {
+                file.read_to_end(&mut binary)?;
+                return Ok(binary);
+            }
         }
-        fs::write(&target, &bytes).await?;
+        return Err(anyhow!("{} does not contain an executable", asset_name));
     }
+    flate2::read::GzDecoder::new(archive)
+        .read_to_end(&mut binary)
+        .with_context(|| format!("failed to decompress {}", asset_name))?;
+    Ok(binary)
+}
 
-    Ok(())
+/// Release tag reported by `<bin> -v` (`Mihomo Meta v1.19.3 linux amd64 ...`).
+async fn installed_mihomo_version(bin: &Path) -> Option<String> {
+    let output = tokio::process::Command::new(bin)
+        .arg("-v")
+        .output()
+        .await
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .find(|word| word.starts_with('v') && word[1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map(str::to_string)
+}
+
+/// mihomo binary for `test`/`doctor`: `--mihomo-bin`, else `mihomo` from PATH, else the binary
+/// installed by `core install` when present.
+fn mihomo_binary(paths: &AppPaths, explicit: Option<String>) -> PathBuf {
+    if let Some(bin) = explicit {
+        return PathBuf::from(bin);
+    }
+    let name = format!("mihomo{}", std::env::consts::EXE_SUFFIX);
+    let in_path = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir.join(&name).is_file()))
+        .unwrap_or(false);
+    let managed = paths.managed_mihomo_path();
+    if !in_path && managed.is_file() {
+        return managed;
+    }
+    PathBuf::from("mihomo")
 }
 
 // Management commands (cache and custom rules)
@@ -4257,6 +11529,194 @@ Tips
         #[command(subcommand)]
         command: ServerCmd,
     },
+
+    /// Inspect saved subscriptions (subscriptions.yaml)
+    #[command(visible_alias = "sub")]
+    Subscription {
+        #[command(subcommand)]
+        command: SubscriptionCmd,
+    },
+
+    /// Manage extra inbound listeners (mihomo `listeners:`) added on every merge
+    #[command(visible_alias = "listener")]
+    Listeners {
+        #[command(subcommand)]
+        command: ListenersCmd,
+    },
+
+    /// Manage static host entries merged into the output `hosts:` section
+    Hosts {
+        #[command(subcommand)]
+        command: HostsCmd,
+    },
+
+    /// Show or clear the per-node reachability record used by `merge --exclude-dead-after`
+    #[command(visible_alias = "node")]
+    Nodes {
+        #[command(subcommand)]
+        command: NodesCmd,
+    },
+
+    /// Keep the controller secret and subscription URLs in the OS keyring, referenced from
+    /// app.yaml/subscriptions.yaml as `keyring:<name>`
+    #[command(visible_alias = "secret")]
+    Secrets {
+        #[command(subcommand)]
+        command: SecretsCmd,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretsCmd {
+    /// Store a value in the keyring and print the `keyring:<name>` reference to use in YAML
+    Set(SecretSetArgs),
+    /// Delete a keyring entry
+    Remove(SecretNameArgs),
+    /// Move the plaintext controller secret and subscription URLs into the keyring, leaving
+    /// references behind
+    Migrate,
+}
+
+#[derive(Args)]
+struct SecretSetArgs {
+    /// Entry name, e.g. `airport-url`
+    name: String,
+    /// Value to store; read from the first line of stdin when omitted (keeps it out of the
+    /// shell history)
+    #[arg(long)]
+    value: Option<String>,
+}
+
+#[derive(Args)]
+struct SecretNameArgs {
+    /// Entry name
+    name: String,
+}
+
+#[derive(Subcommand)]
+enum HostsCmd {
+    /// Pin a hostname to one or more IP addresses (replaces its previous addresses)
+    Add(HostsAddArgs),
+    /// List host entries
+    List,
+    /// Remove a host entry
+    Remove(HostsRemoveArgs),
+}
+
+#[derive(Args)]
+struct HostsAddArgs {
+    /// Hostname, or a mihomo wildcard such as `*.corp.lan` / `+.corp.lan`
+    name: String,
+    /// IP address(es) to resolve the name to
+    #[arg(required = true)]
+    ips: Vec<std::net::IpAddr>,
+}
+
+#[derive(Args)]
+struct HostsRemoveArgs {
+    /// Hostname to remove
+    name: String,
+}
+
+#[derive(Subcommand)]
+enum NodesCmd {
+    /// List recorded nodes with their failed runs in a row and last check
+    List,
+    /// Forget the record of the named nodes (all nodes when none are named), so excluded
+    /// nodes are merged again
+    Reset(NodesResetArgs),
+}
+
+#[derive(Args)]
+struct NodesResetArgs {
+    /// Proxy names to forget
+    names: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum ListenersCmd {
+    /// Add or update an inbound listener
+    Add(ListenerAddArgs),
+    /// List managed listeners
+    List,
+    /// Remove a listener by name
+    Remove(ListenerRemoveArgs),
+}
+
+#[derive(Args)]
+struct ListenerAddArgs {
+    /// Unique listener name (e.g., socks-lan)
+    name: String,
+    /// Listener type
+    #[arg(long = "type", value_enum, default_value = "mixed")]
+    kind: ListenerKindArg,
+    /// Port to listen on
+    #[arg(long)]
+    port: u16,
+    /// Address to bind; use an interface's address to expose the port only there, or 0.0.0.0
+    /// for all interfaces
+    #[arg(long, default_value = "127.0.0.1")]
+    listen: String,
+    /// Route all traffic from this listener through a proxy or group, bypassing rules
+    #[arg(long)]
+    proxy: Option<String>,
+    /// Disable UDP relay (socks/mixed only)
+    #[arg(long, default_value_t = false)]
+    no_udp: bool,
+    /// Replace existing entry with the same name
+    #[arg(long, default_value_t = false)]
+    replace: bool,
+}
+
+#[derive(Args)]
+struct ListenerRemoveArgs {
+    /// Name to remove
+    name: String,
+}
+
+#[derive(Subcommand)]
+enum SubscriptionCmd {
+    /// List saved subscriptions with their status and failure counts
+    List(SubscriptionListArgs),
+    /// Enable a subscription (by id or name) and reset its failure count
+    Enable(SubscriptionKeyArgs),
+    /// Disable a subscription (by id or name)
+    Disable(SubscriptionKeyArgs),
+    /// Show traffic quota and expiry reported by the provider (subscription-userinfo)
+    Info(SubscriptionInfoArgs),
+    /// Show recorded traffic usage per day and project it to the quota reset
+    Usage(SubscriptionUsageArgs),
+}
+
+#[derive(Args)]
+struct SubscriptionListArgs {
+    /// Also show freshness: last update, how the last fetch was served, cache age and validators
+    #[arg(long, short = 'v', default_value_t = false)]
+    verbose: bool,
+}
+
+#[derive(Args)]
+struct SubscriptionInfoArgs {
+    /// Subscription id or name (default: all URL subscriptions)
+    key: Option<String>,
+}
+
+#[derive(Args)]
+struct SubscriptionUsageArgs {
+    /// Subscription id or name
+    key: String,
+    /// Number of most recent days to list
+    #[arg(long, default_value_t = 14)]
+    days: usize,
+    /// Day of month the provider resets the quota (default: the subscription's expire date)
+    #[arg(long, value_name = "DAY", value_parser = clap::value_parser!(u32).range(1..=31))]
+    reset_day: Option<u32>,
+}
+
+#[derive(Args)]
+struct SubscriptionKeyArgs {
+    /// Subscription id or name
+    key: String,
 }
 
 #[derive(Subcommand)]
@@ -4288,6 +11748,10 @@ struct CustomAddArgs {
     /// Match kind: domain|suffix|keyword (default: suffix)
     #[arg(long, default_value = "suffix")]
     kind: String,
+    /// Only merge the rule during these local time windows (HH:MM-HH:MM, comma separated or
+    /// repeated; 22:00-06:00 runs past midnight). `merge --watch` re-merges at each boundary.
+    #[arg(long = "active-hours", value_name = "WINDOWS", value_delimiter = ',')]
+    active_hours: Vec<TimeWindow>,
 }
 
 #[derive(Args)]
@@ -4353,7 +11817,166 @@ async fn run_manage(cmd: Manage) -> anyhow::Result<()> {
         Manage::Check(c) => manage_check(&paths, c).await,
         Manage::DevList(args) => manage_dev_list(args).await,
         Manage::Server { command } => manage_server(&paths, command).await,
+        Manage::Subscription { command } => manage_subscription(&paths, command).await,
+        Manage::Listeners { command } => manage_listeners(&paths, command).await,
+        Manage::Hosts { command } => manage_hosts(&paths, command).await,
+        Manage::Nodes { command } => manage_nodes(&paths, command).await,
+        Manage::Secrets { command } => manage_secrets(&paths, command).await,
+    }
+}
+
+async fn manage_subscription(paths: &AppPaths, cmd: SubscriptionCmd) -> anyhow::Result<()> {
+    let mut list = storage::load_subscription_list(paths).await?;
+    match cmd {
+        SubscriptionCmd::List(args) => {
+            if list.items.is_empty() {
+                println!("<no subscriptions>");
+            }
+            let now = chrono::Utc::now();
+            for sub in &list.items {
+                let state = if sub.enabled { "enabled" } else { "disabled" };
+                let name = if sub.name.is_empty() { "-" } else { &sub.name };
+                println!("{} [{}] {}", name, state, sub.id);
+                if let Some(format) = sub.format {
+                    println!("  format: {}", format.as_str());
+                }
+                if args.verbose {
+                    match sub.last_updated {
+                        Some(updated) => println!(
+                            "  last update: {} ({} ago, {})",
+                            updated.format("%Y-%m-%d %H:%M UTC"),
+                            format_age(now - updated),
+                            sub.last_source.map(|source| source.as_str()).unwrap_or("-")
+                        ),
+                        None => println!("  last update: <never>"),
+                    }
+                    if let Some(modified) = sub.cache_modified(paths).await {
+                        println!("  cached content age: {}", format_age(now - modified));
+                    }
+                    if let Some(etag) = sub.etag.as_deref() {
+                        println!("  etag: {}", etag);
+                    }
+                    if let Some(last_modified) = sub.last_modified.as_deref() {
+                        println!("  last-modified: {}", last_modified);
+                    }
+                    if let Some(secs) = sub.update_interval {
+                        println!(
+                            "  provider update interval: {}",
+                            format_age(chrono::Duration::seconds(secs as i64))
+                        );
+                    }
+                }
+                if sub.failure_count > 0 {
+                    println!(
+                        "  failures: {} (last error: {})",
+                        sub.failure_count,
+                        sub.last_error.as_deref().unwrap_or("<unknown>")
+                    );
+                }
+            }
+        }
+        SubscriptionCmd::Enable(args) => {
+            let sub = list
+                .find_mut(&args.key)
+                .ok_or_else(|| anyhow!("no subscription with id or name {}", args.key))?;
+            sub.enabled = true;
+            sub.clear_failures();
+            storage::save_subscription_list(paths, &list).await?;
+            println!("enabled subscription {}", args.key);
+        }
+        SubscriptionCmd::Disable(args) => {
+            let sub = list
+                .find_mut(&args.key)
+                .ok_or_else(|| anyhow!("no subscription with id or name {}", args.key))?;
+            sub.enabled = false;
+            storage::save_subscription_list(paths, &list).await?;
+            println!("disabled subscription {}", args.key);
+        }
+        SubscriptionCmd::Info(args) => {
+            let selected: Vec<&Subscription> = match args.key.as_deref() {
+                Some(key) => vec![list
+                    .find_mut(key)
+                    .map(|sub| &*sub)
+                    .ok_or_else(|| anyhow!("no subscription with id or name {}", key))?],
+                None => list.items.iter().filter(|sub| sub.url.is_some()).collect(),
+            };
+            if selected.is_empty() {
+                println!("<no subscriptions>");
+            }
+            let now = chrono::Utc::now();
+            for sub in selected {
+                println!("{}", subscription_label(sub));
+                let Some(usage) = sub.usage(paths).await? else {
+                    println!("  <no subscription-userinfo reported; run merge to refresh>");
+                    continue;
+                };
+                println!(
+                    "  used: {} (upload {}, download {})",
+                    format_bytes(usage.used()),
+                    format_bytes(usage.upload),
+                    format_bytes(usage.download)
+                );
+                match usage.remaining() {
+                    Some(remaining) => println!(
+                        "  total: {} ({} left)",
+                        format_bytes(usage.total),
+                        format_bytes(remaining)
+                    ),
+                    None => println!("  total: <unlimited>"),
+                }
+                match usage.expire {
+                    Some(expire) => println!("  expires: {}", expire.format("%Y-%m-%d %H:%M UTC")),
+                    None => println!("  expires: <never>"),
+                }
+                for message in usage.warnings(now) {
+                    println!("  warning: {}", message);
+                }
+            }
+        }
+        SubscriptionCmd::Usage(args) => {
+            let sub = list
+                .find_mut(&args.key)
+                .ok_or_else(|| anyhow!("no subscription with id or name {}", args.key))?;
+            let history = load_usage_history(&paths.usage_history_file(&sub.id)).await?;
+            println!("{}", subscription_label(sub));
+            if history.is_empty() {
+                println!("  <no usage recorded yet; it is sampled on every fetch>");
+                return Ok(());
+            }
+            let days = daily_usage(&history);
+            println!("  {:<12} {:>12} {:>12}", "date", "used", "period total");
+            for day in &days[days.len().saturating_sub(args.days)..] {
+                println!(
+                    "  {:<12} {:>12} {:>12}",
+                    day.date.to_string(),
+                    format_bytes(day.delta),
+                    format_bytes(day.used)
+                );
+            }
+            let now = chrono::Utc::now();
+            let reset = args
+                .reset_day
+                .map(|day| mihomo_core::subscription::next_monthly_reset(now, day));
+            match project_usage(&history, reset, now) {
+                Some(projection) => {
+                    println!(
+                        "  rate: {}/day, projected {} of {} by {}",
+                        format_bytes(projection.per_day as u64),
+                        format_bytes(projection.projected),
+                        format_bytes(projection.total),
+                        projection.reset.format("%Y-%m-%d")
+                    );
+                    if let Some(message) = projection.warning() {
+                        println!("  warning: {}", message);
+                    }
+                }
+                None => println!(
+                    "  <no projection: needs a quota, a reset date and readings at least an hour apart>"
+                ),
+            }
+        }
     }
+    Ok(())
 }
 
 async fn manage_cache(paths: &AppPaths, cmd: CacheCmd) -> anyhow::Result<()> {
@@ -4396,6 +12019,7 @@ async fn manage_custom(paths: &AppPaths, cmd: CustomCmd) -> anyhow::Result<()> {
                 domain: args.domain,
                 kind,
                 via: via_value,
+                active_hours: args.active_hours,
             };
             if !cfg.custom_rules.contains(&rule) {
                 cfg.custom_rules.push(rule);
@@ -4415,7 +12039,19 @@ async fn manage_custom(paths: &AppPaths, cmd: CustomCmd) -> anyhow::Result<()> {
                         RuleKind::DomainSuffix => "DOMAIN-SUFFIX",
                         RuleKind::DomainKeyword => "DOMAIN-KEYWORD",
                     };
-                    println!("{},{},{}", kind, r.domain, r.via);
+                    if r.active_hours.is_empty() {
+                        println!("{},{},{}", kind, r.domain, r.via);
+                    } else {
+                        let windows: Vec<String> =
+                            r.active_hours.iter().map(ToString::to_string).collect();
+                        println!(
+                            "{},{},{}  (active {})",
+                            kind,
+                            r.domain,
+                            r.via,
+                            windows.join(", ")
+                        );
+                    }
                 }
             }
         }
@@ -4443,7 +12079,8 @@ async fn manage_custom(paths: &AppPaths, cmd: CustomCmd) -> anyhow::Result<()> {
 async fn manage_check(paths: &AppPaths, args: CheckArgs) -> anyhow::Result<()> {
     let cfg = storage::load_app_config(paths).await?;
     // Check user custom rules first (highest precedence)
-    for r in &cfg.custom_rules {
+    let now = chrono::Local::now().time();
+    for r in cfg.custom_rules.iter().filter(|r| r.is_active_at(now)) {
         let kind = match r.kind {
             RuleKind::Domain => "DOMAIN",
             RuleKind::DomainSuffix => "DOMAIN-SUFFIX",
@@ -4530,6 +12167,358 @@ async fn manage_server(paths: &AppPaths, cmd: ServerCmd) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn manage_listeners(paths: &AppPaths, cmd: ListenersCmd) -> anyhow::Result<()> {
+    let mut cfg = storage::load_app_config(paths).await?;
+    match cmd {
+        ListenersCmd::Add(args) => {
+            if args.listen.parse::<std::net::IpAddr>().is_err() {
+                return Err(anyhow!(
+                    "--listen must be an IP address, got {}",
+                    args.listen
+                ));
+            }
+            if let Some(other) = cfg
+                .listeners
+                .iter()
+                .find(|l| l.port == args.port && l.name != args.name)
+            {
+                return Err(anyhow!(
+                    "port {} is already used by listener {}",
+                    args.port,
+                    other.name
+                ));
+            }
+
+            let entry = ListenerDef {
+                name: args.name.clone(),
+                kind: args.kind.into(),
+                port: args.port,
+                listen: args.listen.clone(),
+                proxy: args.proxy.clone(),
+                udp: !args.no_udp,
+            };
+
+            if let Some(existing) = cfg.listeners.iter_mut().find(|l| l.name == args.name) {
+                if args.replace {
+                    *existing = entry;
+                    storage::save_app_config(paths, &cfg).await?;
+                    println!("listener updated");
+                } else {
+                    println!("listener already exists (use --replace to update)");
+                }
+            } else {
+                cfg.listeners.push(entry);
+                storage::save_app_config(paths, &cfg).await?;
+                println!("listener added");
+            }
+        }
+        ListenersCmd::List => {
+            if cfg.listeners.is_empty() {
+                println!("<no listeners>");
+            } else {
+                for l in &cfg.listeners {
+                    println!(
+                        "{}\t{}\t{}:{}\tproxy={}",
+                        l.name,
+                        l.kind.as_str(),
+                        l.listen,
+                        l.port,
+                        l.proxy.as_deref().unwrap_or("<rules>")
+                    );
+                }
+            }
+        }
+        ListenersCmd::Remove(args) => {
+            let before = cfg.listeners.len();
+            cfg.listeners.retain(|l| l.name != args.name);
+            let after = cfg.listeners.len();
+            storage::save_app_config(paths, &cfg).await?;
+            println!("removed {} listener(s)", before.saturating_sub(after));
+        }
+    }
+    Ok(())
+}
+
+async fn manage_secrets(paths: &AppPaths, cmd: SecretsCmd) -> anyhow::Result<()> {
+    match cmd {
+        SecretsCmd::Set(args) => {
+            let value = match args.value {
+                Some(value) => value,
+                None => {
+                    use tokio::io::AsyncBufReadExt;
+                    let mut line = String::new();
+                    tokio::io::BufReader::new(tokio::io::stdin())
+                        .read_line(&mut line)
+                        .await?;
+                    line.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+            if value.is_empty() {
+                return Err(anyhow!("refusing to store an empty value"));
+            }
+            secrets::store_secret(&args.name, &value).await?;
+            println!("{}", secrets::keyring_reference(&args.name));
+        }
+        SecretsCmd::Remove(args) => {
+            secrets::delete_secret(&args.name).await?;
+            println!("removed keyring entry '{}'", args.name);
+        }
+        SecretsCmd::Migrate => {
+            let mut cfg = storage::load_app_config(paths).await?;
+            if let Some(secret) = cfg
+                .controller_secret
+                .clone()
+                .filter(|secret| secrets::keyring_name(secret).is_none())
+            {
+                secrets::store_secret(secrets::CONTROLLER_SECRET_ENTRY, &secret).await?;
+                cfg.controller_secret =
+                    Some(secrets::keyring_reference(secrets::CONTROLLER_SECRET_ENTRY));
+                storage::save_app_config(paths, &cfg).await?;
+                println!(
+                    "controller secret -> {}",
+                    secrets::keyring_reference(secrets::CONTROLLER_SECRET_ENTRY)
+                );
+            }
+
+            let mut list = storage::load_subscription_list(paths).await?;
+            let mut moved = 0;
+            let mut orphaned = 0;
+            for sub in list.items.iter_mut() {
+                let Some(url) = sub
+                    .url
+                    .clone()
+                    .filter(|url| secrets::keyring_name(url).is_none())
+                else {
+                    continue;
+                };
+                let key = if sub.name.is_empty() {
+                    &sub.id
+                } else {
+                    &sub.name
+                };
+                let entry = subscription_secret_entry(key);
+                secrets::store_secret(&entry, &url).await?;
+                // An id derived from the URL would keep the token in plain sight.
+                if sub.id == url {
+                    if list.current.as_deref() == Some(url.as_str()) {
+                        list.current = Some(entry.clone());
+                    }
+                    sub.id = entry.clone();
+                    orphaned += 1;
+                }
+                sub.url = Some(secrets::keyring_reference(&entry));
+                if cfg.last_subscription_url.as_deref() == Some(url.as_str()) {
+                    cfg.last_subscription_url = sub.url.clone();
+                    storage::save_app_config(paths, &cfg).await?;
+                }
+                println!(
+                    "subscription {} -> {}",
+                    subscription_label(sub),
+                    secrets::keyring_reference(&entry)
+                );
+                moved += 1;
+            }
+            if moved > 0 {
+                storage::save_subscription_list(paths, &list).await?;
+            }
+            println!("moved {} subscription URL(s) into the keyring", moved);
+            if orphaned > 0 {
+                println!(
+                    "{} subscription(s) got a new id; the caches kept under their old URL ids in {} \
+                     are no longer used and will be fetched again",
+                    orphaned,
+                    paths.cache_dir().display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keyring entry for a subscription's URL: `subscription.` plus its name, with anything but
+/// ASCII letters, digits, `-` and `_` replaced by `_`.
+fn subscription_secret_entry(name: &str) -> String {
+    let slug: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("subscription.{}", slug)
+}
+
+async fn manage_hosts(paths: &AppPaths, cmd: HostsCmd) -> anyhow::Result<()> {
+    let mut cfg = storage::load_app_config(paths).await?;
+    match cmd {
+        HostsCmd::Add(args) => {
+            let name = args.name.trim().to_ascii_lowercase();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(anyhow!("invalid host name: {:?}", args.name));
+            }
+            let ips: Vec<String> = args.ips.iter().map(ToString::to_string).collect();
+            let updated = cfg.hosts.insert(name, ips).is_some();
+            storage::save_app_config(paths, &cfg).await?;
+            println!(
+                "{}",
+                if updated {
+                    "host updated"
+                } else {
+                    "host added"
+                }
+            );
+        }
+        HostsCmd::List => {
+            if cfg.hosts.is_empty() {
+                println!("<no hosts>");
+            } else {
+                for (name, ips) in &cfg.hosts {
+                    println!("{}\t{}", name, ips.join(","));
+                }
+            }
+        }
+        HostsCmd::Remove(args) => {
+            let removed = cfg
+                .hosts
+                .remove(&args.name.trim().to_ascii_lowercase())
+                .is_some();
+            storage::save_app_config(paths, &cfg).await?;
+            println!("removed {} host(s)", usize::from(removed));
+        }
+    }
+    Ok(())
+}
+
+async fn manage_nodes(paths: &AppPaths, cmd: NodesCmd) -> anyhow::Result<()> {
+    let path = paths.node_liveness_file();
+    let mut liveness = NodeLiveness::load(&path).await;
+    match cmd {
+        NodesCmd::List => {
+            if liveness.nodes().is_empty() {
+                println!("<no nodes>");
+            } else {
+                for (name, record) in liveness.nodes() {
+                    println!(
+                        "{}\t{} failed\t{}",
+                        name,
+                        record.consecutive_failures,
+                        record.last_checked.to_rfc3339()
+                    );
+                }
+            }
+        }
+        NodesCmd::Reset(args) => {
+            storage::ensure_writable(&path)?;
+            let removed = liveness.reset(&args.names);
+            liveness.save(&path).await?;
+            println!("reset {} node(s)", removed);
+        }
+    }
+    Ok(())
+}
+
+/// Merge the managed host entries into `hosts:`; they win over entries from the template or
+/// base-config with the same name.
+fn inject_hosts(merged: &mut mihomo_core::ClashConfig, hosts: &BTreeMap<String, Vec<String>>) {
+    use serde_yaml::Mapping;
+    let slot = merged
+        .extra
+        .entry("hosts".to_string())
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if !slot.is_mapping() {
+        *slot = Value::Mapping(Mapping::new());
+    }
+    let map = slot.as_mapping_mut().expect("hosts is a mapping");
+    for (name, ips) in hosts {
+        let value = match ips.as_slice() {
+            [single] => Value::from(single.as_str()),
+            _ => Value::Sequence(ips.iter().map(|ip| Value::from(ip.as_str())).collect()),
+        };
+        map.insert(Value::from(name.as_str()), value);
+    }
+}
+
+/// Upsert the managed listeners into `listeners:` by name. A port already taken by the
+/// top-level inbounds or another listener is an error, since mihomo would fail to bind it.
+fn inject_listeners(
+    merged: &mut mihomo_core::ClashConfig,
+    listeners: &[ListenerDef],
+) -> anyhow::Result<()> {
+    let mut taken: Vec<(u64, String)> = [
+        "port",
+        "socks-port",
+        "mixed-port",
+        "redir-port",
+        "tproxy-port",
+    ]
+    .iter()
+    .filter_map(|key| {
+        merged
+            .extra
+            .get(*key)
+            .and_then(Value::as_u64)
+            .filter(|port| *port > 0)
+            .map(|port| (port, key.to_string()))
+    })
+    .collect();
+
+    let targets: HashSet<String> = merged
+        .proxy_names()
+        .into_iter()
+        .chain(merged.proxy_group_names())
+        .chain(["DIRECT", "REJECT"].map(String::from))
+        .collect();
+
+    let slot = merged
+        .extra
+        .entry("listeners".to_string())
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    if !slot.is_sequence() {
+        *slot = Value::Sequence(Vec::new());
+    }
+    let entries = slot.as_sequence_mut().expect("listeners is a sequence");
+    entries.retain(|entry| {
+        let name = entry.get("name").and_then(Value::as_str);
+        !listeners.iter().any(|l| Some(l.name.as_str()) == name)
+    });
+    for entry in entries.iter() {
+        if let (Some(port), Some(name)) = (
+            entry.get("port").and_then(Value::as_u64),
+            entry.get("name").and_then(Value::as_str),
+        ) {
+            taken.push((port, format!("listener {}", name)));
+        }
+    }
+
+    for listener in listeners {
+        if let Some((_, owner)) = taken
+            .iter()
+            .find(|(port, _)| *port == u64::from(listener.port))
+        {
+            return Err(anyhow!(
+                "listener {} uses port {}, which is already taken by {}",
+                listener.name,
+                listener.port,
+                owner
+            ));
+        }
+        if let Some(proxy) = listener.proxy.as_deref() {
+            if !targets.contains(proxy) {
+                warn!(listener = %listener.name, proxy = %proxy, "listener proxy not found in merged config");
+            }
+        }
+        taken.push((
+            u64::from(listener.port),
+            format!("listener {}", listener.name),
+        ));
+        entries.push(listener.to_value());
+    }
+    Ok(())
+}
+
 #[derive(Args)]
 struct DevListArgs {
     /// Output format: plain|yaml|json (default: plain)
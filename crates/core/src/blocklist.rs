@@ -0,0 +1,247 @@
+//! Domain block lists (hosts files, AdGuard/Adblock DNS filters) turned into mihomo rules.
+//!
+//! Only whole-domain entries are kept: cosmetic filters, exceptions, paths and wildcards have
+//! no rule equivalent and are skipped.
+
+use std::collections::BTreeSet;
+
+use crate::storage::RuleKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlocklistFormat {
+    /// `0.0.0.0 ads.example.com` (one or more names per line).
+    Hosts,
+    /// `||ads.example.com^`, optionally with `$important`; bare domains are taken as exact.
+    Adblock,
+}
+
+/// A blocked domain: exact (`DOMAIN`) or including subdomains (`DOMAIN-SUFFIX`).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockedDomain {
+    pub domain: String,
+    pub kind: RuleKind,
+}
+
+impl BlockedDomain {
+    /// `DOMAIN-SUFFIX,ads.example.com,REJECT`.
+    pub fn rule(&self, via: &str) -> String {
+        format!("{},{},{}", rule_tag(self.kind), self.domain, via)
+    }
+
+    /// Entry of a `behavior: domain` rule-provider payload (`+.` marks subdomains).
+    pub fn payload_entry(&self) -> String {
+        match self.kind {
+            RuleKind::DomainSuffix => format!("+.{}", self.domain),
+            _ => self.domain.clone(),
+        }
+    }
+
+    /// Inverse of [`Self::payload_entry`].
+    pub fn from_payload_entry(entry: &str) -> Option<Self> {
+        match entry.strip_prefix("+.") {
+            Some(domain) => normalize_domain(domain).map(|domain| Self {
+                domain,
+                kind: RuleKind::DomainSuffix,
+            }),
+            None => normalize_domain(entry).map(|domain| Self {
+                domain,
+                kind: RuleKind::Domain,
+            }),
+        }
+    }
+
+    /// Whether `rule` (a mihomo rule line) already sends every request for this entry
+    /// somewhere, so a block rule after it would never match.
+    pub fn is_covered_by(&self, rule: &str) -> bool {
+        let mut parts = rule.split(',').map(str::trim);
+        let (Some(tag), Some(target)) = (parts.next(), parts.next()) else {
+            return false;
+        };
+        let target = target.to_ascii_lowercase();
+        match tag.to_ascii_uppercase().as_str() {
+            "DOMAIN-SUFFIX" => is_same_or_subdomain(&self.domain, &target),
+            "DOMAIN" => self.kind == RuleKind::Domain && self.domain == target,
+            _ => false,
+        }
+    }
+}
+
+fn rule_tag(kind: RuleKind) -> &'static str {
+    match kind {
+        RuleKind::Domain => "DOMAIN",
+        RuleKind::DomainSuffix => "DOMAIN-SUFFIX",
+        RuleKind::DomainKeyword => "DOMAIN-KEYWORD",
+    }
+}
+
+/// Names hosts files map to loopback for the machine itself.
+const HOSTS_LOCAL_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+    "0.0.0.0",
+];
+
+/// Parse `text` and return its domains sorted and deduplicated; exact entries below a
+/// blocked suffix are dropped as redundant.
+pub fn parse_blocklist(text: &str, format: BlocklistFormat) -> Vec<BlockedDomain> {
+    let parsed: BTreeSet<BlockedDomain> = text
+        .lines()
+        .flat_map(|line| match format {
+            BlocklistFormat::Hosts => parse_hosts_line(line),
+            BlocklistFormat::Adblock => parse_adblock_line(line).into_iter().collect(),
+        })
+        .collect();
+    let suffixes: BTreeSet<&str> = parsed
+        .iter()
+        .filter(|entry| entry.kind == RuleKind::DomainSuffix)
+        .map(|entry| entry.domain.as_str())
+        .collect();
+    parsed
+        .iter()
+        .filter(|entry| {
+            !parent_domains(&entry.domain)
+                .skip(usize::from(entry.kind == RuleKind::DomainSuffix))
+                .any(|parent| suffixes.contains(parent))
+        })
+        .cloned()
+        .collect()
+}
+
+fn parse_hosts_line(line: &str) -> Vec<BlockedDomain> {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut fields = line.split_whitespace();
+    let Some(address) = fields.next() else {
+        return Vec::new();
+    };
+    if address.parse::<std::net::IpAddr>().is_err() {
+        return Vec::new();
+    }
+    fields
+        .filter(|name| !HOSTS_LOCAL_NAMES.contains(&name.to_ascii_lowercase().as_str()))
+        .filter_map(normalize_domain)
+        .map(|domain| BlockedDomain {
+            domain,
+            kind: RuleKind::Domain,
+        })
+        .collect()
+}
+
+fn parse_adblock_line(line: &str) -> Option<BlockedDomain> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(['!', '#', '[', '@']) || line.contains("##") {
+        return None;
+    }
+    let Some(rest) = line.strip_prefix("||") else {
+        return normalize_domain(line).map(|domain| BlockedDomain {
+            domain,
+            kind: RuleKind::Domain,
+        });
+    };
+    let (pattern, options) = rest.split_once('$').unwrap_or((rest, ""));
+    if !options.is_empty() && options != "important" {
+        return None;
+    }
+    let domain = pattern.strip_suffix('^').unwrap_or(pattern);
+    normalize_domain(domain).map(|domain| BlockedDomain {
+        domain,
+        kind: RuleKind::DomainSuffix,
+    })
+}
+
+/// Lowercase `raw` without a trailing dot, or `None` when it is not a plain host name
+/// (wildcards, paths, IP addresses, single labels).
+fn normalize_domain(raw: &str) -> Option<String> {
+    let domain = raw.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid = domain.contains('.')
+        && domain.parse::<std::net::IpAddr>().is_err()
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+    valid.then_some(domain)
+}
+
+/// `a.b.example.com`, `b.example.com`, `example.com`, `com`.
+fn parent_domains(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(domain), |current| {
+        current.split_once('.').map(|(_, parent)| parent)
+    })
+}
+
+fn is_same_or_subdomain(domain: &str, parent: &str) -> bool {
+    domain == parent || domain.ends_with(&format!(".{}", parent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_and_adblock_lists_are_normalized_and_deduped() {
+        let hosts = "\
+# StevenBlack style
+127.0.0.1 localhost
+0.0.0.0 0.0.0.0
+0.0.0.0 Ads.Example.com. tracker.example.net # trailing comment
+0.0.0.0 ads.example.com
+::1 ip6-localhost
+not-an-address example.org
+";
+        let domains = parse_blocklist(hosts, BlocklistFormat::Hosts);
+        let names: Vec<&str> = domains.iter().map(|d| d.domain.as_str()).collect();
+        assert_eq!(names, ["ads.example.com", "tracker.example.net"]);
+        assert!(domains.iter().all(|d| d.kind == RuleKind::Domain));
+
+        let adblock = "\
+! Title: AdGuard DNS filter
+[Adblock Plus 2.0]
+||doubleclick.net^
+||ad.doubleclick.net^
+||metrics.example.com^$important
+||example.org^$third-party
+@@||allowed.example.com^
+example.com##.banner
+||*.wildcard.com^
+/banner/ads/
+exact.example.io
+";
+        let domains = parse_blocklist(adblock, BlocklistFormat::Adblock);
+        let payload: Vec<String> = domains.iter().map(BlockedDomain::payload_entry).collect();
+        assert_eq!(
+            payload,
+            [
+                "+.doubleclick.net",
+                "exact.example.io",
+                "+.metrics.example.com"
+            ]
+        );
+        assert_eq!(
+            BlockedDomain::from_payload_entry("+.doubleclick.net").as_ref(),
+            domains.first()
+        );
+        assert_eq!(
+            domains[0].rule("REJECT"),
+            "DOMAIN-SUFFIX,doubleclick.net,REJECT"
+        );
+
+        let ad = BlockedDomain {
+            domain: "ad.doubleclick.net".to_string(),
+            kind: RuleKind::DomainSuffix,
+        };
+        assert!(ad.is_covered_by("DOMAIN-SUFFIX,doubleclick.net,DIRECT"));
+        assert!(!ad.is_covered_by("DOMAIN,ad.doubleclick.net,DIRECT"));
+        assert!(!ad.is_covered_by("MATCH,Proxy"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Export a merged config into Clash Verge's profiles layout.
+//!
+//! Clash Verge keeps `profiles.yaml` (the profile list) next to a `profiles/` directory with
+//! one file per item. A profile may reference one Merge (`m…`) and one Script (`s…`) item via
+//! its `option`, which the GUI applies on activation. The item uids are derived from the
+//! profile name, so re-exporting updates the same entries instead of piling up new ones.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::model::ClashConfig;
+
+/// A profile to export: the config plus the chain the GUI should apply on top of it.
+#[derive(Debug, Clone)]
+pub struct VergeProfile<'a> {
+    pub name: &'a str,
+    pub config: &'a ClashConfig,
+    pub merge: Option<Mapping>,
+    pub script: Option<String>,
+}
+
+/// Write `profile` into the Clash Verge directory `dir` (the one holding `profiles.yaml`) and
+/// return the path of the profile YAML. The active profile is left unchanged.
+pub async fn write_profile(dir: &Path, profile: &VergeProfile<'_>) -> anyhow::Result<PathBuf> {
+    crate::storage::ensure_writable(dir)?;
+    let digest = hex::encode(Sha256::digest(format!("mihomo-cli:{}", profile.name)));
+    let uid = format!("l{}", &digest[..12]);
+    let merge_uid = format!("m{}", &digest[..12]);
+    let script_uid = format!("s{}", &digest[..12]);
+
+    let profiles_dir = dir.join("profiles");
+    fs::create_dir_all(&profiles_dir)
+        .await
+        .with_context(|| format!("failed to create {}", profiles_dir.display()))?;
+
+    let profile_path = profiles_dir.join(format!("{}.yaml", uid));
+    write_file(&profile_path, &profile.config.to_yaml_string()?).await?;
+    let merge_file = match &profile.merge {
+        Some(patch) => {
+            let file = format!("{}.yaml", merge_uid);
+            write_file(&profiles_dir.join(&file), &serde_yaml::to_string(patch)?).await?;
+            Some(file)
+        }
+        None => None,
+    };
+    let script_file = match &profile.script {
+        Some(source) => {
+            let file = format!("{}.js", script_uid);
+            write_file(&profiles_dir.join(&file), source).await?;
+            Some(file)
+        }
+        None => None,
+    };
+
+    let list_path = dir.join("profiles.yaml");
+    let mut list = match fs::read_to_string(&list_path).await {
+        Ok(raw) => serde_yaml::from_str::<Value>(&raw)
+            .with_context(|| format!("failed to parse {}", list_path.display()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Value::Null,
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", list_path.display()))
+        }
+    };
+    if !list.is_mapping() {
+        list = Value::Mapping(Mapping::new());
+    }
+    let root = list
+        .as_mapping_mut()
+        .expect("profiles.yaml root is a mapping");
+    if !root.get("items").is_some_and(Value::is_sequence) {
+        root.insert("items".into(), Value::Sequence(Vec::new()));
+    }
+    let items = root
+        .get_mut("items")
+        .and_then(Value::as_sequence_mut)
+        .expect("items is a sequence");
+
+    let updated = Value::from(chrono::Utc::now().timestamp());
+    let item = upsert_item(items, &uid);
+    item.insert("type".into(), "local".into());
+    item.insert("name".into(), profile.name.into());
+    item.insert("desc".into(), "generated by mihomo-cli".into());
+    item.insert("file".into(), format!("{}.yaml", uid).into());
+    item.insert("updated".into(), updated.clone());
+    if !item.get("option").is_some_and(Value::is_mapping) {
+        item.insert("option".into(), Value::Mapping(Mapping::new()));
+    }
+    let option = item
+        .get_mut("option")
+        .and_then(Value::as_mapping_mut)
+        .expect("option is a mapping");
+    for (key, chain_uid, file) in [
+        ("merge", &merge_uid, &merge_file),
+        ("script", &script_uid, &script_file),
+    ] {
+        match file {
+            Some(_) => {
+                option.insert(key.into(), chain_uid.as_str().into());
+            }
+            None => {
+                option.remove(key);
+            }
+        }
+    }
+
+    for (kind, chain_uid, file) in [
+        ("merge", &merge_uid, merge_file),
+        ("script", &script_uid, script_file),
+    ] {
+        match file {
+            Some(file) => {
+                let item = upsert_item(items, chain_uid);
+                item.insert("type".into(), kind.into());
+                item.insert("name".into(), format!("{} ({})", profile.name, kind).into());
+                item.insert("file".into(), file.into());
+                item.insert("updated".into(), updated.clone());
+            }
+            None => {
+                items.retain(|item| item.get("uid").and_then(Value::as_str) != Some(chain_uid));
+                for ext in ["yaml", "js"] {
+                    let stale = profiles_dir.join(format!("{}.{}", chain_uid, ext));
+                    let _ = fs::remove_file(stale).await;
+                }
+            }
+        }
+    }
+
+    write_file(&list_path, &serde_yaml::to_string(&list)?).await?;
+    Ok(profile_path)
+}
+
+fn upsert_item<'a>(items: &'a mut Vec<Value>, uid: &str) -> &'a mut Mapping {
+    let idx = match items
+        .iter()
+        .position(|item| item.get("uid").and_then(Value::as_str) == Some(uid))
+    {
+        Some(idx) => idx,
+        None => {
+            let mut item = Mapping::new();
+            item.insert("uid".into(), uid.into());
+            items.push(Value::Mapping(item));
+            items.len() - 1
+        }
+    };
+    if !items[idx].is_mapping() {
+        items[idx] = Value::Mapping(Mapping::new());
+    }
+    items[idx].as_mapping_mut().expect("item is a mapping")
+}
+
+async fn write_file(path: &Path, contents: &str) -> anyhow::Result<()> {
+    fs::write(path, contents)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn profile_is_upserted_with_chain_items() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("profiles.yaml"),
+            "current: rAAAA\nitems:\n  - { uid: rAAAA, type: remote, name: provider, file: rAAAA.yaml }\n",
+        )
+        .await
+        .unwrap();
+        let config = ClashConfig {
+            rules: vec!["MATCH,DIRECT".to_string()],
+            ..Default::default()
+        };
+        let merge: Mapping =
+            serde_yaml::from_str("prepend-rules: ['DOMAIN,a.example,DIRECT']").unwrap();
+        let mut profile = VergeProfile {
+            name: "home",
+            config: &config,
+            merge: Some(merge),
+            script: Some("function main(c) { return c; }".to_string()),
+        };
+
+        let path = write_profile(dir.path(), &profile).await.unwrap();
+        assert!(path.exists());
+        let list: Value = serde_yaml::from_str(
+            &std::fs::read_to_string(dir.path().join("profiles.yaml")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(list["current"], Value::from("rAAAA"));
+        let items = list["items"].as_sequence().unwrap();
+        assert_eq!(items.len(), 4);
+        let uid = items[1]["uid"].as_str().unwrap();
+        assert_eq!(items[1]["type"], Value::from("local"));
+        let merge_uid = items[1]["option"]["merge"].as_str().unwrap();
+        assert_eq!(merge_uid, format!("m{}", &uid[1..]));
+        assert!(dir
+            .path()
+            .join(format!("profiles/{}.yaml", merge_uid))
+            .exists());
+
+        // Re-exporting without a script updates in place and drops the script item.
+        profile.script = None;
+        write_profile(dir.path(), &profile).await.unwrap();
+        let list: Value = serde_yaml::from_str(
+            &std::fs::read_to_string(dir.path().join("profiles.yaml")).unwrap(),
+        )
+        .unwrap();
+        let items = list["items"].as_sequence().unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(items[1]["option"].get("script").is_none());
+        assert!(!dir
+            .path()
+            .join(format!("profiles/s{}.js", &uid[1..]))
+            .exists());
+    }
+}
@@ -0,0 +1,690 @@
+//! Client for the REST API (`external-controller`) of a running mihomo.
+//!
+//! The controller is reached over plain HTTP, HTTPS (`external-controller-tls`, optionally
+//! with a self-signed certificate whose CA is passed in) or a unix socket
+//! (`external-controller-unix`, addressed as `unix:///path/to/mihomo.sock`).
+
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::merge::PROVIDER_URL_TEST_URL;
+
+/// URL used by `delay` when the caller does not pass one.
+pub const DEFAULT_DELAY_URL: &str = PROVIDER_URL_TEST_URL;
+
+/// Timeout of ordinary (non-streaming) requests.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Scheme of controller endpoints that are unix sockets.
+const UNIX_SCHEME: &str = "unix://";
+
+/// A proxy group as reported by `GET /proxies`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupState {
+    pub name: String,
+    /// `Selector`, `URLTest`, `Fallback`, `LoadBalance`, `Relay`.
+    pub kind: String,
+    /// Member currently in use.
+    pub now: Option<String>,
+    pub members: Vec<String>,
+}
+
+pub struct ControllerClient {
+    base: String,
+    secret: Option<String>,
+    transport: Transport,
+}
+
+enum Transport {
+    Http(reqwest::Client),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl ControllerClient {
+    /// `base` is the controller URL (`http://127.0.0.1:9090`, `https://…` or
+    /// `unix:///path.sock`); an empty secret means none.
+    pub fn new(base: &str, secret: Option<&str>) -> anyhow::Result<Self> {
+        Self::with_ca_cert(base, secret, None)
+    }
+
+    /// Like [`Self::new`], additionally trusting the PEM certificate(s) in `ca_cert` for
+    /// `https://` controllers.
+    pub fn with_ca_cert(
+        base: &str,
+        secret: Option<&str>,
+        ca_cert: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let base = base.trim_end_matches('/');
+        let transport = match base.strip_prefix(UNIX_SCHEME) {
+            #[cfg(unix)]
+            Some(socket) => Transport::Unix(PathBuf::from(socket)),
+            #[cfg(not(unix))]
+            Some(_) => anyhow::bail!("unix socket controllers are not supported on this platform"),
+            None => {
+                // No client-wide timeout: streaming endpoints stay open; other requests set
+                // REQUEST_TIMEOUT themselves.
+                let mut builder = reqwest::Client::builder().no_proxy();
+                if let Some(path) = ca_cert {
+                    let pem = std::fs::read(path)
+                        .with_context(|| format!("failed to read {}", path.display()))?;
+                    for cert in reqwest::Certificate::from_pem_bundle(&pem)
+                        .with_context(|| format!("{} is not a PEM certificate", path.display()))?
+                    {
+                        builder = builder.add_root_certificate(cert);
+                    }
+                }
+                Transport::Http(builder.build()?)
+            }
+        };
+        Ok(Self {
+            base: base.to_string(),
+            secret: secret.filter(|s| !s.is_empty()).map(str::to_string),
+            transport,
+        })
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// `GET /version`: the version string, with ` (meta)` appended for mihomo/Clash.Meta.
+    pub async fn version(&self) -> anyhow::Result<String> {
+        let body = self.get_json("/version").await?;
+        let version = body
+            .get("version")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("controller {} sent no version", self.base))?;
+        let meta = body.get("meta").and_then(Value::as_bool).unwrap_or(false);
+        Ok(if meta {
+            format!("{} (meta)", version)
+        } else {
+            version.to_string()
+        })
+    }
+
+    /// Every proxy group, in the order of the `GLOBAL` group (mihomo lists groups there in
+    /// config order); groups missing from it follow by name.
+    pub async fn groups(&self) -> anyhow::Result<Vec<GroupState>> {
+        let body = self.get_json("/proxies").await?;
+        Ok(groups_from_proxies(&body))
+    }
+
+    /// `PUT /proxies/<group>`: make `proxy` the selected member of a select group.
+    pub async fn select(&self, group: &str, proxy: &str) -> anyhow::Result<()> {
+        let request = ApiRequest::new(Method::PUT, format!("/proxies/{}", encode(group)))
+            .json(serde_json::json!({ "name": proxy }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// `PUT /configs?force=true`: replace the running config with `yaml`.
+    pub async fn put_config(&self, yaml: &str) -> anyhow::Result<()> {
+        let request = ApiRequest::new(Method::PUT, "/configs".to_string())
+            .query(&[("force", "true")])
+            .json(serde_json::json!({ "payload": yaml }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// `GET /proxies/<proxy>/delay`: measured delay in ms.
+    pub async fn delay(&self, proxy: &str, url: &str, timeout: Duration) -> anyhow::Result<u64> {
+        let request = ApiRequest::new(Method::GET, format!("/proxies/{}/delay", encode(proxy)))
+            .query(&[("url", url), ("timeout", &timeout.as_millis().to_string())])
+            .timeout(timeout + Duration::from_secs(5));
+        let body: Value = self.send(request).await?.json().await?;
+        body.get("delay")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("controller {} sent no delay for {}", self.base, proxy))
+    }
+
+    /// `GET /traffic`: calls `on_sample` with every per-second sample until it returns
+    /// `false` or the controller closes the stream.
+    pub async fn traffic(
+        &self,
+        mut on_sample: impl FnMut(TrafficSample, &str) -> bool,
+    ) -> anyhow::Result<()> {
+        let request = ApiRequest::new(Method::GET, "/traffic".to_string()).streaming();
+        self.stream_lines(request, |line| {
+            let sample: TrafficSample = serde_json::from_str(line).with_context(|| {
+                format!("controller {} sent an invalid traffic sample", self.base)
+            })?;
+            Ok(on_sample(sample, line))
+        })
+        .await
+    }
+
+    /// `GET /logs?level=<level>`: calls `on_entry` with every log line at `level` or above
+    /// whose payload matches `filter` (a regex), until it returns `false` or the controller
+    /// closes the stream.
+    pub async fn logs(
+        &self,
+        level: &str,
+        filter: Option<&str>,
+        mut on_entry: impl FnMut(LogEntry, &str) -> bool,
+    ) -> anyhow::Result<()> {
+        let filter = filter
+            .map(Regex::new)
+            .transpose()
+            .context("invalid log filter")?;
+        let request = ApiRequest::new(Method::GET, "/logs".to_string())
+            .query(&[("level", level)])
+            .streaming();
+        self.stream_lines(request, |line| {
+            let entry: LogEntry = serde_json::from_str(line)
+                .with_context(|| format!("controller {} sent an invalid log entry", self.base))?;
+            if filter
+                .as_ref()
+                .is_some_and(|filter| !filter.is_match(&entry.payload))
+            {
+                return Ok(true);
+            }
+            Ok(on_entry(entry, line))
+        })
+        .await
+    }
+
+    /// `GET /connections`, as sent by the controller.
+    pub async fn connections(&self) -> anyhow::Result<Value> {
+        self.get_json("/connections").await
+    }
+
+    /// `DELETE /connections/<id>`.
+    pub async fn close_connection(&self, id: &str) -> anyhow::Result<()> {
+        let request = ApiRequest::new(Method::DELETE, format!("/connections/{}", encode(id)));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// `DELETE /connections`: close every connection.
+    pub async fn close_all_connections(&self) -> anyhow::Result<()> {
+        let request = ApiRequest::new(Method::DELETE, "/connections".to_string());
+        self.send(request).await?;
+        Ok(())
+    }
+
+    pub async fn get_json(&self, path: &str) -> anyhow::Result<Value> {
+        let response = self
+            .send(ApiRequest::new(Method::GET, path.to_string()))
+            .await?;
+        response
+            .json()
+            .await
+            .with_context(|| format!("controller {} sent invalid JSON for {}", self.base, path))
+    }
+
+    /// Send a streaming request and feed each non-empty line of the body to `on_line` until it
+    /// returns `false` or the body ends.
+    async fn stream_lines(
+        &self,
+        request: ApiRequest,
+        mut on_line: impl FnMut(&str) -> anyhow::Result<bool>,
+    ) -> anyhow::Result<()> {
+        let mut response = self.send(request).await?;
+        let mut pending = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            pending.extend_from_slice(&chunk);
+            while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                if !on_line(line)? {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&self, request: ApiRequest) -> anyhow::Result<ApiResponse> {
+        let (status, response) = self
+            .dispatch(request)
+            .await
+            .with_context(|| format!("failed to reach external controller {}", self.base))?;
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.bytes().await.unwrap_or_default();
+        let body = String::from_utf8_lossy(&body).into_owned();
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|json| {
+                json.get("message")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            })
+            .unwrap_or(body);
+        Err(anyhow!(
+            "external controller {} returned {}: {}",
+            self.base,
+            status,
+            message.trim()
+        ))
+    }
+
+    async fn dispatch(&self, request: ApiRequest) -> anyhow::Result<(StatusCode, ApiResponse)> {
+        match &self.transport {
+            Transport::Http(client) => {
+                let mut builder = client.request(
+                    request.method,
+                    format!("{}{}", self.base, request.path_and_query),
+                );
+                if let Some(timeout) = request.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(body) = &request.body {
+                    builder = builder.json(body);
+                }
+                if let Some(secret) = self.secret.as_deref() {
+                    builder = builder.bearer_auth(secret);
+                }
+                let response = builder.send().await?;
+                Ok((response.status(), ApiResponse::Http(response)))
+            }
+            #[cfg(unix)]
+            Transport::Unix(socket) => {
+                let exchange = self.send_unix(socket, &request);
+                let response = match request.timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, exchange)
+                        .await
+                        .map_err(|_| anyhow!("request timed out after {:?}", timeout))??,
+                    None => exchange.await?,
+                };
+                Ok((response.status(), ApiResponse::Unix(response.into_body())))
+            }
+        }
+    }
+
+    /// HTTP/1.1 over a fresh connection to `socket`; mihomo ignores the host name.
+    #[cfg(unix)]
+    async fn send_unix(
+        &self,
+        socket: &Path,
+        request: &ApiRequest,
+    ) -> anyhow::Result<hyper::Response<hyper::Body>> {
+        use hyper::header::{AUTHORIZATION, CONTENT_TYPE, HOST};
+
+        let stream = tokio::net::UnixStream::connect(socket)
+            .await
+            .with_context(|| format!("failed to connect to {}", socket.display()))?;
+        let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::debug!(error = %err, "controller connection closed");
+            }
+        });
+        let mut builder = hyper::Request::builder()
+            .method(request.method.clone())
+            .uri(request.path_and_query.as_str())
+            .header(HOST, "localhost");
+        if let Some(secret) = self.secret.as_deref() {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {}", secret));
+        }
+        let body = match &request.body {
+            Some(json) => {
+                builder = builder.header(CONTENT_TYPE, "application/json");
+                hyper::Body::from(serde_json::to_vec(json)?)
+            }
+            None => hyper::Body::empty(),
+        };
+        Ok(sender.send_request(builder.body(body)?).await?)
+    }
+}
+
+/// A controller request independent of the transport it is sent over.
+struct ApiRequest {
+    method: Method,
+    path_and_query: String,
+    body: Option<Value>,
+    /// `None` for streaming endpoints.
+    timeout: Option<Duration>,
+}
+
+impl ApiRequest {
+    fn new(method: Method, path: String) -> Self {
+        Self {
+            method,
+            path_and_query: path,
+            body: None,
+            timeout: Some(REQUEST_TIMEOUT),
+        }
+    }
+
+    fn query(mut self, pairs: &[(&str, &str)]) -> Self {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(pairs)
+            .finish();
+        self.path_and_query = format!("{}?{}", self.path_and_query, query);
+        self
+    }
+
+    fn json(mut self, body: Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn streaming(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+}
+
+enum ApiResponse {
+    Http(reqwest::Response),
+    #[cfg(unix)]
+    Unix(hyper::Body),
+}
+
+impl ApiResponse {
+    async fn chunk(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            ApiResponse::Http(response) => Ok(response.chunk().await?.map(|bytes| bytes.to_vec())),
+            #[cfg(unix)]
+            ApiResponse::Unix(body) => {
+                use hyper::body::HttpBody;
+                Ok(body.data().await.transpose()?.map(|bytes| bytes.to_vec()))
+            }
+        }
+    }
+
+    async fn bytes(mut self) -> anyhow::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    async fn json<T: DeserializeOwned>(self) -> anyhow::Result<T> {
+        Ok(serde_json::from_slice(&self.bytes().await?)?)
+    }
+}
+
+/// One `/traffic` sample: bytes per second in each direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct TrafficSample {
+    pub up: u64,
+    pub down: u64,
+}
+
+/// One `/logs` line.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LogEntry {
+    /// `debug`, `info`, `warning`, `error`.
+    #[serde(rename = "type")]
+    pub level: String,
+    pub payload: String,
+}
+
+/// An active connection from `GET /connections`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub id: String,
+    pub network: String,
+    /// Sniffed/requested host, falling back to the destination IP.
+    pub host: String,
+    pub port: String,
+    pub process: Option<String>,
+    /// Proxy chain as reported by mihomo (outermost group last).
+    pub chains: Vec<String>,
+    pub rule: String,
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl ConnectionInfo {
+    /// Case-insensitive substring match on id, host, process, chains and rule.
+    pub fn matches(&self, needle: &str) -> bool {
+        let needle = needle.to_lowercase();
+        [
+            self.id.as_str(),
+            self.host.as_str(),
+            self.process.as_deref().unwrap_or_default(),
+            self.rule.as_str(),
+        ]
+        .into_iter()
+        .chain(self.chains.iter().map(String::as_str))
+        .any(|field| field.to_lowercase().contains(&needle))
+    }
+}
+
+/// Connections out of a `GET /connections` body.
+pub fn connections_from_json(body: &Value) -> Vec<ConnectionInfo> {
+    let Some(connections) = body.get("connections").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    let text = |value: Option<&Value>| {
+        value
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+    connections
+        .iter()
+        .filter_map(|connection| {
+            let metadata = connection.get("metadata");
+            let field = |key: &str| text(metadata.and_then(|m| m.get(key)));
+            let rule = match (
+                text(connection.get("rule")),
+                text(connection.get("rulePayload")),
+            ) {
+                (Some(rule), Some(payload)) => format!("{},{}", rule, payload),
+                (Some(rule), None) => rule,
+                _ => String::new(),
+            };
+            Some(ConnectionInfo {
+                id: text(connection.get("id"))?,
+                network: field("network").unwrap_or_default(),
+                host: field("host")
+                    .or_else(|| field("destinationIP"))
+                    .unwrap_or_default(),
+                port: field("destinationPort").unwrap_or_default(),
+                process: field("process"),
+                chains: connection
+                    .get("chains")
+                    .and_then(Value::as_array)
+                    .map(|chains| {
+                        chains
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                rule,
+                upload: connection
+                    .get("upload")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(0),
+                download: connection
+                    .get("download")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+/// Characters escaped in a path segment: everything except RFC 3986 unreserved ones, so that
+/// connection ids (UUIDs) reach the router verbatim.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+fn encode(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT).to_string()
+}
+
+/// Groups out of a `GET /proxies` body.
+pub fn groups_from_proxies(body: &Value) -> Vec<GroupState> {
+    let Some(proxies) = body.get("proxies").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    let mut groups: Vec<GroupState> = proxies
+        .iter()
+        .filter(|(name, _)| name.as_str() != "GLOBAL")
+        .filter_map(|(name, proxy)| {
+            let members = proxy.get("all")?.as_array()?;
+            Some(GroupState {
+                name: name.clone(),
+                kind: proxy
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                now: proxy.get("now").and_then(Value::as_str).map(str::to_string),
+                members: members
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })
+        .collect();
+    let order: Vec<&str> = proxies
+        .get("GLOBAL")
+        .and_then(|global| global.get("all"))
+        .and_then(Value::as_array)
+        .map(|all| all.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    groups.sort_by_key(|group| {
+        let position = order.iter().position(|name| *name == group.name);
+        (position.unwrap_or(usize::MAX), group.name.clone())
+    });
+    groups
+}
+
+/// Current selection of every select group, in group order; what `ctl dump-selections` saves.
+pub fn selections(groups: &[GroupState]) -> Vec<(String, String)> {
+    groups
+        .iter()
+        .filter(|group| group.kind == "Selector")
+        .filter_map(|group| Some((group.name.clone(), group.now.clone()?)))
+        .collect()
+}
+
+/// Why `proxy` cannot be selected in `group` of the running config, if it cannot: the group
+/// is gone or no longer lists `proxy`.
+pub fn selection_problem(groups: &[GroupState], group: &str, proxy: &str) -> Option<String> {
+    let Some(state) = groups.iter().find(|state| state.name == group) else {
+        return Some(format!("no group '{}'", group));
+    };
+    if !state.members.iter().any(|member| member == proxy) {
+        return Some(format!("'{}' is not a member of '{}'", proxy, group));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_follow_global_order() {
+        let body = serde_json::json!({
+            "proxies": {
+                "Auto": { "type": "URLTest", "now": "HK 01", "all": ["HK 01", "JP 01"] },
+                "GLOBAL": { "type": "Selector", "now": "DIRECT", "all": ["Proxy", "Auto", "DIRECT"] },
+                "HK 01": { "type": "Shadowsocks" },
+                "Proxy": { "type": "Selector", "now": "Auto", "all": ["Auto", "HK 01"] },
+                "Streaming": { "type": "Selector", "all": ["Proxy"] }
+            }
+        });
+        let groups = groups_from_proxies(&body);
+        let names: Vec<&str> = groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["Proxy", "Auto", "Streaming"]);
+        assert_eq!(groups[0].now.as_deref(), Some("Auto"));
+        assert_eq!(groups[1].kind, "URLTest");
+        assert_eq!(
+            selections(&groups),
+            vec![("Proxy".to_string(), "Auto".to_string())]
+        );
+        assert_eq!(selection_problem(&groups, "Proxy", "HK 01"), None);
+        assert!(selection_problem(&groups, "Proxy", "JP 01").is_some());
+        assert!(selection_problem(&groups, "Media", "HK 01").is_some());
+        assert_eq!(encode("🇭🇰 HK/01"), "%F0%9F%87%AD%F0%9F%87%B0%20HK%2F01");
+        assert_eq!(encode("5f0c2d9e-aaaa"), "5f0c2d9e-aaaa");
+    }
+
+    #[test]
+    fn connections_are_flattened_and_filterable() {
+        let body = serde_json::json!({
+            "downloadTotal": 10,
+            "uploadTotal": 5,
+            "connections": [
+                {
+                    "id": "5f0c2d9e-aaaa",
+                    "metadata": { "network": "tcp", "host": "www.youtube.com", "destinationIP": "142.250.1.1", "destinationPort": "443", "process": "firefox" },
+                    "upload": 1200, "download": 48000,
+                    "chains": ["HK 01", "Proxy"], "rule": "DomainSuffix", "rulePayload": "youtube.com"
+                },
+                {
+                    "id": "77aa",
+                    "metadata": { "network": "udp", "host": "", "destinationIP": "1.1.1.1", "destinationPort": "53" },
+                    "chains": ["DIRECT"], "rule": "Match", "rulePayload": ""
+                }
+            ]
+        });
+        let connections = connections_from_json(&body);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].rule, "DomainSuffix,youtube.com");
+        assert_eq!(connections[1].host, "1.1.1.1");
+        assert_eq!(connections[1].rule, "Match");
+        assert!(connections[0].matches("YOUTUBE"));
+        assert!(connections[0].matches("hk 01"));
+        assert!(!connections[1].matches("firefox"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_controller_receives_force_reload() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("mihomo.sock");
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !String::from_utf8_lossy(&request).contains("\"payload\"") {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed before the body");
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let base = format!("unix://{}", socket.display());
+        let client = ControllerClient::new(&base, Some("s3cret")).unwrap();
+        client.put_config("mode: rule\n").await.unwrap();
+        let request = server.await.unwrap();
+        assert!(request.starts_with("PUT /configs?force=true HTTP/1.1\r\n"));
+        assert!(request.contains("authorization: Bearer s3cret\r\n"));
+        assert!(request.contains(r#"{"payload":"mode: rule\n"}"#));
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::merge::proxy_endpoint;
+use crate::model::ClashConfig;
+
+/// Top-level keys reported individually under "ports".
+const PORT_KEYS: &[&str] = &[
+    "port",
+    "socks-port",
+    "redir-port",
+    "mixed-port",
+    "tproxy-port",
+];
+
+/// Structured difference between two configs, as reported by `merge --diff`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigDiff {
+    pub proxies_added: Vec<String>,
+    pub proxies_removed: Vec<String>,
+    /// Same name, different settings.
+    pub proxies_changed: Vec<String>,
+    pub groups_added: Vec<String>,
+    pub groups_removed: Vec<String>,
+    pub groups_changed: Vec<String>,
+    pub rules_added: Vec<String>,
+    pub rules_removed: Vec<String>,
+    /// Same rules in a different order (rule order decides matching).
+    pub rules_reordered: bool,
+    /// `(key, old, new)` for port settings; `None` means unset.
+    pub ports_changed: Vec<(String, Option<String>, Option<String>)>,
+    /// Other top-level keys whose value differs (added, removed or changed).
+    pub settings_changed: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigDiff::default()
+    }
+
+    pub fn render(&self) -> String {
+        if self.is_empty() {
+            return "no changes\n".to_string();
+        }
+        let mut out = String::new();
+        section(&mut out, "proxies", '+', &self.proxies_added);
+        section(&mut out, "proxies", '-', &self.proxies_removed);
+        section(&mut out, "proxies", '~', &self.proxies_changed);
+        section(&mut out, "proxy-groups", '+', &self.groups_added);
+        section(&mut out, "proxy-groups", '-', &self.groups_removed);
+        section(&mut out, "proxy-groups", '~', &self.groups_changed);
+        section(&mut out, "rules", '+', &self.rules_added);
+        section(&mut out, "rules", '-', &self.rules_removed);
+        if self.rules_reordered {
+            out.push_str("rules: order changed\n");
+        }
+        if !self.ports_changed.is_empty() {
+            out.push_str("ports:\n");
+            for (key, old, new) in &self.ports_changed {
+                out.push_str(&format!(
+                    "  ~ {}: {} -> {}\n",
+                    key,
+                    old.as_deref().unwrap_or("<unset>"),
+                    new.as_deref().unwrap_or("<unset>")
+                ));
+            }
+        }
+        section(&mut out, "settings", '~', &self.settings_changed);
+        out
+    }
+}
+
+fn section(out: &mut String, title: &str, marker: char, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    let verb = match marker {
+        '+' => "added",
+        '-' => "removed",
+        _ => "changed",
+    };
+    out.push_str(&format!("{} {} ({}):\n", title, verb, items.len()));
+    for item in items {
+        out.push_str(&format!("  {} {}\n", marker, item));
+    }
+}
+
+/// Compare the previously deployed config with a newly merged one.
+pub fn diff_configs(old: &ClashConfig, new: &ClashConfig) -> ConfigDiff {
+    let (proxies_added, proxies_removed, proxies_changed) = diff_named(&old.proxies, &new.proxies);
+    let (groups_added, groups_removed, groups_changed) =
+        diff_named(&old.proxy_groups, &new.proxy_groups);
+
+    let old_rules: HashSet<&String> = old.rules.iter().collect();
+    let new_rules: HashSet<&String> = new.rules.iter().collect();
+    let rules_added: Vec<String> = new
+        .rules
+        .iter()
+        .filter(|rule| !old_rules.contains(rule))
+        .cloned()
+        .collect();
+    let rules_removed: Vec<String> = old
+        .rules
+        .iter()
+        .filter(|rule| !new_rules.contains(rule))
+        .cloned()
+        .collect();
+    let rules_reordered =
+        rules_added.is_empty() && rules_removed.is_empty() && old.rules != new.rules;
+
+    let old_top = top_level(old);
+    let new_top = top_level(new);
+    let mut ports_changed = Vec::new();
+    for key in PORT_KEYS {
+        let before = old_top.get(*key).map(render_scalar);
+        let after = new_top.get(*key).map(render_scalar);
+        if before != after {
+            ports_changed.push((key.to_string(), before, after));
+        }
+    }
+    let settings_changed: Vec<String> = new_top
+        .keys()
+        .chain(old_top.keys().filter(|key| !new_top.contains_key(*key)))
+        .filter(|key| !PORT_KEYS.contains(&key.as_str()))
+        .filter(|key| old_top.get(*key) != new_top.get(*key))
+        .cloned()
+        .collect();
+
+    ConfigDiff {
+        proxies_added,
+        proxies_removed,
+        proxies_changed,
+        groups_added,
+        groups_removed,
+        groups_changed,
+        rules_added,
+        rules_removed,
+        rules_reordered,
+        ports_changed,
+        settings_changed,
+    }
+}
+
+/// Top-level scalar/mapping keys other than proxies, groups and rules, ports included.
+fn top_level(config: &ClashConfig) -> indexmap::IndexMap<String, Value> {
+    let mut map = config.extra.clone();
+    if let Some(port) = config.port {
+        map.insert("port".to_string(), Value::from(port));
+    }
+    if let Some(port) = config.socks_port {
+        map.insert("socks-port".to_string(), Value::from(port));
+    }
+    if let Some(port) = config.redir_port {
+        map.insert("redir-port".to_string(), Value::from(port));
+    }
+    map
+}
+
+fn render_scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim_end().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+fn entry_name(value: &Value) -> Option<&str> {
+    value
+        .as_mapping()
+        .and_then(|map| map.get(Value::from("name")))
+        .and_then(Value::as_str)
+}
+
+/// A subscription's nodes as of one merge, saved next to its cache to compare with the next.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeSet {
+    nodes: Vec<NodeEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct NodeEntry {
+    name: String,
+    /// `type://server:port`, used to recognise a renamed node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    endpoint: Option<String>,
+}
+
+/// Nodes a subscription added, removed and renamed since the previous merge.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeChanges {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+    /// `(old, new)` names of nodes whose type, server and port stayed the same.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub renamed: Vec<(String, String)>,
+}
+
+impl NodeChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+    }
+}
+
+impl NodeSet {
+    pub fn of(config: &ClashConfig) -> Self {
+        let nodes = config
+            .proxies
+            .iter()
+            .filter_map(|proxy| {
+                Some(NodeEntry {
+                    name: entry_name(proxy)?.to_string(),
+                    endpoint: proxy_endpoint(proxy)
+                        .map(|(kind, server, port)| format!("{}://{}:{}", kind, server, port)),
+                })
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    /// The saved set, or `None` when `path` is missing or unreadable (the first merge).
+    pub async fn load(path: &Path) -> Option<Self> {
+        let bytes = tokio::fs::read(path).await.ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        crate::storage::write_atomic(path, &serde_json::to_vec_pretty(self)?).await
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// What changed from `previous` to `self`. A removed and an added node sharing an endpoint
+    /// that no other node of either set uses count as a rename.
+    pub fn changes_since(&self, previous: &NodeSet) -> NodeChanges {
+        let old_names: HashSet<&str> = previous.nodes.iter().map(|n| n.name.as_str()).collect();
+        let new_names: HashSet<&str> = self.nodes.iter().map(|n| n.name.as_str()).collect();
+        let removed: Vec<&NodeEntry> = previous
+            .nodes
+            .iter()
+            .filter(|node| !new_names.contains(node.name.as_str()))
+            .collect();
+        let added: Vec<&NodeEntry> = self
+            .nodes
+            .iter()
+            .filter(|node| !old_names.contains(node.name.as_str()))
+            .collect();
+
+        let unique_endpoint = |nodes: &[NodeEntry], endpoint: &str| {
+            nodes
+                .iter()
+                .filter(|node| node.endpoint.as_deref() == Some(endpoint))
+                .count()
+                == 1
+        };
+        let mut changes = NodeChanges::default();
+        let mut renamed_to: HashSet<&str> = HashSet::new();
+        for old in &removed {
+            let new = old.endpoint.as_deref().and_then(|endpoint| {
+                (unique_endpoint(&previous.nodes, endpoint)
+                    && unique_endpoint(&self.nodes, endpoint))
+                .then(|| {
+                    added
+                        .iter()
+                        .find(|node| node.endpoint.as_deref() == Some(endpoint))
+                })
+                .flatten()
+            });
+            match new {
+                Some(new) => {
+                    renamed_to.insert(new.name.as_str());
+                    changes.renamed.push((old.name.clone(), new.name.clone()));
+                }
+                None => changes.removed.push(old.name.clone()),
+            }
+        }
+        changes.added = added
+            .iter()
+            .filter(|node| !renamed_to.contains(node.name.as_str()))
+            .map(|node| node.name.clone())
+            .collect();
+        changes
+    }
+}
+
+/// Added, removed and changed entries of a list of named mappings, in list order.
+fn diff_named(old: &[Value], new: &[Value]) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let old_by_name: HashMap<&str, &Value> = old
+        .iter()
+        .filter_map(|value| entry_name(value).map(|name| (name, value)))
+        .collect();
+    let new_by_name: HashMap<&str, &Value> = new
+        .iter()
+        .filter_map(|value| entry_name(value).map(|name| (name, value)))
+        .collect();
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for value in new {
+        let Some(name) = entry_name(value) else {
+            continue;
+        };
+        match old_by_name.get(name) {
+            None => added.push(name.to_string()),
+            Some(previous) if *previous != value => changed.push(name.to_string()),
+            Some(_) => {}
+        }
+    }
+    let removed = old
+        .iter()
+        .filter_map(entry_name)
+        .filter(|name| !new_by_name.contains_key(name))
+        .map(str::to_string)
+        .collect();
+    (added, removed, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> ClashConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn node_set_changes_recognise_renames_by_endpoint() {
+        let old = NodeSet::of(&config(
+            r#"
+proxies:
+  - { name: "HK 01", type: ss, server: hk.example.com, port: 443 }
+  - { name: "JP 01", type: ss, server: jp.example.com, port: 443 }
+  - { name: "US 01", type: ss, server: us.example.com, port: 443 }
+"#,
+        ));
+        let new = NodeSet::of(&config(
+            r#"
+proxies:
+  - { name: "香港 01", type: ss, server: HK.example.com, port: 443 }
+  - { name: "US 01", type: ss, server: us.example.com, port: 443 }
+  - { name: "SG 01", type: ss, server: sg.example.com, port: 443 }
+"#,
+        ));
+        let changes = new.changes_since(&old);
+        assert_eq!(changes.added, vec!["SG 01"]);
+        assert_eq!(changes.removed, vec!["JP 01"]);
+        assert_eq!(
+            changes.renamed,
+            vec![("HK 01".to_string(), "香港 01".to_string())]
+        );
+        assert!(new.changes_since(&new).is_empty());
+    }
+
+    #[test]
+    fn reports_nodes_rules_and_ports() {
+        let old = config(
+            r#"
+port: 7890
+mode: rule
+proxies:
+  - { name: a, type: http, server: s, port: 1 }
+  - { name: b, type: http, server: s, port: 2 }
+proxy-groups:
+  - { name: Proxy, type: select, proxies: [a, b] }
+rules:
+  - DOMAIN,x.com,Proxy
+  - MATCH,DIRECT
+"#,
+        );
+        let new = config(
+            r#"
+mixed-port: 7897
+mode: rule
+proxies:
+  - { name: a, type: http, server: s, port: 10 }
+  - { name: c, type: http, server: s, port: 3 }
+proxy-groups:
+  - { name: Proxy, type: select, proxies: [a, c] }
+rules:
+  - DOMAIN,y.com,Proxy
+  - MATCH,DIRECT
+"#,
+        );
+
+        let diff = diff_configs(&old, &new);
+        assert_eq!(diff.proxies_added, vec!["c"]);
+        assert_eq!(diff.proxies_removed, vec!["b"]);
+        assert_eq!(diff.proxies_changed, vec!["a"]);
+        assert_eq!(diff.groups_changed, vec!["Proxy"]);
+        assert_eq!(diff.rules_added, vec!["DOMAIN,y.com,Proxy"]);
+        assert_eq!(diff.rules_removed, vec!["DOMAIN,x.com,Proxy"]);
+        assert_eq!(
+            diff.ports_changed,
+            vec![
+                ("port".to_string(), Some("7890".to_string()), None),
+                ("mixed-port".to_string(), None, Some("7897".to_string())),
+            ]
+        );
+        assert!(diff.settings_changed.is_empty());
+        assert!(diff.render().contains("  ~ port: 7890 -> <unset>\n"));
+    }
+
+    #[test]
+    fn identical_configs_have_no_diff() {
+        let cfg = config("port: 7890\nrules:\n  - A\n  - B\n");
+        let diff = diff_configs(&cfg, &cfg);
+        assert!(diff.is_empty());
+        assert_eq!(diff.render(), "no changes\n");
+
+        let reordered = config("port: 7890\nrules:\n  - B\n  - A\n");
+        assert!(diff_configs(&cfg, &reordered).rules_reordered);
+    }
+}
@@ -0,0 +1,238 @@
+//! Drop proxies by name with include/exclude regexes.
+//!
+//! Providers pad their node lists with informational entries ("剩余流量：10GB", "官网",
+//! "过期时间") that are not real servers. Filtering removes them from `proxies` and strips
+//! their names from every proxy-group member list.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::model::ClashConfig;
+
+/// Include/exclude patterns as written in subscriptions.yaml (`filter:`) or on the command line.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyFilterSpec {
+    /// Keep only proxies whose name matches one of these; empty keeps everything.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Drop proxies whose name matches any of these (checked after `include`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl ProxyFilterSpec {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn compile(&self) -> anyhow::Result<ProxyFilter> {
+        Ok(ProxyFilter {
+            include: compile_all(&self.include)?,
+            exclude: compile_all(&self.exclude)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProxyFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl ProxyFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn keeps(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(name)))
+            && !self.exclude.iter().any(|re| re.is_match(name))
+    }
+
+    /// Remove filtered proxies from `config` and from its group member lists; returns the
+    /// removed names.
+    pub fn apply(&self, config: &mut ClashConfig) -> Vec<String> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let mut removed = Vec::new();
+        config.proxies.retain(|proxy| {
+            let Some(name) = proxy.get("name").and_then(Value::as_str) else {
+                return true;
+            };
+            let keep = self.keeps(name);
+            if !keep {
+                removed.push(name.to_string());
+            }
+            keep
+        });
+        if !removed.is_empty() {
+            let gone: HashSet<&str> = removed.iter().map(String::as_str).collect();
+            strip_group_members(config, &gone);
+        }
+        removed
+    }
+}
+
+/// Remove `gone` from every proxy-group member list.
+pub(crate) fn strip_group_members(config: &mut ClashConfig, gone: &HashSet<&str>) {
+    for group in config.proxy_groups.iter_mut() {
+        if let Some(members) = group.get_mut("proxies").and_then(Value::as_sequence_mut) {
+            members.retain(|member| !member.as_str().is_some_and(|name| gone.contains(name)));
+        }
+    }
+}
+
+/// A proxy found by [`find_proxies`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyMatch {
+    pub name: String,
+    pub kind: String,
+    pub server: Option<String>,
+    pub port: Option<String>,
+    /// Groups listing the proxy directly, in config order.
+    pub groups: Vec<String>,
+    pub entry: Value,
+}
+
+/// Proxies whose name or server matches `pattern` (case-insensitive regex) and, when `types`
+/// is non-empty, whose `type` is one of them.
+pub fn find_proxies(
+    config: &ClashConfig,
+    pattern: &str,
+    types: &[String],
+) -> anyhow::Result<Vec<ProxyMatch>> {
+    let re = regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .with_context(|| format!("invalid search regex: {}", pattern))?;
+    let scalar = |value: Option<&Value>| match value? {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    };
+    let mut matches = Vec::new();
+    for proxy in &config.proxies {
+        let Some(name) = proxy.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+        let kind = proxy
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if !types.is_empty() && !types.iter().any(|t| t.eq_ignore_ascii_case(kind)) {
+            continue;
+        }
+        let server = scalar(proxy.get("server"));
+        if !re.is_match(name) && !server.as_deref().is_some_and(|s| re.is_match(s)) {
+            continue;
+        }
+        let groups = config
+            .proxy_groups
+            .iter()
+            .filter(|group| {
+                group
+                    .get("proxies")
+                    .and_then(Value::as_sequence)
+                    .is_some_and(|members| members.iter().any(|m| m.as_str() == Some(name)))
+            })
+            .filter_map(|group| group.get("name").and_then(Value::as_str))
+            .map(str::to_string)
+            .collect();
+        matches.push(ProxyMatch {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            server,
+            port: scalar(proxy.get("port")),
+            groups,
+            entry: proxy.clone(),
+        });
+    }
+    Ok(matches)
+}
+
+fn compile_all(patterns: &[String]) -> anyhow::Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| format!("invalid proxy filter regex: {}", pattern))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_drops_nodes_and_group_members() {
+        let mut config: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - { name: 剩余流量：10GB, type: ss, server: a.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: 官网 example.com, type: ss, server: a.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: HK 01, type: ss, server: hk.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: JP 01, type: ss, server: jp.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: US 01, type: ss, server: us.example, port: 1, cipher: aes-128-gcm, password: x }
+proxy-groups:
+  - { name: PROXY, type: select, proxies: [Auto, 剩余流量：10GB, HK 01, JP 01, US 01] }
+  - { name: Auto, type: url-test, proxies: [官网 example.com, HK 01, JP 01] }
+"#,
+        )
+        .unwrap();
+        let spec = ProxyFilterSpec {
+            include: vec!["HK|JP|剩余".to_string()],
+            exclude: vec!["剩余流量|官网|过期".to_string()],
+        };
+        let removed = spec.compile().unwrap().apply(&mut config);
+        assert_eq!(removed, vec!["剩余流量：10GB", "官网 example.com", "US 01"]);
+        assert_eq!(config.proxy_names(), vec!["HK 01", "JP 01"]);
+        let members = |idx: usize| {
+            config.proxy_groups[idx]["proxies"]
+                .as_sequence()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(members(0), vec!["Auto", "HK 01", "JP 01"]);
+        assert_eq!(members(1), vec!["HK 01", "JP 01"]);
+
+        let bad = ProxyFilterSpec {
+            include: Vec::new(),
+            exclude: vec!["(".to_string()],
+        };
+        assert!(bad.compile().is_err());
+    }
+
+    #[test]
+    fn find_matches_name_or_server_and_lists_groups() {
+        let config: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - { name: 🇭🇰 香港 01, type: trojan, server: a.example, port: 443, password: x }
+  - { name: HK 02, type: ss, server: b.example, port: "8388", cipher: aes-128-gcm, password: x }
+  - { name: Relay, type: trojan, server: hk-relay.example, port: 443, password: x }
+  - { name: JP 01, type: trojan, server: jp.example, port: 443, password: x }
+proxy-groups:
+  - { name: PROXY, type: select, proxies: [Auto, 🇭🇰 香港 01, HK 02] }
+  - { name: Auto, type: url-test, proxies: [🇭🇰 香港 01, JP 01] }
+"#,
+        )
+        .unwrap();
+        let found = find_proxies(&config, "hk|香港", &[]).unwrap();
+        let names: Vec<&str> = found.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["🇭🇰 香港 01", "HK 02", "Relay"]);
+        assert_eq!(found[0].groups, ["PROXY", "Auto"]);
+        assert_eq!(found[1].port.as_deref(), Some("8388"));
+        assert!(found[2].groups.is_empty());
+
+        let trojans = find_proxies(&config, "hk|香港", &["Trojan".to_string()]).unwrap();
+        assert_eq!(trojans.len(), 2);
+        assert!(find_proxies(&config, "(", &[]).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Proxy-group member lists as mihomo resolves them, for `show groups --expanded`.
+//!
+//! A group's members are its `proxies` list followed by the nodes `include-all` or
+//! `include-all-proxies` pull in. Those pulled-in nodes go through `filter`,
+//! `exclude-filter` and `exclude-type` (several regexes are separated by a backtick, several
+//! types by `|`). Members from `use:` proxy-providers are only known at runtime, so the
+//! providers are listed by name instead.
+
+use regex::Regex;
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::model::ClashConfig;
+
+/// Policies mihomo provides without a proxy entry.
+const BUILTIN_POLICIES: &[&str] = &["DIRECT", "REJECT", "REJECT-DROP", "PASS", "COMPATIBLE"];
+
+/// One proxy-group with its resolved members.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExpandedGroup {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub members: Vec<GroupMember>,
+    /// `use:` providers (all of them with `include-all`/`include-all-providers`).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<String>,
+    /// Patterns that could not be evaluated, e.g. lookarounds the regex engine lacks.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupMember {
+    pub name: String,
+    /// Added by `include-all`/`include-all-proxies` rather than listed in `proxies`.
+    pub included: bool,
+    /// The member is another proxy-group.
+    pub is_group: bool,
+    /// The member's name matches the group's `filter`.
+    pub matches_filter: bool,
+    /// Neither a proxy, a group nor a built-in policy; mihomo rejects the config.
+    pub unknown: bool,
+}
+
+/// Every proxy-group of `config` with its members in the order mihomo shows them.
+pub fn expand_groups(config: &ClashConfig) -> Vec<ExpandedGroup> {
+    let proxy_names = config.proxy_names();
+    let group_names = config.proxy_group_names();
+    let all_providers: Vec<String> = config
+        .extra
+        .get("proxy-providers")
+        .and_then(Value::as_mapping)
+        .map(|providers| {
+            providers
+                .keys()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    config
+        .proxy_groups
+        .iter()
+        .filter_map(|group| {
+            let name = group.get("name").and_then(Value::as_str)?;
+            let text = |key: &str| group.get(key).and_then(Value::as_str).unwrap_or_default();
+            let flag = |key: &str| group.get(key).and_then(Value::as_bool) == Some(true);
+            let mut problems = Vec::new();
+            let filter = compile_patterns(text("filter"), &mut problems);
+            let exclude = compile_patterns(text("exclude-filter"), &mut problems);
+            let excluded_types: Vec<String> = text("exclude-type")
+                .split('|')
+                .map(str::trim)
+                .filter(|kind| !kind.is_empty())
+                .map(str::to_ascii_lowercase)
+                .collect();
+
+            let member = |member: &str, included: bool| GroupMember {
+                name: member.to_string(),
+                included,
+                is_group: group_names.iter().any(|group| group == member),
+                matches_filter: filter.iter().any(|re| re.is_match(member)),
+                unknown: !group_names.iter().any(|group| group == member)
+                    && !proxy_names.iter().any(|proxy| proxy == member)
+                    && !BUILTIN_POLICIES.contains(&member),
+            };
+            let mut members: Vec<GroupMember> = group
+                .get("proxies")
+                .and_then(Value::as_sequence)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(|name| member(name, false))
+                .collect();
+
+            if flag("include-all") || flag("include-all-proxies") {
+                let candidates: Vec<&Value> = config
+                    .proxies
+                    .iter()
+                    .filter(|proxy| {
+                        let kind = proxy
+                            .get("type")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default();
+                        !excluded_types.contains(&kind.to_ascii_lowercase())
+                    })
+                    .collect();
+                let passes = |proxy: &&Value, re: Option<&Regex>| {
+                    let name = proxy
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    re.is_none_or(|re| re.is_match(name))
+                        && !exclude.iter().any(|re| re.is_match(name))
+                };
+                // mihomo collects the matches of each filter pattern in turn.
+                let passes_each: Vec<Option<&Regex>> = if filter.is_empty() {
+                    vec![None]
+                } else {
+                    filter.iter().map(Some).collect()
+                };
+                for re in passes_each {
+                    for proxy in candidates.iter().filter(|proxy| passes(proxy, re)) {
+                        let name = proxy
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .unwrap_or_default();
+                        if !members.iter().any(|existing| existing.name == name) {
+                            members.push(member(name, true));
+                        }
+                    }
+                }
+            }
+
+            let providers = if flag("include-all") || flag("include-all-providers") {
+                all_providers.clone()
+            } else {
+                group
+                    .get("use")
+                    .and_then(Value::as_sequence)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            };
+
+            Some(ExpandedGroup {
+                name: name.to_string(),
+                kind: text("type").to_string(),
+                members,
+                providers,
+                problems,
+            })
+        })
+        .collect()
+}
+
+/// Compile backtick-separated patterns; the ones the regex engine rejects are reported in
+/// `problems` and skipped.
+fn compile_patterns(raw: &str, problems: &mut Vec<String>) -> Vec<Regex> {
+    raw.split('`')
+        .filter(|pattern| !pattern.is_empty())
+        .filter_map(|pattern| match Regex::new(pattern) {
+            Ok(re) => Some(re),
+            Err(_) => {
+                problems.push(format!("cannot evaluate pattern '{}'", pattern));
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_all_groups_apply_filters_in_pattern_order() {
+        let config = ClashConfig::from_yaml_str(
+            r#"
+proxies:
+  - {name: "US 01", type: trojan, server: a, port: 1}
+  - {name: "HK 01", type: ss, server: b, port: 1}
+  - {name: "HK 02", type: vmess, server: c, port: 1}
+  - {name: "HK info", type: ss, server: d, port: 1}
+proxy-providers:
+  extra: {type: http, url: "https://example.com/p.yaml"}
+proxy-groups:
+  - name: Proxy
+    type: select
+    proxies: [Asia, "US 01", Gone, DIRECT]
+  - name: Asia
+    type: url-test
+    include-all: true
+    filter: "HK`US"
+    exclude-filter: info
+    exclude-type: vmess
+  - name: Odd
+    type: select
+    use: [extra]
+    filter: "(?!HK)"
+"#,
+        )
+        .unwrap();
+        let groups = expand_groups(&config);
+
+        let names = |group: &ExpandedGroup| {
+            group
+                .members
+                .iter()
+                .map(|m| m.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&groups[0]), ["Asia", "US 01", "Gone", "DIRECT"]);
+        assert!(groups[0].members[0].is_group);
+        assert!(groups[0].members[2].unknown && !groups[0].members[3].unknown);
+
+        assert_eq!(names(&groups[1]), ["HK 01", "US 01"]);
+        assert!(groups[1]
+            .members
+            .iter()
+            .all(|m| m.included && m.matches_filter));
+        assert_eq!(groups[1].providers, ["extra"]);
+
+        assert!(groups[2].members.is_empty());
+        assert_eq!(groups[2].providers, ["extra"]);
+        assert_eq!(groups[2].problems.len(), 1);
+    }
+}
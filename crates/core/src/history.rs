@@ -0,0 +1,186 @@
+//! History of generated configs under `output/history/` for `history list` and
+//! `history rollback`.
+//!
+//! Each config is stored as `<id>.yaml`, where the id is the start of the SHA-256 of the
+//! config without its generation header, next to `<id>.meta.yaml` describing when and from
+//! what it was generated. Writing the same config again only refreshes its metadata.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::output::strip_header;
+use crate::storage::write_atomic;
+
+/// Entries `merge` keeps unless `--keep-history` says otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+/// Hex digits of the content hash used as the id.
+const ID_LEN: usize = 12;
+
+/// Metadata of one stored config (`<id>.meta.yaml`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub generated_at: DateTime<Utc>,
+    /// Where the config was written; `history rollback` restores it there by default.
+    pub output: PathBuf,
+    #[serde(default)]
+    pub subscriptions: Vec<String>,
+    #[serde(default)]
+    pub proxies: usize,
+}
+
+impl HistoryEntry {
+    /// Entry for `yaml` generated now.
+    pub fn new(yaml: &str, output: &Path, subscriptions: Vec<String>, proxies: usize) -> Self {
+        Self {
+            id: config_id(yaml),
+            generated_at: Utc::now(),
+            output: output.to_path_buf(),
+            subscriptions,
+            proxies,
+        }
+    }
+}
+
+/// Content id of a config; the generation header is ignored so a re-merge with a new
+/// timestamp maps to the same entry.
+pub fn config_id(yaml: &str) -> String {
+    let digest = Sha256::digest(strip_header(yaml).as_bytes());
+    hex::encode(digest)[..ID_LEN].to_string()
+}
+
+/// Store `yaml` under `entry.id` in `dir` and drop the oldest entries beyond `keep`.
+pub async fn record(
+    dir: &Path,
+    entry: &HistoryEntry,
+    yaml: &str,
+    keep: usize,
+) -> anyhow::Result<()> {
+    let config = config_path(dir, &entry.id);
+    if !fs::try_exists(&config).await.unwrap_or(false) {
+        write_atomic(&config, yaml.as_bytes()).await?;
+    }
+    write_atomic(
+        &meta_path(dir, &entry.id),
+        serde_yaml::to_string(entry)?.as_bytes(),
+    )
+    .await?;
+    for old in list(dir).await?.iter().skip(keep) {
+        remove(dir, &old.id).await?;
+    }
+    Ok(())
+}
+
+/// Stored entries, newest first. Entries whose metadata cannot be read are skipped.
+pub async fn list(dir: &Path) -> anyhow::Result<Vec<HistoryEntry>> {
+    let mut entries = Vec::new();
+    let mut read_dir = match fs::read_dir(dir).await {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    while let Some(item) = read_dir.next_entry().await? {
+        let name = item.file_name();
+        let Some(id) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".meta.yaml"))
+        else {
+            continue;
+        };
+        if !fs::try_exists(config_path(dir, id)).await.unwrap_or(false) {
+            continue;
+        }
+        let parsed = fs::read_to_string(item.path())
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|raw| serde_yaml::from_str::<HistoryEntry>(&raw).map_err(Into::into));
+        match parsed {
+            Ok(entry) => entries.push(entry),
+            Err(err) => tracing::warn!(id, error = %err, "skipping unreadable history entry"),
+        }
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.generated_at));
+    Ok(entries)
+}
+
+/// The entry whose id starts with `prefix`, and its config.
+pub async fn load(dir: &Path, prefix: &str) -> anyhow::Result<(HistoryEntry, String)> {
+    let entries = list(dir).await?;
+    let mut matches = entries.iter().filter(|entry| entry.id.starts_with(prefix));
+    let entry = match (matches.next(), matches.next()) {
+        (Some(entry), None) if !prefix.is_empty() => entry.clone(),
+        (Some(_), _) => return Err(anyhow!("history id '{}' is ambiguous", prefix)),
+        (None, _) => {
+            return Err(anyhow!(
+                "no config with id '{}' in {}",
+                prefix,
+                dir.display()
+            ))
+        }
+    };
+    let path = config_path(dir, &entry.id);
+    let yaml = fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok((entry, yaml))
+}
+
+async fn remove(dir: &Path, id: &str) -> anyhow::Result<()> {
+    for path in [config_path(dir, id), meta_path(dir, id)] {
+        match fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).with_context(|| format!("failed to remove {}", path.display()))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn config_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{id}.yaml"))
+}
+
+fn meta_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{id}.meta.yaml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_dedupes_and_prunes_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = Path::new("/tmp/config.yaml");
+        let first = "# Generated at: 2026-01-01T00:00:00Z\nproxies: []\n";
+        let mut entry = HistoryEntry::new(first, output, vec!["main".to_string()], 0);
+        record(dir.path(), &entry, first, 5).await.unwrap();
+
+        let again = "# Generated at: 2026-01-02T00:00:00Z\nproxies: []\n";
+        assert_eq!(config_id(again), entry.id);
+        entry.generated_at += chrono::Duration::days(1);
+        record(dir.path(), &entry, again, 5).await.unwrap();
+        assert_eq!(list(dir.path()).await.unwrap(), [entry.clone()]);
+
+        for n in 1..=3 {
+            let yaml = format!("proxies: []\nrules: [\"MATCH,P{n}\"]\n");
+            let mut newer = HistoryEntry::new(&yaml, output, Vec::new(), n);
+            newer.generated_at = entry.generated_at + chrono::Duration::hours(n as i64);
+            record(dir.path(), &newer, &yaml, 2).await.unwrap();
+        }
+        let kept = list(dir.path()).await.unwrap();
+        assert_eq!(kept.iter().map(|e| e.proxies).collect::<Vec<_>>(), [3, 2]);
+
+        let (found, yaml) = load(dir.path(), &kept[1].id[..6]).await.unwrap();
+        assert_eq!(found, kept[1]);
+        assert!(yaml.contains("MATCH,P2"));
+        assert!(load(dir.path(), &entry.id).await.is_err());
+    }
+}
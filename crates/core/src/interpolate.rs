@@ -0,0 +1,73 @@
+//! `${VAR}` interpolation for subscriptions.yaml and templates, so tokens can live in the
+//! environment instead of committed dotfiles.
+//!
+//! `${VAR}` is replaced by the variable's value and fails when it is unset; `${VAR:-fallback}`
+//! uses `fallback` instead. `$${` writes a literal `${`. A `$` not followed by `{` is left alone.
+
+use anyhow::anyhow;
+
+/// Expand `${VAR}` references in `text` from the process environment.
+pub fn expand_env_vars(text: &str) -> anyhow::Result<String> {
+    expand_with(text, |name| std::env::var(name).ok())
+}
+
+fn expand_with(text: &str, lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if let Some(escaped) = after.strip_prefix("${") {
+            out.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            out.push('$');
+            rest = after;
+            continue;
+        };
+        let end = body
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated `${{`"))?;
+        let (name, fallback) = match body[..end].split_once(":-") {
+            Some((name, fallback)) => (name, Some(fallback)),
+            None => (&body[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!("invalid environment variable name `{}`", name));
+        }
+        match (lookup(name), fallback) {
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(fallback)) => out.push_str(fallback),
+            (None, None) => {
+                return Err(anyhow!("environment variable `{}` is not set", name));
+            }
+        }
+        rest = &body[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_references_fallbacks_and_escapes() {
+        let lookup = |name: &str| (name == "TOKEN").then(|| "s3cret".to_string());
+        assert_eq!(
+            expand_with("https://example.com/sub?token=${TOKEN}", lookup).unwrap(),
+            "https://example.com/sub?token=s3cret"
+        );
+        assert_eq!(
+            expand_with("${MISSING:-direct} costs $5, $${TOKEN}", lookup).unwrap(),
+            "direct costs $5, ${TOKEN}"
+        );
+        let err = expand_with("token=${MISSING}", lookup).unwrap_err();
+        assert!(err.to_string().contains("`MISSING` is not set"));
+        assert!(expand_with("token=${TOKEN", lookup).is_err());
+        assert!(expand_with("${BAD-NAME}", lookup).is_err());
+    }
+}
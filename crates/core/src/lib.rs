@@ -1,10 +1,40 @@
+pub mod blocklist;
+pub mod clash_verge;
+pub mod controller;
+pub mod diff;
+pub mod filter;
+pub mod groups;
+pub mod history;
+pub mod interpolate;
+pub mod lint;
+pub mod liveness;
 pub mod merge;
+pub mod mirror;
 pub mod model;
 pub mod output;
+pub mod probe;
+pub mod redact;
+pub mod region;
+pub mod rename;
+pub mod report;
+pub mod rule_providers;
+pub mod schedule;
+pub mod schema;
+pub mod script;
+pub mod secrets;
+pub mod serve;
 pub mod storage;
 pub mod subscription;
 pub mod template;
+pub mod throttle;
 
-pub use merge::{apply_base_config, merge_configs};
+pub use filter::{find_proxies, ProxyFilter, ProxyFilterSpec, ProxyMatch};
+pub use merge::{
+    add_provider_groups, apply_base_config, apply_base_config_sections, apply_group_icons,
+    apply_health_check_urls, apply_merge_directives, apply_merge_patch, combine_merge_patches,
+    merge_configs, merge_configs_with_options, merge_configs_with_warnings, BaseSection,
+    GroupOrder, HealthCheckUrls, MergeOptions, ProviderGroupKind, ProviderNodes, ProxyDedup,
+};
 pub use model::ClashConfig;
-pub use template::Template;
+pub use report::{MergeReport, Warning, WarningKind, Warnings};
+pub use template::{sync_bundled_template, BundledTemplateSync, Template};
@@ -0,0 +1,255 @@
+//! Detection of deprecated, renamed or unsupported keys in templates and base configs.
+
+use serde_yaml::{Mapping, Value};
+
+/// One deprecated/unsupported construct and how to fix it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    /// Where it was found, e.g. `tun.inet4-route-address` or `proxy-groups[chain]`.
+    pub location: String,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl std::fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " ({})", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Top-level keys that were renamed: (legacy, replacement).
+const RENAMED_TOP_LEVEL: &[(&str, &str)] = &[
+    ("Proxy", "proxies"),
+    ("Proxy Group", "proxy-groups"),
+    ("Rule", "rules"),
+    ("proxy-provider", "proxy-providers"),
+    ("rule-provider", "rule-providers"),
+    ("enable-process", "find-process-mode"),
+];
+
+/// Top-level keys mihomo ignores or rejects: (key, explanation).
+const UNSUPPORTED_TOP_LEVEL: &[(&str, &str)] = &[
+    (
+        "script",
+        "Clash Premium script mode is not supported by mihomo; express it as rules",
+    ),
+    ("cfw-bypass", "Clash for Windows only; ignored by mihomo"),
+    (
+        "cfw-latency-timeout",
+        "Clash for Windows only; ignored by mihomo",
+    ),
+    (
+        "cfw-latency-url",
+        "Clash for Windows only; ignored by mihomo",
+    ),
+    (
+        "cfw-conn-break-strategy",
+        "Clash for Windows only; ignored by mihomo",
+    ),
+];
+
+/// Renamed keys inside `tun`.
+const RENAMED_TUN: &[(&str, &str)] = &[
+    ("inet4-route-address", "route-address"),
+    ("inet6-route-address", "route-address"),
+    ("inet4-route-exclude-address", "route-exclude-address"),
+    ("inet6-route-exclude-address", "route-exclude-address"),
+];
+
+/// Check a raw template/base-config mapping for constructs that newer mihomo versions
+/// deprecate, rename or no longer support.
+pub fn lint_mapping(raw: &Mapping) -> Vec<LintFinding> {
+    let mut findings = Vec::new();
+
+    for (legacy, replacement) in RENAMED_TOP_LEVEL {
+        if raw.contains_key(Value::from(*legacy)) {
+            findings.push(finding(
+                legacy,
+                "renamed key",
+                Some(format!("use `{}`", replacement)),
+            ));
+        }
+    }
+    for (key, why) in UNSUPPORTED_TOP_LEVEL {
+        if raw.contains_key(Value::from(*key)) {
+            findings.push(finding(key, why, None));
+        }
+    }
+    if raw.get(Value::from("mode")).and_then(Value::as_str) == Some("script") {
+        findings.push(finding(
+            "mode",
+            "`mode: script` is not supported by mihomo",
+            Some("use `mode: rule`".to_string()),
+        ));
+    }
+
+    if let Some(experimental) = raw
+        .get(Value::from("experimental"))
+        .and_then(Value::as_mapping)
+    {
+        if experimental.contains_key(Value::from("sniff-tls-sni")) {
+            findings.push(finding(
+                "experimental.sniff-tls-sni",
+                "removed",
+                Some("configure the top-level `sniffer` section".to_string()),
+            ));
+        }
+        if experimental.contains_key(Value::from("ignore-resolve-fail")) {
+            findings.push(finding("experimental.ignore-resolve-fail", "removed", None));
+        }
+    }
+
+    if let Some(tun) = raw.get(Value::from("tun")).and_then(Value::as_mapping) {
+        for (legacy, replacement) in RENAMED_TUN {
+            if tun.contains_key(Value::from(*legacy)) {
+                findings.push(finding(
+                    &format!("tun.{}", legacy),
+                    "deprecated",
+                    Some(format!("use `tun.{}`", replacement)),
+                ));
+            }
+        }
+    }
+    if raw.contains_key(Value::from("auto-redir")) {
+        findings.push(finding(
+            "auto-redir",
+            "removed",
+            Some("use `tun.auto-redirect`".to_string()),
+        ));
+    }
+
+    for proxy in sequence(raw, "proxies") {
+        lint_proxy(proxy, &mut findings);
+    }
+    for group in sequence(raw, "proxy-groups") {
+        if group.get("type").and_then(Value::as_str) == Some("relay") {
+            findings.push(finding(
+                &format!("proxy-groups[{}]", entry_name(group)),
+                "`type: relay` groups are deprecated",
+                Some("set `dialer-proxy` on the proxies instead".to_string()),
+            ));
+        }
+    }
+    for rule in sequence(raw, "rules")
+        .iter()
+        .filter_map(|rule| rule.as_str())
+    {
+        if rule.trim_start().starts_with("SCRIPT,") {
+            findings.push(finding(
+                &format!("rules[{}]", rule),
+                "SCRIPT rules are Clash Premium only",
+                Some("rewrite as a regular or logic (AND/OR/NOT) rule".to_string()),
+            ));
+        }
+    }
+
+    findings
+}
+
+fn lint_proxy(proxy: &Value, findings: &mut Vec<LintFinding>) {
+    let location = format!("proxies[{}]", entry_name(proxy));
+    let has = |key: &str| proxy.get(key).is_some();
+    if has("obfs") && proxy.get("type").and_then(Value::as_str) == Some("ss") {
+        findings.push(finding(
+            &location,
+            "legacy `obfs`/`obfs-host` fields",
+            Some("use `plugin: obfs` with `plugin-opts: {mode, host}`".to_string()),
+        ));
+    }
+    if has("ws-path") || has("ws-headers") {
+        findings.push(finding(
+            &location,
+            "legacy `ws-path`/`ws-headers` fields",
+            Some("use `ws-opts: {path, headers}`".to_string()),
+        ));
+    }
+    if proxy
+        .get("flow")
+        .and_then(Value::as_str)
+        .is_some_and(|flow| flow.starts_with("xtls-rprx-") && flow != "xtls-rprx-vision")
+    {
+        findings.push(finding(
+            &location,
+            "XTLS flows other than vision were removed",
+            Some("use `flow: xtls-rprx-vision`".to_string()),
+        ));
+    }
+}
+
+fn finding(location: &str, message: &str, suggestion: Option<String>) -> LintFinding {
+    LintFinding {
+        location: location.to_string(),
+        message: message.to_string(),
+        suggestion,
+    }
+}
+
+fn sequence<'a>(raw: &'a Mapping, key: &str) -> &'a [Value] {
+    raw.get(Value::from(key))
+        .and_then(Value::as_sequence)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+fn entry_name(value: &Value) -> &str {
+    value.get("name").and_then(Value::as_str).unwrap_or("?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_legacy_and_unsupported_keys() {
+        let raw: Mapping = serde_yaml::from_str(
+            r#"
+Proxy: []
+mode: script
+cfw-bypass: [localhost]
+tun:
+  enable: true
+  inet4-route-address: [0.0.0.0/1]
+proxies:
+  - { name: old-ss, type: ss, server: s, port: 1, cipher: aes-128-gcm, password: p, obfs: http }
+  - { name: old-vmess, type: vmess, server: s, port: 1, uuid: u, ws-path: /ws }
+proxy-groups:
+  - { name: chain, type: relay, proxies: [old-ss, old-vmess] }
+rules:
+  - SCRIPT,quic,REJECT
+"#,
+        )
+        .unwrap();
+        let findings = lint_mapping(&raw);
+        let locations: Vec<&str> = findings.iter().map(|f| f.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            vec![
+                "Proxy",
+                "cfw-bypass",
+                "mode",
+                "tun.inet4-route-address",
+                "proxies[old-ss]",
+                "proxies[old-vmess]",
+                "proxy-groups[chain]",
+                "rules[SCRIPT,quic,REJECT]",
+            ]
+        );
+        assert_eq!(
+            findings[0].to_string(),
+            "Proxy: renamed key (use `proxies`)"
+        );
+    }
+
+    #[test]
+    fn modern_config_is_clean() {
+        let raw: Mapping = serde_yaml::from_str(
+            "mixed-port: 7890\nmode: rule\ntun: { enable: true, route-exclude-address: [] }\nrules: [MATCH,DIRECT]\n",
+        )
+        .unwrap();
+        assert!(lint_mapping(&raw).is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+//! Per-node reachability remembered across merge runs.
+//!
+//! Each run that observes a node (a `--probe` connect, or the delay history of the running
+//! mihomo) records whether it answered. A node that failed `threshold` runs in a row is
+//! excluded from later merges until it answers again or the record is reset.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::filter::strip_group_members;
+use crate::model::ClashConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    /// Runs in a row in which the node did not answer; reset when it does.
+    pub consecutive_failures: u32,
+    pub last_checked: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_reachable: Option<DateTime<Utc>>,
+}
+
+/// Liveness records by proxy name, persisted in the cache dir between runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeLiveness {
+    nodes: BTreeMap<String, NodeRecord>,
+}
+
+impl NodeLiveness {
+    /// The saved records, or none when `path` is missing or unreadable.
+    pub async fn load(path: &Path) -> Self {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        crate::storage::write_atomic(path, &serde_json::to_vec_pretty(self)?).await
+    }
+
+    pub fn nodes(&self) -> &BTreeMap<String, NodeRecord> {
+        &self.nodes
+    }
+
+    /// Record one run's observations: proxy name → whether it answered.
+    pub fn record(&mut self, observed: &BTreeMap<String, bool>, now: DateTime<Utc>) {
+        for (name, &reachable) in observed {
+            let record = self
+                .nodes
+                .entry(name.clone())
+                .or_insert_with(|| NodeRecord {
+                    consecutive_failures: 0,
+                    last_checked: now,
+                    last_reachable: None,
+                });
+            record.last_checked = now;
+            if reachable {
+                record.consecutive_failures = 0;
+                record.last_reachable = Some(now);
+            } else {
+                record.consecutive_failures = record.consecutive_failures.saturating_add(1);
+            }
+        }
+    }
+
+    /// Names of the nodes that failed at least `threshold` runs in a row.
+    pub fn dead(&self, threshold: u32) -> Vec<&str> {
+        self.nodes
+            .iter()
+            .filter(|(_, record)| record.consecutive_failures >= threshold.max(1))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Forget `names`, or every node when `names` is empty. Returns the number forgotten.
+    pub fn reset(&mut self, names: &[String]) -> usize {
+        if names.is_empty() {
+            let count = self.nodes.len();
+            self.nodes.clear();
+            return count;
+        }
+        names
+            .iter()
+            .filter(|name| self.nodes.remove(name.as_str()).is_some())
+            .count()
+    }
+}
+
+/// Remove the nodes `liveness` considers dead from `proxies` and every group. Returns their
+/// names.
+pub fn exclude_dead(
+    config: &mut ClashConfig,
+    liveness: &NodeLiveness,
+    threshold: u32,
+) -> Vec<String> {
+    let dead: HashSet<&str> = liveness.dead(threshold).into_iter().collect();
+    let mut removed = Vec::new();
+    config
+        .proxies
+        .retain(|proxy| match proxy.get("name").and_then(Value::as_str) {
+            Some(name) if dead.contains(name) => {
+                removed.push(name.to_string());
+                false
+            }
+            _ => true,
+        });
+    if !removed.is_empty() {
+        let gone: HashSet<&str> = removed.iter().map(String::as_str).collect();
+        strip_group_members(config, &gone);
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nodes_failing_threshold_runs_in_a_row_are_excluded() {
+        let mut liveness = NodeLiveness::default();
+        let run =
+            |hk: bool, jp: bool| BTreeMap::from([("HK".to_string(), hk), ("JP".to_string(), jp)]);
+        liveness.record(&run(false, false), Utc::now());
+        liveness.record(&run(false, true), Utc::now());
+        liveness.record(&run(false, false), Utc::now());
+        assert_eq!(liveness.dead(3), vec!["HK"]);
+        assert_eq!(liveness.nodes()["JP"].consecutive_failures, 1);
+
+        let mut config: ClashConfig = serde_yaml::from_str(
+            "proxies: [{name: HK, type: ss}, {name: JP, type: ss}]\n\
+             proxy-groups: [{name: Proxy, type: select, proxies: [HK, JP]}]\n",
+        )
+        .unwrap();
+        assert_eq!(exclude_dead(&mut config, &liveness, 3), vec!["HK"]);
+        assert_eq!(config.proxy_names(), vec!["JP"]);
+        assert_eq!(config.proxy_groups[0]["proxies"][0].as_str(), Some("JP"));
+
+        liveness.record(&run(true, true), Utc::now());
+        assert!(liveness.dead(1).is_empty());
+        assert_eq!(liveness.reset(&["HK".to_string()]), 1);
+        assert_eq!(liveness.reset(&[]), 1);
+    }
+}
@@ -1,19 +1,141 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use serde_yaml::Value;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 
 use crate::model::ClashConfig;
+use crate::report::{WarningKind, Warnings};
 
 const DEFAULT_SELECTOR_NAME: &str = "🚀 节点选择";
 
+/// How proxy groups are ordered in the merged output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum GroupOrder {
+    /// Template groups first, then subscription groups in the order they were first seen.
+    #[default]
+    Template,
+    /// Template groups first, then subscription groups sorted by name.
+    Name,
+    /// Groups named in the list come first (in list order); the rest keep template order.
+    Explicit(Vec<String>),
+}
+
+/// Type of the per-subscription groups generated by [`add_provider_groups`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderGroupKind {
+    Select,
+    UrlTest,
+}
+
+/// Nodes contributed by one subscription.
+#[derive(Debug, Clone)]
+pub struct ProviderNodes {
+    pub name: String,
+    pub proxies: Vec<String>,
+}
+
+pub(crate) const PROVIDER_URL_TEST_URL: &str = "https://www.gstatic.com/generate_204";
+pub(crate) const PROVIDER_URL_TEST_INTERVAL: u64 = 300;
+
+/// How proxies that appear in more than one source are handled; mihomo rejects duplicate
+/// names, so every mode ends with unique names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProxyDedup {
+    /// Keep every node; a name already taken gets a `-2`, `-3`, ... suffix.
+    #[default]
+    Rename,
+    /// Drop a node whose name is already taken.
+    Name,
+    /// Drop a node whose type, server and port match a node from an earlier source (the
+    /// template or a previous subscription), then rename any remaining name clashes.
+    Endpoint,
+}
+
+/// Group types that probe their members with `url`.
+const HEALTH_CHECKED_GROUP_TYPES: &[&str] = &["url-test", "fallback", "load-balance"];
+
+/// Group keys only dashboards read (Clash Meta's `icon` URL and `hidden` flag).
+const GROUP_DISPLAY_KEYS: &[&str] = &["icon", "hidden"];
+
+/// Health-check URL overrides (`health_check_urls` in app.yaml), e.g. a URL reachable from
+/// mainland China for domestic groups and gstatic for the rest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheckUrls {
+    /// URL for every url-test/fallback/load-balance group without an entry in `groups`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// Group name → URL; applies whatever the group type (select groups use it for delay
+    /// tests in dashboards).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, String>,
+}
+
+impl HealthCheckUrls {
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.groups.is_empty()
+    }
+
+    /// `self` with `other`'s default and group entries taking precedence.
+    pub fn overridden_by(mut self, other: &HealthCheckUrls) -> HealthCheckUrls {
+        if other.default.is_some() {
+            self.default = other.default.clone();
+        }
+        self.groups
+            .extend(other.groups.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    pub group_order: GroupOrder,
+    pub dedup: ProxyDedup,
+    pub health_checks: HealthCheckUrls,
+}
+
 pub fn merge_configs(template: ClashConfig, subs: Vec<ClashConfig>) -> ClashConfig {
+    merge_configs_with_options(template, subs, &MergeOptions::default())
+}
+
+pub fn merge_configs_with_options(
+    template: ClashConfig,
+    subs: Vec<ClashConfig>,
+    options: &MergeOptions,
+) -> ClashConfig {
+    merge_configs_with_warnings(template, subs, options, &mut Warnings::default())
+}
+
+/// Like [`merge_configs_with_options`], recording dropped and duplicate nodes in `warnings`.
+pub fn merge_configs_with_warnings(
+    template: ClashConfig,
+    subs: Vec<ClashConfig>,
+    options: &MergeOptions,
+    warnings: &mut Warnings,
+) -> ClashConfig {
     let mut out = template;
+    let template_group_count = out.proxy_groups.len();
     let mut all_proxy_names = Vec::new();
     let mut seen_proxy_names = HashSet::new();
+    let mut seen_endpoints: HashMap<ProxyEndpoint, String> = HashMap::new();
 
     collect_proxy_names(&out.proxies, &mut all_proxy_names, &mut seen_proxy_names);
+    let mut taken_names = seen_proxy_names.clone();
+    for proxy in &out.proxies {
+        if let (Some(endpoint), Some(name)) = (proxy_endpoint(proxy), proxy_group_name(proxy)) {
+            seen_endpoints.entry(endpoint).or_insert(name);
+        }
+    }
 
     for mut sub in subs {
+        drop_unnamed_proxies(&mut sub.proxies, warnings);
+        dedup_proxies(
+            &mut sub,
+            &mut taken_names,
+            &mut seen_endpoints,
+            options.dedup,
+            warnings,
+        );
         collect_proxy_names(&sub.proxies, &mut all_proxy_names, &mut seen_proxy_names);
 
         // Prefer append over extend(drain(..)) per clippy
@@ -26,47 +148,633 @@ pub fn merge_configs(template: ClashConfig, subs: Vec<ClashConfig>) -> ClashConf
         }
     }
 
+    order_proxy_groups(
+        &mut out.proxy_groups,
+        template_group_count,
+        &options.group_order,
+    );
     populate_default_selector(&mut out.proxy_groups, &all_proxy_names);
+    apply_health_check_urls(&mut out, &options.health_checks);
 
     out
 }
 
-pub fn apply_base_config(mut merged: ClashConfig, base: &ClashConfig) -> ClashConfig {
-    // Ports: prefer base-config values. If base uses mixed-port (extra), drop legacy ports.
-    if let Some(port) = base.port {
-        merged.port = Some(port);
+/// Set the `url` of the groups covered by `urls`. Returns the number of groups changed.
+pub fn apply_health_check_urls(config: &mut ClashConfig, urls: &HealthCheckUrls) -> usize {
+    if urls.is_empty() {
+        return 0;
+    }
+    let mut changed = 0;
+    for group in config.proxy_groups.iter_mut() {
+        let Some(map) = group.as_mapping_mut() else {
+            continue;
+        };
+        let name = map.get("name").and_then(Value::as_str).unwrap_or_default();
+        let health_checked = map
+            .get("type")
+            .and_then(Value::as_str)
+            .is_some_and(|kind| HEALTH_CHECKED_GROUP_TYPES.contains(&kind));
+        let url = match urls.groups.get(name) {
+            Some(url) => url,
+            None => match urls.default.as_ref().filter(|_| health_checked) {
+                Some(url) => url,
+                None => continue,
+            },
+        };
+        if map.get("url").and_then(Value::as_str) != Some(url.as_str()) {
+            map.insert(Value::from("url"), Value::from(url.as_str()));
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Set `icon` from `icons` (group name → icon URL) on the groups that have none, e.g. the
+/// generated provider and region groups. Icons set by the template or a subscription are kept.
+/// Returns the number of groups changed.
+pub fn apply_group_icons(config: &mut ClashConfig, icons: &BTreeMap<String, String>) -> usize {
+    let mut changed = 0;
+    for group in config.proxy_groups.iter_mut() {
+        let Some(map) = group.as_mapping_mut() else {
+            continue;
+        };
+        if map.contains_key("icon") {
+            continue;
+        }
+        let name = map.get("name").and_then(Value::as_str).unwrap_or_default();
+        if let Some(icon) = icons.get(name) {
+            map.insert(Value::from("icon"), Value::from(icon.as_str()));
+            changed += 1;
+        }
     }
-    if let Some(socks) = base.socks_port {
-        merged.socks_port = Some(socks);
+    changed
+}
+
+/// type, server and port of a proxy.
+pub(crate) type ProxyEndpoint = (String, String, String);
+
+pub(crate) fn proxy_endpoint(proxy: &Value) -> Option<ProxyEndpoint> {
+    let kind = proxy.get("type").and_then(Value::as_str)?;
+    let server = proxy.get("server").and_then(Value::as_str)?;
+    let port = match proxy.get("port")? {
+        Value::Number(port) => port.to_string(),
+        Value::String(port) => port.trim().to_string(),
+        _ => return None,
+    };
+    Some((kind.to_ascii_lowercase(), server.to_ascii_lowercase(), port))
+}
+
+/// Make the proxy names of `sub` unique against `taken` (names from earlier sources, extended
+/// as this runs) according to `mode`. References in the subscription's own groups follow the
+/// node they meant: a renamed proxy keeps being referenced under its new name and a proxy
+/// dropped as an endpoint duplicate is replaced by the node it duplicated.
+fn dedup_proxies(
+    sub: &mut ClashConfig,
+    taken: &mut HashSet<String>,
+    endpoints: &mut HashMap<ProxyEndpoint, String>,
+    mode: ProxyDedup,
+    warnings: &mut Warnings,
+) {
+    let mut own: HashSet<String> = HashSet::new();
+    let mut own_endpoints: Vec<(ProxyEndpoint, String)> = Vec::new();
+    let mut renamed: HashMap<String, String> = HashMap::new();
+
+    sub.proxies.retain_mut(|proxy| {
+        let Some(name) = proxy_group_name(proxy) else {
+            return true;
+        };
+        let endpoint = proxy_endpoint(proxy);
+        if mode == ProxyDedup::Endpoint {
+            if let Some(kept) = endpoint.as_ref().and_then(|ep| endpoints.get(ep)) {
+                warnings.push(
+                    WarningKind::DuplicateProxy,
+                    format!(
+                        "dropped proxy '{}': same endpoint as '{}' from an earlier source",
+                        name, kept
+                    ),
+                );
+                if !own.contains(&name) {
+                    renamed.entry(name).or_insert_with(|| kept.clone());
+                }
+                return false;
+            }
+        }
+        if mode == ProxyDedup::Name && taken.contains(&name) {
+            warnings.push(
+                WarningKind::DuplicateProxy,
+                format!("dropped proxy '{}': name is used more than once", name),
+            );
+            return false;
+        }
+
+        let mut final_name = name.clone();
+        if taken.contains(&name) {
+            final_name = (2..)
+                .map(|n| format!("{}-{}", name, n))
+                .find(|candidate| !taken.contains(candidate))
+                .expect("unbounded suffix range");
+            warnings.push(
+                WarningKind::DuplicateProxy,
+                format!(
+                    "proxy name '{}' is used more than once; renamed to '{}'",
+                    name, final_name
+                ),
+            );
+            if let Some(map) = proxy.as_mapping_mut() {
+                map.insert(Value::from("name"), Value::from(final_name.as_str()));
+            }
+            if !own.contains(&name) {
+                renamed
+                    .entry(name.clone())
+                    .or_insert_with(|| final_name.clone());
+            }
+        }
+        taken.insert(final_name.clone());
+        own.insert(name);
+        if let Some(endpoint) = endpoint {
+            own_endpoints.push((endpoint, final_name));
+        }
+        true
+    });
+
+    for (endpoint, name) in own_endpoints {
+        endpoints.entry(endpoint).or_insert(name);
     }
-    if let Some(redir) = base.redir_port {
-        merged.redir_port = Some(redir);
+    if renamed.is_empty() {
+        return;
     }
-    if base.extra.contains_key("mixed-port") {
-        merged.port = None;
-        merged.socks_port = None;
-        merged.redir_port = None;
+    for group in sub.proxy_groups.iter_mut() {
+        let Some(members) = group.get_mut("proxies").and_then(Value::as_sequence_mut) else {
+            continue;
+        };
+        let mut seen = HashSet::new();
+        members.retain_mut(|member| {
+            if let Some(new) = member.as_str().and_then(|name| renamed.get(name)) {
+                *member = Value::from(new.as_str());
+            }
+            member
+                .as_str()
+                .is_none_or(|name| seen.insert(name.to_string()))
+        });
+    }
+}
+
+/// mihomo rejects proxies without a name, so drop them instead of emitting a broken config.
+fn drop_unnamed_proxies(proxies: &mut Vec<Value>, warnings: &mut Warnings) {
+    proxies.retain(|proxy| {
+        let named = proxy
+            .as_mapping()
+            .and_then(|map| map.get(Value::from("name")))
+            .and_then(Value::as_str)
+            .is_some_and(|name| !name.is_empty());
+        if !named {
+            let server = proxy
+                .get("server")
+                .and_then(Value::as_str)
+                .unwrap_or("<unknown server>");
+            warnings.push(
+                WarningKind::DroppedNode,
+                format!("dropped proxy without a name (server {})", server),
+            );
+        }
+        named
+    });
+}
+
+fn order_proxy_groups(groups: &mut [Value], template_count: usize, order: &GroupOrder) {
+    match order {
+        GroupOrder::Template => {}
+        GroupOrder::Name => {
+            let start = template_count.min(groups.len());
+            // Stable sort keeps unnamed groups in arrival order at the end.
+            groups[start..].sort_by(|a, b| match (proxy_group_name(a), proxy_group_name(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            });
+        }
+        GroupOrder::Explicit(names) => {
+            groups.sort_by_key(|group| {
+                proxy_group_name(group)
+                    .and_then(|name| names.iter().position(|wanted| *wanted == name))
+                    .unwrap_or(usize::MAX)
+            });
+        }
+    }
+}
+
+/// Parts of a base-config that can be inherited independently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BaseSection {
+    /// `port`, `socks-port`, `redir-port`, `mixed-port`, `tproxy-port`.
+    Ports,
+    /// `dns` and `hosts`.
+    Dns,
+    /// `tun`.
+    Tun,
+    /// Proxy-groups, rebuilt around the merged proxies.
+    Groups,
+    /// Rules (replace the merged rules). Not inherited by default.
+    Rules,
+    /// Every other top-level key (`mode`, `log-level`, `external-controller`, ...).
+    Other,
+}
+
+impl BaseSection {
+    /// Sections applied when none are configured: everything except rules.
+    pub const DEFAULT: &'static [BaseSection] = &[
+        BaseSection::Ports,
+        BaseSection::Dns,
+        BaseSection::Tun,
+        BaseSection::Groups,
+        BaseSection::Other,
+    ];
+
+    fn of_key(key: &str) -> BaseSection {
+        match key {
+            "mixed-port" | "tproxy-port" => BaseSection::Ports,
+            "dns" | "hosts" => BaseSection::Dns,
+            "tun" => BaseSection::Tun,
+            _ => BaseSection::Other,
+        }
+    }
+}
+
+pub fn apply_base_config(merged: ClashConfig, base: &ClashConfig) -> ClashConfig {
+    apply_base_config_sections(merged, base, BaseSection::DEFAULT)
+}
+
+/// Inherit only the given `sections` of `base`; everything else stays as merged.
+pub fn apply_base_config_sections(
+    mut merged: ClashConfig,
+    base: &ClashConfig,
+    sections: &[BaseSection],
+) -> ClashConfig {
+    // Ports: prefer base-config values. If base uses mixed-port (extra), drop legacy ports.
+    if sections.contains(&BaseSection::Ports) {
+        if let Some(port) = base.port {
+            merged.port = Some(port);
+        }
+        if let Some(socks) = base.socks_port {
+            merged.socks_port = Some(socks);
+        }
+        if let Some(redir) = base.redir_port {
+            merged.redir_port = Some(redir);
+        }
+        if base.extra.contains_key("mixed-port") {
+            merged.port = None;
+            merged.socks_port = None;
+            merged.redir_port = None;
+        }
     }
 
     // Extra: keep base-config values when keys overlap; only add merged keys that base lacks.
-    let mut extra = base.extra.clone();
+    let mut extra: IndexMap<String, Value> = base
+        .extra
+        .iter()
+        .filter(|(key, _)| sections.contains(&BaseSection::of_key(key)))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
     for (key, value) in merged.extra.into_iter() {
         extra.entry(key).or_insert(value);
     }
     merged.extra = extra;
 
-    if !base.proxy_groups.is_empty() {
+    if sections.contains(&BaseSection::Groups) && !base.proxy_groups.is_empty() {
         let names = merged.proxy_names();
         let mut rebuilt = Vec::with_capacity(base.proxy_groups.len());
         for group in &base.proxy_groups {
-            rebuilt.push(rebuild_group(group, &names));
+            let mut group = rebuild_group(group, &names);
+            let name = proxy_group_name(&group);
+            if let (Some(target), Some(replaced)) = (
+                group.as_mapping_mut(),
+                merged
+                    .proxy_groups
+                    .iter()
+                    .find(|candidate| name.is_some() && proxy_group_name(candidate) == name)
+                    .and_then(Value::as_mapping),
+            ) {
+                inherit_display_keys(target, replaced);
+            }
+            rebuilt.push(group);
         }
         merged.proxy_groups = rebuilt;
     }
 
+    if sections.contains(&BaseSection::Rules) && !base.rules.is_empty() {
+        merged.rules = base.rules.clone();
+    }
+
     merged
 }
 
+/// Add one group per subscription holding only that subscription's nodes, and list those
+/// groups first in `main_selector` (when it is a group) so a provider can be picked at runtime.
+/// Returns the number of groups added.
+pub fn add_provider_groups(
+    config: &mut ClashConfig,
+    providers: &[ProviderNodes],
+    kind: ProviderGroupKind,
+    main_selector: &str,
+    warnings: &mut Warnings,
+) -> usize {
+    let existing_proxies: HashSet<String> = config.proxy_names().into_iter().collect();
+    let mut taken: HashSet<String> = config.proxy_group_names().into_iter().collect();
+    let mut added = Vec::new();
+
+    for provider in providers {
+        let proxies: Vec<&String> = provider
+            .proxies
+            .iter()
+            .filter(|name| existing_proxies.contains(*name))
+            .collect();
+        if proxies.is_empty() {
+            continue;
+        }
+        if taken.contains(&provider.name) || existing_proxies.contains(&provider.name) {
+            warnings.push(
+                WarningKind::DuplicateGroup,
+                format!(
+                    "provider group '{}' clashes with an existing group or proxy; skipped",
+                    provider.name
+                ),
+            );
+            continue;
+        }
+        taken.insert(provider.name.clone());
+
+        let mut group = serde_yaml::Mapping::new();
+        group.insert(Value::from("name"), Value::from(provider.name.as_str()));
+        match kind {
+            ProviderGroupKind::Select => {
+                group.insert(Value::from("type"), Value::from("select"));
+            }
+            ProviderGroupKind::UrlTest => {
+                group.insert(Value::from("type"), Value::from("url-test"));
+                group.insert(Value::from("url"), Value::from(PROVIDER_URL_TEST_URL));
+                group.insert(
+                    Value::from("interval"),
+                    Value::from(PROVIDER_URL_TEST_INTERVAL),
+                );
+            }
+        }
+        group.insert(
+            Value::from("proxies"),
+            Value::Sequence(proxies.into_iter().cloned().map(Value::from).collect()),
+        );
+        config.proxy_groups.push(Value::Mapping(group));
+        added.push(provider.name.clone());
+    }
+
+    prepend_group_members(config, main_selector, &added);
+    added.len()
+}
+
+/// List `members` first in the group named `group` (if there is one), moving them when they
+/// are already present.
+pub(crate) fn prepend_group_members(config: &mut ClashConfig, group: &str, members: &[String]) {
+    if let Some(list) = config
+        .proxy_groups
+        .iter_mut()
+        .find(|candidate| proxy_group_name(candidate).as_deref() == Some(group))
+        .and_then(|group| group.as_mapping_mut())
+        .map(|map| {
+            map.entry(Value::from("proxies"))
+                .or_insert_with(|| Value::Sequence(Vec::new()))
+        })
+        .and_then(Value::as_sequence_mut)
+    {
+        let mut front: Vec<Value> = members.iter().cloned().map(Value::from).collect();
+        list.retain(|value| !front.contains(value));
+        front.append(list);
+        *list = front;
+    }
+}
+
+/// Targets mihomo understands without a matching proxy or group.
+pub(crate) const BUILTIN_TARGETS: &[&str] =
+    &["DIRECT", "REJECT", "REJECT-DROP", "PASS", "COMPATIBLE"];
+
+/// Trailing rule options that follow the target, e.g. `IP-CIDR,10.0.0.0/8,DIRECT,no-resolve`.
+const RULE_OPTIONS: &[&str] = &["no-resolve", "src"];
+
+/// Index (in the comma-split rule) of the policy a rule routes to.
+pub(crate) fn rule_target_index(parts: &[&str]) -> Option<usize> {
+    (0..parts.len())
+        .rev()
+        .find(|&idx| !RULE_OPTIONS.contains(&parts[idx].trim()))
+        .filter(|&idx| idx > 0)
+}
+
+/// The policy (proxy/group/builtin) a rule routes to.
+pub fn rule_target(rule: &str) -> Option<&str> {
+    let parts: Vec<&str> = rule.split(',').collect();
+    rule_target_index(&parts).map(|idx| parts[idx].trim())
+}
+
+/// Remove proxy-groups that end up with no members (after dropping references to proxies or
+/// groups that no longer exist), repeatedly, since removing one group can empty another.
+/// References from other groups are dropped and rules targeting a pruned group are pointed at
+/// `fallback` (or `DIRECT` if `fallback` was pruned too). Returns the pruned group names.
+pub fn prune_empty_groups(config: &mut ClashConfig, fallback: &str) -> Vec<String> {
+    let proxy_names: HashSet<String> = config.proxy_names().into_iter().collect();
+    let mut pruned: Vec<String> = Vec::new();
+
+    loop {
+        let group_names: HashSet<String> = config.proxy_group_names().into_iter().collect();
+        let mut emptied = Vec::new();
+        for group in config.proxy_groups.iter_mut() {
+            let Some(map) = group.as_mapping_mut() else {
+                continue;
+            };
+            let uses_provider = map
+                .get(Value::from("use"))
+                .and_then(Value::as_sequence)
+                .is_some_and(|providers| !providers.is_empty());
+            let members = map
+                .get_mut(Value::from("proxies"))
+                .and_then(Value::as_sequence_mut);
+            let has_members = match members {
+                Some(list) => {
+                    list.retain(|member| {
+                        member.as_str().is_some_and(|name| {
+                            proxy_names.contains(name)
+                                || group_names.contains(name)
+                                || BUILTIN_TARGETS.contains(&name)
+                        })
+                    });
+                    !list.is_empty()
+                }
+                None => false,
+            };
+            if !has_members && !uses_provider {
+                if let Some(name) = proxy_group_name(group) {
+                    emptied.push(name);
+                }
+            }
+        }
+        if emptied.is_empty() {
+            break;
+        }
+        config
+            .proxy_groups
+            .retain(|group| !proxy_group_name(group).is_some_and(|name| emptied.contains(&name)));
+        pruned.extend(emptied);
+    }
+
+    if !pruned.is_empty() {
+        let fallback = if pruned.iter().any(|name| name == fallback) {
+            "DIRECT"
+        } else {
+            fallback
+        };
+        for rule in config.rules.iter_mut() {
+            let mut parts: Vec<&str> = rule.split(',').collect();
+            let Some(idx) = rule_target_index(&parts) else {
+                continue;
+            };
+            if pruned.iter().any(|name| name == parts[idx].trim()) {
+                parts[idx] = fallback;
+                *rule = parts.join(",");
+            }
+        }
+    }
+
+    pruned
+}
+
+/// Deep-merge a local override document onto the final config: mappings merge key by key,
+/// anything else (scalars, sequences) in the override replaces the existing value.
+pub fn apply_override(merged: ClashConfig, overlay: &Value) -> anyhow::Result<ClashConfig> {
+    let mut value = serde_yaml::to_value(&merged)?;
+    deep_merge(&mut value, overlay);
+    Ok(serde_yaml::from_value(value)?)
+}
+
+/// Keys of a clash-verge Merge patch that extend a list instead of replacing a value.
+pub const MERGE_DIRECTIVES: [&str; 6] = [
+    "prepend-rules",
+    "append-rules",
+    "prepend-proxies",
+    "append-proxies",
+    "prepend-proxy-groups",
+    "append-proxy-groups",
+];
+
+/// Resolve [`MERGE_DIRECTIVES`] left in `config.extra` (from a template, base config or
+/// merge file) against the config's own lists, so they never reach the output verbatim.
+pub fn apply_merge_directives(mut config: ClashConfig) -> anyhow::Result<ClashConfig> {
+    let mut patch = Mapping::new();
+    for key in MERGE_DIRECTIVES {
+        if let Some(value) = config.extra.shift_remove(key) {
+            patch.insert(Value::from(key), value);
+        }
+    }
+    if patch.is_empty() {
+        return Ok(config);
+    }
+    apply_merge_patch(config, &patch)
+}
+
+/// Apply a clash-verge style Merge patch: `prepend-`/`append-` `rules`, `proxies` and
+/// `proxy-groups` extend those lists, every other key is deep-merged like [`apply_override`]
+/// (before the lists are extended, so a patch can replace `rules` and still prepend to them).
+pub fn apply_merge_patch(merged: ClashConfig, patch: &Mapping) -> anyhow::Result<ClashConfig> {
+    let mut rest = Mapping::new();
+    let mut edits = Vec::new();
+    for (key, value) in patch {
+        match key.as_str() {
+            Some(name) if MERGE_DIRECTIVES.contains(&name) => {
+                let items = value
+                    .as_sequence()
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("`{}` must be a list", name))?;
+                edits.push((name, items));
+            }
+            _ => {
+                rest.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    let mut merged = if rest.is_empty() {
+        merged
+    } else {
+        apply_override(merged, &Value::Mapping(rest))?
+    };
+    for (name, items) in edits {
+        let prepend = name.starts_with("prepend-");
+        match name.split_once('-').map(|(_, target)| target) {
+            Some("rules") => {
+                let rules = items
+                    .iter()
+                    .map(|rule| {
+                        rule.as_str()
+                            .map(str::to_string)
+                            .ok_or_else(|| anyhow::anyhow!("`{}` entries must be strings", name))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                extend_list(&mut merged.rules, rules, prepend);
+            }
+            Some("proxies") => extend_list(&mut merged.proxies, items, prepend),
+            _ => extend_list(&mut merged.proxy_groups, items, prepend),
+        }
+    }
+    Ok(merged)
+}
+
+/// Fold several Merge patches into one with the same effect as applying them in order, for
+/// consumers that take a single patch (Clash Verge allows one Merge per profile). The one
+/// difference: a later patch that replaces a list outright no longer discards the earlier
+/// patches' prepends/appends to it.
+pub fn combine_merge_patches(patches: &[Mapping]) -> Mapping {
+    let mut combined = Mapping::new();
+    for patch in patches {
+        for (key, value) in patch {
+            let list_edit = key
+                .as_str()
+                .filter(|name| name.starts_with("prepend-") || name.starts_with("append-"));
+            match (list_edit, value.as_sequence(), combined.get_mut(key)) {
+                (Some(name), Some(items), Some(Value::Sequence(list))) => {
+                    // Later prepends land in front of earlier ones.
+                    extend_list(list, items.clone(), name.starts_with("prepend-"));
+                }
+                (_, _, Some(existing)) => deep_merge(existing, value),
+                (_, _, None) => {
+                    combined.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    combined
+}
+
+fn extend_list<T>(list: &mut Vec<T>, items: Vec<T>, prepend: bool) {
+    if prepend {
+        list.splice(0..0, items);
+    } else {
+        list.extend(items);
+    }
+}
+
+pub(crate) fn deep_merge(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Mapping(base_map), Value::Mapping(overlay_map)) => {
+            for (key, value) in overlay_map {
+                match base_map.get_mut(key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base_map.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
 fn merge_proxy_groups(mut base: Vec<Value>, incoming: Vec<Value>) -> Vec<Value> {
     for group in incoming.into_iter() {
         match proxy_group_name(&group) {
@@ -98,6 +806,8 @@ fn merge_proxy_group(base: &mut Value, incoming: &Value) {
         None => return,
     };
 
+    inherit_display_keys(base_map, incoming_map);
+
     let proxies_key = Value::from("proxies");
 
     let base_proxies = base_map
@@ -130,13 +840,26 @@ fn merge_proxy_group(base: &mut Value, incoming: &Value) {
     }
 }
 
+/// Copy the dashboard-only group keys `target` lacks from `source`, so a group replaced or
+/// merged by name keeps the icon and visibility set by either side.
+fn inherit_display_keys(target: &mut Mapping, source: &Mapping) {
+    for key in GROUP_DISPLAY_KEYS {
+        if target.contains_key(*key) {
+            continue;
+        }
+        if let Some(value) = source.get(*key) {
+            target.insert(Value::from(*key), value.clone());
+        }
+    }
+}
+
 fn populate_default_selector(groups: &mut [Value], proxy_names: &[String]) {
     for group in groups.iter_mut() {
         let Some(name) = proxy_group_name(group) else {
             continue;
         };
 
-        if name == DEFAULT_SELECTOR_NAME {
+        if name == DEFAULT_SELECTOR_NAME && !populates_itself(group) {
             if let Some(mapping) = group.as_mapping_mut() {
                 let proxies_key = Value::from("proxies");
                 let sequence = mapping
@@ -178,10 +901,34 @@ fn collect_proxy_names(values: &[Value], dest: &mut Vec<String>, seen: &mut Hash
     }
 }
 
+/// Groups filled by mihomo itself — from `use:` proxy-providers or `include-all*` — keep their
+/// inline `proxies` (usually references to other groups) instead of being stuffed with every
+/// node name.
+fn populates_itself(group: &Value) -> bool {
+    let Some(map) = group.as_mapping() else {
+        return false;
+    };
+    let uses_providers = map
+        .get(Value::from("use"))
+        .and_then(Value::as_sequence)
+        .is_some_and(|providers| !providers.is_empty());
+    let includes_all = [
+        "include-all",
+        "include-all-proxies",
+        "include-all-providers",
+    ]
+    .iter()
+    .any(|key| map.get(Value::from(*key)).and_then(Value::as_bool) == Some(true));
+    uses_providers || includes_all
+}
+
 fn rebuild_group(group: &Value, proxy_names: &[String]) -> Value {
     let Some(map) = group.as_mapping() else {
         return group.clone();
     };
+    if populates_itself(group) {
+        return group.clone();
+    }
 
     let mut rebuilt = map.clone();
     let proxies_key = Value::from("proxies");
@@ -200,6 +947,7 @@ fn rebuild_group(group: &Value, proxy_names: &[String]) -> Value {
 mod tests {
     use super::*;
     use crate::model::ClashConfig;
+    use crate::report::{WarningKind, Warnings};
 
     fn proxy(name: &str) -> Value {
         serde_yaml::from_str(&format!(
@@ -275,6 +1023,37 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn group_icons_survive_merges_and_fill_generated_groups() {
+        let mut template = ClashConfig::default();
+        template.proxy_groups.push(selector_group("Media", &[]));
+        let mut sub = ClashConfig::default();
+        sub.proxies.push(proxy("B"));
+        sub.proxy_groups.push(
+            serde_yaml::from_str("{ name: Media, type: select, proxies: [B], icon: https://i/media.png, hidden: true }")
+                .unwrap(),
+        );
+        let merged = merge_configs(template, vec![sub]);
+        let base = ClashConfig {
+            proxy_groups: vec![selector_group("Media", &[]), selector_group("HK", &[])],
+            ..Default::default()
+        };
+        let mut merged = apply_base_config(merged, &base);
+        let icons = BTreeMap::from([
+            ("Media".to_string(), "https://i/other.png".to_string()),
+            ("HK".to_string(), "https://i/hk.png".to_string()),
+        ]);
+        assert_eq!(apply_group_icons(&mut merged, &icons), 1);
+
+        let media = &merged.proxy_groups[0];
+        assert_eq!(media["icon"].as_str(), Some("https://i/media.png"));
+        assert_eq!(media["hidden"].as_bool(), Some(true));
+        assert_eq!(
+            merged.proxy_groups[1]["icon"].as_str(),
+            Some("https://i/hk.png")
+        );
+    }
+
     #[test]
     fn test_merge_rules_append() {
         let template = ClashConfig {
@@ -322,6 +1101,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_merge_group_order_name_sorts_subscription_groups() {
+        let mut template = ClashConfig::default();
+        template.proxy_groups.push(selector_group("Zeta", &[]));
+
+        let mut sub = ClashConfig::default();
+        sub.proxy_groups.push(selector_group("Charlie", &[]));
+        sub.proxy_groups.push(selector_group("Alpha", &[]));
+
+        let options = MergeOptions {
+            group_order: GroupOrder::Name,
+            ..Default::default()
+        };
+        let merged = merge_configs_with_options(template, vec![sub], &options);
+        assert_eq!(merged.proxy_group_names(), vec!["Zeta", "Alpha", "Charlie"]);
+    }
+
+    #[test]
+    fn test_merge_group_order_explicit_list() {
+        let mut template = ClashConfig::default();
+        template.proxy_groups.push(selector_group("A", &[]));
+        template.proxy_groups.push(selector_group("B", &[]));
+
+        let mut sub = ClashConfig::default();
+        sub.proxy_groups.push(selector_group("C", &[]));
+        sub.proxy_groups.push(selector_group("D", &[]));
+
+        let options = MergeOptions {
+            group_order: GroupOrder::Explicit(vec!["D".to_string(), "B".to_string()]),
+            ..Default::default()
+        };
+        let merged = merge_configs_with_options(template, vec![sub], &options);
+        assert_eq!(merged.proxy_group_names(), vec!["D", "B", "A", "C"]);
+    }
+
     #[test]
     fn test_merge_empty_configs() {
         let template = ClashConfig::default();
@@ -433,4 +1247,426 @@ mod tests {
         // Proxies should be preserved
         assert_eq!(result.proxies.len(), 2);
     }
+
+    #[test]
+    fn test_merge_with_warnings_drops_unnamed_and_flags_duplicates() {
+        let template = ClashConfig::default();
+        let mut first = ClashConfig::default();
+        first.proxies.push(proxy("A"));
+        let mut second = ClashConfig::default();
+        second.proxies.push(proxy("A"));
+        second
+            .proxies
+            .push(serde_yaml::from_str("{ type: ss, server: x.example.com }").unwrap());
+
+        let mut warnings = Warnings::default();
+        let merged = merge_configs_with_warnings(
+            template,
+            vec![first, second],
+            &MergeOptions::default(),
+            &mut warnings,
+        );
+
+        assert_eq!(merged.proxy_names(), vec!["A", "A-2"]);
+        let kinds: Vec<_> = warnings.iter().map(|w| w.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![WarningKind::DroppedNode, WarningKind::DuplicateProxy]
+        );
+    }
+
+    #[test]
+    fn dedup_modes_drop_or_rename_across_subscriptions() {
+        let node = |name: &str, server: &str| -> Value {
+            serde_yaml::from_str(&format!(
+                "{{ name: \"{}\", type: ss, server: {}, port: 443, cipher: aes-128-gcm, password: x }}",
+                name, server
+            ))
+            .unwrap()
+        };
+        let subs = || {
+            let mut first = ClashConfig::default();
+            first.proxies.push(node("HK", "hk.example.com"));
+            first.proxies.push(node("JP", "jp.example.com"));
+            let mut second = ClashConfig::default();
+            second.proxies.push(node("香港 01", "HK.example.com"));
+            second.proxies.push(node("JP", "jp2.example.com"));
+            second
+                .proxy_groups
+                .push(selector_group("Second", &["香港 01", "JP"]));
+            vec![first, second]
+        };
+        let merge = |dedup| {
+            let options = MergeOptions {
+                dedup,
+                ..Default::default()
+            };
+            merge_configs_with_options(ClashConfig::default(), subs(), &options)
+        };
+        let members = |config: &ClashConfig| -> Vec<String> {
+            config.proxy_groups[0]["proxies"]
+                .as_sequence()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let merged = merge(ProxyDedup::Rename);
+        assert_eq!(merged.proxy_names(), vec!["HK", "JP", "香港 01", "JP-2"]);
+        assert_eq!(members(&merged), vec!["香港 01", "JP-2"]);
+
+        let merged = merge(ProxyDedup::Name);
+        assert_eq!(merged.proxy_names(), vec!["HK", "JP", "香港 01"]);
+        assert_eq!(members(&merged), vec!["香港 01", "JP"]);
+
+        let merged = merge(ProxyDedup::Endpoint);
+        assert_eq!(merged.proxy_names(), vec!["HK", "JP", "JP-2"]);
+        assert_eq!(members(&merged), vec!["HK", "JP-2"]);
+    }
+
+    #[test]
+    fn override_deep_merges_mappings_and_replaces_sequences() {
+        let merged: ClashConfig = serde_yaml::from_str(
+            "port: 7890\ntun:\n  enable: true\n  stack: system\nrules:\n  - MATCH,DIRECT\n",
+        )
+        .unwrap();
+        let overlay: Value = serde_yaml::from_str(
+            "interface-name: en0\ntun:\n  stack: gvisor\nrules:\n  - MATCH,Proxy\n",
+        )
+        .unwrap();
+
+        let result = apply_override(merged, &overlay).unwrap();
+        assert_eq!(result.port, Some(7890));
+        assert_eq!(
+            result.extra.get("interface-name").and_then(Value::as_str),
+            Some("en0")
+        );
+        let tun = result.extra.get("tun").and_then(Value::as_mapping).unwrap();
+        assert_eq!(tun.get("enable").and_then(Value::as_bool), Some(true));
+        assert_eq!(tun.get("stack").and_then(Value::as_str), Some("gvisor"));
+        assert_eq!(result.rules, vec!["MATCH,Proxy".to_string()]);
+    }
+
+    #[test]
+    fn merge_patch_prepends_appends_and_deep_merges() {
+        let merged = ClashConfig {
+            proxies: vec![proxy("a1")],
+            proxy_groups: vec![selector_group("Proxy", &["a1"])],
+            rules: vec!["MATCH,Proxy".to_string()],
+            ..Default::default()
+        };
+        let patch: Mapping = serde_yaml::from_str(
+            r#"
+prepend-rules:
+  - DOMAIN-SUFFIX,corp.example,DIRECT
+append-proxies:
+  - { name: home, type: socks5, server: 10.0.0.1, port: 1080 }
+prepend-proxy-groups:
+  - { name: Work, type: select, proxies: [home, DIRECT] }
+dns:
+  enable: true
+"#,
+        )
+        .unwrap();
+
+        let result = apply_merge_patch(merged, &patch).unwrap();
+        assert_eq!(
+            result.rules,
+            vec![
+                "DOMAIN-SUFFIX,corp.example,DIRECT".to_string(),
+                "MATCH,Proxy".to_string()
+            ]
+        );
+        assert_eq!(result.proxy_names(), vec!["a1", "home"]);
+        assert_eq!(result.proxy_group_names(), vec!["Work", "Proxy"]);
+        assert!(result.extra.contains_key("dns"));
+
+        let bad: Mapping = serde_yaml::from_str("append-rules: MATCH,DIRECT").unwrap();
+        assert!(apply_merge_patch(ClashConfig::default(), &bad).is_err());
+    }
+
+    #[test]
+    fn health_check_urls_apply_per_group_then_default() {
+        let template: ClashConfig = serde_yaml::from_str(
+            r#"
+proxy-groups:
+  - { name: Auto, type: url-test, url: "http://www.gstatic.com/generate_204", proxies: [A] }
+  - { name: Fallback, type: fallback, proxies: [A] }
+  - { name: 国内, type: select, proxies: [DIRECT] }
+  - { name: Manual, type: select, proxies: [A] }
+"#,
+        )
+        .unwrap();
+        let options = MergeOptions {
+            health_checks: HealthCheckUrls {
+                default: Some("https://cp.cloudflare.com/generate_204".to_string()),
+                groups: BTreeMap::from([(
+                    "国内".to_string(),
+                    "http://connect.rom.miui.com/generate_204".to_string(),
+                )]),
+            },
+            ..Default::default()
+        };
+        let merged = merge_configs_with_options(template, Vec::new(), &options);
+        let url = |idx: usize| merged.proxy_groups[idx].get("url").cloned();
+        assert_eq!(
+            url(0),
+            Some(Value::from("https://cp.cloudflare.com/generate_204"))
+        );
+        assert_eq!(
+            url(1),
+            Some(Value::from("https://cp.cloudflare.com/generate_204"))
+        );
+        assert_eq!(
+            url(2),
+            Some(Value::from("http://connect.rom.miui.com/generate_204"))
+        );
+        assert_eq!(url(3), None);
+    }
+
+    #[test]
+    fn merge_directives_in_extra_are_resolved() {
+        let config = ClashConfig::from_yaml_str(
+            r#"
+proxies:
+  - { name: a1, type: ss, server: a.example.com, port: 443, cipher: aes-128-gcm, password: x }
+proxy-groups:
+  - { name: Proxy, type: select, proxies: [a1] }
+rules:
+  - MATCH,Proxy
+prepend-rules:
+  - DOMAIN-SUFFIX,corp.example,DIRECT
+append-proxy-groups:
+  - { name: Fallback, type: select, proxies: [Proxy, DIRECT] }
+"#,
+        )
+        .unwrap();
+
+        let resolved = apply_merge_directives(config).unwrap();
+        assert_eq!(resolved.rules[0], "DOMAIN-SUFFIX,corp.example,DIRECT");
+        assert_eq!(resolved.proxy_group_names(), vec!["Proxy", "Fallback"]);
+        assert!(!resolved.extra.contains_key("prepend-rules"));
+        assert!(!resolved
+            .to_yaml_string()
+            .unwrap()
+            .contains("append-proxy-groups"));
+    }
+
+    #[test]
+    fn combined_merge_patches_match_sequential_application() {
+        let base = ClashConfig {
+            proxies: vec![proxy("a1")],
+            proxy_groups: vec![selector_group("Proxy", &["a1"])],
+            rules: vec!["MATCH,Proxy".to_string()],
+            ..Default::default()
+        };
+        let first: Mapping = serde_yaml::from_str(
+            "prepend-rules: [\"DOMAIN,a.example,DIRECT\"]\nappend-rules: [\"GEOIP,CN,DIRECT\"]\ndns: { enable: true, ipv6: false }",
+        )
+        .unwrap();
+        let second: Mapping = serde_yaml::from_str(
+            "prepend-rules: [\"DOMAIN,b.example,DIRECT\"]\nappend-proxies: [{ name: home, type: socks5, server: 10.0.0.1, port: 1080 }]\ndns: { ipv6: true }",
+        )
+        .unwrap();
+
+        let sequential =
+            apply_merge_patch(apply_merge_patch(base.clone(), &first).unwrap(), &second).unwrap();
+        let combined = combine_merge_patches(&[first, second]);
+        assert_eq!(apply_merge_patch(base, &combined).unwrap(), sequential);
+        assert_eq!(sequential.rules[0], "DOMAIN,b.example,DIRECT");
+    }
+
+    #[test]
+    fn provider_groups_are_added_and_listed_in_main_selector() {
+        let mut config = ClashConfig {
+            proxies: vec![proxy("a1"), proxy("a2"), proxy("b1")],
+            proxy_groups: vec![selector_group("Proxy", &["a1", "a2", "b1"])],
+            ..Default::default()
+        };
+        let providers = vec![
+            ProviderNodes {
+                name: "Provider A".into(),
+                proxies: vec!["a1".into(), "a2".into()],
+            },
+            ProviderNodes {
+                name: "Proxy".into(),
+                proxies: vec!["b1".into()],
+            },
+            ProviderNodes {
+                name: "Empty".into(),
+                proxies: Vec::new(),
+            },
+        ];
+        let mut warnings = Warnings::default();
+
+        let added = add_provider_groups(
+            &mut config,
+            &providers,
+            ProviderGroupKind::UrlTest,
+            "Proxy",
+            &mut warnings,
+        );
+
+        assert_eq!(added, 1);
+        assert_eq!(config.proxy_group_names(), vec!["Proxy", "Provider A"]);
+        let provider = config.proxy_groups[1].as_mapping().unwrap();
+        assert_eq!(
+            provider.get("type").and_then(Value::as_str),
+            Some("url-test")
+        );
+        let main: Vec<&str> = config.proxy_groups[0]
+            .get("proxies")
+            .and_then(Value::as_sequence)
+            .unwrap()
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(main, vec!["Provider A", "a1", "a2", "b1"]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings.as_slice()[0].kind, WarningKind::DuplicateGroup);
+    }
+
+    #[test]
+    fn rule_target_skips_trailing_options() {
+        assert_eq!(rule_target("MATCH,Proxy"), Some("Proxy"));
+        assert_eq!(
+            rule_target("IP-CIDR,10.0.0.0/8,DIRECT,no-resolve"),
+            Some("DIRECT")
+        );
+        assert_eq!(
+            rule_target("AND,((DOMAIN,a.com),(NETWORK,UDP)),REJECT"),
+            Some("REJECT")
+        );
+        assert_eq!(rule_target("MATCH"), None);
+    }
+
+    #[test]
+    fn prune_removes_empty_groups_transitively() {
+        let mut config: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - { name: a1, type: http, server: s, port: 1 }
+proxy-groups:
+  - { name: Proxy, type: select, proxies: [HK, a1] }
+  - { name: HK, type: select, proxies: [HK-auto] }
+  - { name: HK-auto, type: url-test, proxies: [gone] }
+  - { name: Provider, type: select, use: [remote] }
+  - { name: Direct-only, type: select, proxies: [DIRECT] }
+rules:
+  - DOMAIN-SUFFIX,hk.example.com,HK
+  - IP-CIDR,10.0.0.0/8,HK-auto,no-resolve
+  - MATCH,Proxy
+"#,
+        )
+        .unwrap();
+
+        let pruned = prune_empty_groups(&mut config, "Proxy");
+
+        assert_eq!(pruned, vec!["HK-auto".to_string(), "HK".to_string()]);
+        assert_eq!(
+            config.proxy_group_names(),
+            vec!["Proxy", "Provider", "Direct-only"]
+        );
+        assert_eq!(
+            config.rules,
+            vec![
+                "DOMAIN-SUFFIX,hk.example.com,Proxy".to_string(),
+                "IP-CIDR,10.0.0.0/8,Proxy,no-resolve".to_string(),
+                "MATCH,Proxy".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn provider_backed_groups_are_not_force_filled() {
+        let provider_selector: Value = serde_yaml::from_str(&format!(
+            "name: \"{}\"\ntype: select\nuse: [remote]\nproxies: [Auto]\n",
+            DEFAULT_SELECTOR_NAME
+        ))
+        .unwrap();
+        let template = ClashConfig {
+            proxy_groups: vec![provider_selector],
+            ..Default::default()
+        };
+        let sub = ClashConfig {
+            proxies: vec![proxy("node")],
+            ..Default::default()
+        };
+        let merged = merge_configs(template, vec![sub]);
+        let group = merged.proxy_groups[0].as_mapping().unwrap();
+        assert_eq!(
+            group.get("proxies"),
+            Some(&Value::Sequence(vec![Value::from("Auto")]))
+        );
+
+        let base = ClashConfig {
+            proxy_groups: vec![serde_yaml::from_str(
+                "name: All\ntype: select\ninclude-all: true\nproxies: [DIRECT]\n",
+            )
+            .unwrap()],
+            ..Default::default()
+        };
+        let rebuilt = apply_base_config(merged, &base);
+        let group = rebuilt.proxy_groups[0].as_mapping().unwrap();
+        assert_eq!(
+            group.get("proxies"),
+            Some(&Value::Sequence(vec![Value::from("DIRECT")]))
+        );
+    }
+
+    #[test]
+    fn base_sections_limit_what_is_inherited() {
+        let merged: ClashConfig = serde_yaml::from_str(
+            r#"
+port: 7890
+mode: rule
+dns: { enable: false }
+proxies:
+  - { name: a, type: http, server: s, port: 1 }
+proxy-groups:
+  - { name: Template, type: select, proxies: [a] }
+rules:
+  - MATCH,Template
+"#,
+        )
+        .unwrap();
+        let base: ClashConfig = serde_yaml::from_str(
+            r#"
+mixed-port: 7897
+mode: global
+dns: { enable: true }
+proxy-groups:
+  - { name: Base, type: select, proxies: [] }
+rules:
+  - MATCH,Base
+"#,
+        )
+        .unwrap();
+
+        let result = apply_base_config_sections(
+            merged.clone(),
+            &base,
+            &[BaseSection::Ports, BaseSection::Dns],
+        );
+        assert_eq!(result.port, None);
+        assert_eq!(
+            result.extra.get("mixed-port").and_then(Value::as_u64),
+            Some(7897)
+        );
+        assert_eq!(
+            result.extra.get("mode").and_then(Value::as_str),
+            Some("rule")
+        );
+        assert_eq!(
+            result.extra["dns"].get("enable").and_then(Value::as_bool),
+            Some(true)
+        );
+        assert_eq!(result.proxy_group_names(), vec!["Template"]);
+        assert_eq!(result.rules, vec!["MATCH,Template".to_string()]);
+
+        let result = apply_base_config_sections(merged, &base, &[BaseSection::Rules]);
+        assert_eq!(result.port, Some(7890));
+        assert_eq!(result.rules, vec!["MATCH,Base".to_string()]);
+    }
 }
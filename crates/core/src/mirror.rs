@@ -0,0 +1,191 @@
+//! Mirrors for GitHub downloads (geodata, release binaries).
+//!
+//! A mirror is a URL template. `{url}` is the original URL, so `{url}` alone means GitHub
+//! itself and `https://ghproxy.net/{url}` a prefix proxy. Release assets also provide
+//! `{owner}`, `{repo}`, `{tag}` and `{asset}` for CDNs that serve a repository's `release`
+//! branch (jsDelivr). Mirrors are probed in order and the first one that answers within
+//! [`MIRROR_PROBE_TIMEOUT`] wins; callers remember the winner and try it first next time.
+//!
+//! Geodata can also come from `resource_mirrors` / `--resource-mirror`, which accept a plain
+//! base URL (`https://mirror.corp/geo`) that holds the files under their asset names.
+
+use std::time::Duration;
+
+use url::Url;
+
+/// Mirrors tried when `github_mirrors` in app.yaml is empty.
+pub const DEFAULT_GITHUB_MIRRORS: &[&str] = &[
+    "{url}",
+    "https://ghproxy.net/{url}",
+    "https://cdn.jsdelivr.net/gh/{owner}/{repo}@release/{asset}",
+    "https://fastly.jsdelivr.net/gh/{owner}/{repo}@release/{asset}",
+];
+
+/// How long a mirror gets to answer the probe.
+pub const MIRROR_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// `github.com/<owner>/<repo>/releases/download/<tag>/<asset>` (or `releases/latest/download`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseAsset {
+    pub owner: String,
+    pub repo: String,
+    pub tag: String,
+    pub asset: String,
+}
+
+impl ReleaseAsset {
+    pub fn parse(url: &str) -> Option<Self> {
+        let url = Url::parse(url).ok()?;
+        if url.host_str() != Some("github.com") {
+            return None;
+        }
+        let segments: Vec<&str> = url.path_segments()?.collect();
+        let (owner, repo, tag, asset) = match segments.as_slice() {
+            [owner, repo, "releases", "download", tag, asset] => (owner, repo, *tag, asset),
+            [owner, repo, "releases", "latest", "download", asset] => {
+                (owner, repo, "latest", asset)
+            }
+            _ => return None,
+        };
+        Some(Self {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            tag: tag.to_string(),
+            asset: asset.to_string(),
+        })
+    }
+}
+
+/// Whether downloads from `url` can go through a mirror.
+pub fn is_github_url(url: &str) -> bool {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host == "github.com"))
+        .unwrap_or(false)
+}
+
+/// `url` rewritten for `mirror`, or `None` when the template needs release-asset placeholders
+/// and `url` is not a release asset.
+pub fn mirror_url(mirror: &str, url: &str) -> Option<String> {
+    let mut expanded = mirror.replace("{url}", url);
+    if ["{owner}", "{repo}", "{tag}", "{asset}"]
+        .iter()
+        .any(|key| expanded.contains(key))
+    {
+        let asset = ReleaseAsset::parse(url)?;
+        expanded = expanded
+            .replace("{owner}", &asset.owner)
+            .replace("{repo}", &asset.repo)
+            .replace("{tag}", &asset.tag)
+            .replace("{asset}", &asset.asset);
+    }
+    Some(expanded)
+}
+
+/// Template for a geodata mirror: a value without placeholders is a base URL the asset name
+/// is appended to.
+pub fn resource_mirror_template(raw: &str) -> String {
+    let raw = raw.trim();
+    if raw.contains('{') {
+        raw.to_string()
+    } else {
+        format!("{}/{{asset}}", raw.trim_end_matches('/'))
+    }
+}
+
+/// Probe `mirrors` (with `preferred` first) using `probe_url` and return the first mirror
+/// that answers a HEAD request with a success status. `None` when `probe_url` is not on
+/// GitHub or no mirror answered.
+pub async fn pick_mirror(
+    client: &reqwest::Client,
+    mirrors: &[String],
+    preferred: Option<&str>,
+    probe_url: &str,
+) -> Option<String> {
+    if !is_github_url(probe_url) {
+        return None;
+    }
+    let mut order: Vec<&str> = Vec::new();
+    if let Some(preferred) = preferred.filter(|preferred| mirrors.iter().any(|m| m == preferred)) {
+        order.push(preferred);
+    }
+    order.extend(
+        mirrors
+            .iter()
+            .map(String::as_str)
+            .filter(|mirror| Some(*mirror) != preferred),
+    );
+
+    for mirror in order {
+        let Some(candidate) = mirror_url(mirror, probe_url) else {
+            continue;
+        };
+        let probe = client
+            .head(&candidate)
+            .timeout(MIRROR_PROBE_TIMEOUT)
+            .send()
+            .await;
+        match probe {
+            Ok(response) if response.status().is_success() => {
+                tracing::debug!(mirror, "mirror answered");
+                return Some(mirror.to_string());
+            }
+            Ok(response) => {
+                tracing::debug!(mirror, status = %response.status(), "mirror rejected probe")
+            }
+            Err(err) => tracing::debug!(mirror, error = %err, "mirror probe failed"),
+        }
+    }
+    None
+}
+
+/// Mirrors from app.yaml, or [`DEFAULT_GITHUB_MIRRORS`] when none are configured.
+pub fn configured_mirrors(configured: &[String]) -> Vec<String> {
+    if configured.is_empty() {
+        DEFAULT_GITHUB_MIRRORS
+            .iter()
+            .map(|mirror| mirror.to_string())
+            .collect()
+    } else {
+        configured.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirror_templates_expand_release_assets() {
+        let url = "https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/geoip.dat";
+        assert_eq!(mirror_url("{url}", url).unwrap(), url);
+        assert_eq!(
+            mirror_url("https://ghproxy.net/{url}", url).unwrap(),
+            format!("https://ghproxy.net/{url}")
+        );
+        assert_eq!(
+            mirror_url(DEFAULT_GITHUB_MIRRORS[2], url).unwrap(),
+            "https://cdn.jsdelivr.net/gh/MetaCubeX/meta-rules-dat@release/geoip.dat"
+        );
+
+        let archive = "https://github.com/MetaCubeX/metacubexd/archive/refs/heads/gh-pages.zip";
+        assert!(ReleaseAsset::parse(archive).is_none());
+        assert!(mirror_url(DEFAULT_GITHUB_MIRRORS[2], archive).is_none());
+        assert!(mirror_url(DEFAULT_GITHUB_MIRRORS[1], archive).is_some());
+        assert!(!is_github_url("https://example.com/geoip.dat"));
+        assert_eq!(
+            mirror_url(&resource_mirror_template("https://mirror.corp/geo/"), url).unwrap(),
+            "https://mirror.corp/geo/geoip.dat"
+        );
+        assert_eq!(
+            resource_mirror_template("https://ghproxy.net/{url}"),
+            "https://ghproxy.net/{url}"
+        );
+        assert_eq!(
+            ReleaseAsset::parse("https://github.com/o/r/releases/latest/download/a.gz")
+                .unwrap()
+                .tag,
+            "latest"
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use indexmap::IndexMap;
 
 use serde::{Deserialize, Serialize};
-use serde_yaml::Value;
+use serde_yaml::{Mapping, Value};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct ClashConfig {
@@ -72,4 +72,72 @@ impl ClashConfig {
             })
             .collect()
     }
+
+    /// Sort the keys of every proxy and proxy-group entry (`name` and `type` first, the rest
+    /// alphabetically, recursing into nested mappings). Providers emit fields in arbitrary
+    /// order, so this keeps output stable for identical node sets. Top-level key order and the
+    /// order of list entries are left untouched.
+    pub fn canonicalize_entries(&mut self) {
+        for entry in self.proxies.iter_mut().chain(self.proxy_groups.iter_mut()) {
+            canonicalize_value(entry);
+        }
+    }
+}
+
+fn canonicalize_value(value: &mut Value) {
+    match value {
+        Value::Mapping(map) => {
+            let mut entries: Vec<(Value, Value)> = std::mem::take(map).into_iter().collect();
+            entries.sort_by_key(|(key, _)| entry_key_rank(key));
+            let mut sorted = Mapping::with_capacity(entries.len());
+            for (key, mut val) in entries {
+                canonicalize_value(&mut val);
+                sorted.insert(key, val);
+            }
+            *map = sorted;
+        }
+        Value::Sequence(seq) => seq.iter_mut().for_each(canonicalize_value),
+        _ => {}
+    }
+}
+
+fn entry_key_rank(key: &Value) -> (u8, String) {
+    let text = match key {
+        Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other).unwrap_or_default(),
+    };
+    let rank = match text.as_str() {
+        "name" => 0,
+        "type" => 1,
+        _ => 2,
+    };
+    (rank, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalize_entries_orders_keys() {
+        let mut config = ClashConfig::from_yaml_str(
+            r#"
+proxies:
+  - server: a.example.com
+    type: ss
+    port: 443
+    name: A
+    plugin-opts: { mode: websocket, host: h }
+"#,
+        )
+        .unwrap();
+
+        config.canonicalize_entries();
+
+        let yaml = serde_yaml::to_string(&config.proxies).unwrap();
+        assert_eq!(
+            yaml,
+            "- name: A\n  type: ss\n  plugin-opts:\n    host: h\n    mode: websocket\n  port: 443\n  server: a.example.com\n"
+        );
+    }
 }
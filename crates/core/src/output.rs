@@ -1,36 +1,624 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
 
+use anyhow::Context;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::controller::ControllerClient;
+
+/// Provenance details rendered as a comment header at the top of a generated config.
+#[derive(Debug, Clone, Default)]
+pub struct GenerationInfo {
+    /// Omitted from the header when `None` so identical inputs render identically.
+    pub timestamp: Option<DateTime<Utc>>,
+    pub tool_version: String,
+    pub template: String,
+    pub subscriptions: Vec<String>,
+}
+
+impl GenerationInfo {
+    pub fn render_header(&self) -> String {
+        let mut header = String::new();
+        header.push_str(&format!(
+            "# Generated by mihomo-cli {}\n",
+            self.tool_version
+        ));
+        if let Some(ts) = self.timestamp {
+            header.push_str(&format!(
+                "# Generated at: {}\n",
+                ts.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            ));
+        }
+        header.push_str(&format!("# Template: {}\n", self.template));
+        if self.subscriptions.is_empty() {
+            header.push_str("# Subscriptions: <none>\n");
+        } else {
+            header.push_str("# Subscriptions:\n");
+            for name in &self.subscriptions {
+                header.push_str(&format!("#   - {}\n", name.replace('\n', " ")));
+            }
+        }
+        header
+    }
+}
+
+/// Prepend the generation header to a serialized config.
+pub fn with_header(yaml: &str, info: &GenerationInfo) -> String {
+    format!("{}{}", info.render_header(), yaml)
+}
+
+/// The config without the leading comment block added by [`with_header`], for comparing a
+/// previously written file with a freshly serialized config.
+pub fn strip_header(yaml: &str) -> &str {
+    let mut rest = yaml;
+    while rest.starts_with('#') {
+        rest = rest.split_once('\n').map_or("", |(_, tail)| tail);
+    }
+    rest
+}
+
+/// Append a trailing comment (e.g. `89ms`) to the first line of each entry of the top-level
+/// `proxies:` list in serialized YAML. `notes[i]` belongs to the i-th proxy; `None` leaves
+/// the entry alone.
+pub fn annotate_proxies(yaml: &str, notes: &[Option<String>]) -> String {
+    let mut out = String::with_capacity(yaml.len());
+    let mut in_proxies = false;
+    let mut index = 0usize;
+    for line in yaml.split_inclusive('\n') {
+        let body = line.trim_end_matches('\n');
+        if is_top_level_key(body) {
+            in_proxies = body == "proxies:";
+        } else if in_proxies && body.starts_with("- ") {
+            if let Some(Some(note)) = notes.get(index) {
+                out.push_str(body);
+                out.push_str("  # ");
+                out.push_str(note);
+                out.push_str(&line[body.len()..]);
+                index += 1;
+                continue;
+            }
+            index += 1;
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+/// Undo [`annotate_proxies`] for the `NNms`/`timeout` notes written by `merge
+/// --annotate-latency comment`, so annotated output can be compared with a fresh config.
+pub fn strip_proxy_annotations(yaml: &str) -> String {
+    let mut out = String::with_capacity(yaml.len());
+    let mut in_proxies = false;
+    for line in yaml.split_inclusive('\n') {
+        let body = line.trim_end_matches('\n');
+        if is_top_level_key(body) {
+            in_proxies = body == "proxies:";
+        } else if in_proxies && body.starts_with("- ") {
+            if let Some((head, note)) = body.rsplit_once("  # ") {
+                let latency = note
+                    .strip_suffix("ms")
+                    .is_some_and(|ms| !ms.is_empty() && ms.bytes().all(|b| b.is_ascii_digit()));
+                if latency || note == "timeout" {
+                    out.push_str(head);
+                    out.push_str(&line[body.len()..]);
+                    continue;
+                }
+            }
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+fn is_top_level_key(line: &str) -> bool {
+    !line.is_empty() && !line.starts_with([' ', '-', '#'])
+}
 
 #[async_trait]
 pub trait ConfigDeployer {
     async fn deploy(&self, yaml: &str) -> anyhow::Result<()>;
 }
 
+/// Writes the config to a local file atomically (temp file + rename), optionally keeping
+/// timestamped backups of the file it replaces.
 pub struct FileDeployer {
     pub path: PathBuf,
+    /// Number of `<file>.<timestamp>.bak` copies of previous versions to keep (0 = none).
+    pub keep_backups: usize,
+}
+
+impl FileDeployer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            keep_backups: 0,
+        }
+    }
+
+    fn file_name(&self) -> anyhow::Result<String> {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| anyhow::anyhow!("invalid output path {}", self.path.display()))
+    }
+
+    /// Copy the current file to `<file>.<timestamp>.bak` and delete the oldest backups beyond
+    /// `keep_backups`.
+    async fn rotate_backups(&self) -> anyhow::Result<()> {
+        if self.keep_backups == 0 || !fs::try_exists(&self.path).await.unwrap_or(false) {
+            return Ok(());
+        }
+        let file_name = self.file_name()?;
+        let stamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+        let backup = self
+            .path
+            .with_file_name(format!("{}.{}.bak", file_name, stamp));
+        fs::copy(&self.path, &backup).await.with_context(|| {
+            format!(
+                "failed to back up {} to {}",
+                self.path.display(),
+                backup.display()
+            )
+        })?;
+
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let prefix = format!("{}.", file_name);
+        let mut backups = Vec::new();
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_backup = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".bak"))
+                .is_some_and(|stamp| stamp.len() == 20 && stamp.ends_with('Z'));
+            if is_backup {
+                backups.push(name);
+            }
+        }
+        // Timestamps sort lexicographically; newest last.
+        backups.sort();
+        let excess = backups.len().saturating_sub(self.keep_backups);
+        for name in backups.into_iter().take(excess) {
+            fs::remove_file(dir.join(&name)).await?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl ConfigDeployer for FileDeployer {
     async fn deploy(&self, yaml: &str) -> anyhow::Result<()> {
+        crate::storage::ensure_writable(&self.path)?;
         if let Some(parent) = self.path.parent() {
             fs::create_dir_all(parent).await?;
         }
-        fs::write(&self.path, yaml).await?;
-        Ok(())
+        self.rotate_backups().await?;
+        crate::storage::write_atomic(&self.path, yaml.as_bytes()).await
     }
 }
 
+/// Pushes the config to a running mihomo through its external controller
+/// (`PUT /configs?force=true` with the YAML as `payload`), so it also works for remote hosts
+/// that cannot read our output path.
 pub struct HttpDeployer {
+    /// Controller base URL, e.g. `http://127.0.0.1:9090`, `https://router:9443` or
+    /// `unix:///var/run/mihomo.sock`.
     pub endpoint: String,
     pub secret: Option<String>,
+    /// Extra CA (PEM) trusted for an `https://` endpoint with a self-signed certificate.
+    pub ca_cert: Option<PathBuf>,
 }
 
 #[async_trait]
 impl ConfigDeployer for HttpDeployer {
-    async fn deploy(&self, _yaml: &str) -> anyhow::Result<()> {
-        anyhow::bail!("HTTP deployer not implemented yet: {}", self.endpoint);
+    async fn deploy(&self, yaml: &str) -> anyhow::Result<()> {
+        let client = ControllerClient::with_ca_cert(
+            &self.endpoint,
+            self.secret.as_deref(),
+            self.ca_cert.as_deref(),
+        )?;
+        client
+            .put_config(yaml)
+            .await
+            .context("mihomo rejected the config")
+    }
+}
+
+/// Copies the config to a remote host over `ssh` (streamed through stdin and moved into place
+/// atomically) and optionally runs a reload command there, e.g. for a router running mihomo.
+pub struct SshDeployer {
+    /// `[user@]host`, anything `ssh` accepts (including `~/.ssh/config` aliases).
+    pub destination: String,
+    pub remote_path: String,
+    pub port: Option<u16>,
+    pub identity: Option<PathBuf>,
+    /// Shell command run on the remote host after the file is in place.
+    pub reload_command: Option<String>,
+}
+
+impl SshDeployer {
+    /// Split an scp-style `[user@]host:/path` target.
+    pub fn parse_target(spec: &str) -> anyhow::Result<(String, String)> {
+        let split_at = match spec.find('[') {
+            // [v6::addr]:/path or user@[v6::addr]:/path
+            Some(open) => spec[open..].find("]:").map(|close| open + close + 1),
+            None => spec.find(':'),
+        };
+        match split_at {
+            Some(idx) if idx > 0 && idx + 1 < spec.len() => {
+                let host = spec[..idx].replace(['[', ']'], "");
+                Ok((host, spec[idx + 1..].to_string()))
+            }
+            _ => anyhow::bail!("expected [user@]host:/remote/path, got '{}'", spec),
+        }
+    }
+
+    fn remote_command(&self) -> String {
+        let path = shell_quote(&self.remote_path);
+        let tmp = shell_quote(&format!("{}.tmp", self.remote_path));
+        let mut command = format!(
+            "mkdir -p \"$(dirname {path})\" && cat > {tmp} && mv {tmp} {path}",
+            path = path,
+            tmp = tmp
+        );
+        if let Some(reload) = self
+            .reload_command
+            .as_deref()
+            .filter(|c| !c.trim().is_empty())
+        {
+            command.push_str(" && ");
+            command.push_str(reload);
+        }
+        command
+    }
+
+    fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+        if let Some(port) = self.port {
+            args.push("-p".to_string());
+            args.push(port.to_string());
+        }
+        if let Some(identity) = &self.identity {
+            args.push("-i".to_string());
+            args.push(identity.display().to_string());
+        }
+        args.push(self.destination.clone());
+        args.push(self.remote_command());
+        args
+    }
+}
+
+#[async_trait]
+impl ConfigDeployer for SshDeployer {
+    async fn deploy(&self, yaml: &str) -> anyhow::Result<()> {
+        let mut child = Command::new("ssh")
+            .args(self.ssh_args())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run ssh (is OpenSSH installed?)")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(yaml.as_bytes()).await?;
+        }
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "ssh deploy to {}:{} failed ({}): {}",
+                self.destination,
+                self.remote_path,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// How to make a running mihomo pick up a freshly written config (`merge --reload`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadTarget {
+    /// `systemd:<unit>` / `systemd-user:<unit>`: `systemctl [--user] reload <unit>`.
+    Systemd { unit: String, user: bool },
+    /// `launchd:<label>`: `launchctl kickstart -k`; a bare label means `system/<label>`.
+    Launchd(String),
+    /// `signal:<pid-file>`: send SIGHUP to the pid stored in the file.
+    Signal(PathBuf),
+    /// `api` / `api:<url>`: `PUT /configs` on the external controller with the new path.
+    /// Without a URL the controller of the merged config is used.
+    Api(Option<String>),
+}
+
+impl std::str::FromStr for ReloadTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> anyhow::Result<Self> {
+        let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+        let arg = arg.trim();
+        let target = match kind {
+            "systemd" | "systemd-user" if !arg.is_empty() => ReloadTarget::Systemd {
+                unit: arg.to_string(),
+                user: kind == "systemd-user",
+            },
+            "launchd" if !arg.is_empty() => ReloadTarget::Launchd(if arg.contains('/') {
+                arg.to_string()
+            } else {
+                format!("system/{}", arg)
+            }),
+            "signal" if !arg.is_empty() => ReloadTarget::Signal(PathBuf::from(arg)),
+            "api" => ReloadTarget::Api((!arg.is_empty()).then(|| arg.to_string())),
+            _ => anyhow::bail!(
+                "invalid reload target '{}' (expected systemd:<unit>, systemd-user:<unit>, \
+                 launchd:<label>, signal:<pid-file>, api or api:<url>)",
+                spec
+            ),
+        };
+        Ok(target)
+    }
+}
+
+impl ReloadTarget {
+    /// Reload mihomo after `config_path` was written. `controller` is the endpoint/secret of
+    /// the merged config, used by `api` when no URL was given.
+    pub async fn reload(
+        &self,
+        config_path: &Path,
+        controller: Option<(&str, Option<&str>)>,
+    ) -> anyhow::Result<()> {
+        match self {
+            ReloadTarget::Systemd { unit, user } => {
+                let mut args = Vec::new();
+                if *user {
+                    args.push("--user");
+                }
+                args.extend(["reload", unit.as_str()]);
+                run_reload_command("systemctl", &args).await
+            }
+            ReloadTarget::Launchd(label) => {
+                run_reload_command("launchctl", &["kickstart", "-k", label]).await
+            }
+            ReloadTarget::Signal(pid_file) => {
+                let raw = fs::read_to_string(pid_file)
+                    .await
+                    .with_context(|| format!("failed to read pid file {}", pid_file.display()))?;
+                let pid: u32 = raw.trim().parse().with_context(|| {
+                    format!("pid file {} does not contain a pid", pid_file.display())
+                })?;
+                run_reload_command("kill", &["-HUP", &pid.to_string()]).await
+            }
+            ReloadTarget::Api(endpoint) => {
+                let (endpoint, secret) = match (endpoint.as_deref(), controller) {
+                    (Some(endpoint), controller) => (endpoint, controller.and_then(|c| c.1)),
+                    (None, Some(controller)) => controller,
+                    (None, None) => anyhow::bail!(
+                        "--reload api needs an external-controller in the config or api:<url>"
+                    ),
+                };
+                let client = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(30))
+                    .build()?;
+                let mut request = client
+                    .put(format!(
+                        "{}/configs?force=true",
+                        endpoint.trim_end_matches('/')
+                    ))
+                    .json(&serde_json::json!({ "path": config_path.display().to_string() }));
+                if let Some(secret) = secret.filter(|s| !s.is_empty()) {
+                    request = request.bearer_auth(secret);
+                }
+                let response = request
+                    .send()
+                    .await
+                    .with_context(|| format!("failed to reach external controller {}", endpoint))?;
+                if !response.status().is_success() {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    anyhow::bail!(
+                        "external controller {} refused to reload: {} {}",
+                        endpoint,
+                        status,
+                        body.trim()
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn run_reload_command(program: &str, args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("failed to run {}", program))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`{} {}` failed ({}): {}",
+            program,
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Quote a string for a POSIX shell.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn proxy_annotations_round_trip() {
+        let yaml = "proxies:\n- name: HK 01\n  type: ss\n- name: JP 01\n  type: ss\n- name: US 01\n  type: ss\nproxy-groups:\n- name: PROXY\n  proxies:\n  - HK 01\n";
+        let notes = vec![Some("89ms".to_string()), None, Some("timeout".to_string())];
+        let annotated = annotate_proxies(yaml, &notes);
+        assert_eq!(
+            annotated,
+            "proxies:\n- name: HK 01  # 89ms\n  type: ss\n- name: JP 01\n  type: ss\n- name: US 01  # timeout\n  type: ss\nproxy-groups:\n- name: PROXY\n  proxies:\n  - HK 01\n"
+        );
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&annotated).unwrap();
+        assert_eq!(
+            parsed["proxies"][0]["name"],
+            serde_yaml::Value::from("HK 01")
+        );
+        assert_eq!(strip_proxy_annotations(&annotated), yaml);
+    }
+
+    #[test]
+    fn reload_targets_parse() {
+        assert_eq!(
+            "systemd:mihomo.service".parse::<ReloadTarget>().unwrap(),
+            ReloadTarget::Systemd {
+                unit: "mihomo.service".to_string(),
+                user: false
+            }
+        );
+        assert_eq!(
+            "launchd:io.mihomo".parse::<ReloadTarget>().unwrap(),
+            ReloadTarget::Launchd("system/io.mihomo".to_string())
+        );
+        assert_eq!(
+            "signal:/run/mihomo.pid".parse::<ReloadTarget>().unwrap(),
+            ReloadTarget::Signal(PathBuf::from("/run/mihomo.pid"))
+        );
+        assert_eq!(
+            "api".parse::<ReloadTarget>().unwrap(),
+            ReloadTarget::Api(None)
+        );
+        assert_eq!(
+            "api:http://127.0.0.1:9090".parse::<ReloadTarget>().unwrap(),
+            ReloadTarget::Api(Some("http://127.0.0.1:9090".to_string()))
+        );
+        assert!("systemd:".parse::<ReloadTarget>().is_err());
+        assert!("restart".parse::<ReloadTarget>().is_err());
+    }
+
+    #[tokio::test]
+    async fn signal_reload_rejects_bad_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("mihomo.pid");
+        std::fs::write(&pid_file, "not-a-pid\n").unwrap();
+        let err = ReloadTarget::Signal(pid_file)
+            .reload(Path::new("/tmp/config.yaml"), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("does not contain a pid"));
+    }
+
+    #[test]
+    fn header_lists_template_and_subscriptions() {
+        let info = GenerationInfo {
+            timestamp: Some(Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap()),
+            tool_version: "0.1.0".to_string(),
+            template: "cvr_template.yaml".to_string(),
+            subscriptions: vec!["example.com".to_string()],
+        };
+
+        let out = with_header("port: 7890\n", &info);
+        assert_eq!(strip_header(&out), "port: 7890\n");
+        assert_eq!(
+            out,
+            "# Generated by mihomo-cli 0.1.0\n\
+             # Generated at: 2025-01-02T03:04:05Z\n\
+             # Template: cvr_template.yaml\n\
+             # Subscriptions:\n\
+             #   - example.com\n\
+             port: 7890\n"
+        );
+    }
+
+    #[test]
+    fn ssh_target_parsing_and_remote_command() {
+        assert_eq!(
+            SshDeployer::parse_target("root@router:/etc/mihomo/config.yaml").unwrap(),
+            (
+                "root@router".to_string(),
+                "/etc/mihomo/config.yaml".to_string()
+            )
+        );
+        assert_eq!(
+            SshDeployer::parse_target("admin@[fd00::1]:/tmp/c.yaml").unwrap(),
+            ("admin@fd00::1".to_string(), "/tmp/c.yaml".to_string())
+        );
+        assert!(SshDeployer::parse_target("router").is_err());
+        assert!(SshDeployer::parse_target("router:").is_err());
+
+        let deployer = SshDeployer {
+            destination: "router".to_string(),
+            remote_path: "/etc/mihomo/it's.yaml".to_string(),
+            port: Some(2222),
+            identity: None,
+            reload_command: Some("/etc/init.d/mihomo reload".to_string()),
+        };
+        let args = deployer.ssh_args();
+        assert_eq!(&args[..5], ["-o", "BatchMode=yes", "-p", "2222", "router"]);
+        assert_eq!(
+            args[5],
+            "mkdir -p \"$(dirname '/etc/mihomo/it'\\''s.yaml')\" && \
+             cat > '/etc/mihomo/it'\\''s.yaml.tmp' && \
+             mv '/etc/mihomo/it'\\''s.yaml.tmp' '/etc/mihomo/it'\\''s.yaml' && \
+             /etc/init.d/mihomo reload"
+        );
+    }
+
+    #[tokio::test]
+    async fn file_deployer_rotates_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let deployer = FileDeployer {
+            path: path.clone(),
+            keep_backups: 2,
+        };
+        for version in 0..4 {
+            deployer
+                .deploy(&format!("version: {}\n", version))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "version: 3\n");
+        let mut backups: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != "config.yaml")
+            .collect();
+        backups.sort();
+        assert_eq!(backups.len(), 2, "{:?}", backups);
+        assert!(backups.iter().all(|name| name.ends_with(".bak")));
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(&backups[1])).unwrap(),
+            "version: 2\n"
+        );
+    }
+
+    #[test]
+    fn header_without_timestamp_is_stable() {
+        let info = GenerationInfo {
+            timestamp: None,
+            tool_version: "0.1.0".to_string(),
+            template: "t.yaml".to_string(),
+            subscriptions: Vec::new(),
+        };
+
+        let header = info.render_header();
+        assert!(!header.contains("Generated at"));
+        assert!(header.contains("# Subscriptions: <none>"));
     }
 }
@@ -0,0 +1,193 @@
+//! Reachability pre-filter for subscription nodes.
+//!
+//! Providers keep dead servers in their lists for weeks. Before merging, each proxy's
+//! `server:port` gets a plain TCP connect with a short timeout; nodes that refuse or time out
+//! are dropped (or tagged) so they never reach a selector. UDP-only protocols cannot be checked
+//! this way and are always kept.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_yaml::Value;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+
+use crate::filter::strip_group_members;
+use crate::model::ClashConfig;
+
+/// Proxy types that run over UDP only; a TCP connect says nothing about them.
+const UDP_ONLY_TYPES: &[&str] = &["hysteria", "hysteria2", "tuic", "wireguard"];
+
+/// Key set on unreachable proxies by [`ProbeAction::Annotate`].
+pub const PROBE_KEY: &str = "x-probe";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeOptions {
+    /// Per-endpoint connect timeout.
+    pub timeout: Duration,
+    /// Endpoints probed at the same time.
+    pub concurrency: usize,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(3),
+            concurrency: 32,
+        }
+    }
+}
+
+/// What happens to a node that failed the probe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProbeAction {
+    /// Remove it from `proxies` and from every group.
+    #[default]
+    Drop,
+    /// Keep it, with `x-probe: unreachable` for later inspection.
+    Annotate,
+}
+
+/// Names of the proxies in `config` whose endpoint did not accept a TCP connection.
+pub async fn unreachable_proxies(config: &ClashConfig, options: ProbeOptions) -> Vec<String> {
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut probes = tokio::task::JoinSet::new();
+    for (idx, proxy) in config.proxies.iter().enumerate() {
+        let Some((name, endpoint)) = tcp_endpoint(proxy) else {
+            continue;
+        };
+        let semaphore = semaphore.clone();
+        probes.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let connect = TcpStream::connect(endpoint.as_str());
+            let reachable = matches!(
+                tokio::time::timeout(options.timeout, connect).await,
+                Ok(Ok(_))
+            );
+            if !reachable {
+                tracing::debug!(proxy = %name, endpoint = %endpoint, "probe failed");
+            }
+            (idx, name, reachable)
+        });
+    }
+
+    let mut dead = Vec::new();
+    while let Some(joined) = probes.join_next().await {
+        if let Ok((idx, name, false)) = joined {
+            dead.push((idx, name));
+        }
+    }
+    dead.sort();
+    dead.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Probe `config` and apply `action` to the unreachable proxies; returns their names.
+pub async fn probe_proxies(
+    config: &mut ClashConfig,
+    options: ProbeOptions,
+    action: ProbeAction,
+) -> Vec<String> {
+    let dead = unreachable_proxies(config, options).await;
+    if dead.is_empty() {
+        return dead;
+    }
+    let gone: HashSet<&str> = dead.iter().map(String::as_str).collect();
+    let is_dead = |proxy: &Value| {
+        proxy
+            .get("name")
+            .and_then(Value::as_str)
+            .is_some_and(|name| gone.contains(name))
+    };
+    match action {
+        ProbeAction::Drop => {
+            config.proxies.retain(|proxy| !is_dead(proxy));
+            strip_group_members(config, &gone);
+        }
+        ProbeAction::Annotate => {
+            for proxy in config.proxies.iter_mut() {
+                if is_dead(proxy) {
+                    if let Some(map) = proxy.as_mapping_mut() {
+                        map.insert(Value::from(PROBE_KEY), Value::from("unreachable"));
+                    }
+                }
+            }
+        }
+    }
+    dead
+}
+
+/// Names of the proxies in `config` that [`unreachable_proxies`] checks.
+pub fn probed_proxies(config: &ClashConfig) -> Vec<String> {
+    config
+        .proxies
+        .iter()
+        .filter_map(|proxy| tcp_endpoint(proxy).map(|(name, _)| name))
+        .collect()
+}
+
+/// `(name, "host:port")` of a proxy that can be checked with a TCP connect.
+fn tcp_endpoint(proxy: &Value) -> Option<(String, String)> {
+    let kind = proxy.get("type").and_then(Value::as_str)?;
+    if UDP_ONLY_TYPES.contains(&kind) || kind == "direct" || kind == "dns" {
+        return None;
+    }
+    let name = proxy.get("name").and_then(Value::as_str)?;
+    let server = proxy.get("server").and_then(Value::as_str)?;
+    let port = match proxy.get("port")? {
+        Value::Number(port) => port.as_u64()?,
+        Value::String(port) => port.trim().parse().ok()?,
+        _ => return None,
+    };
+    let host = if server.contains(':') && !server.starts_with('[') {
+        format!("[{}]", server)
+    } else {
+        server.to_string()
+    };
+    Some((name.to_string(), format!("{}:{}", host, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unreachable_nodes_are_dropped_or_annotated() {
+        let open = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open_port = open.local_addr().unwrap().port();
+        let closed_port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let yaml = format!(
+            r#"
+proxies:
+  - {{ name: alive, type: ss, server: 127.0.0.1, port: {open_port}, cipher: aes-128-gcm, password: x }}
+  - {{ name: dead, type: trojan, server: 127.0.0.1, port: "{closed_port}", password: x }}
+  - {{ name: quic, type: hysteria2, server: 127.0.0.1, port: {closed_port}, password: x }}
+proxy-groups:
+  - {{ name: PROXY, type: select, proxies: [alive, dead, quic] }}
+"#
+        );
+        let options = ProbeOptions {
+            timeout: Duration::from_secs(2),
+            concurrency: 4,
+        };
+
+        let mut annotated: ClashConfig = serde_yaml::from_str(&yaml).unwrap();
+        let dead = probe_proxies(&mut annotated, options, ProbeAction::Annotate).await;
+        assert_eq!(dead, vec!["dead"]);
+        assert_eq!(annotated.proxies.len(), 3);
+        assert_eq!(annotated.proxies[1][PROBE_KEY], Value::from("unreachable"));
+        assert!(annotated.proxies[0].get(PROBE_KEY).is_none());
+
+        let mut dropped: ClashConfig = serde_yaml::from_str(&yaml).unwrap();
+        probe_proxies(&mut dropped, options, ProbeAction::Drop).await;
+        assert_eq!(dropped.proxy_names(), vec!["alive", "quic"]);
+        assert_eq!(
+            dropped.proxy_groups[0]["proxies"],
+            serde_yaml::from_str::<Value>("[alive, quic]").unwrap()
+        );
+        drop(open);
+    }
+}
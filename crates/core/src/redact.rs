@@ -0,0 +1,214 @@
+//! Mask credentials in configs printed for sharing (bug reports, chat).
+//!
+//! Only copies meant for humans are redacted; configs written for mihomo keep their secrets.
+
+use serde_yaml::Value;
+
+use crate::model::ClashConfig;
+
+/// Replacement for masked values.
+pub const REDACTED: &str = "<redacted>";
+
+/// Keys whose value is a credential wherever they appear (proxies, listeners, dialer options).
+const SECRET_KEYS: &[&str] = &[
+    "password",
+    "uuid",
+    "psk",
+    "private-key",
+    "pre-shared-key",
+    "auth",
+    "auth-str",
+    "auth_str",
+    "obfs-password",
+    "token",
+    "short-id",
+    "secret",
+];
+
+/// Mask every credential in `config`: proxy passwords/uuids/psks, the controller `secret`,
+/// `authentication` passwords and proxy-provider URLs (which embed subscription tokens).
+/// Returns the number of values masked.
+pub fn redact_secrets(config: &mut ClashConfig) -> usize {
+    let mut masked = 0;
+    for value in config
+        .proxies
+        .iter_mut()
+        .chain(config.proxy_groups.iter_mut())
+    {
+        masked += redact_value(value);
+    }
+    for (key, value) in config.extra.iter_mut() {
+        masked += match key.as_str() {
+            "authentication" => redact_authentication(value),
+            "proxy-providers" => redact_provider_urls(value),
+            key if SECRET_KEYS.contains(&key) => mask(value),
+            _ => redact_value(value),
+        };
+    }
+    masked
+}
+
+/// Mask the values of [`SECRET_KEYS`] anywhere below `value`.
+pub fn redact_value(value: &mut Value) -> usize {
+    match value {
+        Value::Mapping(map) => map
+            .iter_mut()
+            .map(|(key, value)| match key.as_str() {
+                Some(key) if SECRET_KEYS.contains(&key) => mask(value),
+                _ => redact_value(value),
+            })
+            .sum(),
+        Value::Sequence(items) => items.iter_mut().map(redact_value).sum(),
+        Value::Tagged(tagged) => redact_value(&mut tagged.value),
+        _ => 0,
+    }
+}
+
+/// Replace a scalar (or every scalar of a list) with [`REDACTED`].
+fn mask(value: &mut Value) -> usize {
+    match value {
+        Value::Null => 0,
+        Value::Sequence(items) => items.iter_mut().map(mask).sum(),
+        Value::Mapping(_) => redact_value(value),
+        _ => {
+            *value = Value::from(REDACTED);
+            1
+        }
+    }
+}
+
+/// `user:pass` entries keep the user name.
+fn redact_authentication(value: &mut Value) -> usize {
+    let Some(items) = value.as_sequence_mut() else {
+        return mask(value);
+    };
+    let mut masked = 0;
+    for item in items.iter_mut() {
+        if let Some((user, _)) = item.as_str().and_then(|entry| entry.split_once(':')) {
+            *item = Value::from(format!("{}:{}", user, REDACTED));
+            masked += 1;
+        }
+    }
+    masked
+}
+
+/// Provider URLs keep scheme and host; path and query usually carry the subscription token.
+fn redact_provider_urls(value: &mut Value) -> usize {
+    let mut masked = redact_value(value);
+    let Some(providers) = value.as_mapping_mut() else {
+        return masked;
+    };
+    for (_, provider) in providers.iter_mut() {
+        let Some(url) = provider.get_mut("url") else {
+            continue;
+        };
+        let Some(raw) = url.as_str() else {
+            continue;
+        };
+        *url = Value::from(redact_url(raw));
+        masked += 1;
+    }
+    masked
+}
+
+fn redact_url(raw: &str) -> String {
+    match url::Url::parse(raw) {
+        Ok(parsed) => format!(
+            "{}://{}/{}",
+            parsed.scheme(),
+            parsed.host_str().unwrap_or_default(),
+            REDACTED
+        ),
+        Err(_) => REDACTED.to_string(),
+    }
+}
+
+/// Cut every `http(s)://` URL in free text (warnings, error chains) down to scheme and host,
+/// as for provider URLs. Punctuation ending a sentence or clause right after a URL (`.,;:)]`)
+/// is left in the text.
+pub fn redact_urls(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | ')' | '>' | ','))
+            .unwrap_or(rest.len() - start);
+        let len = rest[start..start + end]
+            .trim_end_matches(['.', ',', ';', ':', ')', ']'])
+            .len();
+        out.push_str(&redact_url(&rest[start..start + len]));
+        rest = &rest[start + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_masked_and_the_rest_kept() {
+        let mut config: ClashConfig = serde_yaml::from_str(
+            r#"
+secret: controller-secret
+authentication: ["alice:hunter2"]
+proxy-providers:
+  airport: { type: http, url: "https://sub.example.com/api/v1/client/subscribe?token=abc", path: ./a.yaml }
+proxies:
+  - { name: SS, type: ss, server: a.example, port: 443, cipher: aes-128-gcm, password: p@ss }
+  - name: VL
+    type: vless
+    server: b.example
+    port: 443
+    uuid: 00000000-0000-0000-0000-000000000000
+    reality-opts: { public-key: pub, short-id: "0123" }
+  - { name: WG, type: wireguard, server: c.example, port: 51820, private-key: key, peers: [{ pre-shared-key: psk }] }
+proxy-groups:
+  - { name: Proxy, type: select, proxies: [SS, VL, WG] }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(redact_secrets(&mut config), 8);
+        let yaml = config.to_yaml_string().unwrap();
+        for leaked in [
+            "controller-secret",
+            "hunter2",
+            "token=abc",
+            "p@ss",
+            "00000000-0000",
+            "\"0123\"",
+            "private-key: key",
+            "pre-shared-key: psk",
+        ] {
+            assert!(!yaml.contains(leaked), "{leaked} leaked:\n{yaml}");
+        }
+        assert!(yaml.contains("alice:<redacted>"));
+        assert!(yaml.contains("https://sub.example.com/<redacted>"));
+        assert!(yaml.contains("public-key: pub"));
+        assert!(yaml.contains("server: a.example"));
+    }
+
+    #[test]
+    fn urls_in_messages_keep_only_scheme_and_host() {
+        assert_eq!(
+            redact_urls(
+                "failed to load ad-hoc subscription https://sub.example.com/s?token=abc: \
+                 error sending request for url (http://10.0.0.1:8080/link/xyz)"
+            ),
+            "failed to load ad-hoc subscription https://sub.example.com/<redacted>: \
+             error sending request for url (http://10.0.0.1/<redacted>)"
+        );
+        assert_eq!(
+            redact_urls("see https://a.example/x?t=1. Then [https://b.example/y]; done"),
+            "see https://a.example/<redacted>. Then [https://b.example/<redacted>]; done"
+        );
+        assert_eq!(redact_urls("no links here"), "no links here");
+    }
+}
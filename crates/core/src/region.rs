@@ -0,0 +1,630 @@
+//! Region-based url-test groups, in the spirit of subconverter's `🇭🇰 香港节点` groups.
+//!
+//! Proxies are classified from their names (flags, place names, ISO code tokens and the
+//! [`NameDictionary`]) and/or by looking their server up in server-based sources: an offline
+//! CSV table, `Country.mmdb`, and answers from an IP-geo API for servers the other two cannot
+//! place (typically CDN hostnames). Every requested region that ends up with nodes gets a
+//! url-test group named after its code, listed first in the main selector.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use crate::merge::{prepend_group_members, PROVIDER_URL_TEST_INTERVAL, PROVIDER_URL_TEST_URL};
+use crate::model::ClashConfig;
+use crate::rename::NameDictionary;
+use crate::report::{WarningKind, Warnings};
+
+/// Regions grouped when none are configured.
+pub const DEFAULT_REGIONS: [&str; 4] = ["HK", "JP", "US", "SG"];
+
+/// Where a proxy's region comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionSource {
+    /// Name keywords only.
+    #[default]
+    Name,
+    /// The server's region from the server lookups (CSV table, `Country.mmdb`, API) only.
+    Geoip,
+    /// Name keywords, falling back to the server lookups for names that say nothing.
+    Both,
+}
+
+impl RegionSource {
+    pub fn uses_geoip(self) -> bool {
+        matches!(self, RegionSource::Geoip | RegionSource::Both)
+    }
+}
+
+/// The `auto-region-groups:` template directive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionGroupsSpec {
+    /// ISO codes to build groups for, in the order they are listed in the selector.
+    #[serde(default = "default_regions")]
+    pub regions: Vec<String>,
+    #[serde(default)]
+    pub source: RegionSource,
+}
+
+impl Default for RegionGroupsSpec {
+    fn default() -> Self {
+        Self {
+            regions: default_regions(),
+            source: RegionSource::default(),
+        }
+    }
+}
+
+fn default_regions() -> Vec<String> {
+    DEFAULT_REGIONS
+        .iter()
+        .map(|code| code.to_string())
+        .collect()
+}
+
+/// A `Country.mmdb` (MaxMind GeoLite2-Country layout, as shipped by meta-rules-dat).
+pub struct GeoIp {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let reader = maxminddb::Reader::open_readfile(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        Ok(Self { reader })
+    }
+
+    /// ISO code of the country `ip` is registered in.
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let record: maxminddb::geoip2::Country = self.reader.lookup(ip).ok()?;
+        record
+            .country
+            .and_then(|country| country.iso_code)
+            .map(str::to_ascii_uppercase)
+    }
+}
+
+/// A server-based region source consulted by [`RegionClassifier`].
+pub trait ServerRegionLookup {
+    /// Region code of a proxy `server`; `ip` is its address when known (an IP literal or a
+    /// resolved hostname).
+    fn region(&self, server: &str, ip: Option<IpAddr>) -> Option<String>;
+}
+
+impl ServerRegionLookup for GeoIp {
+    fn region(&self, _server: &str, ip: Option<IpAddr>) -> Option<String> {
+        self.country(ip?)
+    }
+}
+
+/// `region_lookup:` in app.yaml: region sources for servers `Country.mmdb` cannot place.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionLookupConfig {
+    /// Offline `server,CODE` table (see [`RegionTable`]), consulted before `Country.mmdb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csv: Option<PathBuf>,
+
+    /// IP-geo API asked about servers nothing else could place; `{server}` is replaced by the
+    /// proxy's server and `{ip}` by its address (the server itself if unresolved), e.g.
+    /// `http://ip-api.com/json/{server}?fields=countryCode`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
+
+    /// Minimum delay between two API requests.
+    #[serde(default = "default_api_interval_ms")]
+    pub api_interval_ms: u64,
+
+    /// How long an API answer (including "unknown") is reused before asking again.
+    #[serde(default = "default_api_cache_hours")]
+    pub api_cache_hours: u64,
+}
+
+impl Default for RegionLookupConfig {
+    fn default() -> Self {
+        Self {
+            csv: None,
+            api: None,
+            api_interval_ms: default_api_interval_ms(),
+            api_cache_hours: default_api_cache_hours(),
+        }
+    }
+}
+
+impl RegionLookupConfig {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+fn default_api_interval_ms() -> u64 {
+    // ip-api.com's free tier allows 45 requests a minute.
+    1500
+}
+
+fn default_api_cache_hours() -> u64 {
+    24 * 7
+}
+
+/// Most API requests made in one run; the remaining servers are asked on later runs.
+pub const API_LOOKUPS_PER_RUN: usize = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TableKey {
+    Host(String),
+    /// `*.example.com` or `.example.com`: the domain and every subdomain.
+    Suffix(String),
+    Cidr(IpAddr, u8),
+}
+
+/// Offline `server,CODE` mapping. The server column holds a hostname, a domain suffix
+/// (`*.cdn.example` or `.cdn.example`), an IP address or a CIDR range. Blank lines, `#`
+/// comments and a leading `server,...` header are ignored; the first matching line wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegionTable {
+    entries: Vec<(TableKey, String)>,
+}
+
+impl RegionTable {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (server, code) = line
+                .split_once(',')
+                .map(|(server, code)| (server.trim(), code.trim()))
+                .ok_or_else(|| anyhow!("line {}: expected `server,CODE`", index + 1))?;
+            if index == 0 && server.eq_ignore_ascii_case("server") {
+                continue;
+            }
+            if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(anyhow!(
+                    "line {}: '{}' is not a two-letter region code",
+                    index + 1,
+                    code
+                ));
+            }
+            let key = parse_table_key(server)
+                .ok_or_else(|| anyhow!("line {}: invalid server '{}'", index + 1, server))?;
+            entries.push((key, code.to_ascii_uppercase()));
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("in {}", path.display()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl ServerRegionLookup for RegionTable {
+    fn region(&self, server: &str, ip: Option<IpAddr>) -> Option<String> {
+        let host = server.trim_end_matches('.').to_ascii_lowercase();
+        self.entries
+            .iter()
+            .find(|(key, _)| match key {
+                TableKey::Host(name) => *name == host,
+                TableKey::Suffix(domain) => {
+                    host == *domain || host.ends_with(&format!(".{}", domain))
+                }
+                TableKey::Cidr(net, prefix) => {
+                    ip.is_some_and(|ip| cidr_contains(*net, *prefix, ip))
+                }
+            })
+            .map(|(_, code)| code.clone())
+    }
+}
+
+fn parse_table_key(server: &str) -> Option<TableKey> {
+    if server.is_empty() {
+        return None;
+    }
+    if let Some((net, prefix)) = server.split_once('/') {
+        let net: IpAddr = net.parse().ok()?;
+        let prefix: u8 = prefix.parse().ok()?;
+        let max = if net.is_ipv4() { 32 } else { 128 };
+        return (prefix <= max).then_some(TableKey::Cidr(net, prefix));
+    }
+    if let Ok(ip) = server.parse::<IpAddr>() {
+        let prefix = if ip.is_ipv4() { 32 } else { 128 };
+        return Some(TableKey::Cidr(ip, prefix));
+    }
+    let host = server.trim_end_matches('.').to_ascii_lowercase();
+    match host.strip_prefix("*.").or_else(|| host.strip_prefix('.')) {
+        Some(domain) if !domain.is_empty() => Some(TableKey::Suffix(domain.to_string())),
+        Some(_) => None,
+        None => Some(TableKey::Host(host)),
+    }
+}
+
+fn cidr_contains(net: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    match (net, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(net) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(net) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedRegion {
+    /// `None` when the API answered without a usable country.
+    region: Option<String>,
+    fetched_at: DateTime<Utc>,
+}
+
+/// Answers of the IP-geo API per server, persisted in the cache dir between runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteRegions {
+    entries: HashMap<String, CachedRegion>,
+}
+
+impl RemoteRegions {
+    /// The saved answers, or none when `path` is missing or unreadable.
+    pub async fn load(path: &Path) -> Self {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        crate::storage::write_atomic(path, &serde_json::to_vec_pretty(self)?).await
+    }
+
+    fn fresh(&self, server: &str, ttl: chrono::Duration, now: DateTime<Utc>) -> bool {
+        self.entries
+            .get(server)
+            .is_some_and(|entry| now - entry.fetched_at < ttl)
+    }
+
+    /// Ask `config.api` about every server in `servers` without a fresh answer, one request
+    /// per `api_interval_ms` and at most [`API_LOOKUPS_PER_RUN`]. Returns the number of
+    /// requests made; failed requests are logged and asked again next time.
+    pub async fn refresh(
+        &mut self,
+        client: &reqwest::Client,
+        config: &RegionLookupConfig,
+        servers: &[(String, Option<IpAddr>)],
+    ) -> usize {
+        let Some(api) = config.api.as_deref() else {
+            return 0;
+        };
+        let ttl = chrono::Duration::hours(config.api_cache_hours as i64);
+        let now = Utc::now();
+        let pending: Vec<&(String, Option<IpAddr>)> = servers
+            .iter()
+            .filter(|(server, _)| !self.fresh(server, ttl, now))
+            .collect();
+        if pending.len() > API_LOOKUPS_PER_RUN {
+            tracing::warn!(
+                pending = pending.len(),
+                limit = API_LOOKUPS_PER_RUN,
+                "region API lookups capped; the rest are asked on later runs"
+            );
+        }
+        let mut requests = 0;
+        for (server, ip) in pending.into_iter().take(API_LOOKUPS_PER_RUN) {
+            if requests > 0 {
+                tokio::time::sleep(Duration::from_millis(config.api_interval_ms)).await;
+            }
+            requests += 1;
+            let address = ip
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| server.clone());
+            let url = api.replace("{server}", server).replace("{ip}", &address);
+            match fetch_api_region(client, &url).await {
+                Ok(region) => {
+                    self.entries.insert(
+                        server.clone(),
+                        CachedRegion {
+                            region,
+                            fetched_at: Utc::now(),
+                        },
+                    );
+                }
+                Err(err) => {
+                    tracing::warn!(server = %server, error = %format!("{:#}", err), "region API lookup failed");
+                }
+            }
+        }
+        requests
+    }
+}
+
+impl ServerRegionLookup for RemoteRegions {
+    fn region(&self, server: &str, _ip: Option<IpAddr>) -> Option<String> {
+        self.entries.get(server)?.region.clone()
+    }
+}
+
+async fn fetch_api_region(client: &reqwest::Client, url: &str) -> anyhow::Result<Option<String>> {
+    let response = client
+        .get(url)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(parse_api_region(&response.text().await?))
+}
+
+/// Country code in an IP-geo API answer: a bare code (`HK`, as ipinfo's `/country` returns)
+/// or a JSON object with `countryCode`, `country_code`, a two-letter `country` or
+/// `country.iso_code`.
+pub fn parse_api_region(body: &str) -> Option<String> {
+    let is_code = |code: &str| code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic());
+    let body = body.trim();
+    if is_code(body) {
+        return Some(body.to_ascii_uppercase());
+    }
+    let json: serde_json::Value = serde_json::from_str(body).ok()?;
+    ["countryCode", "country_code", "country"]
+        .iter()
+        .filter_map(|key| json.get(key))
+        .chain(json.pointer("/country/iso_code"))
+        .filter_map(serde_json::Value::as_str)
+        .find(|code| is_code(code))
+        .map(str::to_ascii_uppercase)
+}
+
+/// Inputs for classifying proxies; `lookups` are asked in order for server-based sources and
+/// `resolved` maps hostname servers to an address so that IP lookups also work for them.
+pub struct RegionClassifier<'a> {
+    pub source: RegionSource,
+    pub dictionary: &'a NameDictionary,
+    pub lookups: Vec<&'a dyn ServerRegionLookup>,
+    pub resolved: &'a HashMap<String, IpAddr>,
+}
+
+impl RegionClassifier<'_> {
+    /// Region code of `proxy`, if it can be told.
+    pub fn classify(&self, proxy: &Value) -> Option<String> {
+        let by_name = || {
+            proxy
+                .get("name")
+                .and_then(Value::as_str)
+                .and_then(|name| self.dictionary.detect_region(name))
+                .map(str::to_string)
+        };
+        let by_server = || {
+            let (server, ip) = self.server_of(proxy)?;
+            self.lookups
+                .iter()
+                .find_map(|lookup| lookup.region(server, ip))
+        };
+        match self.source {
+            RegionSource::Name => by_name(),
+            RegionSource::Geoip => by_server(),
+            RegionSource::Both => by_name().or_else(by_server),
+        }
+    }
+
+    /// Servers (with their address, when known) of the proxies [`Self::classify`] cannot
+    /// place, without duplicates; these are the ones worth asking an API about.
+    pub fn unclassified_servers(&self, proxies: &[Value]) -> Vec<(String, Option<IpAddr>)> {
+        let mut servers: Vec<(String, Option<IpAddr>)> = Vec::new();
+        if !self.source.uses_geoip() {
+            return servers;
+        }
+        for proxy in proxies {
+            if self.classify(proxy).is_some() {
+                continue;
+            }
+            if let Some((server, ip)) = self.server_of(proxy) {
+                if !servers.iter().any(|(known, _)| known == server) {
+                    servers.push((server.to_string(), ip));
+                }
+            }
+        }
+        servers
+    }
+
+    fn server_of<'p>(&self, proxy: &'p Value) -> Option<(&'p str, Option<IpAddr>)> {
+        let server = proxy.get("server").and_then(Value::as_str)?;
+        let ip = server
+            .parse::<IpAddr>()
+            .ok()
+            .or_else(|| self.resolved.get(server).copied());
+        Some((server, ip))
+    }
+}
+
+/// Add one url-test group per region in `spec` that has at least one proxy, and list those
+/// groups first in `main_selector`. Returns `(group, member count)` for each added group.
+pub fn add_region_groups(
+    config: &mut ClashConfig,
+    spec: &RegionGroupsSpec,
+    classifier: &RegionClassifier<'_>,
+    main_selector: &str,
+    warnings: &mut Warnings,
+) -> Vec<(String, usize)> {
+    let regions: Vec<String> = spec
+        .regions
+        .iter()
+        .map(|code| code.trim().to_ascii_uppercase())
+        .filter(|code| !code.is_empty())
+        .collect();
+    let mut members: HashMap<&str, Vec<String>> = HashMap::new();
+    for proxy in &config.proxies {
+        let Some(name) = proxy.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+        if let Some(code) = classifier.classify(proxy) {
+            if let Some(region) = regions.iter().find(|region| **region == code) {
+                members
+                    .entry(region.as_str())
+                    .or_default()
+                    .push(name.to_string());
+            }
+        }
+    }
+
+    let existing_proxies = config.proxy_names();
+    let existing_groups = config.proxy_group_names();
+    let mut added = Vec::new();
+    for region in &regions {
+        let Some(proxies) = members.remove(region.as_str()) else {
+            continue;
+        };
+        if existing_groups.contains(region) || existing_proxies.contains(region) {
+            warnings.push(
+                WarningKind::DuplicateGroup,
+                format!(
+                    "region group '{}' clashes with an existing group or proxy; skipped",
+                    region
+                ),
+            );
+            continue;
+        }
+        let mut group = Mapping::new();
+        group.insert(Value::from("name"), Value::from(region.as_str()));
+        group.insert(Value::from("type"), Value::from("url-test"));
+        group.insert(Value::from("url"), Value::from(PROVIDER_URL_TEST_URL));
+        group.insert(
+            Value::from("interval"),
+            Value::from(PROVIDER_URL_TEST_INTERVAL),
+        );
+        let count = proxies.len();
+        group.insert(
+            Value::from("proxies"),
+            Value::Sequence(proxies.into_iter().map(Value::from).collect()),
+        );
+        config.proxy_groups.push(Value::Mapping(group));
+        added.push((region.clone(), count));
+    }
+
+    let names: Vec<String> = added.iter().map(|(name, _)| name.clone()).collect();
+    prepend_group_members(config, main_selector, &names);
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_groups_are_built_from_names_and_wired_into_selector() {
+        let mut config: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - { name: 🇭🇰 香港 01, type: ss, server: a.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: HK-02 IPLC, type: ss, server: b.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: 沪日 IEPL, type: ss, server: c.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: 狮城 01, type: ss, server: d.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: Relay, type: ss, server: 1.1.1.1, port: 1, cipher: aes-128-gcm, password: x }
+proxy-groups:
+  - { name: PROXY, type: select, proxies: [Auto, DIRECT] }
+  - { name: SG, type: select, proxies: [DIRECT] }
+"#,
+        )
+        .unwrap();
+        let dictionary: NameDictionary = serde_yaml::from_str("{ 沪日: SH-JP, 狮城: SG }").unwrap();
+        let resolved = HashMap::new();
+        let classifier = RegionClassifier {
+            source: RegionSource::Both,
+            dictionary: &dictionary,
+            lookups: Vec::new(),
+            resolved: &resolved,
+        };
+        let mut warnings = Warnings::default();
+        let added = add_region_groups(
+            &mut config,
+            &RegionGroupsSpec::default(),
+            &classifier,
+            "PROXY",
+            &mut warnings,
+        );
+
+        assert_eq!(added, vec![("HK".to_string(), 2), ("JP".to_string(), 1)]);
+        assert_eq!(warnings.len(), 1);
+        let hk = &config.proxy_groups[2];
+        assert_eq!(hk["type"], Value::from("url-test"));
+        assert_eq!(
+            hk["proxies"],
+            serde_yaml::from_str::<Value>("[🇭🇰 香港 01, HK-02 IPLC]").unwrap()
+        );
+        assert_eq!(
+            config.proxy_groups[0]["proxies"],
+            serde_yaml::from_str::<Value>("[HK, JP, Auto, DIRECT]").unwrap()
+        );
+    }
+
+    #[test]
+    fn server_lookups_use_csv_table_then_cached_api_answers() {
+        let table = RegionTable::parse(
+            "server,region\n# CDN fronts\n*.cdn.example,jp\nedge.example,SG\n10.0.0.0/8,US\n",
+        )
+        .unwrap();
+        assert!(RegionTable::parse("a.example,Japan").is_err());
+
+        let mut remote = RemoteRegions::default();
+        remote.entries.insert(
+            "far.example".to_string(),
+            CachedRegion {
+                region: parse_api_region(r#"{"status":"success","countryCode":"de"}"#),
+                fetched_at: Utc::now(),
+            },
+        );
+        assert_eq!(parse_api_region("HK\n").as_deref(), Some("HK"));
+        assert_eq!(
+            parse_api_region(r#"{"country":{"iso_code":"TW"}}"#).as_deref(),
+            Some("TW")
+        );
+
+        let dictionary = NameDictionary::default();
+        let resolved = HashMap::from([("lan.example".to_string(), "10.1.2.3".parse().unwrap())]);
+        let classifier = RegionClassifier {
+            source: RegionSource::Geoip,
+            dictionary: &dictionary,
+            lookups: vec![&table, &remote],
+            resolved: &resolved,
+        };
+        let proxies: Vec<Value> = serde_yaml::from_str(
+            r#"
+- { name: a, server: hk.cdn.example }
+- { name: b, server: edge.example }
+- { name: c, server: lan.example }
+- { name: d, server: far.example }
+- { name: e, server: 192.0.2.1 }
+"#,
+        )
+        .unwrap();
+        let regions: Vec<Option<String>> = proxies
+            .iter()
+            .map(|proxy| classifier.classify(proxy))
+            .collect();
+        assert_eq!(
+            regions,
+            [
+                Some("JP".to_string()),
+                Some("SG".to_string()),
+                Some("US".to_string()),
+                Some("DE".to_string()),
+                None
+            ]
+        );
+        assert_eq!(
+            classifier.unclassified_servers(&proxies),
+            [("192.0.2.1".to_string(), Some("192.0.2.1".parse().unwrap()))]
+        );
+    }
+}
@@ -0,0 +1,452 @@
+//! Proxy renaming: ordered regex rewrites plus optional country flag/code normalization.
+//!
+//! Airport node names are noisy ("🇭🇰 香港 IPLC 01 | 1.5x", "HK-01【专线】"), which makes
+//! group `filter:` regexes fragile. Renames are applied to `proxies` and propagated to every
+//! place that references a proxy by name: group members, rule targets and `dialer-proxy`.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Context;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::merge::rule_target_index;
+use crate::model::ClashConfig;
+
+/// The `rename:` section of app.yaml or a template.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameConfig {
+    /// Applied in order; `replace` may use `$1`/`${name}` capture references.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RenameRule>,
+    /// Normalize the country marker after the rules have run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<CountryStyle>,
+    /// Provider-specific name fragments translated before region detection, e.g.
+    /// `狮城: SG` or `沪日: SH-JP`. Names themselves are not rewritten by it.
+    #[serde(default, skip_serializing_if = "NameDictionary::is_empty")]
+    pub dictionary: NameDictionary,
+}
+
+/// Fragment → replacement map used to classify creatively named nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NameDictionary(pub BTreeMap<String, String>);
+
+impl NameDictionary {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Replace every known fragment of `name`, longest fragments first so `沪日` wins over `日`.
+    pub fn translate<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.0.is_empty() {
+            return Cow::Borrowed(name);
+        }
+        let mut keys: Vec<&String> = self.0.keys().filter(|key| !key.is_empty()).collect();
+        keys.sort_by_key(|key| std::cmp::Reverse(key.chars().count()));
+        let mut out = String::with_capacity(name.len());
+        let mut rest = name;
+        'scan: while let Some(c) = rest.chars().next() {
+            for key in &keys {
+                if let Some(tail) = rest.strip_prefix(key.as_str()) {
+                    out.push(' ');
+                    out.push_str(&self.0[*key]);
+                    out.push(' ');
+                    rest = tail;
+                    continue 'scan;
+                }
+            }
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        Cow::Owned(out)
+    }
+
+    /// Region of `name` after translation; see [`detect_country`].
+    pub fn detect_region(&self, name: &str) -> Option<&'static str> {
+        detect_country(&self.translate(name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameRule {
+    #[serde(rename = "match")]
+    pub pattern: String,
+    #[serde(default)]
+    pub replace: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CountryStyle {
+    /// Prefix the flag emoji (`🇭🇰 香港 01`), dropping any other flags.
+    Emoji,
+    /// Prefix the ISO code (`HK 香港 01`) instead of a flag, unless the name already has it.
+    Code,
+}
+
+impl RenameConfig {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.country.is_none() && self.dictionary.is_empty()
+    }
+
+    /// `self` followed by `other`: rules run in sequence, `other`'s country style and
+    /// dictionary entries win.
+    pub fn then(mut self, other: &RenameConfig) -> RenameConfig {
+        self.rules.extend(other.rules.iter().cloned());
+        if other.country.is_some() {
+            self.country = other.country;
+        }
+        self.dictionary.0.extend(
+            other
+                .dictionary
+                .0
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        self
+    }
+}
+
+/// Rename the proxies of `config` and update every reference. Returns `(old, new)` pairs.
+/// A name that collides with another proxy after renaming gets a `-2`, `-3`, ... suffix.
+pub fn rename_proxies(
+    config: &mut ClashConfig,
+    rename: &RenameConfig,
+) -> anyhow::Result<Vec<(String, String)>> {
+    if rename.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rules: Vec<(Regex, &str)> = rename
+        .rules
+        .iter()
+        .map(|rule| {
+            Regex::new(&rule.pattern)
+                .with_context(|| format!("invalid rename regex: {}", rule.pattern))
+                .map(|re| (re, rule.replace.as_str()))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut taken: HashSet<String> = config.proxy_group_names().into_iter().collect();
+    let mut changes = Vec::new();
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    for proxy in config.proxies.iter_mut() {
+        let Some(map) = proxy.as_mapping_mut() else {
+            continue;
+        };
+        let Some(old) = map
+            .get(Value::from("name"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let mut name = old.clone();
+        for (re, replace) in &rules {
+            name = re.replace_all(&name, *replace).into_owned();
+        }
+        if let Some(style) = rename.country {
+            name = normalize_country(&name, style, &rename.dictionary);
+        }
+        name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if name.is_empty() {
+            name = old.clone();
+        }
+        if taken.contains(&name) {
+            name = (2..)
+                .map(|n| format!("{}-{}", name, n))
+                .find(|candidate| !taken.contains(candidate))
+                .expect("unbounded suffix range");
+        }
+        taken.insert(name.clone());
+        if name != old {
+            map.insert(Value::from("name"), Value::from(name.as_str()));
+            renamed.entry(old.clone()).or_insert_with(|| name.clone());
+            changes.push((old, name));
+        }
+    }
+    if renamed.is_empty() {
+        return Ok(changes);
+    }
+
+    for group in config.proxy_groups.iter_mut() {
+        if let Some(members) = group.get_mut("proxies").and_then(Value::as_sequence_mut) {
+            for member in members.iter_mut() {
+                if let Some(new) = member.as_str().and_then(|name| renamed.get(name)) {
+                    *member = Value::from(new.as_str());
+                }
+            }
+        }
+    }
+    for proxy in config.proxies.iter_mut() {
+        let Some(map) = proxy.as_mapping_mut() else {
+            continue;
+        };
+        if let Some(new) = map
+            .get(Value::from("dialer-proxy"))
+            .and_then(Value::as_str)
+            .and_then(|name| renamed.get(name))
+        {
+            map.insert(Value::from("dialer-proxy"), Value::from(new.as_str()));
+        }
+    }
+    for rule in config.rules.iter_mut() {
+        let mut parts: Vec<&str> = rule.split(',').collect();
+        let Some(idx) = rule_target_index(&parts) else {
+            continue;
+        };
+        if let Some(new) = renamed.get(parts[idx].trim()) {
+            parts[idx] = new;
+            *rule = parts.join(",");
+        }
+    }
+    Ok(changes)
+}
+
+/// (ISO code, names matched as substrings). English names are matched case-insensitively.
+const COUNTRIES: &[(&str, &[&str])] = &[
+    ("HK", &["香港", "Hong Kong", "HongKong"]),
+    ("TW", &["台湾", "臺灣", "台灣", "Taiwan"]),
+    ("MO", &["澳门", "澳門", "Macau", "Macao"]),
+    ("JP", &["日本", "东京", "大阪", "Japan", "Tokyo", "Osaka"]),
+    ("KR", &["韩国", "韓國", "首尔", "Korea", "Seoul"]),
+    ("SG", &["新加坡", "狮城", "Singapore"]),
+    (
+        "US",
+        &[
+            "美国",
+            "美國",
+            "洛杉矶",
+            "圣何塞",
+            "United States",
+            "America",
+            "Los Angeles",
+        ],
+    ),
+    ("CA", &["加拿大", "Canada"]),
+    (
+        "GB",
+        &[
+            "英国",
+            "英國",
+            "伦敦",
+            "United Kingdom",
+            "Britain",
+            "London",
+        ],
+    ),
+    ("DE", &["德国", "德國", "法兰克福", "Germany", "Frankfurt"]),
+    ("FR", &["法国", "法國", "巴黎", "France", "Paris"]),
+    ("NL", &["荷兰", "荷蘭", "Netherlands", "Amsterdam"]),
+    ("RU", &["俄罗斯", "俄羅斯", "Russia", "Moscow"]),
+    ("TR", &["土耳其", "Turkey", "Türkiye"]),
+    ("ID", &["印尼", "印度尼西亚", "Indonesia"]),
+    ("IN", &["印度", "India"]),
+    ("MY", &["马来西亚", "馬來西亞", "Malaysia"]),
+    ("TH", &["泰国", "泰國", "Thailand"]),
+    ("VN", &["越南", "Vietnam"]),
+    ("PH", &["菲律宾", "菲律賓", "Philippines"]),
+    ("AU", &["澳大利亚", "澳洲", "Australia", "Sydney"]),
+    ("BR", &["巴西", "Brazil"]),
+    ("AR", &["阿根廷", "Argentina"]),
+    ("AE", &["阿联酋", "迪拜", "Dubai", "UAE"]),
+];
+
+/// Codes written differently in node names.
+const CODE_ALIASES: &[(&str, &str)] = &[("UK", "GB"), ("USA", "US"), ("JPN", "JP")];
+
+fn normalize_country(name: &str, style: CountryStyle, dictionary: &NameDictionary) -> String {
+    let Some(code) = dictionary.detect_region(name) else {
+        return name.to_string();
+    };
+    let stripped = strip_flags(name);
+    let stripped = stripped.trim();
+    match style {
+        CountryStyle::Emoji => format!("{} {}", flag_emoji(code), stripped),
+        CountryStyle::Code => {
+            if has_code_token(stripped, code) {
+                stripped.to_string()
+            } else {
+                format!("{} {}", code, stripped)
+            }
+        }
+    }
+}
+
+/// Country of a node name: an existing flag emoji first, then a known name, then an ISO code
+/// written as a separate uppercase token.
+pub fn detect_country(name: &str) -> Option<&'static str> {
+    let chars: Vec<char> = name.chars().collect();
+    for pair in chars.windows(2) {
+        if let (Some(a), Some(b)) = (regional_letter(pair[0]), regional_letter(pair[1])) {
+            let code = format!("{}{}", a, b);
+            if let Some((known, _)) = COUNTRIES.iter().find(|(known, _)| *known == code) {
+                return Some(known);
+            }
+        }
+    }
+    let lower = name.to_lowercase();
+    for (code, names) in COUNTRIES {
+        if names
+            .iter()
+            .any(|candidate| lower.contains(&candidate.to_lowercase()))
+        {
+            return Some(code);
+        }
+    }
+    for token in name.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let token = CODE_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == token)
+            .map_or(token, |(_, code)| *code);
+        if let Some((code, _)) = COUNTRIES.iter().find(|(code, _)| *code == token) {
+            return Some(code);
+        }
+    }
+    None
+}
+
+fn has_code_token(name: &str, code: &str) -> bool {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .any(|token| {
+            token == code
+                || CODE_ALIASES
+                    .iter()
+                    .any(|(alias, aliased)| *alias == token && *aliased == code)
+        })
+}
+
+fn regional_letter(c: char) -> Option<char> {
+    let offset = (c as u32).checked_sub(0x1F1E6)?;
+    (offset < 26).then(|| (b'A' + offset as u8) as char)
+}
+
+fn flag_emoji(code: &str) -> String {
+    code.chars()
+        .filter_map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+        .collect()
+}
+
+fn strip_flags(name: &str) -> String {
+    name.chars()
+        .filter(|c| regional_letter(*c).is_none())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn country_markers_are_detected_and_normalized() {
+        assert_eq!(detect_country("🇭🇰 HK-01"), Some("HK"));
+        assert_eq!(detect_country("香港 IPLC 01"), Some("HK"));
+        assert_eq!(detect_country("Tokyo 02"), Some("JP"));
+        assert_eq!(detect_country("UK 03"), Some("GB"));
+        assert_eq!(detect_country("印度尼西亚 01"), Some("ID"));
+        assert_eq!(detect_country("US-West 1.5x"), Some("US"));
+        assert_eq!(detect_country("剩余流量：10GB"), None);
+
+        let none = NameDictionary::default();
+
+        assert_eq!(
+            normalize_country("香港 01 🇭🇰", CountryStyle::Emoji, &none),
+            "🇭🇰 香港 01"
+        );
+        assert_eq!(
+            normalize_country("🇯🇵 日本 02", CountryStyle::Code, &none),
+            "JP 日本 02"
+        );
+        assert_eq!(
+            normalize_country("🇸🇬 SG 03", CountryStyle::Code, &none),
+            "SG 03"
+        );
+        assert_eq!(
+            normalize_country("Relay", CountryStyle::Emoji, &none),
+            "Relay"
+        );
+    }
+
+    #[test]
+    fn dictionary_feeds_region_detection() {
+        let dictionary: NameDictionary =
+            serde_yaml::from_str("{ 沪日: SH-JP, 日: XX, 魔都: SH, 狮城: SG }").unwrap();
+        assert_eq!(dictionary.translate("沪日 IEPL 01"), " SH-JP  IEPL 01");
+        assert_eq!(dictionary.detect_region("沪日 IEPL 01"), Some("JP"));
+        assert_eq!(dictionary.detect_region("狮城 Premium"), Some("SG"));
+        assert_eq!(
+            NameDictionary::default().detect_region("沪日 IEPL 01"),
+            None
+        );
+
+        let mut config: ClashConfig = serde_yaml::from_str(
+            "proxies:\n  - { name: 沪日 01, type: ss, server: a.example, port: 1, cipher: aes-128-gcm, password: x }\n",
+        )
+        .unwrap();
+        let rename = RenameConfig {
+            country: Some(CountryStyle::Emoji),
+            dictionary,
+            ..Default::default()
+        };
+        rename_proxies(&mut config, &rename).unwrap();
+        assert_eq!(config.proxy_names(), vec!["🇯🇵 沪日 01"]);
+    }
+
+    #[test]
+    fn renames_propagate_to_groups_rules_and_dialer_proxy() {
+        let mut config: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - { name: "香港 01 | 1.5x", type: ss, server: a.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: "香港 01 | 2x", type: ss, server: b.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: "日本【专线】02", type: ss, server: c.example, port: 1, cipher: aes-128-gcm, password: x, dialer-proxy: "香港 01 | 1.5x" }
+proxy-groups:
+  - { name: PROXY, type: select, proxies: ["香港 01 | 1.5x", "香港 01 | 2x", "日本【专线】02"] }
+rules:
+  - "DOMAIN-SUFFIX,example.com,日本【专线】02"
+  - "MATCH,PROXY"
+"#,
+        )
+        .unwrap();
+        let rename = RenameConfig {
+            rules: vec![
+                RenameRule {
+                    pattern: r"\s*\|\s*[\d.]+x$".to_string(),
+                    replace: String::new(),
+                },
+                RenameRule {
+                    pattern: "【(.+?)】".to_string(),
+                    replace: " $1 ".to_string(),
+                },
+            ],
+            country: Some(CountryStyle::Emoji),
+            ..Default::default()
+        };
+        let changes = rename_proxies(&mut config, &rename).unwrap();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(
+            config.proxy_names(),
+            vec!["🇭🇰 香港 01", "🇭🇰 香港 01-2", "🇯🇵 日本 专线 02"]
+        );
+        assert_eq!(
+            config.proxy_groups[0]["proxies"],
+            serde_yaml::from_str::<Value>("['🇭🇰 香港 01', '🇭🇰 香港 01-2', '🇯🇵 日本 专线 02']")
+                .unwrap()
+        );
+        assert_eq!(config.rules[0], "DOMAIN-SUFFIX,example.com,🇯🇵 日本 专线 02");
+        assert_eq!(config.rules[1], "MATCH,PROXY");
+        assert_eq!(config.proxies[2]["dialer-proxy"], Value::from("🇭🇰 香港 01"));
+
+        let bad = RenameConfig {
+            rules: vec![RenameRule {
+                pattern: "(".to_string(),
+                replace: String::new(),
+            }],
+            ..Default::default()
+        };
+        assert!(rename_proxies(&mut config, &bad).is_err());
+    }
+}
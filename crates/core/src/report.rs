@@ -0,0 +1,355 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use tokio::fs;
+
+use crate::diff::NodeChanges;
+use crate::merge::{rule_target, BUILTIN_TARGETS};
+use crate::model::ClashConfig;
+
+/// Category of a merge warning; serialized in kebab-case into report.json.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningKind {
+    /// A requested proxy/group did not exist and a fallback target was used.
+    FallbackVia,
+    /// A node was removed from the output.
+    DroppedNode,
+    /// Several proxies share a name.
+    DuplicateProxy,
+    /// A proxy-group name is used more than once.
+    DuplicateGroup,
+    /// An empty proxy-group was removed by `--prune-empty-groups`.
+    PrunedGroup,
+    /// The same rule appears more than once.
+    DuplicateRule,
+    /// A resource file (mmdb/geo data) looks outdated.
+    StaleResource,
+    /// A template or base-config uses a deprecated, renamed or unsupported key.
+    Deprecated,
+    /// A subscription failed to load or fell back to its cache.
+    Subscription,
+    /// A subscription is about to expire or has nearly used up its traffic quota.
+    SubscriptionUsage,
+    /// A post-merge hook failed to run or exited with an error.
+    Hook,
+    /// A rule, group or proxy refers to a proxy, group or provider that does not exist.
+    UnknownReference,
+}
+
+impl WarningKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WarningKind::FallbackVia => "fallback-via",
+            WarningKind::DroppedNode => "dropped-node",
+            WarningKind::DuplicateProxy => "duplicate-proxy",
+            WarningKind::DuplicateGroup => "duplicate-group",
+            WarningKind::PrunedGroup => "pruned-group",
+            WarningKind::DuplicateRule => "duplicate-rule",
+            WarningKind::StaleResource => "stale-resource",
+            WarningKind::Deprecated => "deprecated",
+            WarningKind::Subscription => "subscription",
+            WarningKind::SubscriptionUsage => "subscription-usage",
+            WarningKind::Hook => "hook",
+            WarningKind::UnknownReference => "unknown-reference",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+/// Collects warnings raised during a merge so they can be summarized at the end.
+///
+/// Every pushed warning is also logged through `tracing` as it happens.
+#[derive(Debug, Clone, Default)]
+pub struct Warnings {
+    items: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn push(&mut self, kind: WarningKind, message: impl Into<String>) {
+        let message = message.into();
+        tracing::warn!(kind = kind.as_str(), "{}", message);
+        self.items.push(Warning { kind, message });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.items.iter()
+    }
+
+    pub fn as_slice(&self) -> &[Warning] {
+        &self.items
+    }
+
+    /// Human-readable "N warnings" block; empty when there is nothing to report.
+    pub fn render_summary(&self) -> String {
+        if self.items.is_empty() {
+            return String::new();
+        }
+        let mut out = format!(
+            "{} warning{}:\n",
+            self.items.len(),
+            if self.items.len() == 1 { "" } else { "s" }
+        );
+        for warning in &self.items {
+            out.push_str(&format!(
+                "  - [{}] {}\n",
+                warning.kind.as_str(),
+                warning.message
+            ));
+        }
+        out
+    }
+}
+
+/// Machine-readable outcome of a merge, written to `output/report.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeReport {
+    pub generated_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    pub subscriptions: Vec<String>,
+    pub proxies: usize,
+    pub proxy_groups: usize,
+    pub rules: usize,
+    pub warnings: Vec<Warning>,
+    /// Nodes each subscription added, removed or renamed since the previous merge, by
+    /// subscription name; subscriptions without changes are left out.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_changes: BTreeMap<String, NodeChanges>,
+}
+
+impl MergeReport {
+    pub async fn write(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+/// Rules that occur more than once, each listed once in first-seen order.
+pub fn find_duplicate_rules(rules: &[String]) -> Vec<String> {
+    find_duplicates(rules)
+}
+
+/// Entries (rules, group names, ...) that occur more than once, each listed once in first-seen
+/// order.
+pub fn find_duplicates(items: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut reported = HashSet::new();
+    items
+        .iter()
+        .filter(|rule| !seen.insert(rule.as_str()) && reported.insert(rule.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// References to proxies, groups, sub-rules or providers that `config` does not define, one
+/// message per reference with its location, e.g.
+/// `rules[12] 'DOMAIN,a.com,Streaming': unknown proxy or group 'Streaming'`.
+pub fn find_unknown_references(config: &ClashConfig) -> Vec<String> {
+    let mut policies: HashSet<String> = config.proxy_names().into_iter().collect();
+    policies.extend(config.proxy_group_names());
+    // mihomo's implicit selector; rules and groups may route to it.
+    policies.insert("GLOBAL".to_string());
+    let is_policy = |name: &str| policies.contains(name) || BUILTIN_TARGETS.contains(&name);
+    let keys = |key: &str| -> HashSet<String> {
+        config
+            .extra
+            .get(key)
+            .and_then(Value::as_mapping)
+            .map(|map| {
+                map.keys()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let sub_rules = keys("sub-rules");
+    let proxy_providers = keys("proxy-providers");
+    let rule_providers = keys("rule-providers");
+
+    let mut found = Vec::new();
+    let mut check_rule = |location: String, rule: &str| {
+        let kind = rule.split(',').next().unwrap_or_default().trim();
+        if kind == "RULE-SET" {
+            if let Some(provider) = rule.split(',').nth(1).map(str::trim) {
+                if !rule_providers.contains(provider) {
+                    found.push(format!(
+                        "{} '{}': unknown rule-provider '{}'",
+                        location, rule, provider
+                    ));
+                }
+            }
+        }
+        let Some(target) = rule_target(rule) else {
+            return;
+        };
+        if kind == "SUB-RULE" {
+            if !sub_rules.contains(target) {
+                found.push(format!(
+                    "{} '{}': unknown sub-rule '{}'",
+                    location, rule, target
+                ));
+            }
+        } else if !is_policy(target) {
+            found.push(format!(
+                "{} '{}': unknown proxy or group '{}'",
+                location, rule, target
+            ));
+        }
+    };
+    for (idx, rule) in config.rules.iter().enumerate() {
+        check_rule(format!("rules[{}]", idx), rule);
+    }
+    if let Some(map) = config.extra.get("sub-rules").and_then(Value::as_mapping) {
+        for (name, rules) in map {
+            let name = name.as_str().unwrap_or_default();
+            for (idx, rule) in rules.as_sequence().into_iter().flatten().enumerate() {
+                if let Some(rule) = rule.as_str() {
+                    check_rule(format!("sub-rules.{}[{}]", name, idx), rule);
+                }
+            }
+        }
+    }
+
+    for group in &config.proxy_groups {
+        let name = group
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        for member in group
+            .get("proxies")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !is_policy(member) {
+                found.push(format!(
+                    "proxy-groups[{}].proxies: unknown proxy or group '{}'",
+                    name, member
+                ));
+            }
+        }
+        for provider in group
+            .get("use")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !proxy_providers.contains(provider) {
+                found.push(format!(
+                    "proxy-groups[{}].use: unknown proxy-provider '{}'",
+                    name, provider
+                ));
+            }
+        }
+    }
+
+    for proxy in &config.proxies {
+        if let Some(dialer) = proxy.get("dialer-proxy").and_then(Value::as_str) {
+            if !is_policy(dialer) {
+                let name = proxy
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                found.push(format!(
+                    "proxies[{}].dialer-proxy: unknown proxy or group '{}'",
+                    name, dialer
+                ));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_references_are_reported_with_location() {
+        let config: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - { name: A, type: ss, server: a.example, port: 1, cipher: aes-128-gcm, password: x }
+  - { name: B, type: ss, server: b.example, port: 1, cipher: aes-128-gcm, password: x, dialer-proxy: Relay }
+proxy-groups:
+  - { name: Proxy, type: select, proxies: [A, B, Auto, DIRECT] }
+  - { name: Providers, type: select, use: [airport, missing] }
+proxy-providers:
+  airport: { type: http, url: "https://a.example/p", path: ./p.yaml }
+sub-rules:
+  lan:
+    - IP-CIDR,10.0.0.0/8,DIRECT
+    - MATCH,Nowhere
+rules:
+  - DOMAIN-SUFFIX,a.com,Proxy
+  - IP-CIDR,1.1.1.1/32,GLOBAL,no-resolve
+  - RULE-SET,ads,REJECT
+  - SUB-RULE,(NETWORK,udp),wan
+  - MATCH,Streaming
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            find_unknown_references(&config),
+            vec![
+                "rules[2] 'RULE-SET,ads,REJECT': unknown rule-provider 'ads'",
+                "rules[3] 'SUB-RULE,(NETWORK,udp),wan': unknown sub-rule 'wan'",
+                "rules[4] 'MATCH,Streaming': unknown proxy or group 'Streaming'",
+                "sub-rules.lan[1] 'MATCH,Nowhere': unknown proxy or group 'Nowhere'",
+                "proxy-groups[Proxy].proxies: unknown proxy or group 'Auto'",
+                "proxy-groups[Providers].use: unknown proxy-provider 'missing'",
+                "proxies[B].dialer-proxy: unknown proxy or group 'Relay'",
+            ]
+        );
+    }
+
+    #[test]
+    fn summary_lists_each_warning() {
+        let mut warnings = Warnings::default();
+        assert_eq!(warnings.render_summary(), "");
+
+        warnings.push(WarningKind::DuplicateRule, "MATCH,DIRECT appears 2 times");
+        warnings.push(WarningKind::StaleResource, "geoip.dat is 40 days old");
+        assert_eq!(
+            warnings.render_summary(),
+            "2 warnings:\n  - [duplicate-rule] MATCH,DIRECT appears 2 times\n  - [stale-resource] geoip.dat is 40 days old\n"
+        );
+    }
+
+    #[test]
+    fn find_duplicate_rules_reports_each_once() {
+        let rules = vec![
+            "DOMAIN,a.com,DIRECT".to_string(),
+            "DOMAIN,a.com,DIRECT".to_string(),
+            "DOMAIN,a.com,DIRECT".to_string(),
+            "MATCH,Proxy".to_string(),
+        ];
+        assert_eq!(
+            find_duplicate_rules(&rules),
+            vec!["DOMAIN,a.com,DIRECT".to_string()]
+        );
+    }
+}
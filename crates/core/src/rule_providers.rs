@@ -0,0 +1,395 @@
+//! Large inline rule blocks moved into rule-provider files (`merge --extract-rule-providers`).
+//!
+//! A block is a run of consecutive rules that send traffic to the same target. Within a block
+//! order does not change where a request goes, so its rules are regrouped by behavior: `DOMAIN`
+//! and `DOMAIN-SUFFIX` into a `domain` provider, `IP-CIDR`/`IP-CIDR6` into `ipcidr` providers
+//! (one per `no-resolve` setting) and the rest into a `classical` one. mihomo matches the first
+//! two with tries instead of one rule at a time, which is what makes large lists load fast.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use crate::model::ClashConfig;
+
+/// Rule types a `classical` provider payload accepts.
+const CLASSICAL_TYPES: &[&str] = &[
+    "DOMAIN",
+    "DOMAIN-SUFFIX",
+    "DOMAIN-KEYWORD",
+    "DOMAIN-REGEX",
+    "DOMAIN-WILDCARD",
+    "GEOSITE",
+    "GEOIP",
+    "IP-CIDR",
+    "IP-CIDR6",
+    "IP-SUFFIX",
+    "IP-ASN",
+    "SRC-GEOIP",
+    "SRC-IP-ASN",
+    "SRC-IP-CIDR",
+    "SRC-IP-SUFFIX",
+    "DST-PORT",
+    "SRC-PORT",
+    "IN-PORT",
+    "IN-TYPE",
+    "IN-USER",
+    "IN-NAME",
+    "PROCESS-NAME",
+    "PROCESS-NAME-REGEX",
+    "PROCESS-PATH",
+    "PROCESS-PATH-REGEX",
+    "UID",
+    "NETWORK",
+    "DSCP",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RuleBehavior {
+    Domain,
+    Classical,
+    Ipcidr,
+}
+
+impl RuleBehavior {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RuleBehavior::Domain => "domain",
+            RuleBehavior::Classical => "classical",
+            RuleBehavior::Ipcidr => "ipcidr",
+        }
+    }
+}
+
+/// One provider file produced by [`extract_rule_providers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedRuleSet {
+    pub name: String,
+    pub behavior: RuleBehavior,
+    pub payload: Vec<String>,
+}
+
+impl ExtractedRuleSet {
+    pub fn file_name(&self) -> String {
+        format!("{}.yaml", self.name)
+    }
+
+    /// The provider file: `payload:` with one entry per rule.
+    pub fn to_yaml(&self) -> anyhow::Result<String> {
+        let mut file = Mapping::new();
+        file.insert("payload".into(), self.payload.clone().into());
+        Ok(serde_yaml::to_string(&file)?)
+    }
+}
+
+/// A `TYPE,value,target[,option...]` rule of a type providers can hold.
+struct SimpleRule<'a> {
+    kind: String,
+    value: &'a str,
+    target: &'a str,
+    options: Vec<&'a str>,
+}
+
+impl<'a> SimpleRule<'a> {
+    fn parse(rule: &'a str) -> Option<Self> {
+        let mut parts = rule.split(',').map(str::trim);
+        let kind = parts.next()?.to_ascii_uppercase();
+        if !CLASSICAL_TYPES.contains(&kind.as_str()) {
+            return None;
+        }
+        let value = parts.next().filter(|value| !value.is_empty())?;
+        let target = parts.next().filter(|target| !target.is_empty())?;
+        Some(Self {
+            kind,
+            value,
+            target,
+            options: parts.collect(),
+        })
+    }
+
+    /// Where this rule goes in a block, and its payload entry there.
+    fn placement(&self) -> (RuleBehavior, bool, String) {
+        let no_resolve = self.options == ["no-resolve"];
+        match self.kind.as_str() {
+            "DOMAIN" if self.options.is_empty() => {
+                (RuleBehavior::Domain, false, self.value.to_string())
+            }
+            "DOMAIN-SUFFIX" if self.options.is_empty() => {
+                (RuleBehavior::Domain, false, format!("+.{}", self.value))
+            }
+            "IP-CIDR" | "IP-CIDR6" if self.options.is_empty() || no_resolve => {
+                (RuleBehavior::Ipcidr, no_resolve, self.value.to_string())
+            }
+            _ => {
+                let mut entry = format!("{},{}", self.kind, self.value);
+                for option in &self.options {
+                    entry.push(',');
+                    entry.push_str(option);
+                }
+                (RuleBehavior::Classical, false, entry)
+            }
+        }
+    }
+}
+
+/// Replace every block of at least `min_rules` same-target rules with `RULE-SET` references
+/// to `file` providers under `dir`, adding them to `rule-providers`. Parts of a block with
+/// fewer than `min_rules` rules of one behavior stay inline. Returns the provider files to
+/// write.
+pub fn extract_rule_providers(
+    config: &mut ClashConfig,
+    min_rules: usize,
+    dir: &Path,
+) -> Vec<ExtractedRuleSet> {
+    let min_rules = min_rules.max(1);
+    let mut providers = match config.extra.get("rule-providers") {
+        Some(Value::Mapping(existing)) => existing.clone(),
+        _ => Mapping::new(),
+    };
+    let mut taken: HashSet<String> = providers
+        .keys()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect();
+    let mut extracted = Vec::new();
+    let mut rules = Vec::with_capacity(config.rules.len());
+
+    let mut index = 0;
+    while index < config.rules.len() {
+        let Some(first) = SimpleRule::parse(&config.rules[index]) else {
+            rules.push(config.rules[index].clone());
+            index += 1;
+            continue;
+        };
+        let target = first.target;
+        let end = config.rules[index..]
+            .iter()
+            .position(|rule| SimpleRule::parse(rule).is_none_or(|rule| rule.target != target))
+            .map_or(config.rules.len(), |offset| index + offset);
+        let block = &config.rules[index..end];
+        index = end;
+        if block.len() < min_rules {
+            rules.extend(block.iter().cloned());
+            continue;
+        }
+
+        let placed: Vec<(RuleBehavior, bool, String)> = block
+            .iter()
+            .map(|rule| {
+                SimpleRule::parse(rule)
+                    .expect("block rules parse")
+                    .placement()
+            })
+            .collect();
+        // (behavior, no-resolve) -> payload
+        let mut parts: BTreeMap<(RuleBehavior, bool), Vec<String>> = BTreeMap::new();
+        for (behavior, no_resolve, entry) in &placed {
+            parts
+                .entry((*behavior, *no_resolve))
+                .or_default()
+                .push(entry.clone());
+        }
+        let mut kept_inline = HashSet::new();
+        let mut references = Vec::new();
+        for ((behavior, no_resolve), payload) in parts {
+            if payload.len() < min_rules {
+                kept_inline.insert((behavior, no_resolve));
+                continue;
+            }
+            let name = unique_name(&mut taken, target, behavior);
+            let mut provider = Mapping::new();
+            provider.insert("type".into(), "file".into());
+            provider.insert("behavior".into(), behavior.as_str().into());
+            provider.insert(
+                "path".into(),
+                dir.join(format!("{}.yaml", name))
+                    .display()
+                    .to_string()
+                    .into(),
+            );
+            providers.insert(name.as_str().into(), Value::Mapping(provider));
+            references.push((
+                behavior,
+                format!(
+                    "RULE-SET,{},{}{}",
+                    name,
+                    target,
+                    if no_resolve { ",no-resolve" } else { "" }
+                ),
+            ));
+            extracted.push(ExtractedRuleSet {
+                name,
+                behavior,
+                payload,
+            });
+        }
+        // Domain and classical sets first; leftovers keep their order; IP sets, which may
+        // need a DNS lookup, go last.
+        let (ip, other): (Vec<_>, Vec<_>) = references
+            .into_iter()
+            .partition(|(behavior, _)| *behavior == RuleBehavior::Ipcidr);
+        rules.extend(other.into_iter().map(|(_, rule)| rule));
+        rules.extend(
+            block
+                .iter()
+                .zip(&placed)
+                .filter(|(_, (behavior, no_resolve, _))| {
+                    kept_inline.contains(&(*behavior, *no_resolve))
+                })
+                .map(|(rule, _)| rule.clone()),
+        );
+        rules.extend(ip.into_iter().map(|(_, rule)| rule));
+    }
+
+    if !extracted.is_empty() {
+        config.rules = rules;
+        config
+            .extra
+            .insert("rule-providers".to_string(), Value::Mapping(providers));
+    }
+    extracted
+}
+
+/// `<target>-<behavior>`, reduced to `[a-z0-9-]` and numbered when taken.
+fn unique_name(taken: &mut HashSet<String>, target: &str, behavior: RuleBehavior) -> String {
+    let mut slug = String::new();
+    for c in target.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    let base = format!(
+        "{}-{}",
+        if slug.is_empty() { "rules" } else { slug },
+        behavior.as_str()
+    );
+    let mut name = base.clone();
+    let mut counter = 2;
+    while taken.contains(&name) {
+        name = format!("{}-{}", base, counter);
+        counter += 1;
+    }
+    taken.insert(name.clone());
+    name
+}
+
+/// Where the provider files of the config at `config_path` go: `<stem>.rule-providers/` next
+/// to it, as an absolute path since mihomo resolves relative ones against its home directory.
+pub fn provider_dir(config_path: &Path) -> PathBuf {
+    let stem = config_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "config".to_string());
+    let dir = config_path.with_file_name(format!("{}.rule-providers", stem));
+    std::path::absolute(&dir).unwrap_or(dir)
+}
+
+/// Whether writing `sets` into `dir` would change anything there: a file differs or is missing,
+/// or a provider file from an earlier run is no longer produced.
+pub async fn rule_files_changed(dir: &Path, sets: &[ExtractedRuleSet]) -> bool {
+    for set in sets {
+        let current = tokio::fs::read_to_string(dir.join(set.file_name())).await;
+        let wanted = set.to_yaml().unwrap_or_default();
+        if current.ok().as_deref() != Some(wanted.as_str()) {
+            return true;
+        }
+    }
+    !stale_files(dir, sets).await.is_empty()
+}
+
+/// Write the provider files of `sets` into `dir` and remove ones earlier runs left behind.
+pub async fn write_rule_files(dir: &Path, sets: &[ExtractedRuleSet]) -> anyhow::Result<()> {
+    if !sets.is_empty() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    for set in sets {
+        crate::storage::write_atomic(&dir.join(set.file_name()), set.to_yaml()?.as_bytes()).await?;
+    }
+    for stale in stale_files(dir, sets).await {
+        tokio::fs::remove_file(&stale).await?;
+    }
+    Ok(())
+}
+
+async fn stale_files(dir: &Path, sets: &[ExtractedRuleSet]) -> Vec<std::path::PathBuf> {
+    let wanted: HashSet<String> = sets.iter().map(ExtractedRuleSet::file_name).collect();
+    let mut stale = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return stale;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".yaml") && !wanted.contains(&name) {
+            stale.push(entry.path());
+        }
+    }
+    stale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn large_blocks_become_rule_sets_by_behavior() {
+        let mut config = ClashConfig {
+            rules: vec!["DOMAIN-KEYWORD,ads,REJECT".to_string()],
+            ..Default::default()
+        };
+        for i in 0..3 {
+            config
+                .rules
+                .push(format!("DOMAIN-SUFFIX,cn{}.example,DIRECT", i));
+            config
+                .rules
+                .push(format!("IP-CIDR,10.{}.0.0/16,DIRECT,no-resolve", i));
+        }
+        config.rules.push("DOMAIN,exact.example,DIRECT".to_string());
+        config.rules.push("PROCESS-NAME,ssh,DIRECT".to_string());
+        config.rules.push("GEOIP,CN,REJECT".to_string());
+        config.rules.push("MATCH,REJECT".to_string());
+
+        let dir = tempfile::tempdir().unwrap();
+        let sets = extract_rule_providers(&mut config, 3, dir.path());
+        assert_eq!(
+            config.rules,
+            vec![
+                "DOMAIN-KEYWORD,ads,REJECT",
+                "RULE-SET,direct-domain,DIRECT",
+                "PROCESS-NAME,ssh,DIRECT",
+                "RULE-SET,direct-ipcidr,DIRECT,no-resolve",
+                "GEOIP,CN,REJECT",
+                "MATCH,REJECT",
+            ]
+        );
+        assert_eq!(sets.len(), 2);
+        assert_eq!(
+            sets[0].payload,
+            vec![
+                "+.cn0.example",
+                "+.cn1.example",
+                "+.cn2.example",
+                "exact.example"
+            ]
+        );
+        assert_eq!(sets[1].behavior, RuleBehavior::Ipcidr);
+        let providers = &config.extra["rule-providers"];
+        assert_eq!(
+            providers["direct-domain"]["behavior"].as_str(),
+            Some("domain")
+        );
+        assert!(crate::report::find_unknown_references(&config).is_empty());
+
+        assert!(rule_files_changed(dir.path(), &sets).await);
+        tokio::fs::write(dir.path().join("old-domain.yaml"), "payload: []\n")
+            .await
+            .unwrap();
+        write_rule_files(dir.path(), &sets).await.unwrap();
+        assert!(!rule_files_changed(dir.path(), &sets).await);
+        assert!(!dir.path().join("old-domain.yaml").exists());
+    }
+}
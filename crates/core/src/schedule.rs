@@ -0,0 +1,137 @@
+//! Daily time windows (`active_hours`) for rules that only apply part of the day.
+//!
+//! mihomo has no notion of time in its rules, so a windowed rule is included or left out when
+//! the config is merged; `merge --watch` re-merges at every window boundary.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+/// `HH:MM-HH:MM` in local time; a window whose end is before its start runs past midnight
+/// (`22:00-06:00`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> anyhow::Result<Self> {
+        let (start, end) = raw
+            .split_once('-')
+            .ok_or_else(|| anyhow!("invalid time window '{}' (expected HH:MM-HH:MM)", raw))?;
+        let parse = |part: &str| {
+            NaiveTime::parse_from_str(part.trim(), "%H:%M")
+                .map_err(|_| anyhow!("invalid time '{}' in window '{}'", part.trim(), raw))
+        };
+        let window = Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+        if window.start == window.end {
+            return Err(anyhow!("time window '{}' is empty", raw));
+        }
+        Ok(window)
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: String) -> anyhow::Result<Self> {
+        raw.parse()
+    }
+}
+
+impl From<TimeWindow> for String {
+    fn from(window: TimeWindow) -> Self {
+        window.to_string()
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Whether `time` falls in any of `windows`; no windows means always.
+pub fn is_active(windows: &[TimeWindow], time: NaiveTime) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(time))
+}
+
+/// The first window start or end strictly after `now`, within the next day.
+pub fn next_boundary<Tz: TimeZone>(
+    windows: &[TimeWindow],
+    now: &DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    let today = now.date_naive();
+    windows
+        .iter()
+        .flat_map(|window| [window.start, window.end])
+        .flat_map(|time| {
+            [
+                today.and_time(time),
+                (today + Duration::days(1)).and_time(time),
+            ]
+        })
+        .filter_map(|naive| now.timezone().from_local_datetime(&naive).earliest())
+        .filter(|at| at > now)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn windows_wrap_midnight_and_report_next_boundary() {
+        let night: TimeWindow = "22:00-06:00".parse().unwrap();
+        assert!(night.contains(at(23, 30)));
+        assert!(night.contains(at(5, 59)));
+        assert!(!night.contains(at(6, 0)));
+        assert!(!night.contains(at(12, 0)));
+        let lunch: TimeWindow = "12:00-13:30".parse().unwrap();
+        assert!(lunch.contains(at(12, 0)) && !lunch.contains(at(13, 30)));
+        assert!(is_active(&[], at(3, 0)));
+        assert_eq!(lunch.to_string(), "12:00-13:30");
+        assert!("25:00-01:00".parse::<TimeWindow>().is_err());
+        assert!("10:00-10:00".parse::<TimeWindow>().is_err());
+
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 45, 0).unwrap();
+        let next = next_boundary(&[night, lunch], &now).unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2026, 3, 1, 13, 30, 0).unwrap());
+        let late = Utc.with_ymd_and_hms(2026, 3, 1, 23, 0, 0).unwrap();
+        assert_eq!(
+            next_boundary(&[night], &late).unwrap(),
+            Utc.with_ymd_and_hms(2026, 3, 2, 6, 0, 0).unwrap()
+        );
+        assert!(next_boundary(&[], &late).is_none());
+    }
+}
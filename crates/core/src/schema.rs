@@ -0,0 +1,146 @@
+//! Offline compatibility check of generated configs against bundled mihomo schema snapshots.
+//!
+//! Each snapshot is a JSON schema describing the keys a mihomo release parses strictly
+//! (types, enums, required proxy fields). It cannot replace `mihomo -t`, but it runs without
+//! the binary and pins what the merge output must keep satisfying across releases.
+
+use std::fmt;
+
+use anyhow::anyhow;
+use jsonschema::{Draft, JSONSchema};
+
+use crate::model::ClashConfig;
+
+/// Bundled snapshots, oldest first: (version, schema JSON).
+const SNAPSHOTS: &[(&str, &str)] = &[("v1.19", include_str!("../schemas/mihomo-v1.19.json"))];
+
+/// Versions accepted by [`validate_against_schema`].
+pub fn schema_versions() -> Vec<&'static str> {
+    SNAPSHOTS.iter().map(|(version, _)| *version).collect()
+}
+
+/// A place where the config does not match the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// JSON pointer into the config, e.g. `/proxies/3/port`.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {}", path, self.message)
+    }
+}
+
+/// Validate `config` against the snapshot for `version` (`v1.19`, `1.19` or a patch release
+/// such as `v1.19.3`). Returns every violation; an empty list means the config conforms.
+pub fn validate_against_schema(
+    config: &ClashConfig,
+    version: &str,
+) -> anyhow::Result<Vec<SchemaViolation>> {
+    let wanted = minor_version(version);
+    let (snapshot, raw) = SNAPSHOTS
+        .iter()
+        .find(|(known, _)| minor_version(known) == wanted)
+        .ok_or_else(|| {
+            anyhow!(
+                "no schema snapshot for mihomo {} (available: {})",
+                version,
+                schema_versions().join(", ")
+            )
+        })?;
+    let schema: serde_json::Value = serde_json::from_str(raw)?;
+    let compiled = JSONSchema::options()
+        .with_draft(Draft::Draft7)
+        .compile(&schema)
+        .map_err(|err| anyhow!("bundled schema {} is invalid: {}", snapshot, err))?;
+
+    let instance = serde_json::to_value(config)?;
+    let violations = match compiled.validate(&instance) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .map(|err| SchemaViolation {
+                path: err.instance_path.to_string(),
+                message: err.to_string(),
+            })
+            .collect(),
+    };
+    Ok(violations)
+}
+
+fn minor_version(version: &str) -> String {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    version.splitn(3, '.').take(2).collect::<Vec<_>>().join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::merge_configs;
+
+    #[test]
+    fn merged_output_round_trips_against_snapshot() {
+        let template: ClashConfig =
+            serde_yaml::from_str(include_str!("../../../examples/cvr_template.yaml")).unwrap();
+        let sub: ClashConfig = serde_yaml::from_str(
+            r#"
+proxies:
+  - { name: HK 01, type: ss, server: hk.example.com, port: 443, cipher: aes-128-gcm, password: x }
+  - { name: JP 01, type: vmess, server: jp.example.com, port: "8443", uuid: 00000000-0000-0000-0000-000000000000, alterId: 0, cipher: auto }
+proxy-groups:
+  - { name: PROXY, type: select, proxies: [HK 01, JP 01] }
+rules:
+  - DOMAIN-SUFFIX,example.com,PROXY
+  - MATCH,DIRECT
+"#,
+        )
+        .unwrap();
+        let merged = merge_configs(template, vec![sub]);
+        let reparsed: ClashConfig =
+            serde_yaml::from_str(&merged.to_yaml_string().unwrap()).unwrap();
+
+        for version in schema_versions() {
+            let violations = validate_against_schema(&reparsed, version).unwrap();
+            assert!(violations.is_empty(), "{version}: {violations:?}");
+        }
+        assert!(validate_against_schema(&reparsed, "v1.19.2")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn violations_point_at_offending_keys() {
+        let config: ClashConfig = serde_yaml::from_str(
+            r#"
+mixed-port: 70000
+mode: rules
+proxies:
+  - { name: broken, type: ss, server: a.example.com, port: 443 }
+proxy-groups:
+  - { name: Empty, type: select }
+rules:
+  - not a rule
+"#,
+        )
+        .unwrap();
+        let violations = validate_against_schema(&config, "1.19").unwrap();
+        let paths: Vec<&str> = violations.iter().map(|v| v.path.as_str()).collect();
+        for expected in [
+            "/mixed-port",
+            "/mode",
+            "/proxies/0",
+            "/proxy-groups/0",
+            "/rules/0",
+        ] {
+            assert!(paths.contains(&expected), "{expected} missing in {paths:?}");
+        }
+
+        assert!(validate_against_schema(&config, "v1.10").is_err());
+    }
+}
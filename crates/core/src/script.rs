@@ -0,0 +1,221 @@
+//! clash-verge style Script profiles.
+//!
+//! A script defines `function main(config, profileName)` and returns the (possibly modified)
+//! config object. It runs in an embedded JS engine with no filesystem or network access;
+//! `console.log`/`info`/`warn`/`error` are forwarded to tracing.
+
+use anyhow::{anyhow, Context as _};
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{js_string, Context, JsResult, JsValue, NativeFunction, Source};
+
+use crate::model::ClashConfig;
+
+/// Guards against runaway scripts; generous for anything that only reshapes a config.
+const LOOP_ITERATION_LIMIT: u64 = 10_000_000;
+const RECURSION_LIMIT: usize = 512;
+
+/// Run `main(config, profile_name)` from `source` against `config` and return its result.
+pub fn run_script(
+    source: &str,
+    config: ClashConfig,
+    profile_name: &str,
+) -> anyhow::Result<ClashConfig> {
+    let input = serde_json::to_value(&config).context("failed to convert config to JSON")?;
+
+    let mut context = Context::default();
+    context
+        .runtime_limits_mut()
+        .set_loop_iteration_limit(LOOP_ITERATION_LIMIT);
+    context
+        .runtime_limits_mut()
+        .set_recursion_limit(RECURSION_LIMIT);
+    install_console(&mut context)?;
+
+    context
+        .eval(Source::from_bytes(source))
+        .map_err(|err| anyhow!("script failed to load: {}", err))?;
+    let main = context
+        .global_object()
+        .get(js_string!("main"), &mut context)
+        .map_err(|err| anyhow!("{}", err))?;
+    let Some(main) = main.as_callable() else {
+        return Err(anyhow!("script does not define a main(config) function"));
+    };
+
+    let arg = JsValue::from_json(&input, &mut context).map_err(|err| anyhow!("{}", err))?;
+    let result = main
+        .call(
+            &JsValue::undefined(),
+            &[arg, JsValue::from(js_string!(profile_name))],
+            &mut context,
+        )
+        .map_err(|err| anyhow!("script main() threw: {}", err))?;
+    if !result.is_object() {
+        return Err(anyhow!(
+            "script main() must return the config object, got {}",
+            result.type_of()
+        ));
+    }
+    let output = result
+        .to_json(&mut context)
+        .map_err(|err| anyhow!("failed to read script result: {}", err))?;
+    serde_json::from_value(output).context("script returned an invalid config")
+}
+
+/// Join several scripts into one whose `main` runs them in order, for consumers that take a
+/// single script (Clash Verge allows one Script per profile). Each script keeps its own scope.
+pub fn chain_scripts(sources: &[&str]) -> String {
+    if let [single] = sources {
+        return single.to_string();
+    }
+    let mut out = String::from("const __mihomoCliSteps = [\n");
+    for source in sources {
+        out.push_str("(function () {\n");
+        out.push_str(source);
+        out.push_str("\n;return main;\n})(),\n");
+    }
+    out.push_str(
+        "];\n\nfunction main(config, profileName) {\n  return __mihomoCliSteps.reduce((config, step) => step(config, profileName), config);\n}\n",
+    );
+    out
+}
+
+fn install_console(context: &mut Context) -> anyhow::Result<()> {
+    let console = ObjectInitializer::new(context)
+        .function(
+            NativeFunction::from_fn_ptr(console_info),
+            js_string!("log"),
+            0,
+        )
+        .function(
+            NativeFunction::from_fn_ptr(console_info),
+            js_string!("info"),
+            0,
+        )
+        .function(
+            NativeFunction::from_fn_ptr(console_warn),
+            js_string!("warn"),
+            0,
+        )
+        .function(
+            NativeFunction::from_fn_ptr(console_warn),
+            js_string!("error"),
+            0,
+        )
+        .build();
+    context
+        .register_global_property(js_string!("console"), console, Attribute::all())
+        .map_err(|err| anyhow!("{}", err))
+}
+
+fn console_info(_: &JsValue, args: &[JsValue], _: &mut Context) -> JsResult<JsValue> {
+    tracing::info!(target: "script", "{}", console_line(args));
+    Ok(JsValue::undefined())
+}
+
+fn console_warn(_: &JsValue, args: &[JsValue], _: &mut Context) -> JsResult<JsValue> {
+    tracing::warn!(target: "script", "{}", console_line(args));
+    Ok(JsValue::undefined())
+}
+
+fn console_line(args: &[JsValue]) -> String {
+    args.iter()
+        .map(|arg| match arg.as_string() {
+            Some(text) => text.to_std_string_escaped(),
+            None => arg.display().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml::Value;
+
+    fn sample() -> ClashConfig {
+        serde_yaml::from_str(
+            r#"
+mode: rule
+proxies:
+  - { name: HK 01, type: ss, server: hk.example.com, port: 443, cipher: aes-128-gcm, password: x }
+  - { name: 剩余流量：10GB, type: ss, server: info.example.com, port: 443, cipher: aes-128-gcm, password: x }
+proxy-groups:
+  - { name: PROXY, type: select, proxies: [HK 01, 剩余流量：10GB] }
+rules:
+  - MATCH,PROXY
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn script_rewrites_config() {
+        let script = r#"
+function main(config, profileName) {
+  console.log("running for", profileName);
+  const info = /剩余流量/;
+  config.proxies = config.proxies.filter(p => !info.test(p.name));
+  for (const group of config["proxy-groups"]) {
+    group.proxies = group.proxies.filter(name => !info.test(name));
+  }
+  config.rules.unshift("DOMAIN-SUFFIX,example.com,DIRECT");
+  config["allow-lan"] = profileName === "home";
+  return config;
+}
+"#;
+        let out = run_script(script, sample(), "home").unwrap();
+        assert_eq!(out.proxies.len(), 1);
+        assert_eq!(out.rules[0], "DOMAIN-SUFFIX,example.com,DIRECT");
+        assert_eq!(out.extra.get("mode"), Some(&Value::from("rule")));
+        assert_eq!(out.extra.get("allow-lan"), Some(&Value::from(true)));
+        let group = out.proxy_groups[0].as_mapping().unwrap();
+        assert_eq!(
+            group.get("proxies").unwrap().as_sequence().unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn chained_scripts_run_in_order() {
+        let first = "function main(c) { c.rules.unshift('DOMAIN,a.example,DIRECT'); return c; }";
+        let second = "const tag = 'b'; function main(c, name) { c.rules.unshift(`DOMAIN,${tag}.example,${name}`); return c; }";
+        let chained = chain_scripts(&[first, second]);
+        let out = run_script(&chained, sample(), "DIRECT").unwrap();
+        assert_eq!(
+            out.rules,
+            vec![
+                "DOMAIN,b.example,DIRECT".to_string(),
+                "DOMAIN,a.example,DIRECT".to_string(),
+                "MATCH,PROXY".to_string(),
+            ]
+        );
+        assert_eq!(chain_scripts(&[first]), first);
+    }
+
+    #[test]
+    fn script_errors_are_reported() {
+        let err = run_script("const x = 1;", sample(), "p").unwrap_err();
+        assert!(err.to_string().contains("main"), "{err}");
+
+        let err = run_script("function main(c) { return 1; }", sample(), "p").unwrap_err();
+        assert!(err.to_string().contains("must return"), "{err}");
+
+        let err = run_script(
+            "function main(c) { throw new Error('boom'); }",
+            sample(),
+            "p",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("boom"), "{err}");
+
+        let err = run_script(
+            "function f() { return f(); } function main(c) { return f(); }",
+            sample(),
+            "p",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("threw"), "{err}");
+    }
+}
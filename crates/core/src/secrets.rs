@@ -0,0 +1,107 @@
+//! Secrets kept in the OS keyring (Secret Service on Linux, the macOS Keychain, the Windows
+//! Credential Manager) instead of plaintext YAML under `~/.config`.
+//!
+//! app.yaml and subscriptions.yaml hold a `keyring:<name>` reference in place of the value; it
+//! is looked up under the [`KEYRING_SERVICE`] service when used. Keyring calls block, so they
+//! run on tokio's blocking pool.
+
+use anyhow::{anyhow, Context};
+
+/// Service name the entries are stored under.
+pub const KEYRING_SERVICE: &str = "mihomocli";
+
+/// Prefix marking a value as a keyring reference.
+pub const KEYRING_PREFIX: &str = "keyring:";
+
+/// Entry name of the generated external-controller secret after `manage secrets migrate`.
+pub const CONTROLLER_SECRET_ENTRY: &str = "controller-secret";
+
+/// Name of the keyring entry `value` refers to, if it is a `keyring:<name>` reference.
+pub fn keyring_name(value: &str) -> Option<&str> {
+    value
+        .strip_prefix(KEYRING_PREFIX)
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// The reference stored in YAML for entry `name`.
+pub fn keyring_reference(name: &str) -> String {
+    format!("{}{}", KEYRING_PREFIX, name)
+}
+
+/// `value` itself, or the secret a `keyring:<name>` reference points to.
+pub async fn resolve_secret(value: &str) -> anyhow::Result<String> {
+    match keyring_name(value) {
+        Some(name) => read_secret(name).await,
+        None => Ok(value.to_string()),
+    }
+}
+
+/// Store `secret` as entry `name`, replacing any previous value.
+pub async fn store_secret(name: &str, secret: &str) -> anyhow::Result<()> {
+    keyring_op(name, Op::Set(secret.to_string()))
+        .await
+        .with_context(|| format!("failed to store keyring entry '{}'", name))?;
+    Ok(())
+}
+
+/// Delete entry `name`.
+pub async fn delete_secret(name: &str) -> anyhow::Result<()> {
+    keyring_op(name, Op::Delete)
+        .await
+        .with_context(|| format!("failed to delete keyring entry '{}'", name))?;
+    Ok(())
+}
+
+async fn read_secret(name: &str) -> anyhow::Result<String> {
+    keyring_op(name, Op::Get)
+        .await
+        .with_context(|| format!("failed to read keyring entry '{}'", name))
+}
+
+#[cfg_attr(not(feature = "keyring"), allow(dead_code))]
+enum Op {
+    Get,
+    Set(String),
+    Delete,
+}
+
+/// Run `op` on entry `name`; returns the stored value for [`Op::Get`], else an empty string.
+#[cfg(feature = "keyring")]
+async fn keyring_op(name: &str, op: Op) -> anyhow::Result<String> {
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || {
+        let entry = keyring::Entry::new(KEYRING_SERVICE, &name)?;
+        match op {
+            Op::Get => entry.get_password(),
+            Op::Set(secret) => entry.set_password(&secret).map(|_| String::new()),
+            Op::Delete => entry.delete_credential().map(|_| String::new()),
+        }
+    })
+    .await?
+    .map_err(|err| anyhow!(err))
+}
+
+#[cfg(not(feature = "keyring"))]
+async fn keyring_op(_name: &str, _op: Op) -> anyhow::Result<String> {
+    Err(anyhow!(
+        "this mihomo-cli was built without keyring support (enable the `keyring` feature)"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn plain_values_pass_through_and_references_are_recognised() {
+        assert_eq!(keyring_name("keyring:airport"), Some("airport"));
+        assert_eq!(keyring_name("keyring:"), None);
+        assert_eq!(keyring_name("https://example.com/sub?token=x"), None);
+        assert_eq!(keyring_reference("airport"), "keyring:airport");
+        assert_eq!(
+            resolve_secret("https://example.com/sub").await.unwrap(),
+            "https://example.com/sub"
+        );
+    }
+}
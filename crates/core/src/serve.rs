@@ -0,0 +1,272 @@
+//! `serve`: the merged config over HTTP, so routers and phones can import it like a provider
+//! subscription.
+//!
+//! Every request re-reads the config file, so whatever the last merge wrote is served. Clients
+//! authenticate with `?token=` or `Authorization: Bearer`. Responses carry an `ETag` (answered
+//! with 304 on `If-None-Match`), the combined `subscription-userinfo` of the subscriptions and,
+//! when the server re-merges on a schedule, `profile-update-interval`.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Context;
+use hyper::header::{
+    HeaderValue, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::subscription::SubscriptionUsage;
+
+/// What the server hands out and to whom.
+#[derive(Debug)]
+pub struct ServeOptions {
+    token: String,
+    config_path: PathBuf,
+    update_interval: Option<Duration>,
+    usage: RwLock<Option<SubscriptionUsage>>,
+}
+
+impl ServeOptions {
+    /// Serve `config_path` to clients presenting `token`; `update_interval` is how often the
+    /// file is re-merged, if at all.
+    pub fn new(token: String, config_path: PathBuf, update_interval: Option<Duration>) -> Self {
+        Self {
+            token,
+            config_path,
+            update_interval,
+            usage: RwLock::new(None),
+        }
+    }
+
+    /// Replace the usage reported in `subscription-userinfo`.
+    pub fn set_usage(&self, usage: Option<SubscriptionUsage>) {
+        *self
+            .usage
+            .write()
+            .unwrap_or_else(|poison| poison.into_inner()) = usage;
+    }
+
+    fn usage(&self) -> Option<SubscriptionUsage> {
+        *self
+            .usage
+            .read()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+/// Answer requests on `listen` until `shutdown` completes, then stop accepting connections and
+/// give open requests up to `drain_timeout` to finish.
+pub async fn serve(
+    listen: SocketAddr,
+    options: Arc<ServeOptions>,
+    shutdown: impl Future<Output = ()>,
+    drain_timeout: Duration,
+) -> anyhow::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let options = options.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let options = options.clone();
+                async move { Ok::<_, Infallible>(respond(&request, &options).await) }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&listen)
+        .with_context(|| format!("failed to listen on {}", listen))?
+        .serve(make_service);
+    tracing::info!(listen = %server.local_addr(), "serving the merged config");
+    let (stopping, stopped) = tokio::sync::oneshot::channel();
+    let server = server.with_graceful_shutdown(async move {
+        shutdown.await;
+        let _ = stopping.send(());
+    });
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => Ok(result?),
+        Ok(()) = stopped => match tokio::time::timeout(drain_timeout, server).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(anyhow::anyhow!(
+                "open requests did not finish within the {}s drain timeout",
+                drain_timeout.as_secs()
+            )),
+        },
+    }
+}
+
+async fn respond(request: &Request<Body>, options: &ServeOptions) -> Response<Body> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return plain(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only GET and HEAD are supported",
+        );
+    }
+    if !matches!(request.uri().path(), "/" | "/config.yaml") {
+        return plain(StatusCode::NOT_FOUND, "not found");
+    }
+    if !authorized(request, &options.token) {
+        return plain(StatusCode::UNAUTHORIZED, "missing or wrong token");
+    }
+    let body = match tokio::fs::read(&options.config_path).await {
+        Ok(body) => body,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return plain(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "no merged config yet; run a merge first",
+            );
+        }
+        Err(err) => {
+            tracing::warn!(
+                path = %options.config_path.display(),
+                error = %err,
+                "failed to read the served config"
+            );
+            return plain(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to read the config",
+            );
+        }
+    };
+    let etag = format!("\"{}\"", &hex::encode(Sha256::digest(&body))[..32]);
+
+    let mut response = Response::builder().header(ETAG, &etag);
+    if let Some(usage) = options.usage() {
+        response = response.header("subscription-userinfo", usage.header_value());
+    }
+    if let Some(interval) = options.update_interval {
+        // Clients read this as whole hours.
+        let hours = interval.as_secs().div_ceil(3600).max(1);
+        response = response.header("profile-update-interval", hours.to_string());
+    }
+    let not_modified = request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if not_modified {
+        return response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap_or_default();
+    }
+    let filename = options
+        .config_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("config.yaml");
+    if let Ok(disposition) =
+        HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+    {
+        response = response.header(CONTENT_DISPOSITION, disposition);
+    }
+    let body = if request.method() == Method::HEAD {
+        Body::empty()
+    } else {
+        Body::from(body)
+    };
+    response
+        .header(CONTENT_TYPE, "text/yaml; charset=utf-8")
+        .body(body)
+        .unwrap_or_default()
+}
+
+/// Whether the request carries `token` in `?token=` or as a bearer token.
+fn authorized(request: &Request<Body>, token: &str) -> bool {
+    let from_query = request.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "token")
+            .map(|(_, value)| value.into_owned())
+    });
+    let from_header = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|value| value.trim().to_string());
+    from_query
+        .into_iter()
+        .chain(from_header)
+        .any(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn plain(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(format!("{}\n", message)))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_config_with_token_etag_and_userinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clash-verge.yaml");
+        let options = ServeOptions::new(
+            "s3cret".to_string(),
+            path.clone(),
+            Some(Duration::from_secs(6 * 3600)),
+        );
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        assert_eq!(
+            respond(&get("/?token=s3cret"), &options).await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        tokio::fs::write(&path, "proxies: []\n").await.unwrap();
+        assert_eq!(
+            respond(&get("/?token=wrong"), &options).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            respond(&get("/other?token=s3cret"), &options)
+                .await
+                .status(),
+            StatusCode::NOT_FOUND
+        );
+
+        options.set_usage(SubscriptionUsage::sum([
+            SubscriptionUsage::parse("upload=1; download=2; total=100; expire=1800000000").unwrap(),
+            SubscriptionUsage::parse("upload=3; download=4; total=50; expire=1700000000").unwrap(),
+        ]));
+        let bearer = Request::get("/config.yaml")
+            .header(AUTHORIZATION, "Bearer s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let response = respond(&bearer, &options).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers["subscription-userinfo"],
+            "upload=4; download=6; total=150; expire=1700000000"
+        );
+        assert_eq!(headers["profile-update-interval"], "6");
+        let etag = headers[ETAG].clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"proxies: []\n");
+
+        let cached = Request::get("/?token=s3cret")
+            .header(IF_NONE_MATCH, etag)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            respond(&cached, &options).await.status(),
+            StatusCode::NOT_MODIFIED
+        );
+    }
+}
@@ -1,13 +1,179 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
-use anyhow::anyhow;
-use directories::BaseDirs;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, NaiveTime, Utc};
+use directories::{BaseDirs, ProjectDirs};
 use serde::{Deserialize, Serialize};
 use tokio::fs;
 
+use crate::merge::{BaseSection, HealthCheckUrls};
+use crate::region::RegionLookupConfig;
+use crate::rename::RenameConfig;
+use crate::schedule::{self, TimeWindow};
 use crate::subscription::Subscription;
 
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Forbid writes to storage for the rest of the process (`--read-only`). Caches, usage history
+/// and other by-products are then skipped silently; explicit writes fail with
+/// [`ensure_writable`].
+pub fn set_read_only(enabled: bool) {
+    READ_ONLY.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// Environment variable naming the config directory; `--config-dir` wins over it.
+pub const CONFIG_DIR_ENV: &str = "MIHOMOCLI_CONFIG_DIR";
+
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `dir` as the config directory for the rest of the process (`--config-dir`). Only the
+/// first call has an effect.
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR.set(dir);
+}
+
+fn config_dir_override() -> Option<PathBuf> {
+    CONFIG_DIR.get().cloned().or_else(|| {
+        env::var_os(CONFIG_DIR_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    })
+}
+
+/// `current` after moving `legacy` there, when only `legacy` exists. In read-only mode, or
+/// when the move fails, `legacy` keeps being used instead.
+fn adopt_legacy_dir(legacy: PathBuf, current: PathBuf) -> PathBuf {
+    if legacy == current || !legacy.is_dir() || current.exists() {
+        return current;
+    }
+    if is_read_only() {
+        return legacy;
+    }
+    let moved = if current.starts_with(&legacy) {
+        move_into_subdir(&legacy, &current)
+    } else {
+        match current.parent() {
+            Some(parent) => std::fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|()| std::fs::rename(&legacy, &current))
+    };
+    match moved {
+        Ok(()) => {
+            tracing::warn!(from = %legacy.display(), to = %current.display(), "moved the mihomo-cli directory to the platform default location");
+            current
+        }
+        Err(err) => {
+            tracing::warn!(from = %legacy.display(), to = %current.display(), error = %err, "failed to move mihomo-cli directory; using the old location");
+            legacy
+        }
+    }
+}
+
+/// Move `legacy` to `current`, which lies inside it (`%APPDATA%\mihomocli` becoming
+/// `%APPDATA%\mihomocli\config` on Windows): rename it aside, recreate the parents and rename
+/// it into place, putting it back if the second step fails.
+fn move_into_subdir(legacy: &Path, current: &Path) -> std::io::Result<()> {
+    let mut aside = legacy.as_os_str().to_owned();
+    aside.push(format!(".moving-{}", std::process::id()));
+    let aside = PathBuf::from(aside);
+    std::fs::rename(legacy, &aside)?;
+    let moved = match current.parent() {
+        Some(parent) => std::fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+    .and_then(|()| std::fs::rename(&aside, current));
+    if moved.is_err() {
+        let _ = std::fs::remove_dir(legacy);
+        let _ = std::fs::rename(&aside, legacy);
+    }
+    moved
+}
+
+/// Fail when read-only mode is on; call before writing `path`.
+pub fn ensure_writable(path: &Path) -> anyhow::Result<()> {
+    if is_read_only() {
+        return Err(anyhow!(
+            "read-only mode: refusing to write {}",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it into place, so a crash
+/// mid-write leaves the previous file intact. Creates the parent directory.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    write_atomic_with(path, contents, false).await
+}
+
+/// [`write_atomic`] for files that may hold credentials (app.yaml): on Unix the new file is
+/// readable and writable by its owner only (0600).
+pub async fn write_atomic_private(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    write_atomic_with(path, contents, true).await
+}
+
+async fn write_atomic_with(path: &Path, contents: &[u8], private: bool) -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} has no file name", path.display()))?
+        .to_string_lossy();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).await?;
+    }
+    // Same directory as the target so the rename stays on one filesystem and is atomic.
+    let tmp = path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+    let result = async {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if private {
+            options.mode(0o600);
+        }
+        #[cfg(not(unix))]
+        let _ = private;
+        let mut file = options.open(&tmp).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&tmp, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp).await;
+    }
+    result.with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// `schema_version` written to app.yaml. Bump it when a field changes meaning; files
+/// without the field (version 0) predate versioning and load unchanged.
+pub const APP_CONFIG_SCHEMA_VERSION: u32 = 1;
+
+/// `schema_version` written to subscriptions.yaml; see [`APP_CONFIG_SCHEMA_VERSION`].
+pub const SUBSCRIPTION_LIST_SCHEMA_VERSION: u32 = 1;
+
+fn warn_if_newer(path: &Path, found: u32, supported: u32) {
+    if found > supported {
+        tracing::warn!(
+            "{} was written by a newer mihomo-cli (schema_version {}, this build knows {}); \
+             settings it does not know are kept but ignored",
+            path.display(),
+            found,
+            supported
+        );
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AppPaths {
     config_dir: PathBuf,
@@ -15,22 +181,54 @@ pub struct AppPaths {
 }
 
 impl AppPaths {
+    /// The config directory from `--config-dir` ([`set_config_dir`]) or
+    /// [`CONFIG_DIR_ENV`], else the platform default: `$XDG_CONFIG_HOME/mihomocli` on Linux,
+    /// `~/Library/Application Support/mihomocli` on macOS and `%APPDATA%\mihomocli\config` on
+    /// Windows. Directories left at the location used before the platform defaults are moved
+    /// over the first time the defaults are used.
     pub fn new() -> anyhow::Result<Self> {
-        let base = BaseDirs::new().ok_or_else(|| anyhow!("failed to resolve base directories"))?;
-        let (config_dir, cache_dir) = if cfg!(target_os = "windows") {
-            (
-                base.config_dir().join("mihomocli"),
-                base.cache_dir().join("mihomocli/subscriptions"),
-            )
-        } else {
-            (
-                base.home_dir().join(".config/mihomocli"),
-                base.home_dir().join(".cache/mihomocli/subscriptions"),
-            )
-        };
-        Ok(Self {
-            config_dir,
+        let project = ProjectDirs::from("", "", "mihomocli")
+            .ok_or_else(|| anyhow!("failed to resolve base directories"))?;
+        let cache_dir = project.cache_dir().join("subscriptions");
+        if let Some(config_dir) = config_dir_override() {
+            return Ok(Self {
+                config_dir,
+                cache_dir,
+            });
+        }
+        let mut paths = Self {
+            config_dir: project.config_dir().to_path_buf(),
             cache_dir,
+        };
+        if let Some(legacy) = Self::legacy() {
+            paths.config_dir = adopt_legacy_dir(legacy.config_dir, paths.config_dir);
+            paths.cache_dir = adopt_legacy_dir(legacy.cache_dir, paths.cache_dir);
+        }
+        Ok(paths)
+    }
+
+    /// `config/` and `cache/` under `root`, for tests that must not touch the real directories.
+    #[cfg(test)]
+    pub(crate) fn under(root: &Path) -> Self {
+        Self {
+            config_dir: root.join("config"),
+            cache_dir: root.join("cache"),
+        }
+    }
+
+    /// The fixed locations used before the platform defaults.
+    fn legacy() -> Option<Self> {
+        let base = BaseDirs::new()?;
+        Some(if cfg!(target_os = "windows") {
+            Self {
+                config_dir: base.config_dir().join("mihomocli"),
+                cache_dir: base.cache_dir().join("mihomocli/subscriptions"),
+            }
+        } else {
+            Self {
+                config_dir: base.home_dir().join(".config/mihomocli"),
+                cache_dir: base.home_dir().join(".cache/mihomocli/subscriptions"),
+            }
         })
     }
 
@@ -66,6 +264,41 @@ impl AppPaths {
         self.config_dir.join("output/clash-verge.yaml")
     }
 
+    /// Machine-local overrides deep-merged onto every generated config.
+    pub fn override_path(&self) -> PathBuf {
+        self.config_dir.join("override.yaml")
+    }
+
+    /// Named `merge` profiles selected with `merge --profile`.
+    pub fn profiles_path(&self) -> PathBuf {
+        self.config_dir.join("profiles.yaml")
+    }
+
+    /// Where `template add` records the source of each named template.
+    pub fn templates_index_path(&self) -> PathBuf {
+        self.config_dir.join("templates.yaml")
+    }
+
+    /// File of a template added with `template add`.
+    pub fn named_template_path(&self, name: &str) -> PathBuf {
+        self.templates_dir().join(format!("{name}.yaml"))
+    }
+
+    /// Machine-readable summary of the last merge (warnings, counts).
+    pub fn report_path(&self) -> PathBuf {
+        self.config_dir.join("output/report.json")
+    }
+
+    /// Previously generated configs kept for `history rollback`.
+    pub fn history_dir(&self) -> PathBuf {
+        self.config_dir.join("output/history")
+    }
+
+    /// Timestamped `subscription-userinfo` samples of a subscription, one JSON object per line.
+    pub fn usage_history_file(&self, id: &str) -> PathBuf {
+        self.config_dir.join("usage").join(format!("{id}.jsonl"))
+    }
+
     pub fn cache_dir(&self) -> &Path {
         &self.cache_dir
     }
@@ -78,7 +311,30 @@ impl AppPaths {
         self.cache_dir.join(format!("{id}.meta.json"))
     }
 
+    /// IP-geo API answers saved by region lookups.
+    pub fn region_cache_file(&self) -> PathBuf {
+        self.cache_dir.join("region-lookup.json")
+    }
+
+    /// Per-node reachability recorded across merge runs (`merge --exclude-dead-after`).
+    pub fn node_liveness_file(&self) -> PathBuf {
+        self.cache_dir.join("node-liveness.json")
+    }
+
+    /// Node names and endpoints a subscription delivered at the last merge.
+    pub fn cache_nodes_file(&self, id: &str) -> PathBuf {
+        self.cache_dir.join(format!("{id}.nodes.json"))
+    }
+
+    /// Saved cookies of a subscription with `persist_cookies`.
+    pub fn cache_cookies_file(&self, id: &str) -> PathBuf {
+        self.cache_dir.join(format!("{id}.cookies.json"))
+    }
+
     pub async fn ensure_runtime_dirs(&self) -> anyhow::Result<()> {
+        if is_read_only() {
+            return Ok(());
+        }
         fs::create_dir_all(self.config_dir()).await?;
         fs::create_dir_all(self.templates_dir()).await?;
         fs::create_dir_all(self.resources_dir()).await?;
@@ -89,6 +345,41 @@ impl AppPaths {
         Ok(())
     }
 
+    /// Pid of the mihomo started by `run`.
+    pub fn mihomo_pid_path(&self) -> PathBuf {
+        self.config_dir.join("mihomo.pid")
+    }
+
+    /// Output of a mihomo started by `run` in the background.
+    pub fn mihomo_log_path(&self) -> PathBuf {
+        self.config_dir.join("mihomo.log")
+    }
+
+    /// Rule-provider payloads written by `rules import`.
+    pub fn rule_sets_dir(&self) -> PathBuf {
+        self.config_dir.join("rules")
+    }
+
+    pub fn rule_set_path(&self, name: &str) -> PathBuf {
+        self.rule_sets_dir().join(format!("{name}.yaml"))
+    }
+
+    /// Binaries installed by `core install`.
+    pub fn bin_dir(&self) -> PathBuf {
+        self.config_dir.join("bin")
+    }
+
+    /// The mihomo binary managed by `core install`.
+    pub fn managed_mihomo_path(&self) -> PathBuf {
+        self.bin_dir()
+            .join(format!("mihomo{}", std::env::consts::EXE_SUFFIX))
+    }
+
+    /// Web dashboards installed by `ui install` live under `ui/<name>`.
+    pub fn ui_dir(&self) -> PathBuf {
+        self.config_dir.join("ui")
+    }
+
     pub fn resource_file<S: AsRef<str>>(&self, name: S) -> PathBuf {
         self.resources_dir().join(name.as_ref())
     }
@@ -120,7 +411,7 @@ impl AppPaths {
         candidates
     }
 
-    fn home_dir_fallback(&self) -> PathBuf {
+    pub fn home_dir_fallback(&self) -> PathBuf {
         if cfg!(target_os = "windows") {
             env::var_os("USERPROFILE")
                 .map(PathBuf::from)
@@ -176,29 +467,58 @@ impl AppPaths {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SubscriptionList {
+    /// See [`SUBSCRIPTION_LIST_SCHEMA_VERSION`].
+    #[serde(default)]
+    pub schema_version: u32,
     pub current: Option<String>,
     #[serde(default)]
     pub items: Vec<Subscription>,
+    /// Keys this version does not know (from a newer mihomo-cli), written back unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 impl SubscriptionList {
     pub fn enabled(&self) -> impl Iterator<Item = &Subscription> {
         self.items.iter().filter(|sub| sub.enabled)
     }
+
+    /// Disable every enabled subscription whose consecutive failures reached `threshold`
+    /// (0 never disables). Returns the ids that were disabled.
+    pub fn disable_failing(&mut self, threshold: u32) -> Vec<String> {
+        if threshold == 0 {
+            return Vec::new();
+        }
+        self.items
+            .iter_mut()
+            .filter(|sub| sub.enabled && sub.failure_count >= threshold)
+            .map(|sub| {
+                sub.enabled = false;
+                sub.id.clone()
+            })
+            .collect()
+    }
+
+    /// Find a subscription by id or, failing that, by name.
+    pub fn find_mut(&mut self, key: &str) -> Option<&mut Subscription> {
+        let idx = self
+            .items
+            .iter()
+            .position(|sub| sub.id == key)
+            .or_else(|| self.items.iter().position(|sub| sub.name == key))?;
+        self.items.get_mut(idx)
+    }
 }
 
 pub async fn load_subscription_list(paths: &AppPaths) -> anyhow::Result<SubscriptionList> {
-    match fs::read_to_string(paths.subscriptions_file()).await {
-        Ok(contents) => {
-            let list: SubscriptionList = serde_yaml::from_str(&contents)?;
-            Ok(list)
-        }
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+    let path = paths.subscriptions_file();
+    match read_subscription_list(&path).await? {
+        Some(list) => Ok(list),
+        None => {
             let list = SubscriptionList::default();
             save_subscription_list(paths, &list).await?;
             Ok(list)
         }
-        Err(err) => Err(err.into()),
     }
 }
 
@@ -206,18 +526,45 @@ pub async fn save_subscription_list(
     paths: &AppPaths,
     list: &SubscriptionList,
 ) -> anyhow::Result<()> {
-    let yaml = serde_yaml::to_string(list)?;
-    if let Some(parent) = paths.subscriptions_file().parent() {
-        fs::create_dir_all(parent).await?;
+    save_subscription_list_to(&paths.subscriptions_file(), list).await
+}
+
+/// [`load_subscription_list`] for a list outside the config dir (`merge --subscriptions-file`);
+/// a missing file is an empty list and is not created.
+pub async fn load_subscription_list_from(path: &Path) -> anyhow::Result<SubscriptionList> {
+    Ok(read_subscription_list(path).await?.unwrap_or_default())
+}
+
+/// [`save_subscription_list`] for a list outside the config dir.
+pub async fn save_subscription_list_to(path: &Path, list: &SubscriptionList) -> anyhow::Result<()> {
+    ensure_writable(path)?;
+    let mut list = list.clone();
+    // Never downgrade a file written by a newer version; its unknown keys are still in `extra`.
+    list.schema_version = list.schema_version.max(SUBSCRIPTION_LIST_SCHEMA_VERSION);
+    write_atomic(path, serde_yaml::to_string(&list)?.as_bytes()).await
+}
+
+async fn read_subscription_list(path: &Path) -> anyhow::Result<Option<SubscriptionList>> {
+    match fs::read_to_string(path).await {
+        Ok(contents) => {
+            let list: SubscriptionList = serde_yaml::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            warn_if_newer(path, list.schema_version, SUBSCRIPTION_LIST_SCHEMA_VERSION);
+            Ok(Some(list))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
     }
-    fs::write(paths.subscriptions_file(), yaml).await?;
-    Ok(())
 }
 
 // App configuration (simple key-value plus custom rules)
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct AppConfig {
+    /// See [`APP_CONFIG_SCHEMA_VERSION`].
+    #[serde(default)]
+    pub schema_version: u32,
+
     #[serde(default)]
     pub last_subscription_url: Option<String>,
 
@@ -242,6 +589,228 @@ pub struct AppConfig {
     /// live inside app.yaml.
     #[serde(default)]
     pub manual_servers: Vec<ManualServerRef>,
+
+    /// Extra inbound listeners from `manage listeners`, upserted by name into the output's
+    /// `listeners:` section.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerDef>,
+
+    /// Static host entries from `manage hosts`, merged into the output's `hosts:` section.
+    /// A name mapped to several addresses is written as a list.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, Vec<String>>,
+
+    /// Proxy rename rules applied on every merge, after those from the template's `rename:`.
+    #[serde(default, skip_serializing_if = "RenameConfig::is_empty")]
+    pub rename: RenameConfig,
+
+    /// Preferred proxy-group order for merged output. Groups listed here come first;
+    /// used when `merge` is not given an explicit `--group-order-list`.
+    #[serde(default)]
+    pub group_order: Vec<String>,
+
+    /// Disable a subscription after this many consecutive fetch failures (0 = never).
+    /// Overridden by `merge --disable-after-failures`.
+    #[serde(default)]
+    pub disable_after_failures: u32,
+
+    /// Secret generated for external-controller when none was configured; reused on later
+    /// merges so clients keep working.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub controller_secret: Option<String>,
+
+    /// Dashboard installed via `ui install`; injected as `external-ui` on merge.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_ui: Option<ExternalUi>,
+
+    /// Parts of the base-config to inherit (`ports`, `dns`, `tun`, `groups`, `rules`, `other`);
+    /// empty means the default set (everything except rules). Overridden by
+    /// `merge --base-sections`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub base_sections: Vec<BaseSection>,
+
+    /// Health-check URLs for merged groups (a default plus per-group entries); extended by
+    /// `merge --health-check-url`/`--group-health-check-url`.
+    #[serde(default, skip_serializing_if = "HealthCheckUrls::is_empty")]
+    pub health_check_urls: HealthCheckUrls,
+
+    /// Group name → icon URL for groups without an `icon`, such as the generated region and
+    /// provider groups; extended by `merge --group-icon`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub group_icons: BTreeMap<String, String>,
+
+    /// Named `merge` flag bundles, e.g. `router: ["--output", "/etc/mihomo/config.yaml"]`,
+    /// applied with `merge --preset router`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub presets: BTreeMap<String, Vec<String>>,
+
+    /// Template (name from `template add`) used when `merge --template` is omitted; unset
+    /// means the bundled default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// Alternative outputs produced by `merge --variants a,b`: each entry is a profiles.yaml
+    /// style bundle (template, flags, output) applied on top of the typed flags.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<String, MergeProfile>,
+
+    /// Shell commands run after every successful merge/deploy, in addition to
+    /// `merge --post-hook`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_hooks: Vec<String>,
+
+    /// When `merge` downloads geodata into the resources dir.
+    #[serde(default, skip_serializing_if = "ResourcesConfig::is_default")]
+    pub resources: ResourcesConfig,
+
+    /// Ordered mirror templates for GitHub downloads (`{url}` = GitHub itself); empty means
+    /// the built-in list.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub github_mirrors: Vec<String>,
+
+    /// Mirrors for geodata only, tried before `github_mirrors`; a plain base URL serves the
+    /// files under their own names (`https://mirror.corp/geo` -> `.../geo/geoip.dat`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resource_mirrors: Vec<String>,
+
+    /// Mirror that answered the last probe; tried first on the next download.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_github_mirror: Option<String>,
+
+    /// Devices fed from one merge with `merge --devices router,laptop`; each gets the shared
+    /// config plus its own settings in a separate file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub devices: BTreeMap<String, DeviceProfile>,
+
+    /// Block lists imported by `rules import`, merged as inline rule-providers routed to
+    /// `via`; the domains live in `rules/<name>.yaml`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rule_sets: BTreeMap<String, ImportedRuleSet>,
+
+    /// Region sources for `--auto-region-groups` beyond Country.mmdb: an offline CSV table
+    /// and an IP-geo API; overridden by `merge --region-csv`/`--region-api`.
+    #[serde(default, skip_serializing_if = "RegionLookupConfig::is_default")]
+    pub region_lookup: RegionLookupConfig,
+
+    /// Keys this version does not know (from a newer mihomo-cli), written back unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ImportedRuleSet {
+    /// Path or URL the list was imported from.
+    pub source: String,
+    pub via: String,
+}
+
+/// Device-specific settings applied on top of the merged config (after override.yaml).
+/// Unset fields keep the merged value.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct DeviceProfile {
+    /// Where this device's config goes; defaults to the merge output with the device name
+    /// inserted (`clash-verge.router.yaml`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+
+    /// `tun.enable`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tun: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_lan: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mixed_port: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socks_port: Option<u16>,
+
+    /// `dns.listen`, e.g. `0.0.0.0:53` on a router.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_listen: Option<String>,
+
+    /// Any other keys, deep-merged last the same way as override.yaml.
+    #[serde(
+        default,
+        rename = "override",
+        skip_serializing_if = "serde_yaml::Value::is_null"
+    )]
+    pub overrides: serde_yaml::Value,
+}
+
+impl DeviceProfile {
+    /// The device settings as one override document for [`crate::merge::apply_override`].
+    pub fn overlay(&self) -> serde_yaml::Value {
+        use serde_yaml::{Mapping, Value};
+
+        let mut overlay = Mapping::new();
+        let mut nested = |section: &str, key: &str, value: Value| {
+            let mut inner = Mapping::new();
+            inner.insert(Value::from(key), value);
+            overlay.insert(Value::from(section), Value::Mapping(inner));
+        };
+        if let Some(tun) = self.tun {
+            nested("tun", "enable", Value::from(tun));
+        }
+        if let Some(listen) = self.dns_listen.as_deref() {
+            nested("dns", "listen", Value::from(listen));
+        }
+        let scalars = [
+            ("allow-lan", self.allow_lan.map(Value::from)),
+            ("mixed-port", self.mixed_port.map(Value::from)),
+            ("port", self.port.map(Value::from)),
+            ("socks-port", self.socks_port.map(Value::from)),
+        ];
+        for (key, value) in scalars {
+            if let Some(value) = value {
+                overlay.insert(Value::from(key), value);
+            }
+        }
+        let mut overlay = Value::Mapping(overlay);
+        if !self.overrides.is_null() {
+            crate::merge::deep_merge(&mut overlay, &self.overrides);
+        }
+        overlay
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct ResourcesConfig {
+    #[serde(default)]
+    pub mode: ResourcesMode,
+}
+
+impl ResourcesConfig {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Geodata download policy for `merge`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourcesMode {
+    /// Never download (the target provides its own geodata).
+    Never,
+    /// Download only the files the merged config references (GEOIP/GEOSITE rules, geo DNS
+    /// policies).
+    Lazy,
+    /// Download every missing file before merging.
+    #[default]
+    Always,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExternalUi {
+    /// Dashboard flavour (`metacubexd`, `yacd`).
+    pub name: String,
+    pub path: PathBuf,
+    /// Archive URL; written as `external-ui-url` so mihomo can refresh the dashboard itself.
+    pub url: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -255,6 +824,63 @@ pub struct ManualServerRef {
     pub enabled: bool,
 }
 
+/// A mihomo inbound listener (`listeners:` entry) managed from the CLI.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ListenerDef {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ListenerKind,
+    pub port: u16,
+    /// Address to bind; `0.0.0.0` exposes the port on every interface.
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    /// Send everything from this listener through one proxy or group, bypassing rules.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(default = "default_true")]
+    pub udp: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerKind {
+    Mixed,
+    Socks,
+    Http,
+}
+
+impl ListenerKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ListenerKind::Mixed => "mixed",
+            ListenerKind::Socks => "socks",
+            ListenerKind::Http => "http",
+        }
+    }
+}
+
+impl ListenerDef {
+    /// The entry as written under mihomo's `listeners:`.
+    pub fn to_value(&self) -> serde_yaml::Value {
+        let mut map = serde_yaml::Mapping::new();
+        map.insert("name".into(), self.name.as_str().into());
+        map.insert("type".into(), self.kind.as_str().into());
+        map.insert("port".into(), self.port.into());
+        map.insert("listen".into(), self.listen.as_str().into());
+        if self.kind != ListenerKind::Http {
+            map.insert("udp".into(), self.udp.into());
+        }
+        if let Some(proxy) = &self.proxy {
+            map.insert("proxy".into(), proxy.as_str().into());
+        }
+        serde_yaml::Value::Mapping(map)
+    }
+}
+
+fn default_listen() -> String {
+    "127.0.0.1".to_string()
+}
+
 fn default_true() -> bool {
     true
 }
@@ -279,7 +905,7 @@ pub struct TailscaleCompatDefaults {
     pub route_exclude_address: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "kebab-case")]
 pub enum RuleKind {
     Domain,
@@ -297,23 +923,138 @@ pub struct CustomRule {
     #[serde(default = "default_rule_kind")]
     pub kind: RuleKind,
     pub via: String,
+    /// Local time windows in which the rule is merged (`["20:00-02:00"]`); empty means
+    /// always.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub active_hours: Vec<TimeWindow>,
+}
+
+impl CustomRule {
+    pub fn is_active_at(&self, time: NaiveTime) -> bool {
+        schedule::is_active(&self.active_hours, time)
+    }
+}
+
+/// One entry of `profiles.yaml`: the inputs and output of a `merge` run.
+///
+/// Paths are interpreted like the matching `merge` flags; unset fields fall back to the usual
+/// defaults.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct MergeProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_config: Option<PathBuf>,
+
+    /// Subscriptions (id or name) from subscriptions.yaml to merge; empty means all enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subscriptions: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev_rules: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev_rules_via: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+
+    /// Any further `merge` flags, e.g. `["--mode", "global", "--keep-backups", "5"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+/// Domains of an imported rule set (`payload:` of a `behavior: domain` rule-provider).
+pub async fn load_rule_set(paths: &AppPaths, name: &str) -> anyhow::Result<Vec<String>> {
+    #[derive(Deserialize)]
+    struct RuleSetFile {
+        #[serde(default)]
+        payload: Vec<String>,
+    }
+
+    let path = paths.rule_set_path(name);
+    let raw = fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let file: RuleSetFile = serde_yaml::from_str(&raw)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(file.payload)
+}
+
+pub async fn save_rule_set(paths: &AppPaths, name: &str, payload: &[String]) -> anyhow::Result<()> {
+    let path = paths.rule_set_path(name);
+    ensure_writable(&path)?;
+    let mut doc = serde_yaml::Mapping::new();
+    doc.insert("payload".into(), payload.to_vec().into());
+    write_atomic(&path, serde_yaml::to_string(&doc)?.as_bytes()).await
+}
+
+/// Load `profiles.yaml` (profile name -> [`MergeProfile`]); a missing file means no profiles.
+pub async fn load_profiles(paths: &AppPaths) -> anyhow::Result<BTreeMap<String, MergeProfile>> {
+    match fs::read_to_string(paths.profiles_path()).await {
+        Ok(raw) if raw.trim().is_empty() => Ok(BTreeMap::new()),
+        Ok(raw) => serde_yaml::from_str(&raw)
+            .with_context(|| format!("failed to parse {}", paths.profiles_path().display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// A template downloaded or copied by `template add`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TemplateEntry {
+    /// URL or absolute path it was added from.
+    pub source: String,
+    /// Hex SHA-256 of the stored file.
+    pub sha256: String,
+    pub added: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+/// Load `templates.yaml` (template name -> [`TemplateEntry`]); a missing file means none.
+pub async fn load_template_index(
+    paths: &AppPaths,
+) -> anyhow::Result<BTreeMap<String, TemplateEntry>> {
+    let path = paths.templates_index_path();
+    match fs::read_to_string(&path).await {
+        Ok(raw) if raw.trim().is_empty() => Ok(BTreeMap::new()),
+        Ok(raw) => serde_yaml::from_str(&raw)
+            .with_context(|| format!("failed to parse {}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn save_template_index(
+    paths: &AppPaths,
+    index: &BTreeMap<String, TemplateEntry>,
+) -> anyhow::Result<()> {
+    let path = paths.templates_index_path();
+    ensure_writable(&path)?;
+    write_atomic(&path, serde_yaml::to_string(index)?.as_bytes()).await
 }
 
 pub async fn load_app_config(paths: &AppPaths) -> anyhow::Result<AppConfig> {
-    match fs::read_to_string(paths.app_config_path()).await {
-        Ok(raw) => Ok(serde_yaml::from_str(&raw)?),
+    let path = paths.app_config_path();
+    match fs::read_to_string(&path).await {
+        Ok(raw) => {
+            let cfg: AppConfig = serde_yaml::from_str(&raw)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            warn_if_newer(&path, cfg.schema_version, APP_CONFIG_SCHEMA_VERSION);
+            Ok(cfg)
+        }
         Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(AppConfig::default()),
         Err(err) => Err(err.into()),
     }
 }
 
 pub async fn save_app_config(paths: &AppPaths, cfg: &AppConfig) -> anyhow::Result<()> {
-    if let Some(parent) = paths.app_config_path().parent() {
-        fs::create_dir_all(parent).await?;
-    }
-    let yaml = serde_yaml::to_string(cfg)?;
-    fs::write(paths.app_config_path(), yaml).await?;
-    Ok(())
+    let path = paths.app_config_path();
+    ensure_writable(&path)?;
+    let mut cfg = cfg.clone();
+    cfg.schema_version = cfg.schema_version.max(APP_CONFIG_SCHEMA_VERSION);
+    write_atomic_private(&path, serde_yaml::to_string(&cfg)?.as_bytes()).await
 }
 
 #[cfg(test)]
@@ -322,12 +1063,48 @@ mod tests {
     use tempfile::TempDir;
 
     fn create_test_paths(temp_dir: &TempDir) -> AppPaths {
-        let config_dir = temp_dir.path().join("config");
-        let cache_dir = temp_dir.path().join("cache");
-        AppPaths {
-            config_dir,
-            cache_dir,
-        }
+        AppPaths::under(temp_dir.path())
+    }
+
+    #[test]
+    fn legacy_directory_is_moved_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let legacy = temp_dir.path().join("home/.config/mihomocli");
+        let current = temp_dir
+            .path()
+            .join("Library/Application Support/mihomocli");
+        std::fs::create_dir_all(&legacy).unwrap();
+        std::fs::write(legacy.join("app.yaml"), "schema_version: 1\n").unwrap();
+
+        assert_eq!(adopt_legacy_dir(legacy.clone(), current.clone()), current);
+        assert!(current.join("app.yaml").is_file());
+        assert!(!legacy.exists());
+
+        // A legacy directory reappearing next to the new one is left alone.
+        std::fs::create_dir_all(&legacy).unwrap();
+        assert_eq!(adopt_legacy_dir(legacy.clone(), current.clone()), current);
+        assert!(legacy.exists());
+    }
+
+    #[test]
+    fn legacy_directory_is_moved_into_its_own_subdirectory() {
+        // Windows: %APPDATA%\mihomocli becomes %APPDATA%\mihomocli\config.
+        let temp_dir = TempDir::new().unwrap();
+        let legacy = temp_dir.path().join("AppData/Roaming/mihomocli");
+        let current = legacy.join("config");
+        std::fs::create_dir_all(legacy.join("templates")).unwrap();
+        std::fs::write(legacy.join("app.yaml"), "schema_version: 1\n").unwrap();
+
+        assert_eq!(adopt_legacy_dir(legacy.clone(), current.clone()), current);
+        assert!(current.join("app.yaml").is_file());
+        assert!(current.join("templates").is_dir());
+        let left: Vec<_> = std::fs::read_dir(&legacy)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["config"]);
+        let siblings = std::fs::read_dir(legacy.parent().unwrap()).unwrap().count();
+        assert_eq!(siblings, 1);
     }
 
     #[tokio::test]
@@ -390,6 +1167,31 @@ mod tests {
         assert!(paths.output_config_path().parent().unwrap().exists());
     }
 
+    #[test]
+    fn disable_failing_respects_threshold() {
+        let mut list = SubscriptionList {
+            current: None,
+            items: vec![
+                Subscription {
+                    id: "dead".to_string(),
+                    failure_count: 3,
+                    ..Default::default()
+                },
+                Subscription {
+                    id: "flaky".to_string(),
+                    failure_count: 1,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        assert!(list.disable_failing(0).is_empty());
+        assert_eq!(list.disable_failing(3), vec!["dead".to_string()]);
+        assert!(!list.items[0].enabled);
+        assert!(list.items[1].enabled);
+    }
+
     #[tokio::test]
     async fn test_load_save_subscription_list() {
         let temp_dir = TempDir::new().unwrap();
@@ -409,12 +1211,10 @@ mod tests {
                 name: "Test Subscription".to_string(),
                 url: Some("https://example.com/sub".to_string()),
                 path: None,
-                last_updated: None,
-                etag: None,
-                last_modified: None,
-                kind: crate::subscription::SubscriptionKind::Clash,
                 enabled: true,
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
         save_subscription_list(&paths, &new_list).await.unwrap();
@@ -435,35 +1235,27 @@ mod tests {
                     name: "Enabled 1".to_string(),
                     url: Some("https://example.com/1".to_string()),
                     path: None,
-                    last_updated: None,
-                    etag: None,
-                    last_modified: None,
-                    kind: crate::subscription::SubscriptionKind::Clash,
                     enabled: true,
+                    ..Default::default()
                 },
                 Subscription {
                     id: "disabled".to_string(),
                     name: "Disabled".to_string(),
                     url: Some("https://example.com/2".to_string()),
                     path: None,
-                    last_updated: None,
-                    etag: None,
-                    last_modified: None,
-                    kind: crate::subscription::SubscriptionKind::Clash,
                     enabled: false,
+                    ..Default::default()
                 },
                 Subscription {
                     id: "enabled2".to_string(),
                     name: "Enabled 2".to_string(),
                     url: Some("https://example.com/3".to_string()),
                     path: None,
-                    last_updated: None,
-                    etag: None,
-                    last_modified: None,
-                    kind: crate::subscription::SubscriptionKind::Clash,
                     enabled: true,
+                    ..Default::default()
                 },
             ],
+            ..Default::default()
         };
 
         let enabled: Vec<_> = list.enabled().collect();
@@ -472,6 +1264,56 @@ mod tests {
         assert_eq!(enabled[1].id, "enabled2");
     }
 
+    #[tokio::test]
+    async fn saves_keep_unknown_keys_and_stamp_schema_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = create_test_paths(&temp_dir);
+        fs::create_dir_all(paths.config_dir()).await.unwrap();
+        fs::write(
+            paths.app_config_path(),
+            "schema_version: 7\ncustom_rules: []\nfuture_setting:\n  nested: true\n",
+        )
+        .await
+        .unwrap();
+        fs::write(
+            paths.subscriptions_file(),
+            "current: null\nitems:\n- id: a\n  name: A\n  future_field: 3\n",
+        )
+        .await
+        .unwrap();
+
+        let mut cfg = load_app_config(&paths).await.unwrap();
+        cfg.group_order.push("Proxy".to_string());
+        save_app_config(&paths, &cfg).await.unwrap();
+        let raw = fs::read_to_string(paths.app_config_path()).await.unwrap();
+        assert!(raw.starts_with("schema_version: 7\n"));
+        assert!(raw.contains("future_setting:\n  nested: true\n"));
+        assert!(raw.contains("- Proxy\n"));
+
+        let list = load_subscription_list(&paths).await.unwrap();
+        assert_eq!(list.schema_version, 0);
+        save_subscription_list(&paths, &list).await.unwrap();
+        let raw = fs::read_to_string(paths.subscriptions_file())
+            .await
+            .unwrap();
+        assert!(raw.starts_with("schema_version: 1\n"));
+        assert!(raw.contains("  future_field: 3\n"));
+
+        let custom = temp_dir.path().join("elsewhere/subs.yaml");
+        let empty = load_subscription_list_from(&custom).await.unwrap();
+        assert!(empty.items.is_empty() && !custom.exists());
+        save_subscription_list_to(&custom, &list).await.unwrap();
+        let reloaded = load_subscription_list_from(&custom).await.unwrap();
+        assert_eq!(reloaded.schema_version, SUBSCRIPTION_LIST_SCHEMA_VERSION);
+        assert_eq!(reloaded.items.len(), 1);
+
+        let mut entries = fs::read_dir(paths.config_dir()).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            assert!(!name.contains(".tmp-"), "left {} behind", name);
+        }
+    }
+
     #[tokio::test]
     async fn test_load_save_app_config() {
         let temp_dir = TempDir::new().unwrap();
@@ -494,11 +1336,13 @@ mod tests {
                     domain: "example.com".to_string(),
                     kind: RuleKind::Domain,
                     via: "PROXY".to_string(),
+                    active_hours: Vec::new(),
                 },
                 CustomRule {
                     domain: "google.com".to_string(),
                     kind: RuleKind::DomainSuffix,
                     via: "DIRECT".to_string(),
+                    active_hours: vec!["22:00-06:00".parse().unwrap()],
                 },
             ],
             managed_tailscale_compat: Some(ManagedTailscaleCompat {
@@ -517,6 +1361,7 @@ mod tests {
                 attach_groups: vec!["BosLife".to_string()],
                 enabled: true,
             }],
+            ..Default::default()
         };
 
         save_app_config(&paths, &new_config).await.unwrap();
@@ -548,6 +1393,16 @@ mod tests {
         );
         assert_eq!(loaded.manual_servers.len(), 1);
         assert_eq!(loaded.manual_servers[0].name, "jp-vultr");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(paths.app_config_path())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
     }
 
     #[tokio::test]
@@ -570,12 +1425,47 @@ via: PROXY
         assert_eq!(rule.kind, RuleKind::DomainSuffix); // Default
     }
 
+    #[test]
+    fn test_device_profile_overlay() {
+        let yaml = r#"
+tun: true
+allow_lan: true
+mixed_port: 7893
+dns_listen: 0.0.0.0:53
+override:
+  dns:
+    enhanced-mode: redir-host
+  tun:
+    stack: system
+"#;
+        let device: DeviceProfile = serde_yaml::from_str(yaml).unwrap();
+        let overlay = device.overlay();
+        assert_eq!(overlay["tun"]["enable"], serde_yaml::Value::from(true));
+        assert_eq!(overlay["tun"]["stack"], serde_yaml::Value::from("system"));
+        assert_eq!(
+            overlay["dns"]["listen"],
+            serde_yaml::Value::from("0.0.0.0:53")
+        );
+        assert_eq!(
+            overlay["dns"]["enhanced-mode"],
+            serde_yaml::Value::from("redir-host")
+        );
+        assert_eq!(overlay["mixed-port"], serde_yaml::Value::from(7893));
+        assert!(overlay.get("port").is_none());
+        assert!(DeviceProfile::default()
+            .overlay()
+            .as_mapping()
+            .unwrap()
+            .is_empty());
+    }
+
     #[tokio::test]
     async fn test_custom_rule_serialization() {
         let rule = CustomRule {
             domain: "test.com".to_string(),
             kind: RuleKind::DomainKeyword,
             via: "REJECT".to_string(),
+            active_hours: Vec::new(),
         };
 
         let yaml = serde_yaml::to_string(&rule).unwrap();
@@ -598,4 +1488,29 @@ via: PROXY
         let yaml_keyword = serde_yaml::to_string(&RuleKind::DomainKeyword).unwrap();
         assert!(yaml_keyword.contains("domain-keyword"));
     }
+    #[tokio::test]
+    async fn test_load_profiles() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = create_test_paths(&temp_dir);
+        assert!(load_profiles(&paths).await.unwrap().is_empty());
+
+        fs::create_dir_all(paths.config_dir()).await.unwrap();
+        let yaml = r#"
+router:
+  template: router.yaml
+  subscriptions: [main, backup]
+  dev_rules: false
+  output: /etc/mihomo/config.yaml
+laptop:
+  args: ["--mode", "global"]
+"#;
+        fs::write(paths.profiles_path(), yaml).await.unwrap();
+        let profiles = load_profiles(&paths).await.unwrap();
+        assert_eq!(profiles.len(), 2);
+        let router = &profiles["router"];
+        assert_eq!(router.template, Some(PathBuf::from("router.yaml")));
+        assert_eq!(router.subscriptions, vec!["main", "backup"]);
+        assert_eq!(router.dev_rules, Some(false));
+        assert_eq!(profiles["laptop"].args, vec!["--mode", "global"]);
+    }
 }
@@ -0,0 +1,129 @@
+//! Fetch coordination for long-running modes (`merge --watch`, the HTTP server).
+//!
+//! Every cache entry has an async lock held from reading its meta to writing the new cache
+//! files, so concurrent loads of one subscription never interleave their writes. While a
+//! refresh window is set, a successful download is also handed to every load of the same
+//! subscription within that window instead of fetching again: callers that raced the first
+//! fetch wait on the lock and then reuse its result.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use super::LoadSource;
+
+/// A completed download, as replayed to coalesced loads.
+#[derive(Debug, Clone)]
+pub(super) struct RecentFetch {
+    pub yaml: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub source: LoadSource,
+    pub update_interval: Option<u64>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+struct State {
+    window: Duration,
+    locks: HashMap<String, Arc<tokio::sync::Mutex<()>>>,
+    recent: HashMap<String, (Instant, RecentFetch)>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn state() -> MutexGuard<'static, Option<State>> {
+    STATE.lock().unwrap_or_else(|poison| poison.into_inner())
+}
+
+fn with_state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    let mut guard = state();
+    let state = guard.get_or_insert_with(|| State {
+        window: Duration::ZERO,
+        locks: HashMap::new(),
+        recent: HashMap::new(),
+    });
+    f(state)
+}
+
+/// Reuse a subscription's successful download until its refresh interval (the provider's
+/// hint, else `window`) has passed; zero (the default) turns coalescing off and forgets stored
+/// downloads. Cache entries stay locked either way.
+pub fn set_refresh_window(window: Duration) {
+    with_state(|state| {
+        state.window = window;
+        if window.is_zero() {
+            state.recent.clear();
+        }
+    });
+}
+
+/// Exclusive access to the cache entry `id` until the guard is dropped.
+pub(super) async fn lock_entry(id: &str) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = with_state(|state| state.locks.entry(id.to_string()).or_default().clone());
+    lock.lock_owned().await
+}
+
+/// The refresh window set by [`set_refresh_window`], if coalescing is on.
+pub(super) fn refresh_window() -> Option<Duration> {
+    with_state(|state| (!state.window.is_zero()).then_some(state.window))
+}
+
+/// The download stored under `key` if it completed less than `window` ago.
+pub(super) fn recent(key: &str, window: Duration) -> Option<RecentFetch> {
+    with_state(|state| {
+        let (at, fetch) = state.recent.get(key)?;
+        (at.elapsed() < window).then(|| fetch.clone())
+    })
+}
+
+pub(super) fn remember(key: String, fetch: RecentFetch) {
+    with_state(|state| {
+        if !state.window.is_zero() {
+            state.recent.insert(key, (Instant::now(), fetch));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn racing_loads_fetch_once_per_window() {
+        set_refresh_window(Duration::from_secs(60));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let load = |fetches: Arc<AtomicUsize>| async move {
+            let _entry = lock_entry("coalesce-test").await;
+            let window = refresh_window().unwrap();
+            if let Some(fetch) = recent("coalesce-test\nhttps://example.com/sub", window) {
+                return fetch.yaml;
+            }
+            fetches.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let yaml = "proxies: []".to_string();
+            remember(
+                "coalesce-test\nhttps://example.com/sub".to_string(),
+                RecentFetch {
+                    yaml: yaml.clone(),
+                    etag: None,
+                    last_modified: None,
+                    source: LoadSource::Network,
+                    update_interval: None,
+                    fetched_at: Utc::now(),
+                },
+            );
+            yaml
+        };
+
+        let loads: Vec<_> = (0..4)
+            .map(|_| tokio::spawn(load(fetches.clone())))
+            .collect();
+        for handle in loads {
+            assert_eq!(handle.await.unwrap(), "proxies: []");
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+}
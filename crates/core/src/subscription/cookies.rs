@@ -0,0 +1,314 @@
+//! Cookie store isolated to one subscription fetch.
+//!
+//! Some panels answer the subscription URL with `Set-Cookie` plus a redirect and reject the
+//! follow-up request unless the session cookie is sent back. reqwest only keeps cookies when
+//! the client has a store, so each fetch gets its own [`SubscriptionCookies`]; subscriptions
+//! with `persist_cookies` also save it next to their cache metadata.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use url::Url;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct StoredCookie {
+    name: String,
+    value: String,
+    domain: String,
+    /// Without a `Domain` attribute the cookie is only sent back to the exact host.
+    #[serde(default)]
+    host_only: bool,
+    path: String,
+    #[serde(default)]
+    secure: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<DateTime<Utc>>,
+}
+
+impl StoredCookie {
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url, now: DateTime<Utc>) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let domain_ok = if self.host_only {
+            host.eq_ignore_ascii_case(&self.domain)
+        } else {
+            domain_match(host, &self.domain)
+        };
+        domain_ok
+            && path_match(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.expired(now)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SubscriptionCookies {
+    cookies: Mutex<Vec<StoredCookie>>,
+}
+
+impl SubscriptionCookies {
+    /// Load cookies saved by [`SubscriptionCookies::save`]; missing or unreadable files start
+    /// an empty store.
+    pub async fn load(path: &Path) -> Self {
+        let now = Utc::now();
+        let cookies = match fs::read_to_string(path).await {
+            Ok(raw) => serde_json::from_str::<Vec<StoredCookie>>(&raw)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|cookie| !cookie.expired(now))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            cookies: Mutex::new(cookies),
+        }
+    }
+
+    /// Persist the unexpired cookies; removes the file when none are left.
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let cookies: Vec<StoredCookie> = self
+            .lock()
+            .iter()
+            .filter(|cookie| !cookie.expired(now))
+            .cloned()
+            .collect();
+        if cookies.is_empty() {
+            match fs::remove_file(path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => return Ok(()),
+            }
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(path, serde_json::to_string(&cookies)?).await?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<StoredCookie>> {
+        self.cookies
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    fn store(&self, header: &str, url: &Url, now: DateTime<Utc>) {
+        let Some(cookie) = parse_set_cookie(header, url, now) else {
+            return;
+        };
+        let mut cookies = self.lock();
+        cookies.retain(|existing| {
+            !(existing.name == cookie.name
+                && existing.domain == cookie.domain
+                && existing.path == cookie.path)
+        });
+        if !cookie.expired(now) {
+            cookies.push(cookie);
+        }
+    }
+
+    fn header_for(&self, url: &Url, now: DateTime<Utc>) -> Option<String> {
+        let cookies = self.lock();
+        let mut matching: Vec<&StoredCookie> = cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // Longer paths first, as browsers do.
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        Some(
+            matching
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+}
+
+impl reqwest::cookie::CookieStore for SubscriptionCookies {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let now = Utc::now();
+        for header in cookie_headers {
+            if let Ok(raw) = header.to_str() {
+                self.store(raw, url, now);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.header_for(url, Utc::now())
+            .and_then(|header| HeaderValue::from_str(&header).ok())
+    }
+}
+
+fn parse_set_cookie(header: &str, url: &Url, now: DateTime<Utc>) -> Option<StoredCookie> {
+    let host = url.host_str()?;
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut cookie = StoredCookie {
+        name: name.to_string(),
+        value: value.trim().trim_matches('"').to_string(),
+        domain: host.to_ascii_lowercase(),
+        host_only: true,
+        path: default_path(url.path()),
+        secure: false,
+        expires: None,
+    };
+    let mut max_age = None;
+    for attr in parts {
+        let (key, val) = attr.split_once('=').unwrap_or((attr, ""));
+        let val = val.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "domain" if !val.is_empty() => {
+                let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                // Reject cookies for unrelated domains.
+                if !domain_match(host, &domain) {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if val.starts_with('/') => cookie.path = val.to_string(),
+            "secure" => cookie.secure = true,
+            "max-age" => max_age = val.parse::<i64>().ok(),
+            "expires" => {
+                cookie.expires = DateTime::parse_from_rfc2822(val)
+                    .ok()
+                    .map(|at| at.with_timezone(&Utc));
+            }
+            _ => {}
+        }
+    }
+    // Max-Age wins over Expires.
+    if let Some(seconds) = max_age {
+        cookie.expires = Some(now + Duration::seconds(seconds.max(0)));
+    }
+    Some(cookie)
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// RFC 6265 default-path: the request path up to (not including) its last `/`.
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(idx) => request_path[..idx].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(raw: &str) -> Url {
+        Url::parse(raw).unwrap()
+    }
+
+    #[test]
+    fn session_cookie_is_sent_back_after_redirect() {
+        let jar = SubscriptionCookies::default();
+        let now = Utc::now();
+        jar.store(
+            "session=abc123; Path=/; HttpOnly",
+            &url("https://panel.example.com/api/v1/client/subscribe?token=x"),
+            now,
+        );
+        jar.store(
+            "theme=dark; Domain=.example.com; Path=/",
+            &url("https://panel.example.com/login"),
+            now,
+        );
+
+        assert_eq!(
+            jar.header_for(&url("https://panel.example.com/sub/x"), now)
+                .as_deref(),
+            Some("session=abc123; theme=dark")
+        );
+        // Host-only cookie does not leak to sibling hosts; domain cookie does.
+        assert_eq!(
+            jar.header_for(&url("https://cdn.example.com/sub"), now)
+                .as_deref(),
+            Some("theme=dark")
+        );
+        assert_eq!(jar.header_for(&url("https://other.test/"), now), None);
+    }
+
+    #[test]
+    fn rejects_foreign_domains_and_honours_expiry() {
+        let jar = SubscriptionCookies::default();
+        let now = Utc::now();
+        let origin = url("https://panel.example.com/");
+        jar.store("evil=1; Domain=attacker.test", &origin, now);
+        assert!(jar.is_empty());
+
+        jar.store("token=1; Max-Age=60", &origin, now);
+        jar.store("secure=1; Secure", &origin, now);
+        assert_eq!(jar.len(), 2);
+        assert_eq!(
+            jar.header_for(&url("http://panel.example.com/"), now)
+                .as_deref(),
+            Some("token=1")
+        );
+        assert_eq!(
+            jar.header_for(&origin, now + Duration::seconds(120))
+                .as_deref(),
+            Some("secure=1")
+        );
+
+        jar.store("token=gone; Max-Age=0", &origin, now);
+        assert_eq!(jar.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn saved_cookies_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub.cookies.json");
+        let jar = SubscriptionCookies::default();
+        jar.store(
+            "session=abc; Path=/",
+            &url("https://panel.example.com/sub"),
+            Utc::now(),
+        );
+        jar.save(&path).await.unwrap();
+
+        let loaded = SubscriptionCookies::load(&path).await;
+        assert_eq!(loaded.len(), 1);
+
+        SubscriptionCookies::default().save(&path).await.unwrap();
+        assert!(!path.exists());
+    }
+}
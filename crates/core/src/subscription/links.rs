@@ -0,0 +1,241 @@
+//! Proxies back to share links (`ss://`, `vmess://`, `trojan://`), the inverse of the
+//! share-link parser, for clients that only import links.
+
+use anyhow::anyhow;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::json;
+use serde_yaml::Value;
+
+/// Characters left as they are in the userinfo and fragment of a link.
+const LINK_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// The share link of `proxy`; fails for types and transports that have no link form.
+pub fn share_link(proxy: &Value) -> anyhow::Result<String> {
+    let kind = str_field(proxy, "type").unwrap_or_default();
+    match kind {
+        "ss" => shadowsocks_link(proxy),
+        "vmess" => vmess_link(proxy),
+        "trojan" => trojan_link(proxy),
+        other => Err(anyhow!("type '{}' has no supported share link", other)),
+    }
+}
+
+fn shadowsocks_link(proxy: &Value) -> anyhow::Result<String> {
+    let (name, host, port) = endpoint(proxy)?;
+    let cipher = required(proxy, "cipher")?;
+    let password = required(proxy, "password")?;
+    let userinfo = URL_SAFE_NO_PAD.encode(format!("{}:{}", cipher, password));
+    let mut link = format!("ss://{}@{}:{}", userinfo, host, port);
+    if let Some(plugin) = str_field(proxy, "plugin") {
+        let opts = |key: &str| proxy.get("plugin-opts").and_then(|o| str_field(o, key));
+        let mut parts: Vec<String> = Vec::new();
+        match plugin {
+            "obfs" => {
+                parts.push("obfs-local".to_string());
+                if let Some(mode) = opts("mode") {
+                    parts.push(format!("obfs={}", mode));
+                }
+                if let Some(host) = opts("host") {
+                    parts.push(format!("obfs-host={}", host));
+                }
+            }
+            "v2ray-plugin" => {
+                parts.push("v2ray-plugin".to_string());
+                if let Some(mode) = opts("mode") {
+                    parts.push(format!("mode={}", mode));
+                }
+                if let Some(host) = opts("host") {
+                    parts.push(format!("host={}", host));
+                }
+                if let Some(path) = opts("path") {
+                    parts.push(format!("path={}", path));
+                }
+                let tls = proxy
+                    .get("plugin-opts")
+                    .and_then(|o| o.get("tls"))
+                    .and_then(Value::as_bool);
+                if tls == Some(true) {
+                    parts.push("tls".to_string());
+                }
+            }
+            other => return Err(anyhow!("ss plugin '{}' has no share link form", other)),
+        }
+        link.push_str("/?plugin=");
+        link.push_str(&utf8_percent_encode(&parts.join(";"), LINK_COMPONENT).to_string());
+    }
+    link.push('#');
+    link.push_str(&utf8_percent_encode(&name, LINK_COMPONENT).to_string());
+    Ok(link)
+}
+
+fn vmess_link(proxy: &Value) -> anyhow::Result<String> {
+    let (name, _, port) = endpoint(proxy)?;
+    let server = required(proxy, "server")?;
+    let network = str_field(proxy, "network").unwrap_or("tcp");
+    let (host, path) = match network {
+        "ws" => {
+            let opts = proxy.get("ws-opts");
+            (
+                opts.and_then(|o| o.get("headers"))
+                    .and_then(|h| str_field(h, "Host")),
+                opts.and_then(|o| str_field(o, "path")),
+            )
+        }
+        "grpc" => (
+            None,
+            proxy
+                .get("grpc-opts")
+                .and_then(|o| str_field(o, "grpc-service-name")),
+        ),
+        "h2" => {
+            let opts = proxy.get("h2-opts");
+            (
+                opts.and_then(|o| o.get("host"))
+                    .and_then(Value::as_sequence)
+                    .and_then(|hosts| hosts.first())
+                    .and_then(Value::as_str),
+                opts.and_then(|o| str_field(o, "path")),
+            )
+        }
+        "tcp" | "http" => (None, None),
+        other => return Err(anyhow!("vmess network '{}' has no share link form", other)),
+    };
+    let tls = proxy.get("tls").and_then(Value::as_bool) == Some(true);
+    let mut body = json!({
+        "v": "2",
+        "ps": name,
+        "add": server,
+        "port": port.to_string(),
+        "id": required(proxy, "uuid")?,
+        "aid": proxy.get("alterId").and_then(Value::as_u64).unwrap_or(0).to_string(),
+        "scy": str_field(proxy, "cipher").unwrap_or("auto"),
+        "net": network,
+        "type": "none",
+        "host": host.unwrap_or_default(),
+        "path": path.unwrap_or_default(),
+        "tls": if tls { "tls" } else { "" },
+        "sni": str_field(proxy, "servername").unwrap_or_default(),
+        "fp": str_field(proxy, "client-fingerprint").unwrap_or_default(),
+    });
+    if let Some(alpn) = alpn(proxy) {
+        body["alpn"] = alpn.into();
+    }
+    Ok(format!("vmess://{}", STANDARD.encode(body.to_string())))
+}
+
+fn trojan_link(proxy: &Value) -> anyhow::Result<String> {
+    let (name, host, port) = endpoint(proxy)?;
+    let password = required(proxy, "password")?;
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(sni) = str_field(proxy, "sni") {
+        query.append_pair("sni", sni);
+    }
+    if let Some(alpn) = alpn(proxy) {
+        query.append_pair("alpn", &alpn);
+    }
+    if proxy.get("skip-cert-verify").and_then(Value::as_bool) == Some(true) {
+        query.append_pair("allowInsecure", "1");
+    }
+    match str_field(proxy, "network") {
+        None | Some("tcp") => {}
+        Some("ws") => {
+            query.append_pair("type", "ws");
+            let opts = proxy.get("ws-opts");
+            if let Some(path) = opts.and_then(|o| str_field(o, "path")) {
+                query.append_pair("path", path);
+            }
+            if let Some(host) = opts
+                .and_then(|o| o.get("headers"))
+                .and_then(|h| str_field(h, "Host"))
+            {
+                query.append_pair("host", host);
+            }
+        }
+        Some(other) => return Err(anyhow!("trojan network '{}' has no share link form", other)),
+    }
+    let query = query.finish();
+    Ok(format!(
+        "trojan://{}@{}:{}{}{}#{}",
+        utf8_percent_encode(password, LINK_COMPONENT),
+        host,
+        port,
+        if query.is_empty() { "" } else { "?" },
+        query,
+        utf8_percent_encode(&name, LINK_COMPONENT)
+    ))
+}
+
+/// `(name, host, port)`, with an IPv6 host in brackets.
+fn endpoint(proxy: &Value) -> anyhow::Result<(String, String, u64)> {
+    let name = required(proxy, "name")?.to_string();
+    let server = required(proxy, "server")?;
+    let port = match proxy.get("port") {
+        Some(Value::Number(port)) => port.as_u64(),
+        Some(Value::String(port)) => port.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("missing or invalid `port`"))?;
+    let host = if server.contains(':') && !server.starts_with('[') {
+        format!("[{}]", server)
+    } else {
+        server.to_string()
+    };
+    Ok((name, host, port))
+}
+
+fn alpn(proxy: &Value) -> Option<String> {
+    let list: Vec<&str> = proxy
+        .get("alpn")?
+        .as_sequence()?
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    (!list.is_empty()).then(|| list.join(","))
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+}
+
+fn required<'a>(proxy: &'a Value, key: &str) -> anyhow::Result<&'a str> {
+    str_field(proxy, key).ok_or_else(|| anyhow!("missing `{}`", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::parse_share_links_payload;
+    use super::*;
+
+    #[test]
+    fn links_parse_back_into_the_same_proxies() {
+        let proxies: Vec<Value> = serde_yaml::from_str(
+            r#"
+- { name: "HK 01", type: ss, server: hk.example.com, port: 8388, cipher: aes-256-gcm, password: "p@ss:word", udp: true }
+- { name: "SS obfs", type: ss, server: "2001:db8::1", port: 443, cipher: chacha20-ietf-poly1305, password: pw, udp: true, plugin: obfs, plugin-opts: { mode: http, host: bing.com } }
+- { name: "JP vmess", type: vmess, server: jp.example.com, port: 443, uuid: 9c5f2b7e-1111-2222-3333-444455556666, udp: true, alterId: 0, cipher: auto, network: ws, ws-opts: { path: /ray, headers: { Host: cdn.example.com } }, tls: true, servername: cdn.example.com }
+- { name: "US trojan", type: trojan, server: us.example.com, port: 443, password: "s3cret/pw", udp: true, sni: us.example.com, skip-cert-verify: true }
+"#,
+        )
+        .unwrap();
+        let links: Vec<String> = proxies.iter().map(|p| share_link(p).unwrap()).collect();
+        assert!(links[0].starts_with("ss://"));
+        assert!(links[0].ends_with("#HK%2001"));
+        let parsed = parse_share_links_payload(&links.join("\n"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.proxies, proxies);
+
+        let vless: Value =
+            serde_yaml::from_str("{ name: x, type: vless, server: a, port: 1 }").unwrap();
+        assert!(share_link(&vless).is_err());
+    }
+}
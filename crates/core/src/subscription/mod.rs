@@ -1,22 +1,47 @@
 use std::{
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
     time::Duration,
 };
 
 use anyhow::{anyhow, Context};
+use base64::Engine;
 use chrono::{DateTime, Utc};
-use reqwest::header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::header::{
+    HeaderMap, CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+};
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use serde_yaml::Mapping;
+use sha2::{Digest, Sha256};
 use tokio::fs;
 use tracing::Instrument;
 
+mod coalesce;
+mod cookies;
+mod links;
 mod parser;
-pub use parser::{parse_share_links_payload, ParseOptions};
+mod singbox;
+mod surge;
+mod usage;
+pub use coalesce::set_refresh_window;
+pub use cookies::SubscriptionCookies;
+pub use links::share_link;
+pub use parser::{
+    detect_and_parse, explain_parse_failure, parse_share_links_payload, ParseMode, ParseOptions,
+    SourceFormat,
+};
+pub use usage::{
+    daily_usage, format_bytes, load_usage_history, next_monthly_reset, project_usage,
+    record_usage_sample, DailyUsage, SubscriptionUsage, UsageProjection, UsageSample,
+};
 
+use crate::filter::ProxyFilterSpec;
+use crate::interpolate::expand_env_vars;
 use crate::model::ClashConfig;
-use crate::storage::AppPaths;
-use parser::parse_subscription_payload_with_options;
+use crate::storage::{self, AppPaths};
+use crate::throttle::throttle_download;
+use parser::{parse_as_format, parse_subscription_payload_for_mode};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Subscription {
@@ -36,12 +61,122 @@ pub struct Subscription {
     pub kind: SubscriptionKind,
     #[serde(default = "default_true")]
     pub enabled: bool,
+    /// Drop proxy-groups shipped by this subscription; only its proxies (and rules, unless
+    /// `ignore_rules` is set) are merged.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ignore_groups: bool,
+    /// Drop rules shipped by this subscription.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ignore_rules: bool,
+    /// Include/exclude regexes on proxy names, applied to this subscription's nodes before
+    /// merging.
+    #[serde(default, skip_serializing_if = "ProxyFilterSpec::is_empty")]
+    pub filter: ProxyFilterSpec,
+    /// For providers without ETag/Last-Modified: send a HEAD first and reuse the cache when the
+    /// advertised Digest matches the cached body (or, lacking a Digest, the Content-Length is
+    /// unchanged).
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub head_probe: bool,
+    /// Consecutive fetches that failed or fell back to the cache; reset on a successful fetch.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failure_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Upstream proxy URL used to fetch this subscription (overrides `merge --fetch-proxy`);
+    /// `direct` fetches without any proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Force how this source is parsed instead of using the global parse options.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<ParseMode>,
+    /// Payload format detected on the first successful parse; later runs parse this format
+    /// directly and only re-detect if it stops matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<SourceFormat>,
+    /// Origin of the payload of the most recent successful load (200, 304/HEAD probe, cache
+    /// fallback or local file).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_source: Option<LoadSource>,
+    /// Keep the panel's cookies between runs (stored next to the cache metadata) instead of
+    /// starting every fetch with an empty cookie jar.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub persist_cookies: bool,
+    /// Refresh interval advertised by the provider (`profile-update-interval` header or a
+    /// `#!MANAGED-CONFIG ... interval=` line), in seconds. `merge --watch` refreshes this
+    /// subscription on that cadence instead of its global `--interval`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_interval: Option<u64>,
+    /// Keys this version does not know (from a newer mihomo-cli), written back unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            url: None,
+            path: None,
+            last_updated: None,
+            etag: None,
+            last_modified: None,
+            kind: SubscriptionKind::default(),
+            enabled: true,
+            ignore_groups: false,
+            filter: ProxyFilterSpec::default(),
+            ignore_rules: false,
+            head_probe: false,
+            failure_count: 0,
+            last_error: None,
+            proxy: None,
+            parse_mode: None,
+            format: None,
+            last_source: None,
+            persist_cookies: false,
+            update_interval: None,
+            extra: BTreeMap::new(),
+        }
+    }
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// Where the payload of the last successful load came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoadSource {
+    /// Downloaded from the provider.
+    Network,
+    /// Cached copy confirmed current by a 304 or HEAD probe.
+    NotModified,
+    /// Cached copy used because the fetch failed or the provider asked us to back off.
+    Cache,
+    /// Local file.
+    File,
+}
+
+impl LoadSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LoadSource::Network => "network",
+            LoadSource::NotModified => "not-modified",
+            LoadSource::Cache => "cache",
+            LoadSource::File => "file",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum SubscriptionKind {
@@ -52,16 +187,20 @@ pub enum SubscriptionKind {
 }
 
 impl Subscription {
+    /// Fill in a missing id. A URL source gets a hash of the URL rather than the URL itself:
+    /// the id names cache files and shows up in every log line, and URLs carry tokens.
     pub fn ensure_id(&mut self) {
         if self.id.is_empty() {
             self.id = self
                 .url
-                .clone()
+                .as_deref()
+                .map(url_id)
                 .or_else(|| self.path.as_ref().map(|p| p.display().to_string()))
                 .unwrap_or_else(|| format!("{}", uuid::Uuid::new_v4()));
         }
     }
 
+    /// Load and parse the subscription, tracking consecutive failures on `self`.
     pub async fn load_config(
         &mut self,
         client: &Client,
@@ -73,26 +212,246 @@ impl Subscription {
 
         self.ensure_id();
 
+        let result = self.load_config_inner(client, paths).await;
+        if let Err(err) = &result {
+            self.record_failure(&format!("{:#}", err));
+        }
+        result
+    }
+
+    /// Like [`Subscription::load_config`], but a remote subscription refreshed less than its
+    /// refresh interval ago is served from the cache without any request.
+    pub async fn load_config_if_due(
+        &mut self,
+        client: &Client,
+        paths: &AppPaths,
+        default_interval: Duration,
+    ) -> anyhow::Result<Option<ClashConfig>> {
+        if self.enabled && self.url.is_some() && !self.is_due(default_interval, Utc::now()) {
+            self.ensure_id();
+            if let Some(cached) = read_cached_yaml(&paths.cache_file(&self.id)).await? {
+                tracing::debug!(id = %self.id, "refresh interval not reached, using cached subscription");
+                let config = self.parse_payload(&cached)?;
+                check_proxy_limit(&self.id, &config, current_fetch_limits())?;
+                return self.strip_ignored(config).map(Some);
+            }
+        }
+        self.load_config(client, paths).await
+    }
+
+    /// The provider's advertised interval, else `default_interval`.
+    pub fn refresh_interval(&self, default_interval: Duration) -> Duration {
+        self.update_interval
+            .map(|secs| Duration::from_secs(secs.clamp(1, MAX_UPDATE_INTERVAL_SECS)))
+            .unwrap_or(default_interval)
+    }
+
+    /// When this subscription should next be fetched; `None` means now (never fetched, or
+    /// the last fetch failed).
+    pub fn next_refresh(&self, default_interval: Duration) -> Option<DateTime<Utc>> {
+        if self.failure_count > 0 {
+            return None;
+        }
+        let interval = chrono::Duration::from_std(self.refresh_interval(default_interval)).ok()?;
+        self.last_updated.map(|updated| {
+            updated
+                .checked_add_signed(interval)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        })
+    }
+
+    fn is_due(&self, default_interval: Duration, now: DateTime<Utc>) -> bool {
+        self.next_refresh(default_interval)
+            .is_none_or(|next| next <= now)
+    }
+
+    /// Quota/expiry last reported by the provider via `subscription-userinfo`, if any.
+    pub async fn usage(&self, paths: &AppPaths) -> anyhow::Result<Option<SubscriptionUsage>> {
+        if self.id.is_empty() || self.url.is_none() {
+            return Ok(None);
+        }
+        Ok(read_cache_meta(&paths.cache_meta_file(&self.id))
+            .await?
+            .usage)
+    }
+
+    /// When the cached payload last changed (unchanged downloads do not rewrite it).
+    pub async fn cache_modified(&self, paths: &AppPaths) -> Option<DateTime<Utc>> {
+        if self.id.is_empty() || self.url.is_none() {
+            return None;
+        }
+        let modified = fs::metadata(paths.cache_file(&self.id))
+            .await
+            .ok()?
+            .modified()
+            .ok()?;
+        Some(DateTime::<Utc>::from(modified))
+    }
+
+    /// Fresh cookie store for fetching this subscription, pre-filled with the saved cookies
+    /// when `persist_cookies` is set.
+    pub async fn cookie_jar(&self, paths: &AppPaths) -> SubscriptionCookies {
+        if self.persist_cookies && !self.id.is_empty() {
+            SubscriptionCookies::load(&paths.cache_cookies_file(&self.id)).await
+        } else {
+            SubscriptionCookies::default()
+        }
+    }
+
+    /// Save the cookies collected while fetching; a no-op unless `persist_cookies` is set.
+    pub async fn save_cookies(
+        &self,
+        paths: &AppPaths,
+        jar: &SubscriptionCookies,
+    ) -> anyhow::Result<()> {
+        if !self.persist_cookies || self.id.is_empty() || storage::is_read_only() {
+            return Ok(());
+        }
+        jar.save(&paths.cache_cookies_file(&self.id)).await
+    }
+
+    pub fn record_failure(&mut self, error: &str) {
+        self.failure_count = self.failure_count.saturating_add(1);
+        self.last_error = Some(error.to_string());
+    }
+
+    pub fn clear_failures(&mut self) {
+        self.failure_count = 0;
+        self.last_error = None;
+    }
+
+    #[tracing::instrument(
+        name = "subscription.load",
+        skip_all,
+        fields(
+            id = %crate::redact::redact_urls(&self.id),
+            subscription = %self.name,
+            source = tracing::field::Empty,
+            bytes = tracing::field::Empty,
+            proxies = tracing::field::Empty,
+        )
+    )]
+    async fn load_config_inner(
+        &mut self,
+        client: &Client,
+        paths: &AppPaths,
+    ) -> anyhow::Result<Option<ClashConfig>> {
         match self.kind {
             SubscriptionKind::Clash => {}
-            SubscriptionKind::Merge | SubscriptionKind::Script => {
+            SubscriptionKind::Merge => {
+                return Err(anyhow!(
+                    "subscription {} is a merge patch, not a proxy source",
+                    self.id
+                ))
+            }
+            SubscriptionKind::Script => {
                 return Err(anyhow!(
-                    "subscription kind {:?} is not supported for merging yet",
-                    self.kind
+                    "subscription {} is a script, not a proxy source",
+                    self.id
                 ))
             }
         }
 
+        let raw = self.load_raw(client, paths).await?;
+        let span = tracing::Span::current();
+        span.record("bytes", raw.len());
+        if let Some(source) = self.last_source {
+            span.record("source", source.as_str());
+        }
+        let config = self.parse_payload(&raw)?;
+        check_proxy_limit(&self.id, &config, current_fetch_limits())?;
+        span.record("proxies", config.proxies.len());
+        self.strip_ignored(config).map(Some)
+    }
+
+    /// Load a `kind: merge` entry: a clash-verge style Merge patch, applied to the merged
+    /// config with [`crate::merge::apply_merge_patch`]. Fetched (and cached) like any other
+    /// subscription.
+    pub async fn load_merge_patch(
+        &mut self,
+        client: &Client,
+        paths: &AppPaths,
+    ) -> anyhow::Result<Option<Mapping>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        self.ensure_id();
+
+        let result = match self.load_raw(client, paths).await {
+            Ok(raw) if raw.trim().is_empty() => Ok(Some(Mapping::new())),
+            Ok(raw) => serde_yaml::from_str::<Mapping>(&raw)
+                .map(Some)
+                .with_context(|| format!("merge patch {} is not a YAML mapping", self.id)),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = &result {
+            self.record_failure(&format!("{:#}", err));
+        }
+        result
+    }
+
+    /// Load a `kind: script` entry: JavaScript defining `main(config, profileName)`, run
+    /// against the merged config with [`crate::script::run_script`].
+    pub async fn load_script(
+        &mut self,
+        client: &Client,
+        paths: &AppPaths,
+    ) -> anyhow::Result<Option<String>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        self.ensure_id();
+
+        let result = self.load_raw(client, paths).await;
+        if let Err(err) = &result {
+            self.record_failure(&format!("{:#}", err));
+        }
+        result.map(Some)
+    }
+
+    /// Fetch (url) or read (path) the raw payload, updating the fetch bookkeeping.
+    async fn load_raw(&mut self, client: &Client, paths: &AppPaths) -> anyhow::Result<String> {
         match (&self.url, &self.path) {
             (Some(url), _) => {
-                let span = tracing::info_span!("fetch_subscription", id = %self.id, url);
+                let shared_key = format!("{}\n{}", self.id, url);
+                if let Some(yaml) = shared_fetch(&shared_key) {
+                    tracing::debug!(id = %self.id, "reusing payload fetched earlier in this run");
+                    return Ok(yaml);
+                }
+                let _entry = coalesce::lock_entry(&self.id).await;
+                let recent = coalesce::refresh_window().and_then(|window| {
+                    coalesce::recent(&shared_key, self.refresh_interval(window))
+                });
+                if let Some(recent) = recent {
+                    tracing::debug!(id = %self.id, "reusing download from the current refresh window");
+                    self.etag = recent.etag.or(self.etag.take());
+                    self.last_modified = recent.last_modified.or(self.last_modified.take());
+                    self.clear_failures();
+                    self.last_updated = Some(recent.fetched_at);
+                    self.last_source = Some(recent.source);
+                    if recent.update_interval.is_some() {
+                        self.update_interval = recent.update_interval;
+                    }
+                    return Ok(recent.yaml);
+                }
+                // Only scheme and host go into the span: paths and queries carry tokens, and a
+                // `keyring:` reference or `${VAR}` stays as written.
+                let span = tracing::info_span!(
+                    "fetch_subscription",
+                    id = %crate::redact::redact_urls(&self.id),
+                    url = %crate::redact::redact_urls(url)
+                );
+                let resolved = expand_env_vars(url)
+                    .with_context(|| format!("failed to expand the url of '{}'", self.name))?;
+                let resolved = crate::secrets::resolve_secret(&resolved).await?;
                 let fetch_result = fetch_remote(
                     client,
                     paths,
                     &self.id,
-                    url,
+                    &resolved,
                     self.etag.clone(),
                     self.last_modified.clone(),
+                    self.head_probe,
                 )
                 .instrument(span)
                 .await?;
@@ -103,57 +462,304 @@ impl Subscription {
                 if let Some(new_last_modified) = fetch_result.last_modified.clone() {
                     self.last_modified = Some(new_last_modified);
                 }
+                match fetch_result.fallback_error.as_deref() {
+                    Some(error) => self.record_failure(error),
+                    None => self.clear_failures(),
+                }
                 self.last_updated = Some(Utc::now());
+                self.last_source = Some(fetch_result.source);
+                if fetch_result.update_interval.is_some() {
+                    self.update_interval = fetch_result.update_interval;
+                }
 
-                let config = parse_subscription_payload_with_options(
-                    &fetch_result.yaml,
-                    current_parse_options(),
-                )?;
-                Ok(Some(config))
+                if fetch_result.fallback_error.is_none() {
+                    coalesce::remember(
+                        shared_key.clone(),
+                        coalesce::RecentFetch {
+                            yaml: fetch_result.yaml.clone(),
+                            etag: fetch_result.etag.clone(),
+                            last_modified: fetch_result.last_modified.clone(),
+                            source: fetch_result.source,
+                            update_interval: fetch_result.update_interval,
+                            fetched_at: Utc::now(),
+                        },
+                    );
+                }
+                remember_fetch(shared_key, &fetch_result.yaml);
+                Ok(fetch_result.yaml)
             }
             (None, Some(path)) => {
-                let span =
-                    tracing::info_span!("read_subscription", id = %self.id, path = %path.display());
-                let yaml = fs::read_to_string(path)
+                let path = &PathBuf::from(
+                    expand_env_vars(&path.to_string_lossy())
+                        .with_context(|| format!("failed to expand the path of '{}'", self.name))?,
+                );
+                let span = tracing::info_span!("read_subscription", id = %crate::redact::redact_urls(&self.id), path = %path.display());
+                let yaml = read_file_limited(path, current_fetch_limits().max_payload_bytes)
                     .instrument(span)
-                    .await
-                    .with_context(|| {
-                        format!("failed to read subscription file {}", path.display())
-                    })?;
+                    .await?;
+                self.clear_failures();
                 self.last_updated = Some(Utc::now());
-                let config =
-                    parse_subscription_payload_with_options(&yaml, current_parse_options())?;
-                Ok(Some(config))
+                self.last_source = Some(LoadSource::File);
+                Ok(yaml)
             }
             _ => Err(anyhow!("subscription {} missing url or path", self.id)),
         }
     }
+
+    /// Parse using the forced `parse_mode`, else the remembered `format`, else detect the
+    /// format and remember it.
+    fn parse_payload(&mut self, raw: &str) -> anyhow::Result<ClashConfig> {
+        let opts = current_parse_options();
+        if self.parse_mode.is_some() {
+            return parse_subscription_payload_for_mode(raw, self.parse_mode, opts);
+        }
+
+        let remembered = self
+            .format
+            .filter(|format| opts.allow_base64 || *format != SourceFormat::Base64);
+        let remembered_err = match remembered {
+            Some(format) => match parse_as_format(raw, format) {
+                Ok(config) => return Ok(config),
+                Err(err) => Some((format, err)),
+            },
+            None => None,
+        };
+
+        match detect_and_parse(raw, opts) {
+            Ok((config, format)) => {
+                if self.format != Some(format) {
+                    tracing::info!(id = %self.id, format = format.as_str(), "detected subscription format");
+                    self.format = Some(format);
+                }
+                Ok(config)
+            }
+            Err(err) => match remembered_err {
+                Some((format, previous)) => Err(previous.context(format!(
+                    "subscription {} was previously detected as {}",
+                    self.id,
+                    format.as_str()
+                ))),
+                None => Err(err),
+            },
+        }
+    }
+
+    fn strip_ignored(&self, mut config: ClashConfig) -> anyhow::Result<ClashConfig> {
+        if self.ignore_groups {
+            config.proxy_groups.clear();
+        }
+        if self.ignore_rules {
+            config.rules.clear();
+        }
+        if !self.filter.is_empty() {
+            let filter = self
+                .filter
+                .compile()
+                .with_context(|| format!("subscription {} has an invalid filter", self.id))?;
+            let removed = filter.apply(&mut config);
+            if !removed.is_empty() {
+                tracing::info!(id = %self.id, removed = removed.len(), "filtered subscription proxies");
+            }
+        }
+        Ok(config)
+    }
 }
 
-static PARSE_OPTIONS: std::sync::OnceLock<ParseOptions> = std::sync::OnceLock::new();
+static PARSE_OPTIONS: std::sync::RwLock<Option<ParseOptions>> = std::sync::RwLock::new(None);
 
-/// Configure how subscription payloads are parsed (e.g., allow/disallow base64 list decoding).
-/// Call once during program initialization.
+/// Configure how subscription payloads are parsed (e.g., allow/disallow base64 list decoding)
+/// for the loads that follow; each merge sets its own.
 pub fn set_parse_options(opts: ParseOptions) {
-    let _ = PARSE_OPTIONS.set(opts);
+    *PARSE_OPTIONS
+        .write()
+        .unwrap_or_else(|poison| poison.into_inner()) = Some(opts);
 }
 
 fn current_parse_options() -> ParseOptions {
-    *PARSE_OPTIONS
-        .get()
-        .unwrap_or(&ParseOptions { allow_base64: true })
+    PARSE_OPTIONS
+        .read()
+        .unwrap_or_else(|poison| poison.into_inner())
+        .unwrap_or(ParseOptions { allow_base64: true })
+}
+
+/// Sanity limits guarding against misbehaving endpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FetchLimits {
+    /// Largest subscription body accepted, in bytes.
+    pub max_payload_bytes: u64,
+    /// Largest number of proxies accepted from a single subscription.
+    pub max_proxies: usize,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        Self {
+            max_payload_bytes: 32 * 1024 * 1024,
+            max_proxies: 10_000,
+        }
+    }
+}
+
+static FETCH_LIMITS: std::sync::RwLock<Option<FetchLimits>> = std::sync::RwLock::new(None);
+
+/// Configure payload/node-count limits for the subscription loads that follow; each merge sets
+/// its own.
+pub fn set_fetch_limits(limits: FetchLimits) {
+    *FETCH_LIMITS
+        .write()
+        .unwrap_or_else(|poison| poison.into_inner()) = Some(limits);
+}
+
+fn current_fetch_limits() -> FetchLimits {
+    FETCH_LIMITS
+        .read()
+        .unwrap_or_else(|poison| poison.into_inner())
+        .unwrap_or_default()
+}
+
+/// Payloads downloaded during the current multi-output run, keyed by subscription id and URL.
+static SHARED_FETCHES: std::sync::Mutex<Option<HashMap<String, String>>> =
+    std::sync::Mutex::new(None);
+
+/// While enabled, each subscription URL is downloaded at most once and later loads in this
+/// process (e.g. the other outputs of `merge --variants`) reuse the payload. Disabling drops
+/// the stored payloads.
+pub fn share_fetches(enabled: bool) {
+    *shared_fetches() = enabled.then(HashMap::new);
+}
+
+fn shared_fetches() -> std::sync::MutexGuard<'static, Option<HashMap<String, String>>> {
+    SHARED_FETCHES
+        .lock()
+        .unwrap_or_else(|poison| poison.into_inner())
+}
+
+fn shared_fetch(key: &str) -> Option<String> {
+    shared_fetches().as_ref()?.get(key).cloned()
+}
+
+fn remember_fetch(key: String, yaml: &str) {
+    if let Some(fetches) = shared_fetches().as_mut() {
+        fetches.insert(key, yaml.to_string());
+    }
+}
+
+/// Read a local subscription file, refusing one larger than `max_bytes`. The size is checked
+/// on the opened file before reading and again while reading, in case it grows in between.
+async fn read_file_limited(path: &Path, max_bytes: u64) -> anyhow::Result<String> {
+    use tokio::io::AsyncReadExt;
+
+    let too_large = |size: u64| {
+        anyhow!(
+            "subscription file {} is {} bytes, more than the limit of {} (raise --max-payload-bytes)",
+            path.display(),
+            size,
+            max_bytes
+        )
+    };
+    let file = fs::File::open(path)
+        .await
+        .with_context(|| format!("failed to read subscription file {}", path.display()))?;
+    let size = file
+        .metadata()
+        .await
+        .with_context(|| format!("failed to stat subscription file {}", path.display()))?
+        .len();
+    if size > max_bytes {
+        return Err(too_large(size));
+    }
+    let mut raw = Vec::with_capacity(size as usize);
+    file.take(max_bytes + 1)
+        .read_to_end(&mut raw)
+        .await
+        .with_context(|| format!("failed to read subscription file {}", path.display()))?;
+    if raw.len() as u64 > max_bytes {
+        return Err(too_large(raw.len() as u64));
+    }
+    String::from_utf8(raw)
+        .with_context(|| format!("subscription file {} is not UTF-8", path.display()))
+}
+
+fn check_proxy_limit(id: &str, config: &ClashConfig, limits: FetchLimits) -> anyhow::Result<()> {
+    if config.proxies.len() > limits.max_proxies {
+        return Err(anyhow!(
+            "subscription {} has {} proxies, more than the limit of {} (raise --max-proxies)",
+            id,
+            config.proxies.len(),
+            limits.max_proxies
+        ));
+    }
+    Ok(())
+}
+
+/// Read a response body, refusing to buffer more than `limit` bytes.
+async fn read_body_limited(
+    mut response: reqwest::Response,
+    id: &str,
+    limit: u64,
+) -> anyhow::Result<String> {
+    let too_large = |size: u64| {
+        anyhow!(
+            "subscription {} payload is {} bytes, more than the limit of {} (raise --max-payload-bytes)",
+            id,
+            size,
+            limit
+        )
+    };
+    if let Some(length) = response.content_length().filter(|length| *length > limit) {
+        return Err(too_large(length));
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(reqwest::Error::without_url)?
+    {
+        throttle_download(chunk.len()).await;
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > limit {
+            return Err(too_large(body.len() as u64));
+        }
+    }
+    String::from_utf8(body).with_context(|| format!("subscription {} body is not UTF-8", id))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct SubscriptionCacheMeta {
     pub etag: Option<String>,
     pub last_modified: Option<String>,
+    /// Provider asked us to back off (429/503 + Retry-After); no requests before this instant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<DateTime<Utc>>,
+    /// Hex SHA-256 of the cached body, used to detect unchanged downloads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<u64>,
+    /// Parsed `subscription-userinfo` header from the latest response that carried one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<SubscriptionUsage>,
 }
 
+/// Backoff applied when a provider rate-limits us without a usable Retry-After header.
+const DEFAULT_RATE_LIMIT_BACKOFF_SECS: i64 = 300;
+
+/// Longest backoff a provider's Retry-After can impose.
+const MAX_RATE_LIMIT_BACKOFF_SECS: i64 = 86_400;
+
+/// Longest refresh interval a provider can advertise (30 days).
+const MAX_UPDATE_INTERVAL_SECS: u64 = 30 * 86_400;
+
 struct FetchResult {
     yaml: String,
     etag: Option<String>,
     last_modified: Option<String>,
+    /// Set when the network fetch failed and `yaml` came from the cache instead.
+    fallback_error: Option<String>,
+    source: LoadSource,
+    /// Refresh interval hint sent with this response, in seconds.
+    update_interval: Option<u64>,
 }
 
 async fn fetch_remote(
@@ -163,15 +769,48 @@ async fn fetch_remote(
     url: &str,
     etag: Option<String>,
     last_modified: Option<String>,
+    head_probe: bool,
 ) -> anyhow::Result<FetchResult> {
     let cache_file = paths.cache_file(id);
     let meta_file = paths.cache_meta_file(id);
 
-    let cached_meta = match fs::read_to_string(&meta_file).await {
-        Ok(raw) => serde_json::from_str::<SubscriptionCacheMeta>(&raw).unwrap_or_default(),
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => SubscriptionCacheMeta::default(),
-        Err(err) => return Err(err.into()),
-    };
+    let cached_meta = read_cache_meta(&meta_file).await?;
+
+    if let Some(until) = cached_meta.retry_after.filter(|until| *until > Utc::now()) {
+        if let Some(cached) = read_cached_yaml(&cache_file).await? {
+            tracing::info!(id = id, until = %until, "provider rate-limit backoff active, using cached subscription");
+            return Ok(FetchResult {
+                yaml: cached,
+                etag: cached_meta.etag,
+                last_modified: cached_meta.last_modified,
+                fallback_error: None,
+                source: LoadSource::Cache,
+                update_interval: None,
+            });
+        }
+        return Err(anyhow!(
+            "subscription {} is rate limited until {} and no cache is available",
+            id,
+            until
+        ));
+    }
+
+    let has_validators = etag.is_some()
+        || last_modified.is_some()
+        || cached_meta.etag.is_some()
+        || cached_meta.last_modified.is_some();
+    if head_probe && !has_validators && cached_meta.body_sha256.is_some() {
+        if let Some(cached) = probe_unchanged(client, url, id, &cache_file, &cached_meta).await? {
+            return Ok(FetchResult {
+                yaml: cached,
+                etag: cached_meta.etag,
+                last_modified: cached_meta.last_modified,
+                fallback_error: None,
+                source: LoadSource::NotModified,
+                update_interval: None,
+            });
+        }
+    }
 
     let mut request = client.get(url);
 
@@ -187,54 +826,122 @@ async fn fetch_remote(
     let response = match request.timeout(Duration::from_secs(30)).send().await {
         Ok(resp) => resp,
         Err(err) => {
+            // The request URL carries the subscription token; keep it out of logs and errors.
+            let err = err.without_url();
             if let Some(cached) = read_cached_yaml(&cache_file).await? {
                 tracing::warn!(id = id, error = %err, "network error, using cached subscription");
                 return Ok(FetchResult {
                     yaml: cached,
                     etag: cached_meta.etag,
                     last_modified: cached_meta.last_modified,
+                    fallback_error: Some(err.to_string()),
+                    source: LoadSource::Cache,
+                    update_interval: None,
                 });
             }
             return Err(err.into());
         }
     };
 
+    let answered = response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED;
+    let record_usage = answered && !storage::is_read_only();
+    if let Some(usage) = parse_usage_header(response.headers()).filter(|_| record_usage) {
+        let sample = UsageSample {
+            at: Utc::now(),
+            usage,
+        };
+        if let Err(err) = record_usage_sample(&paths.usage_history_file(id), sample).await {
+            tracing::warn!(id = id, error = %err, "failed to record subscription usage");
+        }
+    }
+
     match response.status() {
         StatusCode::OK => {
             let headers = response.headers().clone();
-            let yaml = response.text().await?;
-            write_cache_files(&cache_file, &meta_file, &yaml, &headers).await?;
+            let yaml =
+                read_body_limited(response, id, current_fetch_limits().max_payload_bytes).await?;
+            write_cache_files(&cache_file, &meta_file, &yaml, &headers, &cached_meta, id).await?;
             let etag = header_to_string(headers.get(ETAG)).or(cached_meta.etag);
             let last_modified =
                 header_to_string(headers.get(LAST_MODIFIED)).or(cached_meta.last_modified);
 
+            let update_interval = parse_update_interval(&headers, &yaml);
             Ok(FetchResult {
                 yaml,
                 etag,
                 last_modified,
+                fallback_error: None,
+                source: LoadSource::Network,
+                update_interval,
             })
         }
         StatusCode::NOT_MODIFIED => {
             let yaml = read_cached_yaml(&cache_file)
                 .await?
                 .ok_or_else(|| anyhow!("remote responded 304 but cache missing for {}", id))?;
+            let usage = parse_usage_header(response.headers());
+            if usage.is_some() && usage != cached_meta.usage {
+                let meta = SubscriptionCacheMeta {
+                    usage,
+                    ..cached_meta.clone()
+                };
+                write_cache_meta(&meta_file, &meta).await?;
+            }
+            let update_interval = parse_update_interval(response.headers(), &yaml);
             Ok(FetchResult {
                 yaml,
                 etag: cached_meta.etag,
                 last_modified: cached_meta.last_modified,
+                fallback_error: None,
+                source: LoadSource::NotModified,
+                update_interval,
             })
         }
         status if status.is_success() => {
             let headers = response.headers().clone();
-            let yaml = response.text().await?;
-            write_cache_files(&cache_file, &meta_file, &yaml, &headers).await?;
+            let yaml =
+                read_body_limited(response, id, current_fetch_limits().max_payload_bytes).await?;
+            write_cache_files(&cache_file, &meta_file, &yaml, &headers, &cached_meta, id).await?;
             Ok(FetchResult {
+                update_interval: parse_update_interval(&headers, &yaml),
                 yaml,
                 etag: header_to_string(headers.get(ETAG)).or(cached_meta.etag),
                 last_modified: header_to_string(headers.get(LAST_MODIFIED))
                     .or(cached_meta.last_modified),
+                fallback_error: None,
+                source: LoadSource::Network,
             })
         }
+        status
+            if status == StatusCode::TOO_MANY_REQUESTS
+                || (status == StatusCode::SERVICE_UNAVAILABLE
+                    && response.headers().contains_key(RETRY_AFTER)) =>
+        {
+            let until = parse_retry_after(response.headers().get(RETRY_AFTER), Utc::now());
+            let meta = SubscriptionCacheMeta {
+                retry_after: Some(until),
+                ..cached_meta.clone()
+            };
+            write_cache_meta(&meta_file, &meta).await?;
+            if let Some(cached) = read_cached_yaml(&cache_file).await? {
+                tracing::warn!(id = id, status = ?status, until = %until, "provider rate-limited us, backing off and using cached subscription");
+                Ok(FetchResult {
+                    yaml: cached,
+                    etag: cached_meta.etag,
+                    last_modified: cached_meta.last_modified,
+                    fallback_error: None,
+                    source: LoadSource::Cache,
+                    update_interval: None,
+                })
+            } else {
+                Err(anyhow!(
+                    "failed to fetch subscription {}: {} (retry after {})",
+                    id,
+                    status,
+                    until
+                ))
+            }
+        }
         status => {
             if let Some(cached) = read_cached_yaml(&cache_file).await? {
                 tracing::warn!(id = id, status = ?status, "unexpected status, falling back to cache");
@@ -242,6 +949,9 @@ async fn fetch_remote(
                     yaml: cached,
                     etag: cached_meta.etag,
                     last_modified: cached_meta.last_modified,
+                    fallback_error: Some(format!("unexpected status {}", status)),
+                    source: LoadSource::Cache,
+                    update_interval: None,
                 })
             } else {
                 Err(anyhow!("failed to fetch subscription {}: {}", id, status))
@@ -263,22 +973,182 @@ async fn write_cache_files(
     meta_file: &Path,
     yaml: &str,
     headers: &HeaderMap,
+    previous: &SubscriptionCacheMeta,
+    id: &str,
 ) -> anyhow::Result<()> {
-    if let Some(parent) = cache_file.parent() {
-        fs::create_dir_all(parent).await?;
+    if storage::is_read_only() {
+        return Ok(());
+    }
+    let body_sha256 = sha256_hex(yaml.as_bytes());
+    let unchanged = previous.body_sha256.as_deref() == Some(body_sha256.as_str())
+        && fs::try_exists(cache_file).await.unwrap_or(false);
+    if unchanged {
+        tracing::debug!(id = id, "subscription body unchanged since last download");
+    } else {
+        storage::write_atomic(cache_file, yaml.as_bytes()).await?;
     }
-    fs::write(cache_file, yaml).await?;
 
     let meta = SubscriptionCacheMeta {
         etag: header_to_string(headers.get(ETAG)),
         last_modified: header_to_string(headers.get(LAST_MODIFIED)),
+        retry_after: None,
+        body_sha256: Some(body_sha256),
+        content_length: Some(yaml.len() as u64),
+        usage: parse_usage_header(headers).or(previous.usage),
     };
+    write_cache_meta(meta_file, &meta).await
+}
 
-    if let Some(parent) = meta_file.parent() {
-        fs::create_dir_all(parent).await?;
+/// HEAD the provider and return the cached body if it advertises the same content.
+async fn probe_unchanged(
+    client: &Client,
+    url: &str,
+    id: &str,
+    cache_file: &Path,
+    cached_meta: &SubscriptionCacheMeta,
+) -> anyhow::Result<Option<String>> {
+    let response = match client
+        .head(url)
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+    {
+        Ok(resp) if resp.status().is_success() => resp,
+        Ok(resp) => {
+            tracing::debug!(id = id, status = ?resp.status(), "HEAD probe not supported, downloading");
+            return Ok(None);
+        }
+        Err(err) => {
+            tracing::debug!(id = id, error = %err.without_url(), "HEAD probe failed, downloading");
+            return Ok(None);
+        }
+    };
+
+    let headers = response.headers();
+    let advertised_digest = headers
+        .get("repr-digest")
+        .or_else(|| headers.get("digest"))
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_sha256_digest);
+    let unchanged = match (advertised_digest, cached_meta.body_sha256.as_deref()) {
+        (Some(advertised), Some(cached)) => advertised == cached,
+        _ => {
+            let advertised_len = header_to_string(headers.get(CONTENT_LENGTH))
+                .and_then(|raw| raw.trim().parse::<u64>().ok());
+            advertised_len.is_some() && advertised_len == cached_meta.content_length
+        }
+    };
+    if !unchanged {
+        return Ok(None);
+    }
+
+    let cached = read_cached_yaml(cache_file).await?;
+    if cached.is_some() {
+        tracing::info!(
+            id = id,
+            "HEAD probe reports unchanged content, using cached subscription"
+        );
+    }
+    Ok(cached)
+}
+
+/// Cache and log id for a URL subscription: a short hash, so the token never leaves the URL.
+fn url_id(url: &str) -> String {
+    format!("url-{}", &sha256_hex(url.as_bytes())[..16])
+}
+
+fn sha256_hex(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+/// Extract the SHA-256 from a `Digest: sha-256=<b64>` or `Repr-Digest: sha-256=:<b64>:` header
+/// as lowercase hex.
+fn parse_sha256_digest(raw: &str) -> Option<String> {
+    raw.split(',').find_map(|part| {
+        let (alg, value) = part.trim().split_once('=')?;
+        if !alg.trim().eq_ignore_ascii_case("sha-256") {
+            return None;
+        }
+        let value = value.trim().trim_matches(':');
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .ok()?;
+        Some(hex::encode(bytes))
+    })
+}
+
+async fn read_cache_meta(meta_file: &Path) -> anyhow::Result<SubscriptionCacheMeta> {
+    match fs::read_to_string(meta_file).await {
+        Ok(raw) => Ok(serde_json::from_str::<SubscriptionCacheMeta>(&raw).unwrap_or_default()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Ok(SubscriptionCacheMeta::default())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Refresh hint in seconds from a `profile-update-interval` header (hours, as understood by
+/// Clash clients) or a Surge-style `#!MANAGED-CONFIG <url> interval=<seconds>` first line.
+fn parse_update_interval(headers: &HeaderMap, body: &str) -> Option<u64> {
+    let from_header = header_to_string(headers.get("profile-update-interval"))
+        .and_then(|raw| raw.trim().parse::<f64>().ok())
+        .filter(|hours| hours.is_finite() && *hours > 0.0)
+        .map(|hours| ((hours * 3600.0).round() as u64).clamp(1, MAX_UPDATE_INTERVAL_SECS));
+    from_header.or_else(|| {
+        let first = body.lines().next()?.trim();
+        first
+            .strip_prefix("#!MANAGED-CONFIG")?
+            .split_whitespace()
+            .find_map(|part| part.strip_prefix("interval="))?
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map(|secs| secs.min(MAX_UPDATE_INTERVAL_SECS))
+    })
+}
+
+fn parse_usage_header(headers: &HeaderMap) -> Option<SubscriptionUsage> {
+    header_to_string(headers.get("subscription-userinfo"))
+        .and_then(|raw| SubscriptionUsage::parse(&raw))
+}
+
+async fn write_cache_meta(meta_file: &Path, meta: &SubscriptionCacheMeta) -> anyhow::Result<()> {
+    if storage::is_read_only() {
+        return Ok(());
+    }
+    // Readers in other processes (a cron merge next to a daemon) never see a torn file.
+    storage::write_atomic(meta_file, serde_json::to_string(meta)?.as_bytes()).await
+}
+
+/// Resolve a Retry-After header (delta-seconds or HTTP-date) into an absolute deadline.
+fn parse_retry_after(
+    value: Option<&reqwest::header::HeaderValue>,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let fallback = now + chrono::Duration::seconds(DEFAULT_RATE_LIMIT_BACKOFF_SECS);
+    let latest = now + chrono::Duration::seconds(MAX_RATE_LIMIT_BACKOFF_SECS);
+    let Some(raw) = header_to_string(value) else {
+        return fallback;
+    };
+    let raw = raw.trim();
+    let is_digits = |text: &str| !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit());
+    if is_digits(raw) {
+        // A delay too large to represent is still longer than the cap.
+        return raw
+            .parse::<i64>()
+            .ok()
+            .and_then(chrono::TimeDelta::try_seconds)
+            .and_then(|delta| now.checked_add_signed(delta))
+            .map_or(latest, |until| until.min(latest));
+    }
+    if raw.strip_prefix('-').is_some_and(is_digits) {
+        // Negative delays are nonsense; back off as if none was given.
+        return fallback;
+    }
+    match DateTime::parse_from_rfc2822(raw) {
+        Ok(date) => date.with_timezone(&Utc).min(latest),
+        Err(_) => fallback,
     }
-    fs::write(meta_file, serde_json::to_string(&meta)?).await?;
-    Ok(())
 }
 
 fn header_to_string(value: Option<&reqwest::header::HeaderValue>) -> Option<String> {
@@ -286,3 +1156,254 @@ fn header_to_string(value: Option<&reqwest::header::HeaderValue>) -> Option<Stri
         .and_then(|val| val.to_str().ok())
         .map(|s| s.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use reqwest::header::HeaderValue;
+    use tempfile::TempDir;
+
+    #[test]
+    fn check_proxy_limit_rejects_oversized_subscriptions() {
+        let config = ClashConfig {
+            proxies: vec![serde_yaml::Value::Null; 3],
+            ..Default::default()
+        };
+        let limits = FetchLimits {
+            max_payload_bytes: 1024,
+            max_proxies: 2,
+        };
+
+        let err = check_proxy_limit("sub", &config, limits).unwrap_err();
+        assert!(err.to_string().contains("3 proxies"));
+        assert!(check_proxy_limit(
+            "sub",
+            &config,
+            FetchLimits {
+                max_proxies: 3,
+                ..limits
+            }
+        )
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn local_subscription_files_are_size_checked() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sub.yaml");
+        fs::write(&path, "proxies: []\n").await.unwrap();
+
+        assert_eq!(read_file_limited(&path, 64).await.unwrap(), "proxies: []\n");
+        let err = read_file_limited(&path, 4).await.unwrap_err();
+        assert!(err.to_string().contains("12 bytes"), "{err}");
+    }
+
+    #[test]
+    fn parse_sha256_digest_handles_both_header_forms() {
+        let expected = sha256_hex(b"hello");
+        let b64 = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(b"hello"));
+
+        assert_eq!(
+            parse_sha256_digest(&format!("md5=abc, SHA-256={}", b64)),
+            Some(expected.clone())
+        );
+        assert_eq!(
+            parse_sha256_digest(&format!("sha-256=:{}:", b64)),
+            Some(expected)
+        );
+        assert_eq!(parse_sha256_digest("sha-512=:AAAA:"), None);
+    }
+
+    #[test]
+    fn update_interval_from_header_or_managed_config_line() {
+        let mut headers = HeaderMap::new();
+        headers.insert("profile-update-interval", HeaderValue::from_static("12"));
+        assert_eq!(parse_update_interval(&headers, "proxies: []"), Some(43_200));
+
+        let body = "#!MANAGED-CONFIG https://example.com/sub interval=3600 strict=false\n[Proxy]\n";
+        assert_eq!(parse_update_interval(&HeaderMap::new(), body), Some(3600));
+        assert_eq!(
+            parse_update_interval(&HeaderMap::new(), "proxies: []"),
+            None
+        );
+
+        for bogus in ["inf", "NaN", "-3", "0"] {
+            let mut headers = HeaderMap::new();
+            headers.insert("profile-update-interval", HeaderValue::from_static(bogus));
+            assert_eq!(parse_update_interval(&headers, "proxies: []"), None);
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert("profile-update-interval", HeaderValue::from_static("1e300"));
+        assert_eq!(
+            parse_update_interval(&headers, "proxies: []"),
+            Some(MAX_UPDATE_INTERVAL_SECS)
+        );
+        let body = "#!MANAGED-CONFIG https://example.com/sub interval=18446744073709551615\n";
+        assert_eq!(
+            parse_update_interval(&HeaderMap::new(), body),
+            Some(MAX_UPDATE_INTERVAL_SECS)
+        );
+    }
+
+    #[test]
+    fn refresh_is_due_after_provider_interval() {
+        let now = Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap();
+        let mut sub = Subscription {
+            url: Some("https://example.com/sub".to_string()),
+            last_updated: Some(now - chrono::Duration::hours(2)),
+            ..Default::default()
+        };
+        let six_hours = Duration::from_secs(6 * 3600);
+        assert!(!sub.is_due(six_hours, now));
+
+        sub.update_interval = Some(3600);
+        assert!(sub.is_due(six_hours, now));
+
+        sub.update_interval = Some(u64::MAX);
+        assert!(!sub.is_due(six_hours, now));
+        assert_eq!(
+            sub.next_refresh(six_hours),
+            Some(now - chrono::Duration::hours(2) + chrono::Duration::days(30))
+        );
+
+        sub.update_interval = None;
+        sub.failure_count = 1;
+        assert!(sub.is_due(six_hours, now));
+    }
+
+    #[test]
+    fn parse_retry_after_accepts_seconds_and_dates() {
+        let now = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+
+        let secs = HeaderValue::from_static("120");
+        assert_eq!(
+            parse_retry_after(Some(&secs), now),
+            now + chrono::Duration::seconds(120)
+        );
+
+        let date = HeaderValue::from_static("Wed, 01 Jan 2025 01:00:00 GMT");
+        assert_eq!(
+            parse_retry_after(Some(&date), now),
+            Utc.with_ymd_and_hms(2025, 1, 1, 1, 0, 0).unwrap()
+        );
+
+        assert_eq!(
+            parse_retry_after(None, now),
+            now + chrono::Duration::seconds(DEFAULT_RATE_LIMIT_BACKOFF_SECS)
+        );
+
+        let fallback = now + chrono::Duration::seconds(DEFAULT_RATE_LIMIT_BACKOFF_SECS);
+        let latest = now + chrono::Duration::seconds(MAX_RATE_LIMIT_BACKOFF_SECS);
+        for huge in [
+            "99999999999999",
+            "9223372036854775807",
+            "99999999999999999999999",
+        ] {
+            let huge = HeaderValue::from_static(huge);
+            assert_eq!(parse_retry_after(Some(&huge), now), latest);
+        }
+        let negative = HeaderValue::from_static("-5");
+        assert_eq!(parse_retry_after(Some(&negative), now), fallback);
+        let week = HeaderValue::from_static("604800");
+        assert_eq!(
+            parse_retry_after(Some(&week), now),
+            now + chrono::Duration::seconds(MAX_RATE_LIMIT_BACKOFF_SECS)
+        );
+        let far = HeaderValue::from_static("Fri, 01 Jan 2100 00:00:00 GMT");
+        assert_eq!(
+            parse_retry_after(Some(&far), now),
+            now + chrono::Duration::seconds(MAX_RATE_LIMIT_BACKOFF_SECS)
+        );
+    }
+
+    #[tokio::test]
+    async fn load_config_drops_ignored_groups_and_rules() {
+        let temp_dir = TempDir::new().unwrap();
+        let sub_path = temp_dir.path().join("sub.yaml");
+        fs::write(
+            &sub_path,
+            r#"
+proxies:
+  - { name: A, type: http, server: example.com, port: 443 }
+proxy-groups:
+  - { name: Junk, type: select, proxies: [A] }
+rules:
+  - MATCH,Junk
+"#,
+        )
+        .await
+        .unwrap();
+
+        let paths = AppPaths::under(temp_dir.path());
+        let client = Client::new();
+        let mut subscription = Subscription {
+            path: Some(sub_path),
+            ignore_groups: true,
+            ignore_rules: true,
+            ..Default::default()
+        };
+
+        let config = subscription
+            .load_config(&client, &paths)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.proxies.len(), 1);
+        assert!(config.proxy_groups.is_empty());
+        assert!(config.rules.is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_fetch_logs_carry_no_url_token() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths = AppPaths::under(temp_dir.path());
+        let mut subscription = Subscription {
+            url: Some("https://h/sub?token=SECRET".to_string()),
+            ..Default::default()
+        };
+        subscription.ensure_id();
+        assert!(!subscription.id.contains("SECRET"));
+        let cache_file = paths.cache_file(&subscription.id);
+        fs::create_dir_all(cache_file.parent().unwrap())
+            .await
+            .unwrap();
+        fs::write(&cache_file, "proxies: []\n").await.unwrap();
+
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        subscription
+            .load_config(&Client::new(), &paths)
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("using cached subscription"), "{output}");
+        assert!(!output.contains("SECRET"), "{output}");
+        assert!(!subscription
+            .last_error
+            .unwrap_or_default()
+            .contains("SECRET"));
+    }
+}
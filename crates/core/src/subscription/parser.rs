@@ -6,10 +6,13 @@ use std::collections::HashSet;
 use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use base64::Engine;
 use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use serde_yaml::{Mapping, Number, Sequence, Value};
 use url::Url;
 
+use super::singbox::parse_singbox;
+use super::surge::parse_surge_or_quanx;
 use crate::model::ClashConfig;
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -17,11 +20,42 @@ pub struct ParseOptions {
     pub allow_base64: bool,
 }
 
-/// Attempt to interpret the raw subscription payload as a ClashConfig.
-///
-/// - First try native YAML deserialization.
-/// - Then attempt to decode base64-wrapped data.
-/// - Finally, treat the decoded/plain text as a list of share links (trojan/vmess/ss).
+/// Per-source override of how a payload is interpreted, bypassing the global [`ParseOptions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    /// Accept only native Clash YAML.
+    Clash,
+    /// Allow base64-wrapped YAML or share-link lists regardless of the global setting.
+    Base64,
+}
+
+impl ParseMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ParseMode::Clash => "clash",
+            ParseMode::Base64 => "base64",
+        }
+    }
+}
+
+/// Parse a payload honouring an optional per-source [`ParseMode`].
+pub fn parse_subscription_payload_for_mode(
+    raw: &str,
+    mode: Option<ParseMode>,
+    default: ParseOptions,
+) -> anyhow::Result<ClashConfig> {
+    match mode {
+        None => parse_subscription_payload_with_options(raw, default),
+        Some(ParseMode::Base64) => {
+            parse_subscription_payload_with_options(raw, ParseOptions { allow_base64: true })
+        }
+        Some(ParseMode::Clash) => serde_yaml::from_str::<ClashConfig>(raw)
+            .context("payload is not valid Clash YAML (source forced to clash: mode)"),
+    }
+}
+
+/// Attempt to interpret the raw subscription payload as a ClashConfig; see [`detect_and_parse`].
 #[allow(dead_code)]
 pub fn parse_subscription_payload(raw: &str) -> anyhow::Result<ClashConfig> {
     parse_subscription_payload_with_options(raw, ParseOptions { allow_base64: true })
@@ -31,29 +65,64 @@ pub fn parse_subscription_payload_with_options(
     raw: &str,
     opts: ParseOptions,
 ) -> anyhow::Result<ClashConfig> {
-    // Fast path: valid YAML Clash configuration.
-    if let Ok(config) = serde_yaml::from_str::<ClashConfig>(raw) {
-        return Ok(config);
-    }
+    detect_and_parse(raw, opts).map(|(config, _)| config)
+}
 
-    if opts.allow_base64 {
-        let mut decoded_candidates = decode_candidates(raw);
+/// Payload format a subscription turned out to use, remembered so later fetches can skip
+/// guessing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceFormat {
+    /// Native Clash YAML.
+    Clash,
+    /// Base64-wrapped payload (usually a share-link list).
+    Base64,
+    /// Plain-text share links, one per line.
+    ShareLinks,
+    /// Shadowsocks SIP008 JSON.
+    Sip008,
+    /// sing-box JSON with `outbounds`.
+    SingBox,
+    /// Surge `[Proxy]` section or Quantumult X server lines.
+    Surge,
+}
 
-        for candidate in decoded_candidates.iter() {
-            if let Ok(config) = serde_yaml::from_str::<ClashConfig>(candidate) {
-                return Ok(config);
-            }
+impl SourceFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceFormat::Clash => "clash",
+            SourceFormat::Base64 => "base64",
+            SourceFormat::ShareLinks => "share-links",
+            SourceFormat::Sip008 => "sip008",
+            SourceFormat::SingBox => "sing-box",
+            SourceFormat::Surge => "surge",
         }
+    }
+}
 
-        for candidate in decoded_candidates.drain(..) {
-            if let Some(config) = parse_share_links(&candidate)? {
-                return Ok(config);
-            }
+/// Try every supported format in turn and report which one matched.
+///
+/// - Detect Shadowsocks SIP008 and sing-box JSON documents (JSON is also valid YAML, so this
+///   runs first).
+/// - Then try native YAML deserialization, then Surge `[Proxy]` / Quantumult X server lists.
+/// - Then attempt to decode base64-wrapped data.
+/// - Finally, treat the plain text as a list of share links (trojan/vmess/ss).
+pub fn detect_and_parse(
+    raw: &str,
+    opts: ParseOptions,
+) -> anyhow::Result<(ClashConfig, SourceFormat)> {
+    if let Some(found) = parse_structured(raw)? {
+        return Ok(found);
+    }
+
+    if opts.allow_base64 {
+        if let Some(config) = parse_base64(raw)? {
+            return Ok((config, SourceFormat::Base64));
         }
     }
 
     if let Some(config) = parse_share_links(raw)? {
-        return Ok(config);
+        return Ok((config, SourceFormat::ShareLinks));
     }
 
     Err(anyhow!(
@@ -61,6 +130,122 @@ pub fn parse_subscription_payload_with_options(
     ))
 }
 
+/// Why `raw` matched none of the formats [`detect_and_parse`] tries: one line per format the
+/// payload came close to, such as the YAML error of a broken Clash config or the share links
+/// with an unsupported scheme. Empty when nothing in the payload resembles a known format.
+pub fn explain_parse_failure(raw: &str, opts: ParseOptions) -> Vec<String> {
+    if raw.trim().is_empty() {
+        return vec!["payload is empty".to_string()];
+    }
+    let mut reasons = Vec::new();
+    let looks_like_clash = raw
+        .lines()
+        .any(|line| line.starts_with("proxies:") || line.starts_with("proxy-groups:"));
+    match serde_yaml::from_str::<Value>(raw) {
+        Ok(Value::Mapping(_)) => {
+            if let Err(err) = serde_yaml::from_str::<ClashConfig>(raw) {
+                reasons.push(format!("Clash YAML: {}", err));
+            }
+        }
+        Err(err) if looks_like_clash => reasons.push(format!("Clash YAML: {}", err)),
+        _ => {}
+    }
+    if raw.trim_start().starts_with('{') {
+        match serde_json::from_str::<JsonValue>(raw) {
+            Ok(_) => reasons
+                .push("JSON: neither a SIP008 `servers` list nor sing-box `outbounds`".to_string()),
+            Err(err) => reasons.push(format!("JSON: {}", err)),
+        }
+    }
+    let decoded = decode_candidates(raw);
+    if opts.allow_base64 {
+        for candidate in &decoded {
+            reasons.extend(
+                unsupported_share_links(candidate)
+                    .into_iter()
+                    .map(|reason| format!("{} (after base64 decoding)", reason)),
+            );
+        }
+    } else if !decoded.is_empty() {
+        reasons.push("payload looks base64-encoded, but base64 decoding is disabled".to_string());
+    }
+    reasons.extend(unsupported_share_links(raw));
+    reasons
+}
+
+/// `line N: unsupported share link scheme` for every `scheme://` line that is not trojan,
+/// vmess or ss.
+fn unsupported_share_links(text: &str) -> Vec<String> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            let (scheme, _) = line.trim().split_once("://")?;
+            let is_scheme = !scheme.is_empty()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+            (is_scheme && !matches!(scheme, "trojan" | "vmess" | "ss")).then(|| {
+                format!(
+                    "line {}: unsupported share link scheme `{}://`",
+                    idx + 1,
+                    scheme
+                )
+            })
+        })
+        .collect()
+}
+
+/// Parse a payload as a previously detected format, without trying the others.
+pub fn parse_as_format(raw: &str, format: SourceFormat) -> anyhow::Result<ClashConfig> {
+    let parsed = match format {
+        SourceFormat::Clash => {
+            return serde_yaml::from_str::<ClashConfig>(raw)
+                .context("payload is no longer valid Clash YAML")
+        }
+        SourceFormat::Base64 => parse_base64(raw)?,
+        SourceFormat::ShareLinks => parse_share_links(raw)?,
+        SourceFormat::Sip008 => parse_sip008(raw)?,
+        SourceFormat::SingBox => parse_singbox(raw)?,
+        SourceFormat::Surge => parse_surge_or_quanx(raw)?,
+    };
+    parsed.ok_or_else(|| anyhow!("payload is no longer in {} format", format.as_str()))
+}
+
+/// The self-describing formats: SIP008, sing-box, Clash YAML and Surge/Quantumult X.
+fn parse_structured(raw: &str) -> anyhow::Result<Option<(ClashConfig, SourceFormat)>> {
+    if let Some(config) = parse_sip008(raw)? {
+        return Ok(Some((config, SourceFormat::Sip008)));
+    }
+    if let Some(config) = parse_singbox(raw)? {
+        return Ok(Some((config, SourceFormat::SingBox)));
+    }
+    // Fast path: valid YAML Clash configuration.
+    if let Ok(config) = serde_yaml::from_str::<ClashConfig>(raw) {
+        return Ok(Some((config, SourceFormat::Clash)));
+    }
+    if let Some(config) = parse_surge_or_quanx(raw)? {
+        return Ok(Some((config, SourceFormat::Surge)));
+    }
+    Ok(None)
+}
+
+fn parse_base64(raw: &str) -> anyhow::Result<Option<ClashConfig>> {
+    let mut decoded_candidates = decode_candidates(raw);
+
+    for candidate in decoded_candidates.iter() {
+        if let Some((config, _)) = parse_structured(candidate)? {
+            return Ok(Some(config));
+        }
+    }
+
+    for candidate in decoded_candidates.drain(..) {
+        if let Some(config) = parse_share_links(&candidate)? {
+            return Ok(Some(config));
+        }
+    }
+    Ok(None)
+}
+
 /// Parse a plain-text list of share links (trojan/vmess/ss), one per line.
 ///
 /// Unlike `parse_subscription_payload_with_options`, this does not attempt YAML parsing or base64
@@ -120,21 +305,22 @@ fn is_mostly_printable(text: &str) -> bool {
 fn parse_share_links(input: &str) -> anyhow::Result<Option<ClashConfig>> {
     let mut proxies = Vec::new();
 
-    for line in input.lines() {
+    for (idx, line) in input.lines().enumerate() {
         let line = line.trim();
         if line.is_empty() {
             continue;
         }
 
         let value = if line.starts_with("trojan://") {
-            parse_trojan(line)?
+            parse_trojan(line)
         } else if line.starts_with("vmess://") {
-            parse_vmess(line)?
+            parse_vmess(line)
         } else if line.starts_with("ss://") {
-            parse_shadowsocks(line)?
+            parse_shadowsocks(line)
         } else {
             continue;
         };
+        let value = value.with_context(|| format!("invalid share link on line {}", idx + 1))?;
 
         if let Some(value) = value {
             proxies.push(value);
@@ -151,6 +337,109 @@ fn parse_share_links(input: &str) -> anyhow::Result<Option<ClashConfig>> {
     }))
 }
 
+/// Convert a Shadowsocks SIP008 document (`{"version": 1, "servers": [...]}`) into proxies.
+///
+/// Returns `Ok(None)` when the payload is not a SIP008 document.
+fn parse_sip008(raw: &str) -> anyhow::Result<Option<ClashConfig>> {
+    if !raw.trim_start().starts_with('{') {
+        return Ok(None);
+    }
+    let Ok(doc) = serde_json::from_str::<JsonValue>(raw) else {
+        return Ok(None);
+    };
+    let Some(servers) = doc.get("servers").and_then(JsonValue::as_array) else {
+        return Ok(None);
+    };
+
+    let mut proxies = Vec::with_capacity(servers.len());
+    for (idx, entry) in servers.iter().enumerate() {
+        let field = |key: &str| entry.get(key).and_then(JsonValue::as_str);
+        let server =
+            field("server").ok_or_else(|| anyhow!("SIP008 server #{} missing server", idx))?;
+        let port = entry
+            .get("server_port")
+            .and_then(|value| match value {
+                JsonValue::Number(n) => n.as_u64(),
+                JsonValue::String(s) => s.parse().ok(),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("SIP008 server #{} missing server_port", idx))?;
+        let method =
+            field("method").ok_or_else(|| anyhow!("SIP008 server #{} missing method", idx))?;
+        let password =
+            field("password").ok_or_else(|| anyhow!("SIP008 server #{} missing password", idx))?;
+        let name = field("remarks")
+            .filter(|remarks| !remarks.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}:{}", server, port));
+
+        let mut map = Mapping::new();
+        insert_string(&mut map, "name", name);
+        insert_string(&mut map, "type", "ss");
+        insert_string(&mut map, "server", server);
+        insert_u64(&mut map, "port", port);
+        insert_string(&mut map, "cipher", method);
+        insert_string(&mut map, "password", password);
+        map.insert(Value::from("udp"), Value::Bool(true));
+
+        if let Some(plugin) = field("plugin").filter(|plugin| !plugin.is_empty()) {
+            insert_sip003_plugin(&mut map, plugin, field("plugin_opts").unwrap_or(""));
+        }
+
+        proxies.push(Value::Mapping(map));
+    }
+
+    Ok(Some(ClashConfig {
+        proxies,
+        ..Default::default()
+    }))
+}
+
+/// Map SIP003 plugin names/options (`obfs=http;obfs-host=x`) onto Clash `plugin`/`plugin-opts`.
+pub(super) fn insert_sip003_plugin(map: &mut Mapping, plugin: &str, opts: &str) {
+    let pairs: Vec<(&str, &str)> = opts
+        .split(';')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.split_once('=').unwrap_or((part, "")))
+        .collect();
+    let get = |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+
+    let mut plugin_opts = Mapping::new();
+    match plugin {
+        "obfs-local" | "simple-obfs" => {
+            insert_string(map, "plugin", "obfs");
+            if let Some(mode) = get("obfs") {
+                insert_string(&mut plugin_opts, "mode", mode);
+            }
+            if let Some(host) = get("obfs-host") {
+                insert_string(&mut plugin_opts, "host", host);
+            }
+        }
+        "v2ray-plugin" => {
+            insert_string(map, "plugin", "v2ray-plugin");
+            insert_string(&mut plugin_opts, "mode", get("mode").unwrap_or("websocket"));
+            if let Some(host) = get("host") {
+                insert_string(&mut plugin_opts, "host", host);
+            }
+            if let Some(path) = get("path") {
+                insert_string(&mut plugin_opts, "path", path);
+            }
+            if get("tls").is_some() {
+                plugin_opts.insert(Value::from("tls"), Value::Bool(true));
+            }
+        }
+        other => {
+            insert_string(map, "plugin", other);
+            for (key, value) in &pairs {
+                insert_string(&mut plugin_opts, key, value);
+            }
+        }
+    }
+    if !plugin_opts.is_empty() {
+        map.insert(Value::from("plugin-opts"), Value::Mapping(plugin_opts));
+    }
+}
+
 fn parse_trojan(line: &str) -> anyhow::Result<Option<Value>> {
     let url = Url::parse(line)?;
     let server = url
@@ -358,8 +647,19 @@ fn parse_shadowsocks(line: &str) -> anyhow::Result<Option<Value>> {
         None => (main, None),
     };
 
-    let credentials = if body.contains('@') {
-        body.to_string()
+    // SIP002 puts a base64url `method:password` before the `@` and may end the host with `/`.
+    let body = body.trim_end_matches('/');
+    let credentials = if let Some((userinfo, host)) = body.rsplit_once('@') {
+        if userinfo.contains(':') {
+            body.to_string()
+        } else {
+            let decoded = URL_SAFE_NO_PAD
+                .decode(userinfo.trim_end_matches('='))
+                .context("failed to decode shadowsocks userinfo")?;
+            let userinfo =
+                String::from_utf8(decoded).context("shadowsocks credentials are not UTF-8")?;
+            format!("{}@{}", userinfo, host)
+        }
     } else {
         let padded = pad_base64(body);
         let decoded = STANDARD
@@ -375,8 +675,9 @@ fn parse_shadowsocks(line: &str) -> anyhow::Result<Option<Value>> {
         .split_once(':')
         .ok_or_else(|| anyhow!("shadowsocks share link missing cipher or password"))?;
     let (server, port) = server_part
-        .split_once(':')
+        .rsplit_once(':')
         .ok_or_else(|| anyhow!("shadowsocks share link missing port"))?;
+    let server = server.trim_start_matches('[').trim_end_matches(']');
     let port: u16 = port.parse()?;
 
     let mut map = Mapping::new();
@@ -393,19 +694,22 @@ fn parse_shadowsocks(line: &str) -> anyhow::Result<Option<Value>> {
     map.insert(Value::from("udp"), Value::Bool(true));
 
     if let Some(plugin) = plugin {
-        if let Some((_, opts)) = plugin.split_once("plugin=") {
-            insert_string(&mut map, "plugin", opts);
+        if let Some((_, spec)) = plugin.split_once("plugin=") {
+            let spec = spec.split('&').next().unwrap_or_default();
+            let spec = percent_decode_str(spec).decode_utf8_lossy();
+            let (name, opts) = spec.split_once(';').unwrap_or((&spec, ""));
+            insert_sip003_plugin(&mut map, name, opts);
         }
     }
 
     Ok(Some(Value::Mapping(map)))
 }
 
-fn insert_string<S: AsRef<str>>(map: &mut Mapping, key: &str, value: S) {
+pub(super) fn insert_string<S: AsRef<str>>(map: &mut Mapping, key: &str, value: S) {
     map.insert(Value::from(key), Value::from(value.as_ref()));
 }
 
-fn insert_u64(map: &mut Mapping, key: &str, value: u64) {
+pub(super) fn insert_u64(map: &mut Mapping, key: &str, value: u64) {
     map.insert(Value::from(key), Value::Number(Number::from(value)));
 }
 
@@ -541,6 +845,85 @@ ss://aes-128-gcm:test@example3.com:8388#SS1"#;
         assert!(types.contains(&"ss"));
     }
 
+    #[test]
+    fn parse_mode_overrides_global_options() {
+        let encoded = STANDARD.encode("trojan://pw@example.com:443#Node");
+        let disallow = ParseOptions {
+            allow_base64: false,
+        };
+
+        assert!(parse_subscription_payload_for_mode(&encoded, None, disallow).is_err());
+        let config =
+            parse_subscription_payload_for_mode(&encoded, Some(ParseMode::Base64), disallow)
+                .expect("base64 mode should decode");
+        assert_eq!(config.proxies.len(), 1);
+
+        let links = "ss://aes-256-gcm:password@ss.example.com:8388#Test";
+        assert!(parse_subscription_payload_for_mode(
+            links,
+            Some(ParseMode::Clash),
+            ParseOptions { allow_base64: true }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_sip008_document() {
+        let json = r#"{
+  "version": 1,
+  "servers": [
+    {
+      "id": "27b8a625-4f4b-4428-9f0f-8a2317db7c79",
+      "remarks": "Tokyo",
+      "server": "jp.example.com",
+      "server_port": 8388,
+      "password": "secret",
+      "method": "chacha20-ietf-poly1305",
+      "plugin": "obfs-local",
+      "plugin_opts": "obfs=http;obfs-host=www.example.com"
+    },
+    {
+      "server": "us.example.com",
+      "server_port": 443,
+      "password": "secret",
+      "method": "aes-256-gcm"
+    }
+  ]
+}"#;
+        let config = parse_subscription_payload_with_options(json, ParseOptions::default())
+            .expect("should parse");
+        assert_eq!(config.proxies.len(), 2);
+        assert!(config.extra.is_empty());
+
+        let first = config.proxies[0].as_mapping().expect("mapping");
+        assert_eq!(
+            first.get(Value::from("name")).and_then(Value::as_str),
+            Some("Tokyo")
+        );
+        assert_eq!(
+            first.get(Value::from("cipher")).and_then(Value::as_str),
+            Some("chacha20-ietf-poly1305")
+        );
+        assert_eq!(
+            first.get(Value::from("plugin")).and_then(Value::as_str),
+            Some("obfs")
+        );
+        let opts = first
+            .get(Value::from("plugin-opts"))
+            .and_then(Value::as_mapping)
+            .expect("plugin-opts");
+        assert_eq!(
+            opts.get(Value::from("host")).and_then(Value::as_str),
+            Some("www.example.com")
+        );
+
+        let second = config.proxies[1].as_mapping().expect("mapping");
+        assert_eq!(
+            second.get(Value::from("name")).and_then(Value::as_str),
+            Some("us.example.com:443")
+        );
+    }
+
     #[test]
     fn parse_direct_yaml_config() {
         let yaml = r#"
@@ -626,4 +1009,54 @@ rules:
         assert_eq!(pad_base64("YWJjZA"), "YWJjZA==");
         assert_eq!(pad_base64("YWJjZGU"), "YWJjZGU=");
     }
+
+    #[test]
+    fn detects_and_reparses_source_format() {
+        let links = "trojan://password@example.com:443#Example";
+        let encoded = STANDARD.encode(links);
+        let all = ParseOptions { allow_base64: true };
+
+        let (_, format) = detect_and_parse(&encoded, all).unwrap();
+        assert_eq!(format, SourceFormat::Base64);
+        assert_eq!(parse_as_format(&encoded, format).unwrap().proxies.len(), 1);
+
+        let (_, format) = detect_and_parse(links, all).unwrap();
+        assert_eq!(format, SourceFormat::ShareLinks);
+
+        let yaml = "proxies:\n  - {name: a, type: ss, server: s, port: 1}\n";
+        let (_, format) = detect_and_parse(yaml, all).unwrap();
+        assert_eq!(format, SourceFormat::Clash);
+
+        let err = parse_as_format(links, SourceFormat::Sip008).unwrap_err();
+        assert!(err.to_string().contains("sip008"));
+    }
+
+    #[test]
+    fn explains_why_a_payload_did_not_parse() {
+        let off = ParseOptions {
+            allow_base64: false,
+        };
+        let broken = "proxies:\n  - {name: a, type: ss, server: s, port: 1}\n rules: []\n";
+        let reasons = explain_parse_failure(broken, off);
+        assert!(reasons[0].starts_with("Clash YAML: "), "{:?}", reasons);
+
+        let links = "trojan://password@example.com:443#ok\nhysteria2://pw@example.com:443#new\n";
+        assert_eq!(
+            explain_parse_failure(links, off),
+            ["line 2: unsupported share link scheme `hysteria2://`"]
+        );
+        let encoded = STANDARD.encode(links);
+        assert_eq!(
+            explain_parse_failure(&encoded, off),
+            ["payload looks base64-encoded, but base64 decoding is disabled"]
+        );
+        assert_eq!(
+            explain_parse_failure(&encoded, ParseOptions { allow_base64: true }),
+            ["line 2: unsupported share link scheme `hysteria2://` (after base64 decoding)"]
+        );
+
+        let err = detect_and_parse("ss://aes-256-gcm:pw@s.example.com:1\ntrojan://@:443\n", off)
+            .unwrap_err();
+        assert!(format!("{:#}", err).starts_with("invalid share link on line 2: "));
+    }
 }
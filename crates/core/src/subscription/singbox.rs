@@ -0,0 +1,328 @@
+//! Conversion of sing-box JSON profiles into Clash proxies.
+
+use anyhow::anyhow;
+use serde_json::Value as JsonValue;
+use serde_yaml::{Mapping, Sequence, Value};
+
+use super::parser::{insert_sip003_plugin, insert_string, insert_u64};
+use crate::model::ClashConfig;
+
+/// Convert the `outbounds` of a sing-box config into Clash proxy mappings.
+///
+/// Returns `Ok(None)` when the payload is not a sing-box document. Outbounds that are not
+/// proxies (direct, block, dns, selector, urltest) or use unsupported protocols are skipped.
+pub(super) fn parse_singbox(raw: &str) -> anyhow::Result<Option<ClashConfig>> {
+    if !raw.trim_start().starts_with('{') {
+        return Ok(None);
+    }
+    let Ok(doc) = serde_json::from_str::<JsonValue>(raw) else {
+        return Ok(None);
+    };
+    let Some(outbounds) = doc.get("outbounds").and_then(JsonValue::as_array) else {
+        return Ok(None);
+    };
+
+    let mut proxies = Vec::new();
+    for outbound in outbounds {
+        let kind = outbound
+            .get("type")
+            .and_then(JsonValue::as_str)
+            .unwrap_or("");
+        let converted = match kind {
+            "shadowsocks" => convert_shadowsocks(outbound),
+            "vmess" => convert_vmess(outbound),
+            "trojan" => convert_trojan(outbound),
+            "hysteria2" => convert_hysteria2(outbound),
+            "tuic" => convert_tuic(outbound),
+            "direct" | "block" | "dns" | "selector" | "urltest" => continue,
+            other => {
+                tracing::debug!(kind = other, "skipping unsupported sing-box outbound");
+                continue;
+            }
+        };
+        match converted {
+            Some(map) => proxies.push(Value::Mapping(map)),
+            None => tracing::warn!(
+                kind = kind,
+                tag = outbound
+                    .get("tag")
+                    .and_then(JsonValue::as_str)
+                    .unwrap_or(""),
+                "skipping incomplete sing-box outbound"
+            ),
+        }
+    }
+
+    if proxies.is_empty() {
+        return Err(anyhow!(
+            "sing-box config contains no supported outbounds (shadowsocks, vmess, trojan, hysteria2, tuic)"
+        ));
+    }
+
+    Ok(Some(ClashConfig {
+        proxies,
+        ..Default::default()
+    }))
+}
+
+fn str_field<'a>(value: &'a JsonValue, key: &str) -> Option<&'a str> {
+    value
+        .get(key)
+        .and_then(JsonValue::as_str)
+        .filter(|s| !s.is_empty())
+}
+
+/// Common `name`/`type`/`server`/`port` header shared by every converted outbound.
+fn base_mapping(outbound: &JsonValue, clash_type: &str) -> Option<Mapping> {
+    let server = str_field(outbound, "server")?;
+    let port = outbound.get("server_port").and_then(JsonValue::as_u64)?;
+    let name = str_field(outbound, "tag")
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{}:{}", server, port));
+
+    let mut map = Mapping::new();
+    insert_string(&mut map, "name", name);
+    insert_string(&mut map, "type", clash_type);
+    insert_string(&mut map, "server", server);
+    insert_u64(&mut map, "port", port);
+    map.insert(Value::from("udp"), Value::Bool(true));
+    Some(map)
+}
+
+/// Translate a sing-box `tls` block. `sni_key` differs per protocol (`servername` for vmess).
+fn apply_tls(map: &mut Mapping, outbound: &JsonValue, sni_key: &str, set_tls_flag: bool) {
+    let Some(tls) = outbound.get("tls") else {
+        return;
+    };
+    if tls.get("enabled").and_then(JsonValue::as_bool) == Some(false) {
+        return;
+    }
+    if set_tls_flag {
+        map.insert(Value::from("tls"), Value::Bool(true));
+    }
+    if let Some(sni) = str_field(tls, "server_name") {
+        insert_string(map, sni_key, sni);
+    }
+    if tls.get("insecure").and_then(JsonValue::as_bool) == Some(true) {
+        map.insert(Value::from("skip-cert-verify"), Value::Bool(true));
+    }
+    if let Some(alpn) = tls.get("alpn").and_then(JsonValue::as_array) {
+        let sequence: Sequence = alpn
+            .iter()
+            .filter_map(JsonValue::as_str)
+            .map(Value::from)
+            .collect();
+        if !sequence.is_empty() {
+            map.insert(Value::from("alpn"), Value::Sequence(sequence));
+        }
+    }
+    if let Some(fp) = tls
+        .get("utls")
+        .filter(|utls| utls.get("enabled").and_then(JsonValue::as_bool) != Some(false))
+        .and_then(|utls| str_field(utls, "fingerprint"))
+    {
+        insert_string(map, "client-fingerprint", fp);
+    }
+}
+
+fn apply_transport(map: &mut Mapping, outbound: &JsonValue) {
+    let Some(transport) = outbound.get("transport") else {
+        return;
+    };
+    match str_field(transport, "type") {
+        Some("ws") => {
+            insert_string(map, "network", "ws");
+            let mut ws_opts = Mapping::new();
+            if let Some(path) = str_field(transport, "path") {
+                insert_string(&mut ws_opts, "path", path);
+            }
+            if let Some(host) = transport
+                .get("headers")
+                .and_then(|headers| headers.get("Host"))
+                .and_then(|host| match host {
+                    JsonValue::String(s) => Some(s.as_str()),
+                    JsonValue::Array(items) => items.first().and_then(JsonValue::as_str),
+                    _ => None,
+                })
+            {
+                let mut headers = Mapping::new();
+                insert_string(&mut headers, "Host", host);
+                ws_opts.insert(Value::from("headers"), Value::Mapping(headers));
+            }
+            if !ws_opts.is_empty() {
+                map.insert(Value::from("ws-opts"), Value::Mapping(ws_opts));
+            }
+        }
+        Some("grpc") => {
+            insert_string(map, "network", "grpc");
+            if let Some(service) = str_field(transport, "service_name") {
+                let mut grpc_opts = Mapping::new();
+                insert_string(&mut grpc_opts, "grpc-service-name", service);
+                map.insert(Value::from("grpc-opts"), Value::Mapping(grpc_opts));
+            }
+        }
+        Some(other) => {
+            tracing::debug!(transport = other, "unsupported sing-box transport; ignored");
+        }
+        None => {}
+    }
+}
+
+fn convert_shadowsocks(outbound: &JsonValue) -> Option<Mapping> {
+    let mut map = base_mapping(outbound, "ss")?;
+    insert_string(&mut map, "cipher", str_field(outbound, "method")?);
+    insert_string(&mut map, "password", str_field(outbound, "password")?);
+    if let Some(plugin) = str_field(outbound, "plugin") {
+        insert_sip003_plugin(
+            &mut map,
+            plugin,
+            str_field(outbound, "plugin_opts").unwrap_or(""),
+        );
+    }
+    Some(map)
+}
+
+fn convert_vmess(outbound: &JsonValue) -> Option<Mapping> {
+    let mut map = base_mapping(outbound, "vmess")?;
+    insert_string(&mut map, "uuid", str_field(outbound, "uuid")?);
+    insert_u64(
+        &mut map,
+        "alterId",
+        outbound
+            .get("alter_id")
+            .and_then(JsonValue::as_u64)
+            .unwrap_or(0),
+    );
+    insert_string(
+        &mut map,
+        "cipher",
+        str_field(outbound, "security").unwrap_or("auto"),
+    );
+    apply_tls(&mut map, outbound, "servername", true);
+    apply_transport(&mut map, outbound);
+    Some(map)
+}
+
+fn convert_trojan(outbound: &JsonValue) -> Option<Mapping> {
+    let mut map = base_mapping(outbound, "trojan")?;
+    insert_string(&mut map, "password", str_field(outbound, "password")?);
+    apply_tls(&mut map, outbound, "sni", false);
+    apply_transport(&mut map, outbound);
+    Some(map)
+}
+
+fn convert_hysteria2(outbound: &JsonValue) -> Option<Mapping> {
+    let mut map = base_mapping(outbound, "hysteria2")?;
+    insert_string(&mut map, "password", str_field(outbound, "password")?);
+    if let Some(up) = outbound.get("up_mbps").and_then(JsonValue::as_u64) {
+        insert_string(&mut map, "up", format!("{} Mbps", up));
+    }
+    if let Some(down) = outbound.get("down_mbps").and_then(JsonValue::as_u64) {
+        insert_string(&mut map, "down", format!("{} Mbps", down));
+    }
+    if let Some(obfs) = outbound.get("obfs") {
+        if let Some(kind) = str_field(obfs, "type") {
+            insert_string(&mut map, "obfs", kind);
+        }
+        if let Some(password) = str_field(obfs, "password") {
+            insert_string(&mut map, "obfs-password", password);
+        }
+    }
+    apply_tls(&mut map, outbound, "sni", false);
+    Some(map)
+}
+
+fn convert_tuic(outbound: &JsonValue) -> Option<Mapping> {
+    let mut map = base_mapping(outbound, "tuic")?;
+    insert_string(&mut map, "uuid", str_field(outbound, "uuid")?);
+    insert_string(&mut map, "password", str_field(outbound, "password")?);
+    if let Some(cc) = str_field(outbound, "congestion_control") {
+        insert_string(&mut map, "congestion-controller", cc);
+    }
+    if let Some(mode) = str_field(outbound, "udp_relay_mode") {
+        insert_string(&mut map, "udp-relay-mode", mode);
+    }
+    if outbound
+        .get("zero_rtt_handshake")
+        .and_then(JsonValue::as_bool)
+        == Some(true)
+    {
+        map.insert(Value::from("reduce-rtt"), Value::Bool(true));
+    }
+    apply_tls(&mut map, outbound, "sni", false);
+    Some(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get<'a>(proxy: &'a Value, key: &str) -> Option<&'a Value> {
+        proxy.as_mapping().and_then(|map| map.get(Value::from(key)))
+    }
+
+    #[test]
+    fn converts_supported_outbounds_and_skips_the_rest() {
+        let raw = r#"{
+  "log": { "level": "info" },
+  "outbounds": [
+    { "type": "selector", "tag": "proxy", "outbounds": ["hk-trojan"] },
+    {
+      "type": "trojan", "tag": "hk-trojan", "server": "hk.example.com", "server_port": 443,
+      "password": "pw",
+      "tls": { "enabled": true, "server_name": "sni.example.com", "insecure": true },
+      "transport": { "type": "ws", "path": "/ws", "headers": { "Host": "cdn.example.com" } }
+    },
+    {
+      "type": "hysteria2", "tag": "jp-hy2", "server": "jp.example.com", "server_port": 8443,
+      "password": "pw2", "up_mbps": 50, "down_mbps": 200,
+      "obfs": { "type": "salamander", "password": "ob" },
+      "tls": { "enabled": true, "server_name": "jp.example.com", "alpn": ["h3"] }
+    },
+    {
+      "type": "tuic", "tag": "sg-tuic", "server": "sg.example.com", "server_port": 443,
+      "uuid": "2dd61d93-75d8-4da4-ac0e-6aece7eac365", "password": "pw3",
+      "congestion_control": "bbr"
+    },
+    { "type": "direct", "tag": "direct" },
+    { "type": "wireguard", "tag": "wg" }
+  ]
+}"#;
+
+        let config = parse_singbox(raw).unwrap().unwrap();
+        assert_eq!(config.proxy_names(), vec!["hk-trojan", "jp-hy2", "sg-tuic"]);
+
+        let trojan = &config.proxies[0];
+        assert_eq!(
+            get(trojan, "sni").and_then(Value::as_str),
+            Some("sni.example.com")
+        );
+        assert_eq!(
+            get(trojan, "skip-cert-verify").and_then(Value::as_bool),
+            Some(true)
+        );
+        assert_eq!(get(trojan, "network").and_then(Value::as_str), Some("ws"));
+
+        let hy2 = &config.proxies[1];
+        assert_eq!(get(hy2, "type").and_then(Value::as_str), Some("hysteria2"));
+        assert_eq!(get(hy2, "down").and_then(Value::as_str), Some("200 Mbps"));
+        assert_eq!(
+            get(hy2, "obfs-password").and_then(Value::as_str),
+            Some("ob")
+        );
+
+        let tuic = &config.proxies[2];
+        assert_eq!(
+            get(tuic, "congestion-controller").and_then(Value::as_str),
+            Some("bbr")
+        );
+    }
+
+    #[test]
+    fn non_singbox_json_is_ignored() {
+        assert!(parse_singbox(r#"{"version": 1, "servers": []}"#)
+            .unwrap()
+            .is_none());
+        assert!(parse_singbox("proxies: []").unwrap().is_none());
+    }
+}
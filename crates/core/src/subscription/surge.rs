@@ -0,0 +1,357 @@
+//! Conversion of Surge `[Proxy]` sections and Quantumult X server lines into Clash proxies.
+
+use serde_yaml::{Mapping, Sequence, Value};
+
+use super::parser::{insert_string, insert_u64};
+use crate::model::ClashConfig;
+
+/// Parse a Surge profile (`[Proxy]` section) or a Quantumult X server list.
+///
+/// Returns `Ok(None)` when neither format is recognised. Entries of unsupported types
+/// (e.g. `direct`, `reject`) are skipped.
+pub(super) fn parse_surge_or_quanx(raw: &str) -> anyhow::Result<Option<ClashConfig>> {
+    let proxies = if has_surge_proxy_section(raw) {
+        surge_proxy_lines(raw)
+            .filter_map(parse_surge_line)
+            .map(Value::Mapping)
+            .collect::<Vec<_>>()
+    } else {
+        raw.lines()
+            .filter_map(parse_quanx_line)
+            .map(Value::Mapping)
+            .collect::<Vec<_>>()
+    };
+
+    if proxies.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ClashConfig {
+        proxies,
+        ..Default::default()
+    }))
+}
+
+fn has_surge_proxy_section(raw: &str) -> bool {
+    raw.lines()
+        .any(|line| line.trim().eq_ignore_ascii_case("[proxy]"))
+}
+
+fn surge_proxy_lines(raw: &str) -> impl Iterator<Item = &str> {
+    let mut in_section = false;
+    raw.lines().filter(move |line| {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            in_section = line.eq_ignore_ascii_case("[proxy]");
+            return false;
+        }
+        in_section && !line.is_empty() && !line.starts_with('#') && !line.starts_with(';')
+    })
+}
+
+/// Split `key=value` options (after the positional fields) into pairs.
+fn options<'a>(parts: impl Iterator<Item = &'a str>) -> Vec<(String, String)> {
+    parts
+        .filter_map(|part| {
+            let (key, value) = part.split_once('=')?;
+            Some((
+                key.trim().to_ascii_lowercase(),
+                value.trim().trim_matches('"').to_string(),
+            ))
+        })
+        .collect()
+}
+
+fn opt<'a>(opts: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    opts.iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+        .filter(|v| !v.is_empty())
+}
+
+fn is_true(value: Option<&str>) -> bool {
+    matches!(value, Some(v) if v.eq_ignore_ascii_case("true") || v == "1")
+}
+
+fn base(name: &str, kind: &str, server: &str, port: u64) -> Mapping {
+    let mut map = Mapping::new();
+    insert_string(&mut map, "name", name);
+    insert_string(&mut map, "type", kind);
+    insert_string(&mut map, "server", server);
+    insert_u64(&mut map, "port", port);
+    map
+}
+
+fn insert_ws(map: &mut Mapping, path: Option<&str>, host: Option<&str>) {
+    insert_string(map, "network", "ws");
+    let mut ws_opts = Mapping::new();
+    if let Some(path) = path {
+        insert_string(&mut ws_opts, "path", path);
+    }
+    if let Some(host) = host {
+        let mut headers = Mapping::new();
+        insert_string(&mut headers, "Host", host);
+        ws_opts.insert(Value::from("headers"), Value::Mapping(headers));
+    }
+    if !ws_opts.is_empty() {
+        map.insert(Value::from("ws-opts"), Value::Mapping(ws_opts));
+    }
+}
+
+fn insert_obfs(map: &mut Mapping, mode: Option<&str>, host: Option<&str>) {
+    let Some(mode) = mode else {
+        return;
+    };
+    insert_string(map, "plugin", "obfs");
+    let mut plugin_opts = Mapping::new();
+    insert_string(&mut plugin_opts, "mode", mode);
+    if let Some(host) = host {
+        insert_string(&mut plugin_opts, "host", host);
+    }
+    map.insert(Value::from("plugin-opts"), Value::Mapping(plugin_opts));
+}
+
+/// `Name = type, server, port, key=value, ...`
+fn parse_surge_line(line: &str) -> Option<Mapping> {
+    let (name, rest) = line.split_once('=')?;
+    let name = name.trim();
+    let mut parts = rest.split(',').map(str::trim);
+    let kind = parts.next()?.to_ascii_lowercase();
+    let server = parts.next()?;
+    let port: u64 = parts.next()?.parse().ok()?;
+    let positional: Vec<&str> = parts.clone().filter(|part| !part.contains('=')).collect();
+    let opts = options(parts);
+
+    let mut map = match kind.as_str() {
+        "ss" | "shadowsocks" => {
+            let mut map = base(name, "ss", server, port);
+            insert_string(&mut map, "cipher", opt(&opts, "encrypt-method")?);
+            insert_string(&mut map, "password", opt(&opts, "password")?);
+            insert_obfs(&mut map, opt(&opts, "obfs"), opt(&opts, "obfs-host"));
+            map
+        }
+        "vmess" => {
+            let mut map = base(name, "vmess", server, port);
+            insert_string(&mut map, "uuid", opt(&opts, "username")?);
+            insert_u64(&mut map, "alterId", 0);
+            insert_string(&mut map, "cipher", "auto");
+            if is_true(opt(&opts, "ws")) {
+                let host = opt(&opts, "ws-headers").and_then(|headers| {
+                    headers.split('|').find_map(|header| {
+                        let (key, value) = header.split_once(':')?;
+                        key.trim()
+                            .eq_ignore_ascii_case("host")
+                            .then(|| value.trim())
+                    })
+                });
+                insert_ws(&mut map, opt(&opts, "ws-path"), host);
+            }
+            if is_true(opt(&opts, "tls")) {
+                map.insert(Value::from("tls"), Value::Bool(true));
+            }
+            if let Some(sni) = opt(&opts, "sni") {
+                insert_string(&mut map, "servername", sni);
+            }
+            map
+        }
+        "trojan" => {
+            let mut map = base(name, "trojan", server, port);
+            insert_string(&mut map, "password", opt(&opts, "password")?);
+            if let Some(sni) = opt(&opts, "sni") {
+                insert_string(&mut map, "sni", sni);
+            }
+            map
+        }
+        "hysteria2" => {
+            let mut map = base(name, "hysteria2", server, port);
+            insert_string(&mut map, "password", opt(&opts, "password")?);
+            if let Some(sni) = opt(&opts, "sni") {
+                insert_string(&mut map, "sni", sni);
+            }
+            map
+        }
+        "http" | "https" | "socks5" | "socks5-tls" => {
+            let clash_type = if kind.starts_with("socks5") {
+                "socks5"
+            } else {
+                "http"
+            };
+            let mut map = base(name, clash_type, server, port);
+            let username = positional.first().copied().or(opt(&opts, "username"));
+            let password = positional.get(1).copied().or(opt(&opts, "password"));
+            if let Some(username) = username {
+                insert_string(&mut map, "username", username);
+            }
+            if let Some(password) = password {
+                insert_string(&mut map, "password", password);
+            }
+            if kind == "https" || kind == "socks5-tls" {
+                map.insert(Value::from("tls"), Value::Bool(true));
+            }
+            map
+        }
+        _ => return None,
+    };
+
+    if is_true(opt(&opts, "skip-cert-verify")) {
+        map.insert(Value::from("skip-cert-verify"), Value::Bool(true));
+    }
+    if is_true(opt(&opts, "udp-relay")) {
+        map.insert(Value::from("udp"), Value::Bool(true));
+    }
+    Some(map)
+}
+
+/// `shadowsocks=server:port, method=..., password=..., tag=Name` (and trojan/vmess/http).
+fn parse_quanx_line(line: &str) -> Option<Mapping> {
+    let line = line.trim();
+    let (kind, rest) = line.split_once('=')?;
+    let kind = kind.trim().to_ascii_lowercase();
+    if !matches!(kind.as_str(), "shadowsocks" | "trojan" | "vmess" | "http") {
+        return None;
+    }
+    let mut parts = rest.split(',').map(str::trim);
+    let (server, port) = parts.next()?.rsplit_once(':')?;
+    let port: u64 = port.parse().ok()?;
+    let opts = options(parts);
+    let default_name = format!("{}:{}", server, port);
+    let name = opt(&opts, "tag").unwrap_or(&default_name);
+    let over_tls = is_true(opt(&opts, "over-tls"));
+
+    let mut map = match kind.as_str() {
+        "shadowsocks" => {
+            let mut map = base(name, "ss", server, port);
+            insert_string(&mut map, "cipher", opt(&opts, "method")?);
+            insert_string(&mut map, "password", opt(&opts, "password")?);
+            match opt(&opts, "obfs") {
+                Some("ws") | Some("wss") => {
+                    insert_string(&mut map, "plugin", "v2ray-plugin");
+                    let mut plugin_opts = Mapping::new();
+                    insert_string(&mut plugin_opts, "mode", "websocket");
+                    if let Some(host) = opt(&opts, "obfs-host") {
+                        insert_string(&mut plugin_opts, "host", host);
+                    }
+                    if let Some(path) = opt(&opts, "obfs-uri") {
+                        insert_string(&mut plugin_opts, "path", path);
+                    }
+                    if opt(&opts, "obfs") == Some("wss") {
+                        plugin_opts.insert(Value::from("tls"), Value::Bool(true));
+                    }
+                    map.insert(Value::from("plugin-opts"), Value::Mapping(plugin_opts));
+                }
+                mode => insert_obfs(&mut map, mode, opt(&opts, "obfs-host")),
+            }
+            map
+        }
+        "trojan" => {
+            let mut map = base(name, "trojan", server, port);
+            insert_string(&mut map, "password", opt(&opts, "password")?);
+            if let Some(sni) = opt(&opts, "tls-host") {
+                insert_string(&mut map, "sni", sni);
+            }
+            map
+        }
+        "vmess" => {
+            let mut map = base(name, "vmess", server, port);
+            insert_string(&mut map, "uuid", opt(&opts, "password")?);
+            insert_u64(&mut map, "alterId", 0);
+            insert_string(&mut map, "cipher", opt(&opts, "method").unwrap_or("auto"));
+            match opt(&opts, "obfs") {
+                Some("ws") => insert_ws(&mut map, opt(&opts, "obfs-uri"), opt(&opts, "obfs-host")),
+                Some("wss") => {
+                    insert_ws(&mut map, opt(&opts, "obfs-uri"), opt(&opts, "obfs-host"));
+                    map.insert(Value::from("tls"), Value::Bool(true));
+                }
+                _ => {}
+            }
+            if over_tls {
+                map.insert(Value::from("tls"), Value::Bool(true));
+            }
+            if let Some(sni) = opt(&opts, "tls-host") {
+                insert_string(&mut map, "servername", sni);
+            }
+            map
+        }
+        _ => {
+            let mut map = base(name, "http", server, port);
+            if let Some(username) = opt(&opts, "username") {
+                insert_string(&mut map, "username", username);
+            }
+            if let Some(password) = opt(&opts, "password") {
+                insert_string(&mut map, "password", password);
+            }
+            if over_tls {
+                map.insert(Value::from("tls"), Value::Bool(true));
+            }
+            map
+        }
+    };
+
+    if opt(&opts, "tls-verification") == Some("false") {
+        map.insert(Value::from("skip-cert-verify"), Value::Bool(true));
+    }
+    if is_true(opt(&opts, "udp-relay")) {
+        map.insert(Value::from("udp"), Value::Bool(true));
+    }
+    if let Some(alpn) = opt(&opts, "tls-alpn") {
+        let sequence: Sequence = alpn
+            .split(';')
+            .map(|item| Value::from(item.trim()))
+            .collect();
+        map.insert(Value::from("alpn"), Value::Sequence(sequence));
+    }
+    Some(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get<'a>(proxy: &'a Value, key: &str) -> Option<&'a str> {
+        proxy
+            .as_mapping()
+            .and_then(|map| map.get(Value::from(key)))
+            .and_then(Value::as_str)
+    }
+
+    #[test]
+    fn parses_surge_proxy_section() {
+        let raw = r#"[General]
+loglevel = notify
+
+[Proxy]
+DIRECT = direct
+HK SS = ss, hk.example.com, 8388, encrypt-method=aes-128-gcm, password=pw, obfs=http, obfs-host=bing.com, udp-relay=true
+JP VMess = vmess, jp.example.com, 443, username=2dd61d93-75d8-4da4-ac0e-6aece7eac365, ws=true, ws-path=/ws, ws-headers=Host:cdn.example.com, tls=true
+US Trojan = trojan, us.example.com, 443, password=pw2, sni=us.example.com, skip-cert-verify=true
+
+[Proxy Group]
+Proxy = select, HK SS, JP VMess
+"#;
+        let config = parse_surge_or_quanx(raw).unwrap().unwrap();
+        assert_eq!(config.proxy_names(), vec!["HK SS", "JP VMess", "US Trojan"]);
+        assert_eq!(get(&config.proxies[0], "cipher"), Some("aes-128-gcm"));
+        assert_eq!(get(&config.proxies[0], "plugin"), Some("obfs"));
+        assert_eq!(get(&config.proxies[1], "network"), Some("ws"));
+        assert_eq!(get(&config.proxies[2], "sni"), Some("us.example.com"));
+    }
+
+    #[test]
+    fn parses_quanx_server_lines() {
+        let raw = "shadowsocks=hk.example.com:8388, method=chacha20-ietf-poly1305, password=pw, tag=HK\n\
+                   trojan=us.example.com:443, password=pw2, over-tls=true, tls-host=us.example.com, tag=US\n\
+                   vmess=jp.example.com:443, method=aes-128-gcm, password=2dd61d93-75d8-4da4-ac0e-6aece7eac365, obfs=wss, obfs-uri=/ws, tag=JP\n";
+        let config = parse_surge_or_quanx(raw).unwrap().unwrap();
+        assert_eq!(config.proxy_names(), vec!["HK", "US", "JP"]);
+        assert_eq!(get(&config.proxies[0], "type"), Some("ss"));
+        assert_eq!(get(&config.proxies[1], "sni"), Some("us.example.com"));
+        assert_eq!(
+            get(&config.proxies[2], "uuid"),
+            Some("2dd61d93-75d8-4da4-ac0e-6aece7eac365")
+        );
+    }
+
+    #[test]
+    fn ignores_unrelated_text() {
+        assert!(parse_surge_or_quanx("hello world").unwrap().is_none());
+    }
+}
@@ -0,0 +1,466 @@
+//! Traffic quota and expiry advertised through the `subscription-userinfo` header.
+
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// Warn when the subscription expires within this many days.
+pub const EXPIRY_WARNING_DAYS: i64 = 7;
+/// Warn when at least this share of the traffic quota has been used.
+pub const QUOTA_WARNING_RATIO: f64 = 0.9;
+/// Oldest samples are dropped once a history file holds this many.
+const MAX_USAGE_SAMPLES: usize = 2000;
+/// Consumption rate used for projections is measured over at most this many days.
+const PROJECTION_WINDOW_DAYS: i64 = 7;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct SubscriptionUsage {
+    /// Bytes uploaded in the current period.
+    #[serde(default)]
+    pub upload: u64,
+    /// Bytes downloaded in the current period.
+    #[serde(default)]
+    pub download: u64,
+    /// Traffic quota in bytes; 0 means unknown/unlimited.
+    #[serde(default)]
+    pub total: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire: Option<DateTime<Utc>>,
+}
+
+impl SubscriptionUsage {
+    /// Parse `upload=1; download=2; total=3; expire=1700000000`. Unknown keys are ignored;
+    /// returns `None` when no known field is present.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let mut usage = SubscriptionUsage::default();
+        let mut seen = false;
+        for part in raw.split(';') {
+            let Some((key, value)) = part.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let Some(number) = value
+                .parse::<u64>()
+                .ok()
+                .or_else(|| value.parse::<f64>().ok().map(|v| v.max(0.0) as u64))
+            else {
+                continue;
+            };
+            match key.trim().to_ascii_lowercase().as_str() {
+                "upload" => usage.upload = number,
+                "download" => usage.download = number,
+                "total" => usage.total = number,
+                "expire" => {
+                    usage.expire = (number > 0)
+                        .then(|| Utc.timestamp_opt(number as i64, 0).single())
+                        .flatten()
+                }
+                _ => continue,
+            }
+            seen = true;
+        }
+        seen.then_some(usage)
+    }
+
+    /// The usage of several subscriptions as one: traffic and quotas add up, and the earliest
+    /// expiry wins. `None` when `usages` is empty.
+    pub fn sum(usages: impl IntoIterator<Item = Self>) -> Option<Self> {
+        usages.into_iter().reduce(|acc, usage| Self {
+            upload: acc.upload.saturating_add(usage.upload),
+            download: acc.download.saturating_add(usage.download),
+            total: acc.total.saturating_add(usage.total),
+            expire: match (acc.expire, usage.expire) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        })
+    }
+
+    /// The `subscription-userinfo` header value, the inverse of [`Self::parse`].
+    pub fn header_value(&self) -> String {
+        let mut value = format!(
+            "upload={}; download={}; total={}",
+            self.upload, self.download, self.total
+        );
+        if let Some(expire) = self.expire {
+            value.push_str(&format!("; expire={}", expire.timestamp()));
+        }
+        value
+    }
+
+    pub fn used(&self) -> u64 {
+        self.upload.saturating_add(self.download)
+    }
+
+    pub fn remaining(&self) -> Option<u64> {
+        (self.total > 0).then(|| self.total.saturating_sub(self.used()))
+    }
+
+    /// Human-readable problems worth surfacing during a merge (imminent expiry, quota nearly
+    /// used up).
+    pub fn warnings(&self, now: DateTime<Utc>) -> Vec<String> {
+        let mut out = Vec::new();
+        if let Some(expire) = self.expire {
+            if expire <= now {
+                out.push(format!("expired on {}", expire.format("%Y-%m-%d")));
+            } else if expire - now < chrono::Duration::days(EXPIRY_WARNING_DAYS) {
+                out.push(format!(
+                    "expires in {} day(s) on {}",
+                    (expire - now).num_days(),
+                    expire.format("%Y-%m-%d")
+                ));
+            }
+        }
+        if self.total > 0 && self.used() as f64 >= self.total as f64 * QUOTA_WARNING_RATIO {
+            out.push(format!(
+                "{} of {} traffic used ({} left)",
+                format_bytes(self.used()),
+                format_bytes(self.total),
+                format_bytes(self.remaining().unwrap_or(0))
+            ));
+        }
+        out
+    }
+}
+
+/// One recorded `subscription-userinfo` reading.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UsageSample {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub usage: SubscriptionUsage,
+}
+
+/// Append a sample to the history at `path`. A reading identical to the previous one from the
+/// same day is not stored again, and the file keeps at most the newest
+/// [`MAX_USAGE_SAMPLES`] entries.
+pub async fn record_usage_sample(path: &Path, sample: UsageSample) -> anyhow::Result<()> {
+    let mut samples = load_usage_history(path).await?;
+    if samples.last().is_some_and(|last| {
+        last.usage == sample.usage && last.at.date_naive() == sample.at.date_naive()
+    }) {
+        return Ok(());
+    }
+    samples.push(sample);
+    let skip = samples.len().saturating_sub(MAX_USAGE_SAMPLES);
+    let mut out = String::new();
+    for sample in &samples[skip..] {
+        out.push_str(&serde_json::to_string(sample)?);
+        out.push('\n');
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(path, out).await?;
+    Ok(())
+}
+
+/// Samples recorded by [`record_usage_sample`], oldest first; unreadable lines are skipped.
+pub async fn load_usage_history(path: &Path) -> anyhow::Result<Vec<UsageSample>> {
+    let raw = match fs::read_to_string(path).await {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut samples: Vec<UsageSample> = raw
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    samples.sort_by_key(|sample| sample.at);
+    Ok(samples)
+}
+
+/// Traffic consumed on one (UTC) day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DailyUsage {
+    pub date: NaiveDate,
+    /// Bytes used since the previous day's last reading (or since the quota was reset).
+    pub delta: u64,
+    /// Period usage at the day's last reading.
+    pub used: u64,
+}
+
+/// Per-day consumption derived from the history. The first day only counts traffic between
+/// its own readings, since nothing earlier is known.
+pub fn daily_usage(samples: &[UsageSample]) -> Vec<DailyUsage> {
+    let mut days: Vec<DailyUsage> = Vec::new();
+    let mut previous: Option<u64> = None;
+    for sample in samples {
+        let used = sample.usage.used();
+        let delta = match previous {
+            Some(prev) if used >= prev => used - prev,
+            // Counter went down: the provider reset the period.
+            Some(_) => used,
+            None => 0,
+        };
+        previous = Some(used);
+        match days.last_mut() {
+            Some(day) if day.date == sample.at.date_naive() => {
+                day.delta += delta;
+                day.used = used;
+            }
+            _ => days.push(DailyUsage {
+                date: sample.at.date_naive(),
+                delta,
+                used,
+            }),
+        }
+    }
+    days
+}
+
+/// Where the quota is heading at the recent consumption rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UsageProjection {
+    /// Average bytes per day over the measured window.
+    pub per_day: f64,
+    /// Usage expected at `reset`.
+    pub projected: u64,
+    pub total: u64,
+    pub reset: DateTime<Utc>,
+    /// When the quota runs out at this rate, if that happens before `reset`.
+    pub exhausted_at: Option<DateTime<Utc>>,
+}
+
+impl UsageProjection {
+    pub fn exceeds_quota(&self) -> bool {
+        self.exhausted_at.is_some()
+    }
+
+    pub fn warning(&self) -> Option<String> {
+        let exhausted = self.exhausted_at?;
+        Some(format!(
+            "at {}/day the {} quota runs out around {}, before the reset on {}",
+            format_bytes(self.per_day as u64),
+            format_bytes(self.total),
+            exhausted.format("%Y-%m-%d"),
+            self.reset.format("%Y-%m-%d")
+        ))
+    }
+}
+
+/// Project usage until `reset` (or, without one, the provider's `expire`) from the readings of
+/// the last [`PROJECTION_WINDOW_DAYS`] days since the most recent quota reset. Needs a known
+/// quota and at least an hour between the first and last reading.
+pub fn project_usage(
+    samples: &[UsageSample],
+    reset: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Option<UsageProjection> {
+    let latest = samples.last()?;
+    let total = latest.usage.total;
+    let reset = reset.or(latest.usage.expire)?;
+    if total == 0 || reset <= now {
+        return None;
+    }
+
+    // Only readings from the current period, i.e. after the last drop of the counter.
+    let period_start = samples
+        .windows(2)
+        .rposition(|pair| pair[1].usage.used() < pair[0].usage.used())
+        .map_or(0, |idx| idx + 1);
+    let window_start = latest.at - Duration::days(PROJECTION_WINDOW_DAYS);
+    let first = samples[period_start..]
+        .iter()
+        .find(|sample| sample.at >= window_start)?;
+    let elapsed = latest.at - first.at;
+    if elapsed < Duration::hours(1) {
+        return None;
+    }
+
+    let consumed = latest.usage.used().saturating_sub(first.usage.used()) as f64;
+    let per_second = consumed / elapsed.num_seconds() as f64;
+    let used = latest.usage.used();
+    let projected = used as f64 + per_second * (reset - latest.at).num_seconds() as f64;
+    let exhausted_at = (projected > total as f64 && per_second > 0.0).then(|| {
+        let seconds = total.saturating_sub(used) as f64 / per_second;
+        latest.at + Duration::seconds(seconds as i64)
+    });
+    Some(UsageProjection {
+        per_day: per_second * 86_400.0,
+        projected: projected as u64,
+        total,
+        reset,
+        exhausted_at,
+    })
+}
+
+/// Next midnight (UTC) on day `day` of a month, for providers that reset the quota monthly;
+/// days past the end of a short month fall on its last day.
+pub fn next_monthly_reset(now: DateTime<Utc>, day: u32) -> DateTime<Utc> {
+    let day = day.clamp(1, 31);
+    let reset_in = |year: i32, month: u32| {
+        let last_day = (28..=31)
+            .rev()
+            .find(|d| NaiveDate::from_ymd_opt(year, month, *d).is_some())
+            .unwrap_or(28);
+        let date = NaiveDate::from_ymd_opt(year, month, day.min(last_day)).expect("valid date");
+        Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight"))
+    };
+    let this_month = reset_in(now.year(), now.month());
+    if this_month > now {
+        return this_month;
+    }
+    if now.month() == 12 {
+        reset_in(now.year() + 1, 1)
+    } else {
+        reset_in(now.year(), now.month() + 1)
+    }
+}
+
+/// Format a byte count with binary units, e.g. `1.50 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_userinfo_header() {
+        let usage =
+            SubscriptionUsage::parse("upload=1024; download=2048; total=10240; expire=1700000000")
+                .unwrap();
+        assert_eq!(usage.used(), 3072);
+        assert_eq!(usage.remaining(), Some(7168));
+        assert_eq!(usage.expire.unwrap().timestamp(), 1_700_000_000);
+
+        let no_expiry = SubscriptionUsage::parse("upload=0; download=0; total=0; expire=").unwrap();
+        assert_eq!(no_expiry.expire, None);
+        assert_eq!(no_expiry.remaining(), None);
+
+        assert!(SubscriptionUsage::parse("garbage").is_none());
+    }
+
+    #[test]
+    fn warns_on_expiry_and_quota() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let healthy = SubscriptionUsage {
+            upload: 0,
+            download: 10,
+            total: 100,
+            expire: Some(now + chrono::Duration::days(30)),
+        };
+        assert!(healthy.warnings(now).is_empty());
+
+        let exhausted = SubscriptionUsage {
+            upload: 50,
+            download: 45,
+            total: 100,
+            expire: Some(now + chrono::Duration::days(3)),
+        };
+        let warnings = exhausted.warnings(now);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("expires in 3 day(s)"));
+        assert!(warnings[1].contains("traffic used"));
+    }
+
+    fn sample(at: DateTime<Utc>, used: u64) -> UsageSample {
+        UsageSample {
+            at,
+            usage: SubscriptionUsage {
+                upload: 0,
+                download: used,
+                total: 100 * GIB,
+                expire: None,
+            },
+        }
+    }
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn daily_deltas_survive_quota_reset() {
+        let day = |d: u32, h: u32| Utc.with_ymd_and_hms(2026, 10, d, h, 0, 0).unwrap();
+        let samples = [
+            sample(day(1, 8), 10 * GIB),
+            sample(day(1, 20), 12 * GIB),
+            sample(day(2, 20), 15 * GIB),
+            // Provider reset the counter overnight.
+            sample(day(3, 9), GIB),
+        ];
+        let days = daily_usage(&samples);
+        assert_eq!(days.len(), 3);
+        assert_eq!((days[0].delta, days[0].used), (2 * GIB, 12 * GIB));
+        assert_eq!(days[1].delta, 3 * GIB);
+        assert_eq!(days[2].delta, GIB);
+    }
+
+    #[test]
+    fn projects_quota_exhaustion_before_reset() {
+        let start = Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let samples: Vec<UsageSample> = (0..=5)
+            .map(|d| sample(start + Duration::days(d), 50 * GIB + d as u64 * 5 * GIB))
+            .collect();
+        let now = start + Duration::days(5);
+
+        // 75 GiB used, 5 GiB/day, 20 days left: 175 GiB projected.
+        let projection = project_usage(&samples, Some(now + Duration::days(20)), now).unwrap();
+        assert_eq!((projection.per_day / GIB as f64).round(), 5.0);
+        assert!(projection.exceeds_quota());
+        let exhausted = projection.exhausted_at.unwrap();
+        assert!((exhausted - (now + Duration::days(5))).num_seconds().abs() <= 1);
+        assert!(projection.warning().unwrap().contains("before the reset"));
+
+        let relaxed = project_usage(&samples, Some(now + Duration::days(4)), now).unwrap();
+        assert!(!relaxed.exceeds_quota());
+        assert!(project_usage(&samples, None, now).is_none());
+        assert!(project_usage(&samples[..1], Some(now + Duration::days(4)), now).is_none());
+    }
+
+    #[test]
+    fn monthly_reset_rolls_over_and_clamps() {
+        let now = Utc.with_ymd_and_hms(2026, 1, 31, 12, 0, 0).unwrap();
+        assert_eq!(
+            next_monthly_reset(now, 31),
+            Utc.with_ymd_and_hms(2026, 2, 28, 0, 0, 0).unwrap()
+        );
+        let december = Utc.with_ymd_and_hms(2026, 12, 20, 0, 0, 0).unwrap();
+        assert_eq!(
+            next_monthly_reset(december, 1),
+            Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_monthly_reset(december, 25),
+            Utc.with_ymd_and_hms(2026, 12, 25, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn history_skips_repeated_readings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage/sub.jsonl");
+        let at = Utc.with_ymd_and_hms(2026, 10, 1, 8, 0, 0).unwrap();
+        record_usage_sample(&path, sample(at, GIB)).await.unwrap();
+        record_usage_sample(&path, sample(at + Duration::hours(1), GIB))
+            .await
+            .unwrap();
+        record_usage_sample(&path, sample(at + Duration::days(1), GIB))
+            .await
+            .unwrap();
+        let history = load_usage_history(&path).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].at, at + Duration::days(1));
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.50 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.00 GiB");
+    }
+}
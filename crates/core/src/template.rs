@@ -1,11 +1,18 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
 use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
 use tokio::fs;
 
+use crate::interpolate::expand_env_vars;
 use crate::model::ClashConfig;
 
+/// Key naming a parent template that this one is deep-merged over.
+const EXTENDS_KEY: &str = "extends";
+const MAX_EXTENDS_DEPTH: usize = 16;
+
 #[derive(Debug, Clone)]
 pub struct Template {
     raw: Mapping,
@@ -13,18 +20,71 @@ pub struct Template {
 }
 
 impl Template {
+    /// Load a template file. An `extends: parent.yaml` key (relative to the file) loads the
+    /// parent first and deep-merges this template over it: mappings merge key by key, lists
+    /// and scalars replace. Parents may extend further. `${VAR}` references in each file are
+    /// expanded from the environment before parsing (see [`crate::interpolate`]).
     pub async fn load(path: &Path) -> anyhow::Result<Self> {
-        let content = fs::read_to_string(path).await?;
-        Self::from_yaml_str(&content)
+        let mut chain: Vec<Mapping> = Vec::new();
+        let mut seen: Vec<PathBuf> = Vec::new();
+        let mut next = Some(path.to_path_buf());
+        while let Some(current) = next.take() {
+            let canonical = fs::canonicalize(&current)
+                .await
+                .unwrap_or_else(|_| current.clone());
+            if seen.contains(&canonical) {
+                return Err(anyhow!(
+                    "template {} extends itself (via {})",
+                    current.display(),
+                    path.display()
+                ));
+            }
+            if seen.len() == MAX_EXTENDS_DEPTH {
+                return Err(anyhow!(
+                    "template {} extends more than {} levels deep",
+                    path.display(),
+                    MAX_EXTENDS_DEPTH
+                ));
+            }
+
+            let content = match chain.last() {
+                None => fs::read_to_string(&current).await?,
+                Some(_) => fs::read_to_string(&current).await.with_context(|| {
+                    format!("failed to read parent template {}", current.display())
+                })?,
+            };
+            let content = expand_env_vars(&content)
+                .with_context(|| format!("failed to expand {}", current.display()))?;
+            let mut mapping = parse_mapping(&content)
+                .with_context(|| format!("failed to parse {}", current.display()))?;
+            if let Some(parent) = mapping.shift_remove(EXTENDS_KEY) {
+                let parent = parent.as_str().ok_or_else(|| {
+                    anyhow!("`extends` in {} must be a file path", current.display())
+                })?;
+                let dir = current.parent().unwrap_or_else(|| Path::new("."));
+                next = Some(dir.join(parent));
+            }
+            seen.push(canonical);
+            chain.push(mapping);
+        }
+
+        let mut merged = Value::Mapping(chain.pop().unwrap_or_default());
+        while let Some(child) = chain.pop() {
+            deep_merge(&mut merged, &Value::Mapping(child));
+        }
+        Self::from_mapping(merged.as_mapping().cloned().unwrap_or_default())
     }
 
+    /// Parse a template from YAML. `extends:` needs a file to resolve against, so it is
+    /// dropped here; use [`Template::load`] for templates that extend another.
     pub fn from_yaml_str(yaml: &str) -> anyhow::Result<Self> {
-        let value: Value = serde_yaml::from_str(yaml)?;
-        let mapping = value
-            .as_mapping()
-            .cloned()
-            .ok_or_else(|| anyhow!("template YAML must be a mapping"))?;
-        let config: ClashConfig = serde_yaml::from_value(value)?;
+        let mut mapping = parse_mapping(yaml)?;
+        mapping.shift_remove(EXTENDS_KEY);
+        Self::from_mapping(mapping)
+    }
+
+    fn from_mapping(mapping: Mapping) -> anyhow::Result<Self> {
+        let config: ClashConfig = serde_yaml::from_value(Value::Mapping(mapping.clone()))?;
         Ok(Self {
             raw: mapping,
             config,
@@ -43,14 +103,206 @@ impl Template {
         &self.raw
     }
 
+    /// Deprecated, renamed or unsupported keys; see [`crate::lint::lint_mapping`].
+    pub fn lint(&self) -> Vec<crate::lint::LintFinding> {
+        crate::lint::lint_mapping(&self.raw)
+    }
+
+    /// Deep-merge `merge` onto the template; its `prepend-`/`append-` directives extend the
+    /// template's lists (see [`crate::merge::apply_merge_directives`]).
     pub fn apply_merge(&mut self, merge: Mapping) -> anyhow::Result<()> {
         self.raw = merge_mappings(merge, self.raw.clone());
         let updated_value = Value::Mapping(self.raw.clone());
-        self.config = serde_yaml::from_value(updated_value)?;
+        self.config = crate::merge::apply_merge_directives(serde_yaml::from_value(updated_value)?)?;
         Ok(())
     }
 }
 
+/// Outcome of [`sync_bundled_template`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundledTemplateSync {
+    /// No template existed; the bundled one was installed.
+    Installed,
+    /// The installed template already matches the bundled one.
+    UpToDate,
+    /// The installed template was an unmodified older bundle and was replaced.
+    Updated,
+    /// The user edited the template; it was left alone. `diff` goes from the installed
+    /// copy to the new bundle and is only populated the first time a given bundle is
+    /// offered, so repeated runs stay quiet.
+    Modified { diff: Option<String> },
+    /// The user's edits were overwritten (forced); the old copy was saved to `backup`.
+    Overwritten { backup: PathBuf },
+}
+
+/// Sidecar recording which bundled template version was installed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BundledTemplateStamp {
+    /// SHA-256 of the bundled template last written to disk.
+    installed: String,
+    /// SHA-256 of a newer bundle the user was already shown but did not apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offered: Option<String>,
+}
+
+/// Install or update the bundled default template at `path` without clobbering user
+/// edits. A `.<file>.stamp` sidecar remembers the hash of the bundle that was written, so
+/// an untouched copy can be replaced when a newer CLI ships a different bundle, while an
+/// edited copy is only replaced when `force` is set (after backing it up to `<file>.bak`).
+pub async fn sync_bundled_template(
+    path: &Path,
+    bundled: &str,
+    force: bool,
+) -> anyhow::Result<BundledTemplateSync> {
+    let stamp_path = bundled_stamp_path(path);
+    let bundled_hash = content_hash(bundled);
+
+    let installed = match fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(path, bundled).await?;
+            write_stamp(&stamp_path, &bundled_hash, None).await?;
+            return Ok(BundledTemplateSync::Installed);
+        }
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+
+    let stamp = match fs::read_to_string(&stamp_path).await {
+        Ok(raw) => serde_yaml::from_str::<BundledTemplateStamp>(&raw).ok(),
+        Err(_) => None,
+    };
+
+    if installed == bundled {
+        if stamp.as_ref().map(|stamp| stamp.installed.as_str()) != Some(bundled_hash.as_str()) {
+            write_stamp(&stamp_path, &bundled_hash, None).await?;
+        }
+        return Ok(BundledTemplateSync::UpToDate);
+    }
+
+    let unmodified = stamp
+        .as_ref()
+        .is_some_and(|stamp| stamp.installed == content_hash(&installed));
+    if unmodified {
+        fs::write(path, bundled).await?;
+        write_stamp(&stamp_path, &bundled_hash, None).await?;
+        return Ok(BundledTemplateSync::Updated);
+    }
+
+    if force {
+        let backup = backup_path(path);
+        fs::write(&backup, &installed)
+            .await
+            .with_context(|| format!("failed to back up {}", path.display()))?;
+        fs::write(path, bundled).await?;
+        write_stamp(&stamp_path, &bundled_hash, None).await?;
+        return Ok(BundledTemplateSync::Overwritten { backup });
+    }
+
+    let already_offered =
+        stamp.as_ref().and_then(|stamp| stamp.offered.as_deref()) == Some(bundled_hash.as_str());
+    if already_offered {
+        return Ok(BundledTemplateSync::Modified { diff: None });
+    }
+    // Without a stamp the installed copy predates tracking; keep treating it as user-owned.
+    let installed_hash = stamp
+        .map(|stamp| stamp.installed)
+        .unwrap_or_else(|| content_hash(&installed));
+    write_stamp(&stamp_path, &installed_hash, Some(&bundled_hash)).await?;
+    Ok(BundledTemplateSync::Modified {
+        diff: Some(line_diff(&installed, bundled)),
+    })
+}
+
+fn bundled_stamp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.stamp", name))
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+async fn write_stamp(path: &Path, installed: &str, offered: Option<&str>) -> anyhow::Result<()> {
+    let stamp = BundledTemplateStamp {
+        installed: installed.to_string(),
+        offered: offered.map(str::to_string),
+    };
+    fs::write(path, serde_yaml::to_string(&stamp)?)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Minimal line diff (`-`/`+` lines under `@@ -old +new @@` hunk headers, no context).
+fn line_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] = length of the longest common subsequence of old[i..] and new[j..].
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let mut hunk: Vec<String> = Vec::new();
+    let mut hunk_start = (0, 0);
+    let (mut i, mut j) = (0, 0);
+    let flush = |out: &mut String, hunk: &mut Vec<String>, start: (usize, usize)| {
+        if !hunk.is_empty() {
+            out.push_str(&format!("@@ -{} +{} @@\n", start.0 + 1, start.1 + 1));
+            for line in hunk.drain(..) {
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+    };
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            flush(&mut out, &mut hunk, hunk_start);
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if hunk.is_empty() {
+            hunk_start = (i, j);
+        }
+        if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            hunk.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            hunk.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    flush(&mut out, &mut hunk, hunk_start);
+    out
+}
+
+fn parse_mapping(yaml: &str) -> anyhow::Result<Mapping> {
+    match serde_yaml::from_str(yaml)? {
+        Value::Mapping(mapping) => Ok(mapping),
+        _ => Err(anyhow!("template YAML must be a mapping")),
+    }
+}
+
 fn deep_merge(target: &mut Value, patch: &Value) {
     match (target, patch) {
         (Value::Mapping(target_map), Value::Mapping(patch_map)) => {
@@ -131,6 +383,8 @@ rules:
         let merge_yaml = r#"
 rules:
   - RULE-2
+Prepend-Rules:
+  - RULE-0
 "#;
 
         let mut template = Template::from_yaml_str(base_yaml).unwrap();
@@ -138,7 +392,99 @@ rules:
         template.apply_merge(merge_mapping).unwrap();
 
         let config = template.config();
-        assert_eq!(config.rules, vec!["RULE-2".to_string()]);
+        assert_eq!(
+            config.rules,
+            vec!["RULE-0".to_string(), "RULE-2".to_string()]
+        );
+        assert!(!config.extra.contains_key("prepend-rules"));
         assert_eq!(config.port, Some(7890));
     }
+
+    #[test]
+    fn line_diff_reports_changed_lines() {
+        let diff = line_diff("a\nb\nc\n", "a\nB\nc\nd\n");
+        assert_eq!(diff, "@@ -2 +2 @@\n-b\n+B\n@@ -4 +4 @@\n+d\n");
+        assert!(line_diff("same\n", "same\n").is_empty());
+    }
+
+    #[tokio::test]
+    async fn extends_deep_merges_child_over_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("base")).unwrap();
+        std::fs::write(
+            dir.path().join("base/common.yaml"),
+            "mixed-port: 7890\ndns:\n  enable: true\n  ipv6: false\nrules:\n  - MATCH,DIRECT\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("base/router.yaml"),
+            "extends: common.yaml\nallow-lan: true\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("laptop.yaml"),
+            "extends: base/router.yaml\ndns:\n  ipv6: true\nrules:\n  - MATCH,PROXY\n",
+        )
+        .unwrap();
+
+        let template = Template::load(&dir.path().join("laptop.yaml"))
+            .await
+            .unwrap();
+        let config = template.config();
+        assert_eq!(config.rules, vec!["MATCH,PROXY".to_string()]);
+        assert_eq!(config.extra.get("mixed-port"), Some(&Value::from(7890)));
+        assert_eq!(config.extra.get("allow-lan"), Some(&Value::from(true)));
+        assert_eq!(config.extra["dns"]["enable"], Value::from(true));
+        assert_eq!(config.extra["dns"]["ipv6"], Value::from(true));
+        assert!(!config.extra.contains_key("extends"));
+
+        std::fs::write(dir.path().join("a.yaml"), "extends: b.yaml\n").unwrap();
+        std::fs::write(dir.path().join("b.yaml"), "extends: a.yaml\n").unwrap();
+        let err = Template::load(&dir.path().join("a.yaml"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("extends itself"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn bundled_template_updates_only_untouched_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cvr_template.yaml");
+
+        let sync = sync_bundled_template(&path, "v: 1\n", false).await.unwrap();
+        assert_eq!(sync, BundledTemplateSync::Installed);
+        let sync = sync_bundled_template(&path, "v: 1\n", false).await.unwrap();
+        assert_eq!(sync, BundledTemplateSync::UpToDate);
+
+        // Untouched copy follows the new bundle.
+        let sync = sync_bundled_template(&path, "v: 2\n", false).await.unwrap();
+        assert_eq!(sync, BundledTemplateSync::Updated);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "v: 2\n");
+
+        // User edits are kept; the diff is shown once per bundle.
+        std::fs::write(&path, "v: 2\nmine: true\n").unwrap();
+        let sync = sync_bundled_template(&path, "v: 3\n", false).await.unwrap();
+        assert_eq!(
+            sync,
+            BundledTemplateSync::Modified {
+                diff: Some("@@ -1 +1 @@\n-v: 2\n-mine: true\n+v: 3\n".to_string())
+            }
+        );
+        let sync = sync_bundled_template(&path, "v: 3\n", false).await.unwrap();
+        assert_eq!(sync, BundledTemplateSync::Modified { diff: None });
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "v: 2\nmine: true\n"
+        );
+
+        let sync = sync_bundled_template(&path, "v: 3\n", true).await.unwrap();
+        let BundledTemplateSync::Overwritten { backup } = sync else {
+            panic!("expected overwrite, got {:?}", sync);
+        };
+        assert_eq!(
+            std::fs::read_to_string(&backup).unwrap(),
+            "v: 2\nmine: true\n"
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "v: 3\n");
+    }
 }
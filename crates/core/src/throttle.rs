@@ -0,0 +1,111 @@
+//! Process-wide bandwidth cap for downloads (subscriptions, geodata).
+//!
+//! All streams draw from one budget, so parallel downloads together stay under the limit.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::time::Instant;
+
+/// Spreads byte consumption so that the average rate never exceeds `bytes_per_sec`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    /// End of the transfer time already handed out.
+    next_free: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next_free: Mutex::new(None),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Account for `bytes` just received; sleeps until they fit into the budget.
+    pub async fn consume(&self, bytes: usize) {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        let until = {
+            let mut next_free = self.next_free.lock().unwrap_or_else(|p| p.into_inner());
+            let now = Instant::now();
+            let start = next_free.filter(|next| *next > now).unwrap_or(now);
+            let until = start + cost;
+            *next_free = Some(until);
+            until
+        };
+        tokio::time::sleep_until(until).await;
+    }
+}
+
+static DOWNLOAD_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Cap all downloads of this process at `bytes_per_sec`. Call once during program
+/// initialization.
+pub fn set_max_download_rate(bytes_per_sec: u64) {
+    let _ = DOWNLOAD_LIMITER.set(RateLimiter::new(bytes_per_sec));
+}
+
+/// Throttle a download that just received `bytes`; returns immediately without a cap.
+pub async fn throttle_download(bytes: usize) {
+    if let Some(limiter) = DOWNLOAD_LIMITER.get() {
+        limiter.consume(bytes).await;
+    }
+}
+
+/// Parse a rate such as `500000`, `512K`, `2M` or `1.5MiB` (per second; suffixes are
+/// binary multiples, an optional trailing `B`, `iB` or `/s` is ignored).
+pub fn parse_rate(raw: &str) -> anyhow::Result<u64> {
+    let lowered = raw.trim().to_ascii_lowercase();
+    let value = lowered.strip_suffix("/s").unwrap_or(&lowered);
+    let value = value
+        .strip_suffix("ib")
+        .or_else(|| value.strip_suffix('b'))
+        .unwrap_or(value);
+    let (number, multiplier) = match value.chars().last() {
+        Some('k') => (&value[..value.len() - 1], 1024.0),
+        Some('m') => (&value[..value.len() - 1], 1024.0 * 1024.0),
+        Some('g') => (&value[..value.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (value, 1.0),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid rate '{}' (expected e.g. 500K or 2M)", raw))?;
+    let rate = (number * multiplier).round();
+    if !rate.is_finite() || rate < 1.0 {
+        return Err(anyhow!("rate '{}' must be at least 1 byte per second", raw));
+    }
+    Ok(rate as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_accept_binary_suffixes() {
+        assert_eq!(parse_rate("500000").unwrap(), 500_000);
+        assert_eq!(parse_rate("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_rate("2m").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_rate("1.5MiB/s").unwrap(), 1_572_864);
+        assert_eq!(parse_rate("100kb").unwrap(), 102_400);
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("0").is_err());
+    }
+
+    #[tokio::test]
+    async fn limiter_spreads_bytes_over_time() {
+        let limiter = RateLimiter::new(100_000);
+        let started = std::time::Instant::now();
+        for _ in 0..5 {
+            limiter.consume(10_000).await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(450));
+    }
+}